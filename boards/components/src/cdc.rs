@@ -0,0 +1,68 @@
+//! Component for a CDC-ACM serial port over USB.
+//!
+//! The resulting `CdcAcm` implements the UART HIL, so it can be handed to
+//! `UartMuxComponent` in place of a hardware UART. The board still has to add
+//! it to a USB device created with `UsbCompositeComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let cdc = components::cdc::CdcAcmComponent::new(&nrf52::usbd::USBD, dynamic_deferred_caller)
+//!     .finalize(components::cdc_acm_component_helper!(nrf52::usbd::Usbd<'static>));
+//! usb.add_function(cdc, "Console");
+//! ```
+
+use capsules::usb::cdc::CdcAcm;
+use core::mem::MaybeUninit;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! cdc_acm_component_helper {
+    ($U:ty) => {{
+        use capsules::usb::cdc::CdcAcm;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CdcAcm<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct CdcAcmComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> CdcAcmComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> CdcAcmComponent<U> {
+        CdcAcmComponent {
+            usb,
+            deferred_caller,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for CdcAcmComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CdcAcm<'static, U>>;
+    type Output = &'static CdcAcm<'static, U>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let cdc = static_init_half!(
+            static_buffer,
+            CdcAcm<'static, U>,
+            CdcAcm::new(self.usb, self.deferred_caller)
+        );
+        cdc.initialize_callback_handle(
+            self.deferred_caller
+                .register(cdc)
+                .expect("no deferred call slot available for USB CDC-ACM"),
+        );
+
+        cdc
+    }
+}
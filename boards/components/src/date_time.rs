@@ -0,0 +1,49 @@
+//! Component for a real-time clock.
+//!
+//! This provides one Component, DateTimeComponent, which implements a
+//! userspace syscall interface for reading and setting the wall-clock time of
+//! any `hil::date_time::DateTime`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let date_time = DateTimeComponent::new(board_kernel, &sam4l::ast::AST).finalize(());
+//! ```
+
+use capsules::date_time::DateTimeDriver;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init;
+
+pub struct DateTimeComponent {
+    board_kernel: &'static kernel::Kernel,
+    date_time: &'static dyn hil::date_time::DateTime,
+}
+
+impl DateTimeComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        date_time: &'static dyn hil::date_time::DateTime,
+    ) -> DateTimeComponent {
+        DateTimeComponent {
+            board_kernel,
+            date_time,
+        }
+    }
+}
+
+impl Component for DateTimeComponent {
+    type StaticInput = ();
+    type Output = &'static DateTimeDriver<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            DateTimeDriver<'static>,
+            DateTimeDriver::new(self.date_time, self.board_kernel.create_grant(&grant_cap))
+        )
+    }
+}
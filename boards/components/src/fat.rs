@@ -0,0 +1,84 @@
+//! Component for a FAT filesystem and its syscall driver.
+//!
+//! This provides one Component, FatComponent, which mounts a FAT volume on
+//! any `hil::block_storage::BlockStorage`, such as an SD card or a region of
+//! a flash chip, with a system call interface that gives each app its own
+//! directory. The volume is mounted when the component is finalized, and
+//! again whenever the medium changes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (fat, fat_driver) = components::fat::FatComponent::new(
+//!     board_kernel,
+//!     volume,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules::fat::Fat;
+use capsules::fat_driver::FatDriver;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::static_init;
+
+pub struct FatComponent {
+    board_kernel: &'static kernel::Kernel,
+    storage: &'static dyn BlockStorage<'static>,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl FatComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        storage: &'static dyn BlockStorage<'static>,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> FatComponent {
+        FatComponent {
+            board_kernel,
+            storage,
+            deferred_caller,
+        }
+    }
+}
+
+impl Component for FatComponent {
+    type StaticInput = ();
+    type Output = (&'static Fat<'static>, &'static FatDriver<'static>);
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let fat = static_init!(
+            Fat<'static>,
+            Fat::new(
+                self.storage,
+                &mut capsules::fat::CACHE,
+                self.deferred_caller
+            )
+        );
+        fat.initialize_callback_handle(
+            self.deferred_caller
+                .register(fat)
+                .expect("no deferred call slot available for the FAT filesystem"),
+        );
+        self.storage.set_client(fat);
+
+        let fat_driver = static_init!(
+            FatDriver<'static>,
+            FatDriver::new(
+                fat,
+                self.board_kernel.create_grant(&grant_cap),
+                &mut capsules::fat_driver::BUFFER
+            )
+        );
+        fat.set_client(fat_driver);
+        fat.mount();
+
+        (fat, fat_driver)
+    }
+}
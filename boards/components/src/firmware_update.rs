@@ -0,0 +1,230 @@
+//! Components for kernel updates through two image slots.
+//!
+//! This provides three Components. `FirmwareUpdateComponent` writes update
+//! images into the inactive slot of an internal flash and checks their
+//! digest. `FirmwareUpdateDriverComponent` lets applications write images
+//! and confirm the running kernel. A `usb_dfu::UsbDfuComponent` can be used
+//! instead of the driver to download images over USB, in which case
+//! `FirmwareConfirmComponent` confirms the kernel once it has run for a while.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let update = components::firmware_update::FirmwareUpdateComponent::new(
+//!     &nrf52::nvmc::NVMC,
+//!     sha,
+//!     capsules::firmware_update::FirmwareSlots::new(0x38, 0x30, 0x68),
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::firmware_update_component_helper!(
+//!     nrf52::nvmc::Nvmc,
+//!     capsules::sha256::Sha256Software<'static>
+//! ));
+//! let update_driver =
+//!     components::firmware_update::FirmwareUpdateDriverComponent::new(board_kernel, update)
+//!         .finalize(());
+//! ```
+//!
+//! Without the driver:
+//!
+//! ```rust
+//! components::firmware_update::FirmwareConfirmComponent::new(update, mux_alarm, 60_000)
+//!     .finalize(components::firmware_confirm_component_helper!(nrf52::rtc::Rtc));
+//! ```
+
+use capsules::firmware_update::{ConfirmTimer, FirmwareSlots, FirmwareUpdate, ImageUpdate};
+use capsules::firmware_update_driver::FirmwareUpdateDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::time::{self, Alarm};
+use kernel::{static_init, static_init_half};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! firmware_update_component_helper {
+    ($F:ty, $D:ty) => {{
+        use capsules::firmware_update::FirmwareUpdate;
+        use core::mem::MaybeUninit;
+        use kernel::hil;
+        static mut BUF1: MaybeUninit<<$F as hil::flash::Flash>::Page> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<FirmwareUpdate<'static, $F, $D>> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+#[macro_export]
+macro_rules! firmware_confirm_component_helper {
+    ($A:ty) => {{
+        use capsules::firmware_update::ConfirmTimer;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<ConfirmTimer<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct FirmwareUpdateComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FirmwareUpdate<'static, F, D>>,
+    D: 'static + hil::digest::Digest<'static, [u8; 32]> + hil::digest::Sha256,
+> {
+    flash: &'static F,
+    digest: &'static D,
+    slots: FirmwareSlots,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FirmwareUpdate<'static, F, D>>,
+        D: 'static + hil::digest::Digest<'static, [u8; 32]> + hil::digest::Sha256,
+    > FirmwareUpdateComponent<F, D>
+{
+    pub fn new(
+        flash: &'static F,
+        digest: &'static D,
+        slots: FirmwareSlots,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> Self {
+        Self {
+            flash,
+            digest,
+            slots,
+            deferred_caller,
+        }
+    }
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FirmwareUpdate<'static, F, D>>,
+        D: 'static + hil::digest::Digest<'static, [u8; 32]> + hil::digest::Sha256,
+    > Component for FirmwareUpdateComponent<F, D>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<FirmwareUpdate<'static, F, D>>,
+    );
+    type Output = &'static FirmwareUpdate<'static, F, D>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let page = static_init_half!(
+            static_buffer.0,
+            <F as hil::flash::Flash>::Page,
+            <F as hil::flash::Flash>::Page::default()
+        );
+        let hash_buffer = static_init!([u8; 64], [0; 64]);
+        let digest_buffer = static_init!([u8; 32], [0; 32]);
+
+        let update = static_init_half!(
+            static_buffer.1,
+            FirmwareUpdate<'static, F, D>,
+            FirmwareUpdate::new(
+                self.flash,
+                self.digest,
+                self.slots,
+                page,
+                hash_buffer,
+                digest_buffer,
+                self.deferred_caller
+            )
+        );
+        update.initialize_callback_handle(
+            self.deferred_caller
+                .register(update)
+                .expect("no deferred call slot available for firmware update"),
+        );
+        hil::flash::HasClient::set_client(self.flash, update);
+        hil::digest::Digest::set_client(self.digest, update);
+
+        update
+    }
+}
+
+pub struct FirmwareUpdateDriverComponent {
+    board_kernel: &'static kernel::Kernel,
+    update: &'static dyn ImageUpdate<'static>,
+}
+
+impl FirmwareUpdateDriverComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        update: &'static dyn ImageUpdate<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            update,
+        }
+    }
+}
+
+impl Component for FirmwareUpdateDriverComponent {
+    type StaticInput = ();
+    type Output = &'static FirmwareUpdateDriver<'static>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let update_driver = static_init!(
+            FirmwareUpdateDriver<'static>,
+            FirmwareUpdateDriver::new(
+                self.update,
+                &mut capsules::firmware_update_driver::BUFFER,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        self.update.set_client(update_driver);
+
+        update_driver
+    }
+}
+
+pub struct FirmwareConfirmComponent<A: 'static + time::Alarm<'static>> {
+    update: &'static dyn ImageUpdate<'static>,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    uptime_ms: u32,
+}
+
+impl<A: 'static + time::Alarm<'static>> FirmwareConfirmComponent<A> {
+    /// Confirms the kernel `uptime_ms` milliseconds after `finalize()`.
+    pub fn new(
+        update: &'static dyn ImageUpdate<'static>,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        uptime_ms: u32,
+    ) -> Self {
+        Self {
+            update,
+            mux_alarm,
+            uptime_ms,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for FirmwareConfirmComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<ConfirmTimer<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static ConfirmTimer<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let confirm_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        let confirm_timer = static_init_half!(
+            static_buffer.1,
+            ConfirmTimer<'static, VirtualMuxAlarm<'static, A>>,
+            ConfirmTimer::new(self.update, confirm_alarm)
+        );
+        confirm_alarm.set_client(confirm_timer);
+        confirm_timer.start(self.uptime_ms);
+
+        confirm_timer
+    }
+}
@@ -0,0 +1,88 @@
+//! Component for FramedConsole, a console that frames the output of the kernel
+//! and of each process.
+//!
+//! This provides one Component, `FramedConsoleComponent`, which replaces both
+//! `ConsoleComponent` and `DebugWriterComponent`: the kernel debug writer is
+//! attached to the framed console, so `debug!()` output is framed as well.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let console = FramedConsoleComponent::new(board_kernel, uart_mux).finalize(());
+//! ```
+
+use capsules::framed_console::{self, FramedConsole};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::capabilities;
+use kernel::common::ring_buffer::RingBuffer;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init;
+
+pub struct FramedConsoleComponent {
+    board_kernel: &'static kernel::Kernel,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl FramedConsoleComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        uart_mux: &'static MuxUart,
+    ) -> FramedConsoleComponent {
+        FramedConsoleComponent {
+            board_kernel: board_kernel,
+            uart_mux: uart_mux,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl Component for FramedConsoleComponent {
+    type StaticInput = ();
+    type Output = &'static FramedConsole<'static, Capability>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        // Create virtual device for console.
+        let console_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        console_uart.setup();
+
+        let console = static_init!(
+            FramedConsole<'static, Capability>,
+            FramedConsole::new(
+                console_uart,
+                &mut framed_console::WRITE_BUF,
+                &mut framed_console::READ_BUF,
+                self.board_kernel.create_grant(&grant_cap),
+                self.board_kernel,
+                Capability,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(console_uart, console);
+        hil::uart::Receive::set_receive_client(console_uart, console);
+
+        // Kernel debug output is framed by the console. Each debug write is
+        // split into frames that fit the console's write buffer.
+        let buf = static_init!([u8; 1024], [0; 1024]);
+        let (output_buf, internal_buf) = buf.split_at_mut(64);
+        let ring_buffer = static_init!(RingBuffer<'static, u8>, RingBuffer::new(internal_buf));
+        let debugger = static_init!(
+            kernel::debug::DebugWriter,
+            kernel::debug::DebugWriter::new(console, output_buf, ring_buffer)
+        );
+        hil::uart::Transmit::set_transmit_client(console, debugger);
+
+        let debug_wrapper = static_init!(
+            kernel::debug::DebugWriterWrapper,
+            kernel::debug::DebugWriterWrapper::new(debugger)
+        );
+        kernel::debug::set_debug_writer_wrapper(debug_wrapper);
+
+        console.start();
+        console
+    }
+}
@@ -1,20 +1,26 @@
 //! Components for I2C.
 //!
-//! This provides two components.
+//! This provides three components.
 //!
 //! 1. `I2CMuxComponent` provides a virtualization layer for a I2C bus.
 //!
 //! 2. `I2CComponent` provides a virtualized client to the I2C bus.
 //!
+//! 3. `I2CMasterSlaveDriverComponent` provides a system call interface to an
+//!    I2C controller that can act as both master and slave.
+//!
 //! Usage
 //! -----
 //! ```rust
 //! let mux_i2c = components::i2c::I2CMuxComponent::new(&stm32f3xx::i2c::I2C1).finalize(components::i2c_mux_component_helper!());
 //! let client_i2c = components::i2c::I2CComponent::new(mux_i2c, 0x19).finalize(components::i2c_component_helper!());
+//! let i2c_master_slave = components::i2c::I2CMasterSlaveDriverComponent::new(&stm32f4xx::i2c::I2C1)
+//!     .finalize(components::i2c_master_slave_driver_component_helper!());
 //! ```
 
 // Author: Alexandru Radovici <msg4alex@gmail.com>
 
+use capsules::i2c_master_slave_driver::I2CMasterSlaveDriver;
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use core::mem::MaybeUninit;
 use kernel::component::Component;
@@ -42,6 +48,16 @@ macro_rules! i2c_component_helper {
     };};
 }
 
+#[macro_export]
+macro_rules! i2c_master_slave_driver_component_helper {
+    () => {{
+        use capsules::i2c_master_slave_driver::I2CMasterSlaveDriver;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<I2CMasterSlaveDriver<'static>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
 pub struct I2CMuxComponent {
     i2c: &'static dyn i2c::I2CMaster,
 }
@@ -51,6 +67,10 @@ pub struct I2CComponent {
     address: u8,
 }
 
+pub struct I2CMasterSlaveDriverComponent {
+    i2c: &'static dyn i2c::I2CMasterSlave,
+}
+
 impl I2CMuxComponent {
     pub fn new(i2c: &'static dyn i2c::I2CMaster) -> Self {
         I2CMuxComponent { i2c: i2c }
@@ -93,3 +113,32 @@ impl Component for I2CComponent {
         i2c_device
     }
 }
+
+impl I2CMasterSlaveDriverComponent {
+    pub fn new(i2c: &'static dyn i2c::I2CMasterSlave) -> Self {
+        I2CMasterSlaveDriverComponent { i2c: i2c }
+    }
+}
+
+impl Component for I2CMasterSlaveDriverComponent {
+    type StaticInput = &'static mut MaybeUninit<I2CMasterSlaveDriver<'static>>;
+    type Output = &'static I2CMasterSlaveDriver<'static>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let i2c_master_slave = static_init_half!(
+            static_buffer,
+            I2CMasterSlaveDriver<'static>,
+            I2CMasterSlaveDriver::new(
+                self.i2c,
+                &mut capsules::i2c_master_slave_driver::BUFFER1,
+                &mut capsules::i2c_master_slave_driver::BUFFER2,
+                &mut capsules::i2c_master_slave_driver::BUFFER3,
+            )
+        );
+
+        i2c::I2CMaster::set_master_client(self.i2c, i2c_master_slave);
+        i2c::I2CSlave::set_slave_client(self.i2c, i2c_master_slave);
+
+        i2c_master_slave
+    }
+}
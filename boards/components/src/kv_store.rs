@@ -0,0 +1,139 @@
+//! Component for the key-value store and its syscall driver.
+//!
+//! This provides one component, KVStoreComponent, which provides a
+//! wear-leveled key-value store on a range of flash pages, with a system
+//! call interface that gives each app its own namespace. The store must be
+//! mounted before use.
+//!
+//! Usage
+//! -----
+//! ```rust,ignore
+//! let (kv_store, kv_store_driver) = components::kv_store::KVStoreComponent::new(
+//!     board_kernel,
+//!     kv_flash,
+//!     992,  // First page of the store
+//!     1024, // Quota of each app, in bytes
+//! )
+//! .finalize(components::kv_store_component_helper!(
+//!     capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+//!     32, // Pages
+//!     64  // Keys
+//! ));
+//! kv_store.mount();
+//! ```
+
+use capsules::kv_store::{IndexEntry, KVStore};
+use capsules::kv_store_driver::KVStoreDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! kv_store_component_helper {
+    ($F:ty, $P:expr, $N:expr) => {{
+        use capsules::kv_store::{IndexEntry, KVStore};
+        use capsules::kv_store_driver::KVStoreDriver;
+        use core::mem::MaybeUninit;
+        use kernel::hil;
+        static mut BUF1: MaybeUninit<<$F as hil::flash::Flash>::Page> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<<$F as hil::flash::Flash>::Page> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<KVStore<'static, $F>> = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<KVStoreDriver<'static, $F>> = MaybeUninit::uninit();
+        static mut PAGES: [u32; $P] = [0; $P];
+        static mut INDEX: [IndexEntry; $N] = [IndexEntry::EMPTY; $N];
+        (
+            &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut PAGES, &mut INDEX,
+        )
+    };};
+}
+
+pub struct KVStoreComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, KVStore<'static, F>>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    flash: &'static F,
+    first_page: usize,
+    quota: usize,
+}
+
+impl<F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, KVStore<'static, F>>>
+    KVStoreComponent<F>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        flash: &'static F,
+        first_page: usize,
+        quota: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            flash,
+            first_page,
+            quota,
+        }
+    }
+}
+
+impl<F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, KVStore<'static, F>>> Component
+    for KVStoreComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<KVStore<'static, F>>,
+        &'static mut MaybeUninit<KVStoreDriver<'static, F>>,
+        &'static mut [u32],
+        &'static mut [IndexEntry],
+    );
+    type Output = (
+        &'static KVStore<'static, F>,
+        &'static KVStoreDriver<'static, F>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let head_page = static_init_half!(
+            static_buffer.0,
+            <F as hil::flash::Flash>::Page,
+            <F as hil::flash::Flash>::Page::default()
+        );
+        let scratch_page = static_init_half!(
+            static_buffer.1,
+            <F as hil::flash::Flash>::Page,
+            <F as hil::flash::Flash>::Page::default()
+        );
+
+        let kv_store = static_init_half!(
+            static_buffer.2,
+            KVStore<'static, F>,
+            KVStore::new(
+                self.flash,
+                self.first_page,
+                static_buffer.4,
+                static_buffer.5,
+                self.quota,
+                head_page,
+                scratch_page,
+            )
+        );
+        hil::flash::HasClient::set_client(self.flash, kv_store);
+
+        let kv_store_driver = static_init_half!(
+            static_buffer.3,
+            KVStoreDriver<'static, F>,
+            KVStoreDriver::new(
+                kv_store,
+                self.board_kernel.create_grant(&grant_cap),
+                &mut capsules::kv_store_driver::BUFFER
+            )
+        );
+        kv_store.set_client(kv_store_driver);
+
+        (kv_store, kv_store_driver)
+    }
+}
@@ -3,27 +3,40 @@
 pub mod alarm;
 pub mod analog_comparator;
 pub mod button;
+pub mod cdc;
 pub mod console;
 pub mod crc;
+pub mod date_time;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod fat;
+pub mod firmware_update;
+pub mod framed_console;
 pub mod gpio;
 pub mod hd44780;
 pub mod hmac;
 pub mod i2c;
 pub mod isl29035;
+pub mod kv_store;
 pub mod l3gd20;
 pub mod led;
 pub mod lldb;
 pub mod lsm303dlhc;
+pub mod microphone;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
 pub mod process_console;
+pub mod qspi_flash;
 pub mod rng;
 pub mod segger_rtt;
+pub mod sha256;
 pub mod si7021;
 pub mod spi;
 pub mod temperature;
+pub mod usb_composite;
+pub mod usb_dfu;
+pub mod usb_hid;
+pub mod usb_msc;
@@ -0,0 +1,54 @@
+//! Component for a digital microphone.
+//!
+//! This provides one Component, MicrophoneComponent, which implements a
+//! userspace syscall interface for recording audio from any
+//! `hil::audio::AudioInput`, such as a PDM microphone.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let microphone = MicrophoneComponent::new(board_kernel, &nrf52::pdm::PDM).finalize(());
+//! ```
+
+use capsules::microphone::Microphone;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init;
+
+pub struct MicrophoneComponent {
+    board_kernel: &'static kernel::Kernel,
+    mic: &'static dyn hil::audio::AudioInput<'static>,
+}
+
+impl MicrophoneComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mic: &'static dyn hil::audio::AudioInput<'static>,
+    ) -> MicrophoneComponent {
+        MicrophoneComponent { board_kernel, mic }
+    }
+}
+
+impl Component for MicrophoneComponent {
+    type StaticInput = ();
+    type Output = &'static Microphone<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let microphone = static_init!(
+            Microphone<'static>,
+            Microphone::new(
+                self.mic,
+                self.board_kernel.create_grant(&grant_cap),
+                &mut capsules::microphone::BUFFER1,
+                &mut capsules::microphone::BUFFER2,
+            )
+        );
+        self.mic.set_client(microphone);
+
+        microphone
+    }
+}
@@ -1,7 +1,8 @@
 //! Component for non-volatile storage Drivers.
 //!
 //! This provides one component, NonvolatileStorageComponent, which provides
-//! a system call inteface to non-volatile storage.
+//! a system call inteface to non-volatile storage. The userspace region is
+//! divided among apps, each of which gets `app_quota` bytes.
 //!
 //! Usage
 //! -----
@@ -13,6 +14,7 @@
 //!     0x20000,
 //!     &_sstorage as *const u8 as usize,
 //!     &_estorage as *const u8 as usize,
+//!     0x1000,
 //! )
 //! .finalize(components::nv_storage_component_helper!(
 //!     sam4l::flashcalw::FLASHCALW
@@ -50,6 +52,7 @@ pub struct NonvolatileStorageComponent<
     userspace_length: usize,
     kernel_start: usize,
     kernel_length: usize,
+    app_quota: usize,
 }
 
 impl<
@@ -65,6 +68,7 @@ impl<
         userspace_length: usize,
         kernel_start: usize,
         kernel_length: usize,
+        app_quota: usize,
     ) -> Self {
         Self {
             board_kernel,
@@ -73,6 +77,7 @@ impl<
             userspace_length,
             kernel_start,
             kernel_length,
+            app_quota,
         }
     }
 }
@@ -114,6 +119,7 @@ impl<
                 self.userspace_length, // Length of userspace accessible region
                 self.kernel_start,    // Start address of kernel region
                 self.kernel_length,   // Length of kernel region
+                self.app_quota,       // Bytes of each app
                 &mut capsules::nonvolatile_storage_driver::BUFFER
             )
         );
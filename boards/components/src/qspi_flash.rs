@@ -0,0 +1,53 @@
+//! Component for external flash on a QSPI controller.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let qspi_flash = components::qspi_flash::QspiFlashComponent::new(&nrf52::qspi::QSPI)
+//!     .finalize(components::qspi_flash_component_helper!(
+//!         nrf52::qspi::Qspi<'static>
+//!     ));
+//! ```
+
+use capsules::qspi_flash::QspiFlash;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! qspi_flash_component_helper {
+    ($Q:ty) => {{
+        use capsules::qspi_flash::QspiFlash;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<QspiFlash<'static, $Q>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct QspiFlashComponent<Q: 'static + hil::qspi::QspiMaster<'static>> {
+    qspi: &'static Q,
+}
+
+impl<Q: 'static + hil::qspi::QspiMaster<'static>> QspiFlashComponent<Q> {
+    pub fn new(qspi: &'static Q) -> QspiFlashComponent<Q> {
+        QspiFlashComponent { qspi }
+    }
+}
+
+impl<Q: 'static + hil::qspi::QspiMaster<'static>> Component for QspiFlashComponent<Q> {
+    type StaticInput = &'static mut MaybeUninit<QspiFlash<'static, Q>>;
+    type Output = &'static QspiFlash<'static, Q>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let qspi_flash = static_init_half!(
+            static_buffer,
+            QspiFlash<'static, Q>,
+            QspiFlash::new(self.qspi, &mut capsules::qspi_flash::BUFFER.0)
+        );
+        self.qspi.set_client(qspi_flash);
+        qspi_flash.initialize();
+        qspi_flash
+    }
+}
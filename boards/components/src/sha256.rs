@@ -0,0 +1,42 @@
+//! Component for a software SHA-256 digest engine.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sha = components::sha256::Sha256SoftwareComponent::new(dynamic_deferred_caller)
+//!     .finalize(());
+//! ```
+
+use capsules::sha256::Sha256Software;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct Sha256SoftwareComponent {
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl Sha256SoftwareComponent {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> Self {
+        Self { deferred_caller }
+    }
+}
+
+impl Component for Sha256SoftwareComponent {
+    type StaticInput = ();
+    type Output = &'static Sha256Software<'static>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let sha = static_init!(
+            Sha256Software<'static>,
+            Sha256Software::new(self.deferred_caller)
+        );
+        sha.initialize_callback_handle(
+            self.deferred_caller
+                .register(sha)
+                .expect("no deferred call slot available for SHA-256"),
+        );
+
+        sha
+    }
+}
@@ -1,6 +1,6 @@
 //! Components for SPI.
 //!
-//! This provides three components.
+//! This provides four components.
 //!
 //! 1. `SpiMuxComponent` provides a virtualization layer for a SPI bus.
 //!
@@ -8,6 +8,9 @@
 //!
 //! 3. `SpiComponent` provides a virtualized client to the SPI bus.
 //!
+//! 4. `SpiSlaveSyscallComponent` provides a system call interface to a SPI
+//!    controller in slave mode.
+//!
 //! `SpiSyscallComponent` is used for processes, while `SpiComponent` is used
 //! for kernel capsules that need access to the SPI bus.
 //!
@@ -20,6 +23,8 @@
 //!     components::spi_syscalls_component_helper!(sam4l::spi::SpiHw));
 //! let rf233_spi = SpiComponent::new(mux_spi, 3).finalize(
 //!     components::spi_component_helper!(sam4l::spi::SpiHw));
+//! let spi_slave = SpiSlaveSyscallComponent::new(&nrf52::spis::SPIS2).finalize(
+//!     components::spi_slave_syscall_component_helper!(nrf52::spis::SPIS));
 //! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
@@ -27,8 +32,8 @@
 
 use core::mem::MaybeUninit;
 
-use capsules::spi::{Spi, DEFAULT_READ_BUF_LENGTH, DEFAULT_WRITE_BUF_LENGTH};
-use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules::spi::{Spi, SpiSlave, DEFAULT_READ_BUF_LENGTH, DEFAULT_WRITE_BUF_LENGTH};
+use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice, VirtualSpiSlaveDevice};
 use kernel::component::Component;
 use kernel::hil::spi;
 use kernel::{static_init, static_init_half};
@@ -67,6 +72,19 @@ macro_rules! spi_component_helper {
     };};
 }
 
+#[macro_export]
+macro_rules! spi_slave_syscall_component_helper {
+    ($S:ty) => {{
+        use capsules::spi::SpiSlave;
+        use capsules::virtual_spi::VirtualSpiSlaveDevice;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualSpiSlaveDevice<'static, $S>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<SpiSlave<'static, VirtualSpiSlaveDevice<'static, $S>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
 pub struct SpiMuxComponent<S: 'static + spi::SpiMaster> {
     spi: &'static S,
 }
@@ -81,6 +99,10 @@ pub struct SpiComponent<S: 'static + spi::SpiMaster> {
     chip_select: S::ChipSelect,
 }
 
+pub struct SpiSlaveSyscallComponent<S: 'static + spi::SpiSlave> {
+    spi: &'static S,
+}
+
 impl<S: 'static + spi::SpiMaster> SpiMuxComponent<S> {
     pub fn new(spi: &'static S) -> Self {
         SpiMuxComponent { spi: spi }
@@ -172,3 +194,46 @@ impl<S: 'static + spi::SpiMaster> Component for SpiComponent<S> {
         spi_device
     }
 }
+
+impl<S: 'static + spi::SpiSlave> SpiSlaveSyscallComponent<S> {
+    pub fn new(spi: &'static S) -> Self {
+        SpiSlaveSyscallComponent { spi: spi }
+    }
+}
+
+impl<S: 'static + spi::SpiSlave> Component for SpiSlaveSyscallComponent<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiSlaveDevice<'static, S>>,
+        &'static mut MaybeUninit<SpiSlave<'static, VirtualSpiSlaveDevice<'static, S>>>,
+    );
+    type Output = &'static SpiSlave<'static, VirtualSpiSlaveDevice<'static, S>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let syscall_spi_device = static_init_half!(
+            static_buffer.0,
+            VirtualSpiSlaveDevice<'static, S>,
+            VirtualSpiSlaveDevice::new(self.spi)
+        );
+
+        let spi_syscalls = static_init_half!(
+            static_buffer.1,
+            SpiSlave<'static, VirtualSpiSlaveDevice<'static, S>>,
+            SpiSlave::new(syscall_spi_device)
+        );
+
+        let spi_read_buf =
+            static_init!([u8; DEFAULT_READ_BUF_LENGTH], [0; DEFAULT_READ_BUF_LENGTH]);
+
+        let spi_write_buf = static_init!(
+            [u8; DEFAULT_WRITE_BUF_LENGTH],
+            [0; DEFAULT_WRITE_BUF_LENGTH]
+        );
+
+        spi_syscalls.config_buffers(spi_read_buf, spi_write_buf);
+        syscall_spi_device.set_client(spi_syscalls);
+        self.spi.set_client(Some(syscall_spi_device));
+        self.spi.init();
+
+        spi_syscalls
+    }
+}
@@ -0,0 +1,77 @@
+//! Component for a USB device made of several functions.
+//!
+//! This provides one Component, `UsbCompositeComponent`, which creates the
+//! device answering the control requests of the host. The board adds the
+//! functions, such as those created by `CdcAcmComponent`, `UsbHidComponent`
+//! and `UsbMassStorageComponent`, registers the device as the client of the
+//! USB controller and enables it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let usb = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     0x6667,
+//!     0xabce,
+//!     &["Tock", "Tock Device", "0"],
+//! )
+//! .finalize(components::usb_composite_component_helper!(nrf52::usbd::Usbd<'static>));
+//! usb.add_function(cdc, "Console");
+//! nrf52::usbd::USBD.set_client(usb);
+//! usb.enable();
+//! usb.attach();
+//! ```
+
+use capsules::usb::composite::CompositeDevice;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_composite_component_helper {
+    ($U:ty) => {{
+        use capsules::usb::composite::CompositeDevice;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CompositeDevice<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct UsbCompositeComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeComponent<U> {
+    /// `strings` are the manufacturer, product and serial number strings.
+    pub fn new(
+        usb: &'static U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> UsbCompositeComponent<U> {
+        UsbCompositeComponent {
+            usb,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CompositeDevice<'static, U>>;
+    type Output = &'static CompositeDevice<'static, U>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        static_init_half!(
+            static_buffer,
+            CompositeDevice<'static, U>,
+            CompositeDevice::new(self.usb, self.vendor_id, self.product_id, self.strings)
+        )
+    }
+}
@@ -0,0 +1,40 @@
+//! Component for a USB Device Firmware Upgrade function.
+//!
+//! This provides one Component, `UsbDfuComponent`, which downloads kernel
+//! update images from the host into a `FirmwareUpdate` created with
+//! `FirmwareUpdateComponent`. The board still has to add the function to a
+//! USB device created with `UsbCompositeComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let dfu = components::usb_dfu::UsbDfuComponent::new(update).finalize(());
+//! usb.add_function(dfu, "Firmware");
+//! ```
+
+use capsules::firmware_update::ImageUpdate;
+use capsules::usb::dfu::UsbDfu;
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct UsbDfuComponent {
+    update: &'static dyn ImageUpdate<'static>,
+}
+
+impl UsbDfuComponent {
+    pub fn new(update: &'static dyn ImageUpdate<'static>) -> Self {
+        Self { update }
+    }
+}
+
+impl Component for UsbDfuComponent {
+    type StaticInput = ();
+    type Output = &'static UsbDfu<'static>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let dfu = static_init!(UsbDfu<'static>, UsbDfu::new(self.update));
+        self.update.set_client(dfu);
+
+        dfu
+    }
+}
@@ -0,0 +1,107 @@
+//! Component for a USB HID device with a syscall interface.
+//!
+//! This provides one Component, `UsbHidComponent`, which creates a keyboard,
+//! mouse or vendor-defined HID device on a USB controller and a syscall
+//! driver through which applications send and receive its reports. The board
+//! still has to add the device to a USB device created with
+//! `UsbCompositeComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     board_kernel,
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::hid::HidKind::Keyboard,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52::usbd::Usbd<'static>));
+//! usb.add_function(hid, "Keyboard");
+//! ```
+
+use capsules::usb::hid::{HidDevice, HidKind, UsbHid};
+use capsules::usb::hid_user::{self, HidDriver};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_hid_component_helper {
+    ($U:ty) => {{
+        use capsules::usb::hid::UsbHid;
+        use capsules::usb::hid_user::HidDriver;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<UsbHid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<HidDriver<'static, UsbHid<'static, $U>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UsbHidComponent<U: 'static + hil::usb::UsbController<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    usb: &'static U,
+    kind: HidKind,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbHidComponent<U> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        usb: &'static U,
+        kind: HidKind,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> UsbHidComponent<U> {
+        UsbHidComponent {
+            board_kernel,
+            usb,
+            kind,
+            deferred_caller,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbHidComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UsbHid<'static, U>>,
+        &'static mut MaybeUninit<HidDriver<'static, UsbHid<'static, U>>>,
+    );
+    type Output = (
+        &'static UsbHid<'static, U>,
+        &'static HidDriver<'static, UsbHid<'static, U>>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let hid = static_init_half!(
+            static_buffer.0,
+            UsbHid<'static, U>,
+            UsbHid::new(self.usb, self.kind, self.deferred_caller)
+        );
+        hid.initialize_callback_handle(
+            self.deferred_caller
+                .register(hid)
+                .expect("no deferred call slot available for USB HID"),
+        );
+
+        let hid_driver = static_init_half!(
+            static_buffer.1,
+            HidDriver<'static, UsbHid<'static, U>>,
+            HidDriver::new(
+                hid,
+                &mut hid_user::SEND_BUF,
+                &mut hid_user::RECEIVE_BUF,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        hid.set_client(hid_driver);
+
+        (hid, hid_driver)
+    }
+}
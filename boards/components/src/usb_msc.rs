@@ -0,0 +1,92 @@
+//! Component for a USB mass storage device.
+//!
+//! This provides one Component, `UsbMassStorageComponent`, which exports a
+//! block storage volume to the host as a removable disk. The board still has
+//! to add the device to a USB device created with `UsbCompositeComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let msc = components::usb_msc::UsbMassStorageComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     volume,
+//!     true,
+//!     &["Tock", "Storage"],
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52::usbd::Usbd<'static>));
+//! usb.add_function(msc, "Storage");
+//! ```
+
+use capsules::usb::msc::UsbMassStorage;
+use core::mem::MaybeUninit;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_helper {
+    ($U:ty) => {{
+        use capsules::usb::msc::UsbMassStorage;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<UsbMassStorage<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct UsbMassStorageComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    storage: &'static dyn BlockStorage<'static>,
+    read_only: bool,
+    strings: &'static [&'static str; 2],
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbMassStorageComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        storage: &'static dyn BlockStorage<'static>,
+        read_only: bool,
+        strings: &'static [&'static str; 2],
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> UsbMassStorageComponent<U> {
+        UsbMassStorageComponent {
+            usb,
+            storage,
+            read_only,
+            strings,
+            deferred_caller,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbMassStorageComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<UsbMassStorage<'static, U>>;
+    type Output = &'static UsbMassStorage<'static, U>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let msc = static_init_half!(
+            static_buffer,
+            UsbMassStorage<'static, U>,
+            UsbMassStorage::new(
+                self.usb,
+                self.storage,
+                &mut capsules::usb::msc::BUFFER,
+                self.read_only,
+                self.strings,
+                self.deferred_caller
+            )
+        );
+        msc.initialize_callback_handle(
+            self.deferred_caller
+                .register(msc)
+                .expect("no deferred call slot available for USB mass storage"),
+        );
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
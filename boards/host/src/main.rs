@@ -0,0 +1,340 @@
+//! Board file for running Tock as a Linux process.
+//!
+//! The board runs the kernel main loop with the console, alarm, nonvolatile
+//! storage and 802.15.4 radio capsules on top of the peripherals of
+//! `host_chip`, so that capsules can be tested on a Linux machine. The host
+//! cannot run processes, which are built for a microcontroller, so the
+//! kernel runs without any and the process console only reports an empty
+//! process list.
+//!
+//! Usage
+//! -----
+//!
+//! ```text
+//! host [--uart PATH] [--flash FILE] [--flash-pages N]
+//!      [--radio DIR] [--address ADDR] [--exit-after MS]
+//! ```
+//!
+//! - `--uart`: terminal device for the console, for instance the slave side
+//!   of a pty, instead of stdin and stdout.
+//! - `--flash`: file holding the flash, which enables nonvolatile storage.
+//!   The first half of the flash is for processes and the second half for
+//!   the kernel.
+//! - `--radio`: directory of UNIX sockets shared by the nodes of a simulated
+//!   802.15.4 network, which enables the radio.
+//! - `--address`: short 802.15.4 address of the node, in hexadecimal.
+//! - `--exit-after`: stops the kernel after this many milliseconds.
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::mac::{AwakeMac, Mac};
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::component::Component;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::Alarm;
+use kernel::Platform;
+use kernel::ReturnCode;
+use kernel::{create_capability, debug, static_init};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+// Processes cannot run on the host.
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; 0] = [];
+
+const PAN_ID: u16 = 0xabcd;
+
+/// Bytes of nonvolatile storage that each app gets.
+const APP_STORAGE_QUOTA: usize = 0x1000;
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut RADIO_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+type RadioMac = AwakeMac<'static, host_chip::radio::Radio>;
+type RadioDevice = capsules::ieee802154::framer::Framer<'static, RadioMac, NoCcm>;
+
+/// Options from the command line.
+struct Options {
+    uart: Option<PathBuf>,
+    flash: Option<PathBuf>,
+    flash_pages: usize,
+    radio: Option<PathBuf>,
+    address: u16,
+    exit_after: Option<Duration>,
+}
+
+impl Options {
+    fn usage() -> ! {
+        eprintln!(
+            "usage: host [--uart PATH] [--flash FILE] [--flash-pages N] \
+             [--radio DIR] [--address ADDR] [--exit-after MS]"
+        );
+        process::exit(2);
+    }
+
+    fn parse() -> Options {
+        let mut options = Options {
+            uart: None,
+            flash: None,
+            flash_pages: 256,
+            radio: None,
+            address: 0x0001,
+            exit_after: None,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next().unwrap_or_else(|| Options::usage());
+            match arg.as_str() {
+                "--uart" => options.uart = Some(PathBuf::from(value)),
+                "--flash" => options.flash = Some(PathBuf::from(value)),
+                "--flash-pages" => {
+                    options.flash_pages = value.parse().unwrap_or_else(|_| Options::usage())
+                }
+                "--radio" => options.radio = Some(PathBuf::from(value)),
+                "--address" => {
+                    options.address = u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                        .unwrap_or_else(|_| Options::usage())
+                }
+                "--exit-after" => {
+                    let ms = value.parse().unwrap_or_else(|_| Options::usage());
+                    options.exit_after = Some(Duration::from_millis(ms));
+                }
+                _ => Options::usage(),
+            }
+        }
+        options
+    }
+}
+
+/// The host has no AES engine, so the radio sends and receives unsecured
+/// frames only.
+struct NoCcm;
+
+impl AES128CCM<'static> for NoCcm {
+    fn set_client(&'static self, _client: &'static dyn CCMClient) {}
+
+    fn set_key(&self, _key: &[u8]) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn set_nonce(&self, _nonce: &[u8]) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        _a_off: usize,
+        _m_off: usize,
+        _m_len: usize,
+        _mic_len: usize,
+        _confidential: bool,
+        _encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        (ReturnCode::ENOSUPPORT, Some(buf))
+    }
+}
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct Host {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, host_chip::alarm::Alarm<'static>>,
+    >,
+    nonvolatile_storage:
+        Option<&'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>>,
+    radio_driver: Option<&'static capsules::ieee802154::RadioDriver<'static>>,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for Host {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::nonvolatile_storage_driver::DRIVER_NUM => f(self
+                .nonvolatile_storage
+                .map(|driver| driver as &dyn kernel::Driver)),
+            capsules::ieee802154::DRIVER_NUM => f(self
+                .radio_driver
+                .map(|driver| driver as &dyn kernel::Driver)),
+            _ => f(None),
+        }
+    }
+}
+
+// Exits with a message if a peripheral cannot be set up
+fn open<T>(what: &str, result: std::io::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("host: cannot open the {}: {}", what, err);
+        process::exit(1);
+    })
+}
+
+unsafe fn setup_radio(
+    board_kernel: &'static kernel::Kernel,
+    radio: &'static host_chip::radio::Radio,
+    address: u16,
+) -> &'static capsules::ieee802154::RadioDriver<'static> {
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    // Keeps the radio on permanently; pass-through layer
+    let awake_mac = static_init!(RadioMac, AwakeMac::new(radio));
+    radio.set_transmit_client(awake_mac);
+    radio.set_receive_client(awake_mac, &mut RADIO_RX_BUF);
+
+    let no_ccm = static_init!(NoCcm, NoCcm);
+    let mac_device = static_init!(
+        RadioDevice,
+        capsules::ieee802154::framer::Framer::new(awake_mac, no_ccm)
+    );
+    awake_mac.set_transmit_client(mac_device);
+    awake_mac.set_receive_client(mac_device);
+    awake_mac.set_config_client(mac_device);
+
+    let mux_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        capsules::ieee802154::virtual_mac::MuxMac::new(mac_device)
+    );
+    mac_device.set_transmit_client(mux_mac);
+    mac_device.set_receive_client(mux_mac);
+
+    let radio_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MacUser<'static>,
+        capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+    );
+    mux_mac.add_user(radio_mac);
+
+    let radio_driver = static_init!(
+        capsules::ieee802154::RadioDriver<'static>,
+        capsules::ieee802154::RadioDriver::new(
+            radio_mac,
+            board_kernel.create_grant(&grant_cap),
+            &mut RADIO_BUF
+        )
+    );
+    mac_device.set_key_procedure(radio_driver);
+    mac_device.set_device_procedure(radio_driver);
+    radio_mac.set_transmit_client(radio_driver);
+    radio_mac.set_receive_client(radio_driver);
+    radio_mac.set_pan(PAN_ID);
+    radio_mac.set_address(address);
+    radio_mac.config_commit();
+    radio.start();
+
+    radio_driver
+}
+
+unsafe fn setup(options: Options) {
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel: &'static kernel::Kernel =
+        static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    let alarm = static_init!(
+        host_chip::alarm::Alarm<'static>,
+        host_chip::alarm::Alarm::new()
+    );
+    let uart = match options.uart {
+        Some(ref path) => open("UART", host_chip::uart::Uart::new(path)),
+        None => host_chip::uart::Uart::new_stdio(),
+    };
+    let uart = static_init!(host_chip::uart::Uart<'static>, uart);
+    let chip: &'static host_chip::chip::Host = static_init!(
+        host_chip::chip::Host,
+        host_chip::chip::Host::new(alarm, uart)
+    );
+    if let Some(duration) = options.exit_after {
+        chip.exit_after(duration);
+    }
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux =
+        components::console::UartMuxComponent::new(uart, 115200, dynamic_deferred_caller)
+            .finalize(());
+
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(());
+
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(alarm).finalize(
+        components::alarm_mux_component_helper!(host_chip::alarm::Alarm<'static>),
+    );
+    alarm.set_client(mux_alarm);
+    let alarm_driver = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(
+            host_chip::alarm::Alarm<'static>
+        ));
+
+    let flash_pages = options.flash_pages;
+    let nonvolatile_storage = options.flash.as_ref().map(move |path| {
+        let flash = static_init!(
+            host_chip::flash::Flash<'static>,
+            open("flash", host_chip::flash::Flash::new(path, flash_pages))
+        );
+        chip.set_flash(flash);
+        let half = flash.pages() * host_chip::flash::PAGE_SIZE / 2;
+        components::nonvolatile_storage::NonvolatileStorageComponent::new(
+            board_kernel,
+            flash,
+            0,
+            half,
+            half,
+            half,
+            APP_STORAGE_QUOTA,
+        )
+        .finalize(components::nv_storage_component_helper!(
+            host_chip::flash::Flash<'static>
+        ))
+    });
+
+    let address = options.address;
+    let radio_driver = options.radio.as_ref().map(move |dir| {
+        let name = format!("{:04x}", address);
+        let radio = static_init!(
+            host_chip::radio::Radio,
+            open("radio", host_chip::radio::Radio::new(dir, &name))
+        );
+        chip.set_radio(radio);
+        setup_radio(board_kernel, radio, address)
+    });
+
+    let host = Host {
+        console: console,
+        alarm: alarm_driver,
+        nonvolatile_storage: nonvolatile_storage,
+        radio_driver: radio_driver,
+    };
+
+    process_console.start();
+
+    debug!("Host initialization complete.");
+    debug!("Entering main loop.");
+
+    board_kernel.kernel_loop(&host, chip, None, &main_loop_cap);
+}
+
+fn main() {
+    let options = Options::parse();
+    unsafe {
+        setup(options);
+    }
+}
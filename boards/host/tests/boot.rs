@@ -0,0 +1,58 @@
+//! Boots the host board as a Linux process and checks what it sets up.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// A directory for the files of one test
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tock-host-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_host"))
+        .args(args)
+        .args(&["--exit-after", "200"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn kernel_boots() {
+    let output = run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Host initialization complete."));
+    assert!(stdout.contains("Entering main loop."));
+}
+
+#[test]
+fn flash_file_is_erased() {
+    let dir = scratch_dir("flash");
+    let flash = dir.join("flash.bin");
+    let output = run(&["--flash", flash.to_str().unwrap(), "--flash-pages", "16"]);
+    assert!(output.status.success());
+    let contents = fs::read(&flash).unwrap();
+    assert_eq!(contents.len(), 16 * 512);
+    assert!(contents.iter().all(|&byte| byte == 0xff));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn radio_binds_socket() {
+    let dir = scratch_dir("radio");
+    let output = run(&["--radio", dir.to_str().unwrap(), "--address", "0x00a2"]);
+    assert!(output.status.success());
+    assert!(dir.join("00a2.sock").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bad_option_is_rejected() {
+    let output = run(&["--bogus", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+}
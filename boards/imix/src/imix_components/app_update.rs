@@ -0,0 +1,166 @@
+//! Component for installing applications over the air.
+//!
+//! This provides one Component, AppUpdateComponent, which binds a UDP port
+//! and writes the application images received on it behind the installed
+//! applications, if they are authenticated with `key`. The application flash
+//! is shared with other users through a `MuxFlash`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let app_update = AppUpdateComponent::new(
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_flash,
+//!     sha,
+//!     key,
+//!     apps_region,
+//!     APP_UPDATE_PORT,
+//! )
+//! .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::net::app_update::{AppUpdate, RESPONSE_LENGTH};
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use capsules::sha256::Sha256Software;
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use capsules::virtual_flash::{FlashUser, MuxFlash};
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::{create_capability, static_init};
+
+// Longest chunk of an image in a request
+const CHUNK_LENGTH: usize = 128;
+
+static mut BUFFER: [u8; CHUNK_LENGTH] = [0; CHUNK_LENGTH];
+static mut HEADER_BUFFER: [u8; CHUNK_LENGTH] = [0; CHUNK_LENGTH];
+static mut DIGEST_BUFFER: [u8; 32] = [0; 32];
+static mut TX_BUFFER: [u8; RESPONSE_LENGTH] = [0; RESPONSE_LENGTH];
+
+type Sender = UDPSendStruct<
+    'static,
+    IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+>;
+
+pub struct AppUpdateComponent {
+    udp_send_mux: &'static MuxUdpSender<
+        'static,
+        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    mux_flash: &'static MuxFlash<'static, sam4l::flashcalw::FLASHCALW>,
+    digest: &'static Sha256Software<'static>,
+    key: [u8; 32],
+    apps: &'static [u8],
+    port: u16,
+}
+
+impl AppUpdateComponent {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        mux_flash: &'static MuxFlash<'static, sam4l::flashcalw::FLASHCALW>,
+        digest: &'static Sha256Software<'static>,
+        key: [u8; 32],
+        apps: &'static [u8],
+        port: u16,
+    ) -> AppUpdateComponent {
+        AppUpdateComponent {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            mux_flash,
+            digest,
+            key,
+            apps,
+            port,
+        }
+    }
+}
+
+impl Component for AppUpdateComponent {
+    type StaticInput = ();
+    type Output = &'static AppUpdate<'static, Sha256Software<'static>>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let udp_send = static_init!(Sender, UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no UDP socket available for app updates");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, self.port, net_cap)
+            .ok()
+            .expect("app update port already bound");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let flash_user = static_init!(
+            FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+            FlashUser::new(self.mux_flash)
+        );
+        let page = static_init!(
+            sam4l::flashcalw::Sam4lPage,
+            sam4l::flashcalw::Sam4lPage::default()
+        );
+        let nv_to_page = static_init!(
+            NonvolatileToPages<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+            NonvolatileToPages::new(flash_user, page)
+        );
+        hil::flash::HasClient::set_client(flash_user, nv_to_page);
+
+        let app_update = static_init!(
+            AppUpdate<'static, Sha256Software<'static>>,
+            AppUpdate::new(
+                udp_send,
+                nv_to_page,
+                self.digest,
+                self.key,
+                self.apps,
+                net_cap,
+                &mut BUFFER,
+                &mut HEADER_BUFFER,
+                &mut DIGEST_BUFFER,
+                LeasableBuffer::new(&mut TX_BUFFER),
+            )
+        );
+        udp_send.set_client(app_update);
+        udp_recv.set_client(app_update);
+        nv_to_page.set_client(app_update);
+        hil::digest::Digest::set_client(self.digest, app_update);
+
+        app_update
+    }
+}
@@ -0,0 +1,167 @@
+//! Component for the 6LoWPAN border router.
+//!
+//! This provides one Component, BorderRouterComponent, which forwards IPv6
+//! packets between the 802.15.4 interface set up by the UDPMuxComponent and
+//! a host on a UART, and advertises the mesh prefix to the nodes of the PAN.
+//!
+//! The UART runs SLIP at 115200 baud. On the host, `tools/tunslip6.py`
+//! connects it to a tun interface.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let border_router = BorderRouterComponent::new(
+//!     lowpan,
+//!     &sam4l::usart::USART0,
+//!     mux_alarm,
+//!     local_ip_ifaces,
+//!     MESH_PREFIX,
+//!     MESH_PREFIX_LEN,
+//! )
+//! .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use super::udp_mux::LowpanInterface;
+use capsules::net::border_router::{BorderRouter, ADVERTISEMENT_LENGTH};
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::sixlowpan_state::TxState;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart;
+use kernel::{create_capability, static_init};
+
+// Longest packet accepted from the host
+const HOST_MTU: usize = 640;
+
+static mut RX_BYTE: [u8; 1] = [0; 1];
+static mut RX_BUFFER: [u8; HOST_MTU] = [0; HOST_MTU];
+// Holds the SLIP frame of a packet from the mesh, which are reassembled in
+// buffers of 1280 bytes
+static mut TX_BUFFER: [u8; 1300] = [0; 1300];
+static mut ADVERTISEMENT_BUFFER: [u8; ADVERTISEMENT_LENGTH] = [0; ADVERTISEMENT_LENGTH];
+// Payload of the packets sent into the mesh, behind the IPv6 and transport headers
+static mut MESH_DGRAM: [u8; HOST_MTU - 48] = [0; HOST_MTU - 48];
+
+type Router = BorderRouter<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct BorderRouterComponent {
+    lowpan: LowpanInterface,
+    uart: &'static sam4l::usart::USART<'static>,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    local_addrs: &'static [IPAddr],
+    prefix: [u8; 16],
+    prefix_len: u8,
+}
+
+impl BorderRouterComponent {
+    pub fn new(
+        lowpan: LowpanInterface,
+        uart: &'static sam4l::usart::USART<'static>,
+        alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        local_addrs: &'static [IPAddr],
+        prefix: [u8; 16],
+        prefix_len: u8,
+    ) -> BorderRouterComponent {
+        BorderRouterComponent {
+            lowpan,
+            uart,
+            alarm_mux,
+            local_addrs,
+            prefix,
+            prefix_len,
+        }
+    }
+}
+
+impl Component for BorderRouterComponent {
+    type StaticInput = ();
+    type Output = &'static Router;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        // Packets sent into the mesh are addressed explicitly when forwarded,
+        // and broadcast when they are router advertisements.
+        let ip_pyld = IPPayload::new(
+            TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type134)),
+            &mut MESH_DGRAM,
+        );
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+        let mesh_sender = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                TxState::new(self.lowpan.sixlowpan),
+                self.lowpan.ip_send_mux,
+                MacAddress::Short(0xffff),
+                self.lowpan.src_mac_addr,
+                ip_vis,
+            )
+        );
+        self.lowpan.ip_send_mux.add_sender(mesh_sender);
+        mesh_sender.set_addr(IPAddr::generate_from_mac(self.lowpan.src_mac_addr));
+
+        self.uart.set_mode(sam4l::usart::UsartMode::Uart);
+        uart::Configure::configure(
+            self.uart,
+            uart::Parameters {
+                baud_rate: 115200,
+                width: uart::Width::Eight,
+                stop_bits: uart::StopBits::One,
+                parity: uart::Parity::None,
+                hw_flow_control: false,
+            },
+        );
+
+        let alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let border_router = static_init!(
+            Router,
+            BorderRouter::new(
+                mesh_sender,
+                self.uart,
+                alarm,
+                self.local_addrs,
+                self.prefix,
+                self.prefix_len,
+                net_cap,
+                &mut RX_BYTE,
+                &mut RX_BUFFER,
+                &mut TX_BUFFER,
+                &mut ADVERTISEMENT_BUFFER,
+            )
+        );
+        mesh_sender.set_client(border_router);
+        alarm.set_client(border_router);
+        uart::Transmit::set_transmit_client(self.uart, border_router);
+        uart::Receive::set_receive_client(self.uart, border_router);
+
+        // Packets for this node now pass through the border router
+        self.lowpan.sixlowpan.set_rx_client(border_router);
+        border_router.set_local_client(self.lowpan.ip_receive);
+        border_router.start();
+
+        border_router
+    }
+}
@@ -0,0 +1,146 @@
+//! Component for the CoAP endpoint and its userspace driver.
+//!
+//! This provides one Component, CoapComponent, which binds a UDP port to a
+//! CoAP endpoint and returns the syscall driver that processes use to send
+//! requests and serve resources through it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let coap_driver = CoapComponent::new(
+//!     board_kernel,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//!     COAP_PORT,
+//! )
+//! .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::net::coap::driver::CoapDriver;
+use capsules::net::coap::endpoint::{Coap, CoapEndpoint};
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init};
+
+const UDP_HDR_SIZE: usize = 8;
+const PAYLOAD_LEN: usize = super::udp_mux::PAYLOAD_LEN;
+
+static mut REQUEST_BUF: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+static mut RESPONSE_BUF: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+
+type Sender = UDPSendStruct<
+    'static,
+    IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+>;
+
+type Endpoint = Coap<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct CoapComponent {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux: &'static MuxUdpSender<
+        'static,
+        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    port: u16,
+}
+
+impl CoapComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        port: u16,
+    ) -> CoapComponent {
+        CoapComponent {
+            board_kernel,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+            port,
+        }
+    }
+}
+
+impl Component for CoapComponent {
+    type StaticInput = ();
+    type Output = &'static CoapDriver<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let udp_send = static_init!(Sender, UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no UDP socket available for CoAP");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, self.port, net_cap)
+            .ok()
+            .expect("CoAP port already bound");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let coap_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let coap = static_init!(
+            Endpoint,
+            Coap::new(
+                udp_send,
+                coap_alarm,
+                net_cap,
+                LeasableBuffer::new(&mut REQUEST_BUF),
+                LeasableBuffer::new(&mut RESPONSE_BUF),
+            )
+        );
+        udp_send.set_client(coap);
+        udp_recv.set_client(coap);
+        coap_alarm.set_client(coap);
+
+        let coap_driver = static_init!(
+            CoapDriver<'static>,
+            CoapDriver::new(coap, self.board_kernel.create_grant(&grant_cap))
+        );
+        coap.set_client(coap_driver);
+
+        coap_driver
+    }
+}
@@ -1,4 +1,7 @@
 pub mod adc;
+pub mod app_update;
+pub mod border_router;
+pub mod coap;
 pub mod fxos8700;
 pub mod radio;
 pub mod rf233;
@@ -8,6 +11,9 @@ pub mod udp_mux;
 pub mod usb;
 
 pub use self::adc::AdcComponent;
+pub use self::app_update::AppUpdateComponent;
+pub use self::border_router::BorderRouterComponent;
+pub use self::coap::CoapComponent;
 pub use self::fxos8700::NineDofComponent;
 pub use self::radio::RadioComponent;
 pub use self::rf233::RF233Component;
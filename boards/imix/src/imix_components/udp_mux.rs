@@ -2,12 +2,16 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. The mux has
+//! `UDP_SEND_LANES` lanes, so that many UDP senders can have a packet
+//! in flight at the same time.
+//! It also returns the `LowpanInterface` that components forwarding
+//! IPv6 packets attach to.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, port_table, lowpan) = UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -30,15 +34,14 @@ use capsules::ieee802154::device::MacDevice;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_recv::IP6Receiver;
-use capsules::net::ipv6::ipv6_send::IP6SendStruct;
-use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender, MuxIP6Sender};
 use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp::UDPHeader;
 use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
-use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::net::udp::udp_send::{MuxUdpSender, UdpSendLane};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel;
 use kernel::capabilities;
@@ -50,11 +53,12 @@ use kernel::static_init;
 
 use sam4l;
 
-// The UDP stack requires exactly one of several packet buffers:
+// The UDP stack requires the following packet buffers:
 //
-//   1. RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   1. RF233_BUF: buffer the MuxIP6Sender uses to pass frames to the radio after fragmentation
 //   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
-//   3. udp_dgram: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   3. UDP_DGRAMS: The payloads of the IP6_Packets, which hold full IP Packets before they are
+//      tx'd. There is one per UDP send lane, so that many packets can be fragmented at once.
 //
 //   Additionally, every capsule using the stack needs an additional buffer to craft packets for
 //   tx which can then be passed to the MuxUdpSender for tx.
@@ -64,7 +68,9 @@ static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
 
 pub const PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userspace apps or capsules
 const UDP_HDR_SIZE: usize = 8;
-static mut UDP_DGRAM: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+const UDP_SEND_LANES: usize = 2;
+static mut UDP_DGRAMS: [[u8; PAYLOAD_LEN - UDP_HDR_SIZE]; UDP_SEND_LANES] =
+    [[0; PAYLOAD_LEN - UDP_HDR_SIZE]; UDP_SEND_LANES];
 
 // Rather than require a data structure with 65535 slots (number of UDP ports), we
 // use a structure that can hold up to 16 port bindings. Any given capsule can bind
@@ -78,6 +84,16 @@ static mut UDP_DGRAM: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_H
 static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
     [None; MAX_NUM_BOUND_PORTS];
 
+/// The parts of the 6LoWPAN interface that components forwarding IPv6
+/// packets, such as the border router, attach to.
+pub struct LowpanInterface {
+    pub sixlowpan: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    pub ip_send_mux:
+        &'static MuxIP6Sender<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    pub ip_receive: &'static IP6RecvStruct<'static>,
+    pub src_mac_addr: MacAddress,
+}
+
 pub struct UDPMuxComponent {
     mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
     ctx_pfix_len: u8,
@@ -119,6 +135,7 @@ impl Component for UDPMuxComponent {
         >,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        LowpanInterface,
     );
 
     unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
@@ -160,7 +177,6 @@ impl Component for UDPMuxComponent {
         );
 
         let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
-        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
         let default_rx_state = static_init!(
             sixlowpan_state::RxState<'static>,
             sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
@@ -168,63 +184,69 @@ impl Component for UDPMuxComponent {
         sixlowpan_state.add_rx_state(default_rx_state);
         udp_mac.set_receive_client(sixlowpan);
 
-        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
-        let ip_pyld: IPPayload = IPPayload {
-            header: tr_hdr,
-            payload: &mut UDP_DGRAM,
-        };
-        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
-
-        // In current design, all udp senders share same IP sender, and the IP sender
-        // holds the destination mac address. This means all UDP senders must send to
-        // the same mac address...this works fine under the assumption
-        // of all packets being routed via a single gateway router, but doesn't work
-        // if multiple senders want to send to different addresses on a local network.
-        // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
-        let ip_send = static_init!(
-            capsules::net::ipv6::ipv6_send::IP6SendStruct<
-                'static,
-                VirtualMuxAlarm<'static, sam4l::ast::Ast>,
-            >,
-            capsules::net::ipv6::ipv6_send::IP6SendStruct::new(
-                ip6_dg,
-                ipsender_virtual_alarm,
-                &mut RF233_BUF,
-                sixlowpan_tx,
-                udp_mac,
-                self.dst_mac_addr,
-                self.src_mac_addr,
-                ip_vis,
-            )
-        );
-        ipsender_virtual_alarm.set_client(ip_send);
-
-        // Initially, set src IP of the sender to be the first IP in the Interface
-        // list. Userland apps can change this if they so choose.
-        // Notably, the src addr is the same regardless of if messages are sent from
-        // userland or capsules.
-        ip_send.set_addr(self.interface_list[0]);
-        udp_mac.set_transmit_client(ip_send);
-
-        let ip_receive = static_init!(
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        let ip_send_mux = static_init!(
+            MuxIP6Sender<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            MuxIP6Sender::new(ipsender_virtual_alarm, &mut RF233_BUF, udp_mac)
         );
-        sixlowpan_state.set_rx_client(ip_receive);
-        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
-        ip_receive.set_client(udp_recv_mux);
+        ipsender_virtual_alarm.set_client(ip_send_mux);
+        udp_mac.set_transmit_client(ip_send_mux);
 
         let udp_send_mux = static_init!(
             MuxUdpSender<
                 'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
-                >,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
             >,
-            MuxUdpSender::new(ip_send)
+            MuxUdpSender::new()
         );
-        ip_send.set_client(udp_send_mux);
+
+        for udp_dgram in UDP_DGRAMS.iter_mut() {
+            let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+            let ip_pyld: IPPayload = IPPayload {
+                header: tr_hdr,
+                payload: udp_dgram,
+            };
+            let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+            // In current design, all udp senders share the same IP senders, and each IP
+            // sender holds the destination mac address. This means all UDP senders must
+            // send to the same mac address...this works fine under the assumption
+            // of all packets being routed via a single gateway router, but doesn't work
+            // if multiple senders want to send to different addresses on a local network.
+            // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
+            let ip_send = static_init!(
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+                IP6SendStruct::new(
+                    ip6_dg,
+                    sixlowpan_state::TxState::new(sixlowpan_state),
+                    ip_send_mux,
+                    self.dst_mac_addr,
+                    self.src_mac_addr,
+                    ip_vis,
+                )
+            );
+            ip_send_mux.add_sender(ip_send);
+
+            // Initially, set src IP of the sender to be the first IP in the Interface
+            // list. Userland apps can change this if they so choose.
+            // Notably, the src addr is the same regardless of if messages are sent from
+            // userland or capsules.
+            ip_send.set_addr(self.interface_list[0]);
+
+            let lane = static_init!(
+                UdpSendLane<
+                    'static,
+                    IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+                >,
+                UdpSendLane::new(udp_send_mux, ip_send)
+            );
+            ip_send.set_client(lane);
+            udp_send_mux.add_lane(lane);
+        }
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        sixlowpan_state.set_rx_client(ip_receive);
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        ip_receive.set_client(udp_recv_mux);
 
         let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
         let udp_port_table = static_init!(
@@ -232,6 +254,13 @@ impl Component for UDPMuxComponent {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        let lowpan = LowpanInterface {
+            sixlowpan: sixlowpan_state,
+            ip_send_mux: ip_send_mux,
+            ip_receive: ip_receive,
+            src_mac_addr: self.src_mac_addr,
+        };
+
+        (udp_send_mux, udp_recv_mux, udp_port_table, lowpan)
     }
 }
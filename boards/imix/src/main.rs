@@ -32,6 +32,7 @@ use components;
 use components::alarm::{AlarmDriverComponent, AlarmMuxComponent};
 use components::console::{ConsoleComponent, UartMuxComponent};
 use components::crc::CrcComponent;
+use components::date_time::DateTimeComponent;
 use components::debug_writer::DebugWriterComponent;
 use components::gpio::GpioComponent;
 use components::isl29035::AmbientLightComponent;
@@ -42,6 +43,9 @@ use components::rng::RngComponent;
 use components::si7021::{HumidityComponent, SI7021Component};
 use components::spi::{SpiComponent, SpiSyscallComponent};
 use imix_components::adc::AdcComponent;
+use imix_components::app_update::AppUpdateComponent;
+use imix_components::border_router::BorderRouterComponent;
+use imix_components::coap::CoapComponent;
 use imix_components::fxos8700::NineDofComponent;
 use imix_components::radio::RadioComponent;
 use imix_components::rf233::RF233Component;
@@ -79,6 +83,33 @@ const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compressio
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
 
+// Set to forward IPv6 packets between the PAN and a host on USART0 with SLIP,
+// and advertise MESH_PREFIX to the other nodes. The prefix then also becomes
+// 6LoWPAN compression context 0.
+const BORDER_ROUTER: bool = false;
+const MESH_PREFIX_LEN: u8 = 64;
+const MESH_PREFIX: [u8; 16] = [
+    0xfd, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+// UDP port of the CoAP endpoint
+const COAP_PORT: u16 = 5683;
+
+// Key shared with the server of over-the-air application updates. Anyone
+// with the key can install applications, so updates are off unless a secret
+// key is set here.
+const APP_UPDATE_KEY: Option<[u8; 32]> = None;
+
+// UDP port and flash region for over-the-air application updates
+const APP_UPDATE_PORT: u16 = 5000;
+const APP_UPDATE_START: usize = 0x40000;
+const APP_UPDATE_LENGTH: usize = 0x20000;
+
+// Flash pages of the key-value store, at the end of flash, and the number of
+// bytes each app may store in it
+const KV_STORE_FIRST_PAGE: usize = 992;
+const KV_STORE_QUOTA: usize = 1024;
+
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
@@ -102,6 +133,7 @@ struct Imix {
     console: &'static capsules::console::Console<'static>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     humidity: &'static capsules::humidity::HumiditySensor<'static>,
     ambient_light: &'static capsules::ambient_light::AmbientLight<'static>,
@@ -118,6 +150,7 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    coap_driver: &'static capsules::net::coap::CoapDriver<'static>,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -125,6 +158,10 @@ struct Imix {
     >,
     nrf51822: &'static capsules::nrf51822_serialization::Nrf51822Serialization<'static>,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    kv_store: &'static capsules::kv_store_driver::KVStoreDriver<
+        'static,
+        capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+    >,
 }
 
 // The RF233 radio stack requires our buffers for its SPI operations:
@@ -149,6 +186,7 @@ impl kernel::Platform for Imix {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             capsules::spi::DRIVER_NUM => f(Some(self.spi)),
             capsules::adc::DRIVER_NUM => f(Some(self.adc)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
@@ -162,8 +200,10 @@ impl kernel::Platform for Imix {
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::kv_store_driver::DRIVER_NUM => f(Some(self.kv_store)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
@@ -278,7 +318,7 @@ pub unsafe fn reset_handler() {
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+        static_init!([DynamicDeferredCallClientState; 3], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -309,6 +349,10 @@ pub unsafe fn reset_handler() {
     let alarm = AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(sam4l::ast::Ast));
 
+    // The AST also keeps wall-clock time in the backup domain.
+    let date_time = DateTimeComponent::new(board_kernel, ast).finalize(());
+    pconsole.set_date_time(ast);
+
     // # I2C and I2C Sensors
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2));
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
@@ -421,16 +465,45 @@ pub unsafe fn reset_handler() {
         static _estorage: u8;
     }
 
+    let mux_flash = static_init!(
+        capsules::virtual_flash::MuxFlash<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::virtual_flash::MuxFlash::new(&sam4l::flashcalw::FLASH_CONTROLLER)
+    );
+    kernel::hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, mux_flash);
+    let nv_flash = static_init!(
+        capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::virtual_flash::FlashUser::new(mux_flash)
+    );
+
     let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
         board_kernel,
-        &sam4l::flashcalw::FLASH_CONTROLLER,
+        nv_flash,
         0x60000,                          // Start address for userspace accessible region
-        0x20000,                          // Length of userspace accessible region
+        0x1c000,                          // Length of userspace accessible region
         &_sstorage as *const u8 as usize, //start address of kernel region
         &_estorage as *const u8 as usize - &_sstorage as *const u8 as usize, // length of kernel region
+        0x1000,                                                              // Bytes of each app
     )
     .finalize(components::nv_storage_component_helper!(
-        sam4l::flashcalw::FLASHCALW
+        capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>
+    ));
+
+    // The key-value store takes the last 16kB of flash, after the userspace
+    // storage region.
+    let kv_flash = static_init!(
+        capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::virtual_flash::FlashUser::new(mux_flash)
+    );
+    let (kv_store, kv_store_driver) = components::kv_store::KVStoreComponent::new(
+        board_kernel,
+        kv_flash,
+        KV_STORE_FIRST_PAGE,
+        KV_STORE_QUOTA,
+    )
+    .finalize(components::kv_store_component_helper!(
+        capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        32,
+        64
     ));
 
     let local_ip_ifaces = static_init!(
@@ -448,10 +521,15 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxComponent::new(
+    let (ctx_prefix_len, ctx_prefix) = if BORDER_ROUTER {
+        (MESH_PREFIX_LEN, MESH_PREFIX)
+    } else {
+        (DEFAULT_CTX_PREFIX_LEN, DEFAULT_CTX_PREFIX)
+    };
+    let (udp_send_mux, udp_recv_mux, udp_port_table, lowpan) = UDPMuxComponent::new(
         mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
+        ctx_prefix_len,
+        ctx_prefix,
         DST_MAC_ADDR,
         src_mac_from_serial_num, //comment out for dual rx test only
         //MacAddress::Short(49138), //comment in for dual rx test only
@@ -459,6 +537,19 @@ pub unsafe fn reset_handler() {
         mux_alarm,
     )
     .finalize(());
+    pconsole.set_tx_queue(lowpan.ip_send_mux);
+
+    if BORDER_ROUTER {
+        BorderRouterComponent::new(
+            lowpan,
+            &sam4l::usart::USART0,
+            mux_alarm,
+            local_ip_ifaces,
+            MESH_PREFIX,
+            MESH_PREFIX_LEN,
+        )
+        .finalize(());
+    }
 
     // UDP driver initialization happens here
     let udp_driver = UDPDriverComponent::new(
@@ -470,10 +561,39 @@ pub unsafe fn reset_handler() {
     )
     .finalize(());
 
+    let coap_driver = CoapComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+        COAP_PORT,
+    )
+    .finalize(());
+
+    // Applications received over the air are installed behind the others,
+    // below the userspace storage region.
+    if let Some(key) = APP_UPDATE_KEY {
+        let sha =
+            components::sha256::Sha256SoftwareComponent::new(dynamic_deferred_caller).finalize(());
+        AppUpdateComponent::new(
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            mux_flash,
+            sha,
+            key,
+            core::slice::from_raw_parts(APP_UPDATE_START as *const u8, APP_UPDATE_LENGTH),
+            APP_UPDATE_PORT,
+        )
+        .finalize(());
+    }
+
     let imix = Imix {
         pconsole,
         console,
         alarm,
+        date_time,
         gpio,
         temp,
         humidity,
@@ -489,9 +609,11 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        coap_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
+        kv_store: kv_store_driver,
     };
 
     let chip = static_init!(sam4l::chip::Sam4l, sam4l::chip::Sam4l::new());
@@ -505,6 +627,8 @@ pub unsafe fn reset_handler() {
     rf233.reset();
     rf233.start();
 
+    kv_store.mount();
+
     imix.pconsole.start();
 
     // Optional kernel tests. Note that these might conflict
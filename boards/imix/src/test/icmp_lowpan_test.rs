@@ -16,7 +16,7 @@ use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender, MuxIP6Sender, TxPriority};
 use capsules::net::network_capabilities::{
     AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
 };
@@ -112,20 +112,25 @@ pub unsafe fn run(
         VirtualMuxAlarm::new(mux_alarm)
     );
 
+    let ip_send_mux = static_init!(
+        MuxIP6Sender<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+        MuxIP6Sender::new(ipsender_virtual_alarm, &mut RF233_BUF, radio_mac)
+    );
+    radio_mac.set_transmit_client(ip_send_mux);
+
     let ip6_sender = static_init!(
         IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
         IP6SendStruct::new(
             ip6_dg,
-            ipsender_virtual_alarm,
-            &mut RF233_BUF,
             sixlowpan_tx,
-            radio_mac,
+            ip_send_mux,
             DST_MAC_ADDR,
             SRC_MAC_ADDR,
             ip_vis
         )
     );
-    radio_mac.set_transmit_client(ip6_sender);
+    ip6_sender.set_priority(TxPriority::High);
+    ip_send_mux.add_sender(ip6_sender);
 
     let icmp_send_struct = static_init!(
         ICMP6SendStruct<
@@ -149,7 +154,7 @@ pub unsafe fn run(
     ip6_sender.set_client(icmp_send_struct);
     icmp_send_struct.set_client(icmp_lowpan_test);
     icmp_lowpan_test.alarm.set_client(icmp_lowpan_test);
-    ipsender_virtual_alarm.set_client(ip6_sender);
+    ipsender_virtual_alarm.set_client(ip_send_mux);
     icmp_lowpan_test.start();
 }
 
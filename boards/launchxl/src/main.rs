@@ -36,6 +36,10 @@ pub const HFREQ: u32 = 48 * 1_000_000;
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
+// Whether the console frames the output of the kernel and of each process,
+// which `tools/framed-console` then separates on the host.
+const FRAMED_CONSOLE: bool = false;
+
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 3;
 static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
@@ -56,12 +60,20 @@ pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
 pub struct Platform {
     gpio: &'static capsules::gpio::GPIO<'static, cc26x2::gpio::GPIOPin>,
     led: &'static capsules::led::LED<'static, cc26x2::gpio::GPIOPin>,
-    console: &'static capsules::console::Console<'static>,
+    console: Option<&'static capsules::console::Console<'static>>,
+    // Replaces `console` if `FRAMED_CONSOLE` is set.
+    framed_console: Option<
+        &'static capsules::framed_console::FramedConsole<
+            'static,
+            components::framed_console::Capability,
+        >,
+    >,
     button: &'static capsules::button::Button<'static, cc26x2::gpio::GPIOPin>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc<'static>>,
     >,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static>,
     rng: &'static capsules::rng::RngDriver<'static>,
     i2c_master: &'static capsules::i2c_master::I2CMasterDriver<cc26x2::i2c::I2CMaster<'static>>,
     ipc: kernel::ipc::IPC,
@@ -73,11 +85,15 @@ impl kernel::Platform for Platform {
         F: FnOnce(Option<&dyn kernel::Driver>) -> R,
     {
         match driver_num {
-            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::console::DRIVER_NUM => match self.framed_console {
+                Some(framed_console) => f(Some(framed_console)),
+                None => f(self.console.map_or(None, |console| Some(console))),
+            },
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::i2c_master::DRIVER_NUM => f(Some(self.i2c_master)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
@@ -233,10 +249,19 @@ pub unsafe fn reset_handler() {
     )
     .finalize(());
 
-    // Setup the console.
-    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
-    // Create the debugger object that handles calls to `debug!()`.
-    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+    // Setup the console, and the debugger object that handles calls to
+    // `debug!()`. The framed console does both.
+    let (console, framed_console) = if FRAMED_CONSOLE {
+        let framed_console =
+            components::framed_console::FramedConsoleComponent::new(board_kernel, uart_mux)
+                .finalize(());
+        (None, Some(framed_console))
+    } else {
+        let console =
+            components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+        components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+        (Some(console), None)
+    };
 
     cc26x2::i2c::I2C0.initialize();
 
@@ -289,6 +314,14 @@ pub unsafe fn reset_handler() {
     );
     hil::time::Alarm::set_client(virtual_alarm1, alarm);
 
+    let date_time = static_init!(
+        capsules::date_time::DateTimeDriver<'static>,
+        capsules::date_time::DateTimeDriver::new(
+            &cc26x2::rtc::RTC,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
         capsules::rng::Entropy32ToRandom::new(&cc26x2::trng::TRNG)
@@ -323,10 +356,12 @@ pub unsafe fn reset_handler() {
 
     let launchxl = Platform {
         console,
+        framed_console,
         gpio,
         led,
         button,
         alarm,
+        date_time,
         rng,
         i2c_master,
         ipc,
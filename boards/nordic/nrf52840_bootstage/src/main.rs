@@ -0,0 +1,150 @@
+//! Boot stage for A/B kernel updates on the nRF52840.
+//!
+//! It runs from the start of the flash before the kernel. If the control
+//! pages mark a new kernel as pending, it swaps the two kernel slots page by
+//! page and starts the new kernel for testing. A kernel that has not
+//! confirmed itself after `MAX_BOOT_ATTEMPTS` resets is swapped back out.
+//! See `capsules::firmware_update` for the details.
+//!
+//! Flash layout
+//! ------------
+//!
+//! | Address | Size  | Contents                      |
+//! |---------|-------|-------------------------------|
+//! | 0x00000 | 32K   | Boot stage                    |
+//! | 0x08000 | 192K  | Slot A, the running kernel    |
+//! | 0x38000 | 192K  | Slot B, the update            |
+//! | 0x68000 | 8K    | Control pages                 |
+//! | 0x6a000 | 4K    | Scratch page                  |
+//! | 0x70000 | 576K  | Applications                  |
+
+#![no_std]
+// Disable this attribute when documenting, as a workaround for
+// https://github.com/rust-lang/rust/issues/62184.
+#![cfg_attr(not(doc), no_main)]
+#![feature(asm)]
+#![deny(missing_docs)]
+
+use capsules::firmware_update::{BootAction, BootControl, FirmwareSlots, SwapStep};
+use core::panic::PanicInfo;
+use nrf52840::nvmc::{NrfPage, NVMC};
+
+const PAGE_SIZE: usize = 4096;
+
+/// First page of slot A, which the kernel always runs from.
+const ACTIVE_START: usize = 0x08;
+
+/// Slot B and the control pages, as passed to the kernel's `FirmwareUpdate`.
+const SLOTS: FirmwareSlots = FirmwareSlots::new(0x38, 0x30, 0x68);
+
+/// Page holding a page of slot A while it is being swapped.
+const SCRATCH_PAGE: usize = 0x6a;
+
+static mut PAGE: NrfPage = NrfPage([0; PAGE_SIZE]);
+
+/// Dummy buffer that causes the linker to reserve enough space for the stack.
+#[no_mangle]
+#[link_section = ".stack_buffer"]
+pub static mut STACK_MEMORY: [u8; 0x800] = [0; 0x800];
+
+unsafe fn read_page(page_number: usize, buffer: &mut NrfPage) {
+    let page = core::slice::from_raw_parts((page_number * PAGE_SIZE) as *const u8, PAGE_SIZE);
+    buffer.0.copy_from_slice(page);
+}
+
+unsafe fn copy_page(from: usize, to: usize, buffer: &mut NrfPage) {
+    read_page(from, buffer);
+    NVMC.write_page_blocking(to, buffer);
+}
+
+/// Writes the record with the next sequence number over the older control
+/// page, `next`, which then becomes the other one. The newer control page is
+/// left untouched, so a reset while writing leaves its record in place.
+unsafe fn write_control(control: &mut BootControl, next: &mut usize, buffer: &mut NrfPage) {
+    control.renew();
+    for byte in buffer.0.iter_mut() {
+        *byte = 0xff;
+    }
+    control.encode(&mut buffer.0);
+    NVMC.write_page_blocking(SLOTS.control_page + *next, buffer);
+    *next = 1 - *next;
+}
+
+/// Swaps the kernel slots. The control pages record every step, so that a
+/// swap interrupted by a reset continues where it stopped.
+unsafe fn swap(control: &mut BootControl, next: &mut usize, buffer: &mut NrfPage) {
+    let pages = control.swap_pages(PAGE_SIZE, SLOTS.slot_pages);
+    while let Some((page, step)) = control.swap_step(pages) {
+        match step {
+            SwapStep::SaveActive => copy_page(ACTIVE_START + page, SCRATCH_PAGE, buffer),
+            SwapStep::MoveUpdate => {
+                copy_page(SLOTS.update_start + page, ACTIVE_START + page, buffer)
+            }
+            SwapStep::RestoreActive => copy_page(SCRATCH_PAGE, SLOTS.update_start + page, buffer),
+        }
+        control.advance_swap();
+        write_control(control, next, buffer);
+    }
+    control.swap_done();
+    write_control(control, next, buffer);
+}
+
+/// Starts the kernel whose vector table is at `address`.
+unsafe fn start_kernel(address: usize) -> ! {
+    let vectors = address as *const u32;
+    cortexm4::scb::set_vector_table_offset(vectors as *const ());
+    jump(*vectors, *vectors.offset(1))
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+unsafe fn jump(stack_pointer: u32, reset_handler: u32) -> ! {
+    asm!("msr msp, $0
+          bx $1"
+         :
+         : "r"(stack_pointer), "r"(reset_handler)
+         :
+         : "volatile");
+    loop {}
+}
+
+// Mock implementation for builds on the host.
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+unsafe fn jump(_stack_pointer: u32, _reset_handler: u32) -> ! {
+    unimplemented!()
+}
+
+/// Entry point in the vector table called on hard reset.
+#[no_mangle]
+pub unsafe fn reset_handler() {
+    // Loads relocations and clears BSS
+    nrf52840::init();
+
+    let buffer = &mut PAGE;
+    read_page(SLOTS.control_page, buffer);
+    let first = BootControl::decode(&buffer.0);
+    read_page(SLOTS.control_page + 1, buffer);
+    let second = BootControl::decode(&buffer.0);
+    let (mut control, mut next) = BootControl::newest(first, second);
+    let previous = control;
+
+    let action = control.boot();
+    if control != previous {
+        write_control(&mut control, &mut next, buffer);
+    }
+    if action == BootAction::Swap {
+        swap(&mut control, &mut next, buffer);
+    }
+
+    start_kernel(ACTIVE_START * PAGE_SIZE);
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+#[panic_handler]
+/// Panic handler
+pub unsafe extern "C" fn panic_fmt(_pi: &PanicInfo) -> ! {
+    // Nothing sensible is left to do without a kernel, so wait for a reset.
+    loop {
+        cortexm4::support::nop();
+    }
+}
@@ -9,11 +9,12 @@
 #![cfg_attr(not(doc), no_main)]
 #![deny(missing_docs)]
 
+use capsules::usb::hid::HidKind;
 use kernel::component::Component;
 #[allow(unused_imports)]
 use kernel::{debug, debug_gpio, debug_verbose, static_init};
 use nrf52840::gpio::Pin;
-use nrf52dk_base::{SpiPins, UartChannel, UartPins};
+use nrf52dk_base::{FlashVolume, SpiPins, UartChannel, UartPins};
 
 // The nRF52840 Dongle LEDs
 const LED1_PIN: Pin = Pin::P0_06;
@@ -30,6 +31,15 @@ const UART_TXD: Pin = Pin::P0_15;
 const UART_CTS: Option<Pin> = Some(Pin::P0_17);
 const UART_RXD: Pin = Pin::P0_20;
 
+// Whether to use the native USB port or the UART pins for the console.
+// - Set to true to get a CDC-ACM serial port (e.g. /dev/ttyACM0) on the host.
+// - Set to false to use the UART pins above.
+const USB_CONSOLE: bool = true;
+
+// The kind of USB HID device to offer to applications, if any. It shares the
+// native USB port with the console.
+const USB_HID: Option<HidKind> = None;
+
 const SPI_MOSI: Pin = Pin::P1_01;
 const SPI_MISO: Pin = Pin::P1_02;
 const SPI_CLK: Pin = Pin::P1_04;
@@ -140,6 +150,12 @@ pub unsafe fn reset_handler() {
     let chip = static_init!(nrf52840::chip::Chip, nrf52840::chip::new());
     CHIP = Some(chip);
 
+    let uart_channel = if USB_CONSOLE {
+        UartChannel::Usb
+    } else {
+        UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD))
+    };
+
     nrf52dk_base::setup_board(
         board_kernel,
         BUTTON_RST_PIN,
@@ -149,9 +165,15 @@ pub unsafe fn reset_handler() {
         LED2_G_PIN,
         LED2_B_PIN,
         led,
-        UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD)),
+        uart_channel,
         &SpiPins::new(SPI_MOSI, SPI_MISO, SPI_CLK),
         &None,
+        &None,
+        &None,
+        &None,
+        USB_HID,
+        FlashVolume::None,
+        None,
         button,
         true,
         &mut APP_MEMORY,
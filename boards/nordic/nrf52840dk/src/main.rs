@@ -27,6 +27,9 @@
 //! | 14 | P0.26 | 26 | P4 9   | D14     |
 //! | 15 | P0.27 | 27 | P4 10  | D15     |
 //!
+//! GPIO 14 and 15 are not available when the I2C bus is enabled, and GPIO 10
+//! to 13 are not available when the SPI slave is enabled.
+//!
 //! ### `GPIO` / Analog Inputs
 //!
 //! | #  | Pin        | Header | Arduino |
@@ -54,11 +57,45 @@
 //! | P0.15 | P24 5  | LED 3    |
 //! | P0.16 | P24 6  | LED 4    |
 //! | P0.18 | P24 8  | Reset    |
-//! | P0.19 | P24 9  | SPI CLK  |
-//! | P0.20 | P24 10 | SPI MOSI |
-//! | P0.21 | P24 11 | SPI MISO |
+//! | P0.19 | P24 9  | QSPI SCK |
+//! | P0.20 | P24 10 | QSPI IO0 |
+//! | P0.21 | P24 11 | QSPI IO1 |
 //! | P0.24 | P24 14 | Button 3 |
 //! | P0.25 | P24 15 | Button 4 |
+//!
+//! ### PDM Microphone
+//!
+//! No microphone is mounted on the DK. An external PDM microphone can be
+//! connected to these otherwise unused pins.
+//!
+//! | Pin   | Function |
+//! |-------|----------|
+//! | P1.09 | PDM CLK  |
+//! | P1.00 | PDM DIN  |
+//!
+//! ### I2C
+//!
+//! When `I2C_PINS` is set, the Arduino I2C pins are used by the I2C
+//! master/slave driver instead of GPIO 14 and 15. The bus acts as a master
+//! during master transfers and as a slave otherwise.
+//!
+//! | Pin   | Header | Arduino | Function |
+//! |-------|--------|---------|----------|
+//! | P0.26 | P4 9   | SDA     | I2C SDA  |
+//! | P0.27 | P4 10  | SCL     | I2C SCL  |
+//!
+//! ### SPI Slave
+//!
+//! When `SPI_SLAVE_PINS` is set, the Arduino SPI pins are used by the SPI
+//! slave driver instead of GPIO 10 to 13, so that the board can act as a
+//! peripheral of a host MCU.
+//!
+//! | Pin   | Header | Arduino | Function |
+//! |-------|--------|---------|----------|
+//! | P1.12 | P4 3   | D10     | SPI CSN  |
+//! | P1.13 | P4 4   | D11     | SPI MOSI |
+//! | P1.14 | P4 5   | D12     | SPI MISO |
+//! | P1.15 | P4 6   | D13     | SPI SCK  |
 
 #![no_std]
 // Disable this attribute when documenting, as a workaround for
@@ -70,7 +107,10 @@ use kernel::component::Component;
 #[allow(unused_imports)]
 use kernel::{debug, debug_gpio, debug_verbose, static_init};
 use nrf52840::gpio::Pin;
-use nrf52dk_base::{SpiMX25R6435FPins, SpiPins, UartChannel, UartPins};
+use nrf52dk_base::{
+    FirmwareUpdateChannel, FirmwareUpdateConfig, FlashVolume, I2CPins, MX25R6435FChannel,
+    MicrophonePins, QspiMX25R6435FPins, SpiPins, SpiSlavePins, UartChannel, UartPins,
+};
 
 // The nRF52840DK LEDs (see back of board)
 const LED1_PIN: Pin = Pin::P0_13;
@@ -94,9 +134,34 @@ const SPI_MOSI: Pin = Pin::P0_20;
 const SPI_MISO: Pin = Pin::P0_21;
 const SPI_CLK: Pin = Pin::P0_19;
 
-const SPI_MX25R6435F_CHIP_SELECT: Pin = Pin::P0_17;
-const SPI_MX25R6435F_WRITE_PROTECT_PIN: Pin = Pin::P0_22;
-const SPI_MX25R6435F_HOLD_PIN: Pin = Pin::P0_23;
+// The MX25R6435F flash chip is connected to the QSPI interface.
+const QSPI_MX25R6435F_SCK: Pin = Pin::P0_19;
+const QSPI_MX25R6435F_CSN: Pin = Pin::P0_17;
+const QSPI_MX25R6435F_IO: [Pin; 4] = [Pin::P0_20, Pin::P0_21, Pin::P0_22, Pin::P0_23];
+
+const PDM_CLK: Pin = Pin::P1_09;
+const PDM_DIN: Pin = Pin::P1_00;
+
+// I2C
+const I2C_SDA_PIN: Pin = Pin::P0_26;
+const I2C_SCL_PIN: Pin = Pin::P0_27;
+
+// Whether to use the Arduino I2C pins for the I2C master/slave driver rather
+// than as GPIO 14 and 15.
+// - Set to `Some(I2CPins::new(I2C_SCL_PIN, I2C_SDA_PIN))` to enable I2C.
+const I2C_PINS: Option<I2CPins> = None;
+
+// SPI slave
+const SPI_SLAVE_CSN: Pin = Pin::P1_12;
+const SPI_SLAVE_MOSI: Pin = Pin::P1_13;
+const SPI_SLAVE_MISO: Pin = Pin::P1_14;
+const SPI_SLAVE_SCK: Pin = Pin::P1_15;
+
+// Whether to use the Arduino SPI pins for the SPI slave driver rather than as
+// GPIO 10 to 13.
+// - Set to `Some(SpiSlavePins::new(SPI_SLAVE_SCK, SPI_SLAVE_MISO,
+//   SPI_SLAVE_MOSI, SPI_SLAVE_CSN))` to enable the SPI slave.
+const SPI_SLAVE_PINS: Option<SpiSlavePins> = None;
 
 /// Debug Writer
 pub mod io;
@@ -106,6 +171,24 @@ pub mod io;
 // - Set to true to use Segger RTT over USB.
 const USB_DEBUGGING: bool = false;
 
+// What to do with the first 384 KiB of the MX25R6435F flash chip.
+// - Set to `FlashVolume::UsbMassStorage` to export it as a disk on the nRF USB
+//   port. The host sees the raw flash and has to format it first.
+// - Set to `FlashVolume::Fat` to mount it as a FAT filesystem, giving each app
+//   a directory of its own. It has to be formatted first, for example over
+//   USB mass storage.
+const FLASH_VOLUME: FlashVolume = FlashVolume::None;
+
+// Whether and how to receive kernel updates. Updates require the kernel to be
+// built with `make AB_SLOTS=1` and the boot stage in `../nrf52840_bootstage`.
+// - Set to `Some(FirmwareUpdateChannel::Usb)` for a USB DFU function.
+// - Set to `Some(FirmwareUpdateChannel::Syscall)` for the syscall driver.
+const FIRMWARE_UPDATE: Option<FirmwareUpdateChannel> = None;
+
+// Slot B and the control pages of the A/B layout in `layout_ab.ld`
+const FIRMWARE_SLOTS: capsules::firmware_update::FirmwareSlots =
+    capsules::firmware_update::FirmwareSlots::new(0x38, 0x30, 0x68);
+
 // State for loading and holding applications.
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
@@ -150,29 +233,38 @@ pub unsafe fn reset_handler() {
 
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
-    let gpio = components::gpio::GpioComponent::new(
-        board_kernel,
-        components::gpio_component_helper!(
-            nrf52840::gpio::GPIOPin,
-            0 => &nrf52840::gpio::PORT[Pin::P1_01],
-            1 => &nrf52840::gpio::PORT[Pin::P1_02],
-            2 => &nrf52840::gpio::PORT[Pin::P1_03],
-            3 => &nrf52840::gpio::PORT[Pin::P1_04],
-            4 => &nrf52840::gpio::PORT[Pin::P1_05],
-            5 => &nrf52840::gpio::PORT[Pin::P1_06],
-            6 => &nrf52840::gpio::PORT[Pin::P1_07],
-            7 => &nrf52840::gpio::PORT[Pin::P1_08],
-            8 => &nrf52840::gpio::PORT[Pin::P1_10],
-            9 => &nrf52840::gpio::PORT[Pin::P1_11],
-            10 => &nrf52840::gpio::PORT[Pin::P1_12],
-            11 => &nrf52840::gpio::PORT[Pin::P1_13],
-            12 => &nrf52840::gpio::PORT[Pin::P1_14],
-            13 => &nrf52840::gpio::PORT[Pin::P1_15],
-            14 => &nrf52840::gpio::PORT[Pin::P0_26],
-            15 => &nrf52840::gpio::PORT[Pin::P0_27]
-        ),
-    )
-    .finalize(components::gpio_component_buf!(nrf52840::gpio::GPIOPin));
+    let gpio_pins = components::gpio_component_helper!(
+        nrf52840::gpio::GPIOPin,
+        0 => &nrf52840::gpio::PORT[Pin::P1_01],
+        1 => &nrf52840::gpio::PORT[Pin::P1_02],
+        2 => &nrf52840::gpio::PORT[Pin::P1_03],
+        3 => &nrf52840::gpio::PORT[Pin::P1_04],
+        4 => &nrf52840::gpio::PORT[Pin::P1_05],
+        5 => &nrf52840::gpio::PORT[Pin::P1_06],
+        6 => &nrf52840::gpio::PORT[Pin::P1_07],
+        7 => &nrf52840::gpio::PORT[Pin::P1_08],
+        8 => &nrf52840::gpio::PORT[Pin::P1_10],
+        9 => &nrf52840::gpio::PORT[Pin::P1_11],
+        10 => &nrf52840::gpio::PORT[SPI_SLAVE_CSN],
+        11 => &nrf52840::gpio::PORT[SPI_SLAVE_MOSI],
+        12 => &nrf52840::gpio::PORT[SPI_SLAVE_MISO],
+        13 => &nrf52840::gpio::PORT[SPI_SLAVE_SCK],
+        14 => &nrf52840::gpio::PORT[I2C_SDA_PIN],
+        15 => &nrf52840::gpio::PORT[I2C_SCL_PIN]
+    );
+    if I2C_PINS.is_some() {
+        // Taken by the I2C bus
+        gpio_pins[14] = None;
+        gpio_pins[15] = None;
+    }
+    if SPI_SLAVE_PINS.is_some() {
+        // Taken by the SPI slave
+        for pin in gpio_pins[10..14].iter_mut() {
+            *pin = None;
+        }
+    }
+    let gpio = components::gpio::GpioComponent::new(board_kernel, gpio_pins)
+        .finalize(components::gpio_component_buf!(nrf52840::gpio::GPIOPin));
 
     let button = components::button::ButtonComponent::new(
         board_kernel,
@@ -237,11 +329,17 @@ pub unsafe fn reset_handler() {
         led,
         uart_channel,
         &SpiPins::new(SPI_MOSI, SPI_MISO, SPI_CLK),
-        &Some(SpiMX25R6435FPins::new(
-            SPI_MX25R6435F_CHIP_SELECT,
-            SPI_MX25R6435F_WRITE_PROTECT_PIN,
-            SPI_MX25R6435F_HOLD_PIN,
-        )),
+        &Some(MX25R6435FChannel::Qspi(QspiMX25R6435FPins::new(
+            QSPI_MX25R6435F_SCK,
+            QSPI_MX25R6435F_CSN,
+            QSPI_MX25R6435F_IO,
+        ))),
+        &Some(MicrophonePins::new(PDM_CLK, PDM_DIN)),
+        &I2C_PINS,
+        &SPI_SLAVE_PINS,
+        None,
+        FLASH_VOLUME,
+        FIRMWARE_UPDATE.map(|channel| FirmwareUpdateConfig::new(FIRMWARE_SLOTS, channel)),
         button,
         true,
         &mut APP_MEMORY,
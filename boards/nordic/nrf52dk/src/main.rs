@@ -28,6 +28,14 @@
 //! * P0.30 -> (bottom right header)
 //! * P0.31 -> (bottom right header)
 //!
+//! ### `SPI Slave`
+//! When `SPI_SLAVE_PINS` is set, these top left header pins are used by the
+//! SPI slave driver instead of as GPIOs 8 to 11:
+//! * P0.27 -> SCK
+//! * P0.26 -> MOSI
+//! * P0.02 -> MISO
+//! * P0.25 -> CSN
+//!
 //! ### `LEDs`
 //! * P0.17 -> LED1
 //! * P0.18 -> LED2
@@ -70,7 +78,7 @@ use kernel::component::Component;
 #[allow(unused_imports)]
 use kernel::{debug, debug_gpio, debug_verbose, static_init};
 use nrf52832::gpio::Pin;
-use nrf52dk_base::{SpiPins, UartChannel, UartPins};
+use nrf52dk_base::{FlashVolume, SpiPins, SpiSlavePins, UartChannel, UartPins};
 
 // The nRF52 DK LEDs (see back of board)
 const LED1_PIN: Pin = Pin::P0_17;
@@ -94,6 +102,17 @@ const SPI_MOSI: Pin = Pin::P0_22;
 const SPI_MISO: Pin = Pin::P0_23;
 const SPI_CLK: Pin = Pin::P0_24;
 
+const SPI_SLAVE_SCK: Pin = Pin::P0_27;
+const SPI_SLAVE_MOSI: Pin = Pin::P0_26;
+const SPI_SLAVE_MISO: Pin = Pin::P0_02;
+const SPI_SLAVE_CSN: Pin = Pin::P0_25;
+
+// Whether to use top left header pins for the SPI slave driver rather than as
+// GPIO 8 to 11.
+// - Set to `Some(SpiSlavePins::new(SPI_SLAVE_SCK, SPI_SLAVE_MISO,
+//   SPI_SLAVE_MOSI, SPI_SLAVE_CSN))` to enable the SPI slave.
+const SPI_SLAVE_PINS: Option<SpiSlavePins> = None;
+
 /// UART Writer
 pub mod io;
 
@@ -132,28 +151,32 @@ pub unsafe fn reset_handler() {
 
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
-    let gpio = components::gpio::GpioComponent::new(
-        board_kernel,
-        components::gpio_component_helper!(
-            nrf52832::gpio::GPIOPin,
-            // Bottom right header on DK board
-            0 => &nrf52832::gpio::PORT[Pin::P0_03],
-            1 => &nrf52832::gpio::PORT[Pin::P0_04],
-            2 => &nrf52832::gpio::PORT[Pin::P0_28],
-            3 => &nrf52832::gpio::PORT[Pin::P0_29],
-            4 => &nrf52832::gpio::PORT[Pin::P0_30],
-            5 => &nrf52832::gpio::PORT[Pin::P0_31],
-            // Top mid header on DK board
-            6 => &nrf52832::gpio::PORT[Pin::P0_12],
-            7 => &nrf52832::gpio::PORT[Pin::P0_11],
-            // Top left header on DK board
-            8 => &nrf52832::gpio::PORT[Pin::P0_27],
-            9 => &nrf52832::gpio::PORT[Pin::P0_26],
-            10 => &nrf52832::gpio::PORT[Pin::P0_02],
-            11 => &nrf52832::gpio::PORT[Pin::P0_25]
-        ),
-    )
-    .finalize(components::gpio_component_buf!(nrf52832::gpio::GPIOPin));
+    let gpio_pins = components::gpio_component_helper!(
+        nrf52832::gpio::GPIOPin,
+        // Bottom right header on DK board
+        0 => &nrf52832::gpio::PORT[Pin::P0_03],
+        1 => &nrf52832::gpio::PORT[Pin::P0_04],
+        2 => &nrf52832::gpio::PORT[Pin::P0_28],
+        3 => &nrf52832::gpio::PORT[Pin::P0_29],
+        4 => &nrf52832::gpio::PORT[Pin::P0_30],
+        5 => &nrf52832::gpio::PORT[Pin::P0_31],
+        // Top mid header on DK board
+        6 => &nrf52832::gpio::PORT[Pin::P0_12],
+        7 => &nrf52832::gpio::PORT[Pin::P0_11],
+        // Top left header on DK board
+        8 => &nrf52832::gpio::PORT[SPI_SLAVE_SCK],
+        9 => &nrf52832::gpio::PORT[SPI_SLAVE_MOSI],
+        10 => &nrf52832::gpio::PORT[SPI_SLAVE_MISO],
+        11 => &nrf52832::gpio::PORT[SPI_SLAVE_CSN]
+    );
+    if SPI_SLAVE_PINS.is_some() {
+        // Taken by the SPI slave
+        for pin in gpio_pins[8..12].iter_mut() {
+            *pin = None;
+        }
+    }
+    let gpio = components::gpio::GpioComponent::new(board_kernel, gpio_pins)
+        .finalize(components::gpio_component_buf!(nrf52832::gpio::GPIOPin));
 
     let button = components::button::ButtonComponent::new(
         board_kernel,
@@ -219,6 +242,12 @@ pub unsafe fn reset_handler() {
         UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD)),
         &SpiPins::new(SPI_MOSI, SPI_MISO, SPI_CLK),
         &None,
+        &None,
+        &None,
+        &SPI_SLAVE_PINS,
+        None,
+        FlashVolume::None,
+        None,
         button,
         false,
         &mut APP_MEMORY,
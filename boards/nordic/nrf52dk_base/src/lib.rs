@@ -21,6 +21,20 @@ use nrf52_components::ieee802154::Ieee802154Component;
 const SRC_MAC: u16 = 0xf00f;
 const PAN_ID: u16 = 0xABCD;
 
+// Identification of the USB device carrying the console, HID and mass
+// storage functions. The serial number is the device identifier of the chip.
+const USB_VENDOR_ID: u16 = 0x6667;
+const USB_PRODUCT_ID: u16 = 0xabce;
+const USB_MANUFACTURER: &str = "Tock";
+const USB_PRODUCT: &str = "Tock";
+
+// Vendor and product of the USB mass storage disk
+static USB_MSC_STRINGS: [&str; 2] = ["Tock", "Storage"];
+
+// Time a kernel updated over USB has to run without resetting before it is
+// confirmed. Kernels updated through the driver are confirmed by an app.
+const FIRMWARE_CONFIRM_UPTIME_MS: u32 = 60_000;
+
 /// Pins for SPI for the flash chip MX25R6435F
 #[derive(Debug)]
 pub struct SpiMX25R6435FPins {
@@ -39,6 +53,28 @@ impl SpiMX25R6435FPins {
     }
 }
 
+/// Pins for QSPI for the flash chip MX25R6435F
+#[derive(Debug)]
+pub struct QspiMX25R6435FPins {
+    sck: Pin,
+    csn: Pin,
+    io: [Pin; 4],
+}
+
+impl QspiMX25R6435FPins {
+    pub fn new(sck: Pin, csn: Pin, io: [Pin; 4]) -> Self {
+        Self { sck, csn, io }
+    }
+}
+
+/// Interface to the flash chip MX25R6435F. QSPI is only available on the
+/// nRF52840.
+#[derive(Debug)]
+pub enum MX25R6435FChannel {
+    Spi(SpiMX25R6435FPins),
+    Qspi(QspiMX25R6435FPins),
+}
+
 /// Pins for the SPI driver
 #[derive(Debug)]
 pub struct SpiPins {
@@ -53,6 +89,52 @@ impl SpiPins {
     }
 }
 
+/// Pins for a PDM microphone
+#[derive(Debug)]
+pub struct MicrophonePins {
+    clk: Pin,
+    din: Pin,
+}
+
+impl MicrophonePins {
+    pub fn new(clk: Pin, din: Pin) -> Self {
+        Self { clk, din }
+    }
+}
+
+/// Pins for the I2C bus, used both as master and as slave
+#[derive(Debug)]
+pub struct I2CPins {
+    scl: Pin,
+    sda: Pin,
+}
+
+impl I2CPins {
+    pub const fn new(scl: Pin, sda: Pin) -> Self {
+        Self { scl, sda }
+    }
+}
+
+/// Pins for the SPI bus in slave mode
+#[derive(Debug)]
+pub struct SpiSlavePins {
+    sck: Pin,
+    miso: Pin,
+    mosi: Pin,
+    csn: Pin,
+}
+
+impl SpiSlavePins {
+    pub const fn new(sck: Pin, miso: Pin, mosi: Pin, csn: Pin) -> Self {
+        Self {
+            sck,
+            miso,
+            mosi,
+            csn,
+        }
+    }
+}
+
 /// Pins for the UART
 #[derive(Debug)]
 pub struct UartPins {
@@ -71,6 +153,44 @@ impl UartPins {
 pub enum UartChannel<'a> {
     Pins(UartPins),
     Rtt(components::segger_rtt::SeggerRttMemoryRefs<'a>),
+    /// A CDC-ACM serial port on the native USB port (nRF52840 only)
+    Usb,
+}
+
+/// How kernel update images reach the board
+pub enum FirmwareUpdateChannel {
+    /// A USB DFU function on the native USB port (nRF52840 only)
+    Usb,
+    /// The firmware update driver, for applications receiving images over
+    /// another channel. An application also confirms the new kernel.
+    Syscall,
+}
+
+/// What the kernel region of the MX25R6435F flash chip holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashVolume {
+    /// Nothing, the region is unused
+    None,
+    /// A disk exported on the native USB port (nRF52840 only)
+    UsbMassStorage,
+    /// A FAT filesystem mounted by the kernel, with a directory for each app
+    Fat,
+}
+
+/// Kernel updates through two image slots. The kernel must be linked for
+/// slot A and started by a boot stage that swaps in updates.
+pub struct FirmwareUpdateConfig {
+    slots: capsules::firmware_update::FirmwareSlots,
+    channel: FirmwareUpdateChannel,
+}
+
+impl FirmwareUpdateConfig {
+    pub fn new(
+        slots: capsules::firmware_update::FirmwareSlots,
+        channel: FirmwareUpdateChannel,
+    ) -> Self {
+        Self { slots, channel }
+    }
 }
 
 /// Supported drivers by the platform
@@ -103,6 +223,30 @@ pub struct Platform {
     // The nRF52dk does not have the flash chip on it, so we make this optional.
     nonvolatile_storage:
         Option<&'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>>,
+    // Only boards with a PDM microphone attached provide this.
+    microphone: Option<&'static capsules::microphone::Microphone<'static>>,
+    // Only boards that route I2C pins to a header provide this.
+    i2c_master_slave:
+        Option<&'static capsules::i2c_master_slave_driver::I2CMasterSlaveDriver<'static>>,
+    // Only boards that route SPI slave pins to a header provide this.
+    spi_slave: Option<
+        &'static capsules::spi::SpiSlave<
+            'static,
+            capsules::virtual_spi::VirtualSpiSlaveDevice<'static, nrf52::spis::SPIS>,
+        >,
+    >,
+    // Only boards with a native USB port provide this.
+    usb_hid: Option<
+        &'static capsules::usb::hid_user::HidDriver<
+            'static,
+            capsules::usb::hid::UsbHid<'static, nrf52::usbd::Usbd<'static>>,
+        >,
+    >,
+    // Only boards updating their kernel from applications provide this.
+    firmware_update:
+        Option<&'static capsules::firmware_update_driver::FirmwareUpdateDriver<'static>>,
+    // Only boards keeping a filesystem on the flash chip provide this.
+    fat: Option<&'static capsules::fat_driver::FatDriver<'static>>,
 }
 
 impl kernel::Platform for Platform {
@@ -127,6 +271,16 @@ impl kernel::Platform for Platform {
             capsules::nonvolatile_storage_driver::DRIVER_NUM => {
                 f(self.nonvolatile_storage.map_or(None, |nv| Some(nv)))
             }
+            capsules::microphone::DRIVER_NUM => f(self.microphone.map_or(None, |mic| Some(mic))),
+            capsules::i2c_master_slave_driver::DRIVER_NUM => {
+                f(self.i2c_master_slave.map_or(None, |i2c| Some(i2c)))
+            }
+            capsules::spi::SLAVE_DRIVER_NUM => f(self.spi_slave.map_or(None, |spi| Some(spi))),
+            capsules::usb::hid_user::DRIVER_NUM => f(self.usb_hid.map_or(None, |hid| Some(hid))),
+            capsules::firmware_update_driver::DRIVER_NUM => {
+                f(self.firmware_update.map_or(None, |update| Some(update)))
+            }
+            capsules::fat_driver::DRIVER_NUM => f(self.fat.map_or(None, |fat| Some(fat))),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -146,7 +300,13 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
     led: &'static capsules::led::LED<'static, nrf52::gpio::GPIOPin>,
     uart_channel: UartChannel<'static>,
     spi_pins: &SpiPins,
-    mx25r6435f: &Option<SpiMX25R6435FPins>,
+    mx25r6435f: &Option<MX25R6435FChannel>,
+    microphone: &Option<MicrophonePins>,
+    i2c: &Option<I2CPins>,
+    spi_slave: &Option<SpiSlavePins>,
+    usb_hid: Option<capsules::usb::hid::HidKind>,
+    flash_volume: FlashVolume,
+    firmware_update: Option<FirmwareUpdateConfig>,
     button: &'static capsules::button::Button<'static, nrf52::gpio::GPIOPin>,
     ieee802154: bool,
     app_memory: &mut [u8],
@@ -180,6 +340,54 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(nrf52::rtc::Rtc));
 
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 6], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    let usb_console = match uart_channel {
+        UartChannel::Usb => true,
+        _ => false,
+    };
+    let usb_dfu = match firmware_update {
+        Some(FirmwareUpdateConfig {
+            channel: FirmwareUpdateChannel::Usb,
+            ..
+        }) => true,
+        _ => false,
+    };
+    let usb_mass_storage = flash_volume == FlashVolume::UsbMassStorage;
+    let usb = if usb_console || usb_hid.is_some() || usb_mass_storage || usb_dfu {
+        let serial_number = static_init!([u8; 16], [0; 16]);
+        let id = nrf52::ficr::FICR_INSTANCE.id();
+        for (i, digit) in serial_number.iter_mut().enumerate() {
+            *digit = b"0123456789abcdef"[(id >> (60 - 4 * i) & 0xf) as usize];
+        }
+        let strings = static_init!(
+            [&str; 3],
+            [
+                USB_MANUFACTURER,
+                USB_PRODUCT,
+                core::str::from_utf8(serial_number).unwrap_or("0"),
+            ]
+        );
+        let usb = components::usb_composite::UsbCompositeComponent::new(
+            &nrf52::usbd::USBD,
+            USB_VENDOR_ID,
+            USB_PRODUCT_ID,
+            strings,
+        )
+        .finalize(components::usb_composite_component_helper!(
+            nrf52::usbd::Usbd<'static>
+        ));
+        Some(usb)
+    } else {
+        None
+    };
+
     let channel: &dyn kernel::hil::uart::Uart = match uart_channel {
         UartChannel::Pins(uart_pins) => {
             nrf52::uart::UARTE0.initialize(
@@ -195,16 +403,19 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
                 .finalize(components::segger_rtt_component_helper!(nrf52::rtc::Rtc));
             rtt
         }
+        UartChannel::Usb => {
+            let cdc =
+                components::cdc::CdcAcmComponent::new(&nrf52::usbd::USBD, dynamic_deferred_caller)
+                    .finalize(components::cdc_acm_component_helper!(
+                        nrf52::usbd::Usbd<'static>
+                    ));
+            if let Some(usb) = usb {
+                usb.add_function(cdc, "Console");
+            }
+            cdc
+        }
     };
 
-    let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 2], Default::default());
-    let dynamic_deferred_caller = static_init!(
-        DynamicDeferredCall,
-        DynamicDeferredCall::new(dynamic_deferred_call_clients)
-    );
-    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
-
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux =
         components::console::UartMuxComponent::new(channel, 115200, dynamic_deferred_caller)
@@ -253,42 +464,225 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
 
     let nonvolatile_storage: Option<
         &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
-    > = if let Some(driver) = mx25r6435f {
-        let mx25r6435f = components::mx25r6435f::Mx25r6435fComponent::new(
-            &gpio_port[driver.write_protect_pin],
-            &gpio_port[driver.hold_pin],
-            &gpio_port[driver.chip_select] as &dyn kernel::hil::gpio::Pin,
-            mux_alarm,
-            mux_spi,
+    > = match mx25r6435f {
+        Some(MX25R6435FChannel::Spi(driver)) => {
+            let mx25r6435f = components::mx25r6435f::Mx25r6435fComponent::new(
+                &gpio_port[driver.write_protect_pin],
+                &gpio_port[driver.hold_pin],
+                &gpio_port[driver.chip_select] as &dyn kernel::hil::gpio::Pin,
+                mux_alarm,
+                mux_spi,
+            )
+            .finalize(components::mx25r6435f_component_helper!(
+                nrf52::spi::SPIM,
+                nrf52::gpio::GPIOPin,
+                nrf52::rtc::Rtc
+            ));
+
+            let nonvolatile_storage =
+                components::nonvolatile_storage::NonvolatileStorageComponent::new(
+                    board_kernel,
+                    mx25r6435f,
+                    0x60000, // Start address for userspace accessible region
+                    0x20000, // Length of userspace accessible region
+                    0,       // Start address of kernel region
+                    0x60000, // Length of kernel region
+                    0x2000,  // Bytes of each app
+                )
+                .finalize(components::nv_storage_component_helper!(
+                    capsules::mx25r6435f::MX25R6435F<
+                        'static,
+                        capsules::virtual_spi::VirtualSpiMasterDevice<'static, nrf52::spi::SPIM>,
+                        nrf52::gpio::GPIOPin,
+                        VirtualMuxAlarm<'static, nrf52::rtc::Rtc>,
+                    >
+                ));
+            Some(nonvolatile_storage)
+        }
+        Some(MX25R6435FChannel::Qspi(pins)) => {
+            // SPIM0 is only used for the flash chip, and would otherwise hold
+            // on to the pins if they are shared with the QSPI interface.
+            nrf52::spi::SPIM0.disable();
+            nrf52::qspi::QSPI.initialize(
+                nrf52::pinmux::Pinmux::new(pins.sck as u32),
+                nrf52::pinmux::Pinmux::new(pins.csn as u32),
+                [
+                    nrf52::pinmux::Pinmux::new(pins.io[0] as u32),
+                    nrf52::pinmux::Pinmux::new(pins.io[1] as u32),
+                    nrf52::pinmux::Pinmux::new(pins.io[2] as u32),
+                    nrf52::pinmux::Pinmux::new(pins.io[3] as u32),
+                ],
+            );
+            let qspi_flash = components::qspi_flash::QspiFlashComponent::new(&nrf52::qspi::QSPI)
+                .finalize(components::qspi_flash_component_helper!(
+                    nrf52::qspi::Qspi<'static>
+                ));
+
+            let nonvolatile_storage =
+                components::nonvolatile_storage::NonvolatileStorageComponent::new(
+                    board_kernel,
+                    qspi_flash,
+                    0x60000, // Start address for userspace accessible region
+                    0x20000, // Length of userspace accessible region
+                    0,       // Start address of kernel region
+                    0x60000, // Length of kernel region
+                    0x2000,  // Bytes of each app
+                )
+                .finalize(components::nv_storage_component_helper!(
+                    capsules::qspi_flash::QspiFlash<'static, nrf52::qspi::Qspi<'static>>
+                ));
+            Some(nonvolatile_storage)
+        }
+        None => None,
+    };
+
+    let microphone = microphone.as_ref().map(|pins| {
+        nrf52::pdm::PDM.set_pins(
+            nrf52::pinmux::Pinmux::new(pins.clk as u32),
+            nrf52::pinmux::Pinmux::new(pins.din as u32),
+        );
+        components::microphone::MicrophoneComponent::new(board_kernel, &nrf52::pdm::PDM)
+            .finalize(())
+    });
+
+    // TWIM1 and TWIS1 share their registers, so the bus switches between
+    // master and slave mode as needed. Instance 0 is left to SPIM0.
+    let i2c_master_slave = i2c.as_ref().map(|pins| {
+        let scl = nrf52::pinmux::Pinmux::new(pins.scl as u32);
+        let sda = nrf52::pinmux::Pinmux::new(pins.sda as u32);
+        nrf52::i2c::TWIM1.configure(scl, sda);
+        nrf52::i2c::TWIM1.set_speed(nrf52::i2c::Speed::K400);
+        nrf52::twis::TWIS1.configure(scl, sda);
+        let twi = static_init!(
+            nrf52::i2c::TWIMasterSlave,
+            nrf52::i2c::TWIMasterSlave::new(&nrf52::i2c::TWIM1, &nrf52::twis::TWIS1)
+        );
+        nrf52::i2c::TWIM1.set_client(twi);
+        components::i2c::I2CMasterSlaveDriverComponent::new(twi)
+            .finalize(components::i2c_master_slave_driver_component_helper!())
+    });
+
+    // SPIS2 is the only SPI instance that shares its registers with neither
+    // SPIM0 nor the I2C bus.
+    let spi_slave = spi_slave.as_ref().map(|pins| {
+        nrf52::spis::SPIS2.configure(
+            nrf52::pinmux::Pinmux::new(pins.sck as u32),
+            nrf52::pinmux::Pinmux::new(pins.miso as u32),
+            nrf52::pinmux::Pinmux::new(pins.mosi as u32),
+            nrf52::pinmux::Pinmux::new(pins.csn as u32),
+        );
+        components::spi::SpiSlaveSyscallComponent::new(&nrf52::spis::SPIS2).finalize(
+            components::spi_slave_syscall_component_helper!(nrf52::spis::SPIS),
         )
-        .finalize(components::mx25r6435f_component_helper!(
-            nrf52::spi::SPIM,
-            nrf52::gpio::GPIOPin,
-            nrf52::rtc::Rtc
-        ));
+    });
 
-        let nonvolatile_storage =
-            components::nonvolatile_storage::NonvolatileStorageComponent::new(
+    let usb_hid = match usb_hid {
+        Some(kind) => {
+            let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
                 board_kernel,
-                mx25r6435f,
-                0x60000, // Start address for userspace accessible region
-                0x20000, // Length of userspace accessible region
-                0,       // Start address of kernel region
-                0x60000, // Length of kernel region
+                &nrf52::usbd::USBD,
+                kind,
+                dynamic_deferred_caller,
             )
-            .finalize(components::nv_storage_component_helper!(
-                capsules::mx25r6435f::MX25R6435F<
-                    'static,
-                    capsules::virtual_spi::VirtualSpiMasterDevice<'static, nrf52::spi::SPIM>,
-                    nrf52::gpio::GPIOPin,
-                    VirtualMuxAlarm<'static, nrf52::rtc::Rtc>,
-                >
+            .finalize(components::usb_hid_component_helper!(
+                nrf52::usbd::Usbd<'static>
             ));
-        Some(nonvolatile_storage)
+            if let Some(usb) = usb {
+                usb.add_function(hid, "HID");
+            }
+            Some(hid_driver)
+        }
+        None => None,
+    };
+
+    let fat = if flash_volume != FlashVolume::None {
+        // The kernel region of the flash chip holds a volume, which is either
+        // exported as a disk or mounted by the kernel
+        let nonvolatile_storage = nonvolatile_storage
+            .expect("USB mass storage and the FAT filesystem require the MX25R6435F flash chip");
+        let volume = static_init!(
+            capsules::block_storage::NonvolatileBlocks<'static>,
+            capsules::block_storage::NonvolatileBlocks::new(nonvolatile_storage, 0, 0x60000)
+        );
+        kernel::hil::nonvolatile_storage::NonvolatileStorage::set_client(
+            nonvolatile_storage,
+            volume,
+        );
+        if usb_mass_storage {
+            let msc = components::usb_msc::UsbMassStorageComponent::new(
+                &nrf52::usbd::USBD,
+                volume,
+                false,
+                &USB_MSC_STRINGS,
+                dynamic_deferred_caller,
+            )
+            .finalize(components::usb_msc_component_helper!(
+                nrf52::usbd::Usbd<'static>
+            ));
+            if let Some(usb) = usb {
+                usb.add_function(msc, "Storage");
+            }
+            None
+        } else {
+            let (_fat, fat_driver) =
+                components::fat::FatComponent::new(board_kernel, volume, dynamic_deferred_caller)
+                    .finalize(());
+            Some(fat_driver)
+        }
     } else {
         None
     };
 
+    let firmware_update_driver = match firmware_update {
+        Some(config) => {
+            let sha = components::sha256::Sha256SoftwareComponent::new(dynamic_deferred_caller)
+                .finalize(());
+            let update = components::firmware_update::FirmwareUpdateComponent::new(
+                &nrf52::nvmc::NVMC,
+                sha,
+                config.slots,
+                dynamic_deferred_caller,
+            )
+            .finalize(components::firmware_update_component_helper!(
+                nrf52::nvmc::Nvmc,
+                capsules::sha256::Sha256Software<'static>
+            ));
+            match config.channel {
+                FirmwareUpdateChannel::Usb => {
+                    let dfu = components::usb_dfu::UsbDfuComponent::new(update).finalize(());
+                    if let Some(usb) = usb {
+                        usb.add_function(dfu, "Firmware");
+                    }
+                    components::firmware_update::FirmwareConfirmComponent::new(
+                        update,
+                        mux_alarm,
+                        FIRMWARE_CONFIRM_UPTIME_MS,
+                    )
+                    .finalize(components::firmware_confirm_component_helper!(
+                        nrf52::rtc::Rtc
+                    ));
+                    None
+                }
+                FirmwareUpdateChannel::Syscall => Some(
+                    components::firmware_update::FirmwareUpdateDriverComponent::new(
+                        board_kernel,
+                        update,
+                    )
+                    .finalize(()),
+                ),
+            }
+        }
+        None => None,
+    };
+
+    if let Some(usb) = usb {
+        nrf52::usbd::USBD.set_client(usb);
+        nrf52::power::POWER.set_usb_client(&nrf52::usbd::USBD);
+        nrf52::power::POWER.enable_interrupts();
+        kernel::hil::usb::Client::enable(usb);
+        kernel::hil::usb::Client::attach(usb);
+    }
+
     // Initialize AC using AIN5 (P0.29) as VIN+ and VIN- as AIN0 (P0.02)
     // These are hardcoded pin assignments specified in the driver
     let analog_comparator = components::analog_comparator::AcComponent::new(
@@ -312,6 +706,12 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
         alarm,
         analog_comparator,
         nonvolatile_storage,
+        microphone,
+        i2c_master_slave,
+        spi_slave,
+        usb_hid,
+        firmware_update: firmware_update_driver,
+        fat,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
     };
 
@@ -19,6 +19,7 @@ use kernel::component::Component;
 use kernel::hil::radio;
 use kernel::hil::radio::RadioData;
 use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init};
 
 // Save some deep nesting
@@ -78,6 +79,8 @@ impl Component for Ieee802154Component {
         );
         self.radio.set_transmit_client(awake_mac);
         self.radio.set_receive_client(awake_mac, &mut RADIO_RX_BUF);
+        // The radio times backoffs and acknowledgements with TIMER0
+        nrf52::timer::TIMER0.set_client(self.radio);
 
         let mac_device = static_init!(
             capsules::ieee802154::framer::Framer<
@@ -58,6 +58,7 @@ struct NucleoF429ZI {
         'static,
         VirtualMuxAlarm<'static, stm32f429zi::tim2::Tim2<'static>>,
     >,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static>,
     gpio: &'static capsules::gpio::GPIO<'static, stm32f429zi::gpio::Pin<'static>>,
 }
 
@@ -72,6 +73,7 @@ impl Platform for NucleoF429ZI {
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             _ => f(None),
@@ -164,6 +166,7 @@ unsafe fn set_pin_primary_functions() {
 
 /// Helper function for miscellaneous peripheral functions
 unsafe fn setup_peripherals() {
+    use stm32f429zi::rtc::RTC;
     use stm32f429zi::tim2::TIM2;
 
     // USART3 IRQn is 39
@@ -173,6 +176,10 @@ unsafe fn setup_peripherals() {
     TIM2.enable_clock();
     TIM2.start();
     cortexm4::nvic::Nvic::new(stm32f429zi::nvic::TIM2).enable();
+
+    // RTC
+    RTC.enable_clock();
+    RTC.enable();
 }
 
 /// Reset Handler.
@@ -299,6 +306,12 @@ pub unsafe fn reset_handler() {
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(stm32f429zi::tim2::Tim2));
 
+    // DATE AND TIME
+
+    let date_time =
+        components::date_time::DateTimeComponent::new(board_kernel, &stm32f429zi::rtc::RTC)
+            .finalize(());
+
     // GPIO
     let gpio = GpioComponent::new(
         board_kernel,
@@ -406,6 +419,7 @@ pub unsafe fn reset_handler() {
         led: led,
         button: button,
         alarm: alarm,
+        date_time: date_time,
         gpio: gpio,
     };
 
@@ -63,6 +63,12 @@ struct NucleoF446RE {
         'static,
         VirtualMuxAlarm<'static, stm32f446re::tim2::Tim2<'static>>,
     >,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static>,
+    spi_slave: &'static capsules::spi::SpiSlave<
+        'static,
+        capsules::virtual_spi::VirtualSpiSlaveDevice<'static, stm32f446re::spi::Spi<'static>>,
+    >,
+    i2c_master_slave: &'static capsules::i2c_master_slave_driver::I2CMasterSlaveDriver<'static>,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -76,6 +82,9 @@ impl Platform for NucleoF446RE {
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
+            capsules::spi::SLAVE_DRIVER_NUM => f(Some(self.spi_slave)),
+            capsules::i2c_master_slave_driver::DRIVER_NUM => f(Some(self.i2c_master_slave)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -85,6 +94,8 @@ impl Platform for NucleoF446RE {
 /// Helper function called during bring-up that configures DMA.
 unsafe fn setup_dma() {
     use stm32f446re::dma1::{Dma1Peripheral, DMA1};
+    use stm32f446re::spi;
+    use stm32f446re::spi::SPI3;
     use stm32f446re::usart;
     use stm32f446re::usart::USART2;
 
@@ -106,6 +117,20 @@ unsafe fn setup_dma() {
 
     cortexm4::nvic::Nvic::new(Dma1Peripheral::USART2_TX.get_stream_irqn()).enable();
     cortexm4::nvic::Nvic::new(Dma1Peripheral::USART2_RX.get_stream_irqn()).enable();
+
+    let spi3_tx_stream = Dma1Peripheral::SPI3_TX.get_stream();
+    let spi3_rx_stream = Dma1Peripheral::SPI3_RX.get_stream();
+
+    SPI3.set_dma(spi::TxDMA(spi3_tx_stream), spi::RxDMA(spi3_rx_stream));
+
+    spi3_tx_stream.set_client(&SPI3);
+    spi3_rx_stream.set_client(&SPI3);
+
+    spi3_tx_stream.setup(Dma1Peripheral::SPI3_TX);
+    spi3_rx_stream.setup(Dma1Peripheral::SPI3_RX);
+
+    cortexm4::nvic::Nvic::new(Dma1Peripheral::SPI3_TX.get_stream_irqn()).enable();
+    cortexm4::nvic::Nvic::new(Dma1Peripheral::SPI3_RX.get_stream_irqn()).enable();
 }
 
 /// Helper function called during bring-up that configures multiplexed I/O.
@@ -138,8 +163,49 @@ unsafe fn set_pin_primary_functions() {
         pin.set_alternate_function(AlternateFunction::AF7);
     });
 
+    // pa15 is NSS of SPI3, used in slave mode
+    PinId::PA15.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        // AF6 is SPI3_NSS
+        pin.set_alternate_function(AlternateFunction::AF6);
+    });
+
+    PORT[PortId::B as usize].enable_clock();
+
+    // pb8 and pb9 (I2C1) are connected to the SCL and SDA pins of the
+    // Arduino header
+    PinId::PB08.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        pin.set_mode_output_opendrain();
+        // AF4 is I2C1_SCL
+        pin.set_alternate_function(AlternateFunction::AF4);
+    });
+    PinId::PB09.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        pin.set_mode_output_opendrain();
+        // AF4 is I2C1_SDA
+        pin.set_alternate_function(AlternateFunction::AF4);
+    });
+
     PORT[PortId::C as usize].enable_clock();
 
+    // pc10, pc11 and pc12 (SPI3) are SCK, MISO and MOSI
+    PinId::PC10.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        // AF6 is SPI3_SCK
+        pin.set_alternate_function(AlternateFunction::AF6);
+    });
+    PinId::PC11.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        // AF6 is SPI3_MISO
+        pin.set_alternate_function(AlternateFunction::AF6);
+    });
+    PinId::PC12.get_pin().as_ref().map(|pin| {
+        pin.set_mode(Mode::AlternateFunctionMode);
+        // AF6 is SPI3_MOSI
+        pin.set_alternate_function(AlternateFunction::AF6);
+    });
+
     // button is connected on pc13
     PinId::PC13.get_pin().as_ref().map(|pin| {
         // By default, upon reset, the pin is in input mode, with no internal
@@ -155,6 +221,9 @@ unsafe fn set_pin_primary_functions() {
 
 /// Helper function for miscellaneous peripheral functions
 unsafe fn setup_peripherals() {
+    use stm32f446re::i2c::{I2CSpeed, I2C1};
+    use stm32f446re::rtc::RTC;
+    use stm32f446re::spi::SPI3;
     use stm32f446re::tim2::TIM2;
 
     // USART2 IRQn is 38
@@ -164,6 +233,19 @@ unsafe fn setup_peripherals() {
     TIM2.enable_clock();
     TIM2.start();
     cortexm4::nvic::Nvic::new(stm32f446re::nvic::TIM2).enable();
+
+    // RTC
+    RTC.enable_clock();
+    RTC.enable();
+
+    // SPI3
+    SPI3.enable_clock();
+
+    // I2C1 IRQn is 31 (events) and 32 (errors)
+    I2C1.enable_clock();
+    I2C1.set_speed(I2CSpeed::Speed100k, 16);
+    cortexm4::nvic::Nvic::new(stm32f446re::nvic::I2C1_EV).enable();
+    cortexm4::nvic::Nvic::new(stm32f446re::nvic::I2C1_ER).enable();
 }
 
 /// Reset Handler.
@@ -281,12 +363,34 @@ pub unsafe fn reset_handler() {
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(stm32f446re::tim2::Tim2));
 
+    // DATE AND TIME
+
+    let date_time =
+        components::date_time::DateTimeComponent::new(board_kernel, &stm32f446re::rtc::RTC)
+            .finalize(());
+
+    // SPI SLAVE
+
+    let spi_slave = components::spi::SpiSlaveSyscallComponent::new(&stm32f446re::spi::SPI3)
+        .finalize(components::spi_slave_syscall_component_helper!(
+            stm32f446re::spi::Spi<'static>
+        ));
+
+    // I2C MASTER/SLAVE
+
+    let i2c_master_slave =
+        components::i2c::I2CMasterSlaveDriverComponent::new(&stm32f446re::i2c::I2C1)
+            .finalize(components::i2c_master_slave_driver_component_helper!());
+
     let nucleo_f446re = NucleoF446RE {
         console: console,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
         led: led,
         button: button,
         alarm: alarm,
+        date_time: date_time,
+        spi_slave: spi_slave,
+        i2c_master_slave: i2c_master_slave,
     };
 
     // // Optional kernel tests
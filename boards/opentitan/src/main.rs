@@ -226,8 +226,11 @@ pub unsafe fn reset_handler() {
     );
     hil::time::Alarm::set_client(virtual_alarm_user, alarm);
 
-    // Setup the console.
+    // Setup the console and the process inspection console.
     let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(());
     // Create the debugger object that handles calls to `debug!()`.
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
@@ -294,5 +297,7 @@ pub unsafe fn reset_handler() {
         debug!("{:?}", err);
     });
 
+    process_console.start();
+
     board_kernel.kernel_loop(&opentitan, chip, None, &main_loop_cap);
 }
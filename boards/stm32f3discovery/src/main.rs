@@ -67,6 +67,11 @@ struct STM32F3Discovery {
     l3gd20: &'static capsules::l3gd20::L3gd20Spi<'static>,
     lsm303dlhc: &'static capsules::lsm303dlhc::Lsm303dlhcI2C<'static>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
+    spi_slave: &'static capsules::spi::SpiSlave<
+        'static,
+        capsules::virtual_spi::VirtualSpiSlaveDevice<'static, stm32f303xc::spi::Spi<'static>>,
+    >,
+    i2c_master_slave: &'static capsules::i2c_master_slave_driver::I2CMasterSlaveDriver<'static>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         VirtualMuxAlarm<'static, stm32f303xc::tim2::Tim2<'static>>,
@@ -89,6 +94,8 @@ impl Platform for STM32F3Discovery {
             capsules::lsm303dlhc::DRIVER_NUM => f(Some(self.lsm303dlhc)),
             capsules::ninedof::DRIVER_NUM => f(Some(self.ninedof)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
+            capsules::spi::SLAVE_DRIVER_NUM => f(Some(self.spi_slave)),
+            capsules::i2c_master_slave_driver::DRIVER_NUM => f(Some(self.i2c_master_slave)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -193,6 +200,33 @@ unsafe fn set_pin_primary_functions() {
 
     stm32f303xc::i2c::I2C1.enable_clock();
     stm32f303xc::i2c::I2C1.set_speed(stm32f303xc::i2c::I2CSpeed::Speed400k, 8);
+
+    // SPI2 is a slave on the right inner connector: pb12 is NSS, pb13 SCK,
+    // pb14 MISO and pb15 MOSI
+    for pin_id in [PinId::PB12, PinId::PB13, PinId::PB14, PinId::PB15].iter() {
+        pin_id.get_pin().as_ref().map(|pin| {
+            pin.set_mode(Mode::AlternateFunctionMode);
+            pin.set_floating_state(kernel::hil::gpio::FloatingState::PullNone);
+            // AF5 is SPI1/SPI2
+            pin.set_alternate_function(AlternateFunction::AF5);
+        });
+    }
+
+    stm32f303xc::spi::SPI2.enable_clock();
+
+    // I2C2 is a master and slave on the right inner connector: pa09 is SCL
+    // and pa10 SDA
+    for pin_id in [PinId::PA09, PinId::PA10].iter() {
+        pin_id.get_pin().as_ref().map(|pin| {
+            pin.set_mode(Mode::AlternateFunctionMode);
+            pin.set_floating_state(kernel::hil::gpio::FloatingState::PullNone);
+            // AF4 is I2C
+            pin.set_alternate_function(AlternateFunction::AF4);
+        });
+    }
+
+    stm32f303xc::i2c::I2C2.enable_clock();
+    stm32f303xc::i2c::I2C2.set_speed(stm32f303xc::i2c::I2CSpeed::Speed400k, 8);
 }
 
 /// Helper function for miscellaneous peripheral functions
@@ -206,6 +240,11 @@ unsafe fn setup_peripherals() {
     TIM2.enable_clock();
     TIM2.start();
     cortexm4::nvic::Nvic::new(stm32f303xc::nvic::TIM2).enable();
+
+    // SPI2 IRQn is 36, I2C2 IRQn is 33 (events) and 34 (errors)
+    cortexm4::nvic::Nvic::new(stm32f303xc::nvic::SPI2).enable();
+    cortexm4::nvic::Nvic::new(stm32f303xc::nvic::I2C2_EV).enable();
+    cortexm4::nvic::Nvic::new(stm32f303xc::nvic::I2C2_ER).enable();
 }
 
 /// Reset Handler.
@@ -375,8 +414,8 @@ pub unsafe fn reset_handler() {
             11 => stm32f303xc::gpio::PinId::PE11.get_pin().as_ref().unwrap(),
             12 => stm32f303xc::gpio::PinId::PE13.get_pin().as_ref().unwrap(),
             13 => stm32f303xc::gpio::PinId::PB11.get_pin().as_ref().unwrap(),
-            14 => stm32f303xc::gpio::PinId::PB13.get_pin().as_ref().unwrap(),
-            15 => stm32f303xc::gpio::PinId::PB15.get_pin().as_ref().unwrap(),
+            // 14 => stm32f303xc::gpio::PinId::PB13.get_pin().as_ref().unwrap(),
+            // 15 => stm32f303xc::gpio::PinId::PB15.get_pin().as_ref().unwrap(),
             16 => stm32f303xc::gpio::PinId::PD09.get_pin().as_ref().unwrap(),
             17 => stm32f303xc::gpio::PinId::PD11.get_pin().as_ref().unwrap(),
             18 => stm32f303xc::gpio::PinId::PD13.get_pin().as_ref().unwrap(),
@@ -398,8 +437,8 @@ pub unsafe fn reset_handler() {
             33 => stm32f303xc::gpio::PinId::PE12.get_pin().as_ref().unwrap(),
             34 => stm32f303xc::gpio::PinId::PE14.get_pin().as_ref().unwrap(),
             35 => stm32f303xc::gpio::PinId::PB10.get_pin().as_ref().unwrap(),
-            36 => stm32f303xc::gpio::PinId::PB12.get_pin().as_ref().unwrap(),
-            37 => stm32f303xc::gpio::PinId::PB14.get_pin().as_ref().unwrap(),
+            // 36 => stm32f303xc::gpio::PinId::PB12.get_pin().as_ref().unwrap(),
+            // 37 => stm32f303xc::gpio::PinId::PB14.get_pin().as_ref().unwrap(),
             38 => stm32f303xc::gpio::PinId::PD08.get_pin().as_ref().unwrap(),
             39 => stm32f303xc::gpio::PinId::PD10.get_pin().as_ref().unwrap(),
             40 => stm32f303xc::gpio::PinId::PD14.get_pin().as_ref().unwrap(),
@@ -422,7 +461,7 @@ pub unsafe fn reset_handler() {
             56 => stm32f303xc::gpio::PinId::PA14.get_pin().as_ref().unwrap(),
             57 => stm32f303xc::gpio::PinId::PF06.get_pin().as_ref().unwrap(),
             58 => stm32f303xc::gpio::PinId::PA12.get_pin().as_ref().unwrap(),
-            59 => stm32f303xc::gpio::PinId::PA10.get_pin().as_ref().unwrap(),
+            // 59 => stm32f303xc::gpio::PinId::PA10.get_pin().as_ref().unwrap(),
             60 => stm32f303xc::gpio::PinId::PA08.get_pin().as_ref().unwrap(),
             61 => stm32f303xc::gpio::PinId::PC08.get_pin().as_ref().unwrap(),
             // Right outer connector
@@ -443,7 +482,7 @@ pub unsafe fn reset_handler() {
             76 => stm32f303xc::gpio::PinId::PA15.get_pin().as_ref().unwrap(),
             77 => stm32f303xc::gpio::PinId::PA13.get_pin().as_ref().unwrap(),
             78 => stm32f303xc::gpio::PinId::PA11.get_pin().as_ref().unwrap(),
-            79 => stm32f303xc::gpio::PinId::PA09.get_pin().as_ref().unwrap(),
+            // 79 => stm32f303xc::gpio::PinId::PA09.get_pin().as_ref().unwrap(),
             80 => stm32f303xc::gpio::PinId::PC09.get_pin().as_ref().unwrap()
         ),
     )
@@ -498,6 +537,17 @@ pub unsafe fn reset_handler() {
     let ninedof = components::ninedof::NineDofComponent::new(board_kernel)
         .finalize(components::ninedof_component_helper!(l3gd20, lsm303dlhc));
 
+    // SPI and I2C peripheral mode, for acting as a peripheral of a host MCU
+
+    let spi_slave = components::spi::SpiSlaveSyscallComponent::new(&stm32f303xc::spi::SPI2)
+        .finalize(components::spi_slave_syscall_component_helper!(
+            stm32f303xc::spi::Spi
+        ));
+
+    let i2c_master_slave =
+        components::i2c::I2CMasterSlaveDriverComponent::new(&stm32f303xc::i2c::I2C2)
+            .finalize(components::i2c_master_slave_driver_component_helper!());
+
     let stm32f3discovery = STM32F3Discovery {
         console: console,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
@@ -509,6 +559,8 @@ pub unsafe fn reset_handler() {
         lsm303dlhc: lsm303dlhc,
         ninedof: ninedof,
         temp: temp,
+        spi_slave: spi_slave,
+        i2c_master_slave: i2c_master_slave,
     };
 
     // // Optional kernel tests
@@ -1,7 +1,7 @@
 //! Provides userspace applications with a alarm API.
 
 use core::cell::Cell;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Frequency, TicksAlarm};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
@@ -11,12 +11,12 @@ pub const DRIVER_NUM: usize = driver::NUM::Alarm as usize;
 /// This is based on an exprimental observation (using 16KHz timers) to make sure
 /// that the new tics value is not below the current counter
 /// so the alarm gets fired
-const MIN_TICS_AT_16KHZ: usize = 5;
+const MIN_TICS_AT_16KHZ: u64 = 5;
 
 #[derive(Copy, Clone, Debug)]
 enum Expiration {
     Disabled,
-    Abs(u32),
+    Abs(u64),
 }
 
 #[derive(Copy, Clone)]
@@ -34,49 +34,53 @@ impl Default for AlarmData {
     }
 }
 
-pub struct AlarmDriver<'a, A: Alarm<'a>> {
+pub struct AlarmDriver<'a, A: TicksAlarm<'a>> {
     alarm: &'a A,
     num_armed: Cell<usize>,
     app_alarm: Grant<AlarmData>,
-    prev: Cell<u32>,
 }
 
-impl<A: Alarm<'a>> AlarmDriver<'a, A> {
+impl<A: TicksAlarm<'a>> AlarmDriver<'a, A> {
     pub const fn new(alarm: &'a A, grant: Grant<AlarmData>) -> AlarmDriver<'a, A> {
         AlarmDriver {
             alarm: alarm,
             num_armed: Cell::new(0),
             app_alarm: grant,
-            prev: Cell::new(0),
         }
     }
 
-    fn reset_active_alarm(&self, now: u32) -> Option<u32> {
-        self.prev.set(now);
-        let mut next_alarm = u32::max_value();
-        let mut next_dist = u32::max_value();
+    fn reset_active_alarm(&self) {
+        let mut next_alarm = None;
         for alarm in self.app_alarm.iter() {
             alarm.enter(|alarm, _| match alarm.expiration {
                 Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+                    if next_alarm.map_or(true, |next| exp < next) {
+                        next_alarm = Some(exp);
                     }
                 }
                 Expiration::Disabled => {}
             });
         }
-        if next_alarm != u32::max_value() {
-            self.alarm.set_alarm(next_alarm);
-            Some(next_alarm)
-        } else {
-            None
+        match next_alarm {
+            Some(exp) => self.alarm.set_alarm_ticks(exp),
+            None => self.alarm.disable(),
         }
     }
 }
 
-impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
+/// Userspace sees the low 32 bits of the extended clock. Expand such a value
+/// to the nearest matching time to `now`, so values up to half the 32-bit
+/// range behind `now` are taken to be in the past.
+fn expand(when: u32, now: u64) -> u64 {
+    let ahead = when.wrapping_sub(now as u32);
+    if ahead < 1 << 31 {
+        now + ahead as u64
+    } else {
+        now.saturating_sub((now as u32).wrapping_sub(when) as u64)
+    }
+}
+
+impl<A: TicksAlarm<'a>> Driver for AlarmDriver<'a, A> {
     /// Subscribe to alarm expiration
     ///
     /// ### `_subscribe_num`
@@ -98,6 +102,9 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
 
     /// Setup and read the alarm.
     ///
+    /// Clock values are the low 32 bits of a 64-bit clock that does not wrap
+    /// in practice, regardless of the width of the hardware counter.
+    ///
     /// ### `command_num`
     ///
     /// - `0`: Driver check.
@@ -106,7 +113,10 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
     /// - `3`: Stop the alarm if it is outstanding
     /// - `4`: Set an alarm to fire at a given clock value `time`.
     /// - `5`: Set an alarm to fire at a given clock value `time` relative to `now` (EXPERIMENTAL).
-    fn command(&self, cmd_type: usize, data: usize, _: usize, caller_id: AppId) -> ReturnCode {
+    /// - `6`: Read the upper 32 bits of the current 64-bit clock value.
+    /// - `7`: Set an alarm to fire at the 64-bit clock value with lower 32
+    ///   bits `time` and upper 32 bits `data2`.
+    fn command(&self, cmd_type: usize, data: usize, data2: usize, caller_id: AppId) -> ReturnCode {
         // Returns the error code to return to the user and whether we need to
         // reset which is the next active alarm. We only _don't_ reset if we're
         // disabling the underlying alarm anyway, if the underlying alarm is
@@ -115,14 +125,14 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
         self.app_alarm
             .enter(caller_id, |td, _alloc| {
                 // helper function to rearm alarm
-                let mut rearm = |time: usize| {
+                let mut rearm = |time: u64| {
                     if let Expiration::Disabled = td.expiration {
                         self.num_armed.set(self.num_armed.get() + 1);
                     }
-                    td.expiration = Expiration::Abs(time as u32);
-                    (ReturnCode::SuccessWithValue { value: time }, true)
+                    td.expiration = Expiration::Abs(time);
+                    (ReturnCode::SuccessWithValue { value: time as u32 as usize }, true)
                 };
-                let now = self.alarm.now();
+                let now = self.alarm.now_ticks();
                 let (return_code, reset) = match cmd_type {
                     0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
                     1 /* Get clock frequency */ => {
@@ -130,7 +140,7 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
-                        (ReturnCode::SuccessWithValue { value: now as usize },
+                        (ReturnCode::SuccessWithValue { value: now as u32 as usize },
                          false)
                     },
                     3 /* Stop */ => {
@@ -140,7 +150,7 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
                                 // Request to stop when already stopped
                                 (ReturnCode::EALREADY, false)
                             },
-                            Expiration::Abs(exp) if exp != alarm_id => {
+                            Expiration::Abs(exp) if exp as u32 != alarm_id => {
                                 // Request to stop invalid alarm id
                                 (ReturnCode::EINVAL, false)
                             },
@@ -154,21 +164,25 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
                     },
                     4 /* Set absolute expiration */ => {
                         // if previously unarmed, but now will become armed
-                        rearm(data)
+                        rearm(expand(data as u32, now))
                     },
                     5 /* Set relative expiration */ => {
-                        let mut time = now.wrapping_add (data as u32) as usize;
-                        let min_tics = (MIN_TICS_AT_16KHZ * (<A::Frequency>::frequency() as usize)) / 16000;
-                        if time.wrapping_sub (now as usize) <= min_tics {
-                            time = time.wrapping_add (min_tics);
-                        }
+                        let min_tics = (MIN_TICS_AT_16KHZ * <A::Frequency>::frequency() as u64) / 16000;
+                        let delay = core::cmp::max(data as u32 as u64, min_tics + 1);
                         // if previously unarmed, but now will become armed
-                        rearm(time)
+                        rearm(now + delay)
+                    },
+                    6 /* capture upper half of time */ => {
+                        (ReturnCode::SuccessWithValue { value: (now >> 32) as usize },
+                         false)
+                    },
+                    7 /* Set 64-bit absolute expiration */ => {
+                        rearm((data2 as u32 as u64) << 32 | data as u32 as u64)
                     },
                     _ => (ReturnCode::ENOSUPPORT, false)
                 };
                 if reset {
-                    self.reset_active_alarm(now);
+                    self.reset_active_alarm();
                 }
                 return_code
             })
@@ -176,22 +190,17 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
     }
 }
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
-}
-
-impl<A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
+impl<A: TicksAlarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
     fn fired(&self) {
-        let now = self.alarm.now();
+        let now = self.alarm.now_ticks();
         self.app_alarm.each(|alarm| {
             if let Expiration::Abs(exp) = alarm.expiration {
-                let expired = has_expired(exp, now, self.prev.get());
-                if expired {
+                if exp <= now {
                     alarm.expiration = Expiration::Disabled;
                     self.num_armed.set(self.num_armed.get() - 1);
                     alarm
                         .callback
-                        .map(|mut cb| cb.schedule(now as usize, exp as usize, 0));
+                        .map(|mut cb| cb.schedule(now as u32 as usize, exp as u32 as usize, 0));
                 }
             }
         });
@@ -200,41 +209,40 @@ impl<A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
         // nearest interval.  Otherwise, disable the underlying alarm.
         if self.num_armed.get() == 0 {
             self.alarm.disable();
-        } else if let Some(next_alarm) = self.reset_active_alarm(now) {
-            let new_now = self.alarm.now();
-            if has_expired(next_alarm, new_now, now) {
-                self.fired();
-            }
         } else {
-            self.alarm.disable();
+            self.reset_active_alarm();
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::expand;
+
     #[test]
     pub fn alarm_before_systick_wrap_expired() {
-        assert_eq!(super::has_expired(2u32, 3u32, 1u32), true);
+        assert_eq!(expand(2, 3), 2);
     }
 
     #[test]
     pub fn alarm_before_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(3u32, 2u32, 1u32), false);
+        assert_eq!(expand(3, 2), 3);
     }
 
     #[test]
     pub fn alarm_after_systick_wrap_expired() {
-        assert_eq!(super::has_expired(1u32, 2u32, 3u32), true);
+        // The clock has wrapped its low 32 bits past the alarm.
+        assert_eq!(expand(0xFFFF_FFFF, 0x1_0000_0002), 0xFFFF_FFFF);
     }
 
     #[test]
-    pub fn alarm_after_systick_wrap_time_before_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(1u32, 4u32, 3u32), false);
+    pub fn alarm_after_systick_wrap_not_expired() {
+        // The alarm is set beyond the next wrap of the low 32 bits.
+        assert_eq!(expand(1, 0xFFFF_FFF0), 0x1_0000_0001);
     }
 
     #[test]
-    pub fn alarm_after_systick_wrap_time_after_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(1u32, 0u32, 3u32), false);
+    pub fn alarm_before_clock_start_saturates() {
+        assert_eq!(expand(0xFFFF_FF00, 10), 0);
     }
 }
@@ -0,0 +1,281 @@
+//! `hil::block_storage::BlockStorage` volumes on top of other storage, for
+//! `usb::msc::UsbMassStorage` or the `fat` filesystem.
+//!
+//! - `NonvolatileBlocks` exports a region of a `NonvolatileStorage`, such as
+//!   the kernel region of `nonvolatile_storage_driver` or an external flash
+//!   chip. The start and length of the region should be multiples of
+//!   `BLOCK_SIZE`.
+//! - `SdCardBlocks` exports an SD card. It initializes the card when it is
+//!   started or inserted, and reports no medium while the card is missing or
+//!   failed to initialize.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let volume = static_init!(
+//!     capsules::block_storage::NonvolatileBlocks<'static>,
+//!     capsules::block_storage::NonvolatileBlocks::new(nonvolatile_storage, 0, 0x60000)
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, volume);
+//!
+//! let volume = static_init!(
+//!     capsules::block_storage::SdCardBlocks<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::block_storage::SdCardBlocks::new(sdcard)
+//! );
+//! sdcard.set_client(volume);
+//! volume.start();
+//! ```
+
+use crate::sdcard::{SDCard, SDCardClient};
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient, BLOCK_SIZE};
+use kernel::ReturnCode;
+
+/// A region of a `NonvolatileStorage` exported as blocks.
+pub struct NonvolatileBlocks<'a> {
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    start: usize,
+    length: usize,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+    busy: Cell<bool>,
+}
+
+impl<'a> NonvolatileBlocks<'a> {
+    pub fn new(
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        start: usize,
+        length: usize,
+    ) -> NonvolatileBlocks<'a> {
+        NonvolatileBlocks {
+            storage,
+            start,
+            length,
+            client: OptionalCell::empty(),
+            busy: Cell::new(false),
+        }
+    }
+
+    fn access(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        write: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.busy.get() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if block as usize >= self.length / BLOCK_SIZE || buffer.len() < BLOCK_SIZE {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        let address = self.start + block as usize * BLOCK_SIZE;
+        self.busy.set(true);
+        // The storage keeps the buffer even if it fails to start the access
+        let rcode = if write {
+            self.storage.write(buffer, address, BLOCK_SIZE)
+        } else {
+            self.storage.read(buffer, address, BLOCK_SIZE)
+        };
+        if rcode != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
+        (rcode, None)
+    }
+}
+
+impl<'a> BlockStorage<'a> for NonvolatileBlocks<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_count(&self) -> Option<u32> {
+        Some((self.length / BLOCK_SIZE) as u32)
+    }
+
+    fn read_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.access(buffer, block, false)
+    }
+
+    fn write_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.access(buffer, block, true)
+    }
+}
+
+impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient<'static> for NonvolatileBlocks<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.busy.set(false);
+        let rcode = if length == BLOCK_SIZE {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.client
+            .map(move |client| client.read_done(buffer, rcode));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.busy.set(false);
+        let rcode = if length == BLOCK_SIZE {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.client
+            .map(move |client| client.write_done(buffer, rcode));
+    }
+}
+
+/// Access to the SD card in progress
+#[derive(Copy, Clone, PartialEq)]
+enum SdCardAccess {
+    Idle,
+    Read,
+    Write,
+}
+
+/// An SD card exported as blocks.
+pub struct SdCardBlocks<'a, A: hil::time::Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+    // Number of blocks of the initialized card
+    block_count: Cell<Option<u32>>,
+    access: Cell<SdCardAccess>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SdCardBlocks<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> SdCardBlocks<'a, A> {
+        SdCardBlocks {
+            sdcard,
+            client: OptionalCell::empty(),
+            block_count: Cell::new(None),
+            access: Cell::new(SdCardAccess::Idle),
+        }
+    }
+
+    /// Watches for card changes and initializes the card if it is present.
+    pub fn start(&self) {
+        self.sdcard.detect_changes();
+        if self.sdcard.is_installed() {
+            self.sdcard.initialize();
+        }
+    }
+
+    fn access(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        access: SdCardAccess,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.access.get() != SdCardAccess::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if self.block_count.get().map_or(true, |count| block >= count) || buffer.len() < BLOCK_SIZE
+        {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        if !self.sdcard.is_installed() || !self.sdcard.is_initialized() {
+            // The SD card drops the buffer if it refuses the access
+            return (ReturnCode::EOFF, Some(buffer));
+        }
+        self.access.set(access);
+        let rcode = if access == SdCardAccess::Write {
+            self.sdcard.write_blocks(buffer, block, 1)
+        } else {
+            self.sdcard.read_blocks(buffer, block, 1)
+        };
+        if rcode == ReturnCode::SUCCESS {
+            (rcode, None)
+        } else {
+            self.access.set(SdCardAccess::Idle);
+            (rcode, self.sdcard.take_client_buffer())
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> BlockStorage<'a> for SdCardBlocks<'a, A> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_count(&self) -> Option<u32> {
+        self.block_count.get()
+    }
+
+    fn read_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.access(buffer, block, SdCardAccess::Read)
+    }
+
+    fn write_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.access(buffer, block, SdCardAccess::Write)
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SdCardBlocks<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        self.block_count.set(None);
+        if installed {
+            self.sdcard.initialize();
+        }
+        self.client.map(|client| client.medium_changed());
+    }
+
+    fn init_done(&self, block_size: u32, total_size: u64) {
+        if block_size as usize == BLOCK_SIZE {
+            self.block_count
+                .set(Some((total_size / BLOCK_SIZE as u64) as u32));
+            self.client.map(|client| client.medium_changed());
+        }
+    }
+
+    fn read_done(&self, data: &'static mut [u8], _len: usize) {
+        self.access.set(SdCardAccess::Idle);
+        self.client
+            .map(move |client| client.read_done(data, ReturnCode::SUCCESS));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        self.access.set(SdCardAccess::Idle);
+        self.client
+            .map(move |client| client.write_done(buffer, ReturnCode::SUCCESS));
+    }
+
+    fn error(&self, _error: u32) {
+        let access = self.access.replace(SdCardAccess::Idle);
+        match access {
+            SdCardAccess::Idle => {
+                // Initialization failed, or the card was removed
+                self.block_count.set(None);
+            }
+            SdCardAccess::Read => {
+                self.sdcard.take_client_buffer().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_done(buffer, ReturnCode::FAIL));
+                });
+            }
+            SdCardAccess::Write => {
+                self.sdcard.take_client_buffer().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_done(buffer, ReturnCode::FAIL));
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Provides userspace applications with access to the wall-clock time of a
+//! real-time clock.
+//!
+//! Time is read as calendar fields, the date and the time of day each packed
+//! into one return value, so that every value fits in a positive return code
+//! on 32-bit platforms, also after 2038. Reading the date latches the time of
+//! day of the same clock reading, so that the two values cannot tear across
+//! midnight. The clock can be set from either a Unix timestamp (seconds since
+//! 1970-01-01 00:00:00 UTC) or calendar fields. Any application can set the
+//! clock, for example from a time obtained over the network with SNTP.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let date_time = static_init!(
+//!     capsules::date_time::DateTimeDriver<'static>,
+//!     capsules::date_time::DateTimeDriver::new(
+//!         &sam4l::ast::AST,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! ```
+
+use kernel::hil::date_time::{DateTime, DateTimeValues};
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::DateTime as usize;
+
+#[derive(Default)]
+pub struct App {
+    // Time of day read together with the last date, not yet returned
+    latched_time: Option<DateTimeValues>,
+}
+
+pub struct DateTimeDriver<'a> {
+    date_time: &'a dyn DateTime,
+    apps: Grant<App>,
+}
+
+impl DateTimeDriver<'a> {
+    pub fn new(date_time: &'a dyn DateTime, grant: Grant<App>) -> DateTimeDriver<'a> {
+        DateTimeDriver {
+            date_time: date_time,
+            apps: grant,
+        }
+    }
+}
+
+impl Driver for DateTimeDriver<'a> {
+    /// Read or set the current time.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return the current date as `(year << 16) | (month << 8) | day`
+    ///   and latch the time of day of the same reading for command `4`.
+    ///   Returns EOFF if the clock has not been set.
+    /// - `2`: Set the current time to the Unix timestamp in `data`.
+    /// - `3`: Set the current date and time. `data` holds the date as
+    ///   `(year << 16) | (month << 8) | day` and `data2` holds the time of day
+    ///   as `(hour << 16) | (minute << 8) | second`.
+    /// - `4`: Return the time of day latched by command `1`, or the current one
+    ///   if it has already been returned, as
+    ///   `(hour << 16) | (minute << 8) | second`. Returns EOFF if the clock has
+    ///   not been set.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let result = self.date_time.get_date_time();
+                    app.latched_time = result.ok();
+                    match result {
+                        Ok(date_time) => ReturnCode::SuccessWithValue {
+                            value: (date_time.year as usize) << 16
+                                | (date_time.month as usize) << 8
+                                | date_time.day as usize,
+                        },
+                        Err(rc) => rc,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+            2 => self
+                .date_time
+                .set_date_time(DateTimeValues::from_unix_time(data as u32)),
+            3 => self.date_time.set_date_time(DateTimeValues {
+                year: (data >> 16) as u16,
+                month: (data >> 8) as u8,
+                day: data as u8,
+                hour: (data2 >> 16) as u8,
+                minute: (data2 >> 8) as u8,
+                second: data2 as u8,
+            }),
+            4 => self
+                .apps
+                .enter(appid, |app, _| {
+                    match app
+                        .latched_time
+                        .take()
+                        .map_or_else(|| self.date_time.get_date_time(), Ok)
+                    {
+                        Ok(date_time) => ReturnCode::SuccessWithValue {
+                            value: (date_time.hour as usize) << 16
+                                | (date_time.minute as usize) << 8
+                                | date_time.second as usize,
+                        },
+                        Err(rc) => rc,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -22,14 +22,17 @@ pub enum NUM {
 
     // HW Buses
     Spi                   = 0x20001,
+    SpiSlave              = 0x20002,
     I2cMaster             = 0x20003,
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
+    UsbHid                = 0x20007,
 
     // Radio
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Coap                  = 0x30003,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -40,12 +43,16 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    FirmwareUpdate        = 0x50003,
+    KVStore               = 0x50004,
+    Fat                   = 0x50005,
 
     // Sensors
     Temperature           = 0x60000,
     Humidity              = 0x60001,
     AmbientLight          = 0x60002,
     NINEDOF               = 0x60004,
+    Microphone            = 0x60005,
 
     // Sensor ICs
     Tsl2561               = 0x70000,
@@ -64,5 +71,6 @@ pub enum NUM {
 
     // Misc
     Buzzer                = 0x90000,
+    DateTime              = 0x90001,
 }
 }
@@ -0,0 +1,1777 @@
+//! FAT12, FAT16 and FAT32 filesystem on top of `hil::block_storage`.
+//!
+//! This lets the kernel keep files on SD cards or flash volumes in the format
+//! PCs read, so a data logger can write a file and a PC can read it. The
+//! volume is either formatted as a whole or holds an MBR partition table, in
+//! which case the first FAT partition is used. Volumes with sectors other
+//! than 512 bytes are not supported.
+//!
+//! Files and directories are named by paths from the root directory, with
+//! components separated by `/`. Only short (8.3) names are supported: the
+//! entries of long names are skipped, and names are upper case on disk. Names
+//! are matched without regard to case. `.` and `..` are not accepted in
+//! paths.
+//!
+//! Up to `MAX_FILES` files can be open at once. A file is read and written at
+//! a position, which `seek` moves within the file, and writes past the end
+//! extend the file. Files opened for appending are always written at their
+//! end.
+//!
+//! Operations run one at a time, and complete through the `FatClient`.
+//! Sectors are read into a cache of `CACHE_SLOTS` sectors, and everything
+//! an operation changed is written back before it completes, so the volume
+//! is consistent between operations. Files are extended before their
+//! directory entry is updated, and removed after it is cleared, so an
+//! interrupted operation can lose free space but does not corrupt other
+//! files. Dates are not kept: new entries are dated 2020-01-01.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fat = static_init!(
+//!     capsules::fat::Fat<'static>,
+//!     capsules::fat::Fat::new(volume, &mut capsules::fat::CACHE, dynamic_deferred_caller)
+//! );
+//! fat.initialize_callback_handle(dynamic_deferred_caller.register(fat).unwrap());
+//! volume.set_client(fat);
+//! fat.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient, BLOCK_SIZE};
+use kernel::ReturnCode;
+
+/// Longest path.
+pub const MAX_PATH_LEN: usize = 64;
+/// Number of files that can be open at once.
+pub const MAX_FILES: usize = 8;
+/// Number of sectors cached.
+pub const CACHE_SLOTS: usize = 3;
+
+/// Buffers for the sector cache.
+pub static mut CACHE: [u8; CACHE_SLOTS * BLOCK_SIZE] = [0; CACHE_SLOTS * BLOCK_SIZE];
+
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (BLOCK_SIZE / ENTRY_SIZE) as u32;
+
+const ENTRY_END: u8 = 0x00;
+const ENTRY_FREE: u8 = 0xe5;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+
+/// 2020-01-01, as a FAT date.
+const DATE: u16 = (40 << 9) | (1 << 5) | 1;
+
+/// Partition types of FAT volumes in an MBR.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0b, 0x0c, 0x0e];
+
+/// Ways to open a file.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct OpenFlags {
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// Empty the file when it is opened.
+    pub truncate: bool,
+    /// Write at the end of the file, wherever it was seeked to.
+    pub append: bool,
+}
+
+/// An entry of a directory listing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirEntry {
+    name: [u8; 12],
+    name_len: usize,
+    /// Size of a file, in bytes. Zero for directories.
+    pub size: u32,
+    pub directory: bool,
+}
+
+impl DirEntry {
+    /// The name of the entry, such as `LOG.TXT`.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn from_entry(entry: &[u8]) -> DirEntry {
+        let mut name = [0; 12];
+        let mut name_len = 0;
+        for &byte in entry[0..8].iter().take_while(|byte| **byte != b' ') {
+            name[name_len] = byte;
+            name_len += 1;
+        }
+        if name[0] == 0x05 {
+            // A name starting with 0xe5, which marks free entries otherwise
+            name[0] = ENTRY_FREE;
+        }
+        if entry[8] != b' ' {
+            name[name_len] = b'.';
+            name_len += 1;
+            for &byte in entry[8..11].iter().take_while(|byte| **byte != b' ') {
+                name[name_len] = byte;
+                name_len += 1;
+            }
+        }
+        let directory = entry[11] & ATTR_DIRECTORY != 0;
+        DirEntry {
+            name: name,
+            name_len: name_len,
+            size: if directory { 0 } else { read_u32(entry, 28) },
+            directory: directory,
+        }
+    }
+}
+
+pub trait FatClient {
+    fn mount_done(&self, rcode: ReturnCode);
+
+    /// A file was opened as `handle`.
+    fn open_done(&self, handle: usize, rcode: ReturnCode);
+
+    /// `length` bytes were read into `buffer`, fewer than requested at the
+    /// end of the file.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode);
+
+    /// `length` bytes of `buffer` were written. Fewer than requested are
+    /// written if the volume fills up, which fails with ENOMEM.
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode);
+
+    /// The requested entry of a directory, or `None` past the last entry.
+    fn read_dir_done(&self, entry: Option<DirEntry>, rcode: ReturnCode);
+
+    /// A `mkdir` or `remove` completed.
+    fn command_done(&self, rcode: ReturnCode);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Layout of a mounted volume. Sector numbers are absolute.
+#[derive(Clone, Copy)]
+struct Volume {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    fats: u32,
+    /// Fixed root directory of FAT12 and FAT16 volumes.
+    root_start: u32,
+    root_entries: u32,
+    /// First cluster of the root directory of FAT32 volumes.
+    root_cluster: u32,
+    data_start: u32,
+    /// Clusters are numbered from 2 to `clusters + 1`.
+    clusters: u32,
+}
+
+impl Volume {
+    /// Parses the boot sector of a volume starting at sector `base`.
+    fn parse(sector: &[u8], base: u32, blocks: u32) -> Option<Volume> {
+        if (sector[0] != 0xeb && sector[0] != 0xe9) || sector[510..512] != [0x55, 0xaa] {
+            return None;
+        }
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved = read_u16(sector, 14) as u32;
+        let fats = sector[16] as u32;
+        let root_entries = read_u16(sector, 17) as u32;
+        let total = match read_u16(sector, 19) {
+            0 => read_u32(sector, 32),
+            total => total as u32,
+        };
+        let fat_sectors = match read_u16(sector, 22) {
+            0 => read_u32(sector, 36),
+            fat_sectors => fat_sectors as u32,
+        };
+        if read_u16(sector, 11) as usize != BLOCK_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fats == 0
+            || fat_sectors == 0
+            || base as u64 + total as u64 > blocks as u64
+        {
+            return None;
+        }
+
+        let root_sectors = (root_entries + ENTRIES_PER_SECTOR - 1) / ENTRIES_PER_SECTOR;
+        let data_start = reserved as u64 + fats as u64 * fat_sectors as u64 + root_sectors as u64;
+        if data_start >= total as u64 {
+            return None;
+        }
+        let clusters = (total - data_start as u32) / sectors_per_cluster;
+        let (fat_type, entry_bits) = if clusters < 4085 {
+            (FatType::Fat12, 12)
+        } else if clusters < 65525 {
+            (FatType::Fat16, 16)
+        } else {
+            (FatType::Fat32, 32)
+        };
+        let root_cluster = if fat_type == FatType::Fat32 {
+            read_u32(sector, 44)
+        } else {
+            0
+        };
+        if (clusters as u64 + 2) * entry_bits > fat_sectors as u64 * BLOCK_SIZE as u64 * 8
+            || (fat_type == FatType::Fat32 && (root_cluster < 2 || root_cluster >= clusters + 2))
+        {
+            return None;
+        }
+
+        let fat_start = base + reserved;
+        Some(Volume {
+            fat_type: fat_type,
+            sectors_per_cluster: sectors_per_cluster,
+            fat_start: fat_start,
+            fat_sectors: fat_sectors,
+            fats: fats,
+            root_start: fat_start + fats * fat_sectors,
+            root_entries: root_entries,
+            root_cluster: root_cluster,
+            data_start: base + data_start as u32,
+            clusters: clusters,
+        })
+    }
+
+    /// The first sector of the first FAT partition in an MBR.
+    fn partition_start(sector: &[u8]) -> Option<u32> {
+        if sector[510..512] != [0x55, 0xaa] {
+            return None;
+        }
+        (0..4)
+            .map(|i| &sector[446 + 16 * i..446 + 16 * (i + 1)])
+            .find(|partition| FAT_PARTITION_TYPES.contains(&partition[4]))
+            .map(|partition| read_u32(partition, 8))
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * BLOCK_SIZE as u32
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.clusters + 2
+    }
+
+    /// The directory that `Cursor`s and `Walk`s refer to as the root. The
+    /// fixed root directory of FAT12 and FAT16 is cluster 0.
+    fn root_dir(&self) -> u32 {
+        if self.fat_type == FatType::Fat32 {
+            self.root_cluster
+        } else {
+            0
+        }
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    /// Offset of the FAT entry of `cluster` from the start of the FAT.
+    fn fat_offset(&self, cluster: u32) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        }
+    }
+
+    fn is_fat_sector(&self, sector: u32) -> bool {
+        sector >= self.fat_start && sector < self.fat_start + self.fat_sectors
+    }
+
+    /// Location of the entry `cursor` points at, or `None` past the end of
+    /// the fixed root directory.
+    fn location(&self, cursor: Cursor) -> Option<Location> {
+        if cursor.cluster == 0 && cursor.index >= self.root_entries {
+            return None;
+        }
+        let first = if cursor.cluster == 0 {
+            self.root_start
+        } else {
+            self.cluster_sector(cursor.cluster)
+        };
+        Some(Location {
+            sector: first + cursor.index / ENTRIES_PER_SECTOR,
+            index: (cursor.index % ENTRIES_PER_SECTOR) as usize,
+        })
+    }
+
+    fn entry_cluster(&self, entry: &[u8]) -> u32 {
+        let high = if self.fat_type == FatType::Fat32 {
+            read_u16(entry, 20) as u32
+        } else {
+            0
+        };
+        high << 16 | read_u16(entry, 26) as u32
+    }
+}
+
+/// A directory entry on the volume.
+#[derive(Clone, Copy, PartialEq)]
+struct Location {
+    sector: u32,
+    /// Index of the entry in the sector.
+    index: usize,
+}
+
+/// Position in a directory.
+#[derive(Clone, Copy)]
+struct Cursor {
+    /// Cluster of the directory the position is in, or 0 in the fixed root
+    /// directory.
+    cluster: u32,
+    /// Index of the entry in the cluster or the fixed root directory.
+    index: u32,
+}
+
+/// Progress of looking up a path.
+#[derive(Clone, Copy)]
+struct Walk {
+    /// The directory searched, as in `Cursor::cluster`.
+    dir: u32,
+    /// Where the path component searched for starts.
+    start: usize,
+    cursor: Cursor,
+    /// The first free entry seen in the directory.
+    free: Option<Location>,
+}
+
+/// A path component that does not exist.
+#[derive(Clone, Copy)]
+struct Missing {
+    name: [u8; 11],
+    /// The directory it would be in, as in `Cursor::cluster`.
+    dir: u32,
+    free: Option<Location>,
+    /// The last cluster of the directory, or 0 for the fixed root directory.
+    last_cluster: u32,
+    /// Where the rest of the path starts.
+    rest: usize,
+    is_last: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Lookup {
+    /// The path is empty.
+    Root,
+    Found(Location, [u8; ENTRY_SIZE]),
+    Missing(Missing),
+    Error(ReturnCode),
+}
+
+#[derive(Clone, Copy)]
+struct File {
+    open: bool,
+    append: bool,
+    location: Location,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// The `cluster_index`th cluster of the file, or 0 if not known yet.
+    cluster: u32,
+    cluster_index: u32,
+}
+
+const CLOSED: File = File {
+    open: false,
+    append: false,
+    location: Location {
+        sector: 0,
+        index: 0,
+    },
+    first_cluster: 0,
+    size: 0,
+    position: 0,
+    cluster: 0,
+    cluster_index: 0,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Idle,
+    Mount,
+    Open,
+    Read,
+    Write,
+    ReadDir,
+    Mkdir,
+    Remove,
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Lookup,
+    /// Adding a cluster to the directory of the missing component, which has
+    /// no free entries.
+    ExtendDir,
+    /// Writing the entry of the missing component.
+    CreateEntry(Location),
+    /// Reading or writing file data.
+    Transfer,
+    /// Writing the first cluster and size of the file to its entry.
+    UpdateEntry,
+    /// Freeing the clusters starting at `Fat::freeing`.
+    FreeChain,
+    /// Listing the directory.
+    List(Cursor, usize),
+}
+
+/// A sector that has to be in the cache before an operation can go on.
+#[derive(Clone, Copy, PartialEq)]
+enum Need {
+    Read(u32),
+    /// The sector is about to be overwritten, so it need not be read.
+    Overwrite(u32),
+}
+
+/// What to do once a cache slot is written back.
+#[derive(Clone, Copy, PartialEq)]
+enum Then {
+    Fetch(Need),
+    Flush,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Io {
+    Idle,
+    Read(usize),
+    /// Writing a slot to the `copy`th FAT, or just to its sector if it is not
+    /// in a FAT.
+    Write {
+        slot: usize,
+        copy: u32,
+        then: Then,
+    },
+}
+
+type Step<T> = Result<T, Need>;
+
+struct Slot {
+    buffer: TakeCell<'static, [u8]>,
+    sector: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    used: Cell<u32>,
+}
+
+impl Slot {
+    fn new(buffer: &'static mut [u8]) -> Slot {
+        Slot {
+            buffer: TakeCell::new(buffer),
+            sector: Cell::new(None),
+            dirty: Cell::new(false),
+            used: Cell::new(0),
+        }
+    }
+}
+
+pub struct Fat<'a> {
+    storage: &'a dyn BlockStorage<'a>,
+    client: OptionalCell<&'a dyn FatClient>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    volume: Cell<Option<Volume>>,
+    files: Cell<[File; MAX_FILES]>,
+    slots: [Slot; CACHE_SLOTS],
+    tick: Cell<u32>,
+    io: Cell<Io>,
+    /// Where to start looking for a free cluster.
+    next_free: Cell<u32>,
+    remount: Cell<bool>,
+
+    // The operation in progress
+    op: Cell<Op>,
+    phase: Cell<Phase>,
+    result: Cell<ReturnCode>,
+    path: Cell<[u8; MAX_PATH_LEN]>,
+    path_len: Cell<usize>,
+    flags: Cell<OpenFlags>,
+    file: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    done: Cell<usize>,
+    index: Cell<usize>,
+    dir_entry: Cell<Option<DirEntry>>,
+    walk: Cell<Walk>,
+    missing: Cell<Option<Missing>>,
+    /// Clusters scanned for a free one.
+    scanned: Cell<u32>,
+    /// A cluster allocated but not linked yet, and how many of its sectors
+    /// are zeroed.
+    new_cluster: Cell<Option<u32>>,
+    zeroed: Cell<u32>,
+    freeing: Cell<u32>,
+}
+
+impl Fat<'a> {
+    pub fn new(
+        storage: &'a dyn BlockStorage<'a>,
+        cache: &'static mut [u8; CACHE_SLOTS * BLOCK_SIZE],
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> Fat<'a> {
+        let (first, rest) = cache.split_at_mut(BLOCK_SIZE);
+        let (second, third) = rest.split_at_mut(BLOCK_SIZE);
+        Fat {
+            storage: storage,
+            client: OptionalCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+            volume: Cell::new(None),
+            files: Cell::new([CLOSED; MAX_FILES]),
+            slots: [Slot::new(first), Slot::new(second), Slot::new(third)],
+            tick: Cell::new(0),
+            io: Cell::new(Io::Idle),
+            next_free: Cell::new(2),
+            remount: Cell::new(false),
+            op: Cell::new(Op::Idle),
+            phase: Cell::new(Phase::Lookup),
+            result: Cell::new(ReturnCode::SUCCESS),
+            path: Cell::new([0; MAX_PATH_LEN]),
+            path_len: Cell::new(0),
+            flags: Cell::new(OpenFlags::default()),
+            file: Cell::new(0),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            done: Cell::new(0),
+            index: Cell::new(0),
+            dir_entry: Cell::new(None),
+            walk: Cell::new(Walk {
+                dir: 0,
+                start: 0,
+                cursor: Cursor {
+                    cluster: 0,
+                    index: 0,
+                },
+                free: None,
+            }),
+            missing: Cell::new(None),
+            scanned: Cell::new(0),
+            new_cluster: Cell::new(None),
+            zeroed: Cell::new(0),
+            freeing: Cell::new(0),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    pub fn set_client(&self, client: &'a dyn FatClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.volume.get().is_some()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.op.get() != Op::Idle
+    }
+
+    /// Mounts the volume, closing all files. The volume is mounted again
+    /// whenever the medium changes.
+    pub fn mount(&self) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.unmount();
+        self.start(Op::Mount)
+    }
+
+    pub fn open(&self, path: &[u8], flags: OpenFlags) -> ReturnCode {
+        let rcode = self.check(path);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        let files = self.files.get();
+        match files.iter().position(|file| !file.open) {
+            Some(file) => self.file.set(file),
+            None => return ReturnCode::ENOMEM,
+        }
+        self.flags.set(flags);
+        self.start(Op::Open)
+    }
+
+    pub fn close(&self, handle: usize) -> ReturnCode {
+        self.with_file(handle, |file| {
+            file.open = false;
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Moves the position of a file, which cannot go past its end.
+    pub fn seek(&self, handle: usize, position: usize) -> ReturnCode {
+        self.with_file(handle, |file| {
+            if position > file.size as usize {
+                return ReturnCode::EINVAL;
+            }
+            file.position = position as u32;
+            ReturnCode::SUCCESS
+        })
+    }
+
+    pub fn size(&self, handle: usize) -> Option<usize> {
+        self.open_file(handle).map(|file| file.size as usize)
+    }
+
+    pub fn position(&self, handle: usize) -> Option<usize> {
+        self.open_file(handle).map(|file| file.position as usize)
+    }
+
+    /// Reads up to `length` bytes from the position of a file into `buffer`.
+    pub fn read(
+        &self,
+        handle: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        self.transfer(Op::Read, handle, buffer, length)
+    }
+
+    /// Writes the first `length` bytes of `buffer` at the position of a
+    /// file.
+    pub fn write(
+        &self,
+        handle: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        let size = match self.open_file(handle) {
+            Some(file) if file.append => file.size,
+            Some(file) => file.position,
+            None => return Err((ReturnCode::EINVAL, Some(buffer))),
+        };
+        if size as u64 + length as u64 > u32::max_value() as u64 {
+            return Err((ReturnCode::ESIZE, Some(buffer)));
+        }
+        self.transfer(Op::Write, handle, buffer, length)
+    }
+
+    /// Gets the `index`th entry of the directory at `path`.
+    pub fn read_dir(&self, path: &[u8], index: usize) -> ReturnCode {
+        let rcode = self.check(path);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.index.set(index);
+        self.start(Op::ReadDir)
+    }
+
+    /// Creates the directory at `path`, and any missing directories it is
+    /// in. Fails with EALREADY if it exists.
+    pub fn mkdir(&self, path: &[u8]) -> ReturnCode {
+        let rcode = self.check(path);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.start(Op::Mkdir)
+    }
+
+    /// Removes the file at `path`, which must not be open.
+    pub fn remove(&self, path: &[u8]) -> ReturnCode {
+        let rcode = self.check(path);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.start(Op::Remove)
+    }
+
+    /// Checks that an operation on `path` can start, and saves the path.
+    fn check(&self, path: &[u8]) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        } else if !self.is_mounted() {
+            return ReturnCode::EOFF;
+        } else if path.len() > MAX_PATH_LEN {
+            return ReturnCode::ESIZE;
+        }
+        let mut saved = [0; MAX_PATH_LEN];
+        saved[..path.len()].copy_from_slice(path);
+        self.path.set(saved);
+        self.path_len.set(path.len());
+        ReturnCode::SUCCESS
+    }
+
+    fn transfer(
+        &self,
+        op: Op,
+        handle: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        if self.is_busy() {
+            return Err((ReturnCode::EBUSY, Some(buffer)));
+        } else if self.open_file(handle).is_none() || length > buffer.len() {
+            return Err((ReturnCode::EINVAL, Some(buffer)));
+        }
+        self.file.set(handle);
+        self.buffer.replace(buffer);
+        self.length.set(length);
+        self.done.set(0);
+        match self.start(op) {
+            ReturnCode::SUCCESS => Ok(()),
+            rcode => Err((rcode, self.buffer.take())),
+        }
+    }
+
+    fn start(&self, op: Op) -> ReturnCode {
+        if self
+            .handle
+            .map(|handle| self.deferred_caller.set(*handle))
+            .is_none()
+        {
+            return ReturnCode::FAIL;
+        }
+        self.op.set(op);
+        self.phase.set(if op == Op::Write || op == Op::Read {
+            Phase::Transfer
+        } else {
+            Phase::Lookup
+        });
+        self.result.set(ReturnCode::SUCCESS);
+        self.missing.set(None);
+        self.new_cluster.set(None);
+        self.scanned.set(0);
+        self.freeing.set(0);
+        self.dir_entry.set(None);
+        if let Some(volume) = self.volume.get() {
+            let root = volume.root_dir();
+            self.walk.set(Walk {
+                dir: root,
+                start: 0,
+                cursor: Cursor {
+                    cluster: root,
+                    index: 0,
+                },
+                free: None,
+            });
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn unmount(&self) {
+        self.volume.set(None);
+        self.files.set([CLOSED; MAX_FILES]);
+        for slot in self.slots.iter() {
+            slot.sector.set(None);
+            slot.dirty.set(false);
+        }
+    }
+
+    fn open_file(&self, handle: usize) -> Option<File> {
+        self.files
+            .get()
+            .get(handle)
+            .cloned()
+            .filter(|file| file.open)
+    }
+
+    fn with_file<F: FnOnce(&mut File) -> ReturnCode>(&self, handle: usize, f: F) -> ReturnCode {
+        if self.is_busy() && self.file.get() == handle {
+            return ReturnCode::EBUSY;
+        }
+        let mut files = self.files.get();
+        match files.get_mut(handle) {
+            Some(file) if file.open => {
+                let rcode = f(file);
+                self.files.set(files);
+                rcode
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+
+    fn get_file(&self) -> File {
+        self.files.get()[self.file.get()]
+    }
+
+    fn set_file(&self, file: File) {
+        let mut files = self.files.get();
+        files[self.file.get()] = file;
+        self.files.set(files);
+    }
+
+    // Sector cache
+
+    fn cached(&self, sector: u32) -> Option<&Slot> {
+        self.slots
+            .iter()
+            .find(|slot| slot.sector.get() == Some(sector))
+            .map(|slot| {
+                self.tick.set(self.tick.get().wrapping_add(1));
+                slot.used.set(self.tick.get());
+                slot
+            })
+    }
+
+    fn with_sector<F: FnOnce(&[u8]) -> R, R>(&self, sector: u32, f: F) -> Step<R> {
+        let slot = self.cached(sector).ok_or(Need::Read(sector))?;
+        slot.buffer
+            .map(|buffer| f(&buffer[..BLOCK_SIZE]))
+            .ok_or(Need::Read(sector))
+    }
+
+    fn with_sector_mut<F: FnOnce(&mut [u8]) -> R, R>(&self, sector: u32, f: F) -> Step<R> {
+        let slot = self.cached(sector).ok_or(Need::Read(sector))?;
+        slot.dirty.set(true);
+        slot.buffer
+            .map(|buffer| f(&mut buffer[..BLOCK_SIZE]))
+            .ok_or(Need::Read(sector))
+    }
+
+    /// Like `with_sector_mut`, for a sector whose contents do not matter.
+    fn overwrite_sector<F: FnOnce(&mut [u8]) -> R, R>(&self, sector: u32, f: F) -> Step<R> {
+        self.with_sector_mut(sector, f)
+            .map_err(|_| Need::Overwrite(sector))
+    }
+
+    /// Makes room for `need` in the cache. Returns whether the sector is
+    /// ready, rather than being read or the operation failing.
+    fn fetch(&self, need: Need) -> bool {
+        let slot = (0..CACHE_SLOTS)
+            .min_by_key(|slot| match self.slots[*slot].sector.get() {
+                Some(_) => self.tick.get().wrapping_sub(self.slots[*slot].used.get()) ^ !0,
+                None => 0,
+            })
+            .unwrap_or(0);
+        if self.slots[slot].dirty.get() {
+            self.write_back(slot, 0, Then::Fetch(need));
+            return false;
+        }
+        match need {
+            Need::Overwrite(sector) => {
+                self.slots[slot].sector.set(Some(sector));
+                self.slots[slot].buffer.map(|buffer| {
+                    for byte in buffer.iter_mut() {
+                        *byte = 0;
+                    }
+                });
+                true
+            }
+            Need::Read(sector) => {
+                self.slots[slot].sector.set(None);
+                let buffer = match self.slots[slot].buffer.take() {
+                    Some(buffer) => buffer,
+                    None => {
+                        self.abort(ReturnCode::FAIL);
+                        return false;
+                    }
+                };
+                self.io.set(Io::Read(slot));
+                let (rcode, buffer) = self.storage.read_block(buffer, sector);
+                if rcode != ReturnCode::SUCCESS {
+                    self.io.set(Io::Idle);
+                    buffer.map(|buffer| self.slots[slot].buffer.replace(buffer));
+                    self.abort(rcode);
+                } else {
+                    self.slots[slot].sector.set(Some(sector));
+                }
+                false
+            }
+        }
+    }
+
+    /// Writes a dirty slot to its sector, or to the `copy`th copy of the
+    /// FAT.
+    fn write_back(&self, slot: usize, copy: u32, then: Then) {
+        let sector = match self.slots[slot].sector.get() {
+            Some(sector) => sector,
+            None => return self.abort(ReturnCode::FAIL),
+        };
+        let sector = match self.volume.get() {
+            Some(volume) if volume.is_fat_sector(sector) => sector + copy * volume.fat_sectors,
+            _ => sector,
+        };
+        let buffer = match self.slots[slot].buffer.take() {
+            Some(buffer) => buffer,
+            None => return self.abort(ReturnCode::FAIL),
+        };
+        self.io.set(Io::Write {
+            slot: slot,
+            copy: copy,
+            then: then,
+        });
+        let (rcode, buffer) = self.storage.write_block(buffer, sector);
+        if rcode != ReturnCode::SUCCESS {
+            self.io.set(Io::Idle);
+            buffer.map(|buffer| self.slots[slot].buffer.replace(buffer));
+            self.slots[slot].sector.set(None);
+            self.slots[slot].dirty.set(false);
+            self.abort(rcode);
+        }
+    }
+
+    /// Writes back the next dirty slot, or completes the operation.
+    fn flush(&self) {
+        match self.slots.iter().position(|slot| slot.dirty.get()) {
+            Some(slot) => self.write_back(slot, 0, Then::Flush),
+            None => self.finish(),
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            match self.step() {
+                Ok(rcode) => {
+                    self.result.set(rcode);
+                    self.flush();
+                    return;
+                }
+                Err(need) => {
+                    if !self.fetch(need) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn abort(&self, rcode: ReturnCode) {
+        self.result.set(rcode);
+        self.finish();
+    }
+
+    fn finish(&self) {
+        let rcode = self.result.get();
+        let op = self.op.replace(Op::Idle);
+        match op {
+            Op::Idle => {}
+            Op::Mount => {
+                self.client.map(|client| client.mount_done(rcode));
+            }
+            Op::Open => {
+                let handle = self.file.get();
+                if rcode != ReturnCode::SUCCESS {
+                    let mut file = self.get_file();
+                    file.open = false;
+                    self.set_file(file);
+                }
+                self.client.map(|client| client.open_done(handle, rcode));
+            }
+            Op::Read | Op::Write => {
+                let length = self.done.get();
+                self.buffer.take().map(|buffer| {
+                    self.client.map(move |client| {
+                        if op == Op::Read {
+                            client.read_done(buffer, length, rcode)
+                        } else {
+                            client.write_done(buffer, length, rcode)
+                        }
+                    })
+                });
+            }
+            Op::ReadDir => {
+                let entry = self.dir_entry.take();
+                self.client.map(|client| client.read_dir_done(entry, rcode));
+            }
+            Op::Mkdir | Op::Remove => {
+                self.client.map(|client| client.command_done(rcode));
+            }
+        }
+        if self.remount.take() && !self.is_busy() && self.storage.block_count().is_some() {
+            self.mount();
+        }
+    }
+
+    // FAT entries
+
+    fn fat_byte(&self, volume: &Volume, offset: u32) -> Step<u8> {
+        let sector = volume.fat_start + offset / BLOCK_SIZE as u32;
+        self.with_sector(sector, |data| data[offset as usize % BLOCK_SIZE])
+    }
+
+    fn fat_get(&self, volume: &Volume, cluster: u32) -> Step<u32> {
+        let offset = volume.fat_offset(cluster);
+        let sector = volume.fat_start + offset / BLOCK_SIZE as u32;
+        let at = offset as usize % BLOCK_SIZE;
+        match volume.fat_type {
+            FatType::Fat12 => {
+                // Entries may span two sectors
+                let value = self.fat_byte(volume, offset)? as u32
+                    | (self.fat_byte(volume, offset + 1)? as u32) << 8;
+                Ok(if cluster & 1 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xfff
+                })
+            }
+            FatType::Fat16 => self.with_sector(sector, |data| read_u16(data, at) as u32),
+            FatType::Fat32 => self.with_sector(sector, |data| read_u32(data, at) & 0x0fff_ffff),
+        }
+    }
+
+    fn fat_set(&self, volume: &Volume, cluster: u32, value: u32) -> Step<()> {
+        let offset = volume.fat_offset(cluster);
+        let sector = volume.fat_start + offset / BLOCK_SIZE as u32;
+        let at = offset as usize % BLOCK_SIZE;
+        match volume.fat_type {
+            FatType::Fat12 => {
+                let next = volume.fat_start + (offset + 1) / BLOCK_SIZE as u32;
+                // Both sectors must be cached before either is changed
+                self.with_sector(sector, |_| ())?;
+                self.with_sector(next, |_| ())?;
+                self.with_sector_mut(sector, |data| {
+                    data[at] = if cluster & 1 == 1 {
+                        data[at] & 0x0f | (value << 4) as u8
+                    } else {
+                        value as u8
+                    };
+                })?;
+                let at = (offset as usize + 1) % BLOCK_SIZE;
+                self.with_sector_mut(next, |data| {
+                    data[at] = if cluster & 1 == 1 {
+                        (value >> 4) as u8
+                    } else {
+                        data[at] & 0xf0 | (value >> 8) as u8 & 0x0f
+                    };
+                })
+            }
+            FatType::Fat16 => self.with_sector_mut(sector, |data| {
+                data[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+            }),
+            FatType::Fat32 => self.with_sector_mut(sector, |data| {
+                let value = read_u32(data, at) & 0xf000_0000 | value & 0x0fff_ffff;
+                data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }),
+        }
+    }
+
+    /// The cluster after `cluster` in its chain.
+    fn next_cluster(&self, volume: &Volume, cluster: u32) -> Step<Option<u32>> {
+        let next = self.fat_get(volume, cluster)?;
+        Ok(if volume.is_cluster(next) {
+            Some(next)
+        } else {
+            None
+        })
+    }
+
+    /// Finds a free cluster and marks it as the end of a chain. The cluster
+    /// is kept in `new_cluster` until it is used, so it is not allocated
+    /// again if the operation has to wait for a sector.
+    fn allocate(&self, volume: &Volume) -> Step<Option<u32>> {
+        if let Some(cluster) = self.new_cluster.get() {
+            return Ok(Some(cluster));
+        }
+        while self.scanned.get() < volume.clusters {
+            let cluster = match self.next_free.get() {
+                cluster if volume.is_cluster(cluster) => cluster,
+                _ => 2,
+            };
+            if self.fat_get(volume, cluster)? == 0 {
+                self.fat_set(volume, cluster, volume.end_of_chain())?;
+                self.scanned.set(0);
+                self.new_cluster.set(Some(cluster));
+                self.zeroed.set(0);
+                return Ok(Some(cluster));
+            }
+            self.next_free.set(cluster + 1);
+            self.scanned.set(self.scanned.get() + 1);
+        }
+        self.scanned.set(0);
+        Ok(None)
+    }
+
+    /// Zeroes the sectors of `new_cluster`, calling `first` on its first
+    /// sector.
+    fn zero_cluster<F: Fn(&mut [u8])>(&self, volume: &Volume, cluster: u32, first: F) -> Step<()> {
+        while self.zeroed.get() < volume.sectors_per_cluster {
+            let index = self.zeroed.get();
+            self.overwrite_sector(volume.cluster_sector(cluster) + index, |data| {
+                for byte in data.iter_mut() {
+                    *byte = 0;
+                }
+                if index == 0 {
+                    first(data);
+                }
+            })?;
+            self.zeroed.set(index + 1);
+        }
+        Ok(())
+    }
+
+    // Directories
+
+    /// The cursor after `cursor`, or `None` at the end of the directory.
+    fn advance(&self, volume: &Volume, cursor: Cursor) -> Step<Option<Cursor>> {
+        let index = cursor.index + 1;
+        if cursor.cluster == 0 {
+            Ok(if index < volume.root_entries {
+                Some(Cursor {
+                    cluster: 0,
+                    index: index,
+                })
+            } else {
+                None
+            })
+        } else if index < volume.sectors_per_cluster * ENTRIES_PER_SECTOR {
+            Ok(Some(Cursor {
+                cluster: cursor.cluster,
+                index: index,
+            }))
+        } else {
+            Ok(self
+                .next_cluster(volume, cursor.cluster)?
+                .map(|cluster| Cursor {
+                    cluster: cluster,
+                    index: 0,
+                }))
+        }
+    }
+
+    fn read_entry(&self, location: Location) -> Step<[u8; ENTRY_SIZE]> {
+        self.with_sector(location.sector, |data| {
+            let mut entry = [0; ENTRY_SIZE];
+            let start = location.index * ENTRY_SIZE;
+            entry.copy_from_slice(&data[start..start + ENTRY_SIZE]);
+            entry
+        })
+    }
+
+    fn write_entry(&self, location: Location, entry: &[u8; ENTRY_SIZE]) -> Step<()> {
+        self.with_sector_mut(location.sector, |data| {
+            let start = location.index * ENTRY_SIZE;
+            data[start..start + ENTRY_SIZE].copy_from_slice(entry);
+        })
+    }
+
+    /// Looks up the path of the operation, continuing from `walk`.
+    fn lookup(&self, volume: &Volume) -> Step<Lookup> {
+        let path = self.path.get();
+        let path = &path[..self.path_len.get()];
+        loop {
+            let walk = self.walk.get();
+            let (component, rest) = component(path, walk.start);
+            if component.is_empty() {
+                return Ok(Lookup::Root);
+            }
+            let name = match short_name(component) {
+                Some(name) => name,
+                None => return Ok(Lookup::Error(ReturnCode::EINVAL)),
+            };
+            let missing = |free: Option<Location>| {
+                Lookup::Missing(Missing {
+                    name: name,
+                    dir: walk.dir,
+                    free: free,
+                    last_cluster: walk.cursor.cluster,
+                    rest: rest,
+                    is_last: component_at_end(path, rest),
+                })
+            };
+
+            let location = match volume.location(walk.cursor) {
+                Some(location) => location,
+                None => return Ok(missing(walk.free)),
+            };
+            let entry = self.read_entry(location)?;
+            let mut next = walk;
+            if entry[0] == ENTRY_END {
+                return Ok(missing(walk.free.or(Some(location))));
+            } else if entry[0] == ENTRY_FREE {
+                next.free = next.free.or(Some(location));
+            } else if entry[11] & ATTR_VOLUME_ID == 0 && entry[..11] == name {
+                if component_at_end(path, rest) {
+                    return Ok(Lookup::Found(location, entry));
+                } else if entry[11] & ATTR_DIRECTORY == 0 {
+                    return Ok(Lookup::Error(ReturnCode::ENODEVICE));
+                }
+                let dir = match volume.entry_cluster(&entry) {
+                    0 => volume.root_dir(),
+                    cluster => cluster,
+                };
+                self.walk.set(Walk {
+                    dir: dir,
+                    start: rest,
+                    cursor: Cursor {
+                        cluster: dir,
+                        index: 0,
+                    },
+                    free: None,
+                });
+                continue;
+            }
+            match self.advance(volume, walk.cursor)? {
+                Some(cursor) => {
+                    next.cursor = cursor;
+                    self.walk.set(next);
+                }
+                None => return Ok(missing(next.free)),
+            }
+        }
+    }
+
+    // Operations
+
+    fn step(&self) -> Step<ReturnCode> {
+        if self.op.get() == Op::Mount {
+            return self.step_mount();
+        }
+        let volume = match self.volume.get() {
+            Some(volume) => volume,
+            None => return Ok(ReturnCode::EOFF),
+        };
+        loop {
+            let done = match self.phase.get() {
+                Phase::Lookup => {
+                    let lookup = self.lookup(&volume)?;
+                    self.looked_up(&volume, lookup)?
+                }
+                Phase::ExtendDir => self.extend_dir(&volume)?,
+                Phase::CreateEntry(location) => self.create_entry(&volume, location)?,
+                Phase::Transfer => {
+                    if self.op.get() == Op::Read {
+                        Some(self.read_data(&volume)?)
+                    } else {
+                        self.write_data(&volume)?
+                    }
+                }
+                Phase::UpdateEntry => {
+                    let file = self.get_file();
+                    let mut entry = self.read_entry(file.location)?;
+                    entry[20..22]
+                        .copy_from_slice(&((file.first_cluster >> 16) as u16).to_le_bytes());
+                    entry[26..28].copy_from_slice(&(file.first_cluster as u16).to_le_bytes());
+                    entry[28..32].copy_from_slice(&file.size.to_le_bytes());
+                    self.write_entry(file.location, &entry)?;
+                    self.phase.set(Phase::FreeChain);
+                    None
+                }
+                Phase::FreeChain => {
+                    while volume.is_cluster(self.freeing.get()) {
+                        let cluster = self.freeing.get();
+                        let next = self.fat_get(&volume, cluster)?;
+                        self.fat_set(&volume, cluster, 0)?;
+                        self.freeing.set(next);
+                    }
+                    Some(self.result.get())
+                }
+                Phase::List(cursor, count) => Some(self.list(&volume, cursor, count)?),
+            };
+            if let Some(rcode) = done {
+                return Ok(rcode);
+            }
+        }
+    }
+
+    fn step_mount(&self) -> Step<ReturnCode> {
+        let blocks = match self.storage.block_count() {
+            Some(blocks) => blocks,
+            None => return Ok(ReturnCode::EOFF),
+        };
+        let volume = match self.with_sector(0, |data| {
+            Volume::parse(data, 0, blocks).ok_or_else(|| Volume::partition_start(data))
+        })? {
+            Ok(volume) => Some(volume),
+            Err(Some(start)) if start < blocks => {
+                self.with_sector(start, |data| Volume::parse(data, start, blocks))?
+            }
+            Err(_) => None,
+        };
+        Ok(match volume {
+            Some(volume) => {
+                self.volume.set(Some(volume));
+                self.next_free.set(2);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOSUPPORT,
+        })
+    }
+
+    /// Carries on with the operation once its path is looked up.
+    fn looked_up(&self, volume: &Volume, lookup: Lookup) -> Step<Option<ReturnCode>> {
+        let op = self.op.get();
+        let entry = match lookup {
+            Lookup::Error(rcode) => return Ok(Some(rcode)),
+            Lookup::Root => {
+                return Ok(Some(match op {
+                    Op::ReadDir => self.list(volume, Cursor::start(volume.root_dir()), 0)?,
+                    Op::Mkdir => ReturnCode::EALREADY,
+                    _ => ReturnCode::EINVAL,
+                }))
+            }
+            Lookup::Missing(missing) => {
+                let create = match op {
+                    Op::Open => missing.is_last && self.flags.get().create,
+                    Op::Mkdir => true,
+                    _ => false,
+                };
+                if !create {
+                    return Ok(Some(ReturnCode::ENODEVICE));
+                }
+                self.missing.set(Some(missing));
+                match missing.free {
+                    Some(location) => self.phase.set(Phase::CreateEntry(location)),
+                    None if missing.last_cluster != 0 => self.phase.set(Phase::ExtendDir),
+                    None => return Ok(Some(ReturnCode::ENOMEM)),
+                }
+                return Ok(None);
+            }
+            Lookup::Found(location, entry) => (location, entry),
+        };
+
+        let (location, entry) = entry;
+        let directory = entry[11] & ATTR_DIRECTORY != 0;
+        let is_open = |location| {
+            self.files
+                .get()
+                .iter()
+                .any(|file| file.open && file.location == location)
+        };
+        match op {
+            Op::Open if directory => Ok(Some(ReturnCode::EINVAL)),
+            Op::Open if is_open(location) => Ok(Some(ReturnCode::EBUSY)),
+            Op::Open => {
+                let first_cluster = volume.entry_cluster(&entry);
+                let mut file = File {
+                    open: true,
+                    append: self.flags.get().append,
+                    location: location,
+                    first_cluster: first_cluster,
+                    size: read_u32(&entry, 28),
+                    position: 0,
+                    cluster: 0,
+                    cluster_index: 0,
+                };
+                if self.flags.get().truncate && first_cluster != 0 {
+                    file.first_cluster = 0;
+                    file.size = 0;
+                    self.freeing.set(first_cluster);
+                    self.phase.set(Phase::UpdateEntry);
+                    self.set_file(file);
+                    Ok(None)
+                } else {
+                    self.set_file(file);
+                    Ok(Some(ReturnCode::SUCCESS))
+                }
+            }
+            Op::ReadDir if directory => {
+                let cluster = match volume.entry_cluster(&entry) {
+                    0 => volume.root_dir(),
+                    cluster => cluster,
+                };
+                Ok(Some(self.list(volume, Cursor::start(cluster), 0)?))
+            }
+            Op::Mkdir if directory => Ok(Some(ReturnCode::EALREADY)),
+            Op::Remove if directory => Ok(Some(ReturnCode::EINVAL)),
+            Op::Remove if is_open(location) => Ok(Some(ReturnCode::EBUSY)),
+            Op::Remove => {
+                let mut entry = entry;
+                entry[0] = ENTRY_FREE;
+                self.write_entry(location, &entry)?;
+                self.freeing.set(volume.entry_cluster(&entry));
+                self.phase.set(Phase::FreeChain);
+                Ok(None)
+            }
+            _ => Ok(Some(ReturnCode::EINVAL)),
+        }
+    }
+
+    /// Adds a zeroed cluster to the directory of the missing component.
+    fn extend_dir(&self, volume: &Volume) -> Step<Option<ReturnCode>> {
+        let missing = match self.missing.get() {
+            Some(missing) => missing,
+            None => return Ok(Some(ReturnCode::FAIL)),
+        };
+        let cluster = match self.allocate(volume)? {
+            Some(cluster) => cluster,
+            None => return Ok(Some(ReturnCode::ENOMEM)),
+        };
+        self.zero_cluster(volume, cluster, |_| {})?;
+        self.fat_set(volume, missing.last_cluster, cluster)?;
+        self.new_cluster.set(None);
+        self.phase.set(Phase::CreateEntry(Location {
+            sector: volume.cluster_sector(cluster),
+            index: 0,
+        }));
+        Ok(None)
+    }
+
+    /// Creates the missing file or directory at `location`.
+    fn create_entry(&self, volume: &Volume, location: Location) -> Step<Option<ReturnCode>> {
+        let missing = match self.missing.get() {
+            Some(missing) => missing,
+            None => return Ok(Some(ReturnCode::FAIL)),
+        };
+        let (attributes, cluster) = if self.op.get() == Op::Mkdir {
+            let cluster = match self.allocate(volume)? {
+                Some(cluster) => cluster,
+                None => return Ok(Some(ReturnCode::ENOMEM)),
+            };
+            let parent = if missing.dir == volume.root_dir() {
+                0
+            } else {
+                missing.dir
+            };
+            self.zero_cluster(volume, cluster, |data| {
+                data[..ENTRY_SIZE].copy_from_slice(&new_entry(
+                    b".          ",
+                    ATTR_DIRECTORY,
+                    cluster,
+                ));
+                data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&new_entry(
+                    b"..         ",
+                    ATTR_DIRECTORY,
+                    parent,
+                ));
+            })?;
+            (ATTR_DIRECTORY, cluster)
+        } else {
+            (ATTR_ARCHIVE, 0)
+        };
+        self.write_entry(location, &new_entry(&missing.name, attributes, cluster))?;
+        self.new_cluster.set(None);
+
+        if self.op.get() == Op::Open {
+            self.set_file(File {
+                open: true,
+                append: self.flags.get().append,
+                location: location,
+                ..CLOSED
+            });
+            Ok(Some(ReturnCode::SUCCESS))
+        } else if missing.is_last {
+            Ok(Some(ReturnCode::SUCCESS))
+        } else {
+            // Create the directories below it
+            self.walk.set(Walk {
+                dir: cluster,
+                start: missing.rest,
+                cursor: Cursor::start(cluster),
+                free: None,
+            });
+            self.phase.set(Phase::Lookup);
+            Ok(None)
+        }
+    }
+
+    /// Gets the `index`th entry of a directory, counting `count` entries
+    /// before `cursor`.
+    fn list(&self, volume: &Volume, cursor: Cursor, count: usize) -> Step<ReturnCode> {
+        let (mut cursor, mut count) = (cursor, count);
+        loop {
+            self.phase.set(Phase::List(cursor, count));
+            let location = match volume.location(cursor) {
+                Some(location) => location,
+                None => return Ok(ReturnCode::SUCCESS),
+            };
+            let entry = self.read_entry(location)?;
+            if entry[0] == ENTRY_END {
+                return Ok(ReturnCode::SUCCESS);
+            }
+            let listed =
+                entry[0] != ENTRY_FREE && entry[0] != b'.' && entry[11] & ATTR_VOLUME_ID == 0;
+            if listed {
+                if count == self.index.get() {
+                    self.dir_entry.set(Some(DirEntry::from_entry(&entry)));
+                    return Ok(ReturnCode::SUCCESS);
+                }
+            }
+            match self.advance(volume, cursor)? {
+                Some(next) => cursor = next,
+                None => return Ok(ReturnCode::SUCCESS),
+            }
+            if listed {
+                count += 1;
+            }
+        }
+    }
+
+    /// The cluster holding the position of the file, walking its chain from
+    /// the last cluster found. `None` if the chain ends before it.
+    fn position_cluster(&self, volume: &Volume) -> Step<Option<u32>> {
+        let mut file = self.get_file();
+        if file.first_cluster == 0 {
+            return Ok(None);
+        }
+        let index = file.position / volume.cluster_bytes();
+        if file.cluster == 0 || file.cluster_index > index {
+            file.cluster = file.first_cluster;
+            file.cluster_index = 0;
+            self.set_file(file);
+        }
+        while file.cluster_index < index {
+            match self.next_cluster(volume, file.cluster)? {
+                Some(next) => {
+                    file.cluster = next;
+                    file.cluster_index += 1;
+                    self.set_file(file);
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(file.cluster))
+    }
+
+    fn read_data(&self, volume: &Volume) -> Step<ReturnCode> {
+        loop {
+            let file = self.get_file();
+            let done = self.done.get();
+            if done == self.length.get() || file.position >= file.size {
+                return Ok(ReturnCode::SUCCESS);
+            }
+            let cluster = match self.position_cluster(volume)? {
+                Some(cluster) => cluster,
+                None => return Ok(ReturnCode::FAIL),
+            };
+            let in_cluster = file.position % volume.cluster_bytes();
+            let sector = volume.cluster_sector(cluster) + in_cluster / BLOCK_SIZE as u32;
+            let offset = (in_cluster as usize) % BLOCK_SIZE;
+            let count = cmp::min(
+                cmp::min(BLOCK_SIZE - offset, self.length.get() - done),
+                (file.size - file.position) as usize,
+            );
+            self.with_sector(sector, |data| {
+                self.buffer.map(|buffer| {
+                    buffer[done..done + count].copy_from_slice(&data[offset..offset + count])
+                });
+            })?;
+            self.done.set(done + count);
+            let mut file = self.get_file();
+            file.position += count as u32;
+            self.set_file(file);
+        }
+    }
+
+    fn write_data(&self, volume: &Volume) -> Step<Option<ReturnCode>> {
+        loop {
+            let mut file = self.get_file();
+            if file.append {
+                file.position = file.size;
+                self.set_file(file);
+            }
+            let done = self.done.get();
+            if done == self.length.get() {
+                self.phase.set(Phase::UpdateEntry);
+                return Ok(None);
+            }
+
+            let cluster = match self.position_cluster(volume)? {
+                Some(cluster) => cluster,
+                None => {
+                    // Add a cluster to the end of the file
+                    let cluster = match self.allocate(volume)? {
+                        Some(cluster) => cluster,
+                        None => {
+                            self.result.set(ReturnCode::ENOMEM);
+                            self.phase.set(Phase::UpdateEntry);
+                            return Ok(None);
+                        }
+                    };
+                    let mut file = self.get_file();
+                    if file.first_cluster == 0 {
+                        file.first_cluster = cluster;
+                        file.cluster_index = 0;
+                    } else {
+                        self.fat_set(volume, file.cluster, cluster)?;
+                        file.cluster_index += 1;
+                    }
+                    file.cluster = cluster;
+                    self.new_cluster.set(None);
+                    self.set_file(file);
+                    continue;
+                }
+            };
+
+            let file = self.get_file();
+            let in_cluster = file.position % volume.cluster_bytes();
+            let sector = volume.cluster_sector(cluster) + in_cluster / BLOCK_SIZE as u32;
+            let offset = (in_cluster as usize) % BLOCK_SIZE;
+            let count = cmp::min(BLOCK_SIZE - offset, self.length.get() - done);
+            let copy = |data: &mut [u8]| {
+                self.buffer.map(|buffer| {
+                    data[offset..offset + count].copy_from_slice(&buffer[done..done + count])
+                });
+            };
+            if (file.position - offset as u32) >= file.size || count == BLOCK_SIZE {
+                // Nothing in the sector is kept
+                self.overwrite_sector(sector, copy)?;
+            } else {
+                self.with_sector_mut(sector, copy)?;
+            }
+            self.done.set(done + count);
+            let mut file = self.get_file();
+            file.position += count as u32;
+            file.size = cmp::max(file.size, file.position);
+            self.set_file(file);
+        }
+    }
+}
+
+impl Cursor {
+    fn start(dir: u32) -> Cursor {
+        Cursor {
+            cluster: dir,
+            index: 0,
+        }
+    }
+}
+
+impl DynamicDeferredCallClient for Fat<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.is_busy() && self.io.get() == Io::Idle {
+            self.run();
+        }
+    }
+}
+
+impl BlockStorageClient for Fat<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], rcode: ReturnCode) {
+        if let Io::Read(slot) = self.io.replace(Io::Idle) {
+            self.slots[slot].buffer.replace(buffer);
+            if rcode == ReturnCode::SUCCESS {
+                self.run();
+            } else {
+                self.slots[slot].sector.set(None);
+                self.abort(ReturnCode::FAIL);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], rcode: ReturnCode) {
+        if let Io::Write { slot, copy, then } = self.io.replace(Io::Idle) {
+            self.slots[slot].buffer.replace(buffer);
+            if rcode != ReturnCode::SUCCESS {
+                self.slots[slot].sector.set(None);
+                self.slots[slot].dirty.set(false);
+                return self.abort(ReturnCode::FAIL);
+            }
+            let more_copies = self.volume.get().map_or(false, |volume| {
+                self.slots[slot]
+                    .sector
+                    .get()
+                    .map_or(false, |sector| volume.is_fat_sector(sector))
+                    && copy + 1 < volume.fats
+            });
+            if more_copies {
+                return self.write_back(slot, copy + 1, then);
+            }
+            self.slots[slot].dirty.set(false);
+            match then {
+                Then::Fetch(need) => {
+                    if self.fetch(need) {
+                        self.run();
+                    }
+                }
+                Then::Flush => self.flush(),
+            }
+        }
+    }
+
+    fn medium_changed(&self) {
+        self.unmount();
+        if self.is_busy() {
+            self.remount.set(true);
+        } else if self.storage.block_count().is_some() {
+            self.mount();
+        }
+    }
+}
+
+/// A new directory entry.
+fn new_entry(name: &[u8; 11], attributes: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attributes;
+    for &at in [16, 18, 24].iter() {
+        entry[at..at + 2].copy_from_slice(&DATE.to_le_bytes());
+    }
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry
+}
+
+/// The path component at `start`, skipping slashes, and where the rest of
+/// the path starts.
+fn component(path: &[u8], start: usize) -> (&[u8], usize) {
+    let start = path[start..]
+        .iter()
+        .position(|byte| *byte != b'/')
+        .map_or(path.len(), |skipped| start + skipped);
+    let end = path[start..]
+        .iter()
+        .position(|byte| *byte == b'/')
+        .map_or(path.len(), |length| start + length);
+    (&path[start..end], end)
+}
+
+fn component_at_end(path: &[u8], rest: usize) -> bool {
+    path[rest..].iter().all(|byte| *byte == b'/')
+}
+
+/// The name of a directory entry for a path component, such as
+/// `LOG     TXT` for `log.txt`.
+fn short_name(component: &[u8]) -> Option<[u8; 11]> {
+    let (base, extension) = match component.iter().position(|byte| *byte == b'.') {
+        Some(dot) => (&component[..dot], &component[dot + 1..]),
+        None => (component, &[][..]),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    let mut name = [b' '; 11];
+    let (name_base, name_extension) = name.split_at_mut(8);
+    for (to, from) in name_base
+        .iter_mut()
+        .zip(base)
+        .chain(name_extension.iter_mut().zip(extension))
+    {
+        *to = match from.to_ascii_uppercase() {
+            byte @ b'A'..=b'Z' | byte @ b'0'..=b'9' => byte,
+            byte if b"!#$%&'()-@^_`{}~".contains(&byte) => byte,
+            _ => return None,
+        };
+    }
+    Some(name)
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_names() {
+        assert_eq!(short_name(b"log.txt"), Some(*b"LOG     TXT"));
+        assert_eq!(short_name(b"DATA_001"), Some(*b"DATA_001   "));
+        assert_eq!(short_name(b"a.b"), Some(*b"A       B  "));
+        assert_eq!(short_name(b"toolongname.txt"), None);
+        assert_eq!(short_name(b"file.text"), None);
+        assert_eq!(short_name(b"a.b.c"), None);
+        assert_eq!(short_name(b".."), None);
+        assert_eq!(short_name(b"sp ace"), None);
+    }
+
+    #[test]
+    fn path_components() {
+        let path = b"/apps//data/log.txt/";
+        assert_eq!(component(path, 0), (&b"apps"[..], 5));
+        assert_eq!(component(path, 5), (&b"data"[..], 11));
+        assert_eq!(component(path, 11), (&b"log.txt"[..], 19));
+        assert!(component_at_end(path, 19));
+        assert_eq!(component(path, 19), (&b""[..], 20));
+    }
+}
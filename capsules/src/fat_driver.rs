@@ -0,0 +1,504 @@
+//! Provides userspace with files on a FAT volume.
+//!
+//! Each app gets its own directory on a `capsules::fat::Fat` volume,
+//! `/APPS/XXXXXXXX`, named after the persistent identity of the app in
+//! hexadecimal. Paths given by the app are relative to that directory, which
+//! is created when the app first uses the volume, so an app keeps its files
+//! across reboots and reinstalls, and cannot see the files of other apps.
+//! Apps without a package name cannot use the volume.
+//!
+//! The directory is only private among apps that do not lie about their
+//! package name, which the kernel cannot check. An app whose name hashes to
+//! the same identity as another app shares its directory, see
+//! `AppId::persistent_id`.
+//!
+//! An app opens a file to get a handle, which it then reads, writes and
+//! seeks with. Handles are shared by all apps, and those of apps that no
+//! longer exist are closed when another app needs one. All files are closed
+//! when the medium changes.
+//!
+//! The volume runs one operation at a time. The driver queues one operation
+//! per app, and runs them in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fat_driver = static_init!(
+//!     capsules::fat_driver::FatDriver<'static>,
+//!     capsules::fat_driver::FatDriver::new(
+//!         fat,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut capsules::fat_driver::BUFFER,
+//!     )
+//! );
+//! fat.set_client(fat_driver);
+//! ```
+
+use crate::fat::{DirEntry, Fat, FatClient, OpenFlags, MAX_FILES, MAX_PATH_LEN};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::BLOCK_SIZE;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Fat as usize;
+
+pub static mut BUFFER: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+/// Directory holding the directories of apps.
+const APPS_DIR: &[u8] = b"APPS/";
+/// Length of the path of the directory of an app, with a trailing slash.
+const SANDBOX_LEN: usize = APPS_DIR.len() + 9;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Open { flags: OpenFlags },
+    Read { handle: usize, len: usize },
+    Write { handle: usize, len: usize },
+    ReadDir { index: usize },
+    Mkdir,
+    Remove,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    path: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    /// Operation waiting for the volume, with the length of its path.
+    pending: Option<(Command, usize)>,
+    /// Whether the directory of the app exists on the mounted volume.
+    sandbox: bool,
+}
+
+pub struct FatDriver<'a> {
+    fat: &'a Fat<'a>,
+    apps: Grant<App>,
+    buffer: TakeCell<'static, [u8]>,
+    current_app: OptionalCell<AppId>,
+    /// The operation of the current app, if it is creating the directory of
+    /// the app first.
+    waiting: Cell<Option<(Command, usize)>>,
+    /// The app that opened each file.
+    owners: Cell<[Option<AppId>; MAX_FILES]>,
+    buffer_len: usize,
+}
+
+impl FatDriver<'a> {
+    pub fn new(fat: &'a Fat<'a>, grant: Grant<App>, buffer: &'static mut [u8]) -> FatDriver<'a> {
+        FatDriver {
+            fat: fat,
+            apps: grant,
+            buffer_len: buffer.len(),
+            buffer: TakeCell::new(buffer),
+            current_app: OptionalCell::empty(),
+            waiting: Cell::new(None),
+            owners: Cell::new([None; MAX_FILES]),
+        }
+    }
+
+    fn owns(&self, appid: AppId, handle: usize) -> bool {
+        self.owners.get().get(handle).cloned().flatten() == Some(appid)
+    }
+
+    fn set_owner(&self, handle: usize, owner: Option<AppId>) {
+        let mut owners = self.owners.get();
+        if let Some(entry) = owners.get_mut(handle) {
+            *entry = owner;
+        }
+        self.owners.set(owners);
+    }
+
+    fn enqueue(&self, appid: AppId, command: Command, path_len: usize) -> ReturnCode {
+        if appid.persistent_id().is_none() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                let path_allowed = app.path.as_ref().map_or(0, |path| path.len());
+                let data_allowed = app.data.as_ref().map_or(0, |data| data.len());
+                if app.pending.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                match command {
+                    Command::Read { handle, len } | Command::Write { handle, len } => {
+                        if !self.owns(appid, handle) || len > data_allowed {
+                            return ReturnCode::EINVAL;
+                        }
+                    }
+                    _ => {
+                        if path_len > path_allowed {
+                            return ReturnCode::EINVAL;
+                        } else if path_len > MAX_PATH_LEN - SANDBOX_LEN {
+                            return ReturnCode::ESIZE;
+                        }
+                    }
+                }
+                app.pending = Some((command, path_len));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+
+        if self.current_app.is_some() || self.fat.is_busy() {
+            // Runs once the volume is done
+            ReturnCode::SUCCESS
+        } else {
+            self.run(appid)
+        }
+    }
+
+    /// Starts the pending operation of `appid`, creating the directory of
+    /// the app first if needed.
+    fn run(&self, appid: AppId) -> ReturnCode {
+        let namespace = match appid.persistent_id() {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let mut path = [0; MAX_PATH_LEN];
+        path[..APPS_DIR.len()].copy_from_slice(APPS_DIR);
+        for (i, byte) in path[APPS_DIR.len()..SANDBOX_LEN - 1].iter_mut().enumerate() {
+            *byte = b"0123456789ABCDEF"[(namespace >> (28 - 4 * i)) as usize & 0xf];
+        }
+        path[SANDBOX_LEN - 1] = b'/';
+
+        self.apps
+            .enter(appid, |app, _| {
+                let (command, path_len) = match app.pending.take() {
+                    Some(pending) => pending,
+                    None => return ReturnCode::FAIL,
+                };
+                self.current_app.set(appid);
+                let result = match command {
+                    Command::Read { handle, len } => {
+                        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                            let len = cmp::min(len, buffer.len());
+                            match self.fat.read(handle, buffer, len) {
+                                Ok(()) => ReturnCode::SUCCESS,
+                                Err((result, buffer)) => {
+                                    buffer.map(|buffer| self.buffer.replace(buffer));
+                                    result
+                                }
+                            }
+                        })
+                    }
+                    Command::Write { handle, len } => {
+                        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                            let len = cmp::min(len, buffer.len());
+                            app.data.as_ref().map(|data| {
+                                let len = cmp::min(len, data.len());
+                                buffer[..len].copy_from_slice(&data.as_ref()[..len]);
+                            });
+                            match self.fat.write(handle, buffer, len) {
+                                Ok(()) => ReturnCode::SUCCESS,
+                                Err((result, buffer)) => {
+                                    buffer.map(|buffer| self.buffer.replace(buffer));
+                                    result
+                                }
+                            }
+                        })
+                    }
+                    _ if !app.sandbox && self.fat.is_mounted() => {
+                        self.waiting.set(Some((command, path_len)));
+                        self.fat.mkdir(&path[..SANDBOX_LEN - 1])
+                    }
+                    _ => {
+                        let path_len = SANDBOX_LEN + path_len;
+                        match app.path.as_ref() {
+                            Some(slice) if slice.len() >= path_len - SANDBOX_LEN => {
+                                path[SANDBOX_LEN..path_len]
+                                    .copy_from_slice(&slice.as_ref()[..path_len - SANDBOX_LEN]);
+                            }
+                            _ => {
+                                self.current_app.clear();
+                                return ReturnCode::EINVAL;
+                            }
+                        }
+                        let path = &path[..path_len];
+                        match command {
+                            Command::Open { flags } => {
+                                self.close_orphans();
+                                self.fat.open(path, flags)
+                            }
+                            Command::ReadDir { index } => self.fat.read_dir(path, index),
+                            Command::Mkdir => self.fat.mkdir(path),
+                            _ => self.fat.remove(path),
+                        }
+                    }
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.current_app.clear();
+                    self.waiting.set(None);
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Closes the files of apps that no longer exist.
+    fn close_orphans(&self) {
+        for (handle, owner) in self.owners.get().iter().enumerate() {
+            let exists = owner.map_or(true, |appid| self.apps.enter(appid, |_, _| ()).is_ok());
+            if !exists {
+                self.fat.close(handle);
+                self.set_owner(handle, None);
+            }
+        }
+    }
+
+    /// Runs the next pending operation, reporting operations that fail to
+    /// start through their callbacks.
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            if self.current_app.is_some() || self.fat.is_busy() {
+                return;
+            }
+            let appid = cntr.enter(|app, _| app.pending.map(|_| app.appid()));
+            if let Some(appid) = appid {
+                let result = self.run(appid);
+                if result != ReturnCode::SUCCESS {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback
+                            .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+                    });
+                }
+            }
+        }
+    }
+
+    /// Schedules the callback of the app whose operation completed.
+    fn done(&self, result: ReturnCode, arg1: usize, arg2: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(result), arg1, arg2));
+            });
+        });
+        self.check_queue();
+    }
+}
+
+impl FatClient for FatDriver<'a> {
+    fn mount_done(&self, _rcode: ReturnCode) {
+        // Files were closed, and the volume may be a different one
+        self.owners.set([None; MAX_FILES]);
+        self.apps.each(|app| app.sandbox = false);
+        self.check_queue();
+    }
+
+    fn open_done(&self, handle: usize, rcode: ReturnCode) {
+        if rcode == ReturnCode::SUCCESS {
+            self.set_owner(handle, self.current_app.map(|appid| *appid));
+        }
+        self.done(rcode, handle, 0);
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode) {
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.data.as_mut().map(|data| {
+                    let len = cmp::min(cmp::min(length, buffer.len()), data.len());
+                    data.as_mut()[..len].copy_from_slice(&buffer[..len]);
+                });
+            });
+        });
+        self.buffer.replace(buffer);
+        self.done(rcode, length, 0);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.done(rcode, length, 0);
+    }
+
+    fn read_dir_done(&self, entry: Option<DirEntry>, rcode: ReturnCode) {
+        let (name_len, size) = match entry {
+            Some(entry) => {
+                let name_len = self.current_app.map_or(0, |appid| {
+                    self.apps
+                        .enter(*appid, |app, _| {
+                            app.data.as_mut().map_or(0, |data| {
+                                let data = data.as_mut();
+                                let name = entry.name();
+                                let len = cmp::min(name.len(), data.len());
+                                data[..len].copy_from_slice(&name[..len]);
+                                if entry.directory && len < data.len() {
+                                    data[len] = b'/';
+                                    len + 1
+                                } else {
+                                    len
+                                }
+                            })
+                        })
+                        .unwrap_or(0)
+                });
+                (name_len, entry.size as usize)
+            }
+            None => (0, 0),
+        };
+        self.done(rcode, name_len, size);
+    }
+
+    fn command_done(&self, rcode: ReturnCode) {
+        match self.waiting.take() {
+            Some(pending) => {
+                // The directory of the app was created
+                let appid = match self.current_app.take() {
+                    Some(appid) => appid,
+                    None => return,
+                };
+                if rcode != ReturnCode::SUCCESS && rcode != ReturnCode::EALREADY {
+                    self.current_app.set(appid);
+                    return self.done(rcode, 0, 0);
+                }
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.sandbox = true;
+                    app.pending = Some(pending);
+                });
+                let result = self.run(appid);
+                if result != ReturnCode::SUCCESS {
+                    self.current_app.set(appid);
+                    self.done(result, 0, 0);
+                }
+            }
+            None => self.done(rcode, 0, 0),
+        }
+    }
+}
+
+impl Driver for FatDriver<'a> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The path of a file or directory, relative to the directory of
+    ///   the app.
+    /// - `1`: Data read from or written to a file, or the name of a directory
+    ///   entry.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.path = slice,
+                    1 => app.data = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An operation completed. The callback gets the result of the
+    ///   operation and:
+    ///   - for an open, the handle of the file.
+    ///   - for a read or write, the number of bytes transferred, which is less
+    ///     than requested at the end of the file, or if the volume is full.
+    ///   - for a directory listing, the length of the name of the entry, or 0
+    ///     past the last one, and the size of the entry.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| {
+                match subscribe_num {
+                    0 => app.callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Command interface.
+    ///
+    /// Paths have 8.3 names, separated by `/`. A path operation fails with
+    /// ENODEVICE if the file or directory does not exist.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return SUCCESS if this driver is included on the platform.
+    /// - `1`: Open the file at the path of length `arg1`. `arg2` holds flags:
+    ///   bit 0 creates the file if it does not exist, bit 1 empties it, and
+    ///   bit 2 makes every write append to it.
+    /// - `2`: Close the file with handle `arg1`.
+    /// - `3`: Read up to `arg2` bytes from file `arg1` into the data buffer.
+    /// - `4`: Write `arg2` bytes of the data buffer to file `arg1`.
+    /// - `5`: Move the position of file `arg1` to `arg2`, which cannot be past
+    ///   the end of the file.
+    /// - `6`: Return the size of file `arg1`.
+    /// - `7`: Get entry `arg2` of the directory at the path of length `arg1`,
+    ///   with its name in the data buffer. Names of directories end in `/`.
+    /// - `8`: Create the directory at the path of length `arg1`, and the
+    ///   directories it is in.
+    /// - `9`: Remove the file at the path of length `arg1`.
+    /// - `10`: Return the longest read or write.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                let flags = OpenFlags {
+                    create: arg2 & 1 != 0,
+                    truncate: arg2 & 2 != 0,
+                    append: arg2 & 4 != 0,
+                };
+                self.enqueue(appid, Command::Open { flags: flags }, arg1)
+            }
+            2 => {
+                if !self.owns(appid, arg1) {
+                    return ReturnCode::EINVAL;
+                }
+                let result = self.fat.close(arg1);
+                if result == ReturnCode::SUCCESS {
+                    self.set_owner(arg1, None);
+                }
+                result
+            }
+            3 => self.enqueue(
+                appid,
+                Command::Read {
+                    handle: arg1,
+                    len: arg2,
+                },
+                0,
+            ),
+            4 => self.enqueue(
+                appid,
+                Command::Write {
+                    handle: arg1,
+                    len: arg2,
+                },
+                0,
+            ),
+            5 if self.owns(appid, arg1) => self.fat.seek(arg1, arg2),
+            6 if self.owns(appid, arg1) => self.fat.size(arg1).map_or(ReturnCode::EINVAL, |size| {
+                ReturnCode::SuccessWithValue { value: size }
+            }),
+            5 | 6 => ReturnCode::EINVAL,
+            7 => self.enqueue(appid, Command::ReadDir { index: arg2 }, arg1),
+            8 => self.enqueue(appid, Command::Mkdir, arg1),
+            9 => self.enqueue(appid, Command::Remove, arg1),
+            10 => ReturnCode::SuccessWithValue {
+                value: self.buffer_len,
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,1071 @@
+//! Kernel updates through two image slots.
+//!
+//! The flash holds two slots of the same size for kernel images, a scratch
+//! page and two control pages. The kernel always runs from slot A. A new image is
+//! received into slot B, its SHA-256 digest is checked, and the control page
+//! marks it as pending. At the next reset the boot stage swaps the contents of
+//! the slots, one page at a time through the scratch page, and starts the new
+//! kernel in the testing state. The new kernel is confirmed by calling
+//! `confirm()`, either by an application that checked the system, or by a
+//! `ConfirmTimer` once the kernel has run for some time. If it has not been
+//! confirmed after `MAX_BOOT_ATTEMPTS` resets, the boot stage swaps the slots
+//! back and the previous kernel runs again.
+//!
+//! An update image is the kernel binary followed by the SHA-256 digest of the
+//! binary, 32 bytes. Transports pass the image to `write()` in order, in
+//! chunks that do not cross a flash page boundary. The USB DFU function in
+//! `usb::dfu` and the syscall driver in `firmware_update_driver` are such
+//! transports.
+//!
+//! Each control page starts with the record below, little endian. The
+//! checksum covers the bytes before it. The two control pages are written in
+//! turn, each record with the next sequence number, and the newest valid
+//! record is the state of the slots. A reset while a control page is erased
+//! or programmed thus leaves the previous record in the other page. If
+//! neither page holds a valid record, such as on erased flash, the slots hold
+//! a confirmed image of unknown length.
+//!
+//! ```text
+//! 0      4      5         6          7          8           12            16             20         24         28
+//! +------+------+---------+----------+----------+-----------+-------------+--------------+----------+----------+
+//! | magic| state| attempts| swap step| reserved | swap page | image length| active length| sequence | checksum |
+//! +------+------+---------+----------+----------+-----------+-------------+--------------+----------+----------+
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let update = static_init!(
+//!     capsules::firmware_update::FirmwareUpdate<
+//!         'static,
+//!         nrf52::nvmc::Nvmc,
+//!         capsules::sha256::Sha256Software<'static>,
+//!     >,
+//!     capsules::firmware_update::FirmwareUpdate::new(
+//!         &nrf52::nvmc::NVMC,
+//!         sha,
+//!         capsules::firmware_update::FirmwareSlots::new(0x38, 0x30, 0x68),
+//!         page_buffer,
+//!         hash_buffer,
+//!         digest_buffer,
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52::nvmc::NVMC, update);
+//! hil::digest::Digest::set_client(sha, update);
+//! update.initialize_callback_handle(dynamic_deferred_caller.register(update).unwrap());
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Marks a valid control page ("TKAB").
+pub const CONTROL_MAGIC: u32 = 0x4241_4b54;
+
+/// Size of the record at the start of a control page.
+pub const CONTROL_LENGTH: usize = 28;
+
+/// Number of control pages, starting at `FirmwareSlots::control_page`.
+pub const CONTROL_PAGES: usize = 2;
+
+/// Size of the digest following the kernel binary in an update image.
+pub const DIGEST_LENGTH: usize = 32;
+
+/// Number of resets a new kernel gets to confirm itself before the boot stage
+/// rolls back to the previous one.
+pub const MAX_BOOT_ATTEMPTS: u8 = 3;
+
+/// State of the images recorded in the control page.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SlotState {
+    /// Slot A holds a confirmed kernel.
+    Confirmed = 0,
+    /// Slot B holds a verified image to be swapped in at the next reset.
+    Pending = 1,
+    /// The boot stage is swapping in the image of slot B.
+    Swapping = 2,
+    /// Slot A holds a new kernel that has not confirmed itself yet, slot B
+    /// the previous kernel.
+    Testing = 3,
+    /// The boot stage is swapping the previous kernel back into slot A.
+    RollingBack = 4,
+    /// Slot A holds the previous kernel again after a failed update.
+    RolledBack = 5,
+}
+
+impl SlotState {
+    fn from_u8(value: u8) -> Option<SlotState> {
+        match value {
+            0 => Some(SlotState::Confirmed),
+            1 => Some(SlotState::Pending),
+            2 => Some(SlotState::Swapping),
+            3 => Some(SlotState::Testing),
+            4 => Some(SlotState::RollingBack),
+            5 => Some(SlotState::RolledBack),
+            _ => None,
+        }
+    }
+}
+
+/// What the boot stage does after `BootControl::boot()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BootAction {
+    /// Start the kernel in slot A.
+    Run,
+    /// Swap the slots with `swap_step()` and `advance_swap()`, then call
+    /// `swap_done()` and start the kernel in slot A.
+    Swap,
+}
+
+/// One step of swapping a page of the two slots through the scratch page.
+/// Each step only overwrites data that a previous step saved, so that the
+/// boot stage can repeat an interrupted step after a power loss.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SwapStep {
+    /// Copy the page of slot A to the scratch page.
+    SaveActive = 0,
+    /// Copy the page of slot B to slot A.
+    MoveUpdate = 1,
+    /// Copy the scratch page to slot B.
+    RestoreActive = 2,
+}
+
+/// Record of a control page.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BootControl {
+    pub state: SlotState,
+    /// Number of resets into a kernel in the testing state.
+    pub attempts: u8,
+    pub swap_step: u8,
+    /// Page of the slots being swapped.
+    pub swap_page: u32,
+    /// Length of the kernel in slot B, or 0 if unknown.
+    pub image_length: u32,
+    /// Length of the kernel in slot A, or 0 if unknown.
+    pub active_length: u32,
+    /// Incremented by `renew()` for every record written.
+    pub sequence: u32,
+}
+
+impl Default for BootControl {
+    fn default() -> BootControl {
+        BootControl {
+            state: SlotState::Confirmed,
+            attempts: 0,
+            swap_step: 0,
+            swap_page: 0,
+            image_length: 0,
+            active_length: 0,
+            sequence: 0,
+        }
+    }
+}
+
+fn get_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+/// FNV-1a hash of the record, which detects a control page whose erase or
+/// programming was interrupted.
+fn checksum(buf: &[u8]) -> u32 {
+    buf.iter().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+impl BootControl {
+    /// Reads the record of a control page, or `None` if it does not hold a
+    /// valid one.
+    pub fn decode(buf: &[u8]) -> Option<BootControl> {
+        if buf.len() < CONTROL_LENGTH
+            || get_u32(&buf[0..4]) != CONTROL_MAGIC
+            || get_u32(&buf[24..28]) != checksum(&buf[0..24])
+        {
+            return None;
+        }
+        SlotState::from_u8(buf[4]).map(|state| BootControl {
+            state,
+            attempts: buf[5],
+            swap_step: buf[6],
+            swap_page: get_u32(&buf[8..12]),
+            image_length: get_u32(&buf[12..16]),
+            active_length: get_u32(&buf[16..20]),
+            sequence: get_u32(&buf[20..24]),
+        })
+    }
+
+    /// Writes the record to the start of a control page buffer.
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&CONTROL_MAGIC.to_le_bytes());
+        buf[4] = self.state as u8;
+        buf[5] = self.attempts;
+        buf[6] = self.swap_step;
+        buf[7] = 0xff;
+        buf[8..12].copy_from_slice(&self.swap_page.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.image_length.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.active_length.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.sequence.to_le_bytes());
+        let sum = checksum(&buf[0..24]);
+        buf[24..28].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    /// Picks the newest of the records of the two control pages. Returns it
+    /// with the index of the control page to write the next record to, which
+    /// is the one that does not hold it.
+    pub fn newest(first: Option<BootControl>, second: Option<BootControl>) -> (BootControl, usize) {
+        match (first, second) {
+            (Some(a), Some(b)) => {
+                if (b.sequence.wrapping_sub(a.sequence) as i32) > 0 {
+                    (b, 0)
+                } else {
+                    (a, 1)
+                }
+            }
+            (Some(a), None) => (a, 1),
+            (None, Some(b)) => (b, 0),
+            (None, None) => (BootControl::default(), 0),
+        }
+    }
+
+    /// Moves on to the next sequence number, before the record is written to
+    /// the control page that does not hold the current one.
+    pub fn renew(&mut self) {
+        self.sequence = self.sequence.wrapping_add(1);
+    }
+
+    /// Marks a verified image of `image_length` bytes in slot B as pending.
+    /// Fails if slot B holds the previous kernel of an unconfirmed update.
+    pub fn stage(&mut self, image_length: u32) -> ReturnCode {
+        match self.state {
+            SlotState::Confirmed | SlotState::Pending | SlotState::RolledBack => {
+                self.state = SlotState::Pending;
+                self.image_length = image_length;
+                self.attempts = 0;
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    /// Marks the running kernel as good. Returns `false` if it was not being
+    /// tested.
+    pub fn confirm(&mut self) -> bool {
+        if self.state == SlotState::Testing {
+            self.state = SlotState::Confirmed;
+            self.attempts = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Decides what the boot stage does at a reset, counting the attempts to
+    /// boot a kernel in the testing state. The control page has to be
+    /// written back if this changed it.
+    pub fn boot(&mut self) -> BootAction {
+        match self.state {
+            SlotState::Pending => {
+                self.state = SlotState::Swapping;
+                self.swap_page = 0;
+                self.swap_step = SwapStep::SaveActive as u8;
+                BootAction::Swap
+            }
+            SlotState::Swapping | SlotState::RollingBack => BootAction::Swap,
+            SlotState::Testing => {
+                if self.attempts >= MAX_BOOT_ATTEMPTS {
+                    self.state = SlotState::RollingBack;
+                    self.swap_page = 0;
+                    self.swap_step = SwapStep::SaveActive as u8;
+                    BootAction::Swap
+                } else {
+                    self.attempts += 1;
+                    BootAction::Run
+                }
+            }
+            SlotState::Confirmed | SlotState::RolledBack => BootAction::Run,
+        }
+    }
+
+    /// Number of pages to swap so that both images move entirely.
+    pub fn swap_pages(&self, page_size: usize, slot_pages: usize) -> usize {
+        if self.image_length == 0 || self.active_length == 0 {
+            return slot_pages;
+        }
+        let length = cmp::max(self.image_length, self.active_length) as usize;
+        cmp::min((length + page_size - 1) / page_size, slot_pages)
+    }
+
+    /// The next step of the swap, with the page it applies to, or `None`
+    /// once all `pages` are swapped.
+    pub fn swap_step(&self, pages: usize) -> Option<(usize, SwapStep)> {
+        if self.swap_page as usize >= pages {
+            return None;
+        }
+        let step = match self.swap_step {
+            0 => SwapStep::SaveActive,
+            1 => SwapStep::MoveUpdate,
+            _ => SwapStep::RestoreActive,
+        };
+        Some((self.swap_page as usize, step))
+    }
+
+    /// Records that the current step of the swap is done.
+    pub fn advance_swap(&mut self) {
+        if self.swap_step >= SwapStep::RestoreActive as u8 {
+            self.swap_step = SwapStep::SaveActive as u8;
+            self.swap_page += 1;
+        } else {
+            self.swap_step += 1;
+        }
+    }
+
+    /// Records a completed swap. After swapping in a new kernel, the reset
+    /// that starts it counts as its first attempt.
+    pub fn swap_done(&mut self) {
+        self.state = match self.state {
+            SlotState::RollingBack => SlotState::RolledBack,
+            _ => SlotState::Testing,
+        };
+        self.attempts = if self.state == SlotState::Testing {
+            1
+        } else {
+            0
+        };
+        self.swap_page = 0;
+        self.swap_step = SwapStep::SaveActive as u8;
+        let length = self.image_length;
+        self.image_length = self.active_length;
+        self.active_length = length;
+    }
+}
+
+/// Location of the update slot and the control pages, in flash pages.
+#[derive(Copy, Clone, Debug)]
+pub struct FirmwareSlots {
+    /// First page of slot B.
+    pub update_start: usize,
+    /// Number of pages of each slot.
+    pub slot_pages: usize,
+    /// First of the `CONTROL_PAGES` control pages.
+    pub control_page: usize,
+}
+
+impl FirmwareSlots {
+    pub const fn new(update_start: usize, slot_pages: usize, control_page: usize) -> Self {
+        FirmwareSlots {
+            update_start,
+            slot_pages,
+            control_page,
+        }
+    }
+}
+
+/// Receives an update image into slot B.
+pub trait ImageUpdate<'a> {
+    fn set_client(&self, client: &'a dyn UpdateClient);
+
+    /// Starts receiving a new image. Cancels an image that is pending but not
+    /// swapped in yet. Fails with `EBUSY` while the running kernel is still
+    /// being tested, as slot B then holds the kernel to roll back to.
+    fn begin(&self) -> ReturnCode;
+
+    /// Appends `data` to the image. `data` must not cross a page boundary.
+    fn write(&self, data: &[u8]) -> ReturnCode;
+
+    /// Ends the image, checks its digest and marks it as pending.
+    fn finish(&self) -> ReturnCode;
+
+    /// Drops the image being received.
+    fn abort(&self) -> ReturnCode;
+
+    /// Confirms the running kernel, so that it is kept after the next reset.
+    fn confirm(&self) -> ReturnCode;
+}
+
+/// Completion of the operations of `ImageUpdate`.
+pub trait UpdateClient {
+    fn begin_done(&self, result: ReturnCode);
+    fn write_done(&self, result: ReturnCode);
+
+    /// `FAIL` if the digest does not match the image.
+    fn finish_done(&self, result: ReturnCode);
+
+    /// `EALREADY` if the running kernel did not need to be confirmed.
+    fn confirm_done(&self, result: ReturnCode);
+}
+
+/// Milliseconds after which `ConfirmTimer` tries again if the update is busy.
+const CONFIRM_RETRY_MS: u32 = 1000;
+
+/// Confirms the running kernel once it has run for a given time, for
+/// transports such as USB DFU where no application does so. A new kernel that
+/// resets or stops handling interrupts before then is not confirmed.
+pub struct ConfirmTimer<'a, A: hil::time::Alarm<'a>> {
+    update: &'a dyn ImageUpdate<'a>,
+    alarm: &'a A,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> ConfirmTimer<'a, A> {
+    pub fn new(update: &'a dyn ImageUpdate<'a>, alarm: &'a A) -> ConfirmTimer<'a, A> {
+        ConfirmTimer { update, alarm }
+    }
+
+    /// Confirms the kernel after `uptime_ms` milliseconds, which must be less
+    /// than half the period of the alarm.
+    pub fn start(&self, uptime_ms: u32) {
+        let frequency = <A::Frequency as hil::time::Frequency>::frequency();
+        let tics = uptime_ms as u64 * frequency as u64 / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(tics as u32));
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> hil::time::AlarmClient for ConfirmTimer<'a, A> {
+    fn fired(&self) {
+        // An image being received keeps the update busy
+        if self.update.confirm() == ReturnCode::EBUSY {
+            self.start(CONFIRM_RETRY_MS);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operation {
+    Begin,
+    Finish,
+    Confirm,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Reading the control page with the index.
+    ReadControl(Operation, usize),
+    WriteControl(Operation),
+    Receiving,
+    WritePage,
+    FlushPage,
+    ReadImage,
+    Hashing(usize),
+    Digest,
+}
+
+pub struct FirmwareUpdate<'a, F: hil::flash::Flash + 'static, D: hil::digest::Digest<'a, [u8; 32]>>
+{
+    flash: &'a F,
+    digest: &'a D,
+    slots: FirmwareSlots,
+    client: OptionalCell<&'a dyn UpdateClient>,
+    state: Cell<State>,
+    page: TakeCell<'static, F::Page>,
+    page_size: usize,
+    hash_buffer: TakeCell<'static, [u8]>,
+    digest_buffer: TakeCell<'static, [u8; 32]>,
+    expected_digest: Cell<[u8; DIGEST_LENGTH]>,
+    /// Bytes of the image received, or its length once finished
+    length: Cell<usize>,
+    /// Page of slot B being hashed
+    hash_page: Cell<usize>,
+    /// Offset in the image of the next byte to hash
+    hash_offset: Cell<usize>,
+    write_result: Cell<Option<ReturnCode>>,
+    /// Record of the first control page, while reading the second
+    first_control: Cell<Option<BootControl>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<F: hil::flash::Flash, D: hil::digest::Digest<'a, [u8; 32]> + hil::digest::Sha256>
+    FirmwareUpdate<'a, F, D>
+{
+    pub fn new(
+        flash: &'a F,
+        digest: &'a D,
+        slots: FirmwareSlots,
+        page: &'static mut F::Page,
+        hash_buffer: &'static mut [u8],
+        digest_buffer: &'static mut [u8; 32],
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> FirmwareUpdate<'a, F, D> {
+        let page_size = page.as_mut().len();
+        FirmwareUpdate {
+            flash,
+            digest,
+            slots,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            page: TakeCell::new(page),
+            page_size,
+            hash_buffer: TakeCell::new(hash_buffer),
+            digest_buffer: TakeCell::new(digest_buffer),
+            expected_digest: Cell::new([0; DIGEST_LENGTH]),
+            length: Cell::new(0),
+            hash_page: Cell::new(0),
+            hash_offset: Cell::new(0),
+            write_result: Cell::new(None),
+            first_control: Cell::new(None),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn slot_length(&self) -> usize {
+        self.slots.slot_pages * self.page_size
+    }
+
+    fn read_page(&self, page_number: usize, state: State) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            match self.flash.read_page(page_number, page) {
+                Ok(()) => {
+                    self.state.set(state);
+                    ReturnCode::SUCCESS
+                }
+                Err((rcode, page)) => {
+                    self.page.replace(page);
+                    rcode
+                }
+            }
+        })
+    }
+
+    fn write_page(&self, page_number: usize, state: State) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            match self.flash.write_page(page_number, page) {
+                Ok(()) => {
+                    self.state.set(state);
+                    ReturnCode::SUCCESS
+                }
+                Err((rcode, page)) => {
+                    self.page.replace(page);
+                    rcode
+                }
+            }
+        })
+    }
+
+    /// Fills the page buffer with the value of erased flash.
+    fn clear_page(&self) {
+        self.page.map(|page| {
+            for byte in page.as_mut().iter_mut() {
+                *byte = 0xff;
+            }
+        });
+    }
+
+    /// Reads the next page of slot B to hash, or computes the digest once all
+    /// of the image has been hashed.
+    fn verify_next_page(&self) -> ReturnCode {
+        let page = self.hash_page.get();
+        if page * self.page_size < self.length.get() {
+            self.read_page(self.slots.update_start + page, State::ReadImage)
+        } else {
+            self.digest_buffer
+                .take()
+                .map_or(ReturnCode::ENOMEM, |digest| match self.digest.run(digest) {
+                    Ok(()) => {
+                        self.state.set(State::Digest);
+                        ReturnCode::SUCCESS
+                    }
+                    Err((rcode, digest)) => {
+                        self.digest_buffer.replace(digest);
+                        rcode
+                    }
+                })
+        }
+    }
+
+    /// Feeds the binary in the page buffer to the digest, one hash buffer at
+    /// a time, then moves on to the next page.
+    fn hash_next(&self) -> ReturnCode {
+        let base = self.hash_page.get() * self.page_size;
+        let binary_end = self.length.get() - DIGEST_LENGTH;
+        let page_end = cmp::min(base + self.page_size, binary_end);
+        let offset = self.hash_offset.get();
+
+        if offset >= page_end {
+            self.hash_page.set(self.hash_page.get() + 1);
+            return self.verify_next_page();
+        }
+
+        let page = match self.page.take() {
+            Some(page) => page,
+            None => return ReturnCode::FAIL,
+        };
+        let rcode = self
+            .hash_buffer
+            .take()
+            .map_or(ReturnCode::ENOMEM, |buffer| {
+                let len = cmp::min(buffer.len(), page_end - offset);
+                buffer[..len].copy_from_slice(&page.as_mut()[offset - base..offset - base + len]);
+                let mut data = LeasableBuffer::new(buffer);
+                data.slice(0..len);
+                match self.digest.add_data(data) {
+                    Ok(_) => {
+                        self.state.set(State::Hashing(len));
+                        ReturnCode::SUCCESS
+                    }
+                    Err((rcode, buffer)) => {
+                        self.hash_buffer.replace(buffer);
+                        rcode
+                    }
+                }
+            });
+        self.page.replace(page);
+        rcode
+    }
+
+    /// Ends an operation that is still in progress with an error.
+    fn fail(&self, operation: Operation, rcode: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client.map(|client| match operation {
+            Operation::Begin => client.begin_done(rcode),
+            Operation::Finish => client.finish_done(rcode),
+            Operation::Confirm => client.confirm_done(rcode),
+        });
+    }
+
+    /// Starts reading the control pages for `operation`.
+    fn read_control(&self, operation: Operation) -> ReturnCode {
+        self.read_page(self.slots.control_page, State::ReadControl(operation, 0))
+    }
+
+    fn control_read(&self, operation: Operation, index: usize) {
+        let record = self
+            .page
+            .map_or(None, |page| BootControl::decode(page.as_mut()));
+        if index == 0 {
+            self.first_control.set(record);
+            let rcode = self.read_page(
+                self.slots.control_page + 1,
+                State::ReadControl(operation, 1),
+            );
+            if rcode != ReturnCode::SUCCESS {
+                self.fail(operation, rcode);
+            }
+            return;
+        }
+        let (control, next) = BootControl::newest(self.first_control.get(), record);
+        let mut updated = control;
+        let result = match operation {
+            Operation::Begin => match control.state {
+                SlotState::Pending => {
+                    updated.state = SlotState::Confirmed;
+                    ReturnCode::SUCCESS
+                }
+                SlotState::Confirmed | SlotState::RolledBack => ReturnCode::SUCCESS,
+                _ => ReturnCode::EBUSY,
+            },
+            Operation::Finish => updated.stage((self.length.get() - DIGEST_LENGTH) as u32),
+            Operation::Confirm => {
+                if updated.confirm() {
+                    ReturnCode::SUCCESS
+                } else {
+                    ReturnCode::EALREADY
+                }
+            }
+        };
+
+        if result != ReturnCode::SUCCESS {
+            self.fail(operation, result);
+        } else if updated == control {
+            self.control_written(operation);
+        } else {
+            updated.renew();
+            self.clear_page();
+            self.page.map(|page| updated.encode(page.as_mut()));
+            let rcode = self.write_page(
+                self.slots.control_page + next,
+                State::WriteControl(operation),
+            );
+            if rcode != ReturnCode::SUCCESS {
+                self.fail(operation, rcode);
+            }
+        }
+    }
+
+    fn control_written(&self, operation: Operation) {
+        match operation {
+            Operation::Begin => {
+                self.length.set(0);
+                self.clear_page();
+                self.state.set(State::Receiving);
+                self.client
+                    .map(|client| client.begin_done(ReturnCode::SUCCESS));
+            }
+            Operation::Finish => {
+                self.state.set(State::Idle);
+                self.client
+                    .map(|client| client.finish_done(ReturnCode::SUCCESS));
+            }
+            Operation::Confirm => {
+                self.state.set(State::Idle);
+                self.client
+                    .map(|client| client.confirm_done(ReturnCode::SUCCESS));
+            }
+        }
+    }
+
+    /// Starts checking the image once it is entirely in flash.
+    fn verify(&self) {
+        self.hash_page.set(0);
+        self.hash_offset.set(0);
+        let rcode = match self.digest.set_mode_sha256() {
+            Ok(()) => self.verify_next_page(),
+            Err(rcode) => rcode,
+        };
+        if rcode != ReturnCode::SUCCESS {
+            self.digest.clear_data();
+            self.fail(Operation::Finish, rcode);
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: hil::digest::Digest<'a, [u8; 32]> + hil::digest::Sha256>
+    ImageUpdate<'a> for FirmwareUpdate<'a, F, D>
+{
+    fn set_client(&self, client: &'a dyn UpdateClient) {
+        self.client.set(client);
+    }
+
+    fn begin(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle | State::Receiving if self.write_result.get().is_none() => {
+                self.read_control(Operation::Begin)
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Receiving {
+            return ReturnCode::EOFF;
+        }
+        if self.write_result.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let offset = self.length.get();
+        let start = offset % self.page_size;
+        if start + data.len() > self.page_size {
+            return ReturnCode::EINVAL;
+        }
+        if offset + data.len() > self.slot_length() {
+            return ReturnCode::ESIZE;
+        }
+
+        self.page
+            .map(|page| page.as_mut()[start..start + data.len()].copy_from_slice(data));
+        self.length.set(offset + data.len());
+
+        if start + data.len() == self.page_size {
+            let page_number = self.slots.update_start + offset / self.page_size;
+            self.write_page(page_number, State::WritePage)
+        } else {
+            self.write_result.set(Some(ReturnCode::SUCCESS));
+            self.handle.map(|handle| self.deferred_caller.set(*handle));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn finish(&self) -> ReturnCode {
+        if self.state.get() != State::Receiving || self.write_result.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let length = self.length.get();
+        if length <= DIGEST_LENGTH {
+            return ReturnCode::ESIZE;
+        }
+
+        if length % self.page_size != 0 {
+            let page_number = self.slots.update_start + length / self.page_size;
+            self.write_page(page_number, State::FlushPage)
+        } else {
+            self.verify();
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn abort(&self) -> ReturnCode {
+        if self.state.get() != State::Receiving || self.write_result.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.state.set(State::Idle);
+        ReturnCode::SUCCESS
+    }
+
+    fn confirm(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => self.read_control(Operation::Confirm),
+            _ => ReturnCode::EBUSY,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: hil::digest::Digest<'a, [u8; 32]> + hil::digest::Sha256>
+    hil::flash::Client<F> for FirmwareUpdate<'a, F, D>
+{
+    fn read_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(buffer);
+        let state = self.state.get();
+
+        if error != hil::flash::Error::CommandComplete {
+            match state {
+                State::ReadControl(operation, _) => self.fail(operation, ReturnCode::FAIL),
+                _ => {
+                    self.digest.clear_data();
+                    self.fail(Operation::Finish, ReturnCode::FAIL);
+                }
+            }
+            return;
+        }
+
+        match state {
+            State::ReadControl(operation, index) => self.control_read(operation, index),
+            State::ReadImage => {
+                // Keep the digest that follows the binary.
+                let base = self.hash_page.get() * self.page_size;
+                let length = self.length.get();
+                let digest_start = length - DIGEST_LENGTH;
+                let mut expected = self.expected_digest.get();
+                self.page.map(|page| {
+                    let end = cmp::min(base + self.page_size, length);
+                    for offset in cmp::max(base, digest_start)..end {
+                        expected[offset - digest_start] = page.as_mut()[offset - base];
+                    }
+                });
+                self.expected_digest.set(expected);
+
+                let rcode = self.hash_next();
+                if rcode != ReturnCode::SUCCESS {
+                    self.digest.clear_data();
+                    self.fail(Operation::Finish, rcode);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(buffer);
+        let result = if error == hil::flash::Error::CommandComplete {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+
+        match self.state.get() {
+            State::WritePage => {
+                self.clear_page();
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::Receiving);
+                } else {
+                    self.state.set(State::Idle);
+                }
+                self.client.map(|client| client.write_done(result));
+            }
+            State::FlushPage => {
+                if result == ReturnCode::SUCCESS {
+                    self.verify();
+                } else {
+                    self.fail(Operation::Finish, result);
+                }
+            }
+            State::WriteControl(operation) => {
+                if result == ReturnCode::SUCCESS {
+                    self.control_written(operation);
+                } else {
+                    self.fail(operation, result);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<F: hil::flash::Flash, D: hil::digest::Digest<'a, [u8; 32]> + hil::digest::Sha256>
+    hil::digest::Client<'a, [u8; 32]> for FirmwareUpdate<'a, F, D>
+{
+    fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        self.hash_buffer.replace(data);
+        let rcode = match (result, self.state.get()) {
+            (Ok(()), State::Hashing(len)) => {
+                self.hash_offset.set(self.hash_offset.get() + len);
+                self.hash_next()
+            }
+            (Err(rcode), _) => rcode,
+            _ => ReturnCode::FAIL,
+        };
+        if rcode != ReturnCode::SUCCESS {
+            self.digest.clear_data();
+            self.fail(Operation::Finish, rcode);
+        }
+    }
+
+    fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut [u8; 32]) {
+        let matches = result.is_ok() && digest[..] == self.expected_digest.get()[..];
+        self.digest_buffer.replace(digest);
+        self.digest.clear_data();
+
+        if !matches {
+            self.fail(Operation::Finish, ReturnCode::FAIL);
+            return;
+        }
+        let rcode = self.read_control(Operation::Finish);
+        if rcode != ReturnCode::SUCCESS {
+            self.fail(Operation::Finish, rcode);
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: hil::digest::Digest<'a, [u8; 32]> + hil::digest::Sha256>
+    DynamicDeferredCallClient for FirmwareUpdate<'a, F, D>
+{
+    fn call(&self, _handle: DeferredCallHandle) {
+        if let Some(result) = self.write_result.take() {
+            self.client.map(|client| client.write_done(result));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erased_control_pages_are_confirmed() {
+        let control = BootControl::decode(&[0xff; CONTROL_LENGTH]);
+        assert_eq!(control, None);
+        assert_eq!(BootControl::newest(None, None), (BootControl::default(), 0));
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut control = BootControl::default();
+        control.stage(0x1234);
+        control.renew();
+        let mut buf = [0xff; CONTROL_LENGTH];
+        control.encode(&mut buf);
+        assert_eq!(BootControl::decode(&buf), Some(control));
+
+        // Programming stopped before the last word.
+        buf[24..].copy_from_slice(&[0xff; 4]);
+        assert_eq!(BootControl::decode(&buf), None);
+    }
+
+    #[test]
+    fn newest_record_wins() {
+        let mut old = BootControl::default();
+        old.sequence = 0xffff_ffff;
+        let mut new = old;
+        new.renew();
+        assert_eq!(BootControl::newest(Some(old), Some(new)), (new, 0));
+        assert_eq!(BootControl::newest(Some(new), Some(old)), (new, 1));
+        assert_eq!(BootControl::newest(None, Some(old)), (old, 0));
+    }
+
+    #[test]
+    fn interrupted_swap_resumes_from_other_page() {
+        let mut pages = [[0xff; CONTROL_LENGTH]; CONTROL_PAGES];
+        let read = |pages: &[[u8; CONTROL_LENGTH]; CONTROL_PAGES]| {
+            BootControl::newest(
+                BootControl::decode(&pages[0]),
+                BootControl::decode(&pages[1]),
+            )
+        };
+        let write = |pages: &mut [[u8; CONTROL_LENGTH]; CONTROL_PAGES],
+                     control: &mut BootControl,
+                     next: &mut usize| {
+            control.renew();
+            control.encode(&mut pages[*next]);
+            *next = 1 - *next;
+        };
+
+        let (mut control, mut next) = read(&pages);
+        control.stage(0x3000);
+        write(&mut pages, &mut control, &mut next);
+
+        // The boot stage records the start of the swap and its first two
+        // steps.
+        let (mut control, mut next) = read(&pages);
+        assert_eq!(control.boot(), BootAction::Swap);
+        write(&mut pages, &mut control, &mut next);
+        for _ in 0..2 {
+            control.advance_swap();
+            write(&mut pages, &mut control, &mut next);
+        }
+
+        // A reset after the third step, between erasing the older control
+        // page and programming the next record into it.
+        pages[next] = [0xff; CONTROL_LENGTH];
+
+        let (mut resumed, _) = read(&pages);
+        assert_eq!(resumed.state, SlotState::Swapping);
+        assert_eq!(resumed.swap_step(1), Some((0, SwapStep::RestoreActive)));
+        assert_eq!(resumed.boot(), BootAction::Swap);
+    }
+
+    #[test]
+    fn pending_image_is_swapped_and_tested() {
+        let mut control = BootControl {
+            active_length: 0x2100,
+            ..BootControl::default()
+        };
+        assert_eq!(control.stage(0x1000), ReturnCode::SUCCESS);
+        assert_eq!(control.boot(), BootAction::Swap);
+
+        let pages = control.swap_pages(0x1000, 0x30);
+        assert_eq!(pages, 3);
+        let mut steps = 0;
+        while let Some((page, step)) = control.swap_step(pages) {
+            assert_eq!(page, steps / 3);
+            assert_eq!(step as usize, steps % 3);
+            control.advance_swap();
+            steps += 1;
+        }
+        assert_eq!(steps, 9);
+
+        control.swap_done();
+        assert_eq!(control.state, SlotState::Testing);
+        assert_eq!(control.active_length, 0x1000);
+        assert_eq!(control.image_length, 0x2100);
+
+        // A new image cannot replace the previous kernel before the running
+        // one is confirmed.
+        assert_eq!(control.stage(0x1000), ReturnCode::EBUSY);
+        assert!(control.confirm());
+        assert_eq!(control.boot(), BootAction::Run);
+    }
+
+    #[test]
+    fn unconfirmed_image_is_rolled_back() {
+        let mut control = BootControl::default();
+        control.stage(0x1000);
+        control.boot();
+        control.swap_done();
+
+        for _ in 1..MAX_BOOT_ATTEMPTS {
+            assert_eq!(control.boot(), BootAction::Run);
+        }
+        assert_eq!(control.boot(), BootAction::Swap);
+        assert_eq!(control.state, SlotState::RollingBack);
+        assert_eq!(control.swap_step(1), Some((0, SwapStep::SaveActive)));
+
+        control.swap_done();
+        assert_eq!(control.state, SlotState::RolledBack);
+        assert!(!control.confirm());
+        assert_eq!(control.boot(), BootAction::Run);
+    }
+}
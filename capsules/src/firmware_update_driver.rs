@@ -0,0 +1,254 @@
+//! Provides userspace with access to kernel updates.
+//!
+//! An application writes a new kernel image, received over any channel such
+//! as a UART, into the update slot, and can confirm the running kernel after
+//! checking that the system works. See `firmware_update` for the image format
+//! and the boot sequence.
+//!
+//! One application at a time writes an image, from `begin` until the image is
+//! finished or aborted, or an operation fails.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let update_driver = static_init!(
+//!     capsules::firmware_update_driver::FirmwareUpdateDriver<'static>,
+//!     capsules::firmware_update_driver::FirmwareUpdateDriver::new(
+//!         update,
+//!         &mut capsules::firmware_update_driver::BUFFER,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! capsules::firmware_update::ImageUpdate::set_client(update, update_driver);
+//! ```
+
+use crate::firmware_update::{ImageUpdate, UpdateClient};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FirmwareUpdate as usize;
+
+pub static mut BUFFER: [u8; 512] = [0; 512];
+
+/// Operations reported in the first argument of the callback.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operation {
+    Begin = 0,
+    Write = 1,
+    Finish = 2,
+    Confirm = 3,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct FirmwareUpdateDriver<'a> {
+    update: &'a dyn ImageUpdate<'a>,
+    apps: Grant<App>,
+    /// Application writing an image
+    owner: OptionalCell<AppId>,
+    /// Application waiting for the current operation
+    current_app: OptionalCell<AppId>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Whether the owner gives up the update when the operation completes
+    release: Cell<bool>,
+}
+
+impl FirmwareUpdateDriver<'a> {
+    pub fn new(
+        update: &'a dyn ImageUpdate<'a>,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> FirmwareUpdateDriver<'a> {
+        FirmwareUpdateDriver {
+            update,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            current_app: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            release: Cell::new(false),
+        }
+    }
+
+    fn is_owner(&self, appid: AppId) -> bool {
+        self.owner.map_or(false, |owner| *owner == appid)
+    }
+
+    /// Starts an operation for `appid` if no other one is in progress.
+    fn start<F: FnOnce() -> ReturnCode>(&self, appid: AppId, release: bool, f: F) -> ReturnCode {
+        if self.current_app.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let rcode = f();
+        if rcode == ReturnCode::SUCCESS {
+            self.current_app.set(appid);
+            self.release.set(release);
+        }
+        rcode
+    }
+
+    fn write(&self, appid: AppId, len: usize) -> ReturnCode {
+        if !self.is_owner(appid) {
+            return ReturnCode::EOFF;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let app_buffer = match app.buffer {
+                    Some(ref slice) => slice,
+                    None => return ReturnCode::ERESERVE,
+                };
+                self.buffer.map_or(ReturnCode::EBUSY, |buffer| {
+                    if len > cmp::min(app_buffer.len(), buffer.len()) {
+                        return ReturnCode::ESIZE;
+                    }
+                    buffer[..len].copy_from_slice(&app_buffer.as_ref()[..len]);
+                    self.start(appid, false, || self.update.write(&buffer[..len]))
+                })
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn operation_done(&self, operation: Operation, result: ReturnCode) {
+        if self.release.get() || result != ReturnCode::SUCCESS {
+            // A confirmation does not end the image being written.
+            if operation != Operation::Confirm {
+                self.owner.clear();
+            }
+        }
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(operation as usize, usize::from(result), 0);
+                });
+            });
+        });
+    }
+}
+
+impl UpdateClient for FirmwareUpdateDriver<'a> {
+    fn begin_done(&self, result: ReturnCode) {
+        self.operation_done(Operation::Begin, result);
+    }
+
+    fn write_done(&self, result: ReturnCode) {
+        self.operation_done(Operation::Write, result);
+    }
+
+    fn finish_done(&self, result: ReturnCode) {
+        self.operation_done(Operation::Finish, result);
+    }
+
+    fn confirm_done(&self, result: ReturnCode) {
+        self.operation_done(Operation::Confirm, result);
+    }
+}
+
+impl Driver for FirmwareUpdateDriver<'a> {
+    /// Setup the buffer of image data.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer with the next chunk of the image.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup the callback.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Operation completed. The arguments are the operation (0 begin,
+    ///   1 write, 2 finish, 3 confirm) and its return code.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Kernel update control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start writing a new image.
+    /// - `2`: Append the first `arg1` bytes of the buffer to the image. The
+    ///   chunk must not cross a flash page boundary, which holds if all
+    ///   chunks have the same size and it divides the page size.
+    /// - `3`: Finish the image. It is checked and swapped in at the next reset.
+    /// - `4`: Abort the image.
+    /// - `5`: Confirm the running kernel.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if self.owner.map_or(false, |owner| *owner != appid) {
+                    return ReturnCode::EBUSY;
+                }
+                let rcode = self.start(appid, false, || self.update.begin());
+                if rcode == ReturnCode::SUCCESS {
+                    self.owner.set(appid);
+                }
+                rcode
+            }
+
+            2 => self.write(appid, arg1),
+
+            3 => {
+                if !self.is_owner(appid) {
+                    return ReturnCode::EOFF;
+                }
+                self.start(appid, true, || self.update.finish())
+            }
+
+            4 => {
+                if !self.is_owner(appid) {
+                    return ReturnCode::EOFF;
+                }
+                let rcode = self.update.abort();
+                if rcode == ReturnCode::SUCCESS {
+                    self.owner.clear();
+                }
+                rcode
+            }
+
+            5 => self.start(appid, false, || self.update.confirm()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
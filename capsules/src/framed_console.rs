@@ -0,0 +1,815 @@
+//! Console that keeps the output of the kernel and of each process separable.
+//!
+//! `Console` and the kernel debug writer share one UART without any framing,
+//! so the host cannot tell which process printed what, and binary output of one
+//! process corrupts the text of all others. `FramedConsole` provides the same
+//! system call interface as `Console`, but wraps every write in a frame that
+//! names its source: the name of the process, or an empty name for the kernel.
+//! It also acts as the UART of the kernel debug writer, so `debug!` output is
+//! framed as well. Panic messages are written to the UART directly by the
+//! board and therefore remain unframed.
+//!
+//! The host sends input in frames with the same format, where the name selects
+//! the process that receives the payload. A receive started by a process
+//! completes with the payload of the next frame addressed to it, truncated to
+//! the requested length. Frames for processes that are not waiting for input
+//! are dropped.
+//!
+//! `tools/framed-console` decodes the output on the host and sends input to a
+//! process.
+//!
+//! Frame Format
+//! ------------
+//!
+//! ```text
+//! +------+------+----------+-------------+------+---------+------------+
+//! | 0xF5 | 0x7C | name len | payload len | name | payload | Fletcher16 |
+//! +------+------+----------+-------------+------+---------+------------+
+//! ```
+//!
+//! Both lengths are single bytes, and names are at most `MAX_NAME_LEN` bytes
+//! long. Longer process names are truncated. The Fletcher-16 checksum covers
+//! the name length through the end of the payload and is sent little endian.
+//! There is no byte stuffing: a receiver that lost synchronization searches
+//! for the next start sequence and relies on the checksum to reject false
+//! starts.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let console = static_init!(
+//!     FramedConsole<'static, Capability>,
+//!     FramedConsole::new(
+//!         console_uart,
+//!         &mut framed_console::WRITE_BUF,
+//!         &mut framed_console::READ_BUF,
+//!         board_kernel.create_grant(&grant_cap),
+//!         board_kernel,
+//!         Capability,
+//!     )
+//! );
+//! hil::uart::Transmit::set_transmit_client(console_uart, console);
+//! hil::uart::Receive::set_receive_client(console_uart, console);
+//! console.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, Kernel, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Console as usize;
+
+/// Bytes that start every frame.
+pub const START: [u8; 2] = [0xF5, 0x7C];
+
+/// Longest name carried in a frame.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Longest payload carried in a frame.
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+/// Number of bytes in a frame besides the name and the payload.
+pub const FRAME_OVERHEAD: usize = 6;
+
+pub static mut WRITE_BUF: [u8; 128] = [0; 128];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+
+/// Running Fletcher-16 checksum.
+#[derive(Clone, Copy)]
+struct Fletcher16 {
+    sum1: u16,
+    sum2: u16,
+}
+
+impl Fletcher16 {
+    const fn new() -> Fletcher16 {
+        Fletcher16 { sum1: 0, sum2: 0 }
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.sum1 = (self.sum1 + byte as u16) % 255;
+        self.sum2 = (self.sum2 + self.sum1) % 255;
+    }
+
+    fn value(&self) -> u16 {
+        (self.sum2 << 8) | self.sum1
+    }
+}
+
+/// Returns the name that identifies a process in frames.
+pub fn frame_name(process_name: &str) -> &[u8] {
+    let name = process_name.as_bytes();
+    &name[..cmp::min(name.len(), MAX_NAME_LEN)]
+}
+
+/// Writes a frame carrying `payload` from or to `name` into `buffer` and
+/// returns the length of the frame.
+///
+/// `name` must be at most `MAX_NAME_LEN` and `payload` at most
+/// `MAX_PAYLOAD_LEN` bytes long, and `buffer` must have room for both plus
+/// `FRAME_OVERHEAD` bytes.
+pub fn encode_frame(buffer: &mut [u8], name: &[u8], payload: &[u8]) -> usize {
+    let name_end = 4 + name.len();
+    let payload_end = name_end + payload.len();
+    buffer[..2].copy_from_slice(&START);
+    buffer[2] = name.len() as u8;
+    buffer[3] = payload.len() as u8;
+    buffer[4..name_end].copy_from_slice(name);
+    buffer[name_end..payload_end].copy_from_slice(payload);
+
+    let mut checksum = Fletcher16::new();
+    for byte in buffer[2..payload_end].iter() {
+        checksum.update(*byte);
+    }
+    buffer[payload_end] = checksum.value() as u8;
+    buffer[payload_end + 1] = (checksum.value() >> 8) as u8;
+    payload_end + 2
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DecodeState {
+    Start0,
+    Start1,
+    NameLen,
+    PayloadLen,
+    Name,
+    Payload,
+    Checksum0,
+    Checksum1,
+}
+
+/// Reassembles frames from a stream of bytes.
+pub struct FrameDecoder {
+    state: DecodeState,
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    payload: [u8; MAX_PAYLOAD_LEN],
+    payload_len: usize,
+    index: usize,
+    checksum: Fletcher16,
+    received_checksum: u16,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> FrameDecoder {
+        FrameDecoder {
+            state: DecodeState::Start0,
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+            payload: [0; MAX_PAYLOAD_LEN],
+            payload_len: 0,
+            index: 0,
+            checksum: Fletcher16::new(),
+            received_checksum: 0,
+        }
+    }
+
+    /// Name of the last complete frame.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    /// Payload of the last complete frame.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.payload_len]
+    }
+
+    /// Returns the state that follows the name of the frame.
+    fn after_name(&self) -> DecodeState {
+        if self.payload_len > 0 {
+            DecodeState::Payload
+        } else {
+            DecodeState::Checksum0
+        }
+    }
+
+    /// Feeds the next received byte to the decoder. Returns true if the byte
+    /// completed a frame with a valid checksum, which can then be read through
+    /// `name` and `payload` until the next byte is received.
+    pub fn receive(&mut self, byte: u8) -> bool {
+        match self.state {
+            DecodeState::Start0 => {
+                if byte == START[0] {
+                    self.state = DecodeState::Start1;
+                }
+            }
+            DecodeState::Start1 => {
+                self.state = if byte == START[1] {
+                    self.checksum = Fletcher16::new();
+                    DecodeState::NameLen
+                } else if byte == START[0] {
+                    DecodeState::Start1
+                } else {
+                    DecodeState::Start0
+                };
+            }
+            DecodeState::NameLen => {
+                if byte as usize > MAX_NAME_LEN {
+                    self.state = DecodeState::Start0;
+                } else {
+                    self.checksum.update(byte);
+                    self.name_len = byte as usize;
+                    self.state = DecodeState::PayloadLen;
+                }
+            }
+            DecodeState::PayloadLen => {
+                self.checksum.update(byte);
+                self.payload_len = byte as usize;
+                self.index = 0;
+                self.state = if self.name_len > 0 {
+                    DecodeState::Name
+                } else {
+                    self.after_name()
+                };
+            }
+            DecodeState::Name => {
+                self.checksum.update(byte);
+                self.name[self.index] = byte;
+                self.index += 1;
+                if self.index == self.name_len {
+                    self.index = 0;
+                    self.state = self.after_name();
+                }
+            }
+            DecodeState::Payload => {
+                self.checksum.update(byte);
+                self.payload[self.index] = byte;
+                self.index += 1;
+                if self.index == self.payload_len {
+                    self.state = DecodeState::Checksum0;
+                }
+            }
+            DecodeState::Checksum0 => {
+                self.received_checksum = byte as u16;
+                self.state = DecodeState::Checksum1;
+            }
+            DecodeState::Checksum1 => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.state = DecodeState::Start0;
+                return self.received_checksum == self.checksum.value();
+            }
+        }
+        false
+    }
+}
+
+/// The source of the frame being transmitted.
+#[derive(Clone, Copy)]
+enum Source {
+    Kernel,
+    App(AppId),
+}
+
+#[derive(Default)]
+pub struct App {
+    write_callback: Option<Callback>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    write_len: usize,
+    write_offset: usize,
+    writing: bool,
+    pending_write: bool,
+
+    read_callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    read_len: usize,
+    pending_read: bool,
+}
+
+pub struct FramedConsole<'a, C: ProcessManagementCapability> {
+    uart: &'a dyn uart::UartData<'a>,
+    apps: Grant<App>,
+    kernel: &'static Kernel,
+    capability: C,
+    tx_in_progress: OptionalCell<Source>,
+    /// Process whose frame was transmitted last, so the others go next
+    last_app: OptionalCell<AppId>,
+    /// Payload length of the frame being transmitted
+    tx_payload_len: Cell<usize>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    kernel_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    kernel_buffer: TakeCell<'static, [u8]>,
+    kernel_len: Cell<usize>,
+    kernel_offset: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    decoder: MapCell<FrameDecoder>,
+}
+
+impl<C: ProcessManagementCapability> FramedConsole<'a, C> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        grant: Grant<App>,
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> FramedConsole<'a, C> {
+        FramedConsole {
+            uart: uart,
+            apps: grant,
+            kernel: kernel,
+            capability: capability,
+            tx_in_progress: OptionalCell::empty(),
+            last_app: OptionalCell::empty(),
+            tx_payload_len: Cell::new(0),
+            tx_buffer: TakeCell::new(tx_buffer),
+            kernel_client: OptionalCell::empty(),
+            kernel_buffer: TakeCell::empty(),
+            kernel_len: Cell::new(0),
+            kernel_offset: Cell::new(0),
+            rx_buffer: TakeCell::new(rx_buffer),
+            decoder: MapCell::new(FrameDecoder::new()),
+        }
+    }
+
+    /// Start receiving frames from the host.
+    pub fn start(&self) -> ReturnCode {
+        self.rx_buffer
+            .take()
+            .map_or(ReturnCode::EALREADY, |buffer| {
+                let (rval, buffer) = self.uart.receive_buffer(buffer, 1);
+                self.rx_buffer.put(buffer);
+                rval
+            })
+    }
+
+    fn process_name(&self, appid: AppId) -> &'static str {
+        let name = Cell::new("");
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.appid() == appid {
+                    name.set(process.get_process_name());
+                }
+            });
+        name.get()
+    }
+
+    /// Start transmitting the next frame if the UART is idle. Kernel output
+    /// goes before output of processes, which take turns frame by frame.
+    fn transmit_next(&self) {
+        if self.tx_in_progress.is_some() {
+            return;
+        }
+        if self.kernel_buffer.is_some() {
+            let rval = self.transmit_kernel();
+            if rval == ReturnCode::SUCCESS {
+                return;
+            }
+            // The kernel output ends with what was sent so far, so its
+            // client does not wait for a frame that never goes out.
+            self.finish_kernel(rval);
+        }
+        // Start after the process served last, and wrap around to it
+        let last_app = self.last_app.map(|appid| *appid);
+        let mut after_last = last_app.is_none();
+        for cntr in self.apps.iter() {
+            let (appid, started_tx) = cntr.enter(|app, _| {
+                let appid = app.appid();
+                if after_last && app.pending_write {
+                    (appid, self.transmit_app(appid, app))
+                } else {
+                    (appid, false)
+                }
+            });
+            if started_tx {
+                return;
+            }
+            after_last = after_last || Some(appid) == last_app;
+        }
+        if last_app.is_none() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let (appid, started_tx) = cntr.enter(|app, _| {
+                let appid = app.appid();
+                if app.pending_write {
+                    (appid, self.transmit_app(appid, app))
+                } else {
+                    (appid, false)
+                }
+            });
+            if started_tx || Some(appid) == last_app {
+                return;
+            }
+        }
+    }
+
+    fn transmit_kernel(&self) -> ReturnCode {
+        self.tx_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let offset = self.kernel_offset.get();
+            let len = cmp::min(self.kernel_len.get() - offset, max_payload(buffer.len(), 0));
+            let frame_len = self.kernel_buffer.map_or(0, |kernel_buffer| {
+                encode_frame(buffer, &[], &kernel_buffer[offset..offset + len])
+            });
+            let rval = self.transmit_frame(Source::Kernel, buffer, frame_len, len);
+            if rval == ReturnCode::SUCCESS {
+                self.kernel_offset.set(offset + len);
+            }
+            rval
+        })
+    }
+
+    /// Returns the kernel buffer to the kernel client, with the number of
+    /// bytes sent.
+    fn finish_kernel(&self, rval: ReturnCode) {
+        let written = self.kernel_offset.get();
+        self.kernel_buffer.take().map(|kernel_buffer| {
+            self.kernel_client
+                .map(move |client| client.transmitted_buffer(kernel_buffer, written, rval));
+        });
+    }
+
+    /// Returns true if a frame of the process is being transmitted.
+    fn transmit_app(&self, appid: AppId, app: &mut App) -> bool {
+        let name = frame_name(self.process_name(appid));
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let len = cmp::min(
+                app.write_len - app.write_offset,
+                max_payload(buffer.len(), name.len()),
+            );
+            let frame_len = app.write_buffer.as_ref().map_or(0, |slice| {
+                encode_frame(
+                    buffer,
+                    name,
+                    &slice.as_ref()[app.write_offset..app.write_offset + len],
+                )
+            });
+            let rval = self.transmit_frame(Source::App(appid), buffer, frame_len, len);
+            if rval == ReturnCode::SUCCESS {
+                app.write_offset += len;
+                app.pending_write = false;
+                self.last_app.set(appid);
+                true
+            } else {
+                // The write ends with what was sent so far, so the process
+                // is not left waiting for a frame that never goes out.
+                self.finish_write(app, rval);
+                false
+            }
+        })
+    }
+
+    fn transmit_frame(
+        &self,
+        source: Source,
+        buffer: &'static mut [u8],
+        len: usize,
+        payload_len: usize,
+    ) -> ReturnCode {
+        self.tx_in_progress.set(source);
+        self.tx_payload_len.set(payload_len);
+        let (rval, buffer) = self.uart.transmit_buffer(buffer, len);
+        if rval != ReturnCode::SUCCESS {
+            self.tx_in_progress.clear();
+            self.tx_buffer.put(buffer);
+        }
+        rval
+    }
+
+    fn finish_write(&self, app: &mut App, rval: ReturnCode) {
+        // The buffer is released once it has been written, as in `Console`.
+        app.write_buffer = None;
+        app.writing = false;
+        app.pending_write = false;
+        let written = app.write_offset;
+        app.write_len = 0;
+        app.write_callback.map(|mut cb| {
+            cb.schedule(written, usize::from(rval), 0);
+        });
+    }
+
+    fn write_new(&self, appid: AppId, app: &mut App, len: usize) -> ReturnCode {
+        if app.writing {
+            return ReturnCode::EBUSY;
+        }
+        match app.write_buffer {
+            Some(ref slice) => {
+                app.write_len = cmp::min(len, slice.len());
+                app.write_offset = 0;
+                app.writing = true;
+                app.pending_write = true;
+                if self.tx_in_progress.is_none() && self.kernel_buffer.is_none() {
+                    self.transmit_app(appid, app);
+                }
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EBUSY,
+        }
+    }
+
+    fn read_new(&self, app: &mut App, len: usize) -> ReturnCode {
+        if app.pending_read {
+            return ReturnCode::EBUSY;
+        }
+        match app.read_buffer {
+            Some(ref slice) => {
+                app.read_len = cmp::min(len, slice.len());
+                app.pending_read = true;
+                ReturnCode::SUCCESS
+            }
+            None => {
+                // Must supply read buffer before performing receive operation
+                ReturnCode::EINVAL
+            }
+        }
+    }
+
+    /// Hand the payload of the frame just received to the process it is
+    /// addressed to.
+    fn deliver_frame(&self) {
+        self.decoder.map(|decoder| {
+            for cntr in self.apps.iter() {
+                let delivered = cntr.enter(|app, _| {
+                    if !app.pending_read
+                        || frame_name(self.process_name(app.appid())) != decoder.name()
+                    {
+                        return false;
+                    }
+                    app.pending_read = false;
+                    let payload = decoder.payload();
+                    let len = cmp::min(app.read_len, payload.len());
+                    // The buffer stays allowed for the next read
+                    if let Some(ref mut slice) = app.read_buffer {
+                        slice.as_mut()[..len].copy_from_slice(&payload[..len]);
+                    }
+                    app.read_callback.map(|mut cb| {
+                        cb.schedule(From::from(ReturnCode::SUCCESS), len, 0);
+                    });
+                    true
+                });
+                if delivered {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Largest payload that fits into a frame in a buffer of `buffer_len` bytes.
+fn max_payload(buffer_len: usize, name_len: usize) -> usize {
+    cmp::min(buffer_len - FRAME_OVERHEAD - name_len, MAX_PAYLOAD_LEN)
+}
+
+impl<C: ProcessManagementCapability> Driver for FramedConsole<'a, C> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Writeable buffer for write buffer, which cannot be replaced
+    ///   while a write is in progress
+    /// - `2`: Writeable buffer for read buffer, which cannot be replaced
+    ///   while a read is pending
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.writing {
+                        return ReturnCode::EBUSY;
+                    }
+                    app.write_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    // The pending read was clamped to the current buffer
+                    if app.pending_read {
+                        return ReturnCode::EBUSY;
+                    }
+                    app.read_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `1`: Write buffer completed callback, with the number of bytes
+    ///   written and, if the UART failed, the error
+    /// - `2`: Read completed callback
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            1 /* putstr/write_done */ => {
+                self.apps.enter(app_id, |app, _| {
+                    app.write_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            2 /* getnstr done */ => {
+                self.apps.enter(app_id, |app, _| {
+                    app.read_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    /// Initiate serial transfers
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Transmits a buffer passed via `allow`, up to the length
+    ///        passed in `arg1`
+    /// - `2`: Receives the payload of the next frame addressed to the process
+    ///        into a buffer passed via `allow`, up to the length passed in
+    ///        `arg1`
+    /// - `3`: Cancel an in progress receive. The read callback reports
+    ///        `ECANCEL`.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* putstr */ => {
+                let len = arg1;
+                self.apps.enter(appid, |app, _| {
+                    self.write_new(appid, app, len)
+                }).unwrap_or_else(|err| err.into())
+            },
+            2 /* getnstr */ => {
+                let len = arg1;
+                self.apps.enter(appid, |app, _| {
+                    self.read_new(app, len)
+                }).unwrap_or_else(|err| err.into())
+            },
+            3 /* abort rx */ => {
+                self.apps.enter(appid, |app, _| {
+                    if app.pending_read {
+                        app.pending_read = false;
+                        app.read_callback.map(|mut cb| {
+                            cb.schedule(From::from(ReturnCode::ECANCEL), 0, 0);
+                        });
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            }
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+/// Transmit interface for kernel output, such as the debug writer.
+impl<C: ProcessManagementCapability> uart::Transmit<'a> for FramedConsole<'a, C> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.kernel_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.kernel_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        self.kernel_len.set(cmp::min(tx_len, tx_buffer.len()));
+        self.kernel_offset.set(0);
+        self.kernel_buffer.replace(tx_buffer);
+        if self.tx_in_progress.is_none() {
+            // Report a refused first frame here rather than through a
+            // callback from within this call.
+            let rval = self.transmit_kernel();
+            if rval != ReturnCode::SUCCESS {
+                return (rval, self.kernel_buffer.take());
+            }
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+}
+
+impl<C: ProcessManagementCapability> uart::TransmitClient for FramedConsole<'a, C> {
+    fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, rcode: ReturnCode) {
+        self.tx_buffer.replace(buffer);
+        // A failed frame does not count as written
+        let unsent = if rcode == ReturnCode::SUCCESS {
+            0
+        } else {
+            self.tx_payload_len.get()
+        };
+        match self.tx_in_progress.take() {
+            Some(Source::Kernel) => {
+                self.kernel_offset.set(self.kernel_offset.get() - unsent);
+                if rcode != ReturnCode::SUCCESS || self.kernel_offset.get() >= self.kernel_len.get()
+                {
+                    self.finish_kernel(rcode);
+                }
+            }
+            Some(Source::App(appid)) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.write_offset -= unsent;
+                    if rcode == ReturnCode::SUCCESS && app.write_offset < app.write_len {
+                        app.pending_write = true;
+                    } else {
+                        self.finish_write(app, rcode);
+                    }
+                });
+            }
+            None => {}
+        }
+        self.transmit_next();
+    }
+}
+
+impl<C: ProcessManagementCapability> uart::ReceiveClient for FramedConsole<'a, C> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: ReturnCode,
+        error: uart::Error,
+    ) {
+        if error == uart::Error::None && rx_len == 1 {
+            let complete = self
+                .decoder
+                .map_or(false, |decoder| decoder.receive(buffer[0]));
+            if complete {
+                self.deliver_frame();
+            }
+        }
+        let (_rval, buffer) = self.uart.receive_buffer(buffer, 1);
+        self.rx_buffer.put(buffer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(decoder: &mut FrameDecoder, bytes: &[u8]) -> usize {
+        bytes.iter().filter(|byte| decoder.receive(**byte)).count()
+    }
+
+    #[test]
+    fn checksum() {
+        let mut checksum = Fletcher16::new();
+        for byte in b"abcde".iter() {
+            checksum.update(*byte);
+        }
+        assert_eq!(checksum.value(), 0xC8F0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = [0; 64];
+        let len = encode_frame(&mut buffer, b"blink", b"hello\n");
+        assert_eq!(len, 5 + 6 + FRAME_OVERHEAD);
+
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decode(&mut decoder, &buffer[..len]), 1);
+        assert_eq!(decoder.name(), b"blink");
+        assert_eq!(decoder.payload(), b"hello\n");
+
+        let len = encode_frame(&mut buffer, &[], &[]);
+        assert_eq!(decode(&mut decoder, &buffer[..len]), 1);
+        assert_eq!(decoder.name(), b"");
+        assert_eq!(decoder.payload(), b"");
+    }
+
+    #[test]
+    fn rejects_corrupted_frame() {
+        let mut buffer = [0; 64];
+        let len = encode_frame(&mut buffer, b"app", &[START[0], START[1], 0]);
+        buffer[6] ^= 0x01;
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decode(&mut decoder, &buffer[..len]), 0);
+    }
+
+    #[test]
+    fn resynchronizes() {
+        let mut buffer = [0; 64];
+        let len = encode_frame(&mut buffer, b"app", b"data");
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decode(&mut decoder, &[0x00, START[0], START[0], 0x42]), 0);
+        assert_eq!(decode(&mut decoder, &buffer[..len]), 1);
+        assert_eq!(decoder.payload(), b"data");
+    }
+}
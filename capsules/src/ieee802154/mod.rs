@@ -3,6 +3,8 @@
 pub mod device;
 pub mod framer;
 pub mod mac;
+pub mod multi_phy;
+pub mod tsch;
 pub mod virtual_mac;
 pub mod xmac;
 
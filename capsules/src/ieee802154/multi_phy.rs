@@ -0,0 +1,183 @@
+//! Presents a multi-PHY radio as an 802.15.4 radio.
+//!
+//! Radios implementing `hil::radio::MultiPhyRadio`, such as sub-GHz
+//! transceivers, are configured by modulation and frequency, and know
+//! nothing of 802.15.4 addresses or channel numbers. `MultiPhyAdapter`
+//! implements `hil::radio::Radio` on top of them for the 802.15.4 stack:
+//! channels are mapped to frequencies with a `ChannelPlan`, and addresses
+//! and PAN ID are kept here for the MAC layer, which filters received frames
+//! itself. Frames are not acknowledged by the radio, so `send_done` always
+//! reports them as not acknowledged.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let radio = static_init!(
+//!     capsules::ieee802154::multi_phy::MultiPhyAdapter<'static, cc26x2::rfc::Rfc>,
+//!     capsules::ieee802154::multi_phy::MultiPhyAdapter::new(
+//!         &cc26x2::rfc::RFC,
+//!         PhyConfig::IEEE802154G_FSK_50K,
+//!         ChannelPlan::IEEE802154G_863,
+//!     )
+//! );
+//! // `radio` is then used like any other `kernel::hil::radio::Radio`
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::radio::{self, ChannelPlan, PhyConfig};
+use kernel::ReturnCode;
+
+pub struct MultiPhyAdapter<'a, R: radio::MultiPhyRadio> {
+    radio: &'a R,
+    phy: PhyConfig,
+    plan: ChannelPlan,
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    channel: Cell<u8>,
+}
+
+impl<R: radio::MultiPhyRadio> MultiPhyAdapter<'a, R> {
+    pub fn new(radio: &'a R, phy: PhyConfig, plan: ChannelPlan) -> MultiPhyAdapter<'a, R> {
+        MultiPhyAdapter {
+            radio: radio,
+            phy: phy,
+            plan: plan,
+            addr: Cell::new(0),
+            addr_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            channel: Cell::new(plan.first_channel),
+        }
+    }
+}
+
+impl<R: radio::MultiPhyRadio> radio::Radio for MultiPhyAdapter<'a, R> {}
+
+impl<R: radio::MultiPhyRadio> radio::RadioConfig for MultiPhyAdapter<'a, R> {
+    /// The buffers are not used, as the radio is not accessed over SPI.
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        let result = self.radio.set_phy(self.phy);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.plan
+            .frequency(self.channel.get())
+            .map_or(ReturnCode::EINVAL, |frequency| {
+                self.radio.set_frequency(frequency)
+            })
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.radio.stop()
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.radio.start()
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.radio.stop()
+    }
+
+    fn is_on(&self) -> bool {
+        self.radio.is_on()
+    }
+
+    fn busy(&self) -> bool {
+        self.radio.busy()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.radio.set_power_client(client);
+    }
+
+    fn config_commit(&self) {
+        if let Some(frequency) = self.plan.frequency(self.channel.get()) {
+            self.radio.set_frequency(frequency);
+        }
+        self.radio.config_commit();
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.radio.set_config_client(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.radio.get_tx_power()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.radio.set_tx_power(power)
+    }
+
+    /// Accepts the channels of the `ChannelPlan` of this adapter.
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        match self.plan.frequency(chan) {
+            Some(_) => {
+                self.channel.set(chan);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+impl<R: radio::MultiPhyRadio> radio::RadioData for MultiPhyAdapter<'a, R> {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.radio.set_transmit_client(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'static dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.radio.set_receive_client(client, receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.radio.transmit(spi_buf, frame_len)
+    }
+}
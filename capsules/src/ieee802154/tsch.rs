@@ -0,0 +1,1108 @@
+//! Time-slotted channel hopping (TSCH) MAC layer, IEEE 802.15.4-2015 Section
+//! 6.2.6.
+//!
+//! Nodes running TSCH share a notion of time divided into timeslots, numbered
+//! by the absolute slot number (ASN) since the network started. Timeslots are
+//! grouped into a repeating slotframe, and a schedule of links says in which
+//! timeslots of the slotframe a node transmits or listens, and on which
+//! channel offset. The channel used in a timeslot changes with every
+//! slotframe, following the hopping sequence, which spreads traffic over the
+//! 16 channels of the 2.4 GHz band and works around interference on any one
+//! of them.
+//!
+//! The PAN coordinator starts the network with `start_coordinator`. Other
+//! nodes call `start` and listen for an enhanced beacon (EB), which carries
+//! the ASN and the schedule. The node the first EB is received from becomes
+//! the time source of the joining node. All synchronized nodes send EBs in
+//! shared transmit links, so that the network can grow over several hops.
+//!
+//! Nodes stay synchronized with their time source in two ways:
+//!
+//! - EBs from the time source realign the timeslots to their reception.
+//! - Frames sent to the time source are acknowledged with Enhanced ACKs that
+//!   carry a time correction IE, by how much the frame arrived early or late.
+//!   This node shifts its timeslots by that correction.
+//!
+//! In turn, this layer acknowledges version 2015 frames addressed to it with
+//! an Enhanced ACK carrying its own measurement. Unsecured data frames that
+//! carry both addresses are sent as version 2015 frames, whose header is laid
+//! out as in version 2006 frames, so that they are acknowledged this way.
+//! Other frames are acknowledged by the radio, if at all.
+//!
+//! Limitations:
+//!
+//! - Arrival times are taken when the radio reports a frame, and frames are
+//!   sent when the radio gains access to the channel. Their accuracy is
+//!   limited by the radio driver, so larger corrections are ignored.
+//! - The radio stays on between active timeslots.
+//! - Only the default timeslot template and hopping sequence are supported,
+//!   and the schedule is fixed when the layer is created.
+//! - Frames secured with TSCH mode security (ASN in the nonce) are not
+//!   supported by the `Framer`.
+//!
+//! Usage
+//! -----
+//! This capsule implements the `capsules::ieee802154::mac::Mac` interface
+//! like `AwakeMac`, and sits between the radio and a `Framer`:
+//!
+//! ```rust
+//! static mut MAC_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+//!
+//! let tsch = static_init!(
+//!     capsules::ieee802154::tsch::TschMac<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::tsch::TschMac::new(
+//!         radio,
+//!         alarm,
+//!         capsules::ieee802154::tsch::MINIMAL_SLOTFRAME_LENGTH,
+//!         &capsules::ieee802154::tsch::MINIMAL_SCHEDULE,
+//!     )
+//! );
+//! alarm.set_client(tsch);
+//! radio.set_transmit_client(tsch);
+//! radio.set_receive_client(tsch, &mut RADIO_RX_BUF);
+//! tsch.initialize(&mut MAC_BUF);
+//!
+//! let framer = static_init!(
+//!     capsules::ieee802154::framer::Framer<'static, TschDevice, AesCcm>,
+//!     capsules::ieee802154::framer::Framer::new(tsch, aes_ccm)
+//! );
+//! tsch.set_transmit_client(framer);
+//! tsch.set_receive_client(framer);
+//! tsch.set_config_client(framer);
+//!
+//! // On the PAN coordinator
+//! tsch.start_coordinator();
+//! // On every other node
+//! tsch.start();
+//! ```
+
+use crate::ieee802154::mac::Mac;
+use crate::net::ieee802154::{FrameType, FrameVersion, Header, HeaderIE, MacAddress, PayloadIE};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+
+/// Default 2.4 GHz hopping sequence
+pub const HOPPING_SEQUENCE: [u8; 16] = [
+    16, 17, 23, 18, 26, 15, 25, 22, 19, 11, 12, 13, 24, 14, 20, 21,
+];
+
+// Default timeslot template (Table 8-86), in microseconds
+const TS_TX_OFFSET_US: u32 = 2120;
+const TS_TIMESLOT_LENGTH_US: u32 = 10_000;
+
+// Time after a transmission during which its Enhanced ACK is expected. The
+// acknowledgement is sent by software once the frame has been received, so
+// this is longer than TsTxAckDelay + TsAckWait.
+const ACK_WAIT_US: u32 = 3000;
+
+// Time corrections larger than this are treated as measurement errors
+const MAX_CORRECTION_US: i32 = 1000;
+
+// Airtime of the synchronization header and PHR, and of one byte
+const SHR_PHR_US: u32 = 6 * 32;
+const BYTE_US: u32 = 32;
+
+// Retransmissions of a unicast frame that is not acknowledged
+const MAX_FRAME_RETRIES: u8 = 3;
+
+// Backoff exponents for retransmissions in shared links
+const MIN_BE: u8 = 1;
+const MAX_BE: u8 = 5;
+
+// Average number of timeslots between two EBs of a node
+const EB_PERIOD_SLOTS: u32 = 400;
+
+// Payload IE group of MLME IEs, and the nested IEs of EBs (Section 7.4.4)
+const IE_GROUP_MLME: u8 = 0x1;
+const IE_TSCH_SYNCHRONIZATION: u8 = 0x1a;
+const IE_TSCH_SLOTFRAME_LINK: u8 = 0x1b;
+const IE_TSCH_TIMESLOT: u8 = 0x1c;
+const IE_CHANNEL_HOPPING: u8 = 0x9;
+
+// Header IE carrying time corrections in Enhanced ACKs (Section 7.4.2.7)
+const IE_TIME_CORRECTION: u8 = 0x1e;
+const TIME_CORRECTION_NACK: u16 = 0x8000;
+
+/// Length of the MLME IE of an EB advertising `links` links
+pub const fn beacon_ies_len(links: usize) -> usize {
+    (2 + 6) + (2 + 1) + (2 + 1) + (2 + 5 + 5 * links)
+}
+
+// Largest MLME IE sent in an EB
+const MAX_BEACON_IES_LEN: usize = beacon_ies_len(MAX_BEACON_LINKS);
+const MAX_BEACON_LINKS: usize = 8;
+
+/// Transmit link
+pub const LINK_TX: u8 = 0x01;
+/// Receive link
+pub const LINK_RX: u8 = 0x02;
+/// Transmit link shared with other nodes, which back off after collisions
+pub const LINK_SHARED: u8 = 0x04;
+/// Link to the time source
+pub const LINK_TIMEKEEPING: u8 = 0x08;
+
+/// A link of the slotframe: the timeslot and channel offset it uses, and
+/// whether this node transmits or listens in it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TschLink {
+    pub timeslot: u16,
+    pub channel_offset: u16,
+    /// `LINK_*` flags
+    pub options: u8,
+    /// Destination of the frames sent in a transmit link, or `None` for any
+    pub neighbor: Option<MacAddress>,
+}
+
+/// Slotframe length of the minimal 6TiSCH configuration (RFC 8180)
+pub const MINIMAL_SLOTFRAME_LENGTH: u16 = 7;
+
+/// Schedule of the minimal 6TiSCH configuration: a single shared link in
+/// the first timeslot, which every node transmits and listens in.
+pub const MINIMAL_SCHEDULE: [TschLink; 1] = [TschLink {
+    timeslot: 0,
+    channel_offset: 0,
+    options: LINK_TX | LINK_RX | LINK_SHARED | LINK_TIMEKEEPING,
+    neighbor: None,
+}];
+
+/// Returns the channel of a link with `channel_offset` in timeslot `asn`.
+pub fn channel(asn: u64, channel_offset: u16) -> u8 {
+    let index = (asn + channel_offset as u64) % HOPPING_SEQUENCE.len() as u64;
+    HOPPING_SEQUENCE[index as usize]
+}
+
+// Synchronization information carried by an EB
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct BeaconInfo {
+    asn: u64,
+    join_metric: u8,
+    slotframe_len: Option<u16>,
+}
+
+fn write_nested_ie(buf: &mut [u8], off: usize, sub_id: u8, len: usize, long: bool) -> usize {
+    let ctl = if long {
+        0x8000 | ((sub_id as u16) << 11) | len as u16
+    } else {
+        ((sub_id as u16) << 8) | len as u16
+    };
+    buf[off..off + 2].copy_from_slice(&ctl.to_le_bytes());
+    off + 2
+}
+
+// Writes the content of the MLME IE of an EB, and returns its length, or
+// `None` if `buf` is too short.
+fn write_beacon_ies(
+    buf: &mut [u8],
+    asn: u64,
+    join_metric: u8,
+    slotframe_len: u16,
+    links: &[TschLink],
+) -> Option<usize> {
+    if buf.len() < beacon_ies_len(links.len()) {
+        return None;
+    }
+
+    let mut off = write_nested_ie(buf, 0, IE_TSCH_SYNCHRONIZATION, 6, false);
+    buf[off..off + 5].copy_from_slice(&asn.to_le_bytes()[..5]);
+    buf[off + 5] = join_metric;
+    off += 6;
+
+    // Default timeslot template and hopping sequence
+    off = write_nested_ie(buf, off, IE_TSCH_TIMESLOT, 1, false);
+    buf[off] = 0;
+    off += 1;
+    off = write_nested_ie(buf, off, IE_CHANNEL_HOPPING, 1, true);
+    buf[off] = 0;
+    off += 1;
+
+    off = write_nested_ie(buf, off, IE_TSCH_SLOTFRAME_LINK, 5 + 5 * links.len(), false);
+    buf[off] = 1;
+    buf[off + 1] = 0;
+    buf[off + 2..off + 4].copy_from_slice(&slotframe_len.to_le_bytes());
+    buf[off + 4] = links.len() as u8;
+    off += 5;
+    for link in links {
+        buf[off..off + 2].copy_from_slice(&link.timeslot.to_le_bytes());
+        buf[off + 2..off + 4].copy_from_slice(&link.channel_offset.to_le_bytes());
+        buf[off + 4] = link.options;
+        off += 5;
+    }
+    Some(off)
+}
+
+// Parses the content of the MLME IE of an EB. Returns `None` if the EB has no
+// synchronization IE, or uses a timeslot template or hopping sequence other
+// than the default one.
+fn parse_beacon_ies(ies: &[u8]) -> Option<BeaconInfo> {
+    let mut info = BeaconInfo {
+        asn: 0,
+        join_metric: 0,
+        slotframe_len: None,
+    };
+    let mut synchronized = false;
+    let mut off = 0;
+    while off + 2 <= ies.len() {
+        let ctl = u16::from_le_bytes([ies[off], ies[off + 1]]);
+        let (sub_id, len) = if ctl & 0x8000 != 0 {
+            (((ctl >> 11) & 0xf) as u8 | 0x80, (ctl & 0x7ff) as usize)
+        } else {
+            ((ctl >> 8) as u8 & 0x7f, (ctl & 0xff) as usize)
+        };
+        off += 2;
+        let content = ies.get(off..off + len)?;
+        off += len;
+
+        match sub_id {
+            IE_TSCH_SYNCHRONIZATION if len == 6 => {
+                let mut asn = [0; 8];
+                asn[..5].copy_from_slice(&content[..5]);
+                info.asn = u64::from_le_bytes(asn);
+                info.join_metric = content[5];
+                synchronized = true;
+            }
+            IE_TSCH_TIMESLOT if content.get(0) != Some(&0) => return None,
+            sub_id if sub_id == IE_CHANNEL_HOPPING | 0x80 && content.get(0) != Some(&0) => {
+                return None
+            }
+            IE_TSCH_SLOTFRAME_LINK if len >= 5 && content[0] > 0 => {
+                info.slotframe_len = Some(u16::from_le_bytes([content[2], content[3]]));
+            }
+            _ => {}
+        }
+    }
+    if synchronized {
+        Some(info)
+    } else {
+        None
+    }
+}
+
+// Content of a time correction IE. The correction is a signed 12-bit number
+// of microseconds.
+fn encode_time_correction(correction_us: i32) -> [u8; 2] {
+    let correction = correction_us.max(-2048).min(2047) as u16 & 0x0fff;
+    correction.to_le_bytes()
+}
+
+fn decode_time_correction(content: &[u8]) -> Option<i32> {
+    if content.len() != 2 {
+        return None;
+    }
+    let info = u16::from_le_bytes([content[0], content[1]]);
+    if info & TIME_CORRECTION_NACK != 0 {
+        return None;
+    }
+    // Sign-extend the 12-bit correction
+    Some((((info & 0x0fff) << 4) as i16 >> 4) as i32)
+}
+
+fn airtime_us(frame_len: usize) -> u32 {
+    SHR_PHR_US + (frame_len + radio::MFR_SIZE) as u32 * BYTE_US
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum TschState {
+    Off,
+    /// Listening for an EB to join a network
+    Scanning,
+    /// Waiting for the next timeslot with a link
+    Idle,
+    /// Waiting for the transmit offset of a timeslot
+    TxOffset,
+    /// Transmitting in a timeslot
+    Tx,
+    /// Waiting for the Enhanced ACK of a transmitted frame
+    TxAckWait,
+}
+
+// What is sent in the current timeslot
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum SlotFrame {
+    Data,
+    Beacon,
+}
+
+pub struct TschMac<'a, R: radio::Radio, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    state: Cell<TschState>,
+
+    slotframe_len: u16,
+    links: &'a [TschLink],
+
+    // The current (or next) timeslot. Timeslot `asn` starts at
+    // `epoch + (asn - epoch_asn)` timeslot lengths.
+    asn: Cell<u64>,
+    epoch: Cell<u32>,
+    epoch_asn: Cell<u64>,
+
+    coordinator: Cell<bool>,
+    time_source: Cell<Option<MacAddress>>,
+    join_metric: Cell<u8>,
+
+    // Frame handed down by the MAC device
+    tx_payload: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_dst: Cell<Option<MacAddress>>,
+    tx_seq: Cell<u8>,
+    // Set if the frame is acknowledged with an Enhanced ACK by this layer,
+    // rather than by the radio
+    tx_enhanced_ack: Cell<bool>,
+    tx_retries: Cell<u8>,
+    // Shared transmit links to skip before the next attempt, and the backoff
+    // exponent they were drawn with
+    backoff: Cell<u32>,
+    backoff_exponent: Cell<u8>,
+    slot_frame: Cell<SlotFrame>,
+
+    // Holds EBs and Enhanced ACKs
+    mac_buf: TakeCell<'static, [u8]>,
+    ack_pending: Cell<bool>,
+    eb_seq: Cell<u8>,
+    next_eb_asn: Cell<u64>,
+    random: Cell<u32>,
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> TschMac<'a, R, A> {
+    /// `links` is the schedule of this node in a slotframe of
+    /// `slotframe_len` timeslots. The first `MAX_BEACON_LINKS` links are
+    /// advertised in EBs.
+    pub fn new(
+        radio: &'a R,
+        alarm: &'a A,
+        slotframe_len: u16,
+        links: &'a [TschLink],
+    ) -> TschMac<'a, R, A> {
+        TschMac {
+            radio: radio,
+            alarm: alarm,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            state: Cell::new(TschState::Off),
+            slotframe_len: slotframe_len,
+            links: links,
+            asn: Cell::new(0),
+            epoch: Cell::new(0),
+            epoch_asn: Cell::new(0),
+            coordinator: Cell::new(false),
+            time_source: Cell::new(None),
+            join_metric: Cell::new(0),
+            tx_payload: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_dst: Cell::new(None),
+            tx_seq: Cell::new(0),
+            tx_enhanced_ack: Cell::new(false),
+            tx_retries: Cell::new(0),
+            backoff: Cell::new(0),
+            backoff_exponent: Cell::new(MIN_BE),
+            slot_frame: Cell::new(SlotFrame::Data),
+            mac_buf: TakeCell::empty(),
+            ack_pending: Cell::new(false),
+            eb_seq: Cell::new(0),
+            next_eb_asn: Cell::new(0),
+            random: Cell::new(0xDEADBEEF),
+        }
+    }
+
+    /// Starts a new network as its PAN coordinator, at ASN 0.
+    pub fn start_coordinator(&self) -> ReturnCode {
+        if self.state.get() != TschState::Off {
+            return ReturnCode::EALREADY;
+        }
+        self.seed_random();
+        self.coordinator.set(true);
+        self.time_source.set(None);
+        self.join_metric.set(0);
+        self.epoch.set(self.alarm.now());
+        self.epoch_asn.set(0);
+        self.asn.set(0);
+        self.next_eb_asn.set(0);
+        self.state.set(TschState::Idle);
+        self.alarm.set_alarm(self.epoch.get());
+        ReturnCode::SUCCESS
+    }
+
+    /// Listens for an EB and joins the network it advertises.
+    pub fn start(&self) -> ReturnCode {
+        if self.state.get() != TschState::Off {
+            return ReturnCode::EALREADY;
+        }
+        self.seed_random();
+        self.coordinator.set(false);
+        self.state.set(TschState::Scanning);
+        self.set_channel(HOPPING_SEQUENCE[0]);
+        ReturnCode::SUCCESS
+    }
+
+    /// Whether this node is synchronized to a network
+    pub fn is_synchronized(&self) -> bool {
+        match self.state.get() {
+            TschState::Off | TschState::Scanning => false,
+            _ => true,
+        }
+    }
+
+    /// The absolute slot number of the current timeslot
+    pub fn asn(&self) -> u64 {
+        self.asn.get()
+    }
+
+    /// The neighbor this node keeps its time from, if it is not the PAN
+    /// coordinator
+    pub fn time_source(&self) -> Option<MacAddress> {
+        self.time_source.get()
+    }
+
+    fn seed_random(&self) {
+        let addr = self.radio.get_address_long();
+        let seed = u32::from_le_bytes([addr[4], addr[5], addr[6], addr[7]])
+            ^ self.radio.get_address() as u32;
+        self.random.set(self.random.get() ^ seed);
+    }
+
+    // Xorshift, as in the radio drivers
+    fn random(&self) -> u32 {
+        let mut next = self.random.get();
+        next ^= next << 13;
+        next ^= next >> 17;
+        next ^= next << 5;
+        self.random.set(next);
+        next
+    }
+
+    fn ticks(&self, us: u64) -> u32 {
+        (us * <A::Frequency>::frequency() as u64 / 1_000_000) as u32
+    }
+
+    fn signed_ticks(&self, us: i32) -> u32 {
+        if us < 0 {
+            self.ticks(-us as u64).wrapping_neg()
+        } else {
+            self.ticks(us as u64)
+        }
+    }
+
+    fn slot_start(&self, asn: u64) -> u32 {
+        let slots = asn.wrapping_sub(self.epoch_asn.get());
+        self.epoch
+            .get()
+            .wrapping_add(self.ticks(slots * TS_TIMESLOT_LENGTH_US as u64))
+    }
+
+    // The timeslot `time` falls into
+    fn asn_at(&self, time: u32) -> u64 {
+        let elapsed = time.wrapping_sub(self.epoch.get()) as u64;
+        let slot_ticks = self.ticks(TS_TIMESLOT_LENGTH_US as u64) as u64;
+        self.epoch_asn.get() + elapsed / slot_ticks
+    }
+
+    fn link_at(&self, asn: u64) -> Option<TschLink> {
+        let timeslot = (asn % self.slotframe_len as u64) as u16;
+        self.links
+            .iter()
+            .find(|link| link.timeslot == timeslot)
+            .cloned()
+    }
+
+    fn set_channel(&self, channel: u8) {
+        if self.radio.get_channel() != channel {
+            self.radio.set_channel(channel);
+            self.radio.config_commit();
+        }
+    }
+
+    // Waits for the next timeslot with a link. Timeslots that have already
+    // started are skipped.
+    fn schedule_next(&self) {
+        let now = self.alarm.now();
+        let mut asn = self.asn.get() + 1;
+        let late = now.wrapping_sub(self.slot_start(asn)) as i32;
+        if late >= 0 {
+            asn = self.asn_at(now) + 1;
+        }
+        for _ in 0..self.slotframe_len {
+            if self.link_at(asn).is_some() {
+                break;
+            }
+            asn += 1;
+        }
+        self.asn.set(asn);
+        self.state.set(TschState::Idle);
+        self.alarm.set_alarm(self.slot_start(asn));
+    }
+
+    fn frame_fits(&self, link: &TschLink) -> bool {
+        let neighbor_matches = match link.neighbor {
+            None => true,
+            neighbor => neighbor == self.tx_dst.get(),
+        };
+        self.tx_payload.is_some() && neighbor_matches
+    }
+
+    fn slot_started(&self) {
+        let asn = self.asn.get();
+        let link = match self.link_at(asn) {
+            Some(link) => link,
+            None => {
+                self.schedule_next();
+                return;
+            }
+        };
+        self.set_channel(channel(asn, link.channel_offset));
+
+        // An Enhanced ACK still being sent keeps the radio busy
+        if link.options & LINK_TX != 0 && !self.ack_pending.get() {
+            let shared = link.options & LINK_SHARED != 0;
+            if self.frame_fits(&link) && !(shared && self.backoff.get() > 0) {
+                self.slot_frame.set(SlotFrame::Data);
+                self.state.set(TschState::TxOffset);
+                self.alarm.set_alarm(
+                    self.slot_start(asn)
+                        .wrapping_add(self.ticks(TS_TX_OFFSET_US as u64)),
+                );
+                return;
+            }
+            if shared && self.frame_fits(&link) {
+                self.backoff.set(self.backoff.get() - 1);
+            }
+            if shared && asn >= self.next_eb_asn.get() && self.mac_buf.is_some() {
+                self.slot_frame.set(SlotFrame::Beacon);
+                self.state.set(TschState::TxOffset);
+                self.alarm.set_alarm(
+                    self.slot_start(asn)
+                        .wrapping_add(self.ticks(TS_TX_OFFSET_US as u64)),
+                );
+                return;
+            }
+        }
+        // Receive links listen until the next active timeslot
+        self.schedule_next();
+    }
+
+    fn transmit_in_slot(&self) {
+        self.state.set(TschState::Tx);
+        match self.slot_frame.get() {
+            SlotFrame::Data => match self.tx_payload.take() {
+                Some(buf) => {
+                    let (result, buf) = self.radio.transmit(buf, self.tx_len.get());
+                    if let Some(buf) = buf {
+                        self.attempt_failed(buf, result);
+                    }
+                }
+                None => self.schedule_next(),
+            },
+            SlotFrame::Beacon => {
+                let result = self.mac_buf.take().map_or(ReturnCode::EBUSY, |buf| {
+                    match self.prepare_beacon(buf) {
+                        Some(len) => {
+                            let (result, buf) = self.radio.transmit(buf, len);
+                            buf.map(|buf| self.mac_buf.replace(buf));
+                            result
+                        }
+                        None => {
+                            self.mac_buf.replace(buf);
+                            ReturnCode::ESIZE
+                        }
+                    }
+                });
+                self.beacon_sent();
+                if result != ReturnCode::SUCCESS {
+                    self.schedule_next();
+                }
+            }
+        }
+    }
+
+    fn beacon_sent(&self) {
+        let jitter = self.random() % EB_PERIOD_SLOTS;
+        self.next_eb_asn
+            .set(self.asn.get() + (EB_PERIOD_SLOTS / 2 + jitter) as u64);
+    }
+
+    fn prepare_beacon(&self, buf: &mut [u8]) -> Option<usize> {
+        let links = &self.links[..self.links.len().min(MAX_BEACON_LINKS)];
+        let mut ies = [0; MAX_BEACON_IES_LEN];
+        let ies_len = write_beacon_ies(
+            &mut ies,
+            self.asn.get(),
+            self.join_metric.get(),
+            self.slotframe_len,
+            links,
+        )?;
+
+        let mut payload_ies: [PayloadIE; 5] = Default::default();
+        payload_ies[0] = PayloadIE::Undissected {
+            group_id: IE_GROUP_MLME,
+            content: &ies[..ies_len],
+        };
+        let pan = self.radio.get_pan();
+        let header = Header {
+            frame_type: FrameType::Beacon,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2015,
+            seq: Some(self.eb_seq.get()),
+            dst_pan: Some(pan),
+            dst_addr: Some(MacAddress::Short(0xffff)),
+            src_pan: Some(pan),
+            src_addr: Some(MacAddress::Long(self.radio.get_address_long())),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: payload_ies,
+            payload_ies_len: 1,
+        };
+        self.eb_seq.set(self.eb_seq.get().wrapping_add(1));
+        header
+            .encode(&mut buf[radio::PSDU_OFFSET..], false)
+            .done()
+            .map(|(len, _)| len)
+    }
+
+    // Sends an Enhanced ACK for frame `seq` from `dst`, which arrived
+    // `correction_us` earlier than expected.
+    fn send_enhanced_ack(&self, seq: u8, dst: Option<MacAddress>, correction_us: i32) {
+        let buf = match self.mac_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let correction = encode_time_correction(correction_us);
+        let mut header_ies: [HeaderIE; 5] = Default::default();
+        header_ies[0] = HeaderIE::Undissected {
+            element_id: IE_TIME_CORRECTION,
+            content: &correction,
+        };
+        let header = Header {
+            frame_type: FrameType::Acknowledgement,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2015,
+            seq: Some(seq),
+            dst_pan: None,
+            dst_addr: dst,
+            src_pan: None,
+            src_addr: None,
+            security: None,
+            header_ies: header_ies,
+            header_ies_len: 1,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], false).done() {
+            Some((len, _)) => {
+                let (result, buf) = self.radio.transmit(buf, len);
+                self.ack_pending.set(result == ReturnCode::SUCCESS);
+                buf.map(|buf| self.mac_buf.replace(buf));
+            }
+            None => {
+                self.mac_buf.replace(buf);
+            }
+        }
+    }
+
+    fn attempt_failed(&self, buf: &'static mut [u8], result: ReturnCode) {
+        let retries = self.tx_retries.get() + 1;
+        if retries > MAX_FRAME_RETRIES {
+            let result = if result == ReturnCode::SUCCESS {
+                ReturnCode::ENOACK
+            } else {
+                result
+            };
+            self.tx_done(buf, false, result);
+            return;
+        }
+        self.tx_retries.set(retries);
+        self.tx_payload.replace(buf);
+
+        // Collisions in shared links are resolved with a random backoff
+        let shared = self
+            .link_at(self.asn.get())
+            .map_or(false, |link| link.options & LINK_SHARED != 0);
+        if shared {
+            let exponent = self.backoff_exponent.get();
+            self.backoff.set(self.random() & ((1 << exponent) - 1));
+            self.backoff_exponent.set((exponent + 1).min(MAX_BE));
+        }
+        self.schedule_next();
+    }
+
+    fn tx_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.tx_retries.set(0);
+        self.backoff.set(0);
+        self.backoff_exponent.set(MIN_BE);
+        self.schedule_next();
+        self.tx_client.map(move |client| {
+            client.send_done(buf, acked, result);
+        });
+    }
+
+    fn beacon_received(&self, src: Option<MacAddress>, info: BeaconInfo, rx_time: u32, len: usize) {
+        if self.coordinator.get()
+            || info
+                .slotframe_len
+                .map_or(false, |len| len != self.slotframe_len)
+        {
+            return;
+        }
+        let scanning = self.state.get() == TschState::Scanning;
+        if !scanning && (src.is_none() || src != self.time_source.get()) {
+            return;
+        }
+
+        // The EB was sent at the transmit offset of timeslot `info.asn`
+        let offset = self.ticks((TS_TX_OFFSET_US + airtime_us(len)) as u64);
+        self.epoch.set(rx_time.wrapping_sub(offset));
+        self.epoch_asn.set(info.asn);
+
+        if scanning {
+            self.time_source.set(src);
+            self.join_metric.set(info.join_metric.saturating_add(1));
+            self.asn.set(info.asn);
+            self.next_eb_asn
+                .set(info.asn + (self.random() % EB_PERIOD_SLOTS) as u64);
+            self.schedule_next();
+        }
+    }
+
+    fn is_for_us(&self, dst: Option<MacAddress>) -> bool {
+        match dst {
+            Some(MacAddress::Short(addr)) => addr == self.radio.get_address() || addr == 0xffff,
+            Some(MacAddress::Long(addr)) => addr == self.radio.get_address_long(),
+            None => false,
+        }
+    }
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> Mac for TschMac<'a, R, A> {
+    fn initialize(&self, mac_buf: &'static mut [u8]) -> ReturnCode {
+        self.mac_buf.replace(mac_buf);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.radio.is_on()
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.radio.set_config_client(client)
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.radio.set_pan(id)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.radio.get_pan()
+    }
+
+    fn config_commit(&self) {
+        self.radio.config_commit()
+    }
+
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(buffer);
+    }
+
+    /// Queues the frame for the next transmit link it may use. Frames can
+    /// only be sent once this node is synchronized.
+    fn transmit(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.is_synchronized() {
+            return (ReturnCode::EOFF, Some(full_mac_frame));
+        } else if self.tx_payload.is_some() {
+            return (ReturnCode::EBUSY, Some(full_mac_frame));
+        } else if radio::PSDU_OFFSET + frame_len + radio::MFR_SIZE >= full_mac_frame.len() {
+            return (ReturnCode::ESIZE, Some(full_mac_frame));
+        }
+
+        let psdu = &full_mac_frame[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len];
+        let (dst, seq, ack_requested, upgrade) = match Header::decode(psdu, false).done() {
+            Some((_, (header, _))) => (
+                header.dst_addr,
+                header.seq.unwrap_or(0),
+                header.ack_requested,
+                header.version == FrameVersion::V2006
+                    && header.security.is_none()
+                    && header.dst_addr.is_some()
+                    && header.src_addr.is_some(),
+            ),
+            None => return (ReturnCode::FAIL, Some(full_mac_frame)),
+        };
+        if upgrade {
+            // Frame version bits 12-13 of the little-endian frame control
+            let fcf = radio::PSDU_OFFSET + 1;
+            full_mac_frame[fcf] = (full_mac_frame[fcf] & !0x30) | 0x20;
+        }
+
+        self.tx_dst.set(dst);
+        self.tx_seq.set(seq);
+        self.tx_enhanced_ack.set(ack_requested && upgrade);
+        self.tx_len.set(frame_len);
+        self.tx_retries.set(0);
+        self.tx_payload.replace(full_mac_frame);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> time::AlarmClient for TschMac<'a, R, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            TschState::Idle => self.slot_started(),
+            TschState::TxOffset => self.transmit_in_slot(),
+            TschState::TxAckWait => {
+                let result = self
+                    .tx_payload
+                    .take()
+                    .map(|buf| self.attempt_failed(buf, ReturnCode::SUCCESS));
+                if result.is_none() {
+                    self.schedule_next();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> radio::TxClient for TschMac<'a, R, A> {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        if self.ack_pending.get() {
+            self.ack_pending.set(false);
+            self.mac_buf.replace(buf);
+            return;
+        }
+        match self.slot_frame.get() {
+            SlotFrame::Beacon => {
+                self.mac_buf.replace(buf);
+                self.schedule_next();
+            }
+            SlotFrame::Data => {
+                let broadcast = match self.tx_dst.get() {
+                    Some(MacAddress::Short(0xffff)) | None => true,
+                    _ => false,
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.attempt_failed(buf, result);
+                } else if self.tx_enhanced_ack.get() {
+                    self.tx_payload.replace(buf);
+                    self.state.set(TschState::TxAckWait);
+                    self.alarm.set_alarm(
+                        self.alarm
+                            .now()
+                            .wrapping_add(self.ticks(ACK_WAIT_US as u64)),
+                    );
+                } else if !acked && !broadcast && self.tx_ack_requested(buf) {
+                    self.attempt_failed(buf, result);
+                } else {
+                    self.tx_done(buf, acked, result);
+                }
+            }
+        }
+    }
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> TschMac<'a, R, A> {
+    fn tx_ack_requested(&self, buf: &[u8]) -> bool {
+        // Acknowledgement request bit of the frame control
+        buf[radio::PSDU_OFFSET] & 0x20 != 0
+    }
+}
+
+impl<R: radio::Radio, A: Alarm<'a>> radio::RxClient for TschMac<'a, R, A> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        crc_valid: bool,
+        result: ReturnCode,
+    ) {
+        let rx_time = self.alarm.now();
+        if !crc_valid || result != ReturnCode::SUCCESS || radio::PSDU_OFFSET + frame_len > buf.len()
+        {
+            self.radio.set_receive_buffer(buf);
+            return;
+        }
+
+        let mut deliver = false;
+        if let Some((_, (header, _))) = Header::decode(
+            &buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len],
+            false,
+        )
+        .done()
+        {
+            match header.frame_type {
+                FrameType::Beacon if header.version == FrameVersion::V2015 => {
+                    let info = header.payload_ies[..header.payload_ies_len]
+                        .iter()
+                        .filter_map(|ie| match *ie {
+                            PayloadIE::Undissected { group_id, content }
+                                if group_id == IE_GROUP_MLME =>
+                            {
+                                parse_beacon_ies(content)
+                            }
+                            _ => None,
+                        })
+                        .next();
+                    if let Some(info) = info {
+                        self.beacon_received(header.src_addr, info, rx_time, frame_len);
+                    }
+                }
+                FrameType::Acknowledgement => {
+                    let for_us = header
+                        .dst_addr
+                        .map_or(true, |dst| self.is_for_us(Some(dst)));
+                    if self.state.get() == TschState::TxAckWait
+                        && header.seq == Some(self.tx_seq.get())
+                        && for_us
+                    {
+                        let correction = header.header_ies[..header.header_ies_len]
+                            .iter()
+                            .filter_map(|ie| match *ie {
+                                HeaderIE::Undissected {
+                                    element_id,
+                                    content,
+                                } if element_id == IE_TIME_CORRECTION => {
+                                    decode_time_correction(content)
+                                }
+                                _ => None,
+                            })
+                            .next();
+                        // Only the time source corrects this node's time
+                        let from_time_source = self.tx_dst.get() == self.time_source.get();
+                        if let Some(correction) = correction {
+                            if from_time_source && correction.abs() <= MAX_CORRECTION_US {
+                                self.epoch.set(
+                                    self.epoch.get().wrapping_add(self.signed_ticks(correction)),
+                                );
+                            }
+                        }
+                        self.tx_payload
+                            .take()
+                            .map(|tx_buf| self.tx_done(tx_buf, true, ReturnCode::SUCCESS));
+                    }
+                }
+                _ => {
+                    if self.is_for_us(header.dst_addr) {
+                        let unicast = header.dst_addr != Some(MacAddress::Short(0xffff));
+                        if unicast
+                            && header.ack_requested
+                            && header.version == FrameVersion::V2015
+                            && self.is_synchronized()
+                        {
+                            // The frame was sent at the transmit offset of
+                            // the timeslot it arrived in
+                            let asn = self.asn_at(rx_time);
+                            let expected = self.slot_start(asn).wrapping_add(
+                                self.ticks((TS_TX_OFFSET_US + airtime_us(frame_len)) as u64),
+                            );
+                            let early = expected.wrapping_sub(rx_time) as i32;
+                            let correction =
+                                early as i64 * 1_000_000 / <A::Frequency>::frequency() as i64;
+                            self.send_enhanced_ack(
+                                header.seq.unwrap_or(0),
+                                header.src_addr,
+                                correction as i32,
+                            );
+                        }
+                        deliver = true;
+                    }
+                }
+            }
+        }
+
+        if deliver && self.rx_client.is_some() {
+            self.rx_client.map(move |client| {
+                client.receive(buf, frame_len, crc_valid, result);
+            });
+        } else {
+            self.radio.set_receive_buffer(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hopping() {
+        assert_eq!(channel(0, 0), 16);
+        assert_eq!(channel(1, 0), 17);
+        assert_eq!(channel(15, 1), 16);
+        // Over 16 slotframes of 7 timeslots, a link uses every channel once
+        let mut used = [false; 27];
+        for slotframe in 0..16 {
+            used[channel(slotframe * 7, 3) as usize] = true;
+        }
+        assert!(used[11..].iter().all(|&used| used));
+    }
+
+    #[test]
+    fn beacon_ies() {
+        let mut buf = [0; MAX_BEACON_IES_LEN];
+        let len = write_beacon_ies(&mut buf, 0x12_3456_789a, 2, 7, &MINIMAL_SCHEDULE).unwrap();
+        assert_eq!(len, beacon_ies_len(1));
+        assert_eq!(
+            parse_beacon_ies(&buf[..len]),
+            Some(BeaconInfo {
+                asn: 0x12_3456_789a,
+                join_metric: 2,
+                slotframe_len: Some(7),
+            })
+        );
+
+        // A hopping sequence other than the default one is rejected
+        buf[8 + 3 + 2] = 1;
+        assert_eq!(parse_beacon_ies(&buf[..len]), None);
+        assert_eq!(
+            write_beacon_ies(&mut buf[..10], 0, 0, 7, &MINIMAL_SCHEDULE),
+            None
+        );
+    }
+
+    #[test]
+    fn time_correction() {
+        for &correction in &[0, 1, -1, 500, -2048, 2047] {
+            assert_eq!(
+                decode_time_correction(&encode_time_correction(correction)),
+                Some(correction)
+            );
+        }
+        assert_eq!(
+            decode_time_correction(&encode_time_correction(5000)),
+            Some(2047)
+        );
+        assert_eq!(decode_time_correction(&[0x00, 0x80]), None);
+    }
+}
@@ -0,0 +1,1146 @@
+//! Wear-leveled key-value store on top of `hil::flash`.
+//!
+//! The store keeps small values under short keys, in separate namespaces.
+//! The syscall driver in `kv_store_driver` gives every app its own
+//! namespace, keyed by the app's persistent identity, so an app finds its
+//! values again after a reboot or a reinstall.
+//!
+//! Storage layout
+//! --------------
+//!
+//! The store uses a range of flash pages as a log. Each page holds a header
+//! and a sequence of records:
+//!
+//! ```text
+//! page:   magic (4) | sequence number (4) | records length (2) | 0xffff | CRC-32 (4) | records
+//! record: namespace (4) | key length (1) | flags (1) | value length (2) | key | value
+//! ```
+//!
+//! All fields are little endian. The CRC covers the first 12 bytes of the
+//! header and the records. The sequence number increases with every page
+//! written, and a later record for a key replaces earlier ones. Deleting a
+//! key writes a record with the deleted flag set.
+//!
+//! Flash chips erase a page before writing it, so a page is never updated
+//! in place. To add a record, the store writes the last page it wrote (the
+//! head page) plus the new record to an erased page, and only then erases
+//! the previous copy. A write interrupted by a power loss leaves a page
+//! with a bad CRC, which the store ignores and erases when it next mounts,
+//! and the previous copy is still there. Updates are therefore atomic.
+//!
+//! Erased pages are taken in turn around the range, so pages wear evenly.
+//! When fewer than three erased pages are left, the store compacts the
+//! oldest page: it copies the records that are still current to the head
+//! page and erases the oldest page. Records for deleted keys are dropped
+//! then, as nothing older can remain in the store.
+//!
+//! The store keeps an index of the current records in RAM, which it builds
+//! when mounted by replaying all pages in order. The index has one entry per
+//! key, so its size bounds the number of keys in the store. Each namespace
+//! may use up to a quota of bytes, counting the headers, keys and values of
+//! its current records.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let pages = static_init!([u32; 32], [0; 32]);
+//! let index = static_init!(
+//!     [capsules::kv_store::IndexEntry; 64],
+//!     [capsules::kv_store::IndexEntry::EMPTY; 64]
+//! );
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+//!     capsules::kv_store::KVStore::new(
+//!         kv_flash,
+//!         992,   // First page of the store
+//!         pages, // One entry per page
+//!         index,
+//!         1024,  // Quota of each namespace, in bytes
+//!         head_page_buffer,
+//!         scratch_page_buffer,
+//!     )
+//! );
+//! kernel::hil::flash::HasClient::set_client(kv_flash, kv_store);
+//! kv_store.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash::{self, Flash};
+use kernel::ReturnCode;
+
+/// Longest key.
+pub const MAX_KEY_LEN: usize = 16;
+/// Longest value, if pages are large enough. See `KVStore::max_value_len`.
+pub const MAX_VALUE_LEN: usize = 256;
+/// Smallest number of pages the store can use.
+pub const MIN_PAGES: usize = 5;
+
+/// "KVS1"
+const PAGE_MAGIC: u32 = 0x3153_564b;
+const PAGE_HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 8;
+const FLAG_DELETED: u8 = 0x01;
+
+/// Erased pages the store keeps for updates and compaction.
+const RESERVED_PAGES: usize = 3;
+
+/// Sequence numbers recorded for pages without valid contents.
+const ERASED: u32 = 0xffff_ffff;
+const DIRTY: u32 = 0xffff_fffe;
+
+/// Entry of the index of current records.
+#[derive(Clone, Copy)]
+pub struct IndexEntry {
+    namespace: u32,
+    key: [u8; MAX_KEY_LEN],
+    /// 0 if the entry is free.
+    key_len: u8,
+    page: u16,
+    offset: u16,
+    /// Length of the whole record.
+    len: u16,
+    /// Offset the record was copied to in the head page, while compacting.
+    moved_to: Option<u16>,
+}
+
+impl IndexEntry {
+    pub const EMPTY: IndexEntry = IndexEntry {
+        namespace: 0,
+        key: [0; MAX_KEY_LEN],
+        key_len: 0,
+        page: 0,
+        offset: 0,
+        len: 0,
+        moved_to: None,
+    };
+
+    fn is_free(&self) -> bool {
+        self.key_len == 0
+    }
+
+    fn matches(&self, namespace: u32, key: &[u8]) -> bool {
+        !self.is_free() && self.namespace == namespace && &self.key[..self.key_len as usize] == key
+    }
+}
+
+pub trait KVStoreClient {
+    fn mount_done(&self, error: ReturnCode);
+
+    /// `length` is the length of the value, which is only copied to
+    /// `buffer` up to its length.
+    fn get_done(&self, buffer: &'static mut [u8], length: usize, error: ReturnCode);
+
+    fn set_done(&self, buffer: &'static mut [u8], error: ReturnCode);
+
+    fn delete_done(&self, error: ReturnCode);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Mount,
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    Idle,
+    /// Reading the header of a page.
+    MountScan(usize),
+    /// Erasing a page with invalid contents.
+    MountClean,
+    /// Reading the records of a page.
+    MountReplay(usize),
+    /// Reading the head page into the head buffer.
+    MountHead(usize),
+    /// Reading the page holding a value.
+    Get,
+    /// Erasing a page left behind by a failed write.
+    Clean,
+    /// Reading the oldest page to compact it.
+    CompactRead,
+    /// Erasing the compacted page.
+    CompactErase,
+    /// Writing the head buffer to an erased page.
+    Flush,
+    /// Erasing the previous copy of the head page.
+    FlushErase,
+}
+
+/// Header fields of a record.
+struct Record {
+    namespace: u32,
+    key_len: usize,
+    deleted: bool,
+    value_len: usize,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        RECORD_HEADER_LEN + self.key_len + self.value_len
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    buf[pos] as u16 | (buf[pos + 1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    read_u16(buf, pos) as u32 | (read_u16(buf, pos + 2) as u32) << 16
+}
+
+fn write_u16(buf: &mut [u8], pos: usize, value: u16) {
+    buf[pos] = value as u8;
+    buf[pos + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(buf: &mut [u8], pos: usize, value: u32) {
+    write_u16(buf, pos, value as u16);
+    write_u16(buf, pos + 2, (value >> 16) as u16);
+}
+
+/// CRC-32 (IEEE 802.3), continuing from `crc`.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn page_crc(page: &[u8], used: usize) -> u32 {
+    crc32(
+        crc32(0, &page[..12]),
+        &page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + used],
+    )
+}
+
+/// Returns the sequence number and the length of the records of a page
+/// with valid contents.
+fn parse_header(page: &[u8]) -> Option<(u32, usize)> {
+    let used = read_u16(page, 8) as usize;
+    if read_u32(page, 0) != PAGE_MAGIC
+        || PAGE_HEADER_LEN + used > page.len()
+        || read_u32(page, 12) != page_crc(page, used)
+    {
+        return None;
+    }
+    Some((read_u32(page, 4), used))
+}
+
+/// Parses the record at `pos`, which must end before `end`.
+fn parse_record(page: &[u8], pos: usize, end: usize) -> Option<Record> {
+    if pos + RECORD_HEADER_LEN > end {
+        return None;
+    }
+    let record = Record {
+        namespace: read_u32(page, pos),
+        key_len: page[pos + 4] as usize,
+        deleted: page[pos + 5] & FLAG_DELETED != 0,
+        value_len: read_u16(page, pos + 6) as usize,
+    };
+    if record.key_len == 0 || record.key_len > MAX_KEY_LEN || pos + record.len() > end {
+        None
+    } else {
+        Some(record)
+    }
+}
+
+pub struct KVStore<'a, F: Flash + 'static> {
+    driver: &'a F,
+    /// First flash page of the store.
+    first_page: usize,
+    num_pages: usize,
+    page_size: usize,
+    /// Bytes of records each namespace may use.
+    quota: usize,
+    client: OptionalCell<&'a dyn KVStoreClient>,
+    state: Cell<State>,
+    op: Cell<Op>,
+
+    /// Sequence number of each page, or `ERASED` or `DIRTY`.
+    pages: TakeCell<'static, [u32]>,
+    index: TakeCell<'static, [IndexEntry]>,
+    /// Image of the head page, with the records being added to it.
+    head: TakeCell<'static, F::Page>,
+    /// Buffer for reading pages.
+    scratch: TakeCell<'static, F::Page>,
+
+    /// Page the head buffer was last written to, unless a new head page was
+    /// started since.
+    head_page: Cell<Option<usize>>,
+    /// Length of the records in the head buffer.
+    head_used: Cell<usize>,
+    /// Length of the records on flash in the head page.
+    head_written: Cell<usize>,
+    next_seq: Cell<u32>,
+    last_written: Cell<usize>,
+    /// Page being read, written or erased.
+    target: Cell<usize>,
+
+    /// Sequence number of the page replayed last while mounting.
+    replayed: Cell<Option<u32>>,
+
+    compacting: Cell<bool>,
+    compactions: Cell<usize>,
+    victim: Cell<usize>,
+    victim_pos: Cell<usize>,
+    victim_end: Cell<usize>,
+
+    // The operation in progress.
+    namespace: Cell<u32>,
+    key: Cell<[u8; MAX_KEY_LEN]>,
+    key_len: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    /// Offset of the added record in the head buffer.
+    record_offset: Cell<usize>,
+}
+
+impl<F: Flash> KVStore<'a, F> {
+    pub fn new(
+        driver: &'a F,
+        first_page: usize,
+        pages: &'static mut [u32],
+        index: &'static mut [IndexEntry],
+        quota: usize,
+        head: &'static mut F::Page,
+        scratch: &'static mut F::Page,
+    ) -> KVStore<'a, F> {
+        KVStore {
+            driver: driver,
+            first_page: first_page,
+            num_pages: pages.len(),
+            page_size: head.as_mut().len(),
+            quota: quota,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Unmounted),
+            op: Cell::new(Op::Mount),
+            pages: TakeCell::new(pages),
+            index: TakeCell::new(index),
+            head: TakeCell::new(head),
+            scratch: TakeCell::new(scratch),
+            head_page: Cell::new(None),
+            head_used: Cell::new(0),
+            head_written: Cell::new(0),
+            next_seq: Cell::new(0),
+            last_written: Cell::new(0),
+            target: Cell::new(0),
+            replayed: Cell::new(None),
+            compacting: Cell::new(false),
+            compactions: Cell::new(0),
+            victim: Cell::new(0),
+            victim_pos: Cell::new(0),
+            victim_end: Cell::new(0),
+            namespace: Cell::new(0),
+            key: Cell::new([0; MAX_KEY_LEN]),
+            key_len: Cell::new(0),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            record_offset: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn KVStoreClient) {
+        self.client.set(client);
+    }
+
+    /// Longest value the store accepts. Records are limited to half a page,
+    /// so that compaction always frees space.
+    pub fn max_value_len(&self) -> usize {
+        let max_record = (self.page_size - PAGE_HEADER_LEN) / 2;
+        cmp::min(MAX_VALUE_LEN, max_record - RECORD_HEADER_LEN - MAX_KEY_LEN)
+    }
+
+    /// Bytes of records all namespaces together may use. Compaction can
+    /// always free an erased page as long as the records fit in all but four
+    /// pages, even if records of maximal length leave space at the end of
+    /// every page.
+    pub fn capacity(&self) -> usize {
+        let usable = self.page_size - PAGE_HEADER_LEN;
+        let max_record = RECORD_HEADER_LEN + MAX_KEY_LEN + self.max_value_len();
+        self.num_pages.saturating_sub(4) * (usable - max_record)
+    }
+
+    pub fn quota(&self) -> usize {
+        self.quota
+    }
+
+    /// Bytes of records used by `namespace`.
+    pub fn used(&self, namespace: u32) -> usize {
+        self.index.map_or(0, |index| {
+            index
+                .iter()
+                .filter(|entry| !entry.is_free() && entry.namespace == namespace)
+                .map(|entry| entry.len as usize)
+                .sum()
+        })
+    }
+
+    fn total_used(&self) -> usize {
+        self.index.map_or(0, |index| {
+            index
+                .iter()
+                .filter(|entry| !entry.is_free())
+                .map(|entry| entry.len as usize)
+                .sum()
+        })
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        match self.state.get() {
+            State::Unmounted
+            | State::MountScan(_)
+            | State::MountClean
+            | State::MountReplay(_)
+            | State::MountHead(_) => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        match self.state.get() {
+            State::Idle | State::Unmounted => false,
+            _ => true,
+        }
+    }
+
+    /// Reads the store from flash. The store accepts no other operation
+    /// until mounting succeeds.
+    pub fn mount(&self) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        } else if self.num_pages < MIN_PAGES || self.num_pages > 0x10000 || self.page_size > 0x10000
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.index.map(|index| {
+            for entry in index.iter_mut() {
+                *entry = IndexEntry::EMPTY;
+            }
+        });
+        self.head_page.set(None);
+        self.head_used.set(0);
+        self.head_written.set(0);
+        self.next_seq.set(0);
+        self.last_written.set(self.num_pages - 1);
+        self.replayed.set(None);
+        self.op.set(Op::Mount);
+        let result = self.read(0, State::MountScan(0));
+        if result != ReturnCode::SUCCESS {
+            self.state.set(State::Unmounted);
+        }
+        result
+    }
+
+    /// Reads the value of `key` in `namespace` into `buffer`. Fails with
+    /// ENODEVICE if there is no such key.
+    pub fn get(
+        &self,
+        namespace: u32,
+        key: &[u8],
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        let result = self.check(key);
+        if result != ReturnCode::SUCCESS {
+            return Err((result, Some(buffer)));
+        }
+        match self.find(namespace, key) {
+            Some(entry) => {
+                self.start(Op::Get, namespace, key);
+                self.record_offset.set(entry.offset as usize);
+                match self.read(entry.page as usize, State::Get) {
+                    ReturnCode::SUCCESS => {
+                        self.buffer.replace(buffer);
+                        Ok(())
+                    }
+                    result => {
+                        self.state.set(State::Idle);
+                        Err((result, Some(buffer)))
+                    }
+                }
+            }
+            None => Err((ReturnCode::ENODEVICE, Some(buffer))),
+        }
+    }
+
+    /// Sets `key` in `namespace` to the first `length` bytes of `buffer`.
+    /// Fails with ENOMEM if the namespace would exceed its quota, or the
+    /// store would be full.
+    pub fn set(
+        &self,
+        namespace: u32,
+        key: &[u8],
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        let mut result = self.check(key);
+        if result == ReturnCode::SUCCESS && (length > buffer.len() || length > self.max_value_len())
+        {
+            result = ReturnCode::ESIZE;
+        }
+        if result != ReturnCode::SUCCESS {
+            return Err((result, Some(buffer)));
+        }
+
+        let record_len = RECORD_HEADER_LEN + key.len() + length;
+        let (replaced_len, has_entry) = match self.find(namespace, key) {
+            Some(entry) => (entry.len as usize, true),
+            None => (
+                0,
+                self.index
+                    .map_or(false, |index| index.iter().any(|entry| entry.is_free())),
+            ),
+        };
+        if !has_entry
+            || self.used(namespace) - replaced_len + record_len > self.quota
+            || self.total_used() - replaced_len + record_len > self.capacity()
+        {
+            return Err((ReturnCode::ENOMEM, Some(buffer)));
+        }
+
+        self.start(Op::Set, namespace, key);
+        self.buffer.replace(buffer);
+        self.length.set(length);
+        self.update_step();
+        Ok(())
+    }
+
+    /// Deletes `key` in `namespace`. Fails with ENODEVICE if there is no
+    /// such key.
+    pub fn delete(&self, namespace: u32, key: &[u8]) -> ReturnCode {
+        let result = self.check(key);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        } else if self.find(namespace, key).is_none() {
+            return ReturnCode::ENODEVICE;
+        }
+        self.start(Op::Delete, namespace, key);
+        self.length.set(0);
+        self.update_step();
+        ReturnCode::SUCCESS
+    }
+
+    fn check(&self, key: &[u8]) -> ReturnCode {
+        if self.state.get() == State::Unmounted {
+            ReturnCode::EOFF
+        } else if self.is_busy() {
+            ReturnCode::EBUSY
+        } else if key.is_empty() || key.len() > MAX_KEY_LEN {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn start(&self, op: Op, namespace: u32, key: &[u8]) {
+        let mut stored_key = [0; MAX_KEY_LEN];
+        stored_key[..key.len()].copy_from_slice(key);
+        self.op.set(op);
+        self.namespace.set(namespace);
+        self.key.set(stored_key);
+        self.key_len.set(key.len());
+        self.compactions.set(0);
+    }
+
+    fn find(&self, namespace: u32, key: &[u8]) -> Option<IndexEntry> {
+        self.index.map_or(None, |index| {
+            index
+                .iter()
+                .find(|entry| entry.matches(namespace, key))
+                .copied()
+        })
+    }
+
+    fn finish(&self, result: ReturnCode) {
+        let op = self.op.get();
+        self.compacting.set(false);
+        self.state
+            .set(if op == Op::Mount && result != ReturnCode::SUCCESS {
+                State::Unmounted
+            } else {
+                State::Idle
+            });
+        match op {
+            Op::Mount => {
+                self.client.map(|client| client.mount_done(result));
+            }
+            Op::Get => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.get_done(buffer, self.length.get(), result));
+                });
+            }
+            Op::Set => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.set_done(buffer, result));
+                });
+            }
+            Op::Delete => {
+                self.client.map(|client| client.delete_done(result));
+            }
+        }
+    }
+
+    fn page_state(&self, page: usize) -> u32 {
+        self.pages.map_or(DIRTY, |pages| pages[page])
+    }
+
+    fn set_page_state(&self, page: usize, seq: u32) {
+        self.pages.map(|pages| pages[page] = seq);
+    }
+
+    fn erased_pages(&self) -> usize {
+        self.pages.map_or(0, |pages| {
+            pages.iter().filter(|seq| **seq == ERASED).count()
+        })
+    }
+
+    fn dirty_page(&self) -> Option<usize> {
+        self.pages
+            .map_or(None, |pages| pages.iter().position(|seq| *seq == DIRTY))
+    }
+
+    /// The page written first among the pages in use.
+    fn oldest_page(&self) -> Option<usize> {
+        self.pages.map_or(None, |pages| {
+            (0..pages.len())
+                .filter(|page| pages[*page] < DIRTY)
+                .min_by_key(|page| pages[*page])
+        })
+    }
+
+    /// The page with the lowest sequence number after `after`.
+    fn next_page_to_replay(&self, after: Option<u32>) -> Option<usize> {
+        self.pages.map_or(None, |pages| {
+            (0..pages.len())
+                .filter(|page| pages[*page] < DIRTY && after.map_or(true, |seq| pages[*page] > seq))
+                .min_by_key(|page| pages[*page])
+        })
+    }
+
+    /// The next erased page after the page written last, in turn.
+    fn next_erased_page(&self) -> Option<usize> {
+        let last = self.last_written.get();
+        (1..=self.num_pages)
+            .map(|i| (last + i) % self.num_pages)
+            .find(|page| self.page_state(*page) == ERASED)
+    }
+
+    fn read(&self, page: usize, state: State) -> ReturnCode {
+        self.target.set(page);
+        self.state.set(state);
+        self.scratch.take().map_or(ReturnCode::ERESERVE, |scratch| {
+            match self.driver.read_page(self.first_page + page, scratch) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err((result, scratch)) => {
+                    self.scratch.replace(scratch);
+                    result
+                }
+            }
+        })
+    }
+
+    /// Reads a page while an operation is in progress.
+    fn read_or_finish(&self, page: usize, state: State) {
+        let result = self.read(page, state);
+        if result != ReturnCode::SUCCESS {
+            self.finish(result);
+        }
+    }
+
+    fn erase(&self, page: usize, state: State) {
+        self.target.set(page);
+        self.state.set(state);
+        let result = self.driver.erase_page(self.first_page + page);
+        if result != ReturnCode::SUCCESS {
+            if state == State::FlushErase {
+                // The update was written, the page is erased later
+                self.flushed();
+            } else {
+                self.finish(result);
+            }
+        }
+    }
+
+    /// Classifies a page read while mounting.
+    fn scan_page(&self, page: usize) {
+        let seq = self.scratch.map_or(DIRTY, |scratch| {
+            let scratch = scratch.as_mut();
+            match parse_header(scratch) {
+                Some((seq, _)) => seq,
+                None if scratch.iter().all(|byte| *byte == 0xff) => ERASED,
+                None => DIRTY,
+            }
+        });
+        self.set_page_state(page, seq);
+        if seq < DIRTY && seq >= self.next_seq.get() {
+            self.next_seq.set(seq + 1);
+            self.last_written.set(page);
+        }
+
+        if page + 1 < self.num_pages {
+            self.read_or_finish(page + 1, State::MountScan(page + 1));
+        } else {
+            self.mount_clean();
+        }
+    }
+
+    fn mount_clean(&self) {
+        match self.dirty_page() {
+            Some(page) => self.erase(page, State::MountClean),
+            None => self.replay_next(),
+        }
+    }
+
+    fn replay_next(&self) {
+        match self.next_page_to_replay(self.replayed.get()) {
+            Some(page) => self.read_or_finish(page, State::MountReplay(page)),
+            None => {
+                if self.next_seq.get() == 0 {
+                    // The store is empty
+                    self.finish(ReturnCode::SUCCESS);
+                } else {
+                    let page = self.last_written.get();
+                    self.state.set(State::MountHead(page));
+                    self.target.set(page);
+                    self.head.take().map(|head| {
+                        if let Err((result, head)) =
+                            self.driver.read_page(self.first_page + page, head)
+                        {
+                            self.head.replace(head);
+                            self.finish(result);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Updates the index with the records of a page read while mounting.
+    fn replay_page(&self, page: usize) -> ReturnCode {
+        self.replayed.set(Some(self.page_state(page)));
+        self.scratch.map_or(ReturnCode::FAIL, |scratch| {
+            let scratch = scratch.as_mut();
+            let end = PAGE_HEADER_LEN + parse_header(scratch).map_or(0, |(_, used)| used);
+            let mut pos = PAGE_HEADER_LEN;
+            while let Some(record) = parse_record(scratch, pos, end) {
+                let key =
+                    &scratch[pos + RECORD_HEADER_LEN..pos + RECORD_HEADER_LEN + record.key_len];
+                let indexed = self.index.map_or(false, |index| {
+                    let slot = index
+                        .iter()
+                        .position(|entry| entry.matches(record.namespace, key))
+                        .or_else(|| index.iter().position(|entry| entry.is_free()));
+                    match slot {
+                        Some(slot) if record.deleted => {
+                            if index[slot].matches(record.namespace, key) {
+                                index[slot] = IndexEntry::EMPTY;
+                            }
+                            true
+                        }
+                        Some(slot) => {
+                            let entry = &mut index[slot];
+                            entry.namespace = record.namespace;
+                            entry.key[..key.len()].copy_from_slice(key);
+                            entry.key_len = key.len() as u8;
+                            entry.page = page as u16;
+                            entry.offset = pos as u16;
+                            entry.len = record.len() as u16;
+                            true
+                        }
+                        None => record.deleted,
+                    }
+                });
+                if !indexed {
+                    return ReturnCode::ENOMEM;
+                }
+                pos += record.len();
+            }
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Continues a set or delete, making room first if needed.
+    fn update_step(&self) {
+        if let Some(page) = self.dirty_page() {
+            self.erase(page, State::Clean);
+            return;
+        }
+
+        if self.erased_pages() < RESERVED_PAGES {
+            match self.oldest_page() {
+                Some(victim)
+                    if self.compactions.get() < 2 * self.num_pages
+                        && self.head_page.get() != Some(victim) =>
+                {
+                    self.compactions.set(self.compactions.get() + 1);
+                    self.compacting.set(true);
+                    self.victim.set(victim);
+                    self.read_or_finish(victim, State::CompactRead);
+                }
+                _ => self.finish(ReturnCode::ENOMEM),
+            }
+            return;
+        }
+
+        if !self.append_update() {
+            self.new_head_page();
+            self.append_update();
+        }
+        self.flush();
+    }
+
+    /// Starts a new head page, leaving the current one as it is.
+    fn new_head_page(&self) {
+        self.head_page.set(None);
+        self.head_used.set(0);
+        self.head_written.set(0);
+    }
+
+    /// Adds the record of the set or delete in progress to the head buffer,
+    /// if it fits.
+    fn append_update(&self) -> bool {
+        let key_len = self.key_len.get();
+        let length = self.length.get();
+        let record_len = RECORD_HEADER_LEN + key_len + length;
+        let pos = PAGE_HEADER_LEN + self.head_used.get();
+        if pos + record_len > self.page_size {
+            return false;
+        }
+        self.head.map(|head| {
+            let head = head.as_mut();
+            write_u32(head, pos, self.namespace.get());
+            head[pos + 4] = key_len as u8;
+            head[pos + 5] = if self.op.get() == Op::Delete {
+                FLAG_DELETED
+            } else {
+                0
+            };
+            write_u16(head, pos + 6, length as u16);
+            head[pos + RECORD_HEADER_LEN..pos + RECORD_HEADER_LEN + key_len]
+                .copy_from_slice(&self.key.get()[..key_len]);
+            self.buffer.map(|buffer| {
+                head[pos + RECORD_HEADER_LEN + key_len..pos + record_len]
+                    .copy_from_slice(&buffer[..length]);
+            });
+        });
+        self.record_offset.set(pos);
+        self.head_used.set(self.head_used.get() + record_len);
+        true
+    }
+
+    /// Copies the record at `pos` of the page being compacted to the head
+    /// buffer, if it fits, and returns where it went.
+    fn copy_to_head(&self, pos: usize, len: usize) -> Option<usize> {
+        let to = PAGE_HEADER_LEN + self.head_used.get();
+        if to + len > self.page_size {
+            return None;
+        }
+        self.scratch.map(|scratch| {
+            self.head.map(|head| {
+                head.as_mut()[to..to + len].copy_from_slice(&scratch.as_mut()[pos..pos + len]);
+            });
+        });
+        self.head_used.set(self.head_used.get() + len);
+        Some(to)
+    }
+
+    /// Writes the head buffer to the next erased page.
+    fn flush(&self) {
+        let page = match self.next_erased_page() {
+            Some(page) => page,
+            None => {
+                self.rollback();
+                self.finish(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        let seq = self.next_seq.get();
+        let used = self.head_used.get();
+        self.target.set(page);
+        self.state.set(State::Flush);
+        self.head.take().map(|head| {
+            {
+                let buf = head.as_mut();
+                write_u32(buf, 0, PAGE_MAGIC);
+                write_u32(buf, 4, seq);
+                write_u16(buf, 8, used as u16);
+                write_u16(buf, 10, 0xffff);
+                for byte in buf[PAGE_HEADER_LEN + used..].iter_mut() {
+                    *byte = 0xff;
+                }
+                let crc = page_crc(buf, used);
+                write_u32(buf, 12, crc);
+            }
+            if let Err((result, head)) = self.driver.write_page(self.first_page + page, head) {
+                self.head.replace(head);
+                self.rollback();
+                self.finish(result);
+            }
+        });
+    }
+
+    /// Forgets what was added to the head buffer after a failed write.
+    fn rollback(&self) {
+        self.head_used.set(self.head_written.get());
+        self.index.map(|index| {
+            for entry in index.iter_mut() {
+                entry.moved_to = None;
+            }
+        });
+    }
+
+    /// Updates the index once the head buffer is written to `page`.
+    fn commit(&self, page: usize) {
+        let replaced = self.head_page.get();
+        let compacting = self.compacting.get();
+        let namespace = self.namespace.get();
+        let key = self.key.get();
+        let key = &key[..self.key_len.get()];
+        self.index.map(|index| {
+            for entry in index.iter_mut().filter(|entry| !entry.is_free()) {
+                if let Some(offset) = entry.moved_to.take() {
+                    entry.page = page as u16;
+                    entry.offset = offset;
+                } else if replaced == Some(entry.page as usize) {
+                    entry.page = page as u16;
+                }
+            }
+            if compacting {
+                return;
+            }
+            let slot = index
+                .iter()
+                .position(|entry| entry.matches(namespace, key))
+                .or_else(|| index.iter().position(|entry| entry.is_free()));
+            if let Some(slot) = slot {
+                if self.op.get() == Op::Delete {
+                    index[slot] = IndexEntry::EMPTY;
+                } else {
+                    let entry = &mut index[slot];
+                    entry.namespace = namespace;
+                    entry.key[..key.len()].copy_from_slice(key);
+                    entry.key_len = key.len() as u8;
+                    entry.page = page as u16;
+                    entry.offset = self.record_offset.get() as u16;
+                    entry.len = (RECORD_HEADER_LEN + key.len() + self.length.get()) as u16;
+                }
+            }
+        });
+    }
+
+    /// Continues once the head buffer is written and the previous copy of
+    /// the head page erased.
+    fn flushed(&self) {
+        if self.compacting.get() {
+            self.compact_continue();
+        } else {
+            self.finish(ReturnCode::SUCCESS);
+        }
+    }
+
+    /// Copies the current records of the page being compacted to the head
+    /// page, then erases it.
+    fn compact_continue(&self) {
+        let victim = self.victim.get();
+        loop {
+            let pos = self.victim_pos.get();
+            let record_len = self.scratch.map_or(None, |scratch| {
+                parse_record(scratch.as_mut(), pos, self.victim_end.get()).map(|r| r.len())
+            });
+            let len = match record_len {
+                Some(len) => len,
+                None => break,
+            };
+            let slot = self.index.map_or(None, |index| {
+                index.iter().position(|entry| {
+                    !entry.is_free()
+                        && entry.moved_to.is_none()
+                        && entry.page as usize == victim
+                        && entry.offset as usize == pos
+                })
+            });
+            if let Some(slot) = slot {
+                match self.copy_to_head(pos, len) {
+                    Some(offset) => {
+                        self.index
+                            .map(|index| index[slot].moved_to = Some(offset as u16));
+                    }
+                    None if self.head_used.get() != self.head_written.get() => {
+                        // Write what was copied so far, and carry on with
+                        // this record afterwards
+                        self.flush();
+                        return;
+                    }
+                    None => {
+                        self.new_head_page();
+                        continue;
+                    }
+                }
+            }
+            self.victim_pos.set(pos + len);
+        }
+
+        if self.head_used.get() != self.head_written.get() {
+            self.flush();
+        } else {
+            self.erase(victim, State::CompactErase);
+        }
+    }
+}
+
+impl<F: Flash> flash::Client<F> for KVStore<'a, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, error: flash::Error) {
+        let state = self.state.get();
+        if let State::MountHead(page) = state {
+            let used = parse_header(pagebuffer.as_mut()).map_or(0, |(_, used)| used);
+            self.head.replace(pagebuffer);
+            if error != flash::Error::CommandComplete {
+                self.finish(ReturnCode::FAIL);
+            } else {
+                self.head_page.set(Some(page));
+                self.head_used.set(used);
+                self.head_written.set(used);
+                self.finish(ReturnCode::SUCCESS);
+            }
+            return;
+        }
+
+        self.scratch.replace(pagebuffer);
+        if error != flash::Error::CommandComplete {
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+
+        match state {
+            State::MountScan(page) => self.scan_page(page),
+            State::MountReplay(page) => match self.replay_page(page) {
+                ReturnCode::SUCCESS => self.replay_next(),
+                result => self.finish(result),
+            },
+            State::Get => {
+                let offset = self.record_offset.get();
+                let length = self.scratch.map_or(0, |scratch| {
+                    let page = scratch.as_mut();
+                    match parse_record(page, offset, self.page_size) {
+                        Some(record) => {
+                            let start = offset + RECORD_HEADER_LEN + record.key_len;
+                            self.buffer.map(|buffer| {
+                                let len = cmp::min(buffer.len(), record.value_len);
+                                buffer[..len].copy_from_slice(&page[start..start + len]);
+                            });
+                            record.value_len
+                        }
+                        None => 0,
+                    }
+                });
+                self.length.set(length);
+                self.finish(ReturnCode::SUCCESS);
+            }
+            State::CompactRead => {
+                let used = self.scratch.map_or(0, |scratch| {
+                    parse_header(scratch.as_mut()).map_or(0, |(_, used)| used)
+                });
+                self.victim_pos.set(PAGE_HEADER_LEN);
+                self.victim_end.set(PAGE_HEADER_LEN + used);
+                self.compact_continue();
+            }
+            _ => {}
+        }
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: flash::Error) {
+        self.head.replace(pagebuffer);
+        if self.state.get() != State::Flush {
+            return;
+        }
+
+        let page = self.target.get();
+        if error != flash::Error::CommandComplete {
+            self.set_page_state(page, DIRTY);
+            self.rollback();
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+
+        self.commit(page);
+        let seq = self.next_seq.get();
+        self.set_page_state(page, seq);
+        self.next_seq.set(seq + 1);
+        self.last_written.set(page);
+        let replaced = self.head_page.get();
+        self.head_page.set(Some(page));
+        self.head_written.set(self.head_used.get());
+        match replaced {
+            Some(replaced) => {
+                // Everything on the previous copy is on the new one as well
+                self.set_page_state(replaced, DIRTY);
+                self.erase(replaced, State::FlushErase);
+            }
+            None => self.flushed(),
+        }
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        let page = self.target.get();
+        let erased = error == flash::Error::CommandComplete;
+        if erased {
+            self.set_page_state(page, ERASED);
+        }
+        match self.state.get() {
+            State::MountClean if erased => self.mount_clean(),
+            State::Clean if erased => self.update_step(),
+            State::CompactErase => {
+                if !erased {
+                    self.set_page_state(page, DIRTY);
+                }
+                self.compacting.set(false);
+                self.update_step();
+            }
+            State::FlushErase => self.flushed(),
+            State::MountClean | State::Clean => self.finish(ReturnCode::FAIL),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn records_must_fit() {
+        let mut page = [0xff; 64];
+        write_u32(&mut page, 16, 7);
+        page[20] = 3;
+        page[21] = 0xfe;
+        write_u16(&mut page, 22, 4);
+        let record = parse_record(&page, 16, 16 + 15).unwrap();
+        assert_eq!(record.namespace, 7);
+        assert_eq!(record.len(), 15);
+        assert!(!record.deleted);
+        assert!(parse_record(&page, 16, 16 + 14).is_none());
+        // Erased space is not a record
+        assert!(parse_record(&page, 40, 64).is_none());
+    }
+}
@@ -0,0 +1,325 @@
+//! Provides userspace with a key-value store.
+//!
+//! Each app gets its own namespace in a `capsules::kv_store::KVStore`,
+//! keyed by the persistent identity of the app, which is derived from the
+//! package name in its TBF header. An app therefore keeps its values across
+//! reboots, restarts and reinstalls, and cannot see the values of other
+//! apps. Apps without a package name cannot use the store.
+//!
+//! Package names are not authenticated: an app that claims the name of
+//! another one, or a name with the same hash, reads and writes the values of
+//! that app. Only load apps you trust with each other's values, see
+//! `AppId::persistent_id`.
+//!
+//! The store runs one operation at a time. The driver queues one operation
+//! per app, and runs them in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let kv_store_driver = static_init!(
+//!     capsules::kv_store_driver::KVStoreDriver<'static, F>,
+//!     capsules::kv_store_driver::KVStoreDriver::new(
+//!         kv_store,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut capsules::kv_store_driver::BUFFER,
+//!     )
+//! );
+//! kv_store.set_client(kv_store_driver);
+//! ```
+
+use crate::kv_store::{KVStore, KVStoreClient, MAX_KEY_LEN, MAX_VALUE_LEN};
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash::Flash;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+pub static mut BUFFER: [u8; MAX_VALUE_LEN] = [0; MAX_VALUE_LEN];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Get,
+    Set { value_len: usize },
+    Delete,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    value: Option<AppSlice<Shared, u8>>,
+    /// Operation waiting for the store, with the length of the key.
+    pending: Option<(Command, usize)>,
+}
+
+pub struct KVStoreDriver<'a, F: Flash + 'static> {
+    store: &'a KVStore<'a, F>,
+    apps: Grant<App>,
+    buffer: TakeCell<'static, [u8]>,
+    current_app: OptionalCell<AppId>,
+}
+
+impl<F: Flash> KVStoreDriver<'a, F> {
+    pub fn new(
+        store: &'a KVStore<'a, F>,
+        grant: Grant<App>,
+        buffer: &'static mut [u8],
+    ) -> KVStoreDriver<'a, F> {
+        KVStoreDriver {
+            store: store,
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+            current_app: OptionalCell::empty(),
+        }
+    }
+
+    fn enqueue(&self, appid: AppId, command: Command, key_len: usize) -> ReturnCode {
+        if appid.persistent_id().is_none() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                let key_allowed = app.key.as_ref().map_or(0, |key| key.len());
+                let value_allowed = app.value.as_ref().map_or(0, |value| value.len());
+                if app.pending.is_some() {
+                    return ReturnCode::EBUSY;
+                } else if key_len == 0 || key_len > MAX_KEY_LEN || key_len > key_allowed {
+                    return ReturnCode::EINVAL;
+                }
+                match command {
+                    Command::Get if value_allowed == 0 => return ReturnCode::ERESERVE,
+                    Command::Set { value_len } if value_len > value_allowed => {
+                        return ReturnCode::EINVAL;
+                    }
+                    _ => {}
+                }
+                app.pending = Some((command, key_len));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+
+        if self.current_app.is_some() || self.store.is_busy() {
+            // Runs once the store is done
+            ReturnCode::SUCCESS
+        } else {
+            self.run(appid)
+        }
+    }
+
+    /// Starts the pending operation of `appid`.
+    fn run(&self, appid: AppId) -> ReturnCode {
+        let namespace = match appid.persistent_id() {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        self.apps
+            .enter(appid, |app, _| {
+                let (command, key_len) = match app.pending.take() {
+                    Some(pending) => pending,
+                    None => return ReturnCode::FAIL,
+                };
+                let mut key = [0; MAX_KEY_LEN];
+                match app.key.as_ref() {
+                    Some(slice) if slice.len() >= key_len => {
+                        key[..key_len].copy_from_slice(&slice.as_ref()[..key_len]);
+                    }
+                    _ => return ReturnCode::EINVAL,
+                }
+                let key = &key[..key_len];
+
+                self.current_app.set(appid);
+                let result = match command {
+                    Command::Get => self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                        match self.store.get(namespace, key, buffer) {
+                            Ok(()) => ReturnCode::SUCCESS,
+                            Err((result, buffer)) => {
+                                self.buffer.put(buffer);
+                                result
+                            }
+                        }
+                    }),
+                    Command::Set { value_len } => {
+                        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                            let len = cmp::min(value_len, buffer.len());
+                            app.value.as_ref().map(|value| {
+                                let len = cmp::min(len, value.len());
+                                buffer[..len].copy_from_slice(&value.as_ref()[..len]);
+                            });
+                            match self.store.set(namespace, key, buffer, value_len) {
+                                Ok(()) => ReturnCode::SUCCESS,
+                                Err((result, buffer)) => {
+                                    self.buffer.put(buffer);
+                                    result
+                                }
+                            }
+                        })
+                    }
+                    Command::Delete => self.store.delete(namespace, key),
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.current_app.clear();
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Runs the next pending operation, reporting operations that fail to
+    /// start through their callbacks.
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            if self.current_app.is_some() || self.store.is_busy() {
+                return;
+            }
+            let appid = cntr.enter(|app, _| app.pending.map(|_| app.appid()));
+            if let Some(appid) = appid {
+                let result = self.run(appid);
+                if result != ReturnCode::SUCCESS {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback
+                            .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+                    });
+                }
+            }
+        }
+    }
+
+    /// Schedules the callback of the app whose operation completed.
+    fn done(&self, result: ReturnCode, length: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(result), length, 0));
+            });
+        });
+        self.check_queue();
+    }
+}
+
+impl<F: Flash> KVStoreClient for KVStoreDriver<'a, F> {
+    fn mount_done(&self, _error: ReturnCode) {
+        // Operations that were queued meanwhile fail if the store could not
+        // be mounted
+        self.check_queue();
+    }
+
+    fn get_done(&self, buffer: &'static mut [u8], length: usize, error: ReturnCode) {
+        if error == ReturnCode::SUCCESS {
+            self.current_app.map(|appid| {
+                let _ = self.apps.enter(*appid, |app, _| {
+                    app.value.as_mut().map(|value| {
+                        let len = cmp::min(cmp::min(length, buffer.len()), value.len());
+                        value.as_mut()[..len].copy_from_slice(&buffer[..len]);
+                    });
+                });
+            });
+        }
+        self.buffer.replace(buffer);
+        self.done(error, length);
+    }
+
+    fn set_done(&self, buffer: &'static mut [u8], error: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.done(error, 0);
+    }
+
+    fn delete_done(&self, error: ReturnCode) {
+        self.done(error, 0);
+    }
+}
+
+impl<F: Flash> Driver for KVStoreDriver<'a, F> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key.
+    /// - `1`: The value, read by set and written by get.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.key = slice,
+                    1 => app.value = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An operation completed. The callback gets the result of the
+    ///   operation and, for a get, the length of the value, which may be
+    ///   longer than the value buffer. A get of a key that does not exist
+    ///   fails with ENODEVICE, and a set beyond the quota of the app with
+    ///   ENOMEM.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| {
+                match subscribe_num {
+                    0 => app.callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return SUCCESS if this driver is included on the platform.
+    /// - `1`: Get the value of the key of length `arg1` into the value buffer.
+    /// - `2`: Set the key of length `arg1` to the first `arg2` bytes of the
+    ///   value buffer.
+    /// - `3`: Delete the key of length `arg1`.
+    /// - `4`: Return the number of bytes the app uses.
+    /// - `5`: Return the number of bytes the app may use.
+    /// - `6`: Return the length of the longest value.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.enqueue(appid, Command::Get, arg1),
+            2 => self.enqueue(appid, Command::Set { value_len: arg2 }, arg1),
+            3 => self.enqueue(appid, Command::Delete, arg1),
+            4 => appid
+                .persistent_id()
+                .map_or(ReturnCode::ENOSUPPORT, |namespace| {
+                    ReturnCode::SuccessWithValue {
+                        value: self.store.used(namespace),
+                    }
+                }),
+            5 => ReturnCode::SuccessWithValue {
+                value: self.store.quota(),
+            },
+            6 => ReturnCode::SuccessWithValue {
+                value: self.store.max_value_len(),
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -15,14 +15,21 @@ pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod app_flash_driver;
 pub mod ble_advertising_driver;
+pub mod block_storage;
 pub mod button;
 pub mod buzzer_driver;
 pub mod console;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fat;
+pub mod fat_driver;
+pub mod firmware_update;
+pub mod firmware_update_driver;
 pub mod fm25cl;
+pub mod framed_console;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
@@ -33,6 +40,8 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_store;
+pub mod kv_store_driver;
 pub mod l3gd20;
 pub mod led;
 pub mod log;
@@ -42,6 +51,7 @@ pub mod lsm303dlhc;
 pub mod ltc294x;
 pub mod max17205;
 pub mod mcp230xx;
+pub mod microphone;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
@@ -50,12 +60,15 @@ pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
 pub mod process_console;
+pub mod qspi_flash;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha256;
 pub mod si7021;
+pub mod sim_radio;
 pub mod spi;
 pub mod temperature;
 pub mod tmp006;
@@ -0,0 +1,311 @@
+//! Provides userspace applications with the ability to record audio from a
+//! digital microphone.
+//!
+//! Samples are signed 16-bit PCM, stored little-endian in the buffers the
+//! application has allowed. Recording is double-buffered: in continuous mode
+//! the capsule alternates between the two allowed buffers and signals the
+//! application each time one is full, so the application can process one
+//! buffer while the other is being filled.
+//!
+//! Only one application can record at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let microphone = static_init!(
+//!     capsules::microphone::Microphone<'static>,
+//!     capsules::microphone::Microphone::new(
+//!         &nrf52::pdm::PDM,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut capsules::microphone::BUFFER1,
+//!         &mut capsules::microphone::BUFFER2,
+//!     )
+//! );
+//! hil::audio::AudioInput::set_client(&nrf52::pdm::PDM, microphone);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::audio;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Microphone as usize;
+
+/// Buffers the microphone driver records into. At 16 kHz each buffer holds
+/// 16 ms of audio, which leaves ample time to copy a buffer out to the
+/// application while the other one is being filled.
+pub static mut BUFFER1: [i16; 256] = [0; 256];
+pub static mut BUFFER2: [i16; 256] = [0; 256];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Idle,
+    /// Fill the first application buffer once, then stop.
+    Single,
+    /// Alternate between both application buffers until stopped.
+    Continuous,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    app_buf: [Option<AppSlice<Shared, u8>>; 2],
+}
+
+pub struct Microphone<'a> {
+    mic: &'a dyn audio::AudioInput<'a>,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    mode: Cell<Mode>,
+    // Index of the application buffer currently being filled.
+    app_buf_index: Cell<usize>,
+    // Number of bytes already written to the current application buffer.
+    app_buf_offset: Cell<usize>,
+    buffer1: TakeCell<'static, [i16]>,
+    buffer2: TakeCell<'static, [i16]>,
+}
+
+impl Microphone<'a> {
+    pub fn new(
+        mic: &'a dyn audio::AudioInput<'a>,
+        grant: Grant<App>,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> Microphone<'a> {
+        Microphone {
+            mic: mic,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            mode: Cell::new(Mode::Idle),
+            app_buf_index: Cell::new(0),
+            app_buf_offset: Cell::new(0),
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+        }
+    }
+
+    fn configure(&self, sample_rate: usize) -> ReturnCode {
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.mic.configure(audio::Parameters {
+            sample_rate: sample_rate as u32,
+            channels: audio::Channels::Mono,
+            format: audio::Format::I2s,
+        })
+    }
+
+    fn start(&self, mode: Mode, appid: AppId) -> ReturnCode {
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        let has_buffers = self
+            .apps
+            .enter(appid, |app, _| {
+                // Every buffer in use must have room for at least one sample.
+                let usable = |buf: &Option<AppSlice<Shared, u8>>| {
+                    buf.as_ref().map_or(false, |buf| buf.len() >= 2)
+                };
+                match mode {
+                    Mode::Continuous => usable(&app.app_buf[0]) && usable(&app.app_buf[1]),
+                    _ => usable(&app.app_buf[0]),
+                }
+            })
+            .unwrap_or(false);
+        if !has_buffers {
+            return ReturnCode::ENOMEM;
+        }
+
+        // Reclaim the buffers the driver kept when the last recording stopped.
+        if self.buffer1.is_none() || self.buffer2.is_none() {
+            let (rc, buf1, buf2) = self.mic.retrieve_buffers();
+            buf1.map(|buf| self.store_buffer(buf));
+            buf2.map(|buf| self.store_buffer(buf));
+            if rc != ReturnCode::SUCCESS {
+                return rc;
+            }
+        }
+
+        match (self.buffer1.take(), self.buffer2.take()) {
+            (Some(buf1), Some(buf2)) => {
+                let (rc, buf1, buf2) = self.mic.start_receive(buf1, buf2);
+                buf1.map(|buf| self.buffer1.replace(buf));
+                buf2.map(|buf| self.buffer2.replace(buf));
+                if rc == ReturnCode::SUCCESS {
+                    self.appid.set(appid);
+                    self.mode.set(mode);
+                    self.app_buf_index.set(0);
+                    self.app_buf_offset.set(0);
+                }
+                rc
+            }
+            (buf1, buf2) => {
+                buf1.map(|buf| self.buffer1.replace(buf));
+                buf2.map(|buf| self.buffer2.replace(buf));
+                ReturnCode::EBUSY
+            }
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if self.mode.get() == Mode::Idle {
+            return ReturnCode::EALREADY;
+        }
+        self.mode.set(Mode::Idle);
+        self.appid.clear();
+        self.mic.stop_receive()
+    }
+
+    fn store_buffer(&self, buf: &'static mut [i16]) {
+        if self.buffer1.is_none() {
+            self.buffer1.replace(buf);
+        } else {
+            self.buffer2.replace(buf);
+        }
+    }
+
+    /// Copy `samples` into the active application buffer(s), signalling the
+    /// application for every buffer that fills up.
+    fn deliver(&self, samples: &[i16]) {
+        self.appid.map(|appid| {
+            let res = self.apps.enter(*appid, |app, _| {
+                let mut samples = samples;
+                while !samples.is_empty() && self.mode.get() != Mode::Idle {
+                    let index = self.app_buf_index.get();
+                    let offset = self.app_buf_offset.get();
+                    let (copied, full) = match app.app_buf[index].as_mut() {
+                        Some(buf) if buf.len() >= offset + 2 => {
+                            let dest = &mut buf.as_mut()[offset..];
+                            let count = core::cmp::min(dest.len() / 2, samples.len());
+                            for (chunk, sample) in dest.chunks_mut(2).zip(samples[..count].iter()) {
+                                chunk.copy_from_slice(&sample.to_le_bytes());
+                            }
+                            (count, dest.len() - count * 2 < 2)
+                        }
+                        _ => {
+                            // The buffer was withdrawn or shrunk underneath
+                            // us, so there is nowhere left to record into.
+                            self.stop();
+                            break;
+                        }
+                    };
+                    samples = &samples[copied..];
+
+                    if full {
+                        let length = offset + copied * 2;
+                        app.callback.map(|mut cb| cb.schedule(index, length, 0));
+                        self.app_buf_offset.set(0);
+                        if self.mode.get() == Mode::Continuous {
+                            self.app_buf_index.set(1 - index);
+                        } else {
+                            self.stop();
+                        }
+                    } else {
+                        self.app_buf_offset.set(offset + copied * 2);
+                    }
+                }
+            });
+            if res.is_err() {
+                // The application went away; no one is left to record for.
+                self.stop();
+            }
+        });
+    }
+}
+
+impl audio::InputClient for Microphone<'a> {
+    fn samples_received(&self, buffer: &'static mut [i16], length: usize) {
+        self.deliver(&buffer[..length]);
+
+        if self.mode.get() == Mode::Idle {
+            self.store_buffer(buffer);
+        } else {
+            let (rc, buffer) = self.mic.provide_buffer(buffer);
+            buffer.map(|buf| self.store_buffer(buf));
+            if rc != ReturnCode::SUCCESS {
+                self.stop();
+            }
+        }
+    }
+}
+
+impl Driver for Microphone<'a> {
+    /// Setup buffers to record into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the first buffer.
+    /// - `1`: Set the second buffer, used for continuous recording.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.app_buf[allow_num] = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to recording events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to buffer-full events. The callback receives the
+    ///   index of the buffer that was filled and the number of bytes written
+    ///   to it.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control the microphone.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return the sample rate in Hz.
+    /// - `2`: Set the sample rate in Hz to the closest rate supported.
+    /// - `3`: Record until the first buffer is full.
+    /// - `4`: Record continuously, alternating between both buffers.
+    /// - `5`: Stop recording.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        let owned_by_other = self.appid.map_or(false, |owner| *owner != appid);
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => ReturnCode::SuccessWithValue {
+                value: self.mic.get_sample_rate() as usize,
+            },
+            2 => self.configure(data),
+            3 => self.start(Mode::Single, appid),
+            4 => self.start(Mode::Continuous, appid),
+            5 if owned_by_other => ReturnCode::EBUSY,
+            5 => self.stop(),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Over-the-air installation of applications over UDP.
+//!
+//! An update server sends a TBF application image to the node in chunks over
+//! a bound UDP port. The node appends the image to the application flash,
+//! behind the applications already installed, and checks its HMAC-SHA256
+//! under a key that the node shares with the server. The new application is
+//! started at the next reset, when the kernel loads the processes.
+//!
+//! Only a sender that knows the key can install an application. The requests
+//! themselves are not authenticated, so any node in range can still abort a
+//! transfer or write unverified data behind the installed applications,
+//! which is never loaded. The key must be kept secret and differ between
+//! deployments.
+//!
+//! Applications in flash form a list: each TBF header gives the size of its
+//! application, and the kernel stops loading at the first invalid header. The
+//! node keeps the list terminated until the image is verified. It first
+//! invalidates the header slot behind the last application, receives the
+//! image, and writes the first chunk, which holds the TBF header, only once
+//! the HMAC matches. Images are placed at a multiple of their size rounded
+//! up to a power of two, as the MPU requires on Cortex-M. A padding header,
+//! written last, then fills the gap behind the previous application.
+//!
+//! Protocol
+//! --------
+//!
+//! The server sends one request at a time and waits for the response, which
+//! is sent to the address and port the request came from. Requests that
+//! arrive while the node still handles the previous one are dropped, so the
+//! server retransmits a request when no response arrives in time. All fields
+//! are little endian.
+//!
+//! | Request | Type | Fields                                     |
+//! |---------|------|--------------------------------------------|
+//! | Start   | 1    | image length (u32), HMAC of the image      |
+//! | Data    | 2    | offset (u32), data                         |
+//! | Finish  | 3    |                                            |
+//! | Abort   | 4    |                                            |
+//!
+//! The response is `0x80 | type`, a status byte (`Status`) and the offset of
+//! the next byte the node expects (u32). A `Data` request at another offset
+//! is not written and only answered with the expected offset, which lets the
+//! server resume after lost packets. A `Start` request for the image that is
+//! already being received also resumes it, for example after the server
+//! restarted.
+//!
+//! Chunks are at most as long as the buffers passed to `new()`. The first
+//! chunk has to contain at least the first 8 bytes of the TBF header.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let app_update = static_init!(
+//!     capsules::net::app_update::AppUpdate<'static, capsules::sha256::Sha256Software<'static>>,
+//!     capsules::net::app_update::AppUpdate::new(
+//!         udp_send,
+//!         nv_to_page,
+//!         sha,
+//!         APP_UPDATE_KEY,
+//!         apps_region,
+//!         net_cap,
+//!         &mut BUFFER,
+//!         &mut HEADER_BUFFER,
+//!         &mut DIGEST_BUFFER,
+//!         LeasableBuffer::new(&mut TX_BUFFER),
+//!     )
+//! );
+//! udp_send.set_client(app_update);
+//! udp_recv.set_client(app_update);
+//! nv_to_page.set_client(app_update);
+//! sha.set_client(app_update);
+//! ```
+//!
+//! The board binds `udp_send` and `udp_recv` to the update port through the
+//! `UdpPortManager`.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ReturnCode;
+
+/// Request types
+const START: u8 = 1;
+const DATA: u8 = 2;
+const FINISH: u8 = 3;
+const ABORT: u8 = 4;
+
+/// Set in the type of a response
+const RESPONSE: u8 = 0x80;
+
+const START_LENGTH: usize = 37;
+const DATA_HEADER_LENGTH: usize = 5;
+pub const RESPONSE_LENGTH: usize = 6;
+
+/// Bytes of a TBF header needed to find the size of an application
+const TBF_LENGTHS: usize = 8;
+
+/// Size of a padding header, a TBF header without any TLV entries
+const PADDING_HEADER_LENGTH: usize = 16;
+
+/// Result of a request, sent in the response.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    /// The request is malformed or does not fit the current transfer.
+    Invalid = 1,
+    /// The image does not fit behind the installed applications.
+    NoSpace = 2,
+    /// The HMAC of the received image does not match.
+    VerifyFailed = 3,
+    /// Writing the flash or computing the digest failed.
+    Failed = 4,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Invalidating the header slot behind the last application
+    Starting,
+    /// Waiting for the next chunk
+    Receiving,
+    /// Writing a chunk
+    Writing,
+    /// Adding a chunk to the digest
+    Hashing,
+    /// Computing the digest of the image
+    Verifying,
+    /// Writing the TBF header of the image
+    CommitImage,
+    /// Writing the padding header in front of the image
+    CommitPadding,
+    /// The last image is installed
+    Installed,
+}
+
+/// Returns the total size of the application whose TBF header starts `tbf`,
+/// or `None` if it is not a valid header.
+fn tbf_size(tbf: &[u8]) -> Option<usize> {
+    if tbf.len() < TBF_LENGTHS {
+        return None;
+    }
+    let version = u16::from_le_bytes([tbf[0], tbf[1]]);
+    let header_size = u16::from_le_bytes([tbf[2], tbf[3]]) as u32;
+    let total_size = u32::from_le_bytes([tbf[4], tbf[5], tbf[6], tbf[7]]);
+    if version != 2 || header_size < PADDING_HEADER_LENGTH as u32 || total_size < header_size {
+        None
+    } else {
+        Some(total_size as usize)
+    }
+}
+
+/// Returns the offset in `apps` following the last installed application.
+fn apps_end(apps: &[u8]) -> usize {
+    let mut end = 0;
+    while let Some(size) = apps.get(end..).and_then(tbf_size) {
+        if size > apps.len() - end {
+            break;
+        }
+        end += size;
+    }
+    end
+}
+
+/// Returns where an image of `length` bytes goes if the installed
+/// applications end at address `end`, or `None` if it does not fit before
+/// `limit`.
+fn placement(end: usize, length: usize, limit: usize) -> Option<usize> {
+    let align = length.checked_next_power_of_two()?;
+    let mut start = (end + align - 1) / align * align;
+    if start != end && start - end < PADDING_HEADER_LENGTH {
+        start += align;
+    }
+    if start.checked_add(length)? > limit {
+        None
+    } else {
+        Some(start)
+    }
+}
+
+/// Writes a TBF header for a disabled application of `length` bytes.
+fn padding_header(length: usize, buf: &mut [u8]) {
+    let words = [
+        2 | (PADDING_HEADER_LENGTH as u32) << 16,
+        length as u32,
+        0, // Flags, the padding is not enabled
+    ];
+    let checksum = words.iter().fold(0, |checksum, word| checksum ^ word);
+    for (dst, word) in buf.chunks_mut(4).zip(words.iter().chain(Some(&checksum))) {
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// Compares two digests in constant time.
+fn digests_match(first: &[u8; 32], second: &[u8; 32]) -> bool {
+    first
+        .iter()
+        .zip(second.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+pub struct AppUpdate<'a, D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    storage: &'a dyn NonvolatileStorage<'static>,
+    digest: &'a D,
+    /// Key of the HMAC that authenticates images
+    key: [u8; 32],
+    /// The flash region holding the applications
+    apps: &'a [u8],
+    net_cap: &'static NetworkCapability,
+    state: Cell<State>,
+    /// End of the installed applications, in flash
+    end: Cell<usize>,
+    /// Address of the image in flash
+    start: Cell<usize>,
+    length: Cell<usize>,
+    expected: Cell<[u8; 32]>,
+    /// Offset of the next byte expected
+    received: Cell<usize>,
+    /// Length of the chunk being written
+    chunk: Cell<usize>,
+    /// Length of the first chunk, written last
+    header_len: Cell<usize>,
+    /// Request to answer once the current operation is done
+    request: Cell<u8>,
+    peer_addr: Cell<IPAddr>,
+    peer_port: Cell<u16>,
+    buffer: TakeCell<'static, [u8]>,
+    header: TakeCell<'static, [u8]>,
+    digest_buffer: TakeCell<'static, [u8; 32]>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+}
+
+impl<D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> AppUpdate<'a, D> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        storage: &'a dyn NonvolatileStorage<'static>,
+        digest: &'a D,
+        key: [u8; 32],
+        apps: &'a [u8],
+        net_cap: &'static NetworkCapability,
+        buffer: &'static mut [u8],
+        header: &'static mut [u8],
+        digest_buffer: &'static mut [u8; 32],
+        tx_buffer: LeasableBuffer<'static, u8>,
+    ) -> AppUpdate<'a, D> {
+        AppUpdate {
+            udp_sender,
+            storage,
+            digest,
+            key,
+            apps,
+            net_cap,
+            state: Cell::new(State::Idle),
+            end: Cell::new(0),
+            start: Cell::new(0),
+            length: Cell::new(0),
+            expected: Cell::new([0; 32]),
+            received: Cell::new(0),
+            chunk: Cell::new(0),
+            header_len: Cell::new(0),
+            request: Cell::new(0),
+            peer_addr: Cell::new(IPAddr::new()),
+            peer_port: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            header: TakeCell::new(header),
+            digest_buffer: TakeCell::new(digest_buffer),
+            tx_buffer: MapCell::new(tx_buffer),
+        }
+    }
+
+    fn respond(&self, request: u8, status: Status, offset: usize) {
+        if let Some(mut tx_buffer) = self.tx_buffer.take() {
+            tx_buffer.reset();
+            tx_buffer[0] = RESPONSE | request;
+            tx_buffer[1] = status as u8;
+            tx_buffer[2..RESPONSE_LENGTH].copy_from_slice(&(offset as u32).to_le_bytes());
+            tx_buffer.slice(0..RESPONSE_LENGTH);
+            if let Err(tx_buffer) = self.udp_sender.send_to(
+                self.peer_addr.get(),
+                self.peer_port.get(),
+                tx_buffer,
+                self.net_cap,
+            ) {
+                self.tx_buffer.replace(tx_buffer);
+            }
+        }
+    }
+
+    /// Ends the current operation and answers the request that started it.
+    fn complete(&self, state: State, status: Status) {
+        self.state.set(state);
+        self.respond(self.request.get(), status, self.received.get());
+    }
+
+    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+        let rcode = self.storage.write(buffer, address, length);
+        if rcode != ReturnCode::SUCCESS {
+            // The storage does not give the buffer back when it fails right
+            // away, so this ends the transfer.
+            self.complete(State::Idle, Status::Failed);
+        }
+        rcode
+    }
+
+    fn start(&self, length: usize, expected: [u8; 32]) {
+        if self.state.get() == State::Receiving
+            && self.length.get() == length
+            && self.expected.get() == expected
+        {
+            self.respond(START, Status::Ok, self.received.get());
+            return;
+        }
+
+        let region = self.apps.as_ptr() as usize;
+        let end = region + apps_end(self.apps);
+        let start = match placement(end, length, region + self.apps.len()) {
+            Some(start) if length >= TBF_LENGTHS => start,
+            Some(_) => return self.respond(START, Status::Invalid, 0),
+            None => return self.respond(START, Status::NoSpace, 0),
+        };
+        self.end.set(end);
+        self.start.set(start);
+        self.length.set(length);
+        self.expected.set(expected);
+        self.received.set(0);
+        self.digest.clear_data();
+        if self.digest.set_mode_hmacsha256(&self.key).is_err() {
+            return self.respond(START, Status::Failed, 0);
+        }
+
+        // Keep the list of applications terminated until the image is
+        // verified, in case the flash behind it holds an old header.
+        self.request.set(START);
+        match self.buffer.take() {
+            Some(buffer) => {
+                for byte in buffer[..TBF_LENGTHS].iter_mut() {
+                    *byte = 0xff;
+                }
+                self.state.set(State::Starting);
+                self.write(buffer, end, TBF_LENGTHS);
+            }
+            None => self.complete(State::Idle, Status::Failed),
+        }
+    }
+
+    fn data(&self, offset: usize, data: &[u8]) {
+        let received = self.received.get();
+        if offset != received || data.is_empty() {
+            return self.respond(DATA, Status::Ok, received);
+        }
+        if offset + data.len() > self.length.get() {
+            return self.respond(DATA, Status::Invalid, received);
+        }
+        self.request.set(DATA);
+        self.chunk.set(data.len());
+
+        if offset == 0 {
+            // The header is written after the image is verified.
+            if tbf_size(data) != Some(self.length.get()) {
+                return self.respond(DATA, Status::Invalid, received);
+            }
+            match self.header.take() {
+                Some(header) if data.len() <= header.len() => {
+                    header[..data.len()].copy_from_slice(data);
+                    self.header_len.set(data.len());
+                    self.hash(header);
+                }
+                Some(header) => {
+                    self.header.replace(header);
+                    self.respond(DATA, Status::Invalid, received);
+                }
+                None => self.complete(State::Idle, Status::Failed),
+            }
+        } else {
+            match self.buffer.take() {
+                Some(buffer) if data.len() <= buffer.len() => {
+                    buffer[..data.len()].copy_from_slice(data);
+                    self.state.set(State::Writing);
+                    self.write(buffer, self.start.get() + offset, data.len());
+                }
+                Some(buffer) => {
+                    self.buffer.replace(buffer);
+                    self.respond(DATA, Status::Invalid, received);
+                }
+                None => self.complete(State::Idle, Status::Failed),
+            }
+        }
+    }
+
+    fn hash(&self, buffer: &'static mut [u8]) {
+        let mut lease = LeasableBuffer::new(buffer);
+        lease.slice(0..self.chunk.get());
+        self.state.set(State::Hashing);
+        if let Err((_, buffer)) = self.digest.add_data(lease) {
+            self.put_back(buffer);
+            self.complete(State::Idle, Status::Failed);
+        }
+    }
+
+    /// Returns a chunk buffer to where it belongs.
+    fn put_back(&self, buffer: &'static mut [u8]) {
+        if self.received.get() == 0 {
+            self.header.replace(buffer);
+        } else {
+            self.buffer.replace(buffer);
+        }
+    }
+
+    fn finish(&self) {
+        if self.received.get() != self.length.get() {
+            return self.respond(FINISH, Status::Invalid, self.received.get());
+        }
+        self.request.set(FINISH);
+        self.digest_buffer.take().map(|digest_buffer| {
+            self.state.set(State::Verifying);
+            if let Err((_, digest_buffer)) = self.digest.run(digest_buffer) {
+                self.digest_buffer.replace(digest_buffer);
+                self.complete(State::Idle, Status::Failed);
+            }
+        });
+    }
+}
+
+impl<D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> UDPRecvClient for AppUpdate<'a, D> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let state = self.state.get();
+        let ready = match state {
+            State::Idle | State::Receiving | State::Installed => true,
+            _ => false,
+        };
+        if payload.is_empty() || !ready {
+            return;
+        }
+        self.peer_addr.set(src_addr);
+        self.peer_port.set(src_port);
+
+        let request = payload[0];
+        match request {
+            START if payload.len() == START_LENGTH => {
+                let length = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]);
+                let mut expected = [0; 32];
+                expected.copy_from_slice(&payload[5..START_LENGTH]);
+                self.start(length as usize, expected);
+            }
+            DATA if payload.len() >= DATA_HEADER_LENGTH && state == State::Receiving => {
+                let offset = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]);
+                self.data(offset as usize, &payload[DATA_HEADER_LENGTH..]);
+            }
+            DATA | FINISH if state == State::Installed => {
+                // The response to the last request got lost.
+                self.respond(request, Status::Ok, self.received.get());
+            }
+            FINISH if state == State::Receiving => self.finish(),
+            ABORT => {
+                self.state.set(State::Idle);
+                self.respond(ABORT, Status::Ok, 0);
+            }
+            _ => self.respond(request & !RESPONSE, Status::Invalid, 0),
+        }
+    }
+}
+
+impl<D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> UDPSendClient for AppUpdate<'a, D> {
+    fn send_done(&self, _result: ReturnCode, dgram: LeasableBuffer<'static, u8>) {
+        self.tx_buffer.replace(dgram);
+    }
+}
+
+impl<D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> NonvolatileStorageClient<'static>
+    for AppUpdate<'a, D>
+{
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {}
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        match self.state.get() {
+            State::Starting => {
+                self.buffer.replace(buffer);
+                self.complete(State::Receiving, Status::Ok);
+            }
+            State::Writing => self.hash(buffer),
+            State::CommitImage => {
+                self.header.replace(buffer);
+                let padding = self.start.get() - self.end.get();
+                if padding == 0 {
+                    self.complete(State::Installed, Status::Ok);
+                } else {
+                    match self.buffer.take() {
+                        Some(buffer) => {
+                            padding_header(padding, buffer);
+                            self.state.set(State::CommitPadding);
+                            self.write(buffer, self.end.get(), PADDING_HEADER_LENGTH);
+                        }
+                        None => self.complete(State::Idle, Status::Failed),
+                    }
+                }
+            }
+            State::CommitPadding => {
+                self.buffer.replace(buffer);
+                self.complete(State::Installed, Status::Ok);
+            }
+            _ => self.put_back(buffer),
+        }
+    }
+}
+
+impl<D: digest::Digest<'a, [u8; 32]> + digest::HMACSha256> digest::Client<'a, [u8; 32]>
+    for AppUpdate<'a, D>
+{
+    fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        self.put_back(data);
+        if result.is_err() {
+            return self.complete(State::Idle, Status::Failed);
+        }
+        self.received.set(self.received.get() + self.chunk.get());
+        self.complete(State::Receiving, Status::Ok);
+    }
+
+    fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut [u8; 32]) {
+        let matches = result.is_ok() && digests_match(digest, &self.expected.get());
+        self.digest_buffer.replace(digest);
+        if !matches {
+            return self.complete(State::Idle, Status::VerifyFailed);
+        }
+        self.header.take().map(|header| {
+            self.state.set(State::CommitImage);
+            self.write(header, self.start.get(), self.header_len.get());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apps_end_follows_headers() {
+        let mut apps = [0xff; 256];
+        padding_header(64, &mut apps[0..16]);
+        padding_header(128, &mut apps[64..80]);
+        assert_eq!(apps_end(&apps), 192);
+
+        // An application running past the region ends the list.
+        padding_header(128, &mut apps[192..208]);
+        assert_eq!(apps_end(&apps), 192);
+    }
+
+    #[test]
+    fn placement_aligns_to_size() {
+        assert_eq!(placement(0x40000, 0x2000, 0x80000), Some(0x40000));
+        assert_eq!(placement(0x41000, 0x2000, 0x80000), Some(0x42000));
+        // Too small a gap for a padding header
+        assert_eq!(placement(0x41ff8, 0x2000, 0x80000), Some(0x44000));
+        assert_eq!(placement(0x7f000, 0x2000, 0x80000), None);
+    }
+
+    #[test]
+    fn digests_match_compares_all_bytes() {
+        let digest = [0x5a; 32];
+        let mut other = digest;
+        assert!(digests_match(&digest, &other));
+        other[31] ^= 1;
+        assert!(!digests_match(&digest, &other));
+    }
+
+    #[test]
+    fn padding_header_is_valid() {
+        let mut header = [0; 16];
+        padding_header(0x1000, &mut header);
+        assert_eq!(tbf_size(&header), Some(0x1000));
+        let checksum = header
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .fold(0, |checksum, word| checksum ^ word);
+        assert_eq!(checksum, 0);
+    }
+}
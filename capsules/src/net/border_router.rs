@@ -0,0 +1,453 @@
+//! 6LoWPAN border router.
+//!
+//! Forwards IPv6 packets between the 802.15.4 interface of the 6LoWPAN stack
+//! and a host connected over a UART, with SLIP framing. The UART can also be
+//! a USB CDC-ACM function (`usb::cdc`), so boards with native USB need no
+//! serial adapter. On the host, `tools/tunslip6.py` bridges the serial port
+//! to a tun interface, after which the host routes the mesh prefix over it
+//! like over any other link.
+//!
+//! The border router sits between `Sixlowpan` and the local IPv6 receiver
+//! (`IP6RecvStruct`), and sends into the mesh through an `IP6SendStruct` of
+//! its own:
+//!
+//! - Packets to an address of this node, to a link-local address or to a
+//!   link-local multicast group are passed to the local receiver.
+//! - Packets from the host to the mesh prefix, or to a multicast group of
+//!   larger scope, are sent into the mesh, to the MAC address the destination
+//!   interface identifier was generated from.
+//! - Packets from the mesh to another address of the mesh prefix are sent
+//!   back into the mesh the same way, since mesh nodes send all their packets
+//!   to the border router as their gateway.
+//! - All other packets from the mesh are sent to the host, and all other
+//!   packets from the host are dropped.
+//!
+//! Forwarded packets have their hop limit decremented. Only UDP and ICMPv6
+//! packets, which the 6LoWPAN layer can compress, are sent into the mesh. No
+//! ICMPv6 errors are returned for dropped packets.
+//!
+//! Every `ADVERTISEMENT_INTERVAL_S` seconds the border router multicasts a
+//! router advertisement to all nodes of the mesh. It carries the mesh prefix
+//! in a prefix information option, and as 6LoWPAN compression context 0 in a
+//! context option (RFC 6775).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let border_router = static_init!(
+//!     capsules::net::border_router::BorderRouter<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::net::border_router::BorderRouter::new(
+//!         mesh_sender,
+//!         &sam4l::usart::USART0,
+//!         alarm,
+//!         local_ip_ifaces,
+//!         MESH_PREFIX,
+//!         MESH_PREFIX_LEN,
+//!         net_cap,
+//!         &mut RX_BYTE,
+//!         &mut RX_BUFFER,
+//!         &mut TX_BUFFER,
+//!         &mut ADVERTISEMENT_BUFFER,
+//!     )
+//! );
+//! mesh_sender.set_client(border_router);
+//! alarm.set_client(border_router);
+//! sixlowpan_state.set_rx_client(border_router);
+//! border_router.set_local_client(ip_receive);
+//! border_router.start();
+//! ```
+//!
+//! The UART has to be configured and its clients set to the border router
+//! by the board. `mesh_sender` should be created with the broadcast MAC
+//! address as its destination, which router advertisements are sent to.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6SendStruct, IP6Sender};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use crate::net::slip::{self, SlipDecoder};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+/// Period of the router advertisements
+pub const ADVERTISEMENT_INTERVAL_S: u32 = 60;
+
+/// Longest router advertisement payload written by the border router:
+/// reachable time and retransmission timer, a prefix information option and
+/// a context option for a prefix longer than 64 bits
+pub const ADVERTISEMENT_LENGTH: usize = 8 + 32 + 24;
+
+/// Lifetime of the default route, the prefix and the compression context.
+/// Nodes forget them when the border router stops advertising.
+const LIFETIME_S: u16 = 30 * 60;
+
+/// Offset of the hop limit in an IPv6 header
+const HOP_LIMIT_OFFSET: usize = 7;
+
+// Scope of link-local multicast addresses (RFC 4291)
+const LINK_LOCAL_SCOPE: u8 = 2;
+
+// All-nodes link-local multicast address
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+// Neighbor discovery option types
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_6LOWPAN_CONTEXT: u8 = 34;
+
+/// Counters of forwarded and dropped packets.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BorderRouterStats {
+    /// Packets sent to the host
+    pub to_host: u32,
+    /// Packets sent into the mesh
+    pub to_mesh: u32,
+    /// Packets that could not be forwarded
+    pub dropped: u32,
+}
+
+pub struct BorderRouter<'a, A: time::Alarm<'a>> {
+    mesh: &'a IP6SendStruct<'a, A>,
+    uart: &'a dyn uart::UartData<'a>,
+    alarm: &'a A,
+    local: OptionalCell<&'a dyn SixlowpanRxClient>,
+    local_addrs: &'a [IPAddr],
+    prefix: [u8; 16],
+    prefix_len: u8,
+    net_cap: &'static NetworkCapability,
+    rx_byte: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    decoder: Cell<SlipDecoder>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    advertisement: TakeCell<'static, [u8]>,
+    // Set while `mesh` sends a packet
+    mesh_busy: Cell<bool>,
+    // Set when an advertisement is due once `mesh` is free
+    advertise_pending: Cell<bool>,
+    stats: Cell<BorderRouterStats>,
+}
+
+impl<A: time::Alarm<'a>> BorderRouter<'a, A> {
+    /// `rx_buffer` bounds the packets received from the host. `tx_buffer`
+    /// holds a SLIP frame, up to twice as long as the packet it carries;
+    /// longer packets from the mesh are dropped. `advertisement` has to be at
+    /// least `ADVERTISEMENT_LENGTH` bytes long.
+    pub fn new(
+        mesh: &'a IP6SendStruct<'a, A>,
+        uart: &'a dyn uart::UartData<'a>,
+        alarm: &'a A,
+        local_addrs: &'a [IPAddr],
+        prefix: [u8; 16],
+        prefix_len: u8,
+        net_cap: &'static NetworkCapability,
+        rx_byte: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        tx_buffer: &'static mut [u8],
+        advertisement: &'static mut [u8],
+    ) -> BorderRouter<'a, A> {
+        BorderRouter {
+            mesh: mesh,
+            uart: uart,
+            alarm: alarm,
+            local: OptionalCell::empty(),
+            local_addrs: local_addrs,
+            prefix: prefix,
+            prefix_len: prefix_len,
+            net_cap: net_cap,
+            rx_byte: TakeCell::new(rx_byte),
+            rx_buffer: TakeCell::new(rx_buffer),
+            decoder: Cell::new(SlipDecoder::new()),
+            tx_buffer: TakeCell::new(tx_buffer),
+            advertisement: TakeCell::new(advertisement),
+            mesh_busy: Cell::new(false),
+            advertise_pending: Cell::new(false),
+            stats: Cell::new(BorderRouterStats::default()),
+        }
+    }
+
+    /// Sets the receiver of the packets addressed to this node.
+    pub fn set_local_client(&self, client: &'a dyn SixlowpanRxClient) {
+        self.local.set(client);
+    }
+
+    /// Starts receiving from the host and advertising the prefix. The first
+    /// advertisement is sent after a second, once the radio is up.
+    pub fn start(&self) -> ReturnCode {
+        self.set_alarm(1);
+        self.rx_byte
+            .take()
+            .map_or(ReturnCode::EALREADY, |rx_byte| self.receive_byte(rx_byte))
+    }
+
+    pub fn stats(&self) -> BorderRouterStats {
+        self.stats.get()
+    }
+
+    fn update_stats<F: FnOnce(&mut BorderRouterStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    fn set_alarm(&self, seconds: u32) {
+        let interval = seconds * <A::Frequency>::frequency();
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(interval));
+    }
+
+    fn receive_byte(&self, rx_byte: &'static mut [u8]) -> ReturnCode {
+        let (ret, rx_byte) = self.uart.receive_buffer(rx_byte, 1);
+        rx_byte.map(|rx_byte| self.rx_byte.replace(rx_byte));
+        ret
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        addr.is_unicast_link_local()
+            || (addr.is_multicast() && addr.0[1] & 0x0f <= LINK_LOCAL_SCOPE)
+            || self.local_addrs.iter().any(|local| *local == addr)
+    }
+
+    fn to_local(&self, packet: &[u8]) {
+        self.local
+            .map(|local| local.receive(packet, packet.len(), ReturnCode::SUCCESS));
+    }
+
+    fn to_mesh(&self, packet: &[u8], dst: IPAddr) {
+        let ret = if self.mesh_busy.get() {
+            ReturnCode::EBUSY
+        } else {
+            self.mesh.forward(packet, dst.get_mac())
+        };
+        if ret == ReturnCode::SUCCESS {
+            self.mesh_busy.set(true);
+            self.update_stats(|stats| stats.to_mesh += 1);
+        } else {
+            self.update_stats(|stats| stats.dropped += 1);
+        }
+    }
+
+    fn to_host(&self, packet: &[u8]) {
+        let ret = match self.tx_buffer.take() {
+            Some(tx_buffer) if packet[HOP_LIMIT_OFFSET] > 1 => {
+                let packet = packet.iter().enumerate().map(|(i, &byte)| {
+                    if i == HOP_LIMIT_OFFSET {
+                        byte - 1
+                    } else {
+                        byte
+                    }
+                });
+                match slip::encode(packet, tx_buffer) {
+                    Some(len) => {
+                        let (ret, tx_buffer) = self.uart.transmit_buffer(tx_buffer, len);
+                        tx_buffer.map(|tx_buffer| self.tx_buffer.replace(tx_buffer));
+                        ret
+                    }
+                    None => {
+                        self.tx_buffer.replace(tx_buffer);
+                        ReturnCode::ESIZE
+                    }
+                }
+            }
+            Some(tx_buffer) => {
+                self.tx_buffer.replace(tx_buffer);
+                ReturnCode::EINVAL
+            }
+            None => ReturnCode::EBUSY,
+        };
+        if ret == ReturnCode::SUCCESS {
+            self.update_stats(|stats| stats.to_host += 1);
+        } else {
+            self.update_stats(|stats| stats.dropped += 1);
+        }
+    }
+
+    fn from_host(&self, packet: &[u8]) {
+        let dst = match IP6Header::decode(packet).done() {
+            Some((_, header)) => header.dst_addr,
+            None => {
+                self.update_stats(|stats| stats.dropped += 1);
+                return;
+            }
+        };
+        if self.is_local(dst) {
+            self.to_local(packet);
+        } else if dst.has_prefix(&self.prefix, self.prefix_len) || dst.is_multicast() {
+            self.to_mesh(packet, dst);
+        } else {
+            self.update_stats(|stats| stats.dropped += 1);
+        }
+    }
+
+    fn advertise(&self) {
+        if self.mesh_busy.get() {
+            self.advertise_pending.set(true);
+            return;
+        }
+        self.advertise_pending.set(false);
+        self.advertisement.take().map(|buffer| {
+            let len = write_advertisement(buffer, &self.prefix, self.prefix_len);
+            let mut header = ICMP6Header::new(ICMP6Type::Type134);
+            header.set_options(ICMP6HeaderOptions::Type134 {
+                hop_limit: 64,
+                flags: 0,
+                lifetime: LIFETIME_S,
+            });
+            let mut payload = LeasableBuffer::new(buffer);
+            payload.slice(..len);
+            let ret = self.mesh.send_to(
+                ALL_NODES,
+                TransportHeader::ICMP(header),
+                &payload,
+                self.net_cap,
+            );
+            self.mesh_busy.set(ret == ReturnCode::SUCCESS);
+            self.advertisement.replace(payload.take());
+        });
+    }
+}
+
+/// Writes the payload of a router advertisement for `prefix` to `buf` and
+/// returns its length. The hop limit, flags and router lifetime are part of
+/// the ICMPv6 header.
+fn write_advertisement(buf: &mut [u8], prefix: &[u8; 16], prefix_len: u8) -> usize {
+    let lifetime = (LIFETIME_S as u32).to_be_bytes();
+    let mut masked = IPAddr::new();
+    masked.set_prefix(prefix, prefix_len);
+
+    // Reachable time and retransmission timer, both unspecified
+    buf[0..8].copy_from_slice(&[0; 8]);
+
+    // Prefix information: on-link and usable for address configuration
+    buf[8] = OPTION_PREFIX_INFORMATION;
+    buf[9] = 4;
+    buf[10] = prefix_len;
+    buf[11] = 0xc0;
+    buf[12..16].copy_from_slice(&lifetime);
+    buf[16..20].copy_from_slice(&lifetime);
+    buf[20..24].copy_from_slice(&[0; 4]);
+    buf[24..40].copy_from_slice(&masked.0);
+
+    // 6LoWPAN context 0, valid for compression, lifetime in minutes
+    let context_len = if prefix_len > 64 { 16 } else { 8 };
+    buf[40] = OPTION_6LOWPAN_CONTEXT;
+    buf[41] = (1 + context_len / 8) as u8;
+    buf[42] = prefix_len;
+    buf[43] = 0x10;
+    buf[44..46].copy_from_slice(&[0; 2]);
+    buf[46..48].copy_from_slice(&(LIFETIME_S / 60).to_be_bytes());
+    buf[48..48 + context_len].copy_from_slice(&masked.0[..context_len]);
+    48 + context_len
+}
+
+impl<A: time::Alarm<'a>> SixlowpanRxClient for BorderRouter<'a, A> {
+    fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
+        if len > buf.len() || result != ReturnCode::SUCCESS {
+            return;
+        }
+        let packet = &buf[..len];
+        let dst = match IP6Header::decode(packet).done() {
+            Some((_, header)) => header.dst_addr,
+            None => return,
+        };
+        if self.is_local(dst) || dst.is_multicast() {
+            self.to_local(packet);
+        } else if dst.has_prefix(&self.prefix, self.prefix_len) {
+            self.to_mesh(packet, dst);
+        } else {
+            self.to_host(packet);
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> IP6SendClient for BorderRouter<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.mesh_busy.set(false);
+        if self.advertise_pending.get() {
+            self.advertise();
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for BorderRouter<'a, A> {
+    fn fired(&self) {
+        self.advertise();
+        self.set_alarm(ADVERTISEMENT_INTERVAL_S);
+    }
+}
+
+impl<A: time::Alarm<'a>> uart::TransmitClient for BorderRouter<'a, A> {
+    fn transmitted_buffer(&self, tx_buffer: &'static mut [u8], _tx_len: usize, _rval: ReturnCode) {
+        self.tx_buffer.replace(tx_buffer);
+    }
+}
+
+impl<A: time::Alarm<'a>> uart::ReceiveClient for BorderRouter<'a, A> {
+    fn received_buffer(
+        &self,
+        rx_byte: &'static mut [u8],
+        rx_len: usize,
+        rval: ReturnCode,
+        _error: uart::Error,
+    ) {
+        if rval == ReturnCode::SUCCESS && rx_len == 1 {
+            let byte = rx_byte[0];
+            self.rx_buffer.take().map(|rx_buffer| {
+                let mut decoder = self.decoder.get();
+                let packet_len = decoder.receive(byte, rx_buffer);
+                self.decoder.set(decoder);
+                packet_len.map(|len| self.from_host(&rx_buffer[..len]));
+                self.rx_buffer.replace(rx_buffer);
+            });
+        }
+        self.receive_byte(rx_byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ieee802154::MacAddress;
+
+    const PREFIX: [u8; 16] = [
+        0xfd, 0x00, 0, 0, 0, 0, 0, 0x01, 0xaa, 0xbb, 0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn addresses_map_to_macs() {
+        let short = IPAddr::generate_from_mac(MacAddress::Short(0x1234));
+        assert_eq!(short.get_mac(), MacAddress::Short(0x1234));
+        let long = IPAddr::generate_from_mac(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(long.get_mac(), MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(ALL_NODES.get_mac(), MacAddress::Short(0xffff));
+
+        let mut addr = short;
+        addr.set_prefix(&PREFIX, 64);
+        assert!(addr.has_prefix(&PREFIX, 64));
+        assert!(addr.has_prefix(&[0xfd, 0x7f], 9));
+        assert!(!addr.has_prefix(&[0xfd, 0x80], 9));
+        assert!(!short.has_prefix(&PREFIX, 64));
+    }
+
+    #[test]
+    fn advertisement_carries_prefix() {
+        let mut buf = [0xff; ADVERTISEMENT_LENGTH];
+        assert_eq!(write_advertisement(&mut buf, &PREFIX, 64), 56);
+        assert_eq!(buf[..12], [0, 0, 0, 0, 0, 0, 0, 0, 3, 4, 64, 0xc0]);
+        assert_eq!(buf[12..16], [0, 0, 0x07, 0x08]);
+        assert_eq!(buf[24..32], PREFIX[..8]);
+        assert_eq!(buf[32..40], [0; 8]);
+        assert_eq!(buf[40..48], [34, 2, 64, 0x10, 0, 0, 0, 30]);
+        assert_eq!(buf[48..56], PREFIX[..8]);
+
+        assert_eq!(write_advertisement(&mut buf, &PREFIX, 80), 64);
+        assert_eq!(buf[41], 3);
+        assert_eq!(buf[48..58], PREFIX[..10]);
+        assert_eq!(buf[58..64], [0; 6]);
+    }
+}
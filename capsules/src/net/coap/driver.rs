@@ -0,0 +1,461 @@
+//! CoAP userspace interface for requests and resources.
+//!
+//! Several processes share the kernel CoAP endpoint. A process sends requests
+//! to other nodes, which are queued while the request of another process is
+//! in progress, and can serve one resource that other nodes send requests
+//! to. The endpoint handles retransmissions and block-wise transfers, so
+//! processes only see complete payloads.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coap_driver = static_init!(
+//!     capsules::net::coap::driver::CoapDriver<'static>,
+//!     capsules::net::coap::driver::CoapDriver::new(
+//!         coap,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! capsules::net::coap::endpoint::CoapEndpoint::set_client(coap, coap_driver);
+//! ```
+
+use crate::net::coap::endpoint::{CoapClient, CoapEndpoint, Request, MAX_URI_LENGTH};
+use crate::net::coap::message::{self, code_class, Message};
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cmp;
+use kernel::common::cells::OptionalCell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// Length of the request configuration in front of the URI: the destination
+/// address (16 bytes), port (2 bytes) and content format (2 bytes).
+const CONFIG_LENGTH: usize = 20;
+
+/// Content format value that stands for none
+const NO_CONTENT_FORMAT: usize = 0xffff;
+
+/// Set in the first argument of the request command for non-confirmable
+/// requests
+const NON_CONFIRMABLE: usize = 1 << 8;
+
+#[derive(Copy, Clone)]
+struct PendingRequest {
+    method: u8,
+    confirmable: bool,
+    payload_length: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    response_callback: Option<Callback>,
+    request_callback: Option<Callback>,
+    request_config: Option<AppSlice<Shared, u8>>,
+    request_payload: Option<AppSlice<Shared, u8>>,
+    response_payload: Option<AppSlice<Shared, u8>>,
+    resource: Option<AppSlice<Shared, u8>>,
+    served_request: Option<AppSlice<Shared, u8>>,
+    served_response: Option<AppSlice<Shared, u8>>,
+    pending: Option<PendingRequest>,
+    registered: bool,
+    resource_format: Option<u16>,
+}
+
+pub struct CoapDriver<'a> {
+    endpoint: &'a dyn CoapEndpoint<'a>,
+    apps: Grant<App>,
+    /// Process whose request is in progress
+    current_app: OptionalCell<AppId>,
+    /// Process that serves the last request from another node
+    serving_app: OptionalCell<AppId>,
+}
+
+fn content_format(value: usize) -> Option<u16> {
+    if value == NO_CONTENT_FORMAT {
+        None
+    } else {
+        Some(value as u16)
+    }
+}
+
+/// Copies `src` at `offset` to `dst` and returns the number of bytes copied.
+fn copy_from(src: &[u8], offset: usize, dst: &mut [u8]) -> usize {
+    let src = src.get(offset..).unwrap_or(&[]);
+    let length = cmp::min(src.len(), dst.len());
+    dst[..length].copy_from_slice(&src[..length]);
+    length
+}
+
+impl CoapDriver<'a> {
+    pub fn new(endpoint: &'a dyn CoapEndpoint<'a>, grant: Grant<App>) -> CoapDriver<'a> {
+        CoapDriver {
+            endpoint,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            serving_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Passes the pending request of `appid` to the endpoint.
+    fn start_request(&self, appid: AppId) -> ReturnCode {
+        self.do_with_app(appid, |app| {
+            let pending = match app.pending.take() {
+                Some(pending) => pending,
+                None => return ReturnCode::SUCCESS,
+            };
+            let config = match app.request_config {
+                Some(ref config) if config.len() >= CONFIG_LENGTH => config.as_ref(),
+                _ => return ReturnCode::EINVAL,
+            };
+            let mut dest = IPAddr::new();
+            dest.0.copy_from_slice(&config[..16]);
+            let request = Request {
+                dest,
+                port: u16::from_le_bytes([config[16], config[17]]),
+                method: pending.method,
+                confirmable: pending.confirmable,
+                uri: &config[CONFIG_LENGTH..],
+                content_format: content_format(
+                    u16::from_le_bytes([config[18], config[19]]) as usize
+                ),
+                payload_length: pending.payload_length,
+            };
+            let rcode = self.endpoint.request(&request);
+            if rcode == ReturnCode::SUCCESS {
+                self.current_app.set(appid);
+            }
+            rcode
+        })
+    }
+
+    /// Starts the next pending request if no request is in progress. Errors
+    /// are reported to the process through its callback.
+    fn next_request(&self) {
+        while self.current_app.is_none() {
+            let mut next = None;
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if app.pending.is_some() {
+                        next = Some(app.appid());
+                    }
+                });
+                if next.is_some() {
+                    break;
+                }
+            }
+            let appid = match next {
+                Some(appid) => appid,
+                None => return,
+            };
+            let rcode = self.start_request(appid);
+            if rcode != ReturnCode::SUCCESS {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.response_callback
+                        .map(|mut cb| cb.schedule(usize::from(rcode), 0, 0));
+                });
+            }
+        }
+    }
+
+    fn request(&self, appid: AppId, flags: usize, payload_length: usize) -> ReturnCode {
+        let method = flags as u8;
+        if method == 0 || code_class(method) != 0 {
+            return ReturnCode::EINVAL;
+        }
+        let rcode = self.do_with_app(appid, |app| {
+            if app.pending.is_some() || self.current_app.map_or(false, |id| *id == appid) {
+                return ReturnCode::EBUSY;
+            }
+            let config_length = app.request_config.as_ref().map_or(0, |config| config.len());
+            let payload_space = app.request_payload.as_ref().map_or(0, |p| p.len());
+            if config_length < CONFIG_LENGTH || payload_length > payload_space {
+                return ReturnCode::EINVAL;
+            }
+            app.pending = Some(PendingRequest {
+                method,
+                confirmable: flags & NON_CONFIRMABLE == 0,
+                payload_length,
+            });
+            ReturnCode::SUCCESS
+        });
+        if rcode != ReturnCode::SUCCESS || self.current_app.is_some() {
+            return rcode;
+        }
+        // Start the request right away, so that errors are returned directly.
+        self.start_request(appid)
+    }
+
+    fn cancel(&self, appid: AppId) -> ReturnCode {
+        if self.current_app.map_or(false, |id| *id == appid) {
+            self.current_app.clear();
+            let rcode = self.endpoint.cancel();
+            self.next_request();
+            return rcode;
+        }
+        self.do_with_app(appid, |app| match app.pending.take() {
+            Some(_) => ReturnCode::SUCCESS,
+            None => ReturnCode::EINVAL,
+        })
+    }
+
+    fn register(&self, appid: AppId, format: usize) -> ReturnCode {
+        // Copy the path, so that other processes can be compared with it.
+        let mut path = [0; MAX_URI_LENGTH];
+        let mut path_length = 0;
+        let rcode = self.do_with_app(appid, |app| match app.resource {
+            Some(ref resource) if resource.len() > 0 && resource.len() <= MAX_URI_LENGTH => {
+                path_length = resource.len();
+                path[..path_length].copy_from_slice(resource.as_ref());
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EINVAL,
+        });
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        let mut path_taken = false;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.appid() != appid
+                    && app.registered
+                    && app
+                        .resource
+                        .as_ref()
+                        .map_or(false, |other| other.as_ref() == &path[..path_length])
+                {
+                    path_taken = true;
+                }
+            });
+        }
+        if path_taken {
+            return ReturnCode::EBUSY;
+        }
+        self.do_with_app(appid, |app| {
+            app.registered = true;
+            app.resource_format = content_format(format);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn respond(&self, appid: AppId, code: usize, payload_length: usize) -> ReturnCode {
+        if !self.serving_app.map_or(false, |id| *id == appid) {
+            return ReturnCode::EINVAL;
+        }
+        let code = code as u8;
+        match code_class(code) {
+            2..=5 => {}
+            _ => return ReturnCode::EINVAL,
+        }
+        self.do_with_app(appid, |app| {
+            let space = app.served_response.as_ref().map_or(0, |p| p.len());
+            if payload_length > space {
+                return ReturnCode::ESIZE;
+            }
+            self.endpoint
+                .respond(code, app.resource_format, payload_length)
+        })
+    }
+}
+
+impl CoapClient for CoapDriver<'a> {
+    fn request_payload(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.current_app.map_or(0, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.request_payload
+                        .as_ref()
+                        .map_or(0, |payload| copy_from(payload.as_ref(), offset, buf))
+                })
+                .unwrap_or(0)
+        })
+    }
+
+    fn response_received(&self, _code: u8, offset: usize, payload: &[u8]) {
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.response_payload.as_mut().map(|buffer| {
+                    let buffer = buffer.as_mut();
+                    if offset < buffer.len() {
+                        let length = cmp::min(payload.len(), buffer.len() - offset);
+                        buffer[offset..offset + length].copy_from_slice(&payload[..length]);
+                    }
+                });
+            });
+        });
+    }
+
+    fn request_done(&self, result: ReturnCode, code: u8, length: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.response_callback.map(|mut cb| {
+                    cb.schedule(usize::from(result), code as usize, length);
+                });
+            });
+        });
+        self.next_request();
+    }
+
+    fn request_received(&self, request: &Message) -> Result<(), u8> {
+        let mut result = Err(message::NOT_FOUND);
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                let matches = app.registered
+                    && app.request_callback.is_some()
+                    && app
+                        .resource
+                        .as_ref()
+                        .map_or(false, |path| request.path_matches(path.as_ref()));
+                if !matches {
+                    return;
+                }
+                let space = app.served_request.as_ref().map_or(0, |p| p.len());
+                if request.payload.len() > space {
+                    result = Err(message::REQUEST_ENTITY_TOO_LARGE);
+                    return;
+                }
+                app.served_request.as_mut().map(|buffer| {
+                    buffer.as_mut()[..request.payload.len()].copy_from_slice(request.payload);
+                });
+                let format = request
+                    .content_format()
+                    .map_or(NO_CONTENT_FORMAT, |format| format as usize);
+                app.request_callback.map(|mut cb| {
+                    cb.schedule(request.code as usize, request.payload.len(), format);
+                });
+                self.serving_app.set(app.appid());
+                result = Ok(());
+            });
+            if result != Err(message::NOT_FOUND) {
+                break;
+            }
+        }
+        result
+    }
+
+    fn response_payload(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.serving_app.map_or(0, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.served_response
+                        .as_ref()
+                        .map_or(0, |payload| copy_from(payload.as_ref(), offset, buf))
+                })
+                .unwrap_or(0)
+        })
+    }
+}
+
+impl Driver for CoapDriver<'a> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Request configuration: the destination IPv6 address (16
+    ///   bytes), port (2 bytes, little endian) and content format of the
+    ///   payload (2 bytes, little endian, 0xffff for none), followed by the
+    ///   URI of the resource, such as `sensors/temp?unit=c`.
+    /// - `1`: Request payload.
+    /// - `2`: Response payload. Longer payloads are truncated.
+    /// - `3`: Path of the resource this process serves, such as `led`.
+    /// - `4`: Payload of a request for the resource.
+    /// - `5`: Payload of the response to a request for the resource. It has
+    ///   to stay unchanged until the next request for the resource, as other
+    ///   nodes fetch long responses block by block.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0..=5 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.request_config = slice,
+                    1 => app.request_payload = slice,
+                    2 => app.response_payload = slice,
+                    3 => app.resource = slice,
+                    4 => app.served_request = slice,
+                    _ => app.served_response = slice,
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A request is done. The arguments are the return code, the
+    ///   response code and the length of the response payload. The return
+    ///   code is ENOACK if no response arrived and FAIL if the request was
+    ///   rejected.
+    /// - `1`: A request for the resource arrived. The arguments are the
+    ///   method, the payload length and the content format (0xffff for
+    ///   none). The process answers with command `5`.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.response_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.request_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// CoAP control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send a request. `arg1` is the method code (1 GET, 2 POST,
+    ///   3 PUT, 4 DELETE), with bit 8 set for a non-confirmable request, and
+    ///   `arg2` the payload length. Returns EBUSY if the process has a
+    ///   request in progress.
+    /// - `2`: Cancel the request of the process.
+    /// - `3`: Serve the resource whose path is in buffer `3`. `arg1` is the
+    ///   content format of its responses (0xffff for none). Returns EBUSY if
+    ///   another process serves the same path.
+    /// - `4`: Stop serving the resource.
+    /// - `5`: Answer the last request for the resource. `arg1` is the
+    ///   response code, such as 0x45 for 2.05 Content, and `arg2` the
+    ///   payload length. The answer has to come within a second, otherwise
+    ///   the kernel answers with 5.03 Service Unavailable.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.request(appid, arg1, arg2),
+            2 => self.cancel(appid),
+            3 => self.register(appid, arg1),
+            4 => self.do_with_app(appid, |app| {
+                app.registered = false;
+                ReturnCode::SUCCESS
+            }),
+            5 => self.respond(appid, arg1, arg2),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
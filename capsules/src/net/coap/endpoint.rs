@@ -0,0 +1,910 @@
+//! A CoAP (RFC 7252) endpoint on a bound UDP port.
+//!
+//! The endpoint sends requests to other nodes and answers requests for local
+//! resources, on behalf of a single `CoapClient` such as the userspace
+//! driver. It takes care of the message layer:
+//!
+//! - Every message gets a new message ID and every request a new token,
+//!   which responses are matched against.
+//! - Confirmable requests are retransmitted with exponential back-off until
+//!   they are acknowledged, at most `MAX_RETRANSMIT` times. Separate
+//!   responses are acknowledged and confirmable requests that the client has
+//!   already answered are answered again from the last response.
+//! - Request payloads longer than a block are sent block-wise (RFC 7959,
+//!   Block1), and block-wise responses are fetched block by block (Block2)
+//!   and passed on to the client as they arrive. Local responses longer than
+//!   a block are served block-wise as well, with the payload read from the
+//!   client for every block. Block-wise request payloads from other nodes
+//!   are refused.
+//!
+//! One request to another node and one request from another node are in
+//! progress at a time. The client answers a request with `respond()`, which
+//! the endpoint sends piggybacked on the acknowledgement. If it does not
+//! answer within `PROCESSING_TIMEOUT_MS`, the endpoint answers with 5.03
+//! Service Unavailable instead.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coap = static_init!(
+//!     capsules::net::coap::endpoint::Coap<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::net::coap::endpoint::Coap::new(
+//!         udp_send,
+//!         coap_alarm,
+//!         net_cap,
+//!         LeasableBuffer::new(&mut REQUEST_BUFFER),
+//!         LeasableBuffer::new(&mut RESPONSE_BUFFER),
+//!     )
+//! );
+//! udp_send.set_client(coap);
+//! udp_recv.set_client(coap);
+//! coap_alarm.set_client(coap);
+//! ```
+//!
+//! The board binds `udp_send` and `udp_recv` to the CoAP port, usually 5683,
+//! through the `UdpPortManager`.
+
+use crate::net::coap::message::{
+    self, code_class, split_uri, Block, Message, MessageType, MessageWriter, MAX_TOKEN_LENGTH,
+};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Block size exponent of block-wise transfers, for 64 byte blocks
+pub const BLOCK_SZX: u8 = 2;
+
+/// Longest URI of a request, as path and query
+pub const MAX_URI_LENGTH: usize = 64;
+
+/// Length of the tokens of requests sent by the endpoint
+const TOKEN_LENGTH: usize = 4;
+
+// Transmission parameters (RFC 7252, section 4.8). The initial timeout of a
+// confirmable message is between ACK_TIMEOUT and ACK_TIMEOUT times
+// ACK_RANDOM_FACTOR, 1.5.
+const ACK_TIMEOUT_MS: u32 = 2000;
+const ACK_RANDOM_MS: u32 = 1000;
+const MAX_RETRANSMIT: u8 = 4;
+
+/// Time to wait for a separate or non-confirmable response
+const RESPONSE_TIMEOUT_MS: u32 = 30000;
+
+/// Time the client has to answer a request, shorter than the time the peer
+/// waits before retransmitting it
+pub const PROCESSING_TIMEOUT_MS: u32 = 1000;
+
+/// A request to another node
+pub struct Request<'u> {
+    pub dest: IPAddr,
+    pub port: u16,
+    /// Method code, such as `message::GET`
+    pub method: u8,
+    pub confirmable: bool,
+    /// Path and query of the resource, such as `sensors/temp?unit=c`
+    pub uri: &'u [u8],
+    /// Content format of the payload
+    pub content_format: Option<u16>,
+    /// Length of the payload, which is read with `request_payload()`
+    pub payload_length: usize,
+}
+
+pub trait CoapEndpoint<'a> {
+    fn set_client(&self, client: &'a dyn CoapClient);
+
+    /// Sends a request. Returns EBUSY if another request is in progress.
+    fn request(&self, request: &Request) -> ReturnCode;
+
+    /// Gives up the request in progress, without a callback.
+    fn cancel(&self) -> ReturnCode;
+
+    /// Answers the request passed to `request_received()`. The payload is
+    /// read with `response_payload()`. Returns EINVAL if there is no request
+    /// to answer.
+    fn respond(&self, code: u8, content_format: Option<u16>, payload_length: usize) -> ReturnCode;
+}
+
+pub trait CoapClient {
+    /// Copies the request payload at `offset` to `buf` and returns the
+    /// number of bytes copied.
+    fn request_payload(&self, offset: usize, buf: &mut [u8]) -> usize;
+
+    /// Passes on a block of the response payload, starting at `offset`.
+    fn response_received(&self, code: u8, offset: usize, payload: &[u8]);
+
+    /// The request is done. On success, `code` is the response code and
+    /// `length` the length of the response payload. Returns ENOACK if no
+    /// response arrived in time and FAIL if the peer rejected the request.
+    fn request_done(&self, result: ReturnCode, code: u8, length: usize);
+
+    /// A request from another node arrived. Returns a response code to
+    /// answer it right away, such as `message::NOT_FOUND`, or `Ok` to answer
+    /// it later with `respond()`.
+    fn request_received(&self, request: &Message) -> Result<(), u8>;
+
+    /// Copies the response payload at `offset` to `buf` and returns the
+    /// number of bytes copied.
+    fn response_payload(&self, offset: usize, buf: &mut [u8]) -> usize;
+}
+
+/// Buffers that are sent over UDP
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Outgoing {
+    Request,
+    Response,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Exchange {
+    Idle,
+    /// Waiting for the acknowledgement of a confirmable request
+    WaitingAck,
+    /// Waiting for a separate or non-confirmable response
+    WaitingResponse,
+}
+
+/// A request from another node
+#[derive(Copy, Clone)]
+struct PeerRequest {
+    addr: IPAddr,
+    port: u16,
+    message_id: u16,
+    confirmable: bool,
+    token: [u8; MAX_TOKEN_LENGTH],
+    token_length: usize,
+    /// Response block the peer asked for
+    block2: Option<Block>,
+}
+
+/// The last response that was longer than a block. The peer asks for the
+/// following blocks in separate requests.
+#[derive(Copy, Clone)]
+struct BlockwiseResponse {
+    addr: IPAddr,
+    port: u16,
+    code: u8,
+    content_format: Option<u16>,
+    length: usize,
+}
+
+/// Returns whether the time `deadline` has passed at `now`.
+fn expired(deadline: Option<u32>, now: u32) -> bool {
+    deadline.map_or(false, |deadline| now.wrapping_sub(deadline) < 1 << 31)
+}
+
+pub struct Coap<'a, A: time::Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    client: OptionalCell<&'a dyn CoapClient>,
+
+    /// Holds the current request, built again for every transmission
+    request_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Holds the last response, acknowledgement or reset
+    response_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Buffer passed to the UDP sender
+    sending: OptionalCell<Outgoing>,
+    request_queued: Cell<bool>,
+    response_queued: Cell<bool>,
+    response_dest: Cell<(IPAddr, u16)>,
+
+    next_message_id: Cell<u16>,
+    next_token: Cell<u16>,
+
+    // The request to another node
+    exchange: Cell<Exchange>,
+    peer: Cell<(IPAddr, u16)>,
+    method: Cell<u8>,
+    confirmable: Cell<bool>,
+    content_format: Cell<Option<u16>>,
+    uri: MapCell<[u8; MAX_URI_LENGTH]>,
+    uri_length: Cell<usize>,
+    payload_length: Cell<usize>,
+    /// Offset of the payload block in the current message
+    payload_offset: Cell<usize>,
+    /// Block size exponent of the request payload
+    upload_szx: Cell<u8>,
+    /// Response block asked for in the current message
+    block2: Cell<Option<Block>>,
+    message_id: Cell<u16>,
+    token: Cell<[u8; TOKEN_LENGTH]>,
+    retransmissions: Cell<u8>,
+    timeout: Cell<u32>,
+    retransmit_at: Cell<Option<u32>>,
+    response_code: Cell<u8>,
+    response_length: Cell<usize>,
+    /// Message ID of the last separate response, acknowledged again when the
+    /// peer retransmits it
+    separate_response: Cell<Option<u16>>,
+
+    // Requests from other nodes
+    serving: Cell<Option<PeerRequest>>,
+    respond_by: Cell<Option<u32>>,
+    /// Source and message ID of the confirmable request that the message in
+    /// `response_buffer` answers
+    answered: Cell<Option<(IPAddr, u16, u16)>>,
+    blockwise: Cell<Option<BlockwiseResponse>>,
+    /// Path of the request passed to the client last
+    served_path: MapCell<[u8; MAX_URI_LENGTH]>,
+    served_path_length: Cell<Option<usize>>,
+}
+
+impl<A: time::Alarm<'a>> Coap<'a, A> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        request_buffer: LeasableBuffer<'static, u8>,
+        response_buffer: LeasableBuffer<'static, u8>,
+    ) -> Coap<'a, A> {
+        Coap {
+            udp_sender,
+            alarm,
+            net_cap,
+            client: OptionalCell::empty(),
+            request_buffer: MapCell::new(request_buffer),
+            response_buffer: MapCell::new(response_buffer),
+            sending: OptionalCell::empty(),
+            request_queued: Cell::new(false),
+            response_queued: Cell::new(false),
+            response_dest: Cell::new((IPAddr::new(), 0)),
+            next_message_id: Cell::new(0),
+            next_token: Cell::new(0),
+            exchange: Cell::new(Exchange::Idle),
+            peer: Cell::new((IPAddr::new(), 0)),
+            method: Cell::new(0),
+            confirmable: Cell::new(true),
+            content_format: Cell::new(None),
+            uri: MapCell::new([0; MAX_URI_LENGTH]),
+            uri_length: Cell::new(0),
+            payload_length: Cell::new(0),
+            payload_offset: Cell::new(0),
+            upload_szx: Cell::new(BLOCK_SZX),
+            block2: Cell::new(None),
+            message_id: Cell::new(0),
+            token: Cell::new([0; TOKEN_LENGTH]),
+            retransmissions: Cell::new(0),
+            timeout: Cell::new(0),
+            retransmit_at: Cell::new(None),
+            response_code: Cell::new(0),
+            response_length: Cell::new(0),
+            separate_response: Cell::new(None),
+            serving: Cell::new(None),
+            respond_by: Cell::new(None),
+            answered: Cell::new(None),
+            blockwise: Cell::new(None),
+            served_path: MapCell::new([0; MAX_URI_LENGTH]),
+            served_path_length: Cell::new(None),
+        }
+    }
+
+    fn tics(&self, ms: u32) -> u32 {
+        (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let id = self.next_message_id.get();
+        self.next_message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    /// Returns a token that is unique among recent requests. Half of it is
+    /// taken from the clock, which makes it harder to guess.
+    fn next_token(&self) -> [u8; TOKEN_LENGTH] {
+        let count = self.next_token.get();
+        self.next_token.set(count.wrapping_add(1));
+        let count = count.to_be_bytes();
+        let clock = (self.alarm.now() as u16).to_be_bytes();
+        [count[0], count[1], clock[0], clock[1]]
+    }
+
+    /// Sets the alarm for the earliest deadline.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let next = [self.retransmit_at.get(), self.respond_by.get()]
+            .iter()
+            .filter_map(|&deadline| deadline)
+            .min_by_key(|deadline| deadline.wrapping_sub(now));
+        match next {
+            Some(deadline) => self.alarm.set_alarm(deadline),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Sends queued messages, responses first.
+    fn send_next(&self) {
+        if self.sending.is_some() {
+            return;
+        }
+        if self.response_queued.replace(false) {
+            let (addr, port) = self.response_dest.get();
+            if let Some(buffer) = self.response_buffer.take() {
+                match self.udp_sender.send_to(addr, port, buffer, self.net_cap) {
+                    Ok(()) => {
+                        self.sending.set(Outgoing::Response);
+                        return;
+                    }
+                    Err(buffer) => {
+                        self.response_buffer.replace(buffer);
+                    }
+                }
+            }
+        }
+        if self.request_queued.replace(false) && self.exchange.get() != Exchange::Idle {
+            if let Some(mut buffer) = self.request_buffer.take() {
+                buffer.reset();
+                match self.build_request(&mut buffer[..]) {
+                    Ok(length) => {
+                        buffer.slice(0..length);
+                        let (addr, port) = self.peer.get();
+                        match self.udp_sender.send_to(addr, port, buffer, self.net_cap) {
+                            Ok(()) => self.sending.set(Outgoing::Request),
+                            // The request is sent again when it times out.
+                            Err(buffer) => {
+                                self.request_buffer.replace(buffer);
+                            }
+                        }
+                    }
+                    Err(rcode) => {
+                        self.request_buffer.replace(buffer);
+                        self.finish(rcode);
+                    }
+                }
+            }
+        }
+    }
+
+    fn build_request(&self, buf: &mut [u8]) -> Result<usize, ReturnCode> {
+        let message_type = if self.confirmable.get() {
+            MessageType::Confirmable
+        } else {
+            MessageType::NonConfirmable
+        };
+        let token = self.token.get();
+        let mut writer = MessageWriter::new(
+            buf,
+            message_type,
+            self.method.get(),
+            self.message_id.get(),
+            &token,
+        )?;
+
+        // Once the response is fetched block-wise, the payload has been sent.
+        let block2 = self.block2.get();
+        let total = self.payload_length.get();
+        let has_payload = block2.is_none() && total > 0;
+
+        self.uri.map_or(Err(ReturnCode::FAIL), |uri| {
+            let (path, query) = split_uri(&uri[..self.uri_length.get()]);
+            writer.uri_path(path)?;
+            if has_payload {
+                if let Some(format) = self.content_format.get() {
+                    writer.uint_option(message::CONTENT_FORMAT, format as u32)?;
+                }
+            }
+            writer.uri_query(query)
+        })?;
+        if let Some(block) = block2 {
+            writer.uint_option(message::BLOCK2, block.encode())?;
+        }
+
+        let mut length = 0;
+        if has_payload {
+            let offset = self.payload_offset.get();
+            let szx = self.upload_szx.get();
+            let size = 16 << szx;
+            length = cmp::min(size, total - offset);
+            if total > size {
+                let block = Block {
+                    num: (offset / size) as u32,
+                    more: offset + length < total,
+                    szx,
+                };
+                writer.uint_option(message::BLOCK1, block.encode())?;
+            }
+            let space = writer.payload_buffer();
+            if space.len() < length {
+                return Err(ReturnCode::ESIZE);
+            }
+            length = self.client.map_or(0, |client| {
+                client.request_payload(offset, &mut space[..length])
+            });
+        }
+        Ok(writer.finish(length))
+    }
+
+    /// Sends the request with a new message ID and token.
+    fn new_message(&self) {
+        self.message_id.set(self.next_message_id());
+        self.token.set(self.next_token());
+        self.retransmissions.set(0);
+        let now = self.alarm.now();
+        if self.confirmable.get() {
+            let jitter = now % cmp::max(self.tics(ACK_RANDOM_MS), 1);
+            let timeout = self.tics(ACK_TIMEOUT_MS) + jitter;
+            self.exchange.set(Exchange::WaitingAck);
+            self.timeout.set(timeout);
+            self.retransmit_at.set(Some(now.wrapping_add(timeout)));
+        } else {
+            self.exchange.set(Exchange::WaitingResponse);
+            self.retransmit_at
+                .set(Some(now.wrapping_add(self.tics(RESPONSE_TIMEOUT_MS))));
+        }
+        self.update_alarm();
+        self.request_queued.set(true);
+        self.send_next();
+    }
+
+    /// Ends the request to another node and reports `result`.
+    fn finish(&self, result: ReturnCode) {
+        self.exchange.set(Exchange::Idle);
+        self.request_queued.set(false);
+        self.retransmit_at.set(None);
+        self.update_alarm();
+        let code = self.response_code.get();
+        let length = self.response_length.get();
+        self.client
+            .map(|client| client.request_done(result, code, length));
+    }
+
+    fn request_timeout(&self) {
+        match self.exchange.get() {
+            Exchange::WaitingAck if self.retransmissions.get() < MAX_RETRANSMIT => {
+                let timeout = self.timeout.get().wrapping_mul(2);
+                self.retransmissions.set(self.retransmissions.get() + 1);
+                self.timeout.set(timeout);
+                self.retransmit_at
+                    .set(Some(self.alarm.now().wrapping_add(timeout)));
+                self.request_queued.set(true);
+                self.send_next();
+            }
+            Exchange::WaitingAck | Exchange::WaitingResponse => self.finish(ReturnCode::ENOACK),
+            Exchange::Idle => {}
+        }
+    }
+
+    /// Whether `response_buffer` can take a new message.
+    fn response_free(&self) -> bool {
+        !self.response_queued.get()
+            && self
+                .sending
+                .map_or(true, |sending| *sending != Outgoing::Response)
+    }
+
+    /// Builds a message in `response_buffer` and sends it. Returns false if
+    /// the buffer is busy or the message does not fit.
+    fn send_reply<F>(&self, addr: IPAddr, port: u16, build: F) -> bool
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, ReturnCode>,
+    {
+        if !self.response_free() {
+            return false;
+        }
+        let built = self
+            .response_buffer
+            .map(|buffer| {
+                buffer.reset();
+                match build(&mut buffer[..]) {
+                    Ok(length) => {
+                        buffer.slice(0..length);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            })
+            .unwrap_or(false);
+        if built {
+            self.answered.set(None);
+            self.response_dest.set((addr, port));
+            self.response_queued.set(true);
+            self.send_next();
+        }
+        built
+    }
+
+    fn send_empty(&self, message_type: MessageType, message_id: u16, addr: IPAddr, port: u16) {
+        self.send_reply(addr, port, |buf| {
+            MessageWriter::new(buf, message_type, 0, message_id, &[]).map(|writer| writer.finish(0))
+        });
+    }
+
+    /// Answers a request from another node. Returns whether the response is
+    /// sent block-wise, or an error if it could not be sent.
+    fn send_response(
+        &self,
+        request: PeerRequest,
+        code: u8,
+        content_format: Option<u16>,
+        length: usize,
+    ) -> Result<bool, ReturnCode> {
+        let (message_type, message_id) = if request.confirmable {
+            (MessageType::Acknowledgement, request.message_id)
+        } else {
+            (MessageType::NonConfirmable, self.next_message_id())
+        };
+
+        let szx = request
+            .block2
+            .map_or(BLOCK_SZX, |block| cmp::min(block.szx, BLOCK_SZX));
+        let num = request.block2.map_or(0, |block| block.num);
+        let mut block = Block {
+            num,
+            more: false,
+            szx,
+        };
+        let blockwise = length > block.size() || num > 0;
+        let (mut code, mut length) = (code, length);
+        if blockwise {
+            if block.offset() >= length {
+                code = message::BAD_OPTION;
+                length = 0;
+            } else {
+                block.more = block.offset() + block.size() < length;
+            }
+        }
+        let (offset, chunk) = if blockwise && length > 0 {
+            (
+                block.offset(),
+                cmp::min(block.size(), length - block.offset()),
+            )
+        } else {
+            (0, length)
+        };
+
+        let sent = self.send_reply(request.addr, request.port, |buf| {
+            let mut writer = MessageWriter::new(
+                buf,
+                message_type,
+                code,
+                message_id,
+                &request.token[..request.token_length],
+            )?;
+            if length > 0 {
+                if let Some(format) = content_format {
+                    writer.uint_option(message::CONTENT_FORMAT, format as u32)?;
+                }
+                if blockwise {
+                    writer.uint_option(message::BLOCK2, block.encode())?;
+                }
+            }
+            let space = writer.payload_buffer();
+            if space.len() < chunk {
+                return Err(ReturnCode::ESIZE);
+            }
+            let copied = self.client.map_or(0, |client| {
+                client.response_payload(offset, &mut space[..chunk])
+            });
+            Ok(writer.finish(copied))
+        });
+        if !sent {
+            return Err(ReturnCode::FAIL);
+        }
+        if request.confirmable {
+            self.answered
+                .set(Some((request.addr, request.port, request.message_id)));
+        }
+        Ok(blockwise && length > 0)
+    }
+
+    fn handle_empty(&self, addr: IPAddr, port: u16, message: &Message) {
+        let from_peer = self.peer.get() == (addr, port);
+        let current = self.exchange.get() != Exchange::Idle
+            && from_peer
+            && message.message_id == self.message_id.get();
+        match message.message_type {
+            MessageType::Acknowledgement => {
+                if current && self.exchange.get() == Exchange::WaitingAck {
+                    // The response follows separately.
+                    self.exchange.set(Exchange::WaitingResponse);
+                    self.retransmit_at.set(Some(
+                        self.alarm
+                            .now()
+                            .wrapping_add(self.tics(RESPONSE_TIMEOUT_MS)),
+                    ));
+                    self.update_alarm();
+                }
+            }
+            MessageType::Reset => {
+                if current {
+                    self.finish(ReturnCode::FAIL);
+                }
+            }
+            // A ping
+            MessageType::Confirmable => {
+                self.send_empty(MessageType::Reset, message.message_id, addr, port)
+            }
+            MessageType::NonConfirmable => {}
+        }
+    }
+
+    fn handle_response(&self, addr: IPAddr, port: u16, message: &Message) {
+        let ours = self.exchange.get() != Exchange::Idle
+            && self.peer.get() == (addr, port)
+            && message.token == &self.token.get()[..];
+
+        // Separate responses are confirmable.
+        if message.message_type == MessageType::Confirmable {
+            if ours || self.separate_response.get() == Some(message.message_id) {
+                self.send_empty(MessageType::Acknowledgement, message.message_id, addr, port);
+            } else {
+                self.send_empty(MessageType::Reset, message.message_id, addr, port);
+            }
+            if !ours {
+                return;
+            }
+            self.separate_response.set(Some(message.message_id));
+        }
+        if !ours
+            || (message.message_type == MessageType::Acknowledgement
+                && message.message_id != self.message_id.get())
+        {
+            return;
+        }
+        self.retransmit_at.set(None);
+
+        // Block-wise request payload
+        let size = 16 << self.upload_szx.get();
+        let offset = self.payload_offset.get();
+        let total = self.payload_length.get();
+        if self.block2.get().is_none() && offset + size < total && message.code == message::CONTINUE
+        {
+            let next = match message.block1() {
+                // The peer asks for smaller blocks.
+                Some(block) if block.szx < self.upload_szx.get() => {
+                    self.upload_szx.set(block.szx);
+                    (block.num as usize + 1) * block.size()
+                }
+                _ => offset + size,
+            };
+            self.payload_offset.set(next);
+            self.new_message();
+            return;
+        }
+
+        let block = message.block2();
+        let offset = block.map_or(0, |block| block.offset());
+        if offset != self.response_length.get() {
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+        self.response_code.set(message.code);
+        self.response_length.set(offset + message.payload.len());
+        self.client
+            .map(|client| client.response_received(message.code, offset, message.payload));
+        match block {
+            Some(block) if block.more => {
+                self.block2.set(Some(Block {
+                    num: block.num + 1,
+                    more: false,
+                    szx: block.szx,
+                }));
+                self.new_message();
+            }
+            _ => self.finish(ReturnCode::SUCCESS),
+        }
+    }
+
+    fn handle_request(&self, addr: IPAddr, port: u16, message: &Message) {
+        let confirmable = match message.message_type {
+            MessageType::Confirmable => true,
+            MessageType::NonConfirmable => false,
+            _ => return,
+        };
+        if confirmable && self.answered.get() == Some((addr, port, message.message_id)) {
+            // The response was lost, send it again.
+            if self.response_free() {
+                self.response_queued.set(true);
+                self.send_next();
+            }
+            return;
+        }
+
+        let mut token = [0; MAX_TOKEN_LENGTH];
+        token[..message.token.len()].copy_from_slice(message.token);
+        let request = PeerRequest {
+            addr,
+            port,
+            message_id: message.message_id,
+            confirmable,
+            token,
+            token_length: message.token.len(),
+            block2: message.block2(),
+        };
+
+        if let Some(serving) = self.serving.get() {
+            if serving.addr != addr
+                || serving.port != port
+                || serving.message_id != message.message_id
+            {
+                let _ = self.send_response(request, message::SERVICE_UNAVAILABLE, None, 0);
+            }
+            return;
+        }
+        if message.has_unsupported_critical_option() {
+            let _ = self.send_response(request, message::BAD_OPTION, None, 0);
+            return;
+        }
+        if message
+            .block1()
+            .map_or(false, |block| block.num > 0 || block.more)
+        {
+            let _ = self.send_response(request, message::REQUEST_ENTITY_TOO_LARGE, None, 0);
+            return;
+        }
+
+        // A following block of the last block-wise response
+        if let (Some(block), Some(response)) = (request.block2, self.blockwise.get()) {
+            let same_path = self.served_path_length.get().map_or(false, |length| {
+                self.served_path
+                    .map_or(false, |path| message.path_matches(&path[..length]))
+            });
+            if block.num > 0 && response.addr == addr && response.port == port && same_path {
+                let _ = self.send_response(
+                    request,
+                    response.code,
+                    response.content_format,
+                    response.length,
+                );
+                return;
+            }
+        }
+
+        self.blockwise.set(None);
+        let result = self.client.map_or(Err(message::NOT_FOUND), |client| {
+            client.request_received(message)
+        });
+        match result {
+            Ok(()) => {
+                let length = self.served_path.and_then(|path| message.copy_path(path));
+                self.served_path_length.set(length);
+                self.serving.set(Some(request));
+                self.respond_by.set(Some(
+                    self.alarm
+                        .now()
+                        .wrapping_add(self.tics(PROCESSING_TIMEOUT_MS)),
+                ));
+                self.update_alarm();
+            }
+            Err(code) => {
+                let _ = self.send_response(request, code, None, 0);
+            }
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> CoapEndpoint<'a> for Coap<'a, A> {
+    fn set_client(&self, client: &'a dyn CoapClient) {
+        self.client.set(client);
+    }
+
+    fn request(&self, request: &Request) -> ReturnCode {
+        if self.exchange.get() != Exchange::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if request.method == 0 || code_class(request.method) != 0 {
+            return ReturnCode::EINVAL;
+        }
+        if request.uri.len() > MAX_URI_LENGTH {
+            return ReturnCode::ESIZE;
+        }
+        self.uri
+            .map(|uri| uri[..request.uri.len()].copy_from_slice(request.uri));
+        self.uri_length.set(request.uri.len());
+        self.peer.set((request.dest, request.port));
+        self.method.set(request.method);
+        self.confirmable.set(request.confirmable);
+        self.content_format.set(request.content_format);
+        self.payload_length.set(request.payload_length);
+        self.payload_offset.set(0);
+        self.upload_szx.set(BLOCK_SZX);
+        self.block2.set(None);
+        self.response_code.set(0);
+        self.response_length.set(0);
+        self.new_message();
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        if self.exchange.get() == Exchange::Idle {
+            return ReturnCode::EINVAL;
+        }
+        self.exchange.set(Exchange::Idle);
+        self.request_queued.set(false);
+        self.retransmit_at.set(None);
+        self.update_alarm();
+        ReturnCode::SUCCESS
+    }
+
+    fn respond(&self, code: u8, content_format: Option<u16>, payload_length: usize) -> ReturnCode {
+        let request = match self.serving.take() {
+            Some(request) => request,
+            None => return ReturnCode::EINVAL,
+        };
+        self.respond_by.set(None);
+        self.update_alarm();
+        match self.send_response(request, code, content_format, payload_length) {
+            Ok(blockwise) => {
+                if blockwise {
+                    self.blockwise.set(Some(BlockwiseResponse {
+                        addr: request.addr,
+                        port: request.port,
+                        code,
+                        content_format,
+                        length: payload_length,
+                    }));
+                }
+                ReturnCode::SUCCESS
+            }
+            Err(rcode) => rcode,
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for Coap<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        if expired(self.respond_by.get(), now) {
+            self.respond_by.set(None);
+            if let Some(request) = self.serving.take() {
+                let _ = self.send_response(request, message::SERVICE_UNAVAILABLE, None, 0);
+            }
+        }
+        if expired(self.retransmit_at.get(), now) {
+            self.retransmit_at.set(None);
+            self.request_timeout();
+        }
+        self.update_alarm();
+    }
+}
+
+impl<A: time::Alarm<'a>> UDPSendClient for Coap<'a, A> {
+    fn send_done(&self, _result: ReturnCode, dgram: LeasableBuffer<'static, u8>) {
+        // Lost requests are retransmitted and lost responses are sent again
+        // when the peer repeats its request, so errors are not handled here.
+        match self.sending.take() {
+            Some(Outgoing::Request) => self.request_buffer.replace(dgram),
+            Some(Outgoing::Response) | None => self.response_buffer.replace(dgram),
+        };
+        self.send_next();
+    }
+}
+
+impl<A: time::Alarm<'a>> UDPRecvClient for Coap<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        match Message::parse(payload) {
+            Some(message) => {
+                if message.is_empty() {
+                    self.handle_empty(src_addr, src_port, &message);
+                } else if message.is_request() {
+                    self.handle_request(src_addr, src_port, &message);
+                } else if message.is_response() {
+                    self.handle_response(src_addr, src_port, &message);
+                } else if message.message_type == MessageType::Confirmable {
+                    // Reserved code classes
+                    self.send_empty(MessageType::Reset, message.message_id, src_addr, src_port);
+                }
+            }
+            None => {
+                if let Some((MessageType::Confirmable, message_id)) = Message::parse_header(payload)
+                {
+                    self.send_empty(MessageType::Reset, message_id, src_addr, src_port);
+                }
+            }
+        }
+    }
+}
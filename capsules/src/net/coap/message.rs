@@ -0,0 +1,548 @@
+//! Encoding and decoding of CoAP messages (RFC 7252).
+//!
+//! A message is a 4 byte header, a token of up to 8 bytes, a list of options
+//! sorted by option number and an optional payload behind a `0xFF` marker.
+//! `Message` parses a received datagram in place and `MessageWriter` builds
+//! one in a buffer. Options are delta encoded, so the writer only accepts
+//! them in ascending order.
+
+use core::cmp;
+use kernel::ReturnCode;
+
+pub const VERSION: u8 = 1;
+
+/// Longest token allowed by the protocol
+pub const MAX_TOKEN_LENGTH: usize = 8;
+
+const HEADER_LENGTH: usize = 4;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+// Method codes
+pub const GET: u8 = 0x01;
+pub const POST: u8 = 0x02;
+pub const PUT: u8 = 0x03;
+pub const DELETE: u8 = 0x04;
+
+// Response codes, written as class.detail in the RFC
+pub const CREATED: u8 = 0x41; // 2.01
+pub const DELETED: u8 = 0x42; // 2.02
+pub const CHANGED: u8 = 0x44; // 2.04
+pub const CONTENT: u8 = 0x45; // 2.05
+pub const CONTINUE: u8 = 0x5f; // 2.31
+pub const BAD_REQUEST: u8 = 0x80; // 4.00
+pub const BAD_OPTION: u8 = 0x82; // 4.02
+pub const NOT_FOUND: u8 = 0x84; // 4.04
+pub const METHOD_NOT_ALLOWED: u8 = 0x85; // 4.05
+pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d; // 4.13
+pub const INTERNAL_SERVER_ERROR: u8 = 0xa0; // 5.00
+pub const SERVICE_UNAVAILABLE: u8 = 0xa3; // 5.03
+
+// Option numbers
+pub const URI_HOST: u16 = 3;
+pub const URI_PORT: u16 = 7;
+pub const URI_PATH: u16 = 11;
+pub const CONTENT_FORMAT: u16 = 12;
+pub const URI_QUERY: u16 = 15;
+pub const ACCEPT: u16 = 17;
+pub const BLOCK2: u16 = 23;
+pub const BLOCK1: u16 = 27;
+
+/// Critical options that requests to this node may carry. Uri-Host and
+/// Uri-Port name the node itself and Accept is left to the application.
+const SUPPORTED_CRITICAL_OPTIONS: [u16; 6] = [URI_HOST, URI_PORT, URI_PATH, ACCEPT, BLOCK2, BLOCK1];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> MessageType {
+        match bits & 0b11 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// Returns the class of a code: 0 for requests, 2 to 5 for responses.
+pub fn code_class(code: u8) -> u8 {
+    code >> 5
+}
+
+/// The value of a Block1 or Block2 option (RFC 7959).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Number of the block within the payload
+    pub num: u32,
+    /// Whether more blocks follow
+    pub more: bool,
+    /// Block size exponent, the block size is `16 << szx` bytes.
+    pub szx: u8,
+}
+
+impl Block {
+    /// Largest block size exponent, for 1024 byte blocks
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn decode(value: u32) -> Option<Block> {
+        let szx = (value & 0x7) as u8;
+        if szx > Block::MAX_SZX || value >> 4 >= 1 << 20 {
+            return None;
+        }
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+
+    pub fn encode(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    pub fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Offset of the first byte of the block in the payload
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Reads an extended option delta or length.
+fn extended(nibble: u8, data: &[u8]) -> Option<(u16, usize)> {
+    match nibble {
+        13 => data.get(0).map(|&b| (b as u16 + 13, 1)),
+        14 => {
+            if data.len() < 2 {
+                return None;
+            }
+            let value = u16::from_be_bytes([data[0], data[1]]) as u32 + 269;
+            if value > u16::max_value() as u32 {
+                None
+            } else {
+                Some((value as u16, 2))
+            }
+        }
+        15 => None,
+        _ => Some((nibble as u16, 0)),
+    }
+}
+
+/// Decodes the option at the start of `data`, following the option numbered
+/// `number`. Returns the option number, its value and the remaining data.
+fn next_option(data: &[u8], number: u16) -> Option<(u16, &[u8], &[u8])> {
+    let first = *data.get(0)?;
+    let (delta, delta_bytes) = extended(first >> 4, &data[1..])?;
+    let (length, length_bytes) = extended(first & 0xf, &data[1 + delta_bytes..])?;
+    let start = 1 + delta_bytes + length_bytes;
+    let end = start + length as usize;
+    if end > data.len() {
+        return None;
+    }
+    let number = number.checked_add(delta)?;
+    Some((number, &data[start..end], &data[end..]))
+}
+
+/// Reads an unsigned integer option value.
+fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, &b| acc << 8 | b as u32))
+}
+
+/// Iterator over the options of a message, as option number and value
+pub struct Options<'b> {
+    data: &'b [u8],
+    number: u16,
+}
+
+impl Iterator for Options<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (number, value, rest) = next_option(self.data, self.number)?;
+        self.data = rest;
+        self.number = number;
+        Some((number, value))
+    }
+}
+
+/// A received message. The token, options and payload borrow from the
+/// datagram.
+pub struct Message<'b> {
+    pub message_type: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: &'b [u8],
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl Message<'b> {
+    /// Parses a message, or returns `None` if it is malformed.
+    pub fn parse(buf: &'b [u8]) -> Option<Message<'b>> {
+        if buf.len() < HEADER_LENGTH || buf[0] >> 6 != VERSION {
+            return None;
+        }
+        let token_length = (buf[0] & 0xf) as usize;
+        if token_length > MAX_TOKEN_LENGTH || buf.len() < HEADER_LENGTH + token_length {
+            return None;
+        }
+        let code = buf[1];
+        let options_start = HEADER_LENGTH + token_length;
+
+        // Find the end of the options, checking that they are well formed.
+        let mut rest = &buf[options_start..];
+        let mut number = 0;
+        while !rest.is_empty() && rest[0] != PAYLOAD_MARKER {
+            let (next, _, tail) = next_option(rest, number)?;
+            number = next;
+            rest = tail;
+        }
+        let options_end = buf.len() - rest.len();
+        let payload = if rest.is_empty() {
+            rest
+        } else if rest.len() == 1 {
+            // A marker has to be followed by a payload.
+            return None;
+        } else {
+            &rest[1..]
+        };
+        // Empty messages have nothing behind the message ID.
+        if code == 0 && buf.len() != HEADER_LENGTH {
+            return None;
+        }
+
+        Some(Message {
+            message_type: MessageType::from_bits(buf[0] >> 4),
+            code,
+            message_id: u16::from_be_bytes([buf[2], buf[3]]),
+            token: &buf[HEADER_LENGTH..options_start],
+            options: &buf[options_start..options_end],
+            payload,
+        })
+    }
+
+    /// Returns the type and message ID of a datagram too malformed to parse,
+    /// so that it can be rejected with a reset.
+    pub fn parse_header(buf: &[u8]) -> Option<(MessageType, u16)> {
+        if buf.len() < HEADER_LENGTH || buf[0] >> 6 != VERSION {
+            return None;
+        }
+        Some((
+            MessageType::from_bits(buf[0] >> 4),
+            u16::from_be_bytes([buf[2], buf[3]]),
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code == 0
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.code != 0 && code_class(self.code) == 0
+    }
+
+    pub fn is_response(&self) -> bool {
+        match code_class(self.code) {
+            2..=5 => true,
+            _ => false,
+        }
+    }
+
+    pub fn options(&self) -> Options<'b> {
+        Options {
+            data: self.options,
+            number: 0,
+        }
+    }
+
+    /// Returns the value of the first option numbered `number`.
+    pub fn option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options()
+            .find(|&(n, _)| n == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    pub fn content_format(&self) -> Option<u16> {
+        self.uint_option(CONTENT_FORMAT)
+            .filter(|&format| format <= u16::max_value() as u32)
+            .map(|format| format as u16)
+    }
+
+    pub fn block1(&self) -> Option<Block> {
+        self.uint_option(BLOCK1).and_then(Block::decode)
+    }
+
+    pub fn block2(&self) -> Option<Block> {
+        self.uint_option(BLOCK2).and_then(Block::decode)
+    }
+
+    /// Whether the message carries a critical option this node does not
+    /// implement. Requests with one are answered with 4.02 Bad Option.
+    pub fn has_unsupported_critical_option(&self) -> bool {
+        self.options()
+            .any(|(n, _)| n & 1 == 1 && !SUPPORTED_CRITICAL_OPTIONS.contains(&n))
+    }
+
+    fn path_segments(&self) -> impl Iterator<Item = &'b [u8]> {
+        self.options()
+            .filter(|&(n, _)| n == URI_PATH)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the Uri-Path options of the message spell `path`, a list of
+    /// segments separated by `/`.
+    pub fn path_matches(&self, path: &[u8]) -> bool {
+        let mut segments = path.split(|&b| b == b'/').filter(|s| !s.is_empty());
+        let mut options = self.path_segments();
+        loop {
+            match (segments.next(), options.next()) {
+                (None, None) => return true,
+                (Some(segment), Some(option)) if segment == option => {}
+                _ => return false,
+            }
+        }
+    }
+
+    /// Writes the path of the message to `buf`, with segments separated by
+    /// `/`. Returns its length, or `None` if it does not fit.
+    pub fn copy_path(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut length = 0;
+        for (i, segment) in self.path_segments().enumerate() {
+            if i > 0 {
+                *buf.get_mut(length)? = b'/';
+                length += 1;
+            }
+            buf.get_mut(length..length + segment.len())?
+                .copy_from_slice(segment);
+            length += segment.len();
+        }
+        Some(length)
+    }
+}
+
+/// Splits a URI into its path and query, which follows a `?`.
+pub fn split_uri(uri: &[u8]) -> (&[u8], &[u8]) {
+    match uri.iter().position(|&b| b == b'?') {
+        Some(i) => (&uri[..i], &uri[i + 1..]),
+        None => (uri, &[]),
+    }
+}
+
+/// Writes a message into a buffer. The header is written by `new()`, then
+/// options in ascending order, then the payload.
+pub struct MessageWriter<'b> {
+    buf: &'b mut [u8],
+    length: usize,
+    last_option: u16,
+}
+
+impl MessageWriter<'b> {
+    pub fn new(
+        buf: &'b mut [u8],
+        message_type: MessageType,
+        code: u8,
+        message_id: u16,
+        token: &[u8],
+    ) -> Result<MessageWriter<'b>, ReturnCode> {
+        let length = HEADER_LENGTH + token.len();
+        if token.len() > MAX_TOKEN_LENGTH || buf.len() < length {
+            return Err(ReturnCode::ESIZE);
+        }
+        buf[0] = VERSION << 6 | (message_type as u8) << 4 | token.len() as u8;
+        buf[1] = code;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[HEADER_LENGTH..length].copy_from_slice(token);
+        Ok(MessageWriter {
+            buf,
+            length,
+            last_option: 0,
+        })
+    }
+
+    /// Returns the nibble for an option delta or length and writes its
+    /// extended bytes at `at`.
+    fn extended(&mut self, value: u16, at: usize) -> Result<(u8, usize), ReturnCode> {
+        let (nibble, bytes) = if value < 13 {
+            return Ok((value as u8, 0));
+        } else if value < 269 {
+            (13, 1)
+        } else {
+            (14, 2)
+        };
+        let ext = self.buf.get_mut(at..at + bytes).ok_or(ReturnCode::ESIZE)?;
+        if bytes == 1 {
+            ext[0] = (value - 13) as u8;
+        } else {
+            ext.copy_from_slice(&(value - 269).to_be_bytes());
+        }
+        Ok((nibble, bytes))
+    }
+
+    pub fn option(&mut self, number: u16, value: &[u8]) -> Result<(), ReturnCode> {
+        if number < self.last_option || value.len() > u16::max_value() as usize {
+            return Err(ReturnCode::EINVAL);
+        }
+        let start = self.length;
+        if start >= self.buf.len() {
+            return Err(ReturnCode::ESIZE);
+        }
+        let (delta, delta_bytes) = self.extended(number - self.last_option, start + 1)?;
+        let (length, length_bytes) = self.extended(value.len() as u16, start + 1 + delta_bytes)?;
+        let value_start = start + 1 + delta_bytes + length_bytes;
+        self.buf
+            .get_mut(value_start..value_start + value.len())
+            .ok_or(ReturnCode::ESIZE)?
+            .copy_from_slice(value);
+        self.buf[start] = delta << 4 | length;
+        self.length = value_start + value.len();
+        self.last_option = number;
+        Ok(())
+    }
+
+    /// Writes an unsigned integer option in as few bytes as possible.
+    pub fn uint_option(&mut self, number: u16, value: u32) -> Result<(), ReturnCode> {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..])
+    }
+
+    /// Writes a Uri-Path option for every segment of `path`.
+    pub fn uri_path(&mut self, path: &[u8]) -> Result<(), ReturnCode> {
+        for segment in path.split(|&b| b == b'/').filter(|s| !s.is_empty()) {
+            self.option(URI_PATH, segment)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a Uri-Query option for every argument of `query`, which are
+    /// separated by `&`.
+    pub fn uri_query(&mut self, query: &[u8]) -> Result<(), ReturnCode> {
+        for argument in query.split(|&b| b == b'&').filter(|s| !s.is_empty()) {
+            self.option(URI_QUERY, argument)?;
+        }
+        Ok(())
+    }
+
+    /// Space left for the payload
+    pub fn payload_buffer(&mut self) -> &mut [u8] {
+        let start = cmp::min(self.length + 1, self.buf.len());
+        &mut self.buf[start..]
+    }
+
+    /// Ends the message with a payload of `payload_length` bytes, written to
+    /// `payload_buffer()`. Returns the length of the message.
+    pub fn finish(self, payload_length: usize) -> usize {
+        if payload_length == 0 {
+            self.length
+        } else {
+            self.buf[self.length] = PAYLOAD_MARKER;
+            self.length + 1 + payload_length
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_parse() {
+        let mut buf = [0; 64];
+        let mut writer = MessageWriter::new(
+            &mut buf,
+            MessageType::Confirmable,
+            POST,
+            0x1234,
+            &[1, 2, 3, 4],
+        )
+        .unwrap();
+        writer.uri_path(b"/sensors/temp").unwrap();
+        writer.uint_option(CONTENT_FORMAT, 50).unwrap();
+        writer.uri_query(b"unit=c").unwrap();
+        let block = Block {
+            num: 3,
+            more: true,
+            szx: 2,
+        };
+        writer.uint_option(BLOCK1, block.encode()).unwrap();
+        // Long enough for extended deltas and lengths
+        writer.option(300, &[0xaa; 20]).unwrap();
+        assert_eq!(writer.option(BLOCK2, &[]), Err(ReturnCode::EINVAL));
+        writer.payload_buffer()[..2].copy_from_slice(b"hi");
+        let length = writer.finish(2);
+
+        let message = Message::parse(&buf[..length]).unwrap();
+        assert_eq!(message.message_type, MessageType::Confirmable);
+        assert_eq!(message.code, POST);
+        assert_eq!(message.message_id, 0x1234);
+        assert_eq!(message.token, &[1, 2, 3, 4]);
+        assert!(message.is_request());
+        assert!(message.path_matches(b"sensors/temp"));
+        assert!(!message.path_matches(b"sensors"));
+        assert!(!message.path_matches(b"sensors/temp/x"));
+        let mut path = [0; 16];
+        assert_eq!(message.copy_path(&mut path), Some(12));
+        assert_eq!(&path[..12], b"sensors/temp");
+        assert_eq!(message.copy_path(&mut path[..4]), None);
+        assert_eq!(message.content_format(), Some(50));
+        assert_eq!(message.option(URI_QUERY), Some(&b"unit=c"[..]));
+        assert_eq!(message.block1(), Some(block));
+        assert_eq!(message.block2(), None);
+        assert_eq!(message.option(300), Some(&[0xaa; 20][..]));
+        assert!(message.has_unsupported_critical_option());
+        assert_eq!(message.payload, b"hi");
+    }
+
+    #[test]
+    fn reject_malformed() {
+        // Version 2
+        assert!(Message::parse(&[0x80, GET, 0, 1]).is_none());
+        // Token longer than 8 bytes
+        assert!(Message::parse(&[0x49, GET, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        // Option value past the end
+        assert!(Message::parse(&[0x40, GET, 0, 1, 0xb4, b'a']).is_none());
+        // Payload marker without payload
+        assert!(Message::parse(&[0x40, GET, 0, 1, 0xff]).is_none());
+        // Empty message with a token
+        assert!(Message::parse(&[0x61, 0, 0, 1, 7]).is_none());
+        assert!(Message::parse(&[0x60, 0, 0, 1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn block_values() {
+        let block = Block::decode(0x2a).unwrap();
+        assert_eq!(
+            block,
+            Block {
+                num: 2,
+                more: true,
+                szx: 2
+            }
+        );
+        assert_eq!(block.size(), 64);
+        assert_eq!(block.offset(), 128);
+        assert_eq!(block.encode(), 0x2a);
+        assert!(Block::decode(0x7).is_none());
+    }
+
+    #[test]
+    fn uri_parts() {
+        assert_eq!(split_uri(b"a/b?x=1&y"), (&b"a/b"[..], &b"x=1&y"[..]));
+        assert_eq!(split_uri(b"a/b"), (&b"a/b"[..], &b""[..]));
+    }
+}
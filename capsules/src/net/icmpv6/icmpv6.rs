@@ -19,10 +19,25 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    Type134 {
+        hop_limit: u8,
+        flags: u8,
+        lifetime: u16,
+    },
 }
 
 #[derive(Copy, Clone)]
@@ -31,6 +46,7 @@ pub enum ICMP6Type {
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type134, // Router Advertisement
 }
 
 impl ICMP6Header {
@@ -40,6 +56,11 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                hop_limit: 0,
+                flags: 0,
+                lifetime: 0,
+            },
         };
 
         ICMP6Header {
@@ -56,6 +77,11 @@ impl ICMP6Header {
             ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
             ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
             ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
+            ICMP6Type::Type134 => self.set_options(ICMP6HeaderOptions::Type134 {
+                hop_limit: 0,
+                flags: 0,
+                lifetime: 0,
+            }),
         }
     }
 
@@ -81,6 +107,7 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
         }
     }
 
@@ -90,6 +117,7 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type134 => 134,
         }
     }
 
@@ -140,6 +168,15 @@ impl ICMP6Header {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type134 {
+                hop_limit,
+                flags,
+                lifetime,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, lifetime);
+            }
         }
 
         stream_done!(off, off);
@@ -163,6 +200,7 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            134 => ICMP6Type::Type134,
             _ => return SResult::Error(()),
         };
 
@@ -171,34 +209,40 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
-
-        match icmp_type {
-            ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+        icmp_header.set_cksum(cksum);
+
+        // The stream decoders already return fields in host byte order, the
+        // order in which `encode` expects them
+        let off = match icmp_type {
+            ICMP6Type::Type1 | ICMP6Type::Type3 => {
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(match icmp_type {
+                    ICMP6Type::Type1 => ICMP6HeaderOptions::Type1 { unused },
+                    _ => ICMP6HeaderOptions::Type3 { unused },
+                });
+                off
             }
-            ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+            ICMP6Type::Type128 | ICMP6Type::Type129 => {
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                icmp_header.set_options(match icmp_type {
+                    ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id, seqno },
+                    _ => ICMP6HeaderOptions::Type129 { id, seqno },
+                });
+                off
             }
-            ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+            ICMP6Type::Type134 => {
+                let (off, hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, lifetime) = dec_try!(buf, off; decode_u16);
+                icmp_header.set_options(ICMP6HeaderOptions::Type134 {
+                    hop_limit,
+                    flags,
+                    lifetime,
+                });
+                off
             }
-            ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
-            }
-        }
+        };
 
         stream_done!(off, icmp_header);
     }
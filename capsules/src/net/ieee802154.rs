@@ -725,7 +725,9 @@ impl Header<'a> {
 
         let mut has_payload_ies = false;
         if ie_present {
-            loop {
+            // The termination IE can be omitted when nothing follows the
+            // header IEs
+            while off < buf.len() {
                 let (next_off, ie) = dec_try!(buf, off; HeaderIE::decode);
                 off = next_off;
                 match ie {
@@ -749,7 +751,8 @@ impl Header<'a> {
         let mac_payload_off = off;
         let unencrypted = unsecured || !security_enabled;
         if has_payload_ies && unencrypted {
-            loop {
+            // Likewise, payload IEs can extend to the end of the frame
+            while off < buf.len() {
                 let (next_off, ie) = dec_try!(buf, off; PayloadIE::decode);
                 off = next_off;
                 match ie {
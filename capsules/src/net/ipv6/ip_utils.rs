@@ -111,6 +111,37 @@ impl IPAddr {
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
+
+    /// Returns whether the first `prefix_len` bits of the address are those
+    /// of `prefix`.
+    pub fn has_prefix(&self, prefix: &[u8], prefix_len: u8) -> bool {
+        let full_bytes = (prefix_len / 8) as usize;
+        let remaining = (prefix_len & 0x7) as usize;
+        if self.0[0..full_bytes] != prefix[0..full_bytes] {
+            return false;
+        }
+        if remaining == 0 {
+            return true;
+        }
+        let mask = (0xff as u8) << (8 - remaining);
+        (self.0[full_bytes] ^ prefix[full_bytes]) & mask == 0
+    }
+
+    /// Returns the MAC address the interface identifier of this address
+    /// was generated from, the inverse of `generate_from_mac`. Multicast
+    /// addresses map to the broadcast address.
+    pub fn get_mac(&self) -> MacAddress {
+        if self.is_multicast() {
+            MacAddress::Short(0xffff)
+        } else if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0b00000010;
+            MacAddress::Long(long_addr)
+        }
+    }
 }
 
 pub fn compute_udp_checksum(
@@ -205,6 +236,14 @@ pub fn compute_icmp_checksum(
             sum += id as u32;
             sum += seqno as u32;
         }
+        ICMP6HeaderOptions::Type134 {
+            hop_limit,
+            flags,
+            lifetime,
+        } => {
+            sum += (hop_limit as u32) << 8 | flags as u32;
+            sum += lifetime as u32;
+        }
     }
 
     // add icmp payload
@@ -508,7 +508,55 @@ impl IP6Packet<'a> {
         self.header.set_payload_len(payload_len);
     }
 
-    // TODO: Do we need a decode equivalent? I don't think so, but we might
+    /// This function fills the packet from a serialized IPv6 packet, as
+    /// when forwarding a packet received on another interface. Only UDP and
+    /// ICMPv6 payloads are supported. The transport checksum is kept as it
+    /// is rather than recomputed.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The serialized IPv6 packet
+    ///
+    /// # Return Value
+    ///
+    /// `ReturnCode` - `EINVAL` if the packet is malformed, `ENOSUPPORT` for
+    /// other payloads and `ESIZE` if the payload does not fit
+    pub fn decode(&mut self, buf: &[u8]) -> ReturnCode {
+        let header = match IP6Header::decode(buf).done() {
+            Some((_, header)) => header,
+            None => return ReturnCode::EINVAL,
+        };
+        let payload_len = header.get_payload_len() as usize;
+        if buf.len() < 40 + payload_len || payload_len < UDP_HDR_LEN {
+            return ReturnCode::EINVAL;
+        }
+        let transport = &buf[40..40 + payload_len];
+        let transport_header = match header.get_next_header() {
+            ip6_nh::UDP => match UDPHeader::decode(transport).done() {
+                Some((_, udp_header)) if udp_header.get_len() as usize == payload_len => {
+                    TransportHeader::UDP(udp_header)
+                }
+                _ => return ReturnCode::EINVAL,
+            },
+            ip6_nh::ICMP => match ICMP6Header::decode(transport).done() {
+                Some((_, mut icmp_header)) => {
+                    icmp_header.set_len(payload_len as u16);
+                    TransportHeader::ICMP(icmp_header)
+                }
+                None => return ReturnCode::ENOSUPPORT,
+            },
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        // Both supported transport headers are 8 bytes long
+        let data = &transport[UDP_HDR_LEN..];
+        if data.len() > self.payload.payload.len() {
+            return ReturnCode::ESIZE;
+        }
+        self.payload.payload[..data.len()].copy_from_slice(data);
+        self.payload.header = transport_header;
+        self.header = header;
+        ReturnCode::SUCCESS
+    }
 
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
         let ip6_header = self.header;
@@ -5,7 +5,10 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. Each `IP6SendStruct` carries one
+//! datagram at a time and several of them share a radio through a
+//! `MuxIP6Sender`, which transmits their fragments interleaved by priority
+//! and in round robin between senders of the same priority.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -17,6 +20,7 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
+use crate::ieee802154::framer::Frame;
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
@@ -25,10 +29,15 @@ use crate::net::sixlowpan::sixlowpan_state::TxState;
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::hil::time::{self, Frequency};
 use kernel::ReturnCode;
 
+/// Minimum number of tics ahead of the counter the alarm that starts a
+/// transmission is set, so that the counter cannot pass it before it is armed.
+const MIN_DT: u32 = 2;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -88,23 +97,256 @@ pub trait IP6Sender<'a> {
     ) -> ReturnCode;
 }
 
+/// Transmit priority of an `IP6SendStruct`. Before every fragment the
+/// `MuxIP6Sender` picks a datagram of the highest priority that has one
+/// pending, so control traffic is not held up behind a large fragmented
+/// datagram of lower priority.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TxPriority {
+    Low,
+    Normal,
+    High,
+}
+
+/// Counters kept by the `MuxIP6Sender` about its transmit queue.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TxQueueStats {
+    /// Datagrams waiting for or in the middle of fragmentation
+    pub queued: usize,
+    /// Highest value `queued` has reached
+    pub max_queued: usize,
+    /// Datagrams whose fragments were all transmitted
+    pub sent: u32,
+    /// Datagrams refused because their sender was busy, or abandoned
+    /// because a fragment could not be built or transmitted
+    pub dropped: u32,
+    /// Frames handed to the radio
+    pub fragments: u32,
+}
+
+/// Reports the counters of a transmit queue, without naming the alarm type
+/// of the `MuxIP6Sender`, so that the process console can print them.
+pub trait TxQueue {
+    /// Returns the queue depth and drop counters.
+    fn stats(&self) -> TxQueueStats;
+}
+
+/// Chooses the sender to transmit the next fragment from. `pending` yields
+/// the priority of the datagram of every sender in list order, or `None` for
+/// senders with nothing to send, and `last` is the index of the sender served
+/// before. The highest priority wins; among equal priorities the first sender
+/// after `last` wins, wrapping around, so datagrams of the same priority take
+/// turns fragment by fragment.
+fn select_next<I: Iterator<Item = Option<TxPriority>>>(
+    pending: I,
+    last: Option<usize>,
+) -> Option<usize> {
+    let mut best: Option<(TxPriority, bool, usize)> = None;
+    for (index, priority) in pending.enumerate() {
+        if let Some(priority) = priority {
+            let after_last = last.map_or(true, |last| index > last);
+            let better = best.map_or(true, |(best_priority, best_after_last, _)| {
+                (priority, after_last) > (best_priority, best_after_last)
+            });
+            if better {
+                best = Some((priority, after_last, index));
+            }
+        }
+    }
+    best.map(|(_, _, index)| index)
+}
+
+/// Shares one `MacDevice` among several `IP6SendStruct`s. Every sender holds
+/// the state of one datagram; the mux owns the frame buffer and sends one
+/// fragment at a time, choosing the sender of each fragment anew, so that
+/// fragments of different datagrams are interleaved.
+pub struct MuxIP6Sender<'a, A: time::Alarm<'a>> {
+    senders: List<'a, IP6SendStruct<'a, A>>,
+    alarm: &'a A, // Alarm so we can introduce a small delay between fragments to ensure
+    // successful reception on receivers with slow copies out of the radio buffer
+    // (imix)
+    radio: &'a dyn MacDevice<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    // Sender of the frame currently owned by the radio
+    current: OptionalCell<&'a IP6SendStruct<'a, A>>,
+    // Index of the sender served last, for round robin
+    last: Cell<Option<usize>>,
+    // Set while a frame is in flight or the alarm is armed
+    running: Cell<bool>,
+    stats: Cell<TxQueueStats>,
+}
+
+impl<A: time::Alarm<'a>> MuxIP6Sender<'a, A> {
+    pub fn new(
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+        radio: &'a dyn MacDevice<'a>,
+    ) -> MuxIP6Sender<'a, A> {
+        MuxIP6Sender {
+            senders: List::new(),
+            alarm: alarm,
+            radio: radio,
+            tx_buf: TakeCell::new(tx_buf),
+            current: OptionalCell::empty(),
+            last: Cell::new(None),
+            running: Cell::new(false),
+            stats: Cell::new(TxQueueStats::default()),
+        }
+    }
+
+    pub fn add_sender(&self, sender: &'a IP6SendStruct<'a, A>) {
+        self.senders.push_tail(sender);
+    }
+
+    fn update_stats<F: FnOnce(&mut TxQueueStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    fn enqueue(&self) {
+        self.update_stats(|stats| {
+            stats.queued += 1;
+            stats.max_queued = core::cmp::max(stats.max_queued, stats.queued);
+        });
+        // Start from the alarm rather than from here so that the sender's
+        // client never sees `send_done` before `send_to` has returned
+        if !self.running.get() {
+            self.running.set(true);
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(MIN_DT));
+        }
+    }
+
+    fn datagram_done(&self, sender: &'a IP6SendStruct<'a, A>, result: ReturnCode) {
+        self.update_stats(|stats| {
+            stats.queued -= 1;
+            if result == ReturnCode::SUCCESS {
+                stats.sent += 1;
+            } else {
+                stats.dropped += 1;
+            }
+        });
+        sender.send_completed(result);
+    }
+
+    fn transmit_next(&self) {
+        loop {
+            let pending = self.senders.iter().map(|sender| {
+                if sender.pending.get() {
+                    Some(sender.priority.get())
+                } else {
+                    None
+                }
+            });
+            let index = match select_next(pending, self.last.get()) {
+                Some(index) => index,
+                None => {
+                    self.running.set(false);
+                    return;
+                }
+            };
+            let sender = match self.senders.iter().nth(index) {
+                Some(sender) => sender,
+                None => {
+                    self.running.set(false);
+                    return;
+                }
+            };
+            self.last.set(Some(index));
+
+            let tx_buf = match self.tx_buf.take() {
+                Some(tx_buf) => tx_buf,
+                None => {
+                    debug!("Missing tx_buf");
+                    self.running.set(false);
+                    return;
+                }
+            };
+            match sender.next_fragment(tx_buf) {
+                Ok((false, frame)) => {
+                    let (result, buf) = self.radio.transmit(frame);
+                    if result == ReturnCode::SUCCESS {
+                        self.update_stats(|stats| stats.fragments += 1);
+                        self.current.set(sender);
+                        return;
+                    }
+                    buf.map(|buf| self.tx_buf.replace(buf));
+                    self.datagram_done(sender, result);
+                }
+                Ok((true, frame)) => {
+                    self.tx_buf.replace(frame.into_buf());
+                    self.datagram_done(sender, ReturnCode::SUCCESS);
+                }
+                Err((result, buf)) => {
+                    self.tx_buf.replace(buf);
+                    self.datagram_done(sender, result);
+                }
+            }
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> TxQueue for MuxIP6Sender<'a, A> {
+    fn stats(&self) -> TxQueueStats {
+        self.stats.get()
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for MuxIP6Sender<'a, A> {
+    fn fired(&self) {
+        self.transmit_next();
+    }
+}
+
+impl<A: time::Alarm<'a>> TxClient for MuxIP6Sender<'a, A> {
+    fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.tx_buf.replace(tx_buf);
+        if result != ReturnCode::SUCCESS {
+            debug!("Send Failed: {:?}, acked: {}", result, acked);
+            self.current
+                .take()
+                .map(|sender| self.datagram_done(sender, result));
+        } else {
+            self.current.clear();
+        }
+        // Below code adds delay between fragments. Despite some efforts
+        // to fix this bug, I find that without it the receiving imix cannot
+        // receive more than 2 fragments in a single packet without hanging
+        // waiting for the third fragments.
+        // Specifically, here we set a timer, which fires and sends the next fragment
+        // One flaw with this is that we also introduce a delay after sending the last
+        // fragment, before passing the send_done callback back to the client. This
+        // could be optimized by checking if it is the last fragment before setting the timer.
+        let interval = (100000 as u32) * <A::Frequency>::frequency() / 1000000;
+        let tics = self.alarm.now().wrapping_add(interval);
+        self.alarm.set_alarm(tics);
+    }
+}
+
 /// This struct is a specific implementation of the `IP6Sender` trait. This
-/// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
+/// struct sends the packet using 6LoWPAN over the `MacDevice` of a
+/// `MuxIP6Sender`. It holds a single datagram at a time; a layer that wants
+/// several datagrams in flight uses several of them.
 pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     // We want the ip6_packet field to be a TakeCell so that it is easy to mutate
     ip6_packet: TakeCell<'static, IP6Packet<'static>>,
-    alarm: &'a A, // Alarm so we can introduce a small delay between fragments to ensure
-    // successful reception on receivers with slow copies out of the radio buffer
-    // (imix)
     src_addr: Cell<IPAddr>,
     gateway: Cell<MacAddress>,
-    tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
-    radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
+    mux: &'a MuxIP6Sender<'a, A>,
     src_mac_addr: MacAddress,
+    priority: Cell<TxPriority>,
+    // Set from `send_to` until the datagram is sent or dropped
+    pending: Cell<bool>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
+    next: ListLink<'a, IP6SendStruct<'a, A>>,
+}
+
+impl<A: time::Alarm<'a>> ListNode<'a, IP6SendStruct<'a, A>> for IP6SendStruct<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6SendStruct<'a, A>> {
+        &self.next
+    }
 }
 
 impl<A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
@@ -135,44 +377,92 @@ impl<A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
-        self.sixlowpan.init(
+        if self.pending.get() {
+            self.mux.update_stats(|stats| stats.dropped += 1);
+            return ReturnCode::EBUSY;
+        }
+        let ret = self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
+            self.gateway.get(),
+            self.mux.radio.get_pan(),
             None,
         );
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
         self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        self.pending.set(true);
+        self.mux.enqueue();
+        ReturnCode::SUCCESS
     }
 }
 
 impl<A: time::Alarm<'a>> IP6SendStruct<'a, A> {
     pub fn new(
         ip6_packet: &'static mut IP6Packet<'static>,
-        alarm: &'a A,
-        tx_buf: &'static mut [u8],
         sixlowpan: TxState<'a>,
-        radio: &'a dyn MacDevice<'a>,
+        mux: &'a MuxIP6Sender<'a, A>,
         dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
         ip_vis: &'static IpVisibilityCapability,
     ) -> IP6SendStruct<'a, A> {
         IP6SendStruct {
             ip6_packet: TakeCell::new(ip6_packet),
-            alarm: alarm,
             src_addr: Cell::new(IPAddr::new()),
             gateway: Cell::new(dst_mac_addr),
-            tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
-            radio: radio,
-            dst_mac_addr: dst_mac_addr,
+            mux: mux,
             src_mac_addr: src_mac_addr,
+            priority: Cell::new(TxPriority::Normal),
+            pending: Cell::new(false),
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
+            next: ListLink::empty(),
         }
     }
 
+    /// Sets the priority of the datagrams sent through this sender. The
+    /// default is `TxPriority::Normal`.
+    pub fn set_priority(&self, priority: TxPriority) {
+        self.priority.set(priority);
+    }
+
+    /// Sends a complete IPv6 packet received on another interface to
+    /// `dst_mac_addr`, decrementing its hop limit. The packet is copied, so
+    /// `packet` can be reused as soon as this returns. Returns `EINVAL` if
+    /// the hop limit is exhausted, and the errors of `IP6Packet::decode`.
+    pub fn forward(&self, packet: &[u8], dst_mac_addr: MacAddress) -> ReturnCode {
+        if self.pending.get() {
+            self.mux.update_stats(|stats| stats.dropped += 1);
+            return ReturnCode::EBUSY;
+        }
+        let ret = self.ip6_packet.map_or(ReturnCode::ENOMEM, |ip6_packet| {
+            match ip6_packet.decode(packet) {
+                ReturnCode::SUCCESS if ip6_packet.header.hop_limit > 1 => {
+                    ip6_packet.header.hop_limit -= 1;
+                    ReturnCode::SUCCESS
+                }
+                ReturnCode::SUCCESS => ReturnCode::EINVAL,
+                error => error,
+            }
+        });
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        let ret = self.sixlowpan.init(
+            self.src_mac_addr,
+            dst_mac_addr,
+            self.mux.radio.get_pan(),
+            None,
+        );
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        self.pending.set(true);
+        self.mux.enqueue();
+        ReturnCode::SUCCESS
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
@@ -193,87 +483,67 @@ impl<A: time::Alarm<'a>> IP6SendStruct<'a, A> {
         );
     }
 
-    // Returns EBUSY if the tx_buf is not there
-    fn send_next_fragment(&self) -> ReturnCode {
-        // Originally send_complete() was called within the below closure.
-        // However, this led to a race condition where when multiple apps transmitted
-        // simultaneously, it was possible for send_complete to trigger another
-        // transmission before the below closure would exit, leading to this function
-        // being called again by another app before ip6_packet is replaced.
-        // To fix this, we pass a bool out of the closure to indicate whether send_completed()
-        // should be called once the closure exits
-        let (ret, call_send_complete) = self
-            .ip6_packet
-            .map(move |ip6_packet| match self.tx_buf.take() {
-                Some(tx_buf) => {
-                    let next_frame = self.sixlowpan.next_fragment(ip6_packet, tx_buf, self.radio);
-                    match next_frame {
-                        Ok((is_done, frame)) => {
-                            if is_done {
-                                self.tx_buf.replace(frame.into_buf());
-                                //self.send_completed(ReturnCode::SUCCESS);
-                                (ReturnCode::SUCCESS, true)
-                            } else {
-                                let (err, _frame_option) = self.radio.transmit(frame);
-                                (err, false)
-                            }
-                        }
-                        Err((retcode, buf)) => {
-                            self.tx_buf.replace(buf);
-                            //self.send_completed(retcode);
-                            (retcode, true)
-                        }
-                    }
-                }
-                None => {
-                    debug!("Missing tx_buf");
-                    (ReturnCode::EBUSY, false)
-                }
-            })
-            .unwrap_or((ReturnCode::ENOMEM, false));
-        if call_send_complete {
-            self.send_completed(ret);
-            return ReturnCode::SUCCESS;
+    fn next_fragment(
+        &self,
+        tx_buf: &'static mut [u8],
+    ) -> Result<(bool, Frame), (ReturnCode, &'static mut [u8])> {
+        match self.ip6_packet.take() {
+            Some(ip6_packet) => {
+                let next_frame = self
+                    .sixlowpan
+                    .next_fragment(ip6_packet, tx_buf, self.mux.radio);
+                self.ip6_packet.replace(ip6_packet);
+                next_frame
+            }
+            None => Err((ReturnCode::ENOMEM, tx_buf)),
         }
-        ret
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        self.pending.set(false);
+        if result != ReturnCode::SUCCESS {
+            self.sixlowpan.abort();
+        }
         self.client.map(move |client| {
             client.send_done(result);
         });
     }
 }
 
-impl<A: time::Alarm<'a>> time::AlarmClient for IP6SendStruct<'a, A> {
-    fn fired(&self) {
-        let result = self.send_next_fragment();
-        if result != ReturnCode::SUCCESS {
-            self.send_completed(result);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(pending: &[Option<TxPriority>], last: Option<usize>) -> Option<usize> {
+        select_next(pending.iter().cloned(), last)
     }
-}
 
-impl<A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
-    fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.tx_buf.replace(tx_buf);
-        if result != ReturnCode::SUCCESS {
-            debug!("Send Failed: {:?}, acked: {}", result, acked);
-            self.client.map(move |client| {
-                client.send_done(result);
-            });
-        } else {
-            // Below code adds delay between fragments. Despite some efforts
-            // to fix this bug, I find that without it the receiving imix cannot
-            // receive more than 2 fragments in a single packet without hanging
-            // waiting for the third fragments.
-            // Specifically, here we set a timer, which fires and sends the next fragment
-            // One flaw with this is that we also introduce a delay after sending the last
-            // fragment, before passing the send_done callback back to the client. This
-            // could be optimized by checking if it is the last fragment before setting the timer.
-            let interval = (100000 as u32) * <A::Frequency>::frequency() / 1000000;
-            let tics = self.alarm.now().wrapping_add(interval);
-            self.alarm.set_alarm(tics);
-        }
+    #[test]
+    fn round_robin_within_priority() {
+        let pending = [
+            Some(TxPriority::Normal),
+            None,
+            Some(TxPriority::Normal),
+            Some(TxPriority::Normal),
+        ];
+        assert_eq!(select(&pending, None), Some(0));
+        assert_eq!(select(&pending, Some(0)), Some(2));
+        assert_eq!(select(&pending, Some(2)), Some(3));
+        assert_eq!(select(&pending, Some(3)), Some(0));
+        assert_eq!(select(&[None, None], Some(1)), None);
+    }
+
+    #[test]
+    fn higher_priority_first() {
+        let pending = [
+            Some(TxPriority::Low),
+            Some(TxPriority::High),
+            Some(TxPriority::Normal),
+            Some(TxPriority::High),
+        ];
+        assert_eq!(select(&pending, Some(0)), Some(1));
+        assert_eq!(select(&pending, Some(1)), Some(3));
+        assert_eq!(select(&pending, Some(3)), Some(1));
+        assert_eq!(select(&pending[..1], Some(0)), Some(0));
     }
 }
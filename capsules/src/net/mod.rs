@@ -1,5 +1,8 @@
 //! Modules for IPv6 over 6LoWPAN stack
 
+pub mod app_update;
+pub mod border_router;
+pub mod coap;
 pub mod frag_utils;
 pub mod sixlowpan;
 pub mod util;
@@ -9,6 +12,7 @@ pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
 pub mod network_capabilities;
+pub mod slip;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
@@ -195,16 +195,18 @@
 // increased the complexity of this layer substantially, and further,
 // necessitated additional initialization complexity by the upper layer.
 //
-// Single TxState:
+// Multiple TxStates:
 // Although both the RxState and TxState structs are treated similarly by
 // the Sixlowpan layer, many aspects of their control flow differ
-// significantly. The final design decision was to have a single upper layer
-// that serialized (or virtualized) both the reception and transmission of
-// IPv6 packets. As a result, only a single outstanding transmission made
-// sense, and thus the layer was designed to have a serial transmit path.
-// Note that this differs greatly from the RxState model, but since we
-// cannot serialize reception in the same way, it did not make sense to treat
-// both RxState and TxState structs identically.
+// significantly. Originally a single upper layer serialized all outgoing
+// IPv6 packets through one TxState, so that one large fragmented datagram
+// held up every other packet until its last fragment was sent. A TxState
+// now holds only the compression state of one packet, and the upper layer
+// (`ipv6_send::MuxIP6Sender`) keeps one per sender and chooses before every
+// fragment which TxState to take the next frame from. Because fragments of
+// different datagrams may then be interleaved, every TxState draws its own
+// datagram tag from the shared `SixlowpanState`, and receivers reassemble
+// them in separate RxStates as they would for packets from different nodes.
 //
 // TODOs and Known Issues
 // ----------------------------------
@@ -446,6 +448,12 @@ impl TxState<'a> {
         }
     }
 
+    /// Abandons the packet being sent, so that `init` can start a new one.
+    /// Called by the upper layer when a fragment could not be built or sent.
+    pub fn abort(&self) {
+        self.end_transmit();
+    }
+
     fn is_transmit_done(&self) -> bool {
         self.dgram_size.get() as usize <= self.dgram_offset.get()
     }
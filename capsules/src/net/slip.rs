@@ -0,0 +1,133 @@
+//! Serial Line Internet Protocol framing (RFC 1055).
+//!
+//! SLIP delimits packets on a byte stream with `END` bytes, and escapes
+//! `END` and `ESC` bytes within a packet. `encode` frames a packet into a
+//! buffer and `SlipDecoder` reassembles packets from received bytes.
+
+pub const END: u8 = 0xc0;
+pub const ESC: u8 = 0xdb;
+pub const ESC_END: u8 = 0xdc;
+pub const ESC_ESC: u8 = 0xdd;
+
+/// Writes `packet` to `buf` as a SLIP frame. The frame starts with an `END`
+/// byte as well, so that line noise received before it is discarded by the
+/// peer as an invalid packet. Returns the length of the frame, or `None` if
+/// it does not fit in `buf`.
+pub fn encode<I: IntoIterator<Item = u8>>(packet: I, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    push(buf, &mut len, END)?;
+    for byte in packet {
+        match byte {
+            END => {
+                push(buf, &mut len, ESC)?;
+                push(buf, &mut len, ESC_END)?;
+            }
+            ESC => {
+                push(buf, &mut len, ESC)?;
+                push(buf, &mut len, ESC_ESC)?;
+            }
+            _ => push(buf, &mut len, byte)?,
+        }
+    }
+    push(buf, &mut len, END)?;
+    Some(len)
+}
+
+fn push(buf: &mut [u8], len: &mut usize, byte: u8) -> Option<()> {
+    *buf.get_mut(*len)? = byte;
+    *len += 1;
+    Some(())
+}
+
+/// Reassembles SLIP frames one received byte at a time.
+#[derive(Copy, Clone, Default)]
+pub struct SlipDecoder {
+    len: usize,
+    escaped: bool,
+    overflow: bool,
+}
+
+impl SlipDecoder {
+    pub fn new() -> SlipDecoder {
+        SlipDecoder::default()
+    }
+
+    /// Adds a received byte to the packet assembled in `buf`. Returns the
+    /// length of the packet when `byte` ends one. Empty packets and packets
+    /// longer than `buf` are dropped.
+    pub fn receive(&mut self, byte: u8, buf: &mut [u8]) -> Option<usize> {
+        match byte {
+            END => {
+                let complete = !self.overflow && self.len > 0;
+                let len = self.len;
+                *self = SlipDecoder::new();
+                if complete {
+                    Some(len)
+                } else {
+                    None
+                }
+            }
+            ESC => {
+                self.escaped = true;
+                None
+            }
+            _ => {
+                let byte = match (self.escaped, byte) {
+                    (true, ESC_END) => END,
+                    (true, ESC_ESC) => ESC,
+                    _ => byte,
+                };
+                self.escaped = false;
+                if self.len < buf.len() {
+                    buf[self.len] = byte;
+                    self.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_escapes() {
+        let mut buf = [0; 10];
+        let len = encode([1, END, 2, ESC].iter().cloned(), &mut buf);
+        assert_eq!(len, Some(8));
+        assert_eq!(buf[..8], [END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, END]);
+        assert_eq!(encode([END; 5].iter().cloned(), &mut buf), None);
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let packet = [0x60, END, ESC, 0, ESC_END, 0xff];
+        let mut frame = [0; 16];
+        let len = encode(packet.iter().cloned(), &mut frame).unwrap();
+
+        let mut decoder = SlipDecoder::new();
+        let mut buf = [0; 8];
+        let mut received = None;
+        for &byte in &frame[..len] {
+            if let Some(len) = decoder.receive(byte, &mut buf) {
+                assert!(received.is_none());
+                received = Some(len);
+            }
+        }
+        assert_eq!(received, Some(packet.len()));
+        assert_eq!(buf[..packet.len()], packet);
+
+        // A frame that does not fit is dropped, and the next one is received
+        let mut small = [0; 4];
+        for &byte in &frame[..len] {
+            assert_eq!(decoder.receive(byte, &mut small), None);
+        }
+        assert_eq!(decoder.receive(1, &mut small), None);
+        assert_eq!(decoder.receive(2, &mut small), None);
+        assert_eq!(decoder.receive(END, &mut small), Some(2));
+    }
+}
@@ -10,7 +10,9 @@
 //! the correctness of port binding / packet transmission/delivery is also dependent
 //! on the port binding logic in the driver being correct.
 //! The MuxUdpSender acts as a FIFO queue for transmitted packets, with each capsule being allowed
-//! a single outstanding / unsent packet at a time.
+//! a single outstanding / unsent packet at a time. Queued packets are handed to the first idle
+//! `UdpSendLane`; every lane owns an IPv6 sender, so packets of different capsules can be in
+//! flight at the same time.
 //! Because the userspace driver is viewed by the MuxUdpSender as being a single capsule,
 //! the userspace driver must queue app packets on its own, as it can only pass a single
 //! packet to the MuxUdpSender queue at a time.
@@ -31,49 +33,37 @@ use kernel::ReturnCode;
 
 pub struct MuxUdpSender<'a, T: IP6Sender<'a>> {
     sender_list: List<'a, UDPSendStruct<'a, T>>,
-    ip_sender: &'a dyn IP6Sender<'a>,
+    lanes: List<'a, UdpSendLane<'a, T>>,
 }
 
 impl<T: IP6Sender<'a>> MuxUdpSender<'a, T> {
-    pub fn new(ip6_sender: &'a dyn IP6Sender<'a>) -> MuxUdpSender<'a, T> {
+    pub fn new() -> MuxUdpSender<'a, T> {
         // similar to UdpSendStruct new()
         MuxUdpSender {
             sender_list: List::new(),
-            ip_sender: ip6_sender,
+            lanes: List::new(),
         }
     }
 
+    pub fn add_lane(&self, lane: &'a UdpSendLane<'a, T>) {
+        self.lanes.push_tail(lane);
+    }
+
     fn send_to(
         &self,
-        dest: IPAddr,
-        transport_header: TransportHeader,
         caller: &'a UDPSendStruct<'a, T>,
         net_cap: &'static NetworkCapability,
     ) -> ReturnCode {
-        // Add this sender to the tail of the sender_list
-        let list_empty = self.sender_list.head().is_none();
-        self.add_client(caller);
-        let mut ret = ReturnCode::SUCCESS;
-        // If list empty, initiate send immediately, and return result.
-        // Otherwise, packet is queued.
-        if list_empty {
-            ret = match caller.tx_buffer.take() {
-                Some(buf) => {
-                    let ret = self
-                        .ip_sender
-                        .send_to(dest, transport_header, &buf, net_cap);
-                    caller.tx_buffer.replace(buf); //Replace buffer as soon as sent.
-                    ret
-                }
-                None => {
-                    debug!("No buffer available to take.");
-                    ReturnCode::FAIL
-                }
+        // Store capability with sender. If a lane is idle, initiate send
+        // immediately, and return result. Otherwise, packet is queued.
+        caller.net_cap.replace(net_cap);
+        match self.lanes.iter().find(|lane| lane.sender.is_none()) {
+            Some(lane) => lane.send(caller),
+            None => {
+                self.add_client(caller);
+                ReturnCode::SUCCESS
             }
-        } else {
-            caller.net_cap.replace(net_cap); //store capability with sender
         }
-        ret
     }
 
     fn add_client(&self, sender: &'a UDPSendStruct<'a, T>) {
@@ -81,65 +71,94 @@ impl<T: IP6Sender<'a>> MuxUdpSender<'a, T> {
     }
 }
 
-/// This function implements the `IP6SendClient` trait for the `UDPSendStruct`,
-/// and is necessary to receive callbacks from the lower (IP) layer. When
-/// the UDP layer receives this callback, it forwards it to the `UDPSendClient`.
-impl<T: IP6Sender<'a>> IP6SendClient for MuxUdpSender<'a, T> {
-    fn send_done(&self, result: ReturnCode) {
-        let last_sender = self.sender_list.pop_head();
-        let next_sender_option = self.sender_list.head(); // must check here, because udp driver
-                                                          // could queue addl. sends in response to
-                                                          // send_done.
-        last_sender.map(|last_sender| {
-            last_sender
-                .client
-                .map(|client| match last_sender.tx_buffer.take() {
-                    Some(buf) => {
-                        client.send_done(result, buf);
-                    }
-                    None => {
-                        debug!("ERROR: Missing buffer in send done.");
-                    }
-                })
-        });
+/// One transmit context of a `MuxUdpSender`. A lane passes the packet of
+/// one `UDPSendStruct` at a time to its own `IP6Sender`, so a mux with
+/// several lanes has that many packets in flight, whose fragments the IPv6
+/// layer interleaves.
+pub struct UdpSendLane<'a, T: IP6Sender<'a>> {
+    udp_mux_sender: &'a MuxUdpSender<'a, T>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    sender: OptionalCell<&'a UDPSendStruct<'a, T>>,
+    next: ListLink<'a, UdpSendLane<'a, T>>,
+}
 
-        let success = match next_sender_option {
-            Some(next_sender) => {
-                //send next packet in queue
-                match next_sender.tx_buffer.take() {
-                    Some(buf) => match next_sender.next_th.take() {
-                        Some(th) => match next_sender.net_cap.take() {
-                            Some(net_cap) => {
-                                let ret = self.ip_sender.send_to(
-                                    next_sender.next_dest.get(),
-                                    th,
-                                    &buf,
-                                    net_cap,
-                                );
-                                next_sender.tx_buffer.replace(buf);
-                                if ret != ReturnCode::SUCCESS {
-                                    debug!("IP send_to failed: {:?}", ret);
-                                }
-                                ret
-                            }
-                            None => ReturnCode::FAIL,
-                        },
-                        None => {
-                            debug!("Missing transport header.");
-                            ReturnCode::FAIL
-                        }
-                    },
+impl<'a, T: IP6Sender<'a>> ListNode<'a, UdpSendLane<'a, T>> for UdpSendLane<'a, T> {
+    fn next(&'a self) -> &'a ListLink<'a, UdpSendLane<'a, T>> {
+        &self.next
+    }
+}
+
+impl<T: IP6Sender<'a>> UdpSendLane<'a, T> {
+    pub fn new(
+        udp_mux_sender: &'a MuxUdpSender<'a, T>,
+        ip_sender: &'a dyn IP6Sender<'a>,
+    ) -> UdpSendLane<'a, T> {
+        UdpSendLane {
+            udp_mux_sender: udp_mux_sender,
+            ip_sender: ip_sender,
+            sender: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    fn send(&self, sender: &'a UDPSendStruct<'a, T>) -> ReturnCode {
+        let ret = match sender.tx_buffer.take() {
+            Some(buf) => match sender.next_th.take() {
+                Some(th) => match sender.net_cap.take() {
+                    Some(net_cap) => {
+                        self.sender.set(sender);
+                        let ret = self
+                            .ip_sender
+                            .send_to(sender.next_dest.get(), th, &buf, net_cap);
+                        sender.tx_buffer.replace(buf); //Replace buffer as soon as sent.
+                        ret
+                    }
                     None => {
-                        debug!("No buffer available to take.");
+                        sender.tx_buffer.replace(buf);
                         ReturnCode::FAIL
                     }
+                },
+                None => {
+                    debug!("Missing transport header.");
+                    sender.tx_buffer.replace(buf);
+                    ReturnCode::FAIL
                 }
+            },
+            None => {
+                debug!("No buffer available to take.");
+                ReturnCode::FAIL
             }
-            None => ReturnCode::SUCCESS, //No more packets queued.
         };
-        if success != ReturnCode::SUCCESS {
-            debug!("Error in udp_send send_done() callback.");
+        if ret != ReturnCode::SUCCESS {
+            self.sender.clear();
         }
+        ret
+    }
+}
+
+/// This function implements the `IP6SendClient` trait for the `UdpSendLane`,
+/// and is necessary to receive callbacks from the lower (IP) layer. When
+/// the UDP layer receives this callback, it forwards it to the `UDPSendClient`.
+impl<T: IP6Sender<'a>> IP6SendClient for UdpSendLane<'a, T> {
+    fn send_done(&self, result: ReturnCode) {
+        let last_sender = self.sender.take();
+
+        // Start the next queued packet before notifying the client, so that a
+        // client sending again from its callback queues up behind the others.
+        while self.sender.is_none() {
+            match self.udp_mux_sender.sender_list.pop_head() {
+                Some(next_sender) => {
+                    let ret = self.send(next_sender);
+                    if ret != ReturnCode::SUCCESS {
+                        debug!("IP send_to failed: {:?}", ret);
+                        next_sender.send_done(ret);
+                    }
+                }
+                None => break, //No more packets queued.
+            }
+        }
+
+        last_sender.map(|last_sender| last_sender.send_done(result));
     }
 }
 
@@ -319,10 +338,7 @@ impl<T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         self.tx_buffer.replace(buf);
         self.next_dest.replace(dest);
         self.next_th.replace(transport_header); // th = transport header
-        match self
-            .udp_mux_sender
-            .send_to(dest, transport_header, &self, net_cap)
-        {
+        match self.udp_mux_sender.send_to(&self, net_cap) {
             ReturnCode::SUCCESS => Ok(()),
             _ => Err(self.tx_buffer.take().unwrap()),
         }
@@ -342,6 +358,17 @@ impl<T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
 }
 
 impl<T: IP6Sender<'a>> UDPSendStruct<'a, T> {
+    fn send_done(&self, result: ReturnCode) {
+        self.client.map(|client| match self.tx_buffer.take() {
+            Some(buf) => {
+                client.send_done(result, buf);
+            }
+            None => {
+                debug!("ERROR: Missing buffer in send done.");
+            }
+        });
+    }
+
     pub fn new(
         udp_mux_sender: &'a MuxUdpSender<'a, T>, /*binding: UdpPortBindingTx*/
         udp_vis: &'static UdpVisibilityCapability,
@@ -1,13 +1,43 @@
 //! This provides kernel and userspace access to nonvolatile memory.
 //!
-//! This is an initial implementation that does not provide safety for
-//! individual userland applications. Each application has full access to
-//! the entire memory space that has been provided to userland. Future revisions
-//! should update this to limit applications to only their allocated regions.
+//! The memory provided to userland is divided into regions of `app_quota`
+//! bytes, and each application only has access to its own region. Regions
+//! are allocated to the persistent identity of applications, which is derived
+//! from the package name in their TBF header, so an application keeps its
+//! data across reboots, restarts and reinstalls. Applications without a
+//! package name cannot use the storage.
 //!
-//! However, the kernel accessible memory does not have to be the same range
-//! as the userspace accessible address space. The kernel memory can overlap
-//! if desired, or can be a completely separate range.
+//! An application that declares the package name of another one, or a name
+//! hashing to the same identity, is given the region of that application.
+//! The regions thus keep trusted applications apart, but do not protect them
+//! from a malicious one, see `AppId::persistent_id`.
+//!
+//! Allocations are recorded in a table at the start of the userspace region:
+//!
+//! ```text
+//! +-------+-------+-------+-----+-----------+-----------+-----
+//! | magic | quota | entry | ... | region 0  | region 1  | ...
+//! +-------+-------+-------+-----+-----------+-----------+-----
+//!                  \
+//!                   id of the app, !id
+//! ```
+//!
+//! Entries that do not hold an identity followed by its complement, such as
+//! erased ones, are free. The region of an application is looked up the
+//! first time it accesses the storage. A free region is cleared before it is
+//! allocated, so applications never see data of previous owners. If the
+//! table was made for a different quota, it is cleared first, and all data
+//! in the userspace region is lost.
+//!
+//! Regions stay allocated when an application is removed, as the kernel
+//! cannot tell a removed application from one that is not loaded yet. An
+//! application frees its region with command 4, after which the region can
+//! be allocated to another application. `RegionTable` does the lookups and
+//! allocations, and can also be used on its own.
+//!
+//! The kernel accessible memory does not have to be the same range as the
+//! userspace accessible address space. The kernel memory can overlap if
+//! desired, or can be a completely separate range.
 //!
 //! Here is a diagram of the expected stack with this capsule:
 //! Boxes are components and between the boxes are the traits that are the
@@ -48,6 +78,7 @@
 //!         0,                           // The byte start address of the region
 //!                                      // that is accessible by the kernel.
 //!         3000,                        // The length of the kernel region.
+//!         256,                         // The bytes of each app.
 //!         &mut capsules::nonvolatile_storage_driver::BUFFER));
 //! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, nonvolatile_storage);
 //! ```
@@ -64,18 +95,30 @@ pub const DRIVER_NUM: usize = driver::NUM::NvmStorage as usize;
 
 pub static mut BUFFER: [u8; 512] = [0; 512];
 
+/// Marks an allocation table.
+const TABLE_MAGIC: u32 = 0x4e56_5354;
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 8;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum NonvolatileCommand {
     UserspaceRead,
     UserspaceWrite,
+    UserspaceRelease,
     KernelRead,
     KernelWrite,
 }
 
 #[derive(Clone, Copy)]
 pub enum NonvolatileUser {
-    App { app_id: AppId },
+    App {
+        app_id: AppId,
+    },
     Kernel,
+    /// Finding or releasing the region of an app.
+    Table {
+        app_id: AppId,
+    },
 }
 
 pub struct App {
@@ -87,6 +130,8 @@ pub struct App {
     length: usize,
     buffer_read: Option<AppSlice<Shared, u8>>,
     buffer_write: Option<AppSlice<Shared, u8>>,
+    // The region of the app, once looked up.
+    region: Option<usize>,
 }
 
 impl Default for App {
@@ -100,6 +145,283 @@ impl Default for App {
             length: 0,
             buffer_read: None,
             buffer_write: None,
+            region: None,
+        }
+    }
+}
+
+impl App {
+    /// Ends the pending command with an error, or the success of a release.
+    fn finish_command(&mut self, result: ReturnCode) {
+        self.pending_command = false;
+        let callback = match self.command {
+            NonvolatileCommand::UserspaceRead => self.callback_read,
+            _ => self.callback_write,
+        };
+        callback.map(|mut cb| cb.schedule(0, usize::from(result), 0));
+    }
+}
+
+/// Steps of an operation on the allocation table.
+#[derive(Clone, Copy, PartialEq)]
+enum TableStep {
+    /// Reading the table from `offset`, looking for the app. `free` is the
+    /// first free entry seen.
+    Search { offset: usize, free: Option<usize> },
+    /// Clearing the table from `offset`.
+    Format { offset: usize },
+    /// Clearing a free region from `offset`.
+    Clear { region: usize, offset: usize },
+    /// Writing the entry of a cleared region.
+    Allocate { region: usize },
+    /// Clearing the entry of a region, which frees it.
+    Release { region: usize },
+}
+
+/// What happened after a step of an operation on the allocation table.
+pub enum TableDone {
+    /// The next step was started.
+    Continue,
+    /// The operation is over. It gives back the buffer, and either the
+    /// region of the app or an error.
+    Done(&'static mut [u8], Result<usize, ReturnCode>),
+    /// The next step could not be started, and the storage kept the buffer.
+    Failed(ReturnCode),
+}
+
+/// The allocation table of the userspace region.
+///
+/// Finding and releasing the region of an app take several reads and
+/// writes of the storage. `find()` and `release()` start the first one, and
+/// whoever gets the read and write done callbacks of the storage passes
+/// them to `step_done()`, until it returns `TableDone::Done`. Only one
+/// operation can run at a time.
+pub struct RegionTable<'a> {
+    driver: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    // The first byte of the userspace region, where the table starts.
+    start_address: usize,
+    // How many bytes each app gets.
+    app_quota: usize,
+    // How many apps get a region.
+    regions: usize,
+    // The persistent identity of the app of the current operation.
+    id: Cell<u32>,
+    // Whether the current operation releases the region of the app.
+    releasing: Cell<bool>,
+    step: Cell<TableStep>,
+}
+
+impl RegionTable<'a> {
+    pub fn new(
+        driver: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        start_address: usize,
+        length: usize,
+        app_quota: usize,
+    ) -> RegionTable<'a> {
+        let regions = if app_quota == 0 {
+            0
+        } else {
+            length.saturating_sub(HEADER_LEN) / (app_quota + ENTRY_LEN)
+        };
+        RegionTable {
+            driver: driver,
+            start_address: start_address,
+            app_quota: app_quota,
+            regions: regions,
+            id: Cell::new(0),
+            releasing: Cell::new(false),
+            step: Cell::new(TableStep::Search {
+                offset: 0,
+                free: None,
+            }),
+        }
+    }
+
+    /// How many bytes each app gets.
+    pub fn app_quota(&self) -> usize {
+        self.app_quota
+    }
+
+    /// How many apps get a region.
+    pub fn regions(&self) -> usize {
+        self.regions
+    }
+
+    /// The byte address of a region in the physical storage.
+    pub fn region_address(&self, region: usize) -> usize {
+        self.start_address + self.table_length() + region * self.app_quota
+    }
+
+    fn table_length(&self) -> usize {
+        HEADER_LEN + ENTRY_LEN * self.regions
+    }
+
+    /// Starts looking up the region of the app with the persistent identity
+    /// `id`. If it has none, a free region is cleared and allocated to it,
+    /// or the operation ends with `ENOMEM` if there is no free region.
+    pub fn find(&self, id: u32, buffer: &'static mut [u8]) -> ReturnCode {
+        self.id.set(id);
+        self.releasing.set(false);
+        self.start(
+            TableStep::Search {
+                offset: 0,
+                free: None,
+            },
+            buffer,
+        )
+    }
+
+    /// Starts releasing the region of the app with the persistent identity
+    /// `id`, so that it can be allocated to another app. The operation ends
+    /// with `EALREADY` if the app has no region.
+    pub fn release(&self, id: u32, buffer: &'static mut [u8]) -> ReturnCode {
+        self.id.set(id);
+        self.releasing.set(true);
+        self.start(
+            TableStep::Search {
+                offset: 0,
+                free: None,
+            },
+            buffer,
+        )
+    }
+
+    fn start(&self, step: TableStep, buffer: &'static mut [u8]) -> ReturnCode {
+        self.step.set(step);
+        let table_length = self.table_length();
+        // Whole entries fit in the buffer
+        let chunk = buffer.len() / ENTRY_LEN * ENTRY_LEN;
+        match step {
+            TableStep::Search { offset, .. } => {
+                let length = cmp::min(chunk, table_length - offset);
+                self.driver
+                    .read(buffer, self.start_address + offset, length)
+            }
+            TableStep::Format { offset } => {
+                let length = cmp::min(chunk, table_length - offset);
+                for byte in buffer.iter_mut() {
+                    *byte = 0;
+                }
+                if offset == 0 {
+                    buffer[0..4].copy_from_slice(&TABLE_MAGIC.to_le_bytes());
+                    buffer[4..8].copy_from_slice(&(self.app_quota as u32).to_le_bytes());
+                }
+                self.driver
+                    .write(buffer, self.start_address + offset, length)
+            }
+            TableStep::Clear { region, offset } => {
+                let length = cmp::min(chunk, self.app_quota - offset);
+                for byte in buffer.iter_mut() {
+                    *byte = 0;
+                }
+                self.driver
+                    .write(buffer, self.region_address(region) + offset, length)
+            }
+            TableStep::Allocate { region } => {
+                let id = self.id.get();
+                buffer[0..4].copy_from_slice(&id.to_le_bytes());
+                buffer[4..8].copy_from_slice(&(!id).to_le_bytes());
+                self.driver.write(
+                    buffer,
+                    self.start_address + HEADER_LEN + region * ENTRY_LEN,
+                    ENTRY_LEN,
+                )
+            }
+            TableStep::Release { region } => {
+                for byte in buffer[0..ENTRY_LEN].iter_mut() {
+                    *byte = 0;
+                }
+                self.driver.write(
+                    buffer,
+                    self.start_address + HEADER_LEN + region * ENTRY_LEN,
+                    ENTRY_LEN,
+                )
+            }
+        }
+    }
+
+    /// Carries on once a step completed, with the buffer and length given
+    /// to the read or write done callback of the storage.
+    pub fn step_done(&self, buffer: &'static mut [u8], length: usize) -> TableDone {
+        let table_length = self.table_length();
+        let releasing = self.releasing.get();
+        // Either the next step, or the result of the operation
+        let next = match self.step.get() {
+            TableStep::Search { offset, free } => {
+                let mut free = free;
+                let mut found = None;
+                let mut valid = true;
+                for (i, entry) in buffer[..length].chunks(ENTRY_LEN).enumerate() {
+                    let first = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                    let second = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+                    let position = offset + i * ENTRY_LEN;
+                    if position == 0 {
+                        valid = first == TABLE_MAGIC && second == self.app_quota as u32;
+                    } else if second != !first {
+                        free = free.or(Some((position - HEADER_LEN) / ENTRY_LEN));
+                    } else if first == self.id.get() {
+                        found = Some((position - HEADER_LEN) / ENTRY_LEN);
+                        break;
+                    }
+                }
+                if !valid && releasing {
+                    Err(Err(ReturnCode::EALREADY))
+                } else if !valid {
+                    Ok(TableStep::Format { offset: 0 })
+                } else if let Some(region) = found {
+                    if releasing {
+                        Ok(TableStep::Release { region: region })
+                    } else {
+                        Err(Ok(region))
+                    }
+                } else if offset + length < table_length {
+                    Ok(TableStep::Search {
+                        offset: offset + length,
+                        free: free,
+                    })
+                } else if releasing {
+                    Err(Err(ReturnCode::EALREADY))
+                } else {
+                    match free {
+                        Some(region) => Ok(TableStep::Clear {
+                            region: region,
+                            offset: 0,
+                        }),
+                        None => Err(Err(ReturnCode::ENOMEM)),
+                    }
+                }
+            }
+            TableStep::Format { offset } => {
+                if offset + length < table_length {
+                    Ok(TableStep::Format {
+                        offset: offset + length,
+                    })
+                } else {
+                    Ok(TableStep::Clear {
+                        region: 0,
+                        offset: 0,
+                    })
+                }
+            }
+            TableStep::Clear { region, offset } => {
+                if offset + length < self.app_quota {
+                    Ok(TableStep::Clear {
+                        region: region,
+                        offset: offset + length,
+                    })
+                } else {
+                    Ok(TableStep::Allocate { region: region })
+                }
+            }
+            TableStep::Allocate { region } | TableStep::Release { region } => Err(Ok(region)),
+        };
+
+        match next {
+            Ok(step) => match self.start(step, buffer) {
+                ReturnCode::SUCCESS => TableDone::Continue,
+                result => TableDone::Failed(result),
+            },
+            Err(result) => TableDone::Done(buffer, result),
         }
     }
 }
@@ -115,10 +437,8 @@ pub struct NonvolatileStorage<'a> {
     // What issued the currently executing call. This can be an app or the kernel.
     current_user: OptionalCell<NonvolatileUser>,
 
-    // The first byte that is accessible from userspace.
-    userspace_start_address: usize,
-    // How many bytes allocated to userspace.
-    userspace_length: usize,
+    // The allocation table of the userspace region.
+    table: RegionTable<'a>,
     // The first byte that is accessible from the kernel.
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
@@ -148,6 +468,7 @@ impl NonvolatileStorage<'a> {
         userspace_length: usize,
         kernel_start_address: usize,
         kernel_length: usize,
+        app_quota: usize,
         buffer: &'static mut [u8],
     ) -> NonvolatileStorage<'a> {
         NonvolatileStorage {
@@ -155,8 +476,7 @@ impl NonvolatileStorage<'a> {
             apps: grant,
             buffer: TakeCell::new(buffer),
             current_user: OptionalCell::empty(),
-            userspace_start_address: userspace_start_address,
-            userspace_length: userspace_length,
+            table: RegionTable::new(driver, userspace_start_address, userspace_length, app_quota),
             kernel_start_address: kernel_start_address,
             kernel_length: kernel_length,
             kernel_client: OptionalCell::empty(),
@@ -181,15 +501,14 @@ impl NonvolatileStorage<'a> {
         // Do bounds check.
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
-                // Userspace sees memory that starts at address 0 even if it
-                // is offset in the physical memory.
-                if offset >= self.userspace_length
-                    || length > self.userspace_length
-                    || offset + length > self.userspace_length
-                {
+                // Userspace sees its region as memory that starts at address
+                // 0.
+                let app_quota = self.table.app_quota();
+                if offset >= app_quota || length > app_quota || offset + length > app_quota {
                     return ReturnCode::EINVAL;
                 }
             }
+            NonvolatileCommand::UserspaceRelease => {}
             NonvolatileCommand::KernelRead | NonvolatileCommand::KernelWrite => {
                 // Because the kernel uses the NonvolatileStorage interface,
                 // its calls are absolute addresses.
@@ -206,76 +525,63 @@ impl NonvolatileStorage<'a> {
         // Do very different actions if this is a call from userspace
         // or from the kernel.
         match command {
-            NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
-                app_id.map_or(ReturnCode::FAIL, |appid| {
-                    self.apps
-                        .enter(appid, |app, _| {
-                            // Get the length of the correct allowed buffer.
-                            let allow_buf_len = match command {
-                                NonvolatileCommand::UserspaceRead => {
-                                    app.buffer_read.as_ref().map_or(0, |appbuf| appbuf.len())
-                                }
-                                NonvolatileCommand::UserspaceWrite => {
-                                    app.buffer_write.as_ref().map_or(0, |appbuf| appbuf.len())
-                                }
-                                _ => 0,
-                            };
-
-                            // Check that it exists.
-                            if allow_buf_len == 0 || self.buffer.is_none() {
-                                return ReturnCode::ERESERVE;
+            NonvolatileCommand::UserspaceRead
+            | NonvolatileCommand::UserspaceWrite
+            | NonvolatileCommand::UserspaceRelease => {
+                let appid = match app_id {
+                    Some(appid) => appid,
+                    None => return ReturnCode::FAIL,
+                };
+                if appid.persistent_id().is_none() {
+                    return ReturnCode::ENOSUPPORT;
+                }
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        // Get the length of the correct allowed buffer.
+                        let allow_buf_len = match command {
+                            NonvolatileCommand::UserspaceRead => {
+                                app.buffer_read.as_ref().map_or(0, |appbuf| appbuf.len())
+                            }
+                            NonvolatileCommand::UserspaceWrite => {
+                                app.buffer_write.as_ref().map_or(0, |appbuf| appbuf.len())
                             }
+                            _ => 0,
+                        };
+
+                        // Check that it exists. Releasing needs no buffer.
+                        if command != NonvolatileCommand::UserspaceRelease
+                            && (allow_buf_len == 0 || self.buffer.is_none())
+                        {
+                            return ReturnCode::ERESERVE;
+                        }
 
-                            // Shorten the length if the application gave us nowhere to
-                            // put it.
-                            let active_len = cmp::min(length, allow_buf_len);
-
-                            // First need to determine if we can execute this or must
-                            // queue it.
-                            if self.current_user.is_none() {
-                                // No app is currently using the underlying storage.
-                                // Mark this app as active, and then execute the command.
-                                self.current_user
-                                    .set(NonvolatileUser::App { app_id: appid });
-
-                                // Need to copy bytes if this is a write!
-                                if command == NonvolatileCommand::UserspaceWrite {
-                                    app.buffer_write.as_mut().map(|app_buffer| {
-                                        self.buffer.map(|kernel_buffer| {
-                                            // Check that the internal buffer and the buffer that was
-                                            // allowed are long enough.
-                                            let write_len =
-                                                cmp::min(active_len, kernel_buffer.len());
-
-                                            let d = &mut app_buffer.as_mut()[0..write_len];
-                                            for (i, c) in
-                                                kernel_buffer[0..write_len].iter_mut().enumerate()
-                                            {
-                                                *c = d[i];
-                                            }
-                                        });
-                                    });
-                                }
+                        if app.pending_command {
+                            // No more room in the queue, nowhere to store this
+                            // request.
+                            return ReturnCode::ENOMEM;
+                        }
 
-                                self.userspace_call_driver(command, offset, active_len)
-                            } else {
-                                // Some app is using the storage, we must wait.
-                                if app.pending_command == true {
-                                    // No more room in the queue, nowhere to store this
-                                    // request.
-                                    ReturnCode::ENOMEM
-                                } else {
-                                    // We can store this, so lets do it.
-                                    app.pending_command = true;
-                                    app.command = command;
-                                    app.offset = offset;
-                                    app.length = active_len;
-                                    ReturnCode::SUCCESS
-                                }
-                            }
-                        })
-                        .unwrap_or_else(|err| err.into())
-                })
+                        // Shorten the length if the application gave us nowhere to
+                        // put it.
+                        app.pending_command = true;
+                        app.command = command;
+                        app.offset = offset;
+                        app.length = cmp::min(length, allow_buf_len);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result != ReturnCode::SUCCESS || self.current_user.is_some() {
+                    // Some user has the storage, so this waits
+                    return result;
+                }
+
+                // No one is currently using the underlying storage.
+                let result = self.run_app(appid);
+                if result != ReturnCode::SUCCESS {
+                    let _ = self.apps.enter(appid, |app, _| app.pending_command = false);
+                }
+                result
             }
             NonvolatileCommand::KernelRead | NonvolatileCommand::KernelWrite => {
                 self.kernel_buffer
@@ -314,23 +620,85 @@ impl NonvolatileStorage<'a> {
         }
     }
 
+    /// Starts the pending command of an app, looking up its region first if
+    /// needed.
+    fn run_app(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if !app.pending_command {
+                    return ReturnCode::FAIL;
+                }
+                let region = match app.region {
+                    Some(region) if app.command != NonvolatileCommand::UserspaceRelease => region,
+                    _ => {
+                        if self.table.regions() == 0 {
+                            return ReturnCode::ENOMEM;
+                        }
+                        let id = match appid.persistent_id() {
+                            Some(id) => id,
+                            None => return ReturnCode::ENOSUPPORT,
+                        };
+                        self.current_user
+                            .set(NonvolatileUser::Table { app_id: appid });
+                        let result = self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                            if app.command == NonvolatileCommand::UserspaceRelease {
+                                self.table.release(id, buffer)
+                            } else {
+                                self.table.find(id, buffer)
+                            }
+                        });
+                        if result != ReturnCode::SUCCESS {
+                            self.current_user.clear();
+                        }
+                        return result;
+                    }
+                };
+                app.pending_command = false;
+                self.current_user
+                    .set(NonvolatileUser::App { app_id: appid });
+
+                // Need to copy bytes if this is a write!
+                if app.command == NonvolatileCommand::UserspaceWrite {
+                    let length = app.length;
+                    app.buffer_write.as_ref().map(|app_buffer| {
+                        self.buffer.map(|kernel_buffer| {
+                            // Check that the internal buffer and the buffer that was
+                            // allowed are long enough.
+                            let write_len =
+                                cmp::min(cmp::min(length, app_buffer.len()), kernel_buffer.len());
+                            kernel_buffer[..write_len]
+                                .copy_from_slice(&app_buffer.as_ref()[..write_len]);
+                        });
+                    });
+                }
+
+                let result =
+                    self.userspace_call_driver(app.command, region, app.offset, app.length);
+                if result != ReturnCode::SUCCESS {
+                    self.current_user.clear();
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
     fn userspace_call_driver(
         &self,
         command: NonvolatileCommand,
+        region: usize,
         offset: usize,
         length: usize,
     ) -> ReturnCode {
         // Calculate where we want to actually read from in the physical
         // storage.
-        let physical_address = offset + self.userspace_start_address;
+        let physical_address = offset + self.table.region_address(region);
 
         self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
             // Check that the internal buffer and the buffer that was
             // allowed are long enough.
             let active_len = cmp::min(length, buffer.len());
 
-            // self.current_app.set(Some(appid));
-            match command {
+            let result = match command {
                 NonvolatileCommand::UserspaceRead => {
                     self.driver.read(buffer, physical_address, active_len)
                 }
@@ -338,11 +706,50 @@ impl NonvolatileStorage<'a> {
                     self.driver.write(buffer, physical_address, active_len)
                 }
                 _ => ReturnCode::FAIL,
-            }
+            };
+            result
         })
     }
 
+    /// Carries on once a step of the table completed.
+    fn table_done(&self, app_id: AppId, buffer: &'static mut [u8], length: usize) {
+        match self.table.step_done(buffer, length) {
+            TableDone::Continue => {
+                self.current_user
+                    .set(NonvolatileUser::Table { app_id: app_id });
+            }
+            TableDone::Done(buffer, result) => {
+                self.buffer.replace(buffer);
+                let _ = self.apps.enter(app_id, |app, _| match result {
+                    // The pending command runs from the queue.
+                    Ok(region) if app.command != NonvolatileCommand::UserspaceRelease => {
+                        app.region = Some(region)
+                    }
+                    Ok(_) => {
+                        app.region = None;
+                        app.finish_command(ReturnCode::SUCCESS);
+                    }
+                    Err(result) => {
+                        if app.command == NonvolatileCommand::UserspaceRelease {
+                            app.region = None;
+                        }
+                        app.finish_command(result);
+                    }
+                });
+            }
+            TableDone::Failed(result) => self.region_failed(app_id, result),
+        }
+    }
+
+    /// Fails the pending command of an app that did not get a region.
+    fn region_failed(&self, app_id: AppId, result: ReturnCode) {
+        let _ = self.apps.enter(app_id, |app, _| app.finish_command(result));
+    }
+
     fn check_queue(&self) {
+        if self.current_user.is_some() {
+            return;
+        }
         // Check if there are any pending events.
         if self.kernel_pending_command.get() {
             self.kernel_buffer.take().map(|kernel_buffer| {
@@ -366,20 +773,19 @@ impl NonvolatileStorage<'a> {
         } else {
             // If the kernel is not requesting anything, check all of the apps.
             for cntr in self.apps.iter() {
-                let started_command = cntr.enter(|app, _| {
+                let appid = cntr.enter(|app, _| {
                     if app.pending_command {
-                        app.pending_command = false;
-                        self.current_user.set(NonvolatileUser::App {
-                            app_id: app.appid(),
-                        });
-                        self.userspace_call_driver(app.command, app.offset, app.length)
-                            == ReturnCode::SUCCESS
+                        Some(app.appid())
                     } else {
-                        false
+                        None
                     }
                 });
-                if started_command {
-                    break;
+                if let Some(appid) = appid {
+                    let result = self.run_app(appid);
+                    if result == ReturnCode::SUCCESS {
+                        break;
+                    }
+                    self.region_failed(appid, result);
                 }
             }
         }
@@ -416,6 +822,7 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for Nonvolatile
                         app.callback_read.map(|mut cb| cb.schedule(length, 0, 0));
                     });
                 }
+                NonvolatileUser::Table { app_id } => self.table_done(app_id, buffer, length),
             }
         });
 
@@ -440,6 +847,7 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for Nonvolatile
                         app.callback_write.map(|mut cb| cb.schedule(length, 0, 0));
                     });
                 }
+                NonvolatileUser::Table { app_id } => self.table_done(app_id, buffer, length),
             }
         });
 
@@ -496,6 +904,10 @@ impl Driver for NonvolatileStorage<'a> {
     ///
     /// - `0`: Setup a read done callback.
     /// - `1`: Setup a write done callback.
+    ///
+    /// The callbacks get the number of bytes read or written. If the app
+    /// could not be given a region, they get 0 and an error code, such as
+    /// ENOMEM if all regions are taken.
     fn subscribe(
         &self,
         subscribe_num: usize,
@@ -521,9 +933,12 @@ impl Driver for NonvolatileStorage<'a> {
     /// ### `command_num`
     ///
     /// - `0`: Return SUCCESS if this driver is included on the platform.
-    /// - `1`: Return the number of bytes available to userspace.
+    /// - `1`: Return the number of bytes available to the app.
     /// - `2`: Start a read from the nonvolatile storage.
     /// - `3`: Start a write to the nonvolatile_storage.
+    /// - `4`: Release the region of the app. Its data is lost, and the
+    ///   region can be allocated to another app. The write done callback
+    ///   gets 0 and SUCCESS, or EALREADY if the app had no region.
     fn command(&self, arg0: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         let command_num = arg0 & 0xFF;
 
@@ -534,9 +949,9 @@ impl Driver for NonvolatileStorage<'a> {
                 ReturnCode::SUCCESS
             }
 
-            // How many bytes are accessible from userspace.
+            // How many bytes are accessible to each app.
             1 => ReturnCode::SuccessWithValue {
-                value: self.userspace_length,
+                value: self.table.app_quota(),
             },
 
             // Issue a read
@@ -563,6 +978,9 @@ impl Driver for NonvolatileStorage<'a> {
                 )
             }
 
+            // Release the region
+            4 => self.enqueue_command(NonvolatileCommand::UserspaceRelease, 0, 0, Some(appid)),
+
             _ => ReturnCode::ENOSUPPORT,
         }
     }
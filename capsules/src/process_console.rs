@@ -5,13 +5,17 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has the following commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'date' prints the current date and time, if the board has a real-time
+//!    clock; 'date YYYY-MM-DD HH:MM:SS' sets it (in UTC)
+//!  - 'netstats' prints the counters of the 6LoWPAN transmit queue, if the
+//!    board has one
 //!
 //! ### `list` Command Fields:
 //!
@@ -105,13 +109,37 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! If the board provides a real-time clock with `set_date_time`, the `date`
+//! command reads and sets the wall-clock time:
+//!
+//! ```text
+//! date 2020-03-06 14:30:00
+//! date
+//! 2020-03-06 14:30:02 UTC
+//! ```
+//!
+//! If the board provides its `MuxIP6Sender` with `set_tx_queue`, the
+//! `netstats` command prints how many IPv6 datagrams are queued for
+//! transmission, the most that have been queued at once, and how many were
+//! sent or dropped:
+//!
+//! ```text
+//! netstats
+//! Queued datagrams: 0 (max 2)
+//! Sent datagrams: 14
+//! Dropped datagrams: 1
+//! Fragments: 37
+//! ```
 
+use crate::net::ipv6::ipv6_send::TxQueue;
 use core::cell::Cell;
 use core::cmp;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::debug;
+use kernel::hil::date_time::{DateTime, DateTimeValues};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
 use kernel::Kernel;
@@ -145,6 +173,8 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     execute: Cell<bool>,
     kernel: &'static Kernel,
     capability: C,
+    date_time: OptionalCell<&'a dyn DateTime>,
+    tx_queue: OptionalCell<&'a dyn TxQueue>,
 }
 
 impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
@@ -168,9 +198,61 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             execute: Cell::new(false),
             kernel: kernel,
             capability: capability,
+            date_time: OptionalCell::empty(),
+            tx_queue: OptionalCell::empty(),
         }
     }
 
+    /// Provide a real-time clock for the `date` command.
+    pub fn set_date_time(&self, date_time: &'a dyn DateTime) {
+        self.date_time.set(date_time);
+    }
+
+    /// Provide the transmit queue of the network stack for the `netstats`
+    /// command.
+    pub fn set_tx_queue(&self, tx_queue: &'a dyn TxQueue) {
+        self.tx_queue.set(tx_queue);
+    }
+
+    fn netstats(&self) {
+        self.tx_queue.map_or_else(
+            || debug!("No network stack available"),
+            |tx_queue| {
+                let stats = tx_queue.stats();
+                debug!(
+                    "Queued datagrams: {} (max {})",
+                    stats.queued, stats.max_queued
+                );
+                debug!("Sent datagrams: {}", stats.sent);
+                debug!("Dropped datagrams: {}", stats.dropped);
+                debug!("Fragments: {}", stats.fragments);
+            },
+        );
+    }
+
+    fn date(&self, date: Option<&str>, time: Option<&str>) {
+        self.date_time.map_or_else(
+            || debug!("No real-time clock available"),
+            |clock| match (date, time) {
+                (None, _) => match clock.get_date_time() {
+                    Ok(dt) => debug!(
+                        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+                    ),
+                    Err(_) => debug!("Date and time not set"),
+                },
+                (Some(date), Some(time)) => match parse_date_time(date, time) {
+                    Some(dt) => match clock.set_date_time(dt) {
+                        ReturnCode::SUCCESS => debug!("Date and time set"),
+                        rc => debug!("Failed to set date and time: {:?}", rc),
+                    },
+                    None => debug!("Usage: date YYYY-MM-DD HH:MM:SS"),
+                },
+                _ => debug!("Usage: date YYYY-MM-DD HH:MM:SS"),
+            },
+        );
+    }
+
     pub fn start(&self) -> ReturnCode {
         if self.running.get() == false {
             self.rx_buffer.take().map(|buffer| {
@@ -205,7 +287,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                         let clean_str = s.trim();
                         if clean_str.starts_with("help") {
                             debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
+                            debug!("Valid commands are: help status list stop start fault date netstats");
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -285,8 +367,13 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                 "Timeslice expirations: {}",
                                 info.timeslice_expirations(&self.capability)
                             );
+                        } else if clean_str.starts_with("date") {
+                            let mut arguments = clean_str.split_whitespace().skip(1);
+                            self.date(arguments.next(), arguments.next());
+                        } else if clean_str.starts_with("netstats") {
+                            self.netstats();
                         } else {
-                            debug!("Valid commands are: help status list stop start fault");
+                            debug!("Valid commands are: help status list stop start fault date netstats");
                         }
                     }
                     Err(_e) => debug!("Invalid command: {:?}", command),
@@ -328,6 +415,25 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
     }
 }
 
+/// Parse a date of the form `YYYY-MM-DD` and a time of the form `HH:MM:SS`.
+fn parse_date_time(date: &str, time: &str) -> Option<DateTimeValues> {
+    let mut date = date.split('-');
+    let mut time = time.split(':');
+    let date_time = DateTimeValues {
+        year: date.next()?.parse().ok()?,
+        month: date.next()?.parse().ok()?,
+        day: date.next()?.parse().ok()?,
+        hour: time.next()?.parse().ok()?,
+        minute: time.next()?.parse().ok()?,
+        second: time.next()?.parse().ok()?,
+    };
+    if date.next().is_some() || time.next().is_some() {
+        None
+    } else {
+        Some(date_time)
+    }
+}
+
 impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole<'a, C> {
     fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, _rcode: ReturnCode) {
         self.tx_buffer.replace(buffer);
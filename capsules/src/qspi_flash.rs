@@ -0,0 +1,292 @@
+//! Exposes an external flash on a QSPI controller as `hil::flash::Flash`.
+//!
+//! Pages are 4 KB flash sectors, the smallest unit the flash can erase, so
+//! users such as the log and nonvolatile storage capsules can run on top of
+//! the much faster QSPI interface instead of a flash driver built on plain
+//! SPI, like `mx25r6435f`. As in that driver, writing a page erases it first.
+//!
+//! The controller transfers data through a word aligned buffer owned by this
+//! capsule, so `QspiFlashSector` pages need no particular alignment.
+//!
+//! `initialize` sets the quad enable bit (bit 6 of the status register) used
+//! by Macronix flash such as the MX25R6435F, which is required before the
+//! controller can use four data lines.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let qspi_flash = static_init!(
+//!     capsules::qspi_flash::QspiFlash<'static, nrf52::qspi::Qspi<'static>>,
+//!     capsules::qspi_flash::QspiFlash::new(
+//!         &nrf52::qspi::QSPI,
+//!         &mut capsules::qspi_flash::BUFFER.0,
+//!     )
+//! );
+//! hil::qspi::QspiMaster::set_client(&nrf52::qspi::QSPI, qspi_flash);
+//! qspi_flash.initialize();
+//! ```
+
+use core::cell::Cell;
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::qspi::{EraseSize, QspiMaster};
+use kernel::ReturnCode;
+
+const SECTOR_SIZE: usize = 4096;
+
+/// Write Status Register instruction.
+const WRSR: u8 = 0x01;
+
+/// Quad enable bit in the status register.
+const QUAD_ENABLE: u8 = 1 << 6;
+
+/// Transfer buffer with the alignment QSPI controllers require.
+#[repr(align(4))]
+pub struct QspiFlashBuffer(pub [u8; SECTOR_SIZE]);
+
+pub static mut BUFFER: QspiFlashBuffer = QspiFlashBuffer([0; SECTOR_SIZE]);
+
+/// A single 4 KB flash sector.
+pub struct QspiFlashSector(pub [u8; SECTOR_SIZE]);
+
+impl Default for QspiFlashSector {
+    fn default() -> Self {
+        Self {
+            0: [0; SECTOR_SIZE],
+        }
+    }
+}
+
+impl QspiFlashSector {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Index<usize> for QspiFlashSector {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for QspiFlashSector {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for QspiFlashSector {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Initializing,
+    Read,
+    Erase,
+    WriteErase { sector_index: usize },
+    Write,
+}
+
+pub struct QspiFlash<'a, Q: QspiMaster<'a>> {
+    qspi: &'a Q,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn hil::flash::Client<QspiFlash<'a, Q>>>,
+    client_sector: TakeCell<'static, QspiFlashSector>,
+}
+
+impl<Q: QspiMaster<'a>> QspiFlash<'a, Q> {
+    pub fn new(qspi: &'a Q, buffer: &'static mut [u8]) -> QspiFlash<'a, Q> {
+        QspiFlash {
+            qspi: qspi,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            client_sector: TakeCell::empty(),
+        }
+    }
+
+    /// Enable quad mode in the flash.
+    pub fn initialize(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let rval = self.qspi.command(WRSR, &[QUAD_ENABLE], true);
+        if rval == ReturnCode::SUCCESS {
+            self.state.set(State::Initializing);
+        }
+        rval
+    }
+
+    fn read_sector(
+        &self,
+        sector_index: usize,
+        sector: &'static mut QspiFlashSector,
+    ) -> Result<(), (ReturnCode, &'static mut QspiFlashSector)> {
+        if self.state.get() != State::Idle {
+            return Err((ReturnCode::EBUSY, sector));
+        }
+        match self.buffer.take() {
+            None => Err((ReturnCode::ENOMEM, sector)),
+            Some(buffer) => {
+                let address = (sector_index * SECTOR_SIZE) as u32;
+                match self.qspi.read(address, buffer, SECTOR_SIZE) {
+                    Ok(()) => {
+                        self.state.set(State::Read);
+                        self.client_sector.replace(sector);
+                        Ok(())
+                    }
+                    Err((rval, buffer)) => {
+                        self.buffer.replace(buffer);
+                        Err((rval, sector))
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_sector(
+        &self,
+        sector_index: usize,
+        sector: &'static mut QspiFlashSector,
+    ) -> Result<(), (ReturnCode, &'static mut QspiFlashSector)> {
+        if self.state.get() != State::Idle {
+            return Err((ReturnCode::EBUSY, sector));
+        }
+        let address = (sector_index * SECTOR_SIZE) as u32;
+        match self.qspi.erase(address, EraseSize::Sector4K) {
+            ReturnCode::SUCCESS => {
+                self.state.set(State::WriteErase { sector_index });
+                self.client_sector.replace(sector);
+                Ok(())
+            }
+            rval => Err((rval, sector)),
+        }
+    }
+
+    fn erase_sector(&self, sector_index: usize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let address = (sector_index * SECTOR_SIZE) as u32;
+        let rval = self.qspi.erase(address, EraseSize::Sector4K);
+        if rval == ReturnCode::SUCCESS {
+            self.state.set(State::Erase);
+        }
+        rval
+    }
+
+    fn write_complete(&self, rval: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client_sector.take().map(|sector| {
+            self.client
+                .map(move |client| client.write_complete(sector, to_error(rval)));
+        });
+    }
+}
+
+fn to_error(rval: ReturnCode) -> hil::flash::Error {
+    match rval {
+        ReturnCode::SUCCESS => hil::flash::Error::CommandComplete,
+        _ => hil::flash::Error::FlashError,
+    }
+}
+
+impl<Q: QspiMaster<'a>> hil::qspi::QspiClient for QspiFlash<'a, Q> {
+    fn read_done(&self, buffer: &'static mut [u8], len: usize, rval: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client_sector.take().map(|sector| {
+            let len = core::cmp::min(len, sector.len());
+            sector.0[..len].copy_from_slice(&buffer[..len]);
+            self.client
+                .map(move |client| client.read_complete(sector, to_error(rval)));
+        });
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _len: usize, rval: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.write_complete(rval);
+    }
+
+    fn erase_done(&self, rval: ReturnCode) {
+        match self.state.get() {
+            State::Erase => {
+                self.state.set(State::Idle);
+                self.client
+                    .map(|client| client.erase_complete(to_error(rval)));
+            }
+            State::WriteErase { sector_index } => {
+                if rval != ReturnCode::SUCCESS {
+                    self.write_complete(rval);
+                    return;
+                }
+                let result = self
+                    .buffer
+                    .take()
+                    .map_or(Err(ReturnCode::ENOMEM), |buffer| {
+                        self.client_sector
+                            .map(|sector| buffer.copy_from_slice(&sector.0));
+                        let address = (sector_index * SECTOR_SIZE) as u32;
+                        self.qspi
+                            .write(address, buffer, SECTOR_SIZE)
+                            .map_err(|(rval, buffer)| {
+                                self.buffer.replace(buffer);
+                                rval
+                            })
+                    });
+                match result {
+                    Ok(()) => self.state.set(State::Write),
+                    Err(rval) => self.write_complete(rval),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn command_done(&self, _response: &[u8], _rval: ReturnCode) {
+        if self.state.get() == State::Initializing {
+            self.state.set(State::Idle);
+        }
+    }
+}
+
+impl<Q: QspiMaster<'a>, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C>
+    for QspiFlash<'a, Q>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<Q: QspiMaster<'a>> hil::flash::Flash for QspiFlash<'a, Q> {
+    type Page = QspiFlashSector;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        self.read_sector(page_number, buf)
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        self.write_sector(page_number, buf)
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.erase_sector(page_number)
+    }
+}
@@ -1198,6 +1198,12 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
         self.is_initialized.get()
     }
 
+    /// Takes back the buffer of a read or write that ended with an error
+    /// callback, which the SD card would otherwise keep.
+    pub fn take_client_buffer(&self) -> Option<&'static mut [u8]> {
+        self.client_buffer.take()
+    }
+
     /// watches SD card detect pin for changes, sends callback on change
     pub fn detect_changes(&self) {
         self.detect_pin.get().map(|pin| {
@@ -0,0 +1,359 @@
+//! Software implementation of SHA-256.
+//!
+//! Provides the digest HIL on chips without a hash engine. The data is hashed
+//! right away in `add_data()` and `run()`; the callbacks are issued from a
+//! deferred call so that clients see the same split-phase behaviour as with
+//! hardware. After `set_mode_hmacsha256()`, `run()` returns the HMAC-SHA256
+//! of the data instead, until `set_mode_sha256()` is called.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sha = static_init!(
+//!     capsules::sha256::Sha256Software<'static>,
+//!     capsules::sha256::Sha256Software::new(dynamic_deferred_caller)
+//! );
+//! sha.initialize_callback_handle(dynamic_deferred_caller.register(sha).unwrap());
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::ReturnCode;
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Bytes XORed into the key for the inner and the outer hash of HMAC.
+const HMAC_INNER_PAD: u8 = 0x36;
+const HMAC_OUTER_PAD: u8 = 0x5c;
+
+/// Running state of a SHA-256 computation.
+struct Sha256State {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256State {
+    const fn new() -> Sha256State {
+        Sha256State {
+            state: INITIAL_STATE,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Returns the state after hashing `key` padded to a block and XORed
+    /// with `pad`, as HMAC does before the data.
+    fn keyed(key: &[u8; 32], pad: u8) -> Sha256State {
+        let mut block = [pad; 64];
+        for (byte, key_byte) in block.iter_mut().zip(key.iter()) {
+            *byte ^= key_byte;
+        }
+        let mut state = Sha256State::new();
+        state.update(&block);
+        state
+    }
+
+    /// Returns the state to start from for the data of a new message.
+    fn start(key: Option<[u8; 32]>) -> Sha256State {
+        match key {
+            Some(key) => Sha256State::keyed(&key, HMAC_INNER_PAD),
+            None => Sha256State::new(),
+        }
+    }
+
+    /// Like `finish()`, but for a state started with `start(key)`.
+    fn finish_keyed(&mut self, key: Option<[u8; 32]>, digest: &mut [u8; 32]) {
+        self.finish(digest);
+        if let Some(key) = key {
+            let mut outer = Sha256State::keyed(&key, HMAC_OUTER_PAD);
+            outer.update(&digest[..]);
+            outer.finish(digest);
+        }
+        *self = Sha256State::start(key);
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.total_len += data.len() as u64;
+        for byte in data {
+            self.block[self.block_len] = *byte;
+            self.block_len += 1;
+            if self.block_len == self.block.len() {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(&mut self, digest: &mut [u8; 32]) {
+        let bit_len = self.total_len * 8;
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > 56 {
+            for byte in self.block[self.block_len..].iter_mut() {
+                *byte = 0;
+            }
+            self.compress();
+            self.block_len = 0;
+        }
+        for byte in self.block[self.block_len..56].iter_mut() {
+            *byte = 0;
+        }
+        self.block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        self.compress();
+
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        *self = Sha256State::new();
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut h = self.state;
+        for i in 0..64 {
+            let s1 = h[4].rotate_right(6) ^ h[4].rotate_right(11) ^ h[4].rotate_right(25);
+            let ch = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let t1 = h[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = h[0].rotate_right(2) ^ h[0].rotate_right(13) ^ h[0].rotate_right(22);
+            let maj = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            h[7] = h[6];
+            h[6] = h[5];
+            h[5] = h[4];
+            h[4] = h[3].wrapping_add(t1);
+            h[3] = h[2];
+            h[2] = h[1];
+            h[1] = h[0];
+            h[0] = t1.wrapping_add(t2);
+        }
+
+        for (state, word) in self.state.iter_mut().zip(h.iter()) {
+            *state = state.wrapping_add(*word);
+        }
+    }
+}
+
+pub struct Sha256Software<'a> {
+    client: OptionalCell<&'a dyn digest::Client<'a, [u8; 32]>>,
+    state: MapCell<Sha256State>,
+    /// Key of the HMAC mode
+    key: Cell<Option<[u8; 32]>>,
+    data: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; 32]>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl Sha256Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> Sha256Software<'a> {
+        Sha256Software {
+            client: OptionalCell::empty(),
+            state: MapCell::new(Sha256State::new()),
+            key: Cell::new(None),
+            data: TakeCell::empty(),
+            digest: TakeCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn schedule_callback(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+}
+
+impl digest::Digest<'a, [u8; 32]> for Sha256Software<'a> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, [u8; 32]>) {
+        self.client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ReturnCode, &'static mut [u8])> {
+        if self.data.is_some() || self.digest.is_some() {
+            return Err((ReturnCode::EBUSY, data.take()));
+        }
+        let len = data.len();
+        self.state.map(|state| state.update(&data[..]));
+        self.data.replace(data.take());
+        self.schedule_callback();
+        Ok(len)
+    }
+
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; 32],
+    ) -> Result<(), (ReturnCode, &'static mut [u8; 32])> {
+        if self.data.is_some() || self.digest.is_some() {
+            return Err((ReturnCode::EBUSY, digest));
+        }
+        let key = self.key.get();
+        self.state.map(|state| state.finish_keyed(key, digest));
+        self.digest.replace(digest);
+        self.schedule_callback();
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        let key = self.key.get();
+        self.state.map(|state| *state = Sha256State::start(key));
+    }
+}
+
+impl digest::Sha256 for Sha256Software<'a> {
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        self.key.set(None);
+        digest::Digest::clear_data(self);
+        Ok(())
+    }
+}
+
+impl digest::HMACSha256 for Sha256Software<'a> {
+    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode> {
+        self.key.set(Some(*key));
+        digest::Digest::clear_data(self);
+        Ok(())
+    }
+}
+
+impl DynamicDeferredCallClient for Sha256Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if let Some(data) = self.data.take() {
+            self.client
+                .map(move |client| client.add_data_done(Ok(()), data));
+        } else if let Some(digest) = self.digest.take() {
+            self.client
+                .map(move |client| client.hash_done(Ok(()), digest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sha256State;
+
+    /// HMAC-SHA256 with a key of at most 32 bytes, padded with zeros.
+    fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut padded = [0; 32];
+        padded[..key.len()].copy_from_slice(key);
+        let mut state = Sha256State::start(Some(padded));
+        let mut digest = [0; 32];
+        state.update(data);
+        state.finish_keyed(Some(padded), &mut digest);
+        digest
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut state = Sha256State::new();
+        let mut digest = [0; 32];
+        state.update(data);
+        state.finish(&mut digest);
+        digest
+    }
+
+    #[test]
+    fn empty_message() {
+        assert_eq!(
+            sha256(b"")[..],
+            [
+                0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+                0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+                0x78, 0x52, 0xb8, 0x55
+            ][..]
+        );
+    }
+
+    #[test]
+    fn two_block_message() {
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")[..],
+            [
+                0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e,
+                0x60, 0x39, 0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4,
+                0x19, 0xdb, 0x06, 0xc1
+            ][..]
+        );
+    }
+
+    #[test]
+    fn split_updates() {
+        let mut state = Sha256State::new();
+        let mut digest = [0; 32];
+        state.update(b"ab");
+        state.update(b"c");
+        state.finish(&mut digest);
+        assert_eq!(
+            digest[..],
+            [
+                0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+                0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+                0xf2, 0x00, 0x15, 0xad
+            ][..]
+        );
+    }
+
+    // Test cases 1 and 2 of RFC 4231
+    #[test]
+    fn hmac_short_keys() {
+        assert_eq!(
+            hmac(&[0x0b; 20], b"Hi There")[..],
+            [
+                0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+                0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+                0x2e, 0x32, 0xcf, 0xf7
+            ][..]
+        );
+        assert_eq!(
+            hmac(b"Jefe", b"what do ya want for nothing?")[..],
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43
+            ][..]
+        );
+    }
+}
@@ -0,0 +1,504 @@
+//! Simulated 802.15.4 radios sharing a simulated medium.
+//!
+//! `SimRadio` implements `hil::radio::Radio` without hardware, so that
+//! several instances of the networking stack can talk to each other in one
+//! process, for instance in host-side tests. All radios are attached to a
+//! `SimMedium`, which carries a frame from its sender to the other radios
+//! that are on, on the same channel, not transmitting themselves, and
+//! connected to the sender.
+//!
+//! The medium models:
+//!
+//! - Topology: every pair of radios is connected by default, and links can
+//!   be cut in both directions with `set_connected`.
+//! - Loss: each link drops frames with the probability set by `set_loss`,
+//!   in parts per thousand. Losses are drawn from a seeded pseudo-random
+//!   generator, so runs are reproducible.
+//! - Latency: frames arrive after their airtime at 250 kb/s plus the
+//!   latency set by `set_latency`.
+//! - Acknowledgements: a frame that requests an acknowledgement is
+//!   acknowledged if it reached the radio it is addressed to, and the
+//!   acknowledgement is not lost on the way back. Unacknowledged frames are
+//!   retransmitted up to `MAX_FRAME_RETRIES` times, like radios with
+//!   hardware retransmission do.
+//!
+//! Frames whose transmissions overlap at a receiver do not collide.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let medium = static_init!(
+//!     capsules::sim_radio::SimMedium<'static, VirtualMuxAlarm<'static, Clock>>,
+//!     capsules::sim_radio::SimMedium::new(medium_alarm)
+//! );
+//! medium_alarm.set_client(medium);
+//!
+//! let radio = static_init!(
+//!     capsules::sim_radio::SimRadio<'static, VirtualMuxAlarm<'static, Clock>>,
+//!     capsules::sim_radio::SimRadio::new(medium)
+//! );
+//! medium.add_radio(radio);
+//! // `radio` is then used like any other `kernel::hil::radio::Radio`
+//! ```
+
+use crate::net::ieee802154::{Header, MacAddress};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+
+/// Number of radios a medium can connect
+pub const MAX_NODES: usize = 8;
+
+/// Retransmissions of a frame that is not acknowledged
+pub const MAX_FRAME_RETRIES: u8 = 3;
+
+// Airtime of the synchronization header and PHR, and of one byte, at
+// 250 kb/s
+const SHR_PHR_US: u32 = 6 * 32;
+const BYTE_US: u32 = 32;
+
+// Acknowledgement request bit of the frame control
+const FRAME_ACK_REQUEST: u8 = 0x20;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Link {
+    disconnected: bool,
+    /// Frames lost, in parts per thousand
+    loss: u16,
+}
+
+/// Counters of the medium, for checking the behavior of the stack.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    /// Transmissions, including retransmissions
+    pub transmissions: u32,
+    /// Frames received by a radio
+    pub deliveries: u32,
+    /// Frames lost on a link
+    pub losses: u32,
+    /// Frames dropped because the receiver had no receive buffer
+    pub overruns: u32,
+}
+
+pub struct SimMedium<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    radios: [Cell<Option<&'a SimRadio<'a, A>>>; MAX_NODES],
+    links: Cell<[[Link; MAX_NODES]; MAX_NODES]>,
+    latency: Cell<u32>,
+    random: Cell<u32>,
+    stats: Cell<SimStats>,
+}
+
+impl<A: Alarm<'a>> SimMedium<'a, A> {
+    pub fn new(alarm: &'a A) -> SimMedium<'a, A> {
+        SimMedium {
+            alarm: alarm,
+            radios: Default::default(),
+            links: Cell::new([[Link::default(); MAX_NODES]; MAX_NODES]),
+            latency: Cell::new(0),
+            random: Cell::new(0x1234_5678),
+            stats: Cell::new(SimStats::default()),
+        }
+    }
+
+    /// Attaches `radio` to the medium, and returns its node number, which
+    /// identifies it in `set_connected` and `set_loss`.
+    pub fn add_radio(&self, radio: &'a SimRadio<'a, A>) -> Option<usize> {
+        let node = self.radios.iter().position(|slot| slot.get().is_none())?;
+        self.radios[node].set(Some(radio));
+        radio.node.set(node);
+        Some(node)
+    }
+
+    /// Connects or disconnects nodes `a` and `b`, in both directions.
+    pub fn set_connected(&self, a: usize, b: usize, connected: bool) {
+        let mut links = self.links.get();
+        links[a][b].disconnected = !connected;
+        links[b][a].disconnected = !connected;
+        self.links.set(links);
+    }
+
+    /// Sets the share of frames from `from` to `to` that are lost, in parts
+    /// per thousand.
+    pub fn set_loss(&self, from: usize, to: usize, per_mille: u16) {
+        let mut links = self.links.get();
+        links[from][to].loss = per_mille;
+        self.links.set(links);
+    }
+
+    /// Sets the time frames take to propagate, in microseconds, on top of
+    /// their airtime.
+    pub fn set_latency(&self, us: u32) {
+        self.latency.set(us);
+    }
+
+    /// Seeds the generator that losses are drawn from.
+    pub fn seed(&self, seed: u32) {
+        self.random.set(seed | 1);
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.stats.get()
+    }
+
+    fn update_stats<F: FnOnce(&mut SimStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    // Xorshift, as in the radio drivers
+    fn random(&self) -> u32 {
+        let mut next = self.random.get();
+        next ^= next << 13;
+        next ^= next >> 17;
+        next ^= next << 5;
+        self.random.set(next);
+        next
+    }
+
+    fn lost(&self, from: usize, to: usize) -> bool {
+        let link = self.links.get()[from][to];
+        let lost = link.disconnected || (self.random() % 1000) < link.loss as u32;
+        if lost {
+            self.update_stats(|stats| stats.losses += 1);
+        }
+        lost
+    }
+
+    fn ticks(us: u32) -> u32 {
+        (us as u64 * <A::Frequency>::frequency() as u64 / 1_000_000) as u32
+    }
+
+    fn due(&self, when: u32) -> bool {
+        self.alarm.now().wrapping_sub(when) < (1 << 31)
+    }
+
+    // Starts sending the frame held by `radio`
+    fn start_transmission(&self, radio: &SimRadio<'a, A>) {
+        self.update_stats(|stats| stats.transmissions += 1);
+        let airtime = SHR_PHR_US + (radio.tx_len.get() + radio::MFR_SIZE) as u32 * BYTE_US;
+        let end = self
+            .alarm
+            .now()
+            .wrapping_add(Self::ticks(airtime + self.latency.get()));
+        radio.tx_end.set(Some(end));
+        self.schedule();
+    }
+
+    // Arms the alarm for the next event of any radio
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .radios
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter_map(|radio| {
+                if radio.power_pending.get() || radio.config_pending.get() {
+                    Some(now)
+                } else {
+                    radio.tx_end.get()
+                }
+            })
+            .min_by_key(|when| {
+                if self.due(*when) {
+                    0
+                } else {
+                    when.wrapping_sub(now)
+                }
+            });
+        match next {
+            Some(when) => self.alarm.set_alarm(when),
+            None => self.alarm.disable(),
+        }
+    }
+
+    fn transmission_ended(&self, sender: &SimRadio<'a, A>) {
+        let buf = match sender.tx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let len = sender.tx_len.get();
+        let frame = &buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len];
+        let ack_requested = frame.get(0).map_or(false, |fc| fc & FRAME_ACK_REQUEST != 0);
+        let dst = Header::decode(frame, false)
+            .done()
+            .and_then(|(_, (header, _))| header.dst_addr);
+
+        let mut acked = false;
+        for receiver in self.radios.iter().filter_map(|slot| slot.get()) {
+            let (from, to) = (sender.node.get(), receiver.node.get());
+            if from == to
+                || !receiver.on.get()
+                || receiver.tx_end.get().is_some()
+                || receiver.channel.get() != sender.channel.get()
+                || self.lost(from, to)
+            {
+                continue;
+            }
+            let addressed = match dst {
+                Some(MacAddress::Short(0xffff)) | None => false,
+                Some(MacAddress::Short(addr)) => addr == receiver.addr.get(),
+                Some(MacAddress::Long(addr)) => addr == receiver.addr_long.get(),
+            };
+            receiver.deliver(frame);
+            if addressed && ack_requested && !self.lost(to, from) {
+                acked = true;
+            }
+        }
+
+        let unicast = match dst {
+            Some(MacAddress::Short(0xffff)) | None => false,
+            _ => true,
+        };
+        if ack_requested && unicast && !acked && sender.tx_retries.get() < MAX_FRAME_RETRIES {
+            sender.tx_retries.set(sender.tx_retries.get() + 1);
+            sender.tx_buf.replace(buf);
+            self.start_transmission(sender);
+            return;
+        }
+        sender.tx_retries.set(0);
+        sender.tx_client.map(move |client| {
+            client.send_done(buf, acked, ReturnCode::SUCCESS);
+        });
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for SimMedium<'a, A> {
+    fn fired(&self) {
+        for radio in self.radios.iter().filter_map(|slot| slot.get()) {
+            if radio.power_pending.get() {
+                radio.power_pending.set(false);
+                let on = radio.on.get();
+                radio.power_client.map(|client| client.changed(on));
+            }
+            if radio.config_pending.get() {
+                radio.config_pending.set(false);
+                radio
+                    .config_client
+                    .map(|client| client.config_done(ReturnCode::SUCCESS));
+            }
+            if let Some(end) = radio.tx_end.get() {
+                if self.due(end) {
+                    radio.tx_end.set(None);
+                    self.transmission_ended(radio);
+                }
+            }
+        }
+        self.schedule();
+    }
+}
+
+pub struct SimRadio<'a, A: Alarm<'a>> {
+    medium: &'a SimMedium<'a, A>,
+    node: Cell<usize>,
+    on: Cell<bool>,
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_retries: Cell<u8>,
+    // End of the transmission in progress
+    tx_end: Cell<Option<u32>>,
+    rx_buf: TakeCell<'static, [u8]>,
+    // Callbacks to issue on the next alarm
+    power_pending: Cell<bool>,
+    config_pending: Cell<bool>,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+}
+
+impl<A: Alarm<'a>> SimRadio<'a, A> {
+    pub fn new(medium: &'a SimMedium<'a, A>) -> SimRadio<'a, A> {
+        SimRadio {
+            medium: medium,
+            node: Cell::new(0),
+            on: Cell::new(false),
+            addr: Cell::new(0),
+            addr_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            channel: Cell::new(26),
+            tx_power: Cell::new(0),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_retries: Cell::new(0),
+            tx_end: Cell::new(None),
+            rx_buf: TakeCell::empty(),
+            power_pending: Cell::new(false),
+            config_pending: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+        }
+    }
+
+    /// The node number of this radio on its medium
+    pub fn node(&self) -> usize {
+        self.node.get()
+    }
+
+    fn deliver(&self, frame: &[u8]) {
+        let len = frame.len();
+        match self.rx_buf.take() {
+            Some(buf) if self.rx_client.is_some() && buf.len() >= radio::PSDU_OFFSET + len => {
+                self.medium.update_stats(|stats| stats.deliveries += 1);
+                buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len].copy_from_slice(frame);
+                buf[1] = (len + radio::MFR_SIZE) as u8;
+                self.rx_client.map(move |client| {
+                    client.receive(buf, len, true, ReturnCode::SUCCESS);
+                });
+            }
+            buf => {
+                self.medium.update_stats(|stats| stats.overruns += 1);
+                buf.map(|buf| self.rx_buf.replace(buf));
+            }
+        }
+    }
+}
+
+impl<A: Alarm<'a>> radio::Radio for SimRadio<'a, A> {}
+
+impl<A: Alarm<'a>> radio::RadioConfig for SimRadio<'a, A> {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.stop()
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.on.set(true);
+        self.power_pending.set(true);
+        self.medium.schedule();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.on.set(false);
+        self.power_pending.set(true);
+        self.medium.schedule();
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_buf.is_some()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.config_pending.set(true);
+        self.medium.schedule();
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.tx_power.set(power);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan >= 11 && chan <= 26 {
+            self.channel.set(chan);
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+}
+
+impl<A: Alarm<'a>> radio::RadioData for SimRadio<'a, A> {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'static dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.rx_client.set(client);
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.on.get() {
+            return (ReturnCode::EOFF, Some(spi_buf));
+        } else if self.tx_buf.is_some() {
+            return (ReturnCode::EBUSY, Some(spi_buf));
+        } else if frame_len + radio::MFR_SIZE > radio::MAX_FRAME_SIZE
+            || radio::PSDU_OFFSET + frame_len > spi_buf.len()
+        {
+            return (ReturnCode::ESIZE, Some(spi_buf));
+        }
+        self.tx_len.set(frame_len);
+        self.tx_retries.set(0);
+        self.tx_buf.replace(spi_buf);
+        self.medium.start_transmission(self);
+        (ReturnCode::SUCCESS, None)
+    }
+}
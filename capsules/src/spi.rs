@@ -12,6 +12,8 @@ use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Spi as usize;
+/// Syscall driver number of `SpiSlave`.
+pub const SLAVE_DRIVER_NUM: usize = driver::NUM::SpiSlave as usize;
 
 /// Suggested length for the Spi read and write buffer
 pub const DEFAULT_READ_BUF_LENGTH: usize = 1024;
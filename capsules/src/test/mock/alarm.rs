@@ -0,0 +1,116 @@
+//! Mock `hil::time::Alarm`.
+//!
+//! Time only passes when the test advances it. The alarm fires when the
+//! counter reaches the alarm value, so an alarm set in the past only fires
+//! once the counter wraps around to it, or when the test fires it. To catch
+//! clients that read the counter and then arm the alarm for that value, the
+//! test can make the counter tick on every read.
+
+use super::CallLog;
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Freq32KHz};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    SetAlarm(u32),
+    Disable,
+}
+
+/// Callbacks an alarm client can trigger from a single `advance` before the
+/// mock decides that it re-arms the alarm at the current time forever.
+const MAX_FIRES_PER_ADVANCE: usize = 10_000;
+
+pub struct MockAlarm<'a> {
+    now: Cell<u32>,
+    tics_per_read: Cell<u32>,
+    alarm: Cell<u32>,
+    enabled: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    pub calls: CallLog<Call>,
+}
+
+impl<'a> MockAlarm<'a> {
+    pub fn new() -> MockAlarm<'a> {
+        MockAlarm {
+            now: Cell::new(0),
+            tics_per_read: Cell::new(0),
+            alarm: Cell::new(0),
+            enabled: Cell::new(false),
+            client: OptionalCell::empty(),
+            calls: CallLog::new(),
+        }
+    }
+
+    pub fn set_now(&self, now: u32) {
+        self.now.set(now);
+    }
+
+    /// Makes the counter advance by `tics` after every call to `now`,
+    /// without firing the alarm.
+    pub fn set_tics_per_read(&self, tics: u32) {
+        self.tics_per_read.set(tics);
+    }
+
+    /// Advances the counter by `tics`, firing the alarm each time the
+    /// counter reaches it.
+    pub fn advance(&self, tics: u32) {
+        let mut left = tics;
+        for _ in 0..MAX_FIRES_PER_ADVANCE {
+            let until_alarm = self.alarm.get().wrapping_sub(self.now.get());
+            if !self.enabled.get() || until_alarm > left {
+                self.now.set(self.now.get().wrapping_add(left));
+                return;
+            }
+            self.now.set(self.alarm.get());
+            left -= until_alarm;
+            self.fire();
+        }
+        panic!("the alarm client keeps setting the alarm to the current time");
+    }
+
+    /// Fires the alarm now, whether or not the counter has reached it.
+    pub fn fire(&self) {
+        self.enabled.set(false);
+        self.client.map(|client| client.fired());
+    }
+}
+
+impl time::Time for MockAlarm<'_> {
+    type Frequency = Freq32KHz;
+
+    fn now(&self) -> u32 {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(self.tics_per_read.get()));
+        now
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl<'a> time::Alarm<'a> for MockAlarm<'a> {
+    fn set_alarm(&self, tics: u32) {
+        self.calls.record(Call::SetAlarm(tics));
+        self.alarm.set(tics);
+        self.enabled.set(true);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn disable(&self) {
+        self.calls.record(Call::Disable);
+        self.enabled.set(false);
+    }
+}
@@ -0,0 +1,176 @@
+//! Mock `hil::block_storage::BlockStorage`.
+//!
+//! The blocks live in a buffer the test provides, so a test can prepare a
+//! disk image and inspect it afterwards. An operation stays pending until the
+//! test completes it: a successful completion carries the operation out,
+//! while a failed one leaves the blocks as they were.
+
+use super::{CallLog, Failure};
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient, BLOCK_SIZE};
+use kernel::ReturnCode;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Read(u32),
+    Write(u32),
+}
+
+pub struct MockBlockStorage<'a> {
+    blocks: RefCell<&'static mut [u8]>,
+    present: Cell<bool>,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+    buffer: TakeCell<'static, [u8]>,
+    pending: Cell<Option<Call>>,
+    writes: Cell<usize>,
+    failure: Failure,
+    pub calls: CallLog<Call>,
+}
+
+impl<'a> MockBlockStorage<'a> {
+    /// A medium with the contents of `blocks`, whose length should be a
+    /// multiple of `BLOCK_SIZE`.
+    pub fn new(blocks: &'static mut [u8]) -> MockBlockStorage<'a> {
+        MockBlockStorage {
+            blocks: RefCell::new(blocks),
+            present: Cell::new(true),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(None),
+            writes: Cell::new(0),
+            failure: Failure::new(),
+            calls: CallLog::new(),
+        }
+    }
+
+    /// Makes the next operation fail with `error` when it is started.
+    pub fn fail_next(&self, error: ReturnCode) {
+        self.failure.set(error);
+    }
+
+    /// The operation waiting for the test to complete it.
+    pub fn pending(&self) -> Option<Call> {
+        self.pending.get()
+    }
+
+    /// Number of blocks written so far.
+    pub fn writes(&self) -> usize {
+        self.writes.get()
+    }
+
+    /// Calls `f` with the contents of the medium.
+    pub fn with_blocks<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.blocks.borrow_mut())
+    }
+
+    /// Inserts or removes the medium, and tells the client.
+    pub fn set_present(&self, present: bool) {
+        self.present.set(present);
+        self.client.map(|client| client.medium_changed());
+    }
+
+    /// Completes the pending operation with `rcode`, carrying it out if it
+    /// succeeds.
+    pub fn complete(&self, rcode: ReturnCode) {
+        let call = match self.pending.take() {
+            Some(call) => call,
+            None => return,
+        };
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let succeeded = rcode == ReturnCode::SUCCESS;
+        match call {
+            Call::Read(block) => {
+                if succeeded {
+                    let start = block as usize * BLOCK_SIZE;
+                    buffer[..BLOCK_SIZE]
+                        .copy_from_slice(&self.blocks.borrow()[start..start + BLOCK_SIZE]);
+                }
+                self.client
+                    .map(move |client| client.read_done(buffer, rcode));
+            }
+            Call::Write(block) => {
+                if succeeded {
+                    let start = block as usize * BLOCK_SIZE;
+                    self.blocks.borrow_mut()[start..start + BLOCK_SIZE]
+                        .copy_from_slice(&buffer[..BLOCK_SIZE]);
+                    self.writes.set(self.writes.get() + 1);
+                }
+                self.client
+                    .map(move |client| client.write_done(buffer, rcode));
+            }
+        }
+    }
+
+    /// Completes operations successfully until the client stops starting
+    /// new ones, and returns how many were completed.
+    pub fn complete_all(&self) -> usize {
+        let mut completed = 0;
+        while self.pending.get().is_some() {
+            self.complete(ReturnCode::SUCCESS);
+            completed += 1;
+        }
+        completed
+    }
+
+    fn start(
+        &self,
+        call: Call,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.calls.record(call);
+        let rcode = if let Some(error) = self.failure.take() {
+            error
+        } else if self.pending.get().is_some() {
+            ReturnCode::EBUSY
+        } else if !self.present.get() {
+            ReturnCode::EOFF
+        } else if self.block_count().map_or(true, |count| block >= count)
+            || buffer.len() < BLOCK_SIZE
+        {
+            ReturnCode::EINVAL
+        } else {
+            self.pending.set(Some(call));
+            self.buffer.replace(buffer);
+            return (ReturnCode::SUCCESS, None);
+        };
+        (rcode, Some(buffer))
+    }
+}
+
+impl<'a> BlockStorage<'a> for MockBlockStorage<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_count(&self) -> Option<u32> {
+        if self.present.get() {
+            Some((self.blocks.borrow().len() / BLOCK_SIZE) as u32)
+        } else {
+            None
+        }
+    }
+
+    fn read_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start(Call::Read(block), buffer, block)
+    }
+
+    fn write_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start(Call::Write(block), buffer, block)
+    }
+}
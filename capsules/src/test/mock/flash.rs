@@ -0,0 +1,223 @@
+//! Mock `hil::flash::Flash`.
+//!
+//! The flash holds `PAGES` pages of `PAGE_SIZE` bytes in memory, and starts
+//! out erased. Like NOR flash, writing a page only clears bits. An operation
+//! stays pending until the test completes it: a successful completion
+//! carries the operation out, while a failed one leaves the flash as it
+//! was.
+
+use super::{CallLog, Failure};
+use core::cell::{Cell, RefCell};
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash;
+use kernel::ReturnCode;
+
+pub const PAGE_SIZE: usize = 512;
+pub const PAGES: usize = 16;
+
+const ERASED: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+pub struct MockPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockPage {
+    fn default() -> Self {
+        MockPage([0; PAGE_SIZE])
+    }
+}
+
+impl Index<usize> for MockPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for MockPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for MockPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+pub struct MockFlash<'a> {
+    pages: RefCell<[[u8; PAGE_SIZE]; PAGES]>,
+    client: OptionalCell<&'a dyn flash::Client<MockFlash<'a>>>,
+    buffer: TakeCell<'static, MockPage>,
+    pending: Cell<Option<Call>>,
+    erases: Cell<[usize; PAGES]>,
+    failure: Failure,
+    pub calls: CallLog<Call>,
+}
+
+impl<'a> MockFlash<'a> {
+    pub fn new() -> MockFlash<'a> {
+        MockFlash {
+            pages: RefCell::new([[ERASED; PAGE_SIZE]; PAGES]),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(None),
+            erases: Cell::new([0; PAGES]),
+            failure: Failure::new(),
+            calls: CallLog::new(),
+        }
+    }
+
+    /// Makes the next operation fail with `error` when it is started.
+    pub fn fail_next(&self, error: ReturnCode) {
+        self.failure.set(error);
+    }
+
+    /// The operation waiting for the test to complete it.
+    pub fn pending(&self) -> Option<Call> {
+        self.pending.get()
+    }
+
+    /// Number of times a page was erased.
+    pub fn erase_count(&self, page_number: usize) -> usize {
+        self.erases.get()[page_number]
+    }
+
+    /// Abandons the pending operation without carrying it out or calling
+    /// back, as if power was lost. The buffer of the operation is lost too.
+    pub fn lose_power(&self) {
+        self.pending.set(None);
+        self.buffer.take();
+    }
+
+    /// Calls `f` with the contents of a page.
+    pub fn with_page<F, R>(&self, page_number: usize, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.pages.borrow()[page_number])
+    }
+
+    /// Sets the contents of a page, from offset 0, bypassing the flash
+    /// semantics.
+    pub fn set_page(&self, page_number: usize, data: &[u8]) {
+        self.pages.borrow_mut()[page_number][..data.len()].copy_from_slice(data);
+    }
+
+    /// Completes the pending operation with `error`, carrying it out if it
+    /// succeeds.
+    pub fn complete(&self, error: flash::Error) {
+        let call = match self.pending.take() {
+            Some(call) => call,
+            None => return,
+        };
+        let succeeded = error == flash::Error::CommandComplete;
+        match call {
+            Call::Read(page_number) => {
+                self.buffer.take().map(|buffer| {
+                    if succeeded {
+                        buffer.0.copy_from_slice(&self.pages.borrow()[page_number]);
+                    }
+                    self.client
+                        .map(move |client| client.read_complete(buffer, error));
+                });
+            }
+            Call::Write(page_number) => {
+                self.buffer.take().map(|buffer| {
+                    if succeeded {
+                        let mut pages = self.pages.borrow_mut();
+                        for (byte, new) in pages[page_number].iter_mut().zip(buffer.0.iter()) {
+                            *byte &= *new;
+                        }
+                    }
+                    self.client
+                        .map(move |client| client.write_complete(buffer, error));
+                });
+            }
+            Call::Erase(page_number) => {
+                if succeeded {
+                    self.pages.borrow_mut()[page_number] = [ERASED; PAGE_SIZE];
+                    let mut erases = self.erases.get();
+                    erases[page_number] += 1;
+                    self.erases.set(erases);
+                }
+                self.client.map(|client| client.erase_complete(error));
+            }
+        }
+    }
+
+    /// Completes operations successfully until the client stops starting
+    /// new ones, and returns how many were completed.
+    pub fn complete_all(&self) -> usize {
+        let mut completed = 0;
+        while self.pending.get().is_some() {
+            self.complete(flash::Error::CommandComplete);
+            completed += 1;
+        }
+        completed
+    }
+
+    fn start(&self, call: Call, page_number: usize) -> ReturnCode {
+        self.calls.record(call);
+        if let Some(error) = self.failure.take() {
+            error
+        } else if self.pending.get().is_some() {
+            ReturnCode::EBUSY
+        } else if page_number >= PAGES {
+            ReturnCode::EINVAL
+        } else {
+            self.pending.set(Some(call));
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a, C: flash::Client<Self>> flash::HasClient<'a, C> for MockFlash<'a> {
+    fn set_client(&'a self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl flash::Flash for MockFlash<'_> {
+    type Page = MockPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(Call::Read(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(Call::Write(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(Call::Erase(page_number), page_number)
+    }
+}
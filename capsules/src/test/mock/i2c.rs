@@ -0,0 +1,139 @@
+//! Mock `hil::i2c::I2CMaster`.
+//!
+//! A command stays pending until the test completes it, with the data the
+//! slave answers and the outcome of the command.
+
+use super::CallLog;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2c;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Enable,
+    Disable,
+    Write {
+        addr: u8,
+        len: u8,
+    },
+    Read {
+        addr: u8,
+        len: u8,
+    },
+    WriteRead {
+        addr: u8,
+        write_len: u8,
+        read_len: u8,
+    },
+}
+
+pub struct MockI2CMaster {
+    client: OptionalCell<&'static dyn i2c::I2CHwMasterClient>,
+    buffer: TakeCell<'static, [u8]>,
+    write_len: Cell<usize>,
+    enabled: Cell<bool>,
+    pub calls: CallLog<Call>,
+}
+
+impl MockI2CMaster {
+    pub fn new() -> MockI2CMaster {
+        MockI2CMaster {
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            write_len: Cell::new(0),
+            enabled: Cell::new(false),
+            calls: CallLog::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    /// Calls `f` with the bytes written by the pending command.
+    pub fn with_written<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.write_len.get();
+        self.buffer.map(|buffer| f(&buffer[..len]))
+    }
+
+    /// Completes the pending command with `error`. The bytes of `read` are
+    /// copied to the start of the buffer, as the slave's answer to a read.
+    pub fn complete(&self, read: &[u8], error: i2c::Error) {
+        if let Some(buffer) = self.buffer.take() {
+            let len = core::cmp::min(read.len(), buffer.len());
+            buffer[..len].copy_from_slice(&read[..len]);
+            self.client
+                .map(move |client| client.command_complete(buffer, error));
+        }
+    }
+
+    fn start(&self, call: Call, buffer: &'static mut [u8], write_len: u8) {
+        self.calls.record(call);
+        // The HIL has no way to refuse a command, so this is a bug in the
+        // capsule under test
+        assert!(
+            self.buffer.is_none(),
+            "I2C command started while another one is pending"
+        );
+        self.write_len
+            .set(core::cmp::min(write_len as usize, buffer.len()));
+        self.buffer.replace(buffer);
+    }
+}
+
+impl i2c::I2CMaster for MockI2CMaster {
+    fn set_master_client(&self, master_client: &'static dyn i2c::I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        self.calls.record(Call::Enable);
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.calls.record(Call::Disable);
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        self.start(
+            Call::WriteRead {
+                addr: addr,
+                write_len: write_len,
+                read_len: read_len,
+            },
+            data,
+            write_len,
+        );
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        self.start(
+            Call::Write {
+                addr: addr,
+                len: len,
+            },
+            data,
+            len,
+        );
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.start(
+            Call::Read {
+                addr: addr,
+                len: len,
+            },
+            buffer,
+            0,
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Scriptable fake implementations of HILs, for unit testing capsules off
+//! target.
+//!
+//! Each mock records the calls a capsule makes to it, and holds on to the
+//! buffers of an operation until the test completes it, with data and an
+//! error of its choosing. A test drives a capsule one step at a time:
+//!
+//! ```rust,ignore
+//! let i2c = leak(MockI2CMaster::new());
+//! let device = leak(SomeSensor::new(i2c, buffer));
+//! i2c.set_master_client(device);
+//!
+//! device.start_reading();
+//! assert_eq!(i2c.calls.next(), Some(i2c::Call::WriteRead { addr: 0x40, write_len: 1, read_len: 2 }));
+//! i2c.complete(&[0x12, 0x34], hil::i2c::Error::CommandComplete);
+//! ```
+//!
+//! Operations that can fail synchronously fail with the error set by
+//! `fail_next`. Callbacks are only issued from the completion methods, never
+//! from within the call that started the operation.
+
+pub mod alarm;
+pub mod block;
+pub mod flash;
+pub mod i2c;
+pub mod spi;
+pub mod uart;
+
+use core::cell::Cell;
+use kernel::ReturnCode;
+
+/// Number of calls a mock remembers. Older calls are dropped.
+pub const MAX_CALLS: usize = 32;
+
+/// The calls made to a mock, oldest first.
+pub struct CallLog<C: Copy> {
+    calls: Cell<[Option<C>; MAX_CALLS]>,
+    len: Cell<usize>,
+}
+
+impl<C: Copy> CallLog<C> {
+    pub fn new() -> CallLog<C> {
+        CallLog {
+            calls: Cell::new([None; MAX_CALLS]),
+            len: Cell::new(0),
+        }
+    }
+
+    pub fn record(&self, call: C) {
+        let mut calls = self.calls.get();
+        let len = self.len.get();
+        if len == MAX_CALLS {
+            calls.rotate_left(1);
+            calls[MAX_CALLS - 1] = Some(call);
+        } else {
+            calls[len] = Some(call);
+            self.len.set(len + 1);
+        }
+        self.calls.set(calls);
+    }
+
+    /// Removes and returns the oldest call.
+    pub fn next(&self) -> Option<C> {
+        let mut calls = self.calls.get();
+        let call = calls[0].take();
+        if call.is_some() {
+            calls.rotate_left(1);
+            self.len.set(self.len.get() - 1);
+            self.calls.set(calls);
+        }
+        call
+    }
+
+    /// The most recent call.
+    pub fn last(&self) -> Option<C> {
+        match self.len.get() {
+            0 => None,
+            len => self.calls.get()[len - 1],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    pub fn clear(&self) {
+        self.calls.set([None; MAX_CALLS]);
+        self.len.set(0);
+    }
+}
+
+impl<C: Copy + PartialEq> CallLog<C> {
+    pub fn contains(&self, call: C) -> bool {
+        self.calls.get()[..self.len.get()].contains(&Some(call))
+    }
+}
+
+/// An error to return from the next call that can fail.
+pub struct Failure {
+    next: Cell<Option<ReturnCode>>,
+}
+
+impl Failure {
+    pub fn new() -> Failure {
+        Failure {
+            next: Cell::new(None),
+        }
+    }
+
+    pub fn set(&self, error: ReturnCode) {
+        self.next.set(Some(error));
+    }
+
+    /// The error to fail with, if any. It is only used once.
+    pub fn take(&self) -> Option<ReturnCode> {
+        self.next.take()
+    }
+}
@@ -0,0 +1,199 @@
+//! Mock `hil::spi::SpiMaster`.
+//!
+//! A buffer transfer stays pending until the test completes it, with the
+//! bytes the slave sends back. Single byte transfers complete immediately,
+//! and read the byte set with `set_read_byte`.
+
+use super::{CallLog, Failure};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity};
+use kernel::ReturnCode;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Init,
+    ReadWriteBytes { len: usize, read: bool },
+    WriteByte(u8),
+    ReadByte,
+    ReadWriteByte(u8),
+    ChipSelect(u8),
+    SetRate(u32),
+    SetClock(ClockPolarity),
+    SetPhase(ClockPhase),
+    HoldLow,
+    ReleaseLow,
+}
+
+pub struct MockSpiMaster {
+    client: OptionalCell<&'static dyn spi::SpiMasterClient>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    read_byte: Cell<u8>,
+    chip_select: Cell<Option<u8>>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    hold_low: Cell<bool>,
+    failure: Failure,
+    pub calls: CallLog<Call>,
+}
+
+impl MockSpiMaster {
+    pub fn new() -> MockSpiMaster {
+        MockSpiMaster {
+            client: OptionalCell::empty(),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            read_byte: Cell::new(0),
+            chip_select: Cell::new(None),
+            rate: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            hold_low: Cell::new(false),
+            failure: Failure::new(),
+            calls: CallLog::new(),
+        }
+    }
+
+    /// Makes the next buffer transfer fail with `error`. The HIL does not
+    /// hand the buffers back on errors, so they are lost.
+    pub fn fail_next(&self, error: ReturnCode) {
+        self.failure.set(error);
+    }
+
+    /// The byte that single byte reads return.
+    pub fn set_read_byte(&self, value: u8) {
+        self.read_byte.set(value);
+    }
+
+    /// The last chip select specified.
+    pub fn chip_select(&self) -> Option<u8> {
+        self.chip_select.get()
+    }
+
+    /// Whether chip select is held low after transfers.
+    pub fn is_held_low(&self) -> bool {
+        self.hold_low.get()
+    }
+
+    /// Calls `f` with the bytes written by the pending transfer.
+    pub fn with_written<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.len.get();
+        self.write_buffer.map(|buffer| f(&buffer[..len]))
+    }
+
+    /// Completes the pending transfer. The bytes of `read` are copied to the
+    /// read buffer, if the transfer has one.
+    pub fn complete(&self, read: &[u8]) {
+        if let Some(write_buffer) = self.write_buffer.take() {
+            let read_buffer = self.read_buffer.take().map(|buffer| {
+                let len = core::cmp::min(read.len(), buffer.len());
+                buffer[..len].copy_from_slice(&read[..len]);
+                buffer
+            });
+            let len = self.len.get();
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        }
+    }
+}
+
+impl spi::SpiMaster for MockSpiMaster {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static dyn spi::SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn init(&self) {
+        self.calls.record(Call::Init);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        self.calls.record(Call::ReadWriteBytes {
+            len: len,
+            read: read_buffer.is_some(),
+        });
+        if let Some(error) = self.failure.take() {
+            return error;
+        } else if self.write_buffer.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.len.set(core::cmp::min(len, write_buffer.len()));
+        self.write_buffer.replace(write_buffer);
+        read_buffer.map(|buffer| self.read_buffer.replace(buffer));
+        ReturnCode::SUCCESS
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.calls.record(Call::WriteByte(val));
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.calls.record(Call::ReadByte);
+        self.read_byte.get()
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        self.calls.record(Call::ReadWriteByte(val));
+        self.read_byte.get()
+    }
+
+    fn specify_chip_select(&self, cs: u8) {
+        self.calls.record(Call::ChipSelect(cs));
+        self.chip_select.set(Some(cs));
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.calls.record(Call::SetRate(rate));
+        self.rate.set(rate);
+        rate
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.calls.record(Call::SetClock(polarity));
+        self.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.calls.record(Call::SetPhase(phase));
+        self.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {
+        self.calls.record(Call::HoldLow);
+        self.hold_low.set(true);
+    }
+
+    fn release_low(&self) {
+        self.calls.record(Call::ReleaseLow);
+        self.hold_low.set(false);
+    }
+}
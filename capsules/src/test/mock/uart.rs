@@ -0,0 +1,185 @@
+//! Mock `hil::uart::Uart`.
+//!
+//! A transmission or reception stays pending until the test completes it.
+
+use super::{CallLog, Failure};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Configure { baud_rate: u32 },
+    Transmit { len: usize },
+    TransmitAbort,
+    Receive { len: usize },
+    ReceiveAbort,
+}
+
+pub struct MockUart<'a> {
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    failure: Failure,
+    pub calls: CallLog<Call>,
+}
+
+impl<'a> MockUart<'a> {
+    pub fn new() -> MockUart<'a> {
+        MockUart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            failure: Failure::new(),
+            calls: CallLog::new(),
+        }
+    }
+
+    /// Makes the next configuration, transmission or reception fail with
+    /// `error`.
+    pub fn fail_next(&self, error: ReturnCode) {
+        self.failure.set(error);
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    /// Length of the pending reception.
+    pub fn receive_len(&self) -> Option<usize> {
+        self.rx_buffer.map(|_| self.rx_len.get())
+    }
+
+    /// Calls `f` with the data of the pending transmission.
+    pub fn with_transmitted<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.tx_len.get();
+        self.tx_buffer.map(|buffer| f(&buffer[..len]))
+    }
+
+    /// Completes the pending transmission with `rcode`.
+    pub fn complete_transmit(&self, rcode: ReturnCode) {
+        if let Some(buffer) = self.tx_buffer.take() {
+            let len = self.tx_len.get();
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, rcode));
+        }
+    }
+
+    /// Completes the pending reception with `data`, which is truncated to
+    /// the length of the reception.
+    pub fn complete_receive(&self, data: &[u8], rcode: ReturnCode, error: uart::Error) {
+        if let Some(buffer) = self.rx_buffer.take() {
+            let len = core::cmp::min(data.len(), self.rx_len.get());
+            buffer[..len].copy_from_slice(&data[..len]);
+            self.rx_client
+                .map(move |client| client.received_buffer(buffer, len, rcode, error));
+        }
+    }
+
+    /// Receives `data` without errors.
+    pub fn receive(&self, data: &[u8]) {
+        self.complete_receive(data, ReturnCode::SUCCESS, uart::Error::None);
+    }
+}
+
+impl uart::Configure for MockUart<'_> {
+    fn configure(&self, params: uart::Parameters) -> ReturnCode {
+        self.calls.record(Call::Configure {
+            baud_rate: params.baud_rate,
+        });
+        self.failure.take().unwrap_or(ReturnCode::SUCCESS)
+    }
+}
+
+impl<'a> uart::Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.calls.record(Call::Transmit { len: tx_len });
+        if let Some(error) = self.failure.take() {
+            return (error, Some(tx_buffer));
+        } else if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        } else if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        self.calls.record(Call::TransmitAbort);
+        if self.tx_buffer.is_some() {
+            // The test completes the transmission, with ECANCEL
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.calls.record(Call::Receive { len: rx_len });
+        if let Some(error) = self.failure.take() {
+            return (error, Some(rx_buffer));
+        } else if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        } else if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        self.rx_len.set(rx_len);
+        self.rx_buffer.replace(rx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        self.calls.record(Call::ReceiveAbort);
+        if self.rx_buffer.is_some() {
+            // The test completes the reception, with ECANCEL
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::UartData<'a> for MockUart<'a> {}
+impl<'a> uart::Uart<'a> for MockUart<'a> {}
@@ -0,0 +1,551 @@
+//! A USB CDC-ACM (virtual serial port) function
+//!
+//! It adds an Abstract Control Model interface and a data interface to a
+//! `usb::composite::CompositeDevice`, and implements the UART HIL on top of
+//! the bulk endpoints of the data interface. This lets the console, the process
+//! console and `debug!()` run over the native USB port of a chip.
+//!
+//! The host opens the port by raising DTR with a `SET_CONTROL_LINE_STATE`
+//! request. Until it does, and after it closes the port again, transmissions
+//! fail with `EOFF` so that output is not held up by a port nobody reads.
+//! The line coding set by the host is stored and reported back, but has no
+//! effect on the transfers.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cdc = static_init!(
+//!     capsules::usb::cdc::CdcAcm<'static, nrf52::usbd::Usbd>,
+//!     capsules::usb::cdc::CdcAcm::new(&nrf52::usbd::USBD, dynamic_deferred_caller)
+//! );
+//! cdc.initialize_callback_handle(dynamic_deferred_caller.register(cdc).unwrap());
+//! usb.add_function(cdc, "Console");
+//! ```
+
+use super::composite::{CtrlRequestResult, FunctionDescriptors, UsbFunction};
+use super::descriptors::{
+    Buffer8, CdcInterfaceDescriptor, CdcInterfaceDescriptorSubType, EndpointAddress,
+    EndpointDescriptor, InterfaceDescriptor, RequestType, SetupData, TransferDirection,
+};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Interrupt IN endpoint for notifications to the host
+const ENDPOINT_NOTIFY: usize = 1;
+/// Bulk IN endpoint carrying data to the host
+const ENDPOINT_IN: usize = 2;
+/// Bulk OUT endpoint carrying data from the host
+const ENDPOINT_OUT: usize = 3;
+
+const N_ENDPOINTS: usize = 3;
+
+/// Maximum packet size of the data endpoints, which all supported controllers
+/// can handle.
+const MAX_PACKET_SIZE: usize = 8;
+
+// Class-specific requests of the Abstract Control Model
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+const SEND_BREAK: u8 = 0x23;
+
+/// Bit of `SET_CONTROL_LINE_STATE` signalling that the host opened the port
+const CONTROL_LINE_DTR: u16 = 1 << 0;
+
+/// 115200 baud, one stop bit, no parity and 8 data bits
+const DEFAULT_LINE_CODING: [u8; 7] = [0x00, 0xc2, 0x01, 0x00, 0, 0, 8];
+
+static INTERFACES: &'static [InterfaceDescriptor] = &[
+    InterfaceDescriptor {
+        interface_number: 0,
+        alternate_setting: 0,
+        num_endpoints: 0,         // Filled in by `CompositeDevice`
+        interface_class: 0x02,    // Communications
+        interface_subclass: 0x02, // Abstract Control Model
+        interface_protocol: 0x00, // No class-specific protocol
+        string_index: 0,
+    },
+    InterfaceDescriptor {
+        interface_number: 1,
+        alternate_setting: 0,
+        num_endpoints: 0,      // Filled in by `CompositeDevice`
+        interface_class: 0x0a, // CDC data
+        interface_subclass: 0x00,
+        interface_protocol: 0x00,
+        string_index: 0,
+    },
+];
+
+static CDC_DESCRIPTORS: &'static [CdcInterfaceDescriptor] = &[
+    CdcInterfaceDescriptor {
+        subtype: CdcInterfaceDescriptorSubType::Header,
+        field1: 0x10, // CDC 1.1
+        field2: 0x01,
+    },
+    CdcInterfaceDescriptor {
+        subtype: CdcInterfaceDescriptorSubType::CallManagement,
+        field1: 0x00, // No call management
+        field2: 0x01, // Data interface
+    },
+    CdcInterfaceDescriptor {
+        subtype: CdcInterfaceDescriptorSubType::AbstractControlManagement,
+        field1: 0x02, // Line coding and control line state requests
+        field2: 0x00,
+    },
+    CdcInterfaceDescriptor {
+        subtype: CdcInterfaceDescriptorSubType::Union,
+        field1: 0x00, // Communications interface
+        field2: 0x01, // Data interface
+    },
+];
+
+static NOTIFY_ENDPOINTS: &'static [EndpointDescriptor] = &[EndpointDescriptor {
+    endpoint_address: EndpointAddress::new_const(ENDPOINT_NOTIFY, TransferDirection::DeviceToHost),
+    transfer_type: TransferType::Interrupt,
+    max_packet_size: MAX_PACKET_SIZE as u16,
+    interval: 100,
+}];
+
+static DATA_ENDPOINTS: &'static [EndpointDescriptor] = &[
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(ENDPOINT_IN, TransferDirection::DeviceToHost),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 100,
+    },
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(ENDPOINT_OUT, TransferDirection::HostToDevice),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 100,
+    },
+];
+
+static INTERFACE_ENDPOINTS: &'static [&'static [EndpointDescriptor]] =
+    &[NOTIFY_ENDPOINTS, DATA_ENDPOINTS];
+
+/// State of a class-specific control transfer
+#[derive(Copy, Clone, PartialEq)]
+enum CtrlState {
+    Idle,
+    /// The host is about to send the line coding
+    SetLineCoding,
+}
+
+pub struct CdcAcm<'a, C: 'a> {
+    controller: &'a C,
+    // Device endpoint number of the notification endpoint
+    first_endpoint: Cell<usize>,
+
+    // An eight-byte buffer for each endpoint
+    buffers: [Buffer8; N_ENDPOINTS],
+
+    ctrl_state: Cell<CtrlState>,
+    line_coding: Cell<[u8; 7]>,
+    port_open: Cell<bool>,
+
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+    // Set once the transmission is over, until its callback is issued
+    tx_result: OptionalCell<ReturnCode>,
+    // Whether the controller waits for `endpoint_resume_in()`
+    in_delayed: Cell<bool>,
+
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_offset: Cell<usize>,
+    rx_abort: Cell<bool>,
+    // The last OUT packet, until reads have consumed it
+    rx_packet: [Cell<u8>; MAX_PACKET_SIZE],
+    rx_packet_len: Cell<usize>,
+    rx_packet_offset: Cell<usize>,
+    // Whether the controller waits for `endpoint_resume_out()`
+    out_delayed: Cell<bool>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> CdcAcm<'a, C> {
+    pub fn new(controller: &'a C, deferred_caller: &'a DynamicDeferredCall) -> Self {
+        CdcAcm {
+            controller,
+            first_endpoint: Cell::new(ENDPOINT_NOTIFY),
+            buffers: Default::default(),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            line_coding: Cell::new(DEFAULT_LINE_CODING),
+            port_open: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            tx_result: OptionalCell::empty(),
+            in_delayed: Cell::new(true),
+            rx_client: OptionalCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_offset: Cell::new(0),
+            rx_abort: Cell::new(false),
+            rx_packet: Default::default(),
+            rx_packet_len: Cell::new(0),
+            rx_packet_offset: Cell::new(0),
+            out_delayed: Cell::new(false),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Whether the host has opened the serial port.
+    pub fn is_open(&self) -> bool {
+        self.port_open.get()
+    }
+
+    /// The device endpoint number of an endpoint of the function
+    fn endpoint(&self, endpoint: usize) -> usize {
+        self.first_endpoint.get() + endpoint - ENDPOINT_NOTIFY
+    }
+
+    fn schedule_callback(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Whether part of the current transmission still has to be sent.
+    fn tx_pending(&self) -> bool {
+        self.tx_buffer.is_some()
+            && self.tx_result.is_none()
+            && self.tx_offset.get() < self.tx_len.get()
+    }
+
+    /// Ends the current transmission, if any, with `ECANCEL`.
+    fn cancel_transmit(&self) -> bool {
+        if self.tx_buffer.is_some() && self.tx_result.is_none() {
+            self.tx_result.set(ReturnCode::ECANCEL);
+            self.schedule_callback();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_port_open(&self, open: bool) {
+        self.port_open.set(open);
+        if !open {
+            self.cancel_transmit();
+        }
+    }
+
+    /// Copies as much of the last OUT packet as fits into the pending read,
+    /// and completes the read once it is full.
+    fn deliver_received(&self) {
+        let pending = self.rx_packet_len.get() - self.rx_packet_offset.get();
+        if pending == 0 {
+            return;
+        }
+        self.rx_buffer.take().map(|buffer| {
+            let offset = self.rx_offset.get();
+            let count = cmp::min(pending, self.rx_len.get() - offset);
+            let start = self.rx_packet_offset.get();
+            for i in 0..count {
+                buffer[offset + i] = self.rx_packet[start + i].get();
+            }
+            self.rx_offset.set(offset + count);
+            self.rx_packet_offset.set(start + count);
+
+            if offset + count == self.rx_len.get() {
+                let len = self.rx_len.get();
+                self.rx_client.map(move |client| {
+                    client.received_buffer(buffer, len, ReturnCode::SUCCESS, uart::Error::None)
+                });
+            } else {
+                self.rx_buffer.replace(buffer);
+            }
+        });
+
+        if self.rx_packet_offset.get() == self.rx_packet_len.get() && self.out_delayed.take() {
+            // The packet is consumed, let the controller hand over the next
+            // one. This may deliver it right away.
+            self.controller
+                .endpoint_resume_out(self.endpoint(ENDPOINT_OUT));
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> UsbFunction<'a> for CdcAcm<'a, C> {
+    fn descriptors(&self) -> FunctionDescriptors {
+        FunctionDescriptors {
+            interfaces: INTERFACES,
+            endpoints: INTERFACE_ENDPOINTS,
+            cdc_descriptors: CDC_DESCRIPTORS,
+            hid_descriptor: None,
+            report_descriptor: None,
+            dfu_descriptor: None,
+        }
+    }
+
+    fn set_first_endpoint(&self, endpoint: usize) {
+        self.first_endpoint.set(endpoint);
+    }
+
+    fn enable(&'a self) {
+        self.controller
+            .endpoint_set_in_buffer(self.endpoint(ENDPOINT_NOTIFY), &self.buffers[0].buf);
+        self.controller
+            .endpoint_in_enable(TransferType::Interrupt, self.endpoint(ENDPOINT_NOTIFY));
+
+        self.controller
+            .endpoint_set_in_buffer(self.endpoint(ENDPOINT_IN), &self.buffers[1].buf);
+        self.controller
+            .endpoint_in_enable(TransferType::Bulk, self.endpoint(ENDPOINT_IN));
+
+        self.controller
+            .endpoint_set_out_buffer(self.endpoint(ENDPOINT_OUT), &self.buffers[2].buf);
+        self.controller
+            .endpoint_out_enable(TransferType::Bulk, self.endpoint(ENDPOINT_OUT));
+    }
+
+    fn bus_reset(&'a self) {
+        // The host has to open the port again after a reset
+        self.set_port_open(false);
+        self.ctrl_state.set(CtrlState::Idle);
+        self.in_delayed.set(true);
+        self.out_delayed.set(false);
+        self.rx_packet_len.set(0);
+        self.rx_packet_offset.set(0);
+    }
+
+    /// Handle a class-specific request of the Abstract Control Model
+    fn ctrl_setup(&'a self, setup: &SetupData, data: &[Cell<u8>]) -> CtrlRequestResult {
+        if let RequestType::Class = setup.request_type.request_type() {
+            match setup.request_code {
+                SET_LINE_CODING => {
+                    self.ctrl_state.set(CtrlState::SetLineCoding);
+                    CtrlRequestResult::Ok
+                }
+                GET_LINE_CODING => CtrlRequestResult::data(data, &self.line_coding.get()),
+                SET_CONTROL_LINE_STATE => {
+                    self.set_port_open(setup.value & CONTROL_LINE_DTR != 0);
+                    CtrlRequestResult::Ok
+                }
+                SEND_BREAK => CtrlRequestResult::Ok,
+                _ => CtrlRequestResult::Error,
+            }
+        } else {
+            CtrlRequestResult::Error
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, data: &[VolatileCell<u8>]) -> hil::usb::CtrlOutResult {
+        if self.ctrl_state.get() == CtrlState::SetLineCoding {
+            let mut line_coding = self.line_coding.get();
+            let len = cmp::min(data.len(), line_coding.len());
+            for i in 0..len {
+                line_coding[i] = data[i].get();
+            }
+            self.line_coding.set(line_coding);
+            hil::usb::CtrlOutResult::Ok
+        } else {
+            hil::usb::CtrlOutResult::Halted
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self) {
+        self.ctrl_state.set(CtrlState::Idle);
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, endpoint: usize) -> hil::usb::InResult {
+        match endpoint {
+            ENDPOINT_NOTIFY => {
+                // Serial state notifications are not supported
+                hil::usb::InResult::Delay
+            }
+            ENDPOINT_IN => {
+                if !self.tx_pending() {
+                    self.in_delayed.set(true);
+                    return hil::usb::InResult::Delay;
+                }
+                let packet = &self.buffers[1].buf;
+                let offset = self.tx_offset.get();
+                let count = cmp::min(packet.len(), self.tx_len.get() - offset);
+                self.tx_buffer.map(|buffer| {
+                    for i in 0..count {
+                        packet[i].set(buffer[offset + i]);
+                    }
+                });
+                self.tx_offset.set(offset + count);
+                if offset + count == self.tx_len.get() {
+                    // The whole buffer has been handed to the controller
+                    self.tx_result.set(ReturnCode::SUCCESS);
+                    self.schedule_callback();
+                }
+                hil::usb::InResult::Packet(count)
+            }
+            _ => hil::usb::InResult::Error,
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::OutResult {
+        if endpoint != ENDPOINT_OUT {
+            return hil::usb::OutResult::Error;
+        }
+        if self.rx_packet_offset.get() < self.rx_packet_len.get() {
+            // The previous packet has not been read yet
+            self.out_delayed.set(true);
+            return hil::usb::OutResult::Delay;
+        }
+        let packet = &self.buffers[2].buf;
+        let len = cmp::min(packet_bytes as usize, self.rx_packet.len());
+        for i in 0..len {
+            self.rx_packet[i].set(packet[i].get());
+        }
+        self.rx_packet_len.set(len);
+        self.rx_packet_offset.set(0);
+        self.deliver_received();
+        hil::usb::OutResult::Ok
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if endpoint == ENDPOINT_IN {
+            if self.tx_pending() {
+                self.controller
+                    .endpoint_resume_in(self.endpoint(ENDPOINT_IN));
+            } else {
+                self.in_delayed.set(true);
+            }
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> DynamicDeferredCallClient for CdcAcm<'a, C> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if let Some(rcode) = self.tx_result.take() {
+            let len = self.tx_offset.get();
+            self.tx_buffer.take().map(|buffer| {
+                self.tx_client
+                    .map(move |client| client.transmitted_buffer(buffer, len, rcode));
+            });
+        }
+
+        if self.rx_abort.take() {
+            let len = self.rx_offset.get();
+            self.rx_buffer.take().map(|buffer| {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(buffer, len, ReturnCode::ECANCEL, uart::Error::Aborted)
+                });
+            });
+        } else {
+            self.deliver_received();
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> uart::Configure for CdcAcm<'a, C> {
+    fn configure(&self, _params: uart::Parameters) -> ReturnCode {
+        // The line coding is chosen by the host and does not matter over USB
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> uart::Transmit<'a> for CdcAcm<'a, C> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if tx_len == 0 || tx_len > tx_buffer.len() {
+            (ReturnCode::ESIZE, Some(tx_buffer))
+        } else if self.tx_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(tx_buffer))
+        } else if !self.port_open.get() {
+            (ReturnCode::EOFF, Some(tx_buffer))
+        } else {
+            self.tx_buffer.replace(tx_buffer);
+            self.tx_len.set(tx_len);
+            self.tx_offset.set(0);
+            if self.in_delayed.take() {
+                self.controller
+                    .endpoint_resume_in(self.endpoint(ENDPOINT_IN));
+            }
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        if self.cancel_transmit() || self.tx_buffer.is_some() {
+            // The callback is still to come
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> uart::Receive<'a> for CdcAcm<'a, C> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            (ReturnCode::ESIZE, Some(rx_buffer))
+        } else if self.rx_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(rx_buffer))
+        } else {
+            self.rx_buffer.replace(rx_buffer);
+            self.rx_len.set(rx_len);
+            self.rx_offset.set(0);
+            // Data may already be waiting from the host
+            self.schedule_callback();
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.rx_buffer.is_some() {
+            self.rx_abort.set(true);
+            self.schedule_callback();
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> uart::UartData<'a> for CdcAcm<'a, C> {}
+impl<'a, C: hil::usb::UsbController<'a>> uart::Uart<'a> for CdcAcm<'a, C> {}
@@ -0,0 +1,741 @@
+//! A composite USB device
+//!
+//! `CompositeDevice` puts several class drivers, called functions, into the
+//! single configuration of a device, such as a CDC-ACM serial port next to a
+//! HID device and a mass storage disk.
+//!
+//! Each function describes its interfaces and endpoints as if it were alone on
+//! the device, numbering them from 0 and 1 respectively. The device numbers
+//! them in the order the functions were added, and groups the interfaces of a
+//! function with more than one interface under an interface association
+//! descriptor, so that the host binds a single class driver to all of them.
+//!
+//! The device answers the standard requests itself, with the manufacturer,
+//! product and serial number strings given by the board and the names of the
+//! functions. Class and vendor requests addressed to an interface or an
+//! endpoint, and the transfers on all endpoints but the control endpoint, are
+//! forwarded to the function owning them, with interface and endpoint numbers
+//! translated back to those of the function.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let usb = static_init!(
+//!     capsules::usb::composite::CompositeDevice<'static, nrf52::usbd::Usbd>,
+//!     capsules::usb::composite::CompositeDevice::new(
+//!         &nrf52::usbd::USBD,
+//!         0x6667,
+//!         0xabce,
+//!         &["Tock", "Tock Device", "0"],
+//!     )
+//! );
+//! usb.add_function(cdc, "Console");
+//! usb.add_function(hid, "HID");
+//! nrf52::usbd::USBD.set_client(usb);
+//! usb.enable();
+//! usb.attach();
+//! ```
+
+use super::descriptors::{
+    Buffer64, CdcInterfaceDescriptor, CdcInterfaceDescriptorSubType, ConfigurationDescriptor,
+    Descriptor, DescriptorType, DeviceDescriptor, DfuFunctionalDescriptor, EndpointDescriptor,
+    HIDDescriptor, InterfaceAssociationDescriptor, InterfaceDescriptor, LanguagesDescriptor,
+    Recipient, ReportDescriptor, SetupData, StandardRequest, StringDescriptor, TransferDirection,
+};
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::VolatileCell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Maximum number of functions of a device
+pub const MAX_FUNCTIONS: usize = 4;
+
+const DESCRIPTOR_BUFLEN: usize = 256;
+
+const CONFIGURATION_VALUE: u8 = 1;
+
+/// Index of the string naming the first function, following the
+/// manufacturer, product and serial number
+const FIRST_FUNCTION_STRING: usize = 4;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// The descriptors of a function, with interfaces numbered from 0 and
+/// endpoints numbered from 1.
+#[derive(Copy, Clone)]
+pub struct FunctionDescriptors {
+    pub interfaces: &'static [InterfaceDescriptor],
+
+    /// A list of endpoints for each interface
+    pub endpoints: &'static [&'static [EndpointDescriptor]],
+
+    /// CDC functional descriptors for the first interface, referring to the
+    /// interface numbers of the function
+    pub cdc_descriptors: &'static [CdcInterfaceDescriptor],
+
+    /// A HID descriptor for the first interface, if any
+    pub hid_descriptor: Option<&'static HIDDescriptor<'static>>,
+
+    /// The report descriptor of the HID interface, if any
+    pub report_descriptor: Option<&'static ReportDescriptor<'static>>,
+
+    /// A DFU functional descriptor for the first interface, if any
+    pub dfu_descriptor: Option<&'static DfuFunctionalDescriptor>,
+}
+
+impl FunctionDescriptors {
+    /// The number of endpoints used by the function, up to its highest
+    /// endpoint number
+    fn endpoint_count(&self) -> usize {
+        self.endpoints
+            .iter()
+            .flat_map(|e| e.iter())
+            .map(|e| e.endpoint_address.endpoint())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Result of a request handled by a function
+pub enum CtrlRequestResult {
+    /// The request is accepted. Data sent by the host is passed to
+    /// `ctrl_out()`.
+    Ok,
+    /// The request is accepted, and the given number of bytes written to the
+    /// data buffer are sent to the host.
+    Data(usize),
+    /// The request is not supported.
+    Error,
+}
+
+impl CtrlRequestResult {
+    /// Copies `src` into the data buffer `buf` to be sent to the host.
+    pub fn data(buf: &[Cell<u8>], src: &[u8]) -> Self {
+        let len = min(buf.len(), src.len());
+        for (dst, src) in buf.iter().zip(src[..len].iter()) {
+            dst.set(*src);
+        }
+        CtrlRequestResult::Data(len)
+    }
+}
+
+/// A class driver that is part of a `CompositeDevice`.
+///
+/// Endpoint numbers passed to the function are its own, starting at 1. It
+/// still has to use device endpoint numbers when calling the controller,
+/// starting at the one given by `set_first_endpoint()`.
+pub trait UsbFunction<'a> {
+    fn descriptors(&self) -> FunctionDescriptors;
+
+    /// Called before `enable()` with the device endpoint number of endpoint 1
+    /// of the function.
+    fn set_first_endpoint(&self, endpoint: usize);
+
+    /// Set up the endpoints of the function.
+    fn enable(&'a self);
+
+    fn bus_reset(&'a self);
+
+    /// Handle a class or vendor request addressed to an interface or an
+    /// endpoint of the function. Data to send to the host is written to
+    /// `data`.
+    fn ctrl_setup(&'a self, setup: &SetupData, data: &[Cell<u8>]) -> CtrlRequestResult;
+
+    /// Handle the data of the last request accepted by `ctrl_setup()`.
+    fn ctrl_out(&'a self, _data: &[VolatileCell<u8>]) -> hil::usb::CtrlOutResult {
+        hil::usb::CtrlOutResult::Halted
+    }
+
+    /// The last request accepted by `ctrl_setup()` is complete.
+    fn ctrl_status_complete(&'a self) {}
+
+    fn packet_in(&'a self, endpoint: usize) -> hil::usb::InResult;
+    fn packet_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::OutResult;
+    fn packet_transmitted(&'a self, endpoint: usize);
+}
+
+/// A function with the device numbers of its first interface and endpoint
+#[derive(Copy, Clone)]
+struct Placement<'a> {
+    index: usize,
+    function: &'a dyn UsbFunction<'a>,
+    descriptors: FunctionDescriptors,
+    first_interface: usize,
+    first_endpoint: usize,
+}
+
+impl Placement<'a> {
+    fn has_interface(&self, interface: usize) -> bool {
+        interface >= self.first_interface
+            && interface < self.first_interface + self.descriptors.interfaces.len()
+    }
+
+    fn has_endpoint(&self, endpoint: usize) -> bool {
+        endpoint >= self.first_endpoint
+            && endpoint < self.first_endpoint + self.descriptors.endpoint_count()
+    }
+
+    /// The endpoint number of the function for a device endpoint number
+    fn local_endpoint(&self, endpoint: usize) -> usize {
+        endpoint + 1 - self.first_endpoint
+    }
+}
+
+#[derive(Copy, Clone)]
+enum State {
+    Init,
+
+    /// We are doing a Control In transfer of some data
+    /// in self.descriptor_storage, with the given extent
+    /// remaining to send
+    CtrlIn(usize, usize),
+
+    /// We will accept data from the host for the function handling the
+    /// request
+    CtrlOut,
+
+    SetAddress,
+}
+
+pub struct CompositeDevice<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    functions: [Cell<Option<&'a dyn UsbFunction<'a>>>; MAX_FUNCTIONS],
+    names: [Cell<&'a str>; MAX_FUNCTIONS],
+
+    // State of the default control endpoint
+    state: Cell<State>,
+
+    // The function handling the current control transfer, if any
+    ctrl_function: Cell<Option<usize>>,
+
+    // A 64-byte buffer for the control endpoint
+    ctrl_buffer: Buffer64,
+
+    // Storage for composing responses to device-descriptor requests
+    descriptor_storage: Cell<[u8; DESCRIPTOR_BUFLEN]>,
+
+    vendor_id: u16,
+    product_id: u16,
+
+    // Manufacturer, product and serial number
+    strings: &'a [&'a str; 3],
+
+    configuration: Cell<u8>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> CompositeDevice<'a, C> {
+    pub fn new(
+        controller: &'a C,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'a [&'a str; 3],
+    ) -> Self {
+        CompositeDevice {
+            controller,
+            functions: Default::default(),
+            names: Default::default(),
+            state: Cell::new(State::Init),
+            ctrl_function: Cell::new(None),
+            ctrl_buffer: Buffer64::default(),
+            descriptor_storage: Cell::new([0; DESCRIPTOR_BUFLEN]),
+            vendor_id,
+            product_id,
+            strings,
+            configuration: Cell::new(0),
+        }
+    }
+
+    /// Adds a function to the configuration, with the name the host shows
+    /// for it. Must be called before `enable()`. Returns `ENOMEM` if the
+    /// device already has `MAX_FUNCTIONS` functions.
+    pub fn add_function(&self, function: &'a dyn UsbFunction<'a>, name: &'a str) -> ReturnCode {
+        for (slot, slot_name) in self.functions.iter().zip(self.names.iter()) {
+            if slot.get().is_none() {
+                slot.set(Some(function));
+                slot_name.set(name);
+                return ReturnCode::SUCCESS;
+            }
+        }
+        ReturnCode::ENOMEM
+    }
+
+    #[inline]
+    fn descriptor_buf(&'a self) -> &'a [Cell<u8>] {
+        let storage: &Cell<[u8]> = &self.descriptor_storage;
+        storage.as_slice_of_cells()
+    }
+
+    /// The functions of the device, with their first interface and endpoint
+    fn placements(&self) -> impl Iterator<Item = Placement<'a>> + '_ {
+        self.functions
+            .iter()
+            .filter_map(|f| f.get())
+            .enumerate()
+            .scan((0, 1), |next, (index, function)| {
+                let descriptors = function.descriptors();
+                let placement = Placement {
+                    index,
+                    function,
+                    descriptors,
+                    first_interface: next.0,
+                    first_endpoint: next.1,
+                };
+                next.0 += descriptors.interfaces.len();
+                next.1 += descriptors.endpoint_count();
+                Some(placement)
+            })
+    }
+
+    fn function_of_interface(&self, interface: usize) -> Option<Placement<'a>> {
+        self.placements().find(|p| p.has_interface(interface))
+    }
+
+    fn function_of_endpoint(&self, endpoint: usize) -> Option<Placement<'a>> {
+        self.placements().find(|p| p.has_endpoint(endpoint))
+    }
+
+    fn device_descriptor(&self) -> DeviceDescriptor {
+        let associations = self
+            .placements()
+            .any(|p| p.descriptors.interfaces.len() > 1);
+        if associations {
+            // Interface association descriptors require the Miscellaneous
+            // device class with the Common Class subclass and the Interface
+            // Association protocol
+            DeviceDescriptor {
+                class: 0xef,
+                subclass: 0x02,
+                protocol: 0x01,
+                ..self.plain_device_descriptor()
+            }
+        } else {
+            self.plain_device_descriptor()
+        }
+    }
+
+    fn plain_device_descriptor(&self) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: self.vendor_id,
+            product_id: self.product_id,
+            manufacturer_string: 1,
+            product_string: 2,
+            serial_number_string: 3,
+            ..DeviceDescriptor::default()
+        }
+    }
+
+    /// Place all the descriptors related to the configuration into `buf`
+    /// contiguously, and return their length.
+    fn write_configuration(&self, buf: &[Cell<u8>]) -> usize {
+        // The configuration descriptor comes first, but its total length is
+        // only known once everything else is written.
+        let mut len = ConfigurationDescriptor::default().size();
+        let mut num_interfaces = 0;
+
+        for p in self.placements() {
+            let d = &p.descriptors;
+            let first_interface = p.first_interface as u8;
+            let string_index = (FIRST_FUNCTION_STRING + p.index) as u8;
+
+            if d.interfaces.len() > 1 {
+                let first = &d.interfaces[0];
+                len += InterfaceAssociationDescriptor {
+                    first_interface,
+                    interface_count: d.interfaces.len() as u8,
+                    function_class: first.interface_class,
+                    function_subclass: first.interface_subclass,
+                    function_protocol: first.interface_protocol,
+                    string_index,
+                }
+                .write_to(&buf[len..]);
+            }
+
+            for (i, interface) in d.interfaces.iter().enumerate() {
+                let endpoints = d.endpoints.get(i).map_or(&[][..], |e| *e);
+                len += InterfaceDescriptor {
+                    interface_number: first_interface + i as u8,
+                    num_endpoints: endpoints.len() as u8,
+                    string_index,
+                    ..*interface
+                }
+                .write_to(&buf[len..]);
+
+                if i == 0 {
+                    // HID descriptor, if any.
+                    if let Some(dh) = d.hid_descriptor {
+                        len += dh.write_to(&buf[len..]);
+                    }
+
+                    // DFU functional descriptor, if any.
+                    if let Some(dd) = d.dfu_descriptor {
+                        len += dd.write_to(&buf[len..]);
+                    }
+
+                    // CDC functional descriptors, if any.
+                    for dc in d.cdc_descriptors.iter() {
+                        len += renumber_cdc_descriptor(dc, first_interface).write_to(&buf[len..]);
+                    }
+                }
+
+                for de in endpoints.iter() {
+                    let endpoint = p.first_endpoint + de.endpoint_address.endpoint() - 1;
+                    len += EndpointDescriptor {
+                        endpoint_address: de.endpoint_address.with_endpoint(endpoint),
+                        transfer_type: de.transfer_type,
+                        max_packet_size: de.max_packet_size,
+                        interval: de.interval,
+                    }
+                    .write_to(&buf[len..]);
+                }
+            }
+            num_interfaces += d.interfaces.len();
+        }
+
+        let configuration = ConfigurationDescriptor {
+            num_interfaces: num_interfaces as u8,
+            configuration_value: CONFIGURATION_VALUE,
+            related_descriptor_length: len - ConfigurationDescriptor::default().size(),
+            ..ConfigurationDescriptor::default()
+        };
+        configuration.write_to(buf);
+        len
+    }
+
+    /// Start a Control In transfer of the first `len` bytes of the descriptor
+    /// buffer.
+    fn ctrl_in_descriptor(&self, len: usize, requested_length: u16) -> hil::usb::CtrlSetupResult {
+        let end = min(len, requested_length as usize);
+        self.state.set(State::CtrlIn(0, end));
+        hil::usb::CtrlSetupResult::Ok
+    }
+
+    fn handle_standard_device_request(
+        &'a self,
+        request: StandardRequest,
+    ) -> hil::usb::CtrlSetupResult {
+        match request {
+            StandardRequest::GetDescriptor {
+                descriptor_type,
+                descriptor_index,
+                lang_id,
+                requested_length,
+            } => match descriptor_type {
+                DescriptorType::Device => match descriptor_index {
+                    0 => {
+                        let len = self.device_descriptor().write_to(self.descriptor_buf());
+                        self.ctrl_in_descriptor(len, requested_length)
+                    }
+                    _ => hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex,
+                },
+                DescriptorType::Configuration => match descriptor_index {
+                    0 => {
+                        let len = self.write_configuration(self.descriptor_buf());
+                        self.ctrl_in_descriptor(len, requested_length)
+                    }
+                    _ => hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
+                },
+                DescriptorType::String => {
+                    let string = match descriptor_index as usize {
+                        0 => {
+                            let d = LanguagesDescriptor { langs: LANGUAGES };
+                            let len = d.write_to(self.descriptor_buf());
+                            return self.ctrl_in_descriptor(len, requested_length);
+                        }
+                        _ if lang_id != LANGUAGES[0] => None,
+                        i if i <= self.strings.len() => Some(self.strings[i - 1]),
+                        i => self
+                            .functions
+                            .get(i - FIRST_FUNCTION_STRING)
+                            .and_then(|function| function.get())
+                            .map(|_| self.names[i - FIRST_FUNCTION_STRING].get()),
+                    };
+                    match string {
+                        Some(string) => {
+                            let len = StringDescriptor { string }.write_to(self.descriptor_buf());
+                            self.ctrl_in_descriptor(len, requested_length)
+                        }
+                        None => hil::usb::CtrlSetupResult::ErrInvalidStringIndex,
+                    }
+                }
+                DescriptorType::DeviceQualifier => {
+                    // We are full-speed only, so we must
+                    // respond with a request error
+                    hil::usb::CtrlSetupResult::ErrNoDeviceQualifier
+                }
+                _ => hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
+            },
+            StandardRequest::SetAddress { device_address } => {
+                // Load the address we've been assigned ...
+                self.controller.set_address(device_address);
+
+                // ... and when this request gets to the Status stage we will actually enable the
+                // address.
+                self.state.set(State::SetAddress);
+                hil::usb::CtrlSetupResult::OkSetAddress
+            }
+            StandardRequest::GetConfiguration => {
+                let buf = self.descriptor_buf();
+                buf[0].set(self.configuration.get());
+                self.ctrl_in_descriptor(1, 1)
+            }
+            StandardRequest::SetConfiguration {
+                configuration_value,
+            } => match configuration_value {
+                0 | CONFIGURATION_VALUE => {
+                    self.configuration.set(configuration_value);
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                _ => hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
+            },
+            _ => hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType,
+        }
+    }
+
+    fn handle_standard_interface_request(
+        &'a self,
+        request: StandardRequest,
+        interface: usize,
+    ) -> hil::usb::CtrlSetupResult {
+        let descriptors = match self.function_of_interface(interface) {
+            Some(p) => p.descriptors,
+            None => return hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
+        };
+        match request {
+            StandardRequest::GetDescriptor {
+                descriptor_type,
+                requested_length,
+                ..
+            } => {
+                let buf = self.descriptor_buf();
+                let len = match descriptor_type {
+                    DescriptorType::HID => descriptors.hid_descriptor.map(|d| d.write_to(buf)),
+                    DescriptorType::Report => {
+                        descriptors.report_descriptor.map(|d| d.write_to(buf))
+                    }
+                    _ => None,
+                };
+                match len {
+                    Some(len) => self.ctrl_in_descriptor(len, requested_length),
+                    None => hil::usb::CtrlSetupResult::ErrGeneric,
+                }
+            }
+            _ => hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+    }
+
+    /// Forward a class or vendor request to the function owning the
+    /// interface or endpoint it is addressed to.
+    fn handle_function_request(&'a self, mut setup: SetupData) -> hil::usb::CtrlSetupResult {
+        let placement = match setup.request_type.recipient() {
+            Recipient::Interface => self
+                .function_of_interface((setup.index & 0xff) as usize)
+                .map(|p| {
+                    setup.index -= p.first_interface as u16;
+                    p
+                }),
+            Recipient::Endpoint => {
+                self.function_of_endpoint((setup.index & 0xf) as usize)
+                    .map(|p| {
+                        setup.index -= (p.first_endpoint - 1) as u16;
+                        p
+                    })
+            }
+            _ => None,
+        };
+        let p = match placement {
+            Some(p) => p,
+            None => return hil::usb::CtrlSetupResult::ErrNonstandardRequest,
+        };
+
+        let state = match p.function.ctrl_setup(&setup, self.descriptor_buf()) {
+            CtrlRequestResult::Ok => match setup.request_type.transfer_direction() {
+                TransferDirection::HostToDevice => State::CtrlOut,
+                TransferDirection::DeviceToHost => State::CtrlIn(0, 0),
+            },
+            CtrlRequestResult::Data(len) => State::CtrlIn(0, min(len, setup.length as usize)),
+            CtrlRequestResult::Error => {
+                return hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType
+            }
+        };
+        self.ctrl_function.set(Some(p.index));
+        self.state.set(state);
+        hil::usb::CtrlSetupResult::Ok
+    }
+}
+
+/// Moves the interface numbers in a CDC functional descriptor of a function
+/// to those of the device.
+fn renumber_cdc_descriptor(
+    d: &CdcInterfaceDescriptor,
+    first_interface: u8,
+) -> CdcInterfaceDescriptor {
+    let (field1, field2) = match d.subtype {
+        CdcInterfaceDescriptorSubType::Union => {
+            (d.field1 + first_interface, d.field2 + first_interface)
+        }
+        CdcInterfaceDescriptorSubType::CallManagement => (d.field1, d.field2 + first_interface),
+        _ => (d.field1, d.field2),
+    };
+    CdcInterfaceDescriptor {
+        subtype: d.subtype,
+        field1,
+        field2,
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CompositeDevice<'a, C> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.controller
+            .endpoint_set_ctrl_buffer(&self.ctrl_buffer.buf);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller
+            .endpoint_out_enable(TransferType::Control, 0);
+
+        for p in self.placements() {
+            p.function.set_first_endpoint(p.first_endpoint);
+            p.function.enable();
+        }
+    }
+
+    fn attach(&'a self) {
+        self.controller.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.state.set(State::Init);
+        self.ctrl_function.set(None);
+        self.configuration.set(0);
+        for p in self.placements() {
+            p.function.bus_reset();
+        }
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // For now we only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.ctrl_function.set(None);
+        let setup = match SetupData::get(&self.ctrl_buffer.buf) {
+            Some(setup) => setup,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        match setup.get_standard_request() {
+            Some(request) => match setup.request_type.recipient() {
+                Recipient::Device => self.handle_standard_device_request(request),
+                Recipient::Interface => {
+                    self.handle_standard_interface_request(request, (setup.index & 0xff) as usize)
+                }
+                _ => hil::usb::CtrlSetupResult::ErrGeneric,
+            },
+            None => self.handle_function_request(setup),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, _endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.state.get() {
+            State::CtrlIn(start, end) => {
+                let len = end.saturating_sub(start);
+                if len > 0 {
+                    let packet_bytes = min(self.ctrl_buffer.buf.len(), len);
+                    let packet = &self.descriptor_buf()[start..start + packet_bytes];
+                    let buf = &self.ctrl_buffer.buf;
+
+                    // Copy a packet into the endpoint buffer
+                    for (i, b) in packet.iter().enumerate() {
+                        buf[i].set(b.get());
+                    }
+
+                    let start = start + packet_bytes;
+                    let transfer_complete = start >= end;
+
+                    self.state.set(State::CtrlIn(start, end));
+
+                    hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete)
+                } else {
+                    hil::usb::CtrlInResult::Packet(0, true)
+                }
+            }
+            _ => hil::usb::CtrlInResult::Error,
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, _endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        match (self.state.get(), self.ctrl_function.get()) {
+            (State::CtrlOut, Some(index)) => match self.functions[index].get() {
+                Some(function) => {
+                    let len = min(packet_bytes as usize, self.ctrl_buffer.buf.len());
+                    function.ctrl_out(&self.ctrl_buffer.buf[..len])
+                }
+                None => hil::usb::CtrlOutResult::Halted,
+            },
+            _ => {
+                // Bad state
+                hil::usb::CtrlOutResult::Halted
+            }
+        }
+    }
+
+    fn ctrl_status(&'a self, _endpoint: usize) {
+        // Entered Status stage
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, _endpoint: usize) {
+        // Control Read: IN request acknowledged
+        // Control Write: status sent
+
+        if let State::SetAddress = self.state.get() {
+            self.controller.enable_address();
+        }
+        if let Some(index) = self.ctrl_function.take() {
+            self.functions[index]
+                .get()
+                .map(|function| function.ctrl_status_complete());
+        }
+        self.state.set(State::Init);
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, _transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        // Some controllers do not report the transfer type of the endpoint,
+        // so dispatch on the endpoint number only.
+        match self.function_of_endpoint(endpoint) {
+            Some(p) => p.function.packet_in(p.local_endpoint(endpoint)),
+            None => hil::usb::InResult::Error,
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match self.function_of_endpoint(endpoint) {
+            Some(p) => p
+                .function
+                .packet_out(p.local_endpoint(endpoint), packet_bytes),
+            None => hil::usb::OutResult::Error,
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if let Some(p) = self.function_of_endpoint(endpoint) {
+            p.function.packet_transmitted(p.local_endpoint(endpoint));
+        }
+    }
+}
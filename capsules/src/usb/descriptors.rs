@@ -153,10 +153,16 @@ pub enum DescriptorType {
     DeviceQualifier,
     OtherSpeedConfiguration,
     InterfacePower,
+    InterfaceAssociation = 0x0b,
     HID = 0x21,
     Report = 0x22,
+    CdcInterface = 0x24,
 }
 
+/// Type of the DFU functional descriptor, which shares its value with the HID
+/// descriptor
+const DFU_FUNCTIONAL_DESCRIPTOR_TYPE: u8 = 0x21;
+
 fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
     match byte {
         1 => Some(DescriptorType::Device),
@@ -167,8 +173,10 @@ fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
         6 => Some(DescriptorType::DeviceQualifier),
         7 => Some(DescriptorType::OtherSpeedConfiguration),
         8 => Some(DescriptorType::InterfacePower),
+        0x0b => Some(DescriptorType::InterfaceAssociation),
         0x21 => Some(DescriptorType::HID),
         0x22 => Some(DescriptorType::Report),
+        0x24 => Some(DescriptorType::CdcInterface),
         _ => None,
     }
 }
@@ -472,6 +480,7 @@ impl Descriptor for InterfaceDescriptor {
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct EndpointAddress(u8);
 
 impl EndpointAddress {
@@ -491,6 +500,16 @@ impl EndpointAddress {
     pub const fn new_const(endpoint: usize, direction: TransferDirection) -> Self {
         EndpointAddress(endpoint as u8 & 0xf | (direction as u8) << 7)
     }
+
+    /// The endpoint number, without the direction
+    pub fn endpoint(&self) -> usize {
+        (self.0 & 0xf) as usize
+    }
+
+    /// The same direction on another endpoint number
+    pub fn with_endpoint(&self, endpoint: usize) -> Self {
+        EndpointAddress(endpoint as u8 & 0xf | self.0 & (1 << 7))
+    }
 }
 
 pub struct EndpointDescriptor {
@@ -520,6 +539,63 @@ impl Descriptor for EndpointDescriptor {
     }
 }
 
+/// Groups the interfaces of one function of a composite device, so that the
+/// host binds a single class driver to all of them
+pub struct InterfaceAssociationDescriptor {
+    pub first_interface: u8,
+    pub interface_count: u8,
+    pub function_class: u8,
+    pub function_subclass: u8,
+    pub function_protocol: u8,
+    pub string_index: u8,
+}
+
+impl Descriptor for InterfaceAssociationDescriptor {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(8); // Size of descriptor
+        buf[1].set(DescriptorType::InterfaceAssociation as u8);
+        buf[2].set(self.first_interface);
+        buf[3].set(self.interface_count);
+        buf[4].set(self.function_class);
+        buf[5].set(self.function_subclass);
+        buf[6].set(self.function_protocol);
+        buf[7].set(self.string_index);
+        8
+    }
+}
+
+/// The functional descriptor of a DFU interface
+pub struct DfuFunctionalDescriptor {
+    /// Bit 0: download capable, bit 1: upload capable, bit 2: manifestation
+    /// tolerant, bit 3: detaches by itself
+    pub attributes: u8,
+    pub detach_timeout: u16,
+    /// Maximum number of bytes per control write transaction
+    pub transfer_size: u16,
+    /// Version of the DFU specification in BCD
+    pub dfu_version: u16,
+}
+
+impl Descriptor for DfuFunctionalDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(DFU_FUNCTIONAL_DESCRIPTOR_TYPE);
+        buf[2].set(self.attributes);
+        put_u16(&buf[3..5], self.detach_timeout);
+        put_u16(&buf[5..7], self.transfer_size);
+        put_u16(&buf[7..9], self.dfu_version);
+        9
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum HIDCountryCode {
     NotSupported = 0,
@@ -591,6 +667,48 @@ impl Descriptor for HIDDescriptor<'a> {
     }
 }
 
+#[derive(Copy, Clone)]
+pub enum CdcInterfaceDescriptorSubType {
+    Header = 0x00,
+    CallManagement = 0x01,
+    AbstractControlManagement = 0x02,
+    Union = 0x06,
+}
+
+/// A functional descriptor of a CDC communications interface.
+///
+/// The meaning of `field1` and `field2` depends on the subtype:
+/// - Header: the CDC release number in BCD (`field1` is the low byte)
+/// - CallManagement: capabilities and the data interface number
+/// - AbstractControlManagement: capabilities (`field2` is not sent)
+/// - Union: the controlling and the subordinate interface numbers
+pub struct CdcInterfaceDescriptor {
+    pub subtype: CdcInterfaceDescriptorSubType,
+    pub field1: u8,
+    pub field2: u8,
+}
+
+impl Descriptor for CdcInterfaceDescriptor {
+    fn size(&self) -> usize {
+        match self.subtype {
+            CdcInterfaceDescriptorSubType::AbstractControlManagement => 4,
+            _ => 5,
+        }
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        let len = self.size();
+        buf[0].set(len as u8);
+        buf[1].set(DescriptorType::CdcInterface as u8);
+        buf[2].set(self.subtype as u8);
+        buf[3].set(self.field1);
+        if len > 4 {
+            buf[4].set(self.field2);
+        }
+        len
+    }
+}
+
 pub struct ReportDescriptor<'a> {
     pub desc: &'a [u8],
 }
@@ -0,0 +1,358 @@
+//! A USB Device Firmware Upgrade (DFU) function
+//!
+//! It adds an interface in DFU mode to a `usb::composite::CompositeDevice`
+//! and passes downloaded images to a `firmware_update::ImageUpdate`. The
+//! kernel keeps running during the download, so the interface never needs a
+//! detach. It is manifestation tolerant: once an image is verified and
+//! pending, the host can reset the device to swap it in, for example with
+//! `dfu-util -D tock.dfu -R`. Uploads are not supported.
+//!
+//! The downloaded file is an update image as described in `firmware_update`:
+//! the kernel binary followed by its SHA-256 digest.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dfu = static_init!(
+//!     capsules::usb::dfu::UsbDfu<'static>,
+//!     capsules::usb::dfu::UsbDfu::new(update)
+//! );
+//! capsules::firmware_update::ImageUpdate::set_client(update, dfu);
+//! usb.add_function(dfu, "Firmware");
+//! ```
+
+use super::composite::{CtrlRequestResult, FunctionDescriptors, UsbFunction};
+use super::descriptors::{DfuFunctionalDescriptor, InterfaceDescriptor, RequestType, SetupData};
+use crate::firmware_update::{ImageUpdate, UpdateClient};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, VolatileCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Bytes per download request. It divides the flash page size of all
+/// supported chips, so that no block crosses a page boundary.
+pub const TRANSFER_SIZE: usize = 256;
+
+/// Time the host waits before asking for the status again while a block is
+/// written, in milliseconds
+const DOWNLOAD_POLL_TIMEOUT: u32 = 100;
+
+/// Time the host waits before asking for the status again while an image is
+/// verified, in milliseconds
+const MANIFEST_POLL_TIMEOUT: u32 = 1000;
+
+// Class-specific requests
+const DFU_DETACH: u8 = 0;
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+static INTERFACES: &'static [InterfaceDescriptor] = &[InterfaceDescriptor {
+    interface_number: 0,
+    alternate_setting: 0,
+    num_endpoints: 0,
+    interface_class: 0xfe,    // Application specific
+    interface_subclass: 0x01, // Device firmware upgrade
+    interface_protocol: 0x02, // DFU mode
+    string_index: 0,
+}];
+
+static DFU_DESCRIPTOR: DfuFunctionalDescriptor = DfuFunctionalDescriptor {
+    attributes: 0b0101, // Download capable, manifestation tolerant
+    detach_timeout: 0,
+    transfer_size: TRANSFER_SIZE as u16,
+    dfu_version: 0x0110,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DfuState {
+    Idle = 2,
+    DownloadSync = 3,
+    DownloadBusy = 4,
+    DownloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    Error = 10,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DfuStatus {
+    Ok = 0x00,
+    ErrWrite = 0x03,
+    ErrVerify = 0x07,
+    ErrNotDone = 0x09,
+    ErrStalledPacket = 0x0f,
+}
+
+/// The request waiting for the end of its control transfer
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Pending {
+    None,
+    Block,
+    Manifest,
+}
+
+pub struct UsbDfu<'a> {
+    update: &'a dyn ImageUpdate<'a>,
+    state: Cell<DfuState>,
+    status: Cell<DfuStatus>,
+    pending: Cell<Pending>,
+    /// An update operation is in progress
+    busy: Cell<bool>,
+    /// `begin()` succeeded for the current download
+    started: Cell<bool>,
+    block: MapCell<[u8; TRANSFER_SIZE]>,
+    block_len: Cell<usize>,
+}
+
+impl UsbDfu<'a> {
+    pub fn new(update: &'a dyn ImageUpdate<'a>) -> Self {
+        UsbDfu {
+            update,
+            state: Cell::new(DfuState::Idle),
+            status: Cell::new(DfuStatus::Ok),
+            pending: Cell::new(Pending::None),
+            busy: Cell::new(false),
+            started: Cell::new(false),
+            block: MapCell::new([0; TRANSFER_SIZE]),
+            block_len: Cell::new(0),
+        }
+    }
+
+    fn error(&self, status: DfuStatus) {
+        self.busy.set(false);
+        self.started.set(false);
+        self.status.set(status);
+        self.state.set(DfuState::Error);
+    }
+
+    fn write_block(&self) {
+        let len = self.block_len.get();
+        let rcode = self
+            .block
+            .map_or(ReturnCode::FAIL, |block| self.update.write(&block[..len]));
+        if rcode == ReturnCode::SUCCESS {
+            self.busy.set(true);
+        } else {
+            self.error(DfuStatus::ErrWrite);
+        }
+    }
+
+    /// Starts the operation of a request once its control transfer is done.
+    fn start_pending(&self) {
+        match self.pending.replace(Pending::None) {
+            Pending::Block => {
+                if self.started.get() {
+                    self.write_block();
+                } else if self.update.begin() == ReturnCode::SUCCESS {
+                    self.busy.set(true);
+                } else {
+                    self.error(DfuStatus::ErrWrite);
+                }
+            }
+            Pending::Manifest => {
+                if self.update.finish() == ReturnCode::SUCCESS {
+                    self.busy.set(true);
+                } else {
+                    self.error(DfuStatus::ErrNotDone);
+                }
+            }
+            Pending::None => {}
+        }
+    }
+
+    /// Moves on from a synchronization state once the operation is done,
+    /// and returns the state and poll timeout to report to the host.
+    fn poll(&self) -> (DfuState, u32) {
+        match self.state.get() {
+            DfuState::DownloadSync | DfuState::DownloadBusy => {
+                if self.busy.get() || self.pending.get() != Pending::None {
+                    self.state.set(DfuState::DownloadBusy);
+                    (DfuState::DownloadBusy, DOWNLOAD_POLL_TIMEOUT)
+                } else {
+                    self.state.set(DfuState::DownloadIdle);
+                    (DfuState::DownloadIdle, 0)
+                }
+            }
+            DfuState::ManifestSync | DfuState::Manifest => {
+                if self.busy.get() || self.pending.get() != Pending::None {
+                    self.state.set(DfuState::Manifest);
+                    (DfuState::Manifest, MANIFEST_POLL_TIMEOUT)
+                } else {
+                    self.state.set(DfuState::Idle);
+                    (DfuState::Idle, 0)
+                }
+            }
+            state => (state, 0),
+        }
+    }
+
+    fn download(&self, setup: &SetupData) -> CtrlRequestResult {
+        let length = setup.length as usize;
+        match self.state.get() {
+            DfuState::Idle | DfuState::DownloadIdle if length > 0 => {
+                if length > TRANSFER_SIZE {
+                    self.error(DfuStatus::ErrStalledPacket);
+                    return CtrlRequestResult::Error;
+                }
+                if self.state.get() == DfuState::Idle {
+                    self.started.set(false);
+                }
+                self.block_len.set(0);
+                self.pending.set(Pending::Block);
+                self.state.set(DfuState::DownloadSync);
+                CtrlRequestResult::Ok
+            }
+            DfuState::DownloadIdle => {
+                self.pending.set(Pending::Manifest);
+                self.state.set(DfuState::ManifestSync);
+                CtrlRequestResult::Ok
+            }
+            _ => {
+                self.error(DfuStatus::ErrStalledPacket);
+                CtrlRequestResult::Error
+            }
+        }
+    }
+
+    fn abort(&self) {
+        if self.started.get() && !self.busy.get() {
+            self.update.abort();
+        }
+        self.started.set(false);
+        self.status.set(DfuStatus::Ok);
+        self.state.set(DfuState::Idle);
+    }
+}
+
+impl UsbFunction<'a> for UsbDfu<'a> {
+    fn descriptors(&self) -> FunctionDescriptors {
+        FunctionDescriptors {
+            interfaces: INTERFACES,
+            endpoints: &[],
+            cdc_descriptors: &[],
+            hid_descriptor: None,
+            report_descriptor: None,
+            dfu_descriptor: Some(&DFU_DESCRIPTOR),
+        }
+    }
+
+    fn set_first_endpoint(&self, _endpoint: usize) {}
+
+    fn enable(&'a self) {}
+
+    fn bus_reset(&'a self) {
+        if !self.busy.get() {
+            self.pending.set(Pending::None);
+            self.abort();
+        }
+    }
+
+    /// Handle a class-specific request
+    fn ctrl_setup(&'a self, setup: &SetupData, data: &[Cell<u8>]) -> CtrlRequestResult {
+        match setup.request_type.request_type() {
+            RequestType::Class => {}
+            _ => return CtrlRequestResult::Error,
+        }
+        match setup.request_code {
+            DFU_DNLOAD => self.download(setup),
+            DFU_GETSTATUS => {
+                let (state, timeout) = self.poll();
+                let timeout = timeout.to_le_bytes();
+                CtrlRequestResult::data(
+                    data,
+                    &[
+                        self.status.get() as u8,
+                        timeout[0],
+                        timeout[1],
+                        timeout[2],
+                        state as u8,
+                        0,
+                    ],
+                )
+            }
+            DFU_CLRSTATUS => {
+                if self.state.get() == DfuState::Error {
+                    self.abort();
+                    CtrlRequestResult::Ok
+                } else {
+                    CtrlRequestResult::Error
+                }
+            }
+            DFU_GETSTATE => CtrlRequestResult::data(data, &[self.state.get() as u8]),
+            DFU_ABORT => match self.state.get() {
+                DfuState::Idle | DfuState::DownloadIdle => {
+                    self.abort();
+                    CtrlRequestResult::Ok
+                }
+                _ => CtrlRequestResult::Error,
+            },
+            DFU_DETACH | DFU_UPLOAD => CtrlRequestResult::Error,
+            _ => CtrlRequestResult::Error,
+        }
+    }
+
+    /// Collect the data of a download request
+    fn ctrl_out(&'a self, data: &[VolatileCell<u8>]) -> hil::usb::CtrlOutResult {
+        if self.pending.get() != Pending::Block {
+            return hil::usb::CtrlOutResult::Halted;
+        }
+        let offset = self.block_len.get();
+        if offset + data.len() > TRANSFER_SIZE {
+            return hil::usb::CtrlOutResult::Halted;
+        }
+        self.block.map(|block| {
+            for (dst, src) in block[offset..offset + data.len()].iter_mut().zip(data) {
+                *dst = src.get();
+            }
+        });
+        self.block_len.set(offset + data.len());
+        hil::usb::CtrlOutResult::Ok
+    }
+
+    fn ctrl_status_complete(&'a self) {
+        self.start_pending();
+    }
+
+    fn packet_in(&'a self, _endpoint: usize) -> hil::usb::InResult {
+        hil::usb::InResult::Error
+    }
+
+    fn packet_out(&'a self, _endpoint: usize, _packet_bytes: u32) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {}
+}
+
+impl UpdateClient for UsbDfu<'a> {
+    fn begin_done(&self, result: ReturnCode) {
+        if result == ReturnCode::SUCCESS {
+            self.started.set(true);
+            self.write_block();
+        } else {
+            self.error(DfuStatus::ErrWrite);
+        }
+    }
+
+    fn write_done(&self, result: ReturnCode) {
+        self.busy.set(false);
+        if result != ReturnCode::SUCCESS {
+            self.error(DfuStatus::ErrWrite);
+        }
+    }
+
+    fn finish_done(&self, result: ReturnCode) {
+        self.busy.set(false);
+        self.started.set(false);
+        if result != ReturnCode::SUCCESS {
+            self.error(DfuStatus::ErrVerify);
+        }
+    }
+
+    fn confirm_done(&self, _result: ReturnCode) {}
+}
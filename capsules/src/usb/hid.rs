@@ -0,0 +1,634 @@
+//! A USB HID (human interface device) class function
+//!
+//! It adds a single HID interface to a `usb::composite::CompositeDevice`, with an interrupt IN endpoint for
+//! input reports and an interrupt OUT endpoint for output reports. Hosts
+//! support HID devices without any extra driver, which makes the class useful
+//! for custom tools as well as for keyboards and mice.
+//!
+//! The report descriptor is chosen with `HidKind`:
+//! - `Keyboard`: the boot keyboard, with 8-byte input reports (modifiers, a
+//!   reserved byte and 6 key codes) and 1-byte output reports (LEDs).
+//! - `Mouse`: a boot mouse with a wheel, with 4-byte input reports (buttons,
+//!   X, Y and wheel) and no output reports.
+//! - `Vendor`: 64-byte input and output reports in the vendor-defined usage
+//!   page 0xFF00, for tools talking to the device through e.g. `hidapi`.
+//!
+//! Output reports sent by the host with a `SET_REPORT` control request are
+//! delivered like those received on the OUT endpoint.
+//!
+//! The endpoints use 64-byte buffers, so this requires a controller that
+//! supports them, such as `nrf52::usbd`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hid = static_init!(
+//!     capsules::usb::hid::UsbHid<'static, nrf52::usbd::Usbd>,
+//!     capsules::usb::hid::UsbHid::new(
+//!         &nrf52::usbd::USBD,
+//!         capsules::usb::hid::HidKind::Vendor,
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! hid.initialize_callback_handle(dynamic_deferred_caller.register(hid).unwrap());
+//! usb.add_function(hid, "HID");
+//! ```
+
+use super::composite::{CtrlRequestResult, FunctionDescriptors, UsbFunction};
+use super::descriptors::{
+    Buffer64, DescriptorType, EndpointAddress, EndpointDescriptor, HIDCountryCode, HIDDescriptor,
+    HIDSubordinateDescriptor, InterfaceDescriptor, ReportDescriptor, RequestType, SetupData,
+    TransferDirection,
+};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Interrupt IN endpoint for input reports
+const ENDPOINT_IN: usize = 1;
+/// Interrupt OUT endpoint for output reports
+const ENDPOINT_OUT: usize = 2;
+
+const N_ENDPOINTS: usize = 2;
+
+/// Largest report of any kind
+pub const MAX_REPORT_LEN: usize = 64;
+
+// Class-specific requests
+const GET_REPORT: u8 = 0x01;
+const GET_IDLE: u8 = 0x02;
+const GET_PROTOCOL: u8 = 0x03;
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+
+const KEYBOARD_REPORT: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifiers
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LEDs
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): keys
+    0xc0, // End Collection
+];
+
+const MOUSE_REPORT: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Buttons)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative): X, Y, wheel
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
+
+const VENDOR_REPORT: &'static [u8] = &[
+    0x06, 0x00, 0xff, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, // Usage (1)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x02, //   Usage (2)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x03, //   Usage (3)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+static KEYBOARD_REPORT_DESCRIPTOR: ReportDescriptor<'static> = ReportDescriptor {
+    desc: KEYBOARD_REPORT,
+};
+static MOUSE_REPORT_DESCRIPTOR: ReportDescriptor<'static> = ReportDescriptor { desc: MOUSE_REPORT };
+static VENDOR_REPORT_DESCRIPTOR: ReportDescriptor<'static> = ReportDescriptor {
+    desc: VENDOR_REPORT,
+};
+
+static KEYBOARD_HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: KEYBOARD_REPORT.len() as u16,
+    }],
+};
+static MOUSE_HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: MOUSE_REPORT.len() as u16,
+    }],
+};
+static VENDOR_HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: VENDOR_REPORT.len() as u16,
+    }],
+};
+
+const fn interface(subclass: u8, protocol: u8) -> InterfaceDescriptor {
+    InterfaceDescriptor {
+        interface_number: 0,
+        alternate_setting: 0,
+        num_endpoints: 0,      // Filled in by `CompositeDevice`
+        interface_class: 0x03, // HID
+        interface_subclass: subclass,
+        interface_protocol: protocol,
+        string_index: 0,
+    }
+}
+
+static KEYBOARD_INTERFACES: &'static [InterfaceDescriptor] = &[interface(0x01, 0x01)];
+static MOUSE_INTERFACES: &'static [InterfaceDescriptor] = &[interface(0x01, 0x02)];
+static VENDOR_INTERFACES: &'static [InterfaceDescriptor] = &[interface(0x00, 0x00)];
+
+const fn endpoints(max_packet_size: u16, interval: u8) -> [EndpointDescriptor; N_ENDPOINTS] {
+    [
+        EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_IN,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size,
+            interval,
+        },
+        EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_OUT,
+                TransferDirection::HostToDevice,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size,
+            interval,
+        },
+    ]
+}
+
+static BOOT_ENDPOINTS: [EndpointDescriptor; N_ENDPOINTS] = endpoints(8, 10);
+static VENDOR_ENDPOINTS: [EndpointDescriptor; N_ENDPOINTS] = endpoints(64, 1);
+
+static BOOT_INTERFACE_ENDPOINTS: &'static [&'static [EndpointDescriptor]] = &[&BOOT_ENDPOINTS];
+static VENDOR_INTERFACE_ENDPOINTS: &'static [&'static [EndpointDescriptor]] = &[&VENDOR_ENDPOINTS];
+
+/// The kind of HID device, which determines its report descriptor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HidKind {
+    Keyboard,
+    Mouse,
+    Vendor,
+}
+
+impl HidKind {
+    /// Length of the input reports sent to the host
+    pub fn input_report_len(self) -> usize {
+        match self {
+            HidKind::Keyboard => 8,
+            HidKind::Mouse => 4,
+            HidKind::Vendor => 64,
+        }
+    }
+
+    /// Length of the output reports received from the host
+    pub fn output_report_len(self) -> usize {
+        match self {
+            HidKind::Keyboard => 1,
+            HidKind::Mouse => 0,
+            HidKind::Vendor => 64,
+        }
+    }
+
+    fn interfaces(self) -> &'static [InterfaceDescriptor] {
+        match self {
+            HidKind::Keyboard => KEYBOARD_INTERFACES,
+            HidKind::Mouse => MOUSE_INTERFACES,
+            HidKind::Vendor => VENDOR_INTERFACES,
+        }
+    }
+
+    fn endpoints(self) -> &'static [&'static [EndpointDescriptor]] {
+        match self {
+            HidKind::Keyboard | HidKind::Mouse => BOOT_INTERFACE_ENDPOINTS,
+            HidKind::Vendor => VENDOR_INTERFACE_ENDPOINTS,
+        }
+    }
+
+    fn hid_descriptor(self) -> &'static HIDDescriptor<'static> {
+        match self {
+            HidKind::Keyboard => &KEYBOARD_HID_DESCRIPTOR,
+            HidKind::Mouse => &MOUSE_HID_DESCRIPTOR,
+            HidKind::Vendor => &VENDOR_HID_DESCRIPTOR,
+        }
+    }
+
+    fn report_descriptor(self) -> &'static ReportDescriptor<'static> {
+        match self {
+            HidKind::Keyboard => &KEYBOARD_REPORT_DESCRIPTOR,
+            HidKind::Mouse => &MOUSE_REPORT_DESCRIPTOR,
+            HidKind::Vendor => &VENDOR_REPORT_DESCRIPTOR,
+        }
+    }
+}
+
+/// Callbacks of a `HidDevice`.
+pub trait HidClient {
+    /// An input report passed to `send_report` has been handed to the
+    /// controller, or was cancelled by a bus reset with `ECANCEL`.
+    fn report_sent(&self, report: &'static mut [u8], len: usize, rcode: ReturnCode);
+
+    /// An output report from the host has been copied into the buffer passed
+    /// to `receive_report`.
+    fn report_received(&self, report: &'static mut [u8], len: usize, rcode: ReturnCode);
+}
+
+/// Sending and receiving reports of a HID device.
+pub trait HidDevice<'a> {
+    fn set_client(&self, client: &'a dyn HidClient);
+
+    /// The kind of the device, giving the length of its reports.
+    fn kind(&self) -> HidKind;
+
+    /// Send the first `len` bytes of `report` as an input report. Returns
+    /// `ESIZE` if `len` is larger than an input report or than the buffer, and
+    /// `EBUSY` if a report is already being sent.
+    fn send_report(
+        &self,
+        report: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Receive the next output report into `report`. Returns `ESIZE` if the
+    /// buffer cannot hold an output report, and `EBUSY` if a buffer is already
+    /// waiting for a report.
+    fn receive_report(&self, report: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Take back the buffer passed to `receive_report`, if no report has been
+    /// received into it yet.
+    fn receive_cancel(&self) -> Option<&'static mut [u8]>;
+}
+
+/// State of a class-specific control transfer
+#[derive(Copy, Clone, PartialEq)]
+enum CtrlState {
+    Idle,
+    /// The host is about to send an output report
+    SetReport,
+}
+
+pub struct UsbHid<'a, C: 'a> {
+    controller: &'a C,
+    // Device endpoint number of the IN endpoint
+    first_endpoint: Cell<usize>,
+    kind: HidKind,
+
+    // A 64-byte buffer for each endpoint
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    client: OptionalCell<&'a dyn HidClient>,
+
+    ctrl_state: Cell<CtrlState>,
+    idle_rate: Cell<u8>,
+    protocol: Cell<u8>,
+
+    send_buffer: TakeCell<'static, [u8]>,
+    send_len: Cell<usize>,
+    // The report has been handed to the controller
+    send_done: Cell<bool>,
+    send_result: OptionalCell<ReturnCode>,
+    // Whether the controller waits for `endpoint_resume_in()`
+    in_delayed: Cell<bool>,
+
+    receive_buffer: TakeCell<'static, [u8]>,
+    // Whether the controller waits for `endpoint_resume_out()`
+    out_delayed: Cell<bool>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> UsbHid<'a, C> {
+    pub fn new(controller: &'a C, kind: HidKind, deferred_caller: &'a DynamicDeferredCall) -> Self {
+        UsbHid {
+            controller,
+            first_endpoint: Cell::new(ENDPOINT_IN),
+            kind,
+            buffers: Default::default(),
+            client: OptionalCell::empty(),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            idle_rate: Cell::new(0),
+            protocol: Cell::new(1), // Report protocol
+            send_buffer: TakeCell::empty(),
+            send_len: Cell::new(0),
+            send_done: Cell::new(false),
+            send_result: OptionalCell::empty(),
+            in_delayed: Cell::new(true),
+            receive_buffer: TakeCell::empty(),
+            out_delayed: Cell::new(false),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// The device endpoint number of an endpoint of the function
+    fn endpoint(&self, endpoint: usize) -> usize {
+        self.first_endpoint.get() + endpoint - ENDPOINT_IN
+    }
+
+    fn schedule_callback(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Copies an output report into the waiting buffer, if any. Returns
+    /// whether there was a buffer.
+    fn deliver_report(&self, packet: &[VolatileCell<u8>], len: usize) -> bool {
+        self.receive_buffer
+            .take()
+            .map(|buffer| {
+                let len = cmp::min(len, buffer.len());
+                for i in 0..len {
+                    buffer[i] = packet[i].get();
+                }
+                self.client
+                    .map(move |client| client.report_received(buffer, len, ReturnCode::SUCCESS));
+            })
+            .is_some()
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> UsbFunction<'a> for UsbHid<'a, C> {
+    fn descriptors(&self) -> FunctionDescriptors {
+        FunctionDescriptors {
+            interfaces: self.kind.interfaces(),
+            endpoints: self.kind.endpoints(),
+            cdc_descriptors: &[],
+            hid_descriptor: Some(self.kind.hid_descriptor()),
+            report_descriptor: Some(self.kind.report_descriptor()),
+            dfu_descriptor: None,
+        }
+    }
+
+    fn set_first_endpoint(&self, endpoint: usize) {
+        self.first_endpoint.set(endpoint);
+    }
+
+    fn enable(&'a self) {
+        self.controller
+            .endpoint_set_in_buffer(self.endpoint(ENDPOINT_IN), &self.buffers[0].buf);
+        self.controller
+            .endpoint_in_enable(TransferType::Interrupt, self.endpoint(ENDPOINT_IN));
+
+        self.controller
+            .endpoint_set_out_buffer(self.endpoint(ENDPOINT_OUT), &self.buffers[1].buf);
+        self.controller
+            .endpoint_out_enable(TransferType::Interrupt, self.endpoint(ENDPOINT_OUT));
+    }
+
+    fn bus_reset(&'a self) {
+        if self.send_buffer.is_some() && self.send_result.is_none() {
+            self.send_result.set(ReturnCode::ECANCEL);
+            self.schedule_callback();
+        }
+        self.ctrl_state.set(CtrlState::Idle);
+        self.idle_rate.set(0);
+        self.protocol.set(1);
+        self.in_delayed.set(true);
+        self.out_delayed.set(false);
+    }
+
+    /// Handle a class-specific request
+    fn ctrl_setup(&'a self, setup: &SetupData, data: &[Cell<u8>]) -> CtrlRequestResult {
+        if let RequestType::Class = setup.request_type.request_type() {
+            match setup.request_code {
+                GET_REPORT => {
+                    // Reports are only sent on the interrupt endpoint, answer
+                    // with an empty one.
+                    let report = [0; MAX_REPORT_LEN];
+                    CtrlRequestResult::data(data, &report[..self.kind.input_report_len()])
+                }
+                SET_REPORT => {
+                    self.ctrl_state.set(CtrlState::SetReport);
+                    CtrlRequestResult::Ok
+                }
+                GET_IDLE => CtrlRequestResult::data(data, &[self.idle_rate.get()]),
+                SET_IDLE => {
+                    self.idle_rate.set((setup.value >> 8) as u8);
+                    CtrlRequestResult::Ok
+                }
+                GET_PROTOCOL => CtrlRequestResult::data(data, &[self.protocol.get()]),
+                SET_PROTOCOL => {
+                    // The boot and report protocols have the same reports here
+                    self.protocol.set(setup.value as u8);
+                    CtrlRequestResult::Ok
+                }
+                _ => CtrlRequestResult::Error,
+            }
+        } else {
+            CtrlRequestResult::Error
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, data: &[VolatileCell<u8>]) -> hil::usb::CtrlOutResult {
+        if self.ctrl_state.get() == CtrlState::SetReport {
+            // Reports nobody waits for are dropped, as the host cannot be
+            // held up on the control endpoint.
+            self.deliver_report(data, data.len());
+            hil::usb::CtrlOutResult::Ok
+        } else {
+            hil::usb::CtrlOutResult::Halted
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self) {
+        self.ctrl_state.set(CtrlState::Idle);
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, endpoint: usize) -> hil::usb::InResult {
+        if endpoint != ENDPOINT_IN {
+            return hil::usb::InResult::Error;
+        }
+        if self.send_done.get() || self.send_result.is_some() {
+            self.in_delayed.set(true);
+            return hil::usb::InResult::Delay;
+        }
+        let packet = &self.buffers[0].buf;
+        let len = self.send_len.get();
+        let sent = self
+            .send_buffer
+            .map(|buffer| {
+                for i in 0..len {
+                    packet[i].set(buffer[i]);
+                }
+            })
+            .is_some();
+        if sent {
+            self.send_done.set(true);
+            self.send_result.set(ReturnCode::SUCCESS);
+            self.schedule_callback();
+            hil::usb::InResult::Packet(len)
+        } else {
+            self.in_delayed.set(true);
+            hil::usb::InResult::Delay
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::OutResult {
+        if endpoint != ENDPOINT_OUT {
+            return hil::usb::OutResult::Error;
+        }
+        if self.deliver_report(&self.buffers[1].buf, packet_bytes as usize) {
+            hil::usb::OutResult::Ok
+        } else {
+            // Wait until a buffer is available
+            self.out_delayed.set(true);
+            hil::usb::OutResult::Delay
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if endpoint == ENDPOINT_IN {
+            if self.send_buffer.is_some() && !self.send_done.get() {
+                self.controller
+                    .endpoint_resume_in(self.endpoint(ENDPOINT_IN));
+            } else {
+                self.in_delayed.set(true);
+            }
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> DynamicDeferredCallClient for UsbHid<'a, C> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if let Some(rcode) = self.send_result.take() {
+            self.send_done.set(false);
+            let len = self.send_len.get();
+            self.send_buffer.take().map(|buffer| {
+                self.client
+                    .map(move |client| client.report_sent(buffer, len, rcode));
+            });
+        }
+
+        if self.receive_buffer.is_some() && self.out_delayed.take() {
+            // This may deliver the delayed report right away
+            self.controller
+                .endpoint_resume_out(self.endpoint(ENDPOINT_OUT));
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> HidDevice<'a> for UsbHid<'a, C> {
+    fn set_client(&self, client: &'a dyn HidClient) {
+        self.client.set(client);
+    }
+
+    fn kind(&self) -> HidKind {
+        self.kind
+    }
+
+    fn send_report(
+        &self,
+        report: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if len > self.kind.input_report_len() || len > report.len() {
+            (ReturnCode::ESIZE, Some(report))
+        } else if self.send_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(report))
+        } else {
+            self.send_buffer.replace(report);
+            self.send_len.set(len);
+            if self.in_delayed.take() {
+                self.controller
+                    .endpoint_resume_in(self.endpoint(ENDPOINT_IN));
+            }
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn receive_report(&self, report: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if report.len() < self.kind.output_report_len() {
+            (ReturnCode::ESIZE, Some(report))
+        } else if self.receive_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(report))
+        } else {
+            self.receive_buffer.replace(report);
+            if self.out_delayed.get() {
+                // Hand over the delayed report outside of this call
+                self.schedule_callback();
+            }
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn receive_cancel(&self) -> Option<&'static mut [u8]> {
+        self.receive_buffer.take()
+    }
+}
@@ -0,0 +1,330 @@
+//! USB HID system call interface
+//!
+//! This capsule lets applications send input reports to the host and receive
+//! output reports from it through a `usb::hid::HidDevice`.
+//!
+//! Reports are sent one at a time, in the order applications request them.
+//! An output report is copied to every application that is waiting for one.
+//! While no application waits, the host is held off, so reports are not lost.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let hid_driver = static_init!(
+//!     capsules::usb::hid_user::HidDriver<
+//!         'static,
+//!         capsules::usb::hid::UsbHid<'static, nrf52::usbd::Usbd<'static>>,
+//!     >,
+//!     capsules::usb::hid_user::HidDriver::new(
+//!         hid,
+//!         &mut capsules::usb::hid_user::SEND_BUF,
+//!         &mut capsules::usb::hid_user::RECEIVE_BUF,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hid.set_client(hid_driver);
+//! ```
+//!
+//! ## Interface
+//!
+//! ### Allow
+//!
+//! - 0: The input report to send.
+//! - 1: The buffer for output reports.
+//!
+//! ### Subscribe
+//!
+//! - 0: An input report was sent. The callback receives the return code and
+//!   the length of the report. The return code is `ESIZE` if the allowed input
+//!   report became shorter than the length to send before it was sent.
+//! - 1: An output report was received. The callback receives the return code
+//!   and the length of the report, which may be larger than the buffer.
+//!
+//! ### Command
+//!
+//! - 0: Driver check.
+//! - 1: Send the first `data` bytes of the allowed input report.
+//! - 2: Wait for the next output report.
+//! - 3: Stop waiting for output reports.
+//! - 4: Return the kind of device: 0 for a keyboard, 1 for a mouse and 2 for
+//!   a vendor-defined device.
+
+use super::hid::{HidClient, HidDevice, HidKind, MAX_REPORT_LEN};
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UsbHid as usize;
+
+pub static mut SEND_BUF: [u8; MAX_REPORT_LEN] = [0; MAX_REPORT_LEN];
+pub static mut RECEIVE_BUF: [u8; MAX_REPORT_LEN] = [0; MAX_REPORT_LEN];
+
+#[derive(Default)]
+pub struct App {
+    send_callback: Option<Callback>,
+    receive_callback: Option<Callback>,
+    send_buffer: Option<AppSlice<Shared, u8>>,
+    receive_buffer: Option<AppSlice<Shared, u8>>,
+    // Length of the report waiting to be sent
+    pending_send: Option<usize>,
+    pending_receive: bool,
+}
+
+pub struct HidDriver<'a, H: HidDevice<'a>> {
+    hid: &'a H,
+    apps: Grant<App>,
+    sending_app: OptionalCell<AppId>,
+    send_buffer: TakeCell<'static, [u8]>,
+    receive_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, H: HidDevice<'a>> HidDriver<'a, H> {
+    pub fn new(
+        hid: &'a H,
+        send_buffer: &'static mut [u8],
+        receive_buffer: &'static mut [u8],
+        apps: Grant<App>,
+    ) -> HidDriver<'a, H> {
+        HidDriver {
+            hid: hid,
+            apps: apps,
+            sending_app: OptionalCell::empty(),
+            send_buffer: TakeCell::new(send_buffer),
+            receive_buffer: TakeCell::new(receive_buffer),
+        }
+    }
+
+    /// Sends the report of the next application waiting to send one.
+    fn send_next(&self) {
+        if self.sending_app.is_some() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let started = cntr.enter(|app, _| {
+                let len = match app.pending_send {
+                    Some(len) => len,
+                    None => return None,
+                };
+                // The application may have allowed a shorter buffer since it
+                // asked to send the report.
+                let allowed = app.send_buffer.as_ref().map_or(0, |slice| slice.len());
+                let result = if allowed < len {
+                    ReturnCode::ESIZE
+                } else {
+                    self.send_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+                        if len > buffer.len() {
+                            self.send_buffer.replace(buffer);
+                            return ReturnCode::ESIZE;
+                        }
+                        app.send_buffer.as_ref().map(|slice| {
+                            buffer[..len].copy_from_slice(&slice.as_ref()[..len]);
+                        });
+                        let (rcode, buffer) = self.hid.send_report(buffer, len);
+                        buffer.map(|buffer| self.send_buffer.replace(buffer));
+                        rcode
+                    })
+                };
+                if result == ReturnCode::SUCCESS {
+                    Some(app.appid())
+                } else {
+                    app.pending_send = None;
+                    app.send_callback
+                        .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+                    None
+                }
+            });
+            if let Some(appid) = started {
+                self.sending_app.set(appid);
+                break;
+            }
+        }
+    }
+
+    /// Hands the receive buffer to the device if an application waits for a
+    /// report.
+    fn receive_next(&self) {
+        let waiting = self
+            .apps
+            .iter()
+            .any(|cntr| cntr.enter(|app, _| app.pending_receive));
+        if waiting {
+            self.receive_buffer.take().map(|buffer| {
+                let (_, buffer) = self.hid.receive_report(buffer);
+                buffer.map(|buffer| self.receive_buffer.replace(buffer));
+            });
+        }
+    }
+}
+
+impl<'a, H: HidDevice<'a>> HidClient for HidDriver<'a, H> {
+    fn report_sent(&self, report: &'static mut [u8], len: usize, rcode: ReturnCode) {
+        self.send_buffer.replace(report);
+        self.sending_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.pending_send = None;
+                app.send_callback
+                    .map(|mut cb| cb.schedule(usize::from(rcode), len, 0));
+            });
+        });
+        self.send_next();
+    }
+
+    fn report_received(&self, report: &'static mut [u8], len: usize, rcode: ReturnCode) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if !app.pending_receive {
+                    return;
+                }
+                app.pending_receive = false;
+                app.receive_buffer.as_mut().map(|slice| {
+                    let count = cmp::min(len, slice.len());
+                    slice.as_mut()[..count].copy_from_slice(&report[..count]);
+                });
+                app.receive_callback
+                    .map(|mut cb| cb.schedule(usize::from(rcode), len, 0));
+            });
+        }
+        self.receive_buffer.replace(report);
+    }
+}
+
+impl<'a, H: HidDevice<'a>> Driver for HidDriver<'a, H> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            // Input report to send
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.send_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            // Buffer for output reports
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.receive_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            // Report sent
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.send_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            // Report received
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.receive_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            // This driver is present
+            0 => ReturnCode::SUCCESS,
+
+            // Send an input report
+            1 => {
+                let max_len = self.hid.kind().input_report_len();
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        if app.pending_send.is_some() {
+                            ReturnCode::EBUSY
+                        } else if app.send_buffer.as_ref().map_or(0, |s| s.len()) < data
+                            || data > max_len
+                        {
+                            ReturnCode::ESIZE
+                        } else {
+                            app.pending_send = Some(data);
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.send_next();
+                }
+                result
+            }
+
+            // Wait for an output report
+            2 => {
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        if app.pending_receive {
+                            ReturnCode::EBUSY
+                        } else if app.receive_buffer.is_none() {
+                            ReturnCode::EINVAL
+                        } else {
+                            app.pending_receive = true;
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.receive_next();
+                }
+                result
+            }
+
+            // Stop waiting for output reports
+            3 => {
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.pending_receive = false;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                let waiting = self
+                    .apps
+                    .iter()
+                    .any(|cntr| cntr.enter(|app, _| app.pending_receive));
+                if !waiting {
+                    self.hid
+                        .receive_cancel()
+                        .map(|buffer| self.receive_buffer.replace(buffer));
+                }
+                result
+            }
+
+            // Kind of device
+            4 => ReturnCode::SuccessWithValue {
+                value: match self.hid.kind() {
+                    HidKind::Keyboard => 0,
+                    HidKind::Mouse => 1,
+                    HidKind::Vendor => 2,
+                },
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
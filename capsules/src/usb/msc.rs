@@ -0,0 +1,744 @@
+//! A USB mass storage class function
+//!
+//! It exports a `BlockStorage` volume to the host as a removable disk through
+//! a `usb::composite::CompositeDevice`, using
+//! the Bulk-Only Transport and the SCSI transparent command set. Hosts mount
+//! such disks without any extra driver, so logs or other files on the device
+//! can be read by plugging it in. `block_storage` provides volumes on top of
+//! nonvolatile storage and SD cards.
+//!
+//! The host sees the raw blocks of the volume and is free to format it; the
+//! kernel should not use the volume at the same time. With `read_only` set,
+//! the disk is write protected and writes from the host fail.
+//!
+//! Commands are handled one at a time. Blocks are transferred through a single
+//! buffer of `BLOCK_SIZE` bytes, so large reads and writes take a round trip
+//! to the storage for every block.
+//!
+//! The bulk endpoints use 64-byte buffers, so this requires a controller that
+//! supports them, such as `nrf52::usbd`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let msc = static_init!(
+//!     capsules::usb::msc::UsbMassStorage<'static, nrf52::usbd::Usbd>,
+//!     capsules::usb::msc::UsbMassStorage::new(
+//!         &nrf52::usbd::USBD,
+//!         volume,
+//!         &mut capsules::usb::msc::BUFFER,
+//!         false,
+//!         &["Tock", "Storage"],
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! msc.initialize_callback_handle(dynamic_deferred_caller.register(msc).unwrap());
+//! volume.set_client(msc);
+//! usb.add_function(msc, "Storage");
+//! ```
+
+use super::composite::{CtrlRequestResult, FunctionDescriptors, UsbFunction};
+use super::descriptors::{
+    Buffer64, EndpointAddress, EndpointDescriptor, InterfaceDescriptor, RequestType, SetupData,
+    TransferDirection,
+};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil;
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient, BLOCK_SIZE};
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Buffer for the blocks transferred to and from the storage
+pub static mut BUFFER: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+/// Bulk IN endpoint carrying data and status to the host
+const ENDPOINT_IN: usize = 1;
+/// Bulk OUT endpoint carrying commands and data from the host
+const ENDPOINT_OUT: usize = 2;
+
+const N_ENDPOINTS: usize = 2;
+
+const MAX_PACKET_SIZE: usize = 64;
+
+// Class-specific requests of the Bulk-Only Transport
+const GET_MAX_LUN: u8 = 0xfe;
+const BULK_ONLY_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_LEN: usize = 13;
+
+// Status of a command, reported in the command status wrapper
+const STATUS_PASSED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+const STATUS_PHASE_ERROR: u8 = 2;
+
+// SCSI commands
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5a;
+
+/// Sense key and additional sense code describing why a command failed
+#[derive(Copy, Clone, PartialEq)]
+struct Sense(u8, u8);
+
+const SENSE_NONE: Sense = Sense(0x00, 0x00);
+const SENSE_NOT_READY: Sense = Sense(0x02, 0x3a); // Medium not present
+const SENSE_READ_ERROR: Sense = Sense(0x03, 0x11); // Unrecovered read error
+const SENSE_WRITE_ERROR: Sense = Sense(0x03, 0x0c); // Write error
+const SENSE_INVALID_COMMAND: Sense = Sense(0x05, 0x20); // Invalid command operation code
+const SENSE_OUT_OF_RANGE: Sense = Sense(0x05, 0x21); // Logical block address out of range
+const SENSE_MEDIUM_CHANGED: Sense = Sense(0x06, 0x28); // Not ready to ready change
+const SENSE_WRITE_PROTECTED: Sense = Sense(0x07, 0x27); // Write protected
+
+static INTERFACES: &'static [InterfaceDescriptor] = &[InterfaceDescriptor {
+    interface_number: 0,
+    alternate_setting: 0,
+    num_endpoints: 0,         // Filled in by `CompositeDevice`
+    interface_class: 0x08,    // Mass storage
+    interface_subclass: 0x06, // SCSI transparent command set
+    interface_protocol: 0x50, // Bulk-Only Transport
+    string_index: 0,
+}];
+
+static ENDPOINTS: [EndpointDescriptor; N_ENDPOINTS] = [
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(ENDPOINT_IN, TransferDirection::DeviceToHost),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(ENDPOINT_OUT, TransferDirection::HostToDevice),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+];
+
+static INTERFACE_ENDPOINTS: &'static [&'static [EndpointDescriptor]] = &[&ENDPOINTS];
+
+/// Stage of the command being processed
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    /// Waiting for a command block wrapper from the host
+    Command,
+    /// Reading the next block from the storage
+    Read,
+    /// Sending the contents of the buffer to the host
+    DataIn,
+    /// Sending zeros until the host got all the data it asked for
+    PadIn,
+    /// Receiving the next block from the host
+    DataOut,
+    /// Writing the received block to the storage
+    Write,
+    /// Dropping the data the host still sends after an error
+    DropOut,
+    /// Sending the command status wrapper
+    Status,
+}
+
+fn get_u16_be(data: &[VolatileCell<u8>]) -> u16 {
+    (data[0].get() as u16) << 8 | data[1].get() as u16
+}
+
+fn get_u32_be(data: &[VolatileCell<u8>]) -> u32 {
+    (get_u16_be(data) as u32) << 16 | get_u16_be(&data[2..]) as u32
+}
+
+fn get_u32_le(data: &[VolatileCell<u8>]) -> u32 {
+    (0..4).fold(0, |acc, i| acc | (data[i].get() as u32) << (8 * i))
+}
+
+fn put_u32_le(data: &[VolatileCell<u8>], value: u32) {
+    for i in 0..4 {
+        data[i].set((value >> (8 * i)) as u8);
+    }
+}
+
+/// Copies `text` into `field`, padded with spaces as SCSI expects.
+fn put_text(field: &mut [u8], text: &str) {
+    for (i, byte) in field.iter_mut().enumerate() {
+        *byte = *text.as_bytes().get(i).unwrap_or(&b' ');
+    }
+}
+
+pub struct UsbMassStorage<'a, C: 'a> {
+    controller: &'a C,
+    // Device endpoint number of the IN endpoint
+    first_endpoint: Cell<usize>,
+    storage: &'a dyn BlockStorage<'a>,
+    read_only: bool,
+    // Vendor and product reported to SCSI inquiries
+    strings: &'static [&'static str; 2],
+
+    // A 64-byte buffer for each endpoint
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    buffer: TakeCell<'static, [u8]>,
+    // Valid bytes in the buffer, and how many of them have been sent
+    buffer_len: Cell<usize>,
+    buffer_offset: Cell<usize>,
+
+    state: Cell<State>,
+    // A read or write of the storage is in progress
+    storage_busy: Cell<bool>,
+
+    // The command being processed
+    tag: Cell<u32>,
+    data_in: Cell<bool>,
+    expected_len: Cell<u32>,
+    // Bytes of the data stage that went over the bus
+    transferred_len: Cell<u32>,
+    // Bytes of the data stage that were actually read or written
+    processed_len: Cell<u32>,
+    status: Cell<u8>,
+    next_block: Cell<u32>,
+    blocks_left: Cell<u32>,
+
+    sense: Cell<Sense>,
+    medium_changed: Cell<bool>,
+
+    // Whether the controller waits for `endpoint_resume_in()`
+    in_delayed: Cell<bool>,
+    // Whether the controller waits for `endpoint_resume_out()`
+    out_delayed: Cell<bool>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> UsbMassStorage<'a, C> {
+    /// `strings` are the vendor and product reported to SCSI inquiries.
+    pub fn new(
+        controller: &'a C,
+        storage: &'a dyn BlockStorage<'a>,
+        buffer: &'static mut [u8],
+        read_only: bool,
+        strings: &'static [&'static str; 2],
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> Self {
+        UsbMassStorage {
+            controller,
+            first_endpoint: Cell::new(ENDPOINT_IN),
+            storage,
+            read_only,
+            strings,
+            buffers: Default::default(),
+            buffer: TakeCell::new(buffer),
+            buffer_len: Cell::new(0),
+            buffer_offset: Cell::new(0),
+            state: Cell::new(State::Command),
+            storage_busy: Cell::new(false),
+            tag: Cell::new(0),
+            data_in: Cell::new(false),
+            expected_len: Cell::new(0),
+            transferred_len: Cell::new(0),
+            processed_len: Cell::new(0),
+            status: Cell::new(STATUS_PASSED),
+            next_block: Cell::new(0),
+            blocks_left: Cell::new(0),
+            sense: Cell::new(SENSE_NONE),
+            medium_changed: Cell::new(false),
+            in_delayed: Cell::new(true),
+            out_delayed: Cell::new(false),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// The device endpoint number of an endpoint of the function
+    fn endpoint(&self, endpoint: usize) -> usize {
+        self.first_endpoint.get() + endpoint - ENDPOINT_IN
+    }
+
+    fn schedule_callback(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn remaining_len(&self) -> u32 {
+        self.expected_len.get() - self.transferred_len.get()
+    }
+
+    /// Ends the data stage of the current command, padding or dropping the
+    /// data the host still expects to transfer.
+    fn end_data(&self) {
+        if self.remaining_len() == 0 {
+            self.state.set(State::Status);
+        } else if self.data_in.get() {
+            self.state.set(State::PadIn);
+        } else {
+            self.state.set(State::DropOut);
+        }
+    }
+
+    fn fail(&self, sense: Sense) {
+        self.sense.set(sense);
+        self.status.set(STATUS_FAILED);
+        self.end_data();
+    }
+
+    /// Sends `data` as the response to the current command.
+    fn respond(&self, data: &[u8]) {
+        if !self.data_in.get() || self.expected_len.get() == 0 {
+            // The host does not expect data from the device
+            self.status.set(STATUS_PHASE_ERROR);
+            self.end_data();
+            return;
+        }
+        let len = cmp::min(data.len(), self.expected_len.get() as usize);
+        self.buffer
+            .map(|buffer| buffer[..len].copy_from_slice(&data[..len]));
+        self.buffer_len.set(len);
+        self.buffer_offset.set(0);
+        self.processed_len.set(len as u32);
+        self.state.set(State::DataIn);
+    }
+
+    /// Sets up the block transfer of a `READ(10)` or `WRITE(10)` command.
+    fn start_transfer(&self, data_in: bool, block: u32, count: u32) {
+        let block_count = match self.storage.block_count() {
+            Some(block_count) => block_count,
+            None => return self.fail(SENSE_NOT_READY),
+        };
+        if block as u64 + count as u64 > block_count as u64 {
+            return self.fail(SENSE_OUT_OF_RANGE);
+        }
+        if !data_in && self.read_only {
+            return self.fail(SENSE_WRITE_PROTECTED);
+        }
+        let len = count as u64 * BLOCK_SIZE as u64;
+        if self.data_in.get() != data_in || len > self.expected_len.get() as u64 {
+            // The host expects less data, or data in the other direction
+            self.status.set(STATUS_PHASE_ERROR);
+            return self.end_data();
+        }
+        self.next_block.set(block);
+        self.blocks_left.set(count);
+        if count == 0 {
+            self.end_data();
+        } else if data_in {
+            self.state.set(State::Read);
+        } else {
+            self.buffer_offset.set(0);
+            self.state.set(State::DataOut);
+        }
+    }
+
+    /// Processes the SCSI command in a command block wrapper.
+    fn handle_command(&self, cb: &[VolatileCell<u8>]) {
+        let present = self.storage.block_count().is_some();
+        match cb[0].get() {
+            TEST_UNIT_READY => {
+                if !present {
+                    self.fail(SENSE_NOT_READY);
+                } else if self.medium_changed.take() {
+                    self.fail(SENSE_MEDIUM_CHANGED);
+                } else {
+                    self.end_data();
+                }
+            }
+            REQUEST_SENSE => {
+                let Sense(key, code) = self.sense.replace(SENSE_NONE);
+                let mut data = [0; 18];
+                data[0] = 0x70; // Current error, fixed format
+                data[2] = key;
+                data[7] = 10; // Additional sense length
+                data[12] = code;
+                self.respond(&data);
+            }
+            INQUIRY => {
+                let mut data = [0; 36];
+                data[0] = 0x00; // Direct access block device
+                data[1] = 0x80; // Removable medium
+                data[2] = 0x04; // SPC-2
+                data[3] = 0x02; // Response data format
+                data[4] = 31; // Additional length
+                put_text(&mut data[8..16], self.strings[0]);
+                put_text(&mut data[16..32], self.strings[1]);
+                put_text(&mut data[32..36], "1.0");
+                self.respond(&data);
+            }
+            MODE_SENSE_6 => {
+                let protect = if self.read_only { 0x80 } else { 0x00 };
+                self.respond(&[3, 0, protect, 0]);
+            }
+            MODE_SENSE_10 => {
+                let protect = if self.read_only { 0x80 } else { 0x00 };
+                self.respond(&[0, 6, 0, protect, 0, 0, 0, 0]);
+            }
+            READ_FORMAT_CAPACITIES => match self.storage.block_count() {
+                Some(count) => {
+                    let mut data = [0; 12];
+                    data[3] = 8; // Capacity list length
+                    data[4..8].copy_from_slice(&count.to_be_bytes());
+                    data[8] = 0x02; // Formatted medium
+                    data[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+                    self.respond(&data);
+                }
+                None => self.fail(SENSE_NOT_READY),
+            },
+            READ_CAPACITY_10 => match self.storage.block_count() {
+                Some(count) if count > 0 => {
+                    let mut data = [0; 8];
+                    data[0..4].copy_from_slice(&(count - 1).to_be_bytes());
+                    data[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                    self.respond(&data);
+                }
+                _ => self.fail(SENSE_NOT_READY),
+            },
+            READ_10 => self.start_transfer(true, get_u32_be(&cb[2..]), get_u16_be(&cb[7..]) as u32),
+            WRITE_10 => {
+                self.start_transfer(false, get_u32_be(&cb[2..]), get_u16_be(&cb[7..]) as u32)
+            }
+            START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL | VERIFY_10 | SYNCHRONIZE_CACHE_10 => {
+                if present {
+                    self.end_data();
+                } else {
+                    self.fail(SENSE_NOT_READY);
+                }
+            }
+            _ => self.fail(SENSE_INVALID_COMMAND),
+        }
+    }
+
+    /// Parses a command block wrapper received on the OUT endpoint.
+    fn handle_cbw(&self, packet: &[VolatileCell<u8>], len: usize) {
+        if len != CBW_LEN || get_u32_le(packet) != CBW_SIGNATURE {
+            // Not a valid command, wait for the next one
+            return;
+        }
+        self.tag.set(get_u32_le(&packet[4..]));
+        self.expected_len.set(get_u32_le(&packet[8..]));
+        self.data_in.set(packet[12].get() & 0x80 != 0);
+        self.transferred_len.set(0);
+        self.processed_len.set(0);
+        self.status.set(STATUS_PASSED);
+        if packet[13].get() != 0 {
+            // There is only one logical unit
+            self.fail(SENSE_INVALID_COMMAND);
+        } else {
+            self.handle_command(&packet[15..31]);
+        }
+        self.schedule_callback();
+    }
+
+    /// Starts the storage access of the current state, if any.
+    fn access_storage(&self) {
+        if self.storage_busy.get() {
+            return;
+        }
+        let write = match self.state.get() {
+            State::Read => false,
+            State::Write => true,
+            _ => return,
+        };
+        self.buffer.take().map(|buffer| {
+            self.storage_busy.set(true);
+            let block = self.next_block.get();
+            let (rcode, buffer) = if write {
+                self.storage.write_block(buffer, block)
+            } else {
+                self.storage.read_block(buffer, block)
+            };
+            if rcode != ReturnCode::SUCCESS {
+                self.storage_busy.set(false);
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.fail(if write {
+                    SENSE_WRITE_ERROR
+                } else {
+                    SENSE_READ_ERROR
+                });
+                self.schedule_callback();
+            }
+        });
+    }
+
+    fn reset(&self) {
+        self.state.set(State::Command);
+        self.sense.set(SENSE_NONE);
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> UsbFunction<'a> for UsbMassStorage<'a, C> {
+    fn descriptors(&self) -> FunctionDescriptors {
+        FunctionDescriptors {
+            interfaces: INTERFACES,
+            endpoints: INTERFACE_ENDPOINTS,
+            cdc_descriptors: &[],
+            hid_descriptor: None,
+            report_descriptor: None,
+            dfu_descriptor: None,
+        }
+    }
+
+    fn set_first_endpoint(&self, endpoint: usize) {
+        self.first_endpoint.set(endpoint);
+    }
+
+    fn enable(&'a self) {
+        self.controller
+            .endpoint_set_in_buffer(self.endpoint(ENDPOINT_IN), &self.buffers[0].buf);
+        self.controller
+            .endpoint_in_enable(TransferType::Bulk, self.endpoint(ENDPOINT_IN));
+
+        self.controller
+            .endpoint_set_out_buffer(self.endpoint(ENDPOINT_OUT), &self.buffers[1].buf);
+        self.controller
+            .endpoint_out_enable(TransferType::Bulk, self.endpoint(ENDPOINT_OUT));
+    }
+
+    fn bus_reset(&'a self) {
+        self.reset();
+        self.in_delayed.set(true);
+        self.out_delayed.set(false);
+    }
+
+    /// Handle a class-specific request of the Bulk-Only Transport
+    fn ctrl_setup(&'a self, setup: &SetupData, data: &[Cell<u8>]) -> CtrlRequestResult {
+        if let RequestType::Class = setup.request_type.request_type() {
+            match setup.request_code {
+                GET_MAX_LUN => CtrlRequestResult::data(data, &[0]),
+                BULK_ONLY_RESET => {
+                    self.reset();
+                    self.schedule_callback();
+                    CtrlRequestResult::Ok
+                }
+                _ => CtrlRequestResult::Error,
+            }
+        } else {
+            CtrlRequestResult::Error
+        }
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, endpoint: usize) -> hil::usb::InResult {
+        if endpoint != ENDPOINT_IN {
+            return hil::usb::InResult::Error;
+        }
+        let packet = &self.buffers[0].buf;
+        match self.state.get() {
+            State::DataIn => {
+                let offset = self.buffer_offset.get();
+                let count = cmp::min(
+                    MAX_PACKET_SIZE,
+                    cmp::min(
+                        self.buffer_len.get() - offset,
+                        self.remaining_len() as usize,
+                    ),
+                );
+                self.buffer.map(|buffer| {
+                    for i in 0..count {
+                        packet[i].set(buffer[offset + i]);
+                    }
+                });
+                self.buffer_offset.set(offset + count);
+                self.transferred_len
+                    .set(self.transferred_len.get() + count as u32);
+                if self.buffer_offset.get() == self.buffer_len.get() {
+                    if self.blocks_left.get() > 0 {
+                        self.state.set(State::Read);
+                        self.schedule_callback();
+                    } else if count < MAX_PACKET_SIZE {
+                        // The short packet ends the data stage
+                        self.state.set(State::Status);
+                    } else {
+                        self.end_data();
+                    }
+                }
+                hil::usb::InResult::Packet(count)
+            }
+            State::PadIn => {
+                let count = cmp::min(MAX_PACKET_SIZE, self.remaining_len() as usize);
+                for i in 0..count {
+                    packet[i].set(0);
+                }
+                self.transferred_len
+                    .set(self.transferred_len.get() + count as u32);
+                self.end_data();
+                hil::usb::InResult::Packet(count)
+            }
+            State::Status => {
+                put_u32_le(&packet[0..], CSW_SIGNATURE);
+                put_u32_le(&packet[4..], self.tag.get());
+                put_u32_le(
+                    &packet[8..],
+                    self.expected_len.get() - self.processed_len.get(),
+                );
+                packet[12].set(self.status.get());
+                self.state.set(State::Command);
+                // Accept the next command
+                self.schedule_callback();
+                hil::usb::InResult::Packet(CSW_LEN)
+            }
+            _ => {
+                self.in_delayed.set(true);
+                hil::usb::InResult::Delay
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::OutResult {
+        if endpoint != ENDPOINT_OUT {
+            return hil::usb::OutResult::Error;
+        }
+        let packet = &self.buffers[1].buf;
+        let len = cmp::min(packet_bytes as usize, packet.len());
+        match self.state.get() {
+            State::Command => {
+                self.handle_cbw(packet, len);
+                hil::usb::OutResult::Ok
+            }
+            State::DataOut => {
+                let offset = self.buffer_offset.get();
+                let count = cmp::min(len, BLOCK_SIZE - offset);
+                self.buffer.map(|buffer| {
+                    for i in 0..count {
+                        buffer[offset + i] = packet[i].get();
+                    }
+                });
+                self.buffer_offset.set(offset + count);
+                self.transferred_len.set(cmp::min(
+                    self.transferred_len.get() + len as u32,
+                    self.expected_len.get(),
+                ));
+                if self.buffer_offset.get() == BLOCK_SIZE {
+                    self.state.set(State::Write);
+                    self.schedule_callback();
+                } else if count < MAX_PACKET_SIZE {
+                    // The host ended the data stage before the whole block
+                    self.status.set(STATUS_PHASE_ERROR);
+                    self.blocks_left.set(0);
+                    self.state.set(State::Status);
+                    self.schedule_callback();
+                }
+                hil::usb::OutResult::Ok
+            }
+            State::DropOut => {
+                self.transferred_len.set(cmp::min(
+                    self.transferred_len.get() + len as u32,
+                    self.expected_len.get(),
+                ));
+                if self.remaining_len() == 0 || len < MAX_PACKET_SIZE {
+                    self.state.set(State::Status);
+                    self.schedule_callback();
+                }
+                hil::usb::OutResult::Ok
+            }
+            _ => {
+                // Wait until the current stage is done
+                self.out_delayed.set(true);
+                hil::usb::OutResult::Delay
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if endpoint == ENDPOINT_IN {
+            match self.state.get() {
+                State::DataIn | State::PadIn | State::Status => self
+                    .controller
+                    .endpoint_resume_in(self.endpoint(ENDPOINT_IN)),
+                _ => self.in_delayed.set(true),
+            }
+        }
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> BlockStorageClient for UsbMassStorage<'a, C> {
+    fn read_done(&self, buffer: &'static mut [u8], rcode: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.storage_busy.set(false);
+        if self.state.get() != State::Read {
+            // The command was aborted by a reset
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.next_block.set(self.next_block.get() + 1);
+            self.blocks_left.set(self.blocks_left.get() - 1);
+            self.buffer_len.set(BLOCK_SIZE);
+            self.buffer_offset.set(0);
+            self.processed_len
+                .set(self.processed_len.get() + BLOCK_SIZE as u32);
+            self.state.set(State::DataIn);
+        } else {
+            self.fail(SENSE_READ_ERROR);
+        }
+        self.schedule_callback();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], rcode: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.storage_busy.set(false);
+        if self.state.get() != State::Write {
+            // The command was aborted by a reset
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.next_block.set(self.next_block.get() + 1);
+            self.blocks_left.set(self.blocks_left.get() - 1);
+            self.processed_len
+                .set(self.processed_len.get() + BLOCK_SIZE as u32);
+            if self.blocks_left.get() > 0 {
+                self.buffer_offset.set(0);
+                self.state.set(State::DataOut);
+            } else {
+                self.end_data();
+            }
+        } else {
+            self.fail(SENSE_WRITE_ERROR);
+        }
+        self.schedule_callback();
+    }
+
+    fn medium_changed(&self) {
+        self.medium_changed.set(true);
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> DynamicDeferredCallClient for UsbMassStorage<'a, C> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.access_storage();
+
+        match self.state.get() {
+            State::DataIn | State::PadIn | State::Status => {
+                if self.in_delayed.take() {
+                    self.controller
+                        .endpoint_resume_in(self.endpoint(ENDPOINT_IN));
+                }
+            }
+            State::Command | State::DataOut | State::DropOut => {
+                if self.out_delayed.take() {
+                    // This may deliver the delayed packet right away
+                    self.controller
+                        .endpoint_resume_out(self.endpoint(ENDPOINT_OUT));
+                }
+            }
+            State::Read | State::Write => {}
+        }
+    }
+}
@@ -3,7 +3,8 @@
 //! It responds to standard device requests and can be enumerated.
 
 use super::descriptors::{
-    self, Buffer8, DeviceDescriptor, EndpointAddress, EndpointDescriptor, TransferDirection,
+    self, Buffer8, DeviceDescriptor, EndpointAddress, EndpointDescriptor, InterfaceDescriptor,
+    TransferDirection,
 };
 use super::usbc_client_ctrl::ClientCtrl;
 use core::cell::Cell;
@@ -27,6 +28,16 @@ static STRINGS: &'static [&'static str] = &[
 
 const N_ENDPOINTS: usize = 2;
 
+static INTERFACES: &'static [InterfaceDescriptor] = &[InterfaceDescriptor {
+    interface_number: 0,
+    alternate_setting: 0,
+    num_endpoints: 0,      // Filled in by `ClientCtrl`
+    interface_class: 0xff, // vendor_specific
+    interface_subclass: 0xab,
+    interface_protocol: 0,
+    string_index: 0,
+}];
+
 static ENDPOINTS: &'static [EndpointDescriptor; N_ENDPOINTS] = &[
     EndpointDescriptor {
         endpoint_address: EndpointAddress::new_const(1, TransferDirection::DeviceToHost),
@@ -42,6 +53,8 @@ static ENDPOINTS: &'static [EndpointDescriptor; N_ENDPOINTS] = &[
     },
 ];
 
+static INTERFACE_ENDPOINTS: &'static [&'static [EndpointDescriptor]] = &[ENDPOINTS];
+
 pub struct Client<'a, C: 'a> {
     client_ctrl: ClientCtrl<'a, 'static, C>,
 
@@ -69,10 +82,11 @@ impl<'a, C: hil::usb::UsbController<'a>> Client<'a, C> {
                     ..DeviceDescriptor::default()
                 },
                 descriptors::ConfigurationDescriptor::default(),
-                descriptors::InterfaceDescriptor::default(),
-                ENDPOINTS,
+                INTERFACES,
+                INTERFACE_ENDPOINTS,
                 None, // No interface class descriptor
                 None, // No report descriptor
+                None, // No CDC functional descriptors
                 LANGUAGES,
                 STRINGS,
             ),
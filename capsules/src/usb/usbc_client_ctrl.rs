@@ -3,6 +3,7 @@
 //! It responds to control requests and forwards bulk/interrupt transfers to the above layer.
 
 use super::descriptors::Buffer64;
+use super::descriptors::CdcInterfaceDescriptor;
 use super::descriptors::ConfigurationDescriptor;
 use super::descriptors::Descriptor;
 use super::descriptors::DescriptorType;
@@ -19,10 +20,11 @@ use super::descriptors::StringDescriptor;
 use super::descriptors::TransferDirection;
 use core::cell::Cell;
 use core::cmp::min;
+use kernel::common::cells::VolatileCell;
 use kernel::hil;
 use kernel::hil::usb::TransferType;
 
-const DESCRIPTOR_BUFLEN: usize = 64;
+const DESCRIPTOR_BUFLEN: usize = 128;
 
 const N_ENDPOINTS: usize = 3;
 
@@ -37,7 +39,7 @@ pub struct ClientCtrl<'a, 'b, C: 'a> {
     ctrl_buffer: Buffer64,
 
     // Storage for composing responses to device-descriptor requests
-    descriptor_storage: [Cell<u8>; DESCRIPTOR_BUFLEN],
+    descriptor_storage: Cell<[u8; DESCRIPTOR_BUFLEN]>,
 
     // Descriptors to reply to control requests
     device_descriptor: DeviceDescriptor,
@@ -45,18 +47,21 @@ pub struct ClientCtrl<'a, 'b, C: 'a> {
     // For now we only support one configuration...
     configuration_descriptor: ConfigurationDescriptor,
 
-    // ...with only one interface
-    interface_descriptor: InterfaceDescriptor,
+    // ...with one or more interfaces
+    interface_descriptors: &'b [InterfaceDescriptor],
 
-    // A list of endpoints for the configuration
-    endpoint_descriptors: &'b [EndpointDescriptor],
+    // A list of endpoints for each interface of the configuration
+    endpoint_descriptors: &'b [&'b [EndpointDescriptor]],
 
-    // A HID descriptor for the configuration, if any
+    // A HID descriptor for the first interface, if any
     hid_descriptor: Option<&'b HIDDescriptor<'b>>,
 
     // A report descriptor for the configuration, if any
     report_descriptor: Option<&'b ReportDescriptor<'b>>,
 
+    // CDC functional descriptors for the first interface, if any
+    cdc_descriptors: Option<&'b [CdcInterfaceDescriptor]>,
+
     // Supported language (only one for now)
     language: &'b [u16; 1],
 
@@ -90,99 +95,40 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
         controller: &'a C,
         device_descriptor: DeviceDescriptor,
         mut configuration_descriptor: ConfigurationDescriptor,
-        mut interface_descriptor: InterfaceDescriptor,
-        endpoint_descriptors: &'b [EndpointDescriptor],
+        interface_descriptors: &'b [InterfaceDescriptor],
+        endpoint_descriptors: &'b [&'b [EndpointDescriptor]],
         hid_descriptor: Option<&'b HIDDescriptor<'b>>,
         report_descriptor: Option<&'b ReportDescriptor<'b>>,
+        cdc_descriptors: Option<&'b [CdcInterfaceDescriptor]>,
         language: &'b [u16; 1],
         strings: &'b [&'b str],
     ) -> Self {
-        // Tweak the configuration/interface descriptors for the given endpoints.
-        interface_descriptor.num_endpoints = endpoint_descriptors.len() as u8;
-
-        configuration_descriptor.num_interfaces = 1;
-        configuration_descriptor.related_descriptor_length = interface_descriptor.size()
-            + endpoint_descriptors.iter().map(|d| d.size()).sum::<usize>()
-            + hid_descriptor.map_or(0, |d| d.size());
+        // Tweak the configuration descriptor for the given interfaces and endpoints.
+        configuration_descriptor.num_interfaces = interface_descriptors.len() as u8;
+        configuration_descriptor.related_descriptor_length = interface_descriptors
+            .iter()
+            .map(|d| d.size())
+            .sum::<usize>()
+            + endpoint_descriptors
+                .iter()
+                .flat_map(|e| e.iter())
+                .map(|d| d.size())
+                .sum::<usize>()
+            + hid_descriptor.map_or(0, |d| d.size())
+            + cdc_descriptors.map_or(0, |c| c.iter().map(|d| d.size()).sum::<usize>());
 
         ClientCtrl {
             controller: controller,
             state: Default::default(),
             ctrl_buffer: Buffer64::default(),
-            // For the moment, the Default trait is not implemented for arrays of length > 32, and
-            // the Cell type is not Copy, so we have to initialize each element manually.
-            descriptor_storage: [
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-                Cell::default(),
-            ],
+            descriptor_storage: Cell::new([0; DESCRIPTOR_BUFLEN]),
             device_descriptor,
             configuration_descriptor,
-            interface_descriptor,
+            interface_descriptors,
             endpoint_descriptors,
             hid_descriptor,
             report_descriptor,
+            cdc_descriptors,
             language,
             strings,
         }
@@ -193,9 +139,42 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
         self.controller
     }
 
+    /// The buffer of the default control endpoint, holding the last Setup
+    /// packet or the data of a Control Out transaction
+    #[inline]
+    pub fn ctrl_buffer(&'a self) -> &'a [VolatileCell<u8>] {
+        &self.ctrl_buffer.buf
+    }
+
     #[inline]
     fn descriptor_buf(&'a self) -> &'a [Cell<u8>] {
-        &self.descriptor_storage
+        let storage: &Cell<[u8]> = &self.descriptor_storage;
+        storage.as_slice_of_cells()
+    }
+
+    /// The descriptor of the interface `index`, with its interface number and
+    /// endpoint count filled in
+    fn interface_descriptor(&self, index: usize) -> Option<InterfaceDescriptor> {
+        self.interface_descriptors
+            .get(index)
+            .map(|d| InterfaceDescriptor {
+                interface_number: index as u8,
+                num_endpoints: self.endpoint_descriptors.get(index).map_or(0, |e| e.len()) as u8,
+                ..*d
+            })
+    }
+
+    /// Arrange for `data` to be sent to the host in the Data stage of the
+    /// current Control In transfer on `endpoint`.  This lets a class layered on
+    /// top of this one answer its own requests.
+    pub fn ctrl_in_data(&'a self, endpoint: usize, data: &[u8], requested_length: u16) {
+        let buf = self.descriptor_buf();
+        let len = min(data.len(), buf.len());
+        for (dst, src) in buf.iter().zip(data[..len].iter()) {
+            dst.set(*src);
+        }
+        let end = min(len, requested_length as usize);
+        self.state[endpoint].set(State::CtrlIn(0, end));
     }
 
     pub fn enable(&'a self) {
@@ -291,20 +270,34 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                                 let buf = self.descriptor_buf();
                                 let mut len = 0;
 
-                                // A single configuration, with the following interface.
+                                // A single configuration, with the following interfaces.
                                 len += self.configuration_descriptor.write_to(&buf[len..]);
 
-                                // A single interface, with the following descriptors and endpoints.
-                                len += self.interface_descriptor.write_to(&buf[len..]);
-
-                                // HID descriptor, if any.
-                                if let Some(dh) = self.hid_descriptor {
-                                    len += dh.write_to(&buf[len..]);
-                                }
-
-                                // Endpoints.
-                                for de in self.endpoint_descriptors {
-                                    len += de.write_to(&buf[len..]);
+                                for i in 0..self.interface_descriptors.len() {
+                                    // Each interface, with the following descriptors and
+                                    // endpoints.
+                                    if let Some(di) = self.interface_descriptor(i) {
+                                        len += di.write_to(&buf[len..]);
+                                    }
+
+                                    if i == 0 {
+                                        // HID descriptor, if any.
+                                        if let Some(dh) = self.hid_descriptor {
+                                            len += dh.write_to(&buf[len..]);
+                                        }
+
+                                        // CDC functional descriptors, if any.
+                                        for dc in self.cdc_descriptors.unwrap_or(&[]) {
+                                            len += dc.write_to(&buf[len..]);
+                                        }
+                                    }
+
+                                    // Endpoints.
+                                    if let Some(endpoints) = self.endpoint_descriptors.get(i) {
+                                        for de in endpoints.iter() {
+                                            len += de.write_to(&buf[len..]);
+                                        }
+                                    }
                                 }
 
                                 let end = min(len, requested_length as usize);
@@ -314,17 +307,19 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                             _ => hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
                         }
                     }
-                    DescriptorType::Interface => match descriptor_index {
-                        0 => {
-                            let buf = self.descriptor_buf();
-                            let len = self.interface_descriptor.write_to(buf);
+                    DescriptorType::Interface => {
+                        match self.interface_descriptor(descriptor_index as usize) {
+                            Some(di) => {
+                                let buf = self.descriptor_buf();
+                                let len = di.write_to(buf);
 
-                            let end = min(len, requested_length as usize);
-                            self.state[endpoint].set(State::CtrlIn(0, end));
-                            hil::usb::CtrlSetupResult::Ok
+                                let end = min(len, requested_length as usize);
+                                self.state[endpoint].set(State::CtrlIn(0, end));
+                                hil::usb::CtrlSetupResult::Ok
+                            }
+                            None => hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
                         }
-                        _ => hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
-                    },
+                    }
                     DescriptorType::String => {
                         if let Some(len) = match descriptor_index {
                             0 => {
@@ -429,7 +424,7 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                 let len = end.saturating_sub(start);
                 if len > 0 {
                     let packet_bytes = min(self.ctrl_buffer.buf.len(), len);
-                    let packet = &self.descriptor_storage[start..start + packet_bytes];
+                    let packet = &self.descriptor_buf()[start..start + packet_bytes];
                     let buf = &self.ctrl_buffer.buf;
 
                     // Copy a packet into the endpoint buffer
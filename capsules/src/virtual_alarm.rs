@@ -1,15 +1,35 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! `MuxAlarm` extends the underlying hardware counter to 64 bits in software,
+//! and every virtual alarm is kept as a 64-bit expiration time, so alarms are
+//! ordered correctly no matter how often the hardware counter wraps. To notice
+//! every wrap, the mux keeps the hardware alarm armed at least once per half
+//! counter period, even when no virtual alarm is pending. This costs one
+//! interrupt per half period (every 256 s on the 24-bit nRF52 RTC at 32 kHz),
+//! and is what keeps `Ticks::now_ticks` correct across idle periods: a counter
+//! that is not observed for a full period would lose a wrap for good.
+//!
+//! `Alarm::set_alarm` only receives the low bits of the expiration time. It is
+//! taken as the first time the counter reaches them after the client last read
+//! `now` or the alarm last expired, or after half a period ago if that was
+//! longer ago. So alarms can be set up to one counter period after a fresh
+//! reading (512 s on the nRF52 RTC), and only times that passed since then
+//! fire right away. Longer alarms need `TicksAlarm::set_alarm_ticks`.
 
 use core::cell::Cell;
+use core::cmp;
 use kernel::common::cells::OptionalCell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Ticks, TicksAlarm, Time};
 
 pub struct VirtualMuxAlarm<'a, A: Alarm<'a>> {
     mux: &'a MuxAlarm<'a, A>,
-    when: Cell<u32>,
+    when: Cell<u64>,
     armed: Cell<bool>,
+    /// Time the client last read or the alarm last expired, from which
+    /// `set_alarm` counts.
+    reference: Cell<u64>,
     next: ListLink<'a, VirtualMuxAlarm<'a, A>>,
     client: OptionalCell<&'a dyn time::AlarmClient>,
 }
@@ -26,6 +46,7 @@ impl<A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
             mux: mux_alarm,
             when: Cell::new(0),
             armed: Cell::new(false),
+            reference: Cell::new(0),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
@@ -40,7 +61,15 @@ impl<A: Alarm<'a>> Time for VirtualMuxAlarm<'a, A> {
     }
 
     fn now(&self) -> u32 {
-        self.mux.alarm.now()
+        let now = self.mux.now_ticks();
+        self.reference.set(now);
+        self.mux.truncate(now)
+    }
+}
+
+impl<A: Alarm<'a>> Ticks for VirtualMuxAlarm<'a, A> {
+    fn now_ticks(&self) -> u64 {
+        self.mux.now_ticks()
     }
 }
 
@@ -49,24 +78,18 @@ impl<A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
         self.mux.virtual_alarms.push_head(self);
         self.when.set(0);
         self.armed.set(false);
+        self.reference.set(self.mux.now_ticks());
         self.client.set(client);
+
+        // Start tracking counter wraps as soon as there is a user.
+        self.mux.schedule();
     }
 
     fn disable(&self) {
-        if !self.armed.get() {
-            return;
-        }
-
+        // The underlying alarm stays armed for the keepalive described in the
+        // module documentation; if it fires early, the mux simply reschedules
+        // it.
         self.armed.set(false);
-
-        let enabled = self.mux.enabled.get() - 1;
-        self.mux.enabled.set(enabled);
-
-        // If there are not more enabled alarms, disable the underlying alarm
-        // completely.
-        if enabled == 0 {
-            self.mux.alarm.disable();
-        }
     }
 
     fn is_enabled(&self) -> bool {
@@ -74,30 +97,33 @@ impl<A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
     }
 
     fn set_alarm(&self, when: u32) {
-        let enabled = self.mux.enabled.get();
-
-        if !self.armed.get() {
-            self.mux.enabled.set(enabled + 1);
-            self.armed.set(true);
-        }
+        // `when` only holds the low bits of the extended clock. Expand it to
+        // the first matching time after the reference described in the module
+        // documentation. A reference more than half a period old is most
+        // likely not the one the client computed `when` from, so it then
+        // falls back to the nearest matching time to now.
+        let now = self.mux.now_ticks();
+        let period = self.mux.period();
+        let reference = cmp::max(self.reference.get(), now.saturating_sub(period / 2));
+        let delta = (when as u64 % period + period - reference % period) % period;
+        self.set_alarm_ticks(reference + delta);
+    }
 
-        if enabled > 0 {
-            let cur_alarm = self.mux.alarm.get_alarm();
-            let now = self.now();
+    fn get_alarm(&self) -> u32 {
+        self.mux.truncate(self.when.get())
+    }
+}
 
-            if cur_alarm.wrapping_sub(now) > when.wrapping_sub(now) {
-                self.mux.prev.set(self.mux.alarm.now());
-                self.mux.alarm.set_alarm(when);
-            }
-        } else {
-            self.mux.prev.set(self.mux.alarm.now());
-            self.mux.alarm.set_alarm(when);
+impl<A: Alarm<'a>> TicksAlarm<'a> for VirtualMuxAlarm<'a, A> {
+    fn set_alarm_ticks(&self, ticks: u64) {
+        self.when.set(ticks);
+        self.armed.set(true);
+        if ticks < self.mux.next.get() {
+            self.mux.schedule();
         }
-
-        self.when.set(when);
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm_ticks(&self) -> u64 {
         self.when.get()
     }
 }
@@ -110,10 +136,17 @@ impl<A: Alarm<'a>> time::AlarmClient for VirtualMuxAlarm<'a, A> {
 
 // MuxAlarm
 
+/// Minimum number of tics ahead of the counter the underlying alarm is set.
+const MIN_LEAD_TICS: u64 = 2;
+
 pub struct MuxAlarm<'a, A: Alarm<'a>> {
     virtual_alarms: List<'a, VirtualMuxAlarm<'a, A>>,
-    enabled: Cell<usize>,
-    prev: Cell<u32>,
+    /// Extended time the underlying alarm is currently set for.
+    next: Cell<u64>,
+    /// Number of times the hardware counter has wrapped.
+    wraps: Cell<u64>,
+    /// Hardware counter value at the last observation.
+    last: Cell<u32>,
     alarm: &'a A,
 }
 
@@ -121,56 +154,86 @@ impl<A: Alarm<'a>> MuxAlarm<'a, A> {
     pub const fn new(alarm: &'a A) -> MuxAlarm<'a, A> {
         MuxAlarm {
             virtual_alarms: List::new(),
-            enabled: Cell::new(0),
-            prev: Cell::new(0),
+            next: Cell::new(0),
+            wraps: Cell::new(0),
+            last: Cell::new(0),
             alarm: alarm,
         }
     }
-}
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
-}
+    /// Number of tics in one period of the hardware counter.
+    fn period(&self) -> u64 {
+        self.alarm.max_tics() as u64 + 1
+    }
 
-impl<A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
-    fn fired(&self) {
-        let now = self.alarm.now();
+    /// Reduce an extended time to a hardware counter value.
+    fn truncate(&self, ticks: u64) -> u32 {
+        (ticks % self.period()) as u32
+    }
 
-        // Capture this before the loop because it can change while checking
-        // each alarm. If a timer fires, it can immediately set a new timer
-        // by calling `VirtualMuxAlarm.set_alarm()` which can change `self.prev`
-        // to the current timer time.
-        let prev = self.prev.get();
+    /// Returns the current time, extending the hardware counter to 64 bits.
+    ///
+    /// This is correct as long as it is called at least once per counter
+    /// period, which `schedule` guarantees.
+    fn now_ticks(&self) -> u64 {
+        let now = self.alarm.now();
+        if now < self.last.get() {
+            self.wraps.set(self.wraps.get() + 1);
+        }
+        self.last.set(now);
+        self.wraps.get() * self.period() + now as u64
+    }
 
-        // Check whether to fire each alarm. At this level, alarms are one-shot,
-        // so a repeating client will set it again in the fired() callback.
-        self.virtual_alarms
-            .iter()
-            .filter(|cur| cur.armed.get() && has_expired(cur.when.get(), now, prev))
-            .for_each(|cur| {
-                cur.armed.set(false);
-                self.enabled.set(self.enabled.get() - 1);
-                cur.fired();
-            });
-
-        // Find the soonest alarm client (if any) and set the "next" underlying
-        // alarm based on it.  This needs to happen after firing all expired
-        // alarms since those may have reset new alarms.
+    /// Set the underlying alarm for the soonest armed virtual alarm, or half
+    /// a counter period from now if that is sooner.
+    ///
+    /// Returns whether the alarm was set for a time that has already passed.
+    fn schedule(&self) -> bool {
+        let now = self.now_ticks();
+        let keepalive = now + self.period() / 2;
         let next = self
             .virtual_alarms
             .iter()
             .filter(|cur| cur.armed.get())
-            .min_by_key(|cur| cur.when.get().wrapping_sub(now));
-
-        self.prev.set(now);
-        // If there is an alarm to fire, set the underlying alarm to it
-        if let Some(valrm) = next {
-            self.alarm.set_alarm(valrm.when.get());
-            if has_expired(valrm.when.get(), self.alarm.now(), prev) {
-                self.fired();
+            .map(|cur| cur.when.get())
+            .fold(keepalive, cmp::min);
+
+        // Hardware alarms only compare against the low bits of the counter,
+        // so an alarm in the past would not fire until the counter wraps.
+        // Set it for shortly after now instead. Some counters (e.g. the nRF
+        // RTC) cannot match the value right after the current one.
+        let target = core::cmp::max(next, now + MIN_LEAD_TICS);
+        self.next.set(target);
+        self.alarm.set_alarm(self.truncate(target));
+        self.now_ticks() >= target
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
+    fn fired(&self) {
+        loop {
+            let now = self.now_ticks();
+
+            // Check whether to fire each alarm. At this level, alarms are
+            // one-shot, so a repeating client will set it again in the fired()
+            // callback.
+            self.virtual_alarms
+                .iter()
+                .filter(|cur| cur.armed.get() && cur.when.get() <= now)
+                .for_each(|cur| {
+                    cur.armed.set(false);
+                    cur.reference.set(cur.when.get());
+                    cur.fired();
+                });
+
+            // Set the underlying alarm for the soonest remaining alarm. This
+            // needs to happen after firing all expired alarms since those may
+            // have set new alarms. If that time passed while we were busy,
+            // handle it right away rather than waiting for the counter to
+            // wrap around.
+            if !self.schedule() {
+                break;
             }
-        } else {
-            self.alarm.disable();
         }
     }
 }
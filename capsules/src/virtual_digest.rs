@@ -120,6 +120,23 @@ impl<A: digest::Digest<'a, T> + digest::HMACSha256, T: DigestType> digest::HMACS
     }
 }
 
+impl<A: digest::Digest<'a, T> + digest::Sha256, T: DigestType> digest::Sha256
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        // Check if any mux is enabled. If it isn't we enable it for us.
+        if self.mux.running.get() == false {
+            self.mux.running.set(true);
+            self.mux.running_id.set(self.id);
+            self.mux.digest.set_mode_sha256()
+        } else if self.mux.running_id.get() == self.id {
+            self.mux.digest.set_mode_sha256()
+        } else {
+            Err(ReturnCode::EBUSY)
+        }
+    }
+}
+
 /// Calling a 'set_mode*()' function from a `VirtualMuxDigest` will mark that
 /// `VirtualMuxDigest` as the one that has been enabled and running. Until that
 /// Mux calls `clear_data()` it will be the only `VirtualMuxDigest` that can
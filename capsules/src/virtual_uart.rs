@@ -219,30 +219,36 @@ impl<'a> MuxUart<'a> {
         if self.inflight.is_none() {
             let mnode = self.devices.iter().find(|node| node.operation.is_some());
             mnode.map(|node| {
-                node.tx_buffer.take().map(|buf| {
-                    node.operation.map(move |op| match op {
-                        Operation::Transmit { len } => {
-                            let (rcode, rbuf) = self.uart.transmit_buffer(buf, *len);
-                            if rcode != ReturnCode::SUCCESS {
-                                node.tx_client.map(|client| {
-                                    node.transmitting.set(false);
-                                    client.transmitted_buffer(rbuf.unwrap(), 0, rcode);
-                                });
-                            }
+                // Clear the operation first, as the client may queue a new
+                // one from the error callback below.
+                let started = node.operation.take().map_or(false, |op| match op {
+                    Operation::Transmit { len } => node.tx_buffer.take().map_or(false, |buf| {
+                        let (rcode, rbuf) = self.uart.transmit_buffer(buf, len);
+                        if rcode != ReturnCode::SUCCESS {
+                            // No callback comes for a transmission that never
+                            // started, so return the buffer now.
+                            node.transmitting.set(false);
+                            rbuf.map(|buf| {
+                                node.tx_client
+                                    .map(move |client| client.transmitted_buffer(buf, 0, rcode))
+                            });
                         }
-                        Operation::TransmitWord { word } => {
-                            let rcode = self.uart.transmit_word(*word);
-                            if rcode != ReturnCode::SUCCESS {
-                                node.tx_client.map(|client| {
-                                    node.transmitting.set(false);
-                                    client.transmitted_word(rcode);
-                                });
-                            }
+                        rcode == ReturnCode::SUCCESS
+                    }),
+                    Operation::TransmitWord { word } => {
+                        let rcode = self.uart.transmit_word(word);
+                        if rcode != ReturnCode::SUCCESS {
+                            node.transmitting.set(false);
+                            node.tx_client.map(|client| client.transmitted_word(rcode));
                         }
-                    });
+                        rcode == ReturnCode::SUCCESS
+                    }
                 });
-                node.operation.clear();
-                self.inflight.set(node);
+                if started {
+                    self.inflight.set(node);
+                } else {
+                    self.do_next_op_async();
+                }
             });
         }
     }
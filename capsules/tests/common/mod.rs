@@ -0,0 +1,501 @@
+//! Host harness running several instances of the networking stack in one
+//! process, on top of `capsules::sim_radio`.
+//!
+//! A `Network` owns a simulated clock and a `SimMedium`. Every node added to
+//! it gets a `SimRadio`, a MAC layer, the 802.15.4 framer, 6LoWPAN and an
+//! IPv6 sender and receiver, wired up as a board does. Nothing runs until the
+//! test advances the clock with `Network::run_for`, which fires the alarms
+//! of the medium and of the stacks as virtual time passes.
+
+#![allow(dead_code)]
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::ieee802154::xmac::XMac;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6SendStruct, IP6Sender, MuxIP6Sender};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::sixlowpan_compression::Context;
+use capsules::net::sixlowpan::sixlowpan_state::{RxState, Sixlowpan, SixlowpanState, TxState};
+use capsules::net::udp::udp::UDPHeader;
+use capsules::sim_radio::{SimMedium, SimRadio};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::RingBuffer;
+use kernel::create_capability;
+use kernel::debug::{self, DebugWriter, DebugWriterWrapper};
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+use std::cell::{Cell, RefCell};
+use std::sync::{Mutex, MutexGuard, Once};
+
+pub type Clock = SimClock<'static>;
+pub type SimAlarm = VirtualMuxAlarm<'static, Clock>;
+pub type Radio = SimRadio<'static, SimAlarm>;
+
+pub const PAN_ID: u16 = 0xabcd;
+pub const UDP_PORT: u16 = 16123;
+
+/// Largest UDP payload a node can send
+pub const MAX_PAYLOAD: usize = 1200;
+
+/// Moves `value` to the heap for the rest of the test run, like
+/// `static_init!` does on a board.
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+pub fn leak_buf(len: usize) -> &'static mut [u8] {
+    Box::leak(vec![0; len].into_boxed_slice())
+}
+
+/// An alarm driven by the test rather than by hardware.
+pub struct SimClock<'a> {
+    now: Cell<u32>,
+    alarm: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> SimClock<'a> {
+    pub fn new() -> SimClock<'a> {
+        SimClock {
+            now: Cell::new(0),
+            alarm: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Advances the clock by `us` microseconds, firing the alarm every time
+    /// the clock reaches it.
+    pub fn run_for(&self, us: u32) {
+        let mut remaining =
+            (us as u64 * <Self as Time>::Frequency::frequency() as u64 / 1_000_000) as u32;
+        for _ in 0..1_000_000 {
+            if self.armed.get() {
+                // Like a hardware compare register, the alarm fires when the
+                // counter reaches it, even if that takes a wrap around
+                let delta = self.alarm.get().wrapping_sub(self.now.get());
+                if delta <= remaining {
+                    self.now.set(self.now.get().wrapping_add(delta));
+                    remaining -= delta;
+                    self.armed.set(false);
+                    self.client.map(|client| client.fired());
+                    continue;
+                }
+            }
+            self.now.set(self.now.get().wrapping_add(remaining));
+            return;
+        }
+        panic!("alarm keeps firing without time passing");
+    }
+}
+
+impl<'a> Time for SimClock<'a> {
+    // The IPv6 sender computes its delay between fragments in 32-bit
+    // arithmetic, which overflows at higher frequencies
+    type Frequency = time::Freq32KHz;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl<'a> Alarm<'a> for SimClock<'a> {
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(tics);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn disable(&self) {
+        self.armed.set(false);
+    }
+}
+
+/// Prints `debug!` output of the stack to the output of the test.
+struct StdoutUart;
+
+impl uart::Transmit<'static> for StdoutUart {
+    fn set_transmit_client(&self, _client: &'static dyn uart::TransmitClient) {}
+
+    // Writes synchronously and hands the buffer straight back, which the
+    // debug writer accepts in place of a callback
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        print!("{}", String::from_utf8_lossy(&tx_buffer[..tx_len]));
+        (ReturnCode::SUCCESS, Some(tx_buffer))
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+// The debug writer is global state of the kernel, so networks run one at a
+// time
+fn lock_network() -> MutexGuard<'static, ()> {
+    static INIT: Once = Once::new();
+    static mut RUNNING: Option<Mutex<()>> = None;
+    unsafe {
+        INIT.call_once(|| {
+            let ring = leak(RingBuffer::new(leak_buf(4096)));
+            let writer = leak(DebugWriter::new(leak(StdoutUart), leak_buf(4096), ring));
+            debug::set_debug_writer_wrapper(leak(DebugWriterWrapper::new(writer)));
+            RUNNING = Some(Mutex::new(()));
+        });
+        match RUNNING.as_ref() {
+            Some(running) => running
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            None => unreachable!(),
+        }
+    }
+}
+
+/// CCM engine for a stack that sends no secured frames.
+pub struct NoCcm;
+
+impl AES128CCM<'static> for NoCcm {
+    fn set_client(&'static self, _client: &'static dyn CCMClient) {}
+
+    fn set_key(&self, _key: &[u8]) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn set_nonce(&self, _nonce: &[u8]) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        _a_off: usize,
+        _m_off: usize,
+        _m_len: usize,
+        _mic_len: usize,
+        _confidential: bool,
+        _encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        (ReturnCode::ENOSUPPORT, Some(buf))
+    }
+}
+
+/// Random numbers for X-MAC backoffs, delivered from an alarm like a
+/// hardware generator would.
+struct SimRng {
+    alarm: &'static SimAlarm,
+    state: Cell<u32>,
+    client: OptionalCell<&'static dyn rng::Client>,
+}
+
+impl Rng<'static> for SimRng {
+    fn get(&self) -> ReturnCode {
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.alarm.disable();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'static self, client: &'static dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl time::AlarmClient for SimRng {
+    fn fired(&self) {
+        let state = &self.state;
+        let mut randomness = core::iter::from_fn(|| {
+            let mut next = state.get();
+            next ^= next << 13;
+            next ^= next >> 17;
+            next ^= next << 5;
+            state.set(next);
+            Some(next)
+        });
+        let more = self.client.map_or(rng::Continue::Done, |client| {
+            client.randomness_available(&mut randomness, ReturnCode::SUCCESS)
+        });
+        if let rng::Continue::More = more {
+            self.get();
+        }
+    }
+}
+
+/// A UDP datagram received by a node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Datagram {
+    pub src: IPAddr,
+    pub payload: Vec<u8>,
+}
+
+pub struct Node {
+    pub radio: &'static Radio,
+    pub addr: u16,
+    pub ip: IPAddr,
+    pub ip_send_mux: &'static MuxIP6Sender<'static, SimAlarm>,
+    ip_send: &'static IP6SendStruct<'static, SimAlarm>,
+    net_cap: &'static NetworkCapability,
+    tx_payload: TakeCell<'static, [u8]>,
+    received: RefCell<Vec<Datagram>>,
+    sent: RefCell<Vec<ReturnCode>>,
+}
+
+impl Node {
+    /// Sends `payload` in a UDP datagram to `dst`, which must be a neighbour
+    /// of this node.
+    pub fn send(&self, dst: &Node, payload: &[u8]) -> ReturnCode {
+        let buf = match self.tx_payload.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        buf[..payload.len()].copy_from_slice(payload);
+        let mut buf = LeasableBuffer::new(buf);
+        buf.slice(..payload.len());
+
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_src_port(UDP_PORT);
+        udp_header.set_dst_port(UDP_PORT);
+        udp_header.set_len((payload.len() + udp_header.get_hdr_size()) as u16);
+        self.ip_send.set_gateway(MacAddress::Short(dst.addr));
+        let result =
+            self.ip_send
+                .send_to(dst.ip, TransportHeader::UDP(udp_header), &buf, self.net_cap);
+        self.tx_payload.replace(buf.take());
+        result
+    }
+
+    /// Datagrams received so far
+    pub fn received(&self) -> Vec<Datagram> {
+        self.received.borrow().clone()
+    }
+
+    /// Results of the datagrams sent so far
+    pub fn sent(&self) -> Vec<ReturnCode> {
+        self.sent.borrow().clone()
+    }
+}
+
+impl IP6RecvClient for Node {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        // Skip the UDP header
+        self.received.borrow_mut().push(Datagram {
+            src: header.get_src_addr(),
+            payload: payload[8..].to_vec(),
+        });
+    }
+}
+
+impl IP6SendClient for Node {
+    fn send_done(&self, result: ReturnCode) {
+        self.sent.borrow_mut().push(result);
+    }
+}
+
+pub struct Network {
+    clock: &'static Clock,
+    mux_alarm: &'static MuxAlarm<'static, Clock>,
+    pub medium: &'static SimMedium<'static, SimAlarm>,
+    _running: MutexGuard<'static, ()>,
+}
+
+impl Network {
+    pub fn new() -> Network {
+        let running = lock_network();
+
+        let clock: &'static Clock = leak(SimClock::new());
+        let mux_alarm: &'static MuxAlarm<'static, Clock> = leak(MuxAlarm::new(clock));
+        clock.set_client(mux_alarm);
+
+        let medium_alarm: &'static SimAlarm = leak(VirtualMuxAlarm::new(mux_alarm));
+        let medium: &'static SimMedium<'static, SimAlarm> = leak(SimMedium::new(medium_alarm));
+        medium_alarm.set_client(medium);
+
+        Network {
+            clock: clock,
+            mux_alarm: mux_alarm,
+            medium: medium,
+            _running: running,
+        }
+    }
+
+    /// Advances virtual time by `ms` milliseconds.
+    pub fn run_for(&self, ms: u32) {
+        self.clock.run_for(ms * 1000);
+    }
+
+    fn alarm(&self) -> &'static SimAlarm {
+        leak(VirtualMuxAlarm::new(self.mux_alarm))
+    }
+
+    fn radio(&self) -> &'static Radio {
+        let radio: &'static Radio = leak(SimRadio::new(self.medium));
+        self.medium
+            .add_radio(radio)
+            .expect("too many nodes on the medium");
+        radio
+    }
+
+    /// Adds a node whose radio is always on.
+    pub fn add_node(&self, addr: u16) -> &'static Node {
+        let radio = self.radio();
+        let mac: &'static AwakeMac<'static, Radio> = leak(AwakeMac::new(radio));
+        radio.set_transmit_client(mac);
+        radio.set_receive_client(mac, leak_buf(radio::MAX_BUF_SIZE));
+        let node = self.add_stack(radio, mac, addr);
+        radio.start();
+        node
+    }
+
+    /// Adds a node whose radio duty cycles with X-MAC.
+    pub fn add_xmac_node(&self, addr: u16) -> &'static Node {
+        let radio = self.radio();
+        let rng_alarm = self.alarm();
+        let rng: &'static SimRng = leak(SimRng {
+            alarm: rng_alarm,
+            state: Cell::new(0x2545_f491 ^ addr as u32),
+            client: OptionalCell::empty(),
+        });
+        rng_alarm.set_client(rng);
+
+        let mac_alarm = self.alarm();
+        let mac: &'static XMac<'static, Radio, SimAlarm> = leak(XMac::new(radio, mac_alarm, rng));
+        rng.set_client(mac);
+        mac_alarm.set_client(mac);
+        radio.set_transmit_client(mac);
+        radio.set_receive_client(mac, leak_buf(radio::MAX_BUF_SIZE));
+        radio.set_power_client(mac);
+        mac.initialize(leak_buf(radio::MAX_BUF_SIZE));
+        let node = self.add_stack(radio, mac, addr);
+        radio.start();
+        node
+    }
+
+    // Wires up the framer, 6LoWPAN and IPv6 on top of `mac`, as the imix
+    // radio and UDP components do
+    fn add_stack<M: Mac>(
+        &self,
+        radio: &'static Radio,
+        mac: &'static M,
+        addr: u16,
+    ) -> &'static Node {
+        let framer: &'static Framer<'static, M, NoCcm> = leak(Framer::new(mac, leak(NoCcm)));
+        mac.set_transmit_client(framer);
+        mac.set_receive_client(framer);
+        mac.set_config_client(framer);
+
+        let mux_mac: &'static MuxMac<'static> = leak(MuxMac::new(framer));
+        framer.set_transmit_client(mux_mac);
+        framer.set_receive_client(mux_mac);
+
+        let mac_user: &'static MacUser<'static> = leak(MacUser::new(mux_mac));
+        mux_mac.add_user(mac_user);
+        mac_user.set_pan(PAN_ID);
+        mac_user.set_address(addr);
+        mac_user.config_commit();
+
+        let sixlowpan: &'static Sixlowpan<'static, Clock, Context> = leak(Sixlowpan::new(
+            Context {
+                prefix: [0; 16],
+                prefix_len: 0,
+                id: 0,
+                compress: false,
+            },
+            self.clock,
+        ));
+        let sixlowpan_state = sixlowpan as &dyn SixlowpanState;
+        sixlowpan_state.add_rx_state(leak(RxState::new(leak_buf(1280))));
+        mac_user.set_receive_client(sixlowpan);
+
+        let ip_alarm = self.alarm();
+        let ip_send_mux: &'static MuxIP6Sender<'static, SimAlarm> = leak(MuxIP6Sender::new(
+            ip_alarm,
+            leak_buf(radio::MAX_BUF_SIZE),
+            mac_user,
+        ));
+        ip_alarm.set_client(ip_send_mux);
+        mac_user.set_transmit_client(ip_send_mux);
+
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = leak(IpVisibilityCapability::new(&create_cap));
+        let net_cap = leak(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let packet = leak(IP6Packet::new(IPPayload::new(
+            TransportHeader::UDP(UDPHeader::new()),
+            leak_buf(MAX_PAYLOAD),
+        )));
+        let ip_send: &'static IP6SendStruct<'static, SimAlarm> = leak(IP6SendStruct::new(
+            packet,
+            TxState::new(sixlowpan_state),
+            ip_send_mux,
+            MacAddress::Short(0xffff),
+            MacAddress::Short(addr),
+            ip_vis,
+        ));
+        ip_send_mux.add_sender(ip_send);
+        let ip = IPAddr::generate_from_mac(MacAddress::Short(addr));
+        ip_send.set_addr(ip);
+
+        let ip_receive: &'static IP6RecvStruct<'static> = leak(IP6RecvStruct::new());
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let node: &'static Node = leak(Node {
+            radio: radio,
+            addr: addr,
+            ip: ip,
+            ip_send_mux: ip_send_mux,
+            ip_send: ip_send,
+            net_cap: net_cap,
+            tx_payload: TakeCell::new(leak_buf(MAX_PAYLOAD)),
+            received: RefCell::new(Vec::new()),
+            sent: RefCell::new(Vec::new()),
+        });
+        ip_send.set_client(node);
+        ip_receive.set_client(node);
+        node
+    }
+}
@@ -0,0 +1,784 @@
+//! The FAT filesystem over a mock block device, on volumes formatted here.
+
+mod common;
+
+use capsules::fat::{DirEntry, Fat, FatClient, OpenFlags, CACHE_SLOTS};
+use capsules::test::mock::block::{Call, MockBlockStorage};
+use common::{leak, leak_buf};
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::hil::block_storage::{BlockStorage, BLOCK_SIZE};
+use kernel::ReturnCode;
+use std::cell::{Cell, RefCell};
+use std::sync::{Mutex, MutexGuard, Once};
+
+const CREATE: OpenFlags = OpenFlags {
+    create: true,
+    truncate: false,
+    append: false,
+};
+const EXISTING: OpenFlags = OpenFlags {
+    create: false,
+    truncate: false,
+    append: false,
+};
+const APPEND: OpenFlags = OpenFlags {
+    create: true,
+    truncate: false,
+    append: true,
+};
+const TRUNCATE: OpenFlags = OpenFlags {
+    create: false,
+    truncate: true,
+    append: false,
+};
+
+/// Layout of a volume to format.
+#[derive(Clone, Copy)]
+struct Format {
+    sectors: u32,
+    sectors_per_cluster: u32,
+    root_entries: u32,
+    fat32: bool,
+    /// Sector of the volume in a partition table, if it is partitioned.
+    partition: Option<u32>,
+}
+
+const FAT12: Format = Format {
+    sectors: 4096,
+    sectors_per_cluster: 4,
+    root_entries: 16,
+    fat32: false,
+    partition: None,
+};
+const FAT16: Format = Format {
+    sectors: 8192,
+    sectors_per_cluster: 1,
+    root_entries: 512,
+    fat32: false,
+    partition: None,
+};
+const FAT32: Format = Format {
+    sectors: 70000,
+    sectors_per_cluster: 1,
+    root_entries: 0,
+    fat32: true,
+    partition: None,
+};
+
+/// The sectors of each FAT, for the clusters of a volume of `sectors`.
+fn fat_sectors(format: &Format) -> u32 {
+    let clusters = format.sectors / format.sectors_per_cluster + 2;
+    let bits = if format.fat32 {
+        32
+    } else if clusters < 4085 {
+        12
+    } else {
+        16
+    };
+    (clusters * bits / 8 + BLOCK_SIZE as u32 - 1) / BLOCK_SIZE as u32
+}
+
+/// A disk holding a new volume with two FATs, as a PC formats it.
+fn format(format: Format) -> &'static mut [u8] {
+    let base = format.partition.unwrap_or(0);
+    let disk = leak_buf((base + format.sectors) as usize * BLOCK_SIZE);
+    if let Some(start) = format.partition {
+        let entry = &mut disk[446..462];
+        entry[4] = if format.fat32 { 0x0c } else { 0x06 };
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&format.sectors.to_le_bytes());
+        disk[510] = 0x55;
+        disk[511] = 0xaa;
+    }
+
+    let reserved: u16 = if format.fat32 { 32 } else { 1 };
+    let fat_sectors = fat_sectors(&format);
+    let boot = &mut disk[base as usize * BLOCK_SIZE..][..BLOCK_SIZE];
+    boot[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+    boot[13] = format.sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&reserved.to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&(format.root_entries as u16).to_le_bytes());
+    if format.sectors < 0x10000 {
+        boot[19..21].copy_from_slice(&(format.sectors as u16).to_le_bytes());
+    } else {
+        boot[32..36].copy_from_slice(&format.sectors.to_le_bytes());
+    }
+    boot[21] = 0xf8;
+    if format.fat32 {
+        boot[36..40].copy_from_slice(&fat_sectors.to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    } else {
+        boot[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    }
+    boot[510] = 0x55;
+    boot[511] = 0xaa;
+
+    let head: &[u8] = if format.fat32 {
+        // The root directory is cluster 2
+        &[
+            0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+        ]
+    } else if format.sectors / format.sectors_per_cluster < 4085 {
+        &[0xf8, 0xff, 0xff]
+    } else {
+        &[0xf8, 0xff, 0xff, 0xff]
+    };
+    for copy in 0..2 {
+        let fat = (base + reserved as u32 + copy * fat_sectors) as usize * BLOCK_SIZE;
+        disk[fat..fat + head.len()].copy_from_slice(head);
+    }
+    disk
+}
+
+/// Sectors and FATs of a volume made by `format`.
+struct Layout {
+    fat: usize,
+    fat_sectors: usize,
+    root: usize,
+    data: usize,
+    sectors_per_cluster: usize,
+}
+
+impl Layout {
+    fn of(format: &Format) -> Layout {
+        let reserved = if format.fat32 { 32 } else { 1 };
+        let fat_sectors = fat_sectors(format) as usize;
+        let fat = format.partition.unwrap_or(0) as usize + reserved;
+        let root = fat + 2 * fat_sectors;
+        Layout {
+            fat: fat,
+            fat_sectors: fat_sectors,
+            root: root,
+            data: root + (format.root_entries as usize * 32 + BLOCK_SIZE - 1) / BLOCK_SIZE,
+            sectors_per_cluster: format.sectors_per_cluster as usize,
+        }
+    }
+
+    /// Offset of a cluster on the disk.
+    fn cluster(&self, cluster: usize) -> usize {
+        (self.data + (cluster - 2) * self.sectors_per_cluster) * BLOCK_SIZE
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Done {
+    Mount(ReturnCode),
+    Open(usize, ReturnCode),
+    Read(usize, ReturnCode),
+    Write(usize, ReturnCode),
+    ReadDir(Option<DirEntry>, ReturnCode),
+    Command(ReturnCode),
+}
+
+struct Client {
+    done: Cell<Option<Done>>,
+    buffer: RefCell<Option<&'static mut [u8]>>,
+}
+
+impl Client {
+    fn finish(&self, done: Done) {
+        let previous = self.done.replace(Some(done));
+        assert_eq!(previous, None, "two callbacks for one operation");
+    }
+}
+
+impl FatClient for Client {
+    fn mount_done(&self, rcode: ReturnCode) {
+        self.finish(Done::Mount(rcode));
+    }
+
+    fn open_done(&self, handle: usize, rcode: ReturnCode) {
+        self.finish(Done::Open(handle, rcode));
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode) {
+        *self.buffer.borrow_mut() = Some(buffer);
+        self.finish(Done::Read(length, rcode));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, rcode: ReturnCode) {
+        *self.buffer.borrow_mut() = Some(buffer);
+        self.finish(Done::Write(length, rcode));
+    }
+
+    fn read_dir_done(&self, entry: Option<DirEntry>, rcode: ReturnCode) {
+        self.finish(Done::ReadDir(entry, rcode));
+    }
+
+    fn command_done(&self, rcode: ReturnCode) {
+        self.finish(Done::Command(rcode));
+    }
+}
+
+// The dynamic deferred call instance is global, so tests run one at a time
+fn lock_deferred_calls() -> (MutexGuard<'static, ()>, &'static DynamicDeferredCall) {
+    static INIT: Once = Once::new();
+    static mut RUNNING: Option<(Mutex<()>, &'static DynamicDeferredCall)> = None;
+    unsafe {
+        INIT.call_once(|| {
+            let states: Vec<DynamicDeferredCallClientState> =
+                (0..256).map(|_| Default::default()).collect();
+            let ddc = leak(DynamicDeferredCall::new(Box::leak(
+                states.into_boxed_slice(),
+            )));
+            DynamicDeferredCall::set_global_instance(ddc);
+            RUNNING = Some((Mutex::new(()), ddc));
+        });
+        match RUNNING.as_ref() {
+            Some((running, ddc)) => (
+                running
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                *ddc,
+            ),
+            None => unreachable!(),
+        }
+    }
+}
+
+struct Harness {
+    storage: &'static MockBlockStorage<'static>,
+    fat: &'static Fat<'static>,
+    client: &'static Client,
+    _running: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    /// A filesystem on `disk`, not mounted yet.
+    fn new(disk: &'static mut [u8]) -> Harness {
+        Harness::on(leak(MockBlockStorage::new(disk)))
+    }
+
+    /// A filesystem on `storage`, as after a reboot.
+    fn on(storage: &'static MockBlockStorage<'static>) -> Harness {
+        let (running, ddc) = lock_deferred_calls();
+        let cache: &'static mut [u8; CACHE_SLOTS * BLOCK_SIZE] =
+            leak([0; CACHE_SLOTS * BLOCK_SIZE]);
+        let fat = leak(Fat::new(storage, cache, ddc));
+        fat.initialize_callback_handle(ddc.register(fat).expect("out of deferred calls"));
+        storage.set_client(fat);
+        let client = leak(Client {
+            done: Cell::new(None),
+            buffer: RefCell::new(None),
+        });
+        fat.set_client(client);
+        Harness {
+            storage: storage,
+            fat: fat,
+            client: client,
+            _running: running,
+        }
+    }
+
+    fn mounted(disk: &'static mut [u8]) -> Harness {
+        let harness = Harness::new(disk);
+        assert_eq!(harness.mount(), ReturnCode::SUCCESS);
+        harness
+    }
+
+    /// Runs deferred calls and completes block operations until nothing is
+    /// left to do, and returns the callback of the operation.
+    fn settle(&self) -> Done {
+        loop {
+            if unsafe { DynamicDeferredCall::global_instance_calls_pending() } == Some(true) {
+                unsafe { DynamicDeferredCall::call_global_instance() };
+            } else if self.storage.pending().is_some() {
+                self.storage.complete(ReturnCode::SUCCESS);
+            } else {
+                break;
+            }
+        }
+        self.client.done.take().expect("no callback")
+    }
+
+    fn started(&self, rcode: ReturnCode) -> Option<Done> {
+        if rcode == ReturnCode::SUCCESS {
+            Some(self.settle())
+        } else {
+            None
+        }
+    }
+
+    fn mount(&self) -> ReturnCode {
+        match self.started(self.fat.mount()) {
+            Some(Done::Mount(rcode)) => rcode,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn open(&self, path: &str, flags: OpenFlags) -> Result<usize, ReturnCode> {
+        let rcode = self.fat.open(path.as_bytes(), flags);
+        match self.started(rcode) {
+            Some(Done::Open(handle, ReturnCode::SUCCESS)) => Ok(handle),
+            Some(Done::Open(_, rcode)) => Err(rcode),
+            None => Err(rcode),
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn buffer(&self) -> &'static mut [u8] {
+        self.client
+            .buffer
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| leak_buf(1024))
+    }
+
+    fn write(&self, handle: usize, data: &[u8]) -> (usize, ReturnCode) {
+        let buffer = self.buffer();
+        buffer[..data.len()].copy_from_slice(data);
+        match self.fat.write(handle, buffer, data.len()) {
+            Ok(()) => match self.settle() {
+                Done::Write(length, rcode) => (length, rcode),
+                done => panic!("unexpected {:?}", done),
+            },
+            Err((rcode, buffer)) => {
+                *self.client.buffer.borrow_mut() = buffer;
+                (0, rcode)
+            }
+        }
+    }
+
+    fn write_all(&self, handle: usize, data: &[u8]) {
+        for chunk in data.chunks(1000) {
+            assert_eq!(
+                self.write(handle, chunk),
+                (chunk.len(), ReturnCode::SUCCESS)
+            );
+        }
+    }
+
+    fn read(&self, handle: usize, length: usize) -> Result<Vec<u8>, ReturnCode> {
+        match self.fat.read(handle, self.buffer(), length) {
+            Ok(()) => match self.settle() {
+                Done::Read(length, ReturnCode::SUCCESS) => {
+                    let buffer = self.client.buffer.borrow();
+                    Ok(buffer.as_ref().unwrap()[..length].to_vec())
+                }
+                Done::Read(_, rcode) => Err(rcode),
+                done => panic!("unexpected {:?}", done),
+            },
+            Err((rcode, buffer)) => {
+                *self.client.buffer.borrow_mut() = buffer;
+                Err(rcode)
+            }
+        }
+    }
+
+    /// Reads a file from its position to its end, in reads of `chunk`
+    /// bytes.
+    fn read_to_end(&self, handle: usize, chunk: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let read = self.read(handle, chunk).expect("read failed");
+            if read.is_empty() {
+                return data;
+            }
+            data.extend(read);
+        }
+    }
+
+    /// The contents of the file at `path`.
+    fn contents(&self, path: &str) -> Vec<u8> {
+        let handle = self.open(path, EXISTING).expect("open failed");
+        let data = self.read_to_end(handle, 1000);
+        assert_eq!(self.fat.close(handle), ReturnCode::SUCCESS);
+        data
+    }
+
+    fn create(&self, path: &str, data: &[u8]) {
+        let handle = self.open(path, CREATE).expect("create failed");
+        self.write_all(handle, data);
+        assert_eq!(self.fat.close(handle), ReturnCode::SUCCESS);
+    }
+
+    fn read_dir(&self, path: &str, index: usize) -> Result<Option<DirEntry>, ReturnCode> {
+        let rcode = self.fat.read_dir(path.as_bytes(), index);
+        match self.started(rcode) {
+            Some(Done::ReadDir(entry, ReturnCode::SUCCESS)) => Ok(entry),
+            Some(Done::ReadDir(_, rcode)) => Err(rcode),
+            None => Err(rcode),
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    /// Names of the entries of the directory at `path`, with a trailing `/`
+    /// for directories.
+    fn list(&self, path: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        while let Some(entry) = self.read_dir(path, names.len()).expect("listing failed") {
+            let mut name = String::from_utf8(entry.name().to_vec()).unwrap();
+            if entry.directory {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names
+    }
+
+    fn command(&self, rcode: ReturnCode) -> ReturnCode {
+        match self.started(rcode) {
+            Some(Done::Command(rcode)) => rcode,
+            None => rcode,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn mkdir(&self, path: &str) -> ReturnCode {
+        self.command(self.fat.mkdir(path.as_bytes()))
+    }
+
+    fn remove(&self, path: &str) -> ReturnCode {
+        self.command(self.fat.remove(path.as_bytes()))
+    }
+
+    fn disk<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+        self.storage.with_blocks(|blocks| f(blocks))
+    }
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+/// Mounts a new filesystem over the disk of `harness`, as after a reboot.
+fn remount(harness: Harness) -> Harness {
+    let storage = harness.storage;
+    drop(harness);
+    let harness = Harness::on(storage);
+    assert_eq!(harness.mount(), ReturnCode::SUCCESS);
+    harness
+}
+
+#[test]
+fn files_survive_remount_on_each_fat_type() {
+    for &format in [FAT12, FAT16, FAT32].iter() {
+        let fs = Harness::mounted(self::format(format));
+        let data = pattern(5000, 7);
+        fs.create("log.txt", &data);
+        assert_eq!(fs.mkdir("data/2020"), ReturnCode::SUCCESS);
+        fs.create("data/2020/jan.csv", b"1,2,3\n");
+
+        let fs = remount(fs);
+        assert_eq!(fs.contents("LOG.TXT"), data);
+        assert_eq!(fs.contents("/data/2020/jan.csv"), b"1,2,3\n");
+        assert_eq!(fs.list(""), vec!["LOG.TXT", "DATA/"]);
+
+        // Both FATs were kept the same
+        let layout = Layout::of(&format);
+        fs.disk(|disk| {
+            let fat = |copy: usize| {
+                let start = (layout.fat + copy * layout.fat_sectors) * BLOCK_SIZE;
+                disk[start..start + layout.fat_sectors * BLOCK_SIZE].to_vec()
+            };
+            assert!(fat(0) == fat(1));
+        });
+    }
+}
+
+#[test]
+fn files_are_laid_out_as_pcs_expect() {
+    let fs = Harness::mounted(format(FAT16));
+    let data = pattern(700, 1);
+    fs.create("Log.Txt", &data);
+    let layout = Layout::of(&FAT16);
+
+    fs.disk(|disk| {
+        let entry = &disk[layout.root * BLOCK_SIZE..][..32];
+        assert_eq!(&entry[..11], b"LOG     TXT");
+        assert_eq!(entry[11], 0x20);
+        assert_eq!(&entry[28..32], &700u32.to_le_bytes());
+
+        // A chain of two clusters, with the data in them
+        let first = u16::from_le_bytes([entry[26], entry[27]]) as usize;
+        let fat = &disk[layout.fat * BLOCK_SIZE..];
+        let second = u16::from_le_bytes([fat[first * 2], fat[first * 2 + 1]]) as usize;
+        let end = u16::from_le_bytes([fat[second * 2], fat[second * 2 + 1]]);
+        assert!(end >= 0xfff8);
+        assert_eq!(&disk[layout.cluster(first)..][..512], &data[..512]);
+        assert_eq!(&disk[layout.cluster(second)..][..188], &data[512..]);
+    });
+}
+
+#[test]
+fn mounts_the_first_fat_partition() {
+    let fs = Harness::mounted(format(Format {
+        partition: Some(63),
+        ..FAT16
+    }));
+    fs.create("a.bin", b"partitioned");
+    let fs = remount(fs);
+    assert_eq!(fs.contents("a.bin"), b"partitioned");
+    let layout = Layout::of(&Format {
+        partition: Some(63),
+        ..FAT16
+    });
+    fs.disk(|disk| assert_eq!(&disk[layout.root * BLOCK_SIZE..][..5], b"A    "));
+}
+
+#[test]
+fn unformatted_volumes_are_not_mounted() {
+    let fs = Harness::new(leak_buf(64 * BLOCK_SIZE));
+    assert_eq!(fs.mount(), ReturnCode::ENOSUPPORT);
+    assert!(!fs.fat.is_mounted());
+    assert_eq!(fs.fat.open(b"a", CREATE), ReturnCode::EOFF);
+}
+
+#[test]
+fn seek_overwrites_and_append_writes_at_the_end() {
+    let fs = Harness::mounted(format(FAT12));
+    let file = fs.open("hello.txt", CREATE).unwrap();
+    fs.write_all(file, b"hello");
+    assert_eq!(fs.fat.seek(file, 0), ReturnCode::SUCCESS);
+    fs.write_all(file, b"J");
+    assert_eq!(fs.fat.position(file), Some(1));
+    assert_eq!(fs.fat.seek(file, 6), ReturnCode::EINVAL);
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    assert_eq!(fs.contents("hello.txt"), b"Jello");
+
+    let file = fs.open("hello.txt", APPEND).unwrap();
+    assert_eq!(fs.fat.seek(file, 0), ReturnCode::SUCCESS);
+    fs.write_all(file, b"!");
+    assert_eq!(fs.fat.size(file), Some(6));
+    assert_eq!(fs.fat.seek(file, 1), ReturnCode::SUCCESS);
+    assert_eq!(fs.read(file, 3).unwrap(), b"ell");
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    assert_eq!(fs.contents("hello.txt"), b"Jello!");
+}
+
+#[test]
+fn reads_and_writes_across_sectors_and_clusters() {
+    // Clusters of 2048 bytes
+    let fs = Harness::mounted(format(FAT12));
+    let data = pattern(9000, 3);
+    let file = fs.open("big.dat", CREATE).unwrap();
+    for chunk in data.chunks(333) {
+        assert_eq!(fs.write(file, chunk), (chunk.len(), ReturnCode::SUCCESS));
+    }
+    assert_eq!(fs.fat.seek(file, 0), ReturnCode::SUCCESS);
+    assert_eq!(fs.read_to_end(file, 777), data);
+
+    // Overwrite the middle, across a cluster boundary
+    assert_eq!(fs.fat.seek(file, 2000), ReturnCode::SUCCESS);
+    fs.write_all(file, &[0xaa; 100]);
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    let mut expected = data.clone();
+    expected[2000..2100].copy_from_slice(&[0xaa; 100]);
+    assert_eq!(remount(fs).contents("big.dat"), expected);
+}
+
+#[test]
+fn fat12_entries_spanning_sectors() {
+    // Entry 341 starts in the first sector of the FAT and ends in the second
+    let format = Format {
+        sectors: 2048,
+        sectors_per_cluster: 1,
+        ..FAT12
+    };
+    let fs = Harness::mounted(self::format(format));
+    let data = pattern(400 * BLOCK_SIZE, 9);
+    fs.create("short", b"x");
+    fs.create("long.log", &data);
+    let fs = remount(fs);
+    assert_eq!(fs.contents("long.log"), data);
+
+    let layout = Layout::of(&format);
+    fs.disk(|disk| {
+        let fat = &disk[layout.fat * BLOCK_SIZE..][..layout.fat_sectors * BLOCK_SIZE];
+        let entry = |n: usize| {
+            let value = u16::from_le_bytes([fat[n + n / 2], fat[n + n / 2 + 1]]);
+            if n & 1 == 1 {
+                value >> 4
+            } else {
+                value & 0xfff
+            }
+        };
+        assert_eq!(entry(2), 0xfff);
+        assert_eq!(entry(3), 4);
+        assert_eq!(entry(340), 341);
+        assert_eq!(entry(341), 342);
+        assert_eq!(entry(402), 0xfff);
+        assert_eq!(entry(403), 0);
+    });
+
+    assert_eq!(fs.remove("long.log"), ReturnCode::SUCCESS);
+    let fs = remount(fs);
+    fs.disk(|disk| {
+        let fat = &disk[layout.fat * BLOCK_SIZE..][..layout.fat_sectors * BLOCK_SIZE];
+        assert_eq!(&fat[..6], &[0xf8, 0xff, 0xff, 0xff, 0x0f, 0x00]);
+        assert!(fat[6..].iter().all(|byte| *byte == 0));
+    });
+    assert_eq!(fs.contents("short"), b"x");
+}
+
+#[test]
+fn truncating_frees_the_file() {
+    let fs = Harness::mounted(format(FAT16));
+    fs.create("a", &pattern(3000, 0));
+    let file = fs.open("a", TRUNCATE).unwrap();
+    assert_eq!(fs.fat.size(file), Some(0));
+    fs.write_all(file, b"short");
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    assert_eq!(fs.contents("a"), b"short");
+
+    // Only one cluster is in use
+    let layout = Layout::of(&FAT16);
+    fs.disk(|disk| {
+        let fat = &disk[layout.fat * BLOCK_SIZE..][..BLOCK_SIZE];
+        let used = fat[4..].chunks(2).filter(|entry| *entry != [0, 0]).count();
+        assert_eq!(used, 1);
+    });
+}
+
+#[test]
+fn directories_are_created_listed_and_extended() {
+    let fs = Harness::mounted(format(FAT16));
+    assert_eq!(fs.mkdir("a/b/c"), ReturnCode::SUCCESS);
+    assert_eq!(fs.mkdir("a/b"), ReturnCode::EALREADY);
+    assert_eq!(fs.mkdir(""), ReturnCode::EALREADY);
+    assert_eq!(fs.list("a"), vec!["B/"]);
+    assert_eq!(fs.list("a/b/c"), Vec::<String>::new());
+    assert_eq!(fs.read_dir("nope", 0), Err(ReturnCode::ENODEVICE));
+
+    // A cluster holds 16 entries, two of them for "." and ".."
+    let mut names = Vec::new();
+    for i in 0..40 {
+        let name = format!("F{}.TXT", i);
+        fs.create(&format!("a/b/{}", name), name.as_bytes());
+        names.push(name);
+    }
+    let mut expected = vec![String::from("C/")];
+    expected.extend(names.iter().cloned());
+    let fs = remount(fs);
+    assert_eq!(fs.list("a/b"), expected);
+    assert_eq!(fs.contents("a/b/F39.TXT"), b"F39.TXT");
+
+    let entry = fs.read_dir("a/b", 1).unwrap().unwrap();
+    assert_eq!(entry.name(), b"F0.TXT");
+    assert_eq!(entry.size, 6);
+    assert!(!entry.directory);
+}
+
+#[test]
+fn fat32_root_directory_grows() {
+    let fs = Harness::mounted(format(FAT32));
+    for i in 0..20 {
+        fs.create(&format!("{}", i), b"x");
+    }
+    let fs = remount(fs);
+    assert_eq!(fs.list("/").len(), 20);
+    assert_eq!(fs.contents("19"), b"x");
+}
+
+#[test]
+fn fixed_root_directory_fills_up() {
+    let fs = Harness::mounted(format(FAT12));
+    for i in 0..16 {
+        fs.create(&format!("{}", i), b"");
+    }
+    assert_eq!(fs.open("16", CREATE), Err(ReturnCode::ENOMEM));
+    assert_eq!(fs.mkdir("dir"), ReturnCode::ENOMEM);
+
+    // A removed entry is reused
+    assert_eq!(fs.remove("3"), ReturnCode::SUCCESS);
+    fs.create("16", b"");
+    assert_eq!(fs.list("").len(), 16);
+}
+
+#[test]
+fn removing_files_frees_their_space() {
+    let fs = Harness::mounted(format(Format {
+        sectors: 64,
+        sectors_per_cluster: 1,
+        ..FAT12
+    }));
+    let file = fs.open("fill", CREATE).unwrap();
+    let mut written = 0;
+    loop {
+        match fs.write(file, &[0x55; 1000]) {
+            (1000, ReturnCode::SUCCESS) => written += 1000,
+            (length, rcode) => {
+                assert_eq!(rcode, ReturnCode::ENOMEM);
+                written += length;
+                break;
+            }
+        }
+    }
+    assert_eq!(written % BLOCK_SIZE, 0);
+    assert_eq!(fs.fat.size(file), Some(written));
+    assert_eq!(fs.remove("fill"), ReturnCode::EBUSY);
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    assert_eq!(fs.contents("fill").len(), written);
+
+    assert_eq!(fs.remove("fill"), ReturnCode::SUCCESS);
+    assert_eq!(fs.open("fill", EXISTING), Err(ReturnCode::ENODEVICE));
+    fs.create("again", &vec![1; written]);
+    assert_eq!(fs.contents("again").len(), written);
+}
+
+#[test]
+fn paths_are_checked() {
+    let fs = Harness::mounted(format(FAT16));
+    fs.create("file", b"");
+    assert_eq!(fs.mkdir("dir"), ReturnCode::SUCCESS);
+    assert_eq!(fs.open("missing", EXISTING), Err(ReturnCode::ENODEVICE));
+    assert_eq!(fs.open("dir/a/b", CREATE), Err(ReturnCode::ENODEVICE));
+    assert_eq!(fs.open("..", CREATE), Err(ReturnCode::EINVAL));
+    assert_eq!(fs.open("dir/../file", EXISTING), Err(ReturnCode::EINVAL));
+    assert_eq!(fs.open("longfilename.txt", CREATE), Err(ReturnCode::EINVAL));
+    assert_eq!(fs.open("dir", EXISTING), Err(ReturnCode::EINVAL));
+    assert_eq!(fs.open("file/a", CREATE), Err(ReturnCode::ENODEVICE));
+    assert_eq!(fs.remove("dir"), ReturnCode::EINVAL);
+    assert_eq!(fs.mkdir("file"), ReturnCode::EINVAL);
+    assert_eq!(fs.open(&"a/".repeat(40), CREATE), Err(ReturnCode::ESIZE));
+
+    let file = fs.open("FILE", EXISTING).unwrap();
+    assert_eq!(fs.open("file", EXISTING), Err(ReturnCode::EBUSY));
+    assert_eq!(fs.fat.close(file), ReturnCode::SUCCESS);
+    assert_eq!(fs.fat.close(file), ReturnCode::EINVAL);
+}
+
+#[test]
+fn block_errors_fail_the_operation() {
+    let fs = Harness::mounted(format(FAT16));
+    fs.create("a", b"data");
+
+    // Nothing is cached after a reboot, so every operation reads sectors
+    let fs = remount(fs);
+    fs.storage.fail_next(ReturnCode::FAIL);
+    assert_eq!(fs.open("a", EXISTING), Err(ReturnCode::FAIL));
+    let file = fs.open("a", APPEND).unwrap();
+    fs.storage.fail_next(ReturnCode::EBUSY);
+    assert_eq!(fs.write(file, b"more"), (0, ReturnCode::EBUSY));
+    assert_eq!(fs.fat.size(file), Some(4));
+
+    let fs = remount(fs);
+    let file = fs.open("a", EXISTING).unwrap();
+    assert!(fs.fat.read(file, fs.buffer(), 4).is_ok());
+    unsafe { DynamicDeferredCall::call_global_instance() };
+    fs.storage.complete(ReturnCode::FAIL);
+    assert_eq!(fs.client.done.take(), Some(Done::Read(0, ReturnCode::FAIL)));
+    assert_eq!(fs.read(file, 4).unwrap(), b"data");
+}
+
+#[test]
+fn medium_changes_close_files_and_remount() {
+    let fs = Harness::mounted(format(FAT16));
+    let file = fs.open("a", CREATE).unwrap();
+    fs.write_all(file, b"before");
+
+    fs.storage.set_present(false);
+    assert!(!fs.fat.is_mounted());
+    assert_eq!(fs.fat.size(file), None);
+    assert_eq!(fs.open("a", EXISTING), Err(ReturnCode::EOFF));
+
+    fs.storage.set_present(true);
+    assert_eq!(fs.settle(), Done::Mount(ReturnCode::SUCCESS));
+    assert_eq!(fs.contents("a"), b"before");
+    assert!(fs.storage.calls.contains(Call::Read(0)));
+}
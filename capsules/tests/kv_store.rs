@@ -0,0 +1,387 @@
+//! The key-value store over a mock flash, including interrupted writes.
+
+mod common;
+
+use capsules::kv_store::{IndexEntry, KVStore, KVStoreClient};
+use capsules::test::mock::flash::{MockFlash, MockPage, PAGES, PAGE_SIZE};
+use common::{leak, leak_buf};
+use kernel::hil::flash::{Error, HasClient};
+use kernel::ReturnCode;
+use std::cell::{Cell, RefCell};
+
+type Store = KVStore<'static, MockFlash<'static>>;
+
+const APP: u32 = 0x1234_5678;
+const OTHER_APP: u32 = 0x0bad_cafe;
+const QUOTA: usize = 1024;
+
+struct Client {
+    result: Cell<Option<ReturnCode>>,
+    length: Cell<usize>,
+    buffer: RefCell<Option<&'static mut [u8]>>,
+}
+
+impl Client {
+    fn buffer(&self) -> &'static mut [u8] {
+        self.buffer
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| leak_buf(256))
+    }
+}
+
+impl KVStoreClient for Client {
+    fn mount_done(&self, error: ReturnCode) {
+        self.result.set(Some(error));
+    }
+
+    fn get_done(&self, buffer: &'static mut [u8], length: usize, error: ReturnCode) {
+        self.result.set(Some(error));
+        self.length.set(length);
+        *self.buffer.borrow_mut() = Some(buffer);
+    }
+
+    fn set_done(&self, buffer: &'static mut [u8], error: ReturnCode) {
+        self.result.set(Some(error));
+        *self.buffer.borrow_mut() = Some(buffer);
+    }
+
+    fn delete_done(&self, error: ReturnCode) {
+        self.result.set(Some(error));
+    }
+}
+
+struct Harness {
+    flash: &'static MockFlash<'static>,
+    store: &'static Store,
+    client: &'static Client,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        Harness::mount(leak(MockFlash::new()))
+    }
+
+    /// Mounts a new store over `flash`, as after a reboot.
+    fn mount(flash: &'static MockFlash<'static>) -> Harness {
+        let harness = Harness::start_mount(flash);
+        harness.flash.complete_all();
+        assert_eq!(harness.client.result.take(), Some(ReturnCode::SUCCESS));
+        harness
+    }
+
+    fn start_mount(flash: &'static MockFlash<'static>) -> Harness {
+        let store = leak(KVStore::new(
+            flash,
+            0,
+            leak([0; PAGES]),
+            leak([IndexEntry::EMPTY; 32]),
+            QUOTA,
+            leak(MockPage::default()),
+            leak(MockPage::default()),
+        ));
+        flash.set_client(store);
+        let client = leak(Client {
+            result: Cell::new(None),
+            length: Cell::new(0),
+            buffer: RefCell::new(None),
+        });
+        store.set_client(client);
+        assert_eq!(store.mount(), ReturnCode::SUCCESS);
+        Harness {
+            flash: flash,
+            store: store,
+            client: client,
+        }
+    }
+
+    /// Starts a set, without completing flash operations.
+    fn start_set(&self, namespace: u32, key: &str, value: &[u8]) -> ReturnCode {
+        let buffer = self.client.buffer();
+        buffer[..value.len()].copy_from_slice(value);
+        match self
+            .store
+            .set(namespace, key.as_bytes(), buffer, value.len())
+        {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((result, buffer)) => {
+                *self.client.buffer.borrow_mut() = buffer;
+                result
+            }
+        }
+    }
+
+    fn set(&self, namespace: u32, key: &str, value: &[u8]) -> ReturnCode {
+        match self.start_set(namespace, key, value) {
+            ReturnCode::SUCCESS => {
+                self.flash.complete_all();
+                self.client.result.take().expect("no callback")
+            }
+            result => result,
+        }
+    }
+
+    fn get(&self, namespace: u32, key: &str) -> Result<Vec<u8>, ReturnCode> {
+        let buffer = self.client.buffer();
+        if let Err((result, buffer)) = self.store.get(namespace, key.as_bytes(), buffer) {
+            *self.client.buffer.borrow_mut() = buffer;
+            return Err(result);
+        }
+        self.flash.complete_all();
+        match self.client.result.take().expect("no callback") {
+            ReturnCode::SUCCESS => {
+                let buffer = self.client.buffer.borrow();
+                Ok(buffer.as_ref().unwrap()[..self.client.length.get()].to_vec())
+            }
+            result => Err(result),
+        }
+    }
+
+    fn delete(&self, namespace: u32, key: &str) -> ReturnCode {
+        match self.store.delete(namespace, key.as_bytes()) {
+            ReturnCode::SUCCESS => {
+                self.flash.complete_all();
+                self.client.result.take().expect("no callback")
+            }
+            result => result,
+        }
+    }
+}
+
+#[test]
+fn set_get_delete() {
+    let kv = Harness::new();
+    assert_eq!(kv.get(APP, "name"), Err(ReturnCode::ENODEVICE));
+    assert_eq!(kv.set(APP, "name", b"tock"), ReturnCode::SUCCESS);
+    assert_eq!(kv.set(APP, "empty", b""), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "name"), Ok(b"tock".to_vec()));
+    assert_eq!(kv.get(APP, "empty"), Ok(vec![]));
+
+    assert_eq!(kv.set(APP, "name", b"tock os"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "name"), Ok(b"tock os".to_vec()));
+
+    assert_eq!(kv.delete(APP, "name"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "name"), Err(ReturnCode::ENODEVICE));
+    assert_eq!(kv.delete(APP, "name"), ReturnCode::ENODEVICE);
+    assert_eq!(kv.get(APP, "empty"), Ok(vec![]));
+}
+
+#[test]
+fn namespaces_are_separate() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "key", b"mine"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(OTHER_APP, "key"), Err(ReturnCode::ENODEVICE));
+    assert_eq!(kv.set(OTHER_APP, "key", b"theirs"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "key"), Ok(b"mine".to_vec()));
+    assert_eq!(kv.delete(OTHER_APP, "key"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "key"), Ok(b"mine".to_vec()));
+}
+
+#[test]
+fn rejects_bad_keys_and_values() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "", b"value"), ReturnCode::EINVAL);
+    assert_eq!(
+        kv.set(APP, "a key longer than 16 bytes", b"value"),
+        ReturnCode::EINVAL
+    );
+    let too_long = vec![0; kv.store.max_value_len() + 1];
+    assert_eq!(kv.set(APP, "key", &too_long), ReturnCode::ESIZE);
+    let longest = vec![0x5a; kv.store.max_value_len()];
+    assert_eq!(kv.set(APP, "key", &longest), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "key"), Ok(longest));
+}
+
+#[test]
+fn busy_until_done() {
+    let kv = Harness::new();
+    assert_eq!(kv.start_set(APP, "a", b"1"), ReturnCode::SUCCESS);
+    assert_eq!(kv.store.delete(APP, b"a"), ReturnCode::EBUSY);
+    kv.flash.complete_all();
+    assert_eq!(kv.client.result.take(), Some(ReturnCode::SUCCESS));
+}
+
+#[test]
+fn quota_is_enforced() {
+    let kv = Harness::new();
+    let value = [7; 100];
+    let mut stored = 0;
+    for i in 0.. {
+        match kv.set(APP, &format!("key{}", i), &value) {
+            ReturnCode::SUCCESS => stored += 1,
+            result => {
+                assert_eq!(result, ReturnCode::ENOMEM);
+                break;
+            }
+        }
+    }
+    assert_eq!(stored, QUOTA / (8 + 4 + 100));
+    assert!(kv.store.used(APP) <= QUOTA);
+    // Other namespaces have their own quota
+    assert_eq!(kv.set(OTHER_APP, "key0", &value), ReturnCode::SUCCESS);
+    // Deleting makes room again
+    assert_eq!(kv.delete(APP, "key0"), ReturnCode::SUCCESS);
+    assert_eq!(kv.set(APP, "new", &value), ReturnCode::SUCCESS);
+}
+
+#[test]
+fn survives_remount() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "a", b"1"), ReturnCode::SUCCESS);
+    assert_eq!(kv.set(APP, "b", b"2"), ReturnCode::SUCCESS);
+    assert_eq!(kv.set(OTHER_APP, "a", b"3"), ReturnCode::SUCCESS);
+    assert_eq!(kv.set(APP, "a", b"4"), ReturnCode::SUCCESS);
+    assert_eq!(kv.delete(APP, "b"), ReturnCode::SUCCESS);
+
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(APP, "a"), Ok(b"4".to_vec()));
+    assert_eq!(kv.get(APP, "b"), Err(ReturnCode::ENODEVICE));
+    assert_eq!(kv.get(OTHER_APP, "a"), Ok(b"3".to_vec()));
+    assert_eq!(kv.store.used(APP), 8 + 1 + 1);
+
+    assert_eq!(kv.set(APP, "b", b"5"), ReturnCode::SUCCESS);
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(APP, "b"), Ok(b"5".to_vec()));
+}
+
+#[test]
+fn many_updates_wear_pages_evenly() {
+    let kv = Harness::new();
+    let mut expected = vec![Vec::new(); 8];
+    for i in 0..2000 {
+        let key = i % 8;
+        let value = vec![i as u8; 20 + (i * 7) % 60];
+        assert_eq!(
+            kv.set(APP, &format!("key{}", key), &value),
+            ReturnCode::SUCCESS,
+            "update {}",
+            i
+        );
+        expected[key] = value;
+    }
+    for (key, value) in expected.iter().enumerate() {
+        assert_eq!(kv.get(APP, &format!("key{}", key)), Ok(value.clone()));
+    }
+
+    let erases: Vec<usize> = (0..PAGES).map(|page| kv.flash.erase_count(page)).collect();
+    let least = *erases.iter().min().unwrap();
+    let most = *erases.iter().max().unwrap();
+    assert!(
+        least > 0 && most * 2 <= least * 3,
+        "erase counts {:?}",
+        erases
+    );
+
+    let kv = Harness::mount(kv.flash);
+    for (key, value) in expected.iter().enumerate() {
+        assert_eq!(kv.get(APP, &format!("key{}", key)), Ok(value.clone()));
+    }
+}
+
+#[test]
+fn full_store_compacts_deleted_keys() {
+    let kv = Harness::new();
+    let value = [1; 200];
+    // Far more than fits at once, but never more than the quota at a time
+    for i in 0..200 {
+        assert_eq!(
+            kv.set(i as u32, "big", &value),
+            ReturnCode::SUCCESS,
+            "set {}",
+            i
+        );
+        if i >= 4 {
+            assert_eq!(kv.delete(i as u32 - 4, "big"), ReturnCode::SUCCESS);
+        }
+    }
+    for i in 196..200 {
+        assert_eq!(kv.get(i, "big"), Ok(value.to_vec()));
+    }
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(195, "big"), Err(ReturnCode::ENODEVICE));
+    assert_eq!(kv.get(199, "big"), Ok(value.to_vec()));
+}
+
+#[test]
+fn store_refuses_more_than_capacity() {
+    let kv = Harness::new();
+    let value = vec![3; kv.store.max_value_len()];
+    let mut namespace = 0;
+    while kv.set(namespace, "k", &value) == ReturnCode::SUCCESS {
+        namespace += 1;
+    }
+    assert!(kv.store.capacity() < (namespace + 1) as usize * (8 + 1 + value.len()));
+    // Updating in place still works when full
+    assert_eq!(kv.set(0, "k", &value), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(0, "k"), Ok(value));
+}
+
+#[test]
+fn failed_write_keeps_previous_value() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "key", b"old"), ReturnCode::SUCCESS);
+
+    assert_eq!(kv.start_set(APP, "key", b"new"), ReturnCode::SUCCESS);
+    kv.flash.complete(Error::FlashError);
+    assert_eq!(kv.client.result.take(), Some(ReturnCode::FAIL));
+    assert_eq!(kv.get(APP, "key"), Ok(b"old".to_vec()));
+
+    // The page the write went to is cleaned up on the next update
+    assert_eq!(kv.set(APP, "key", b"newer"), ReturnCode::SUCCESS);
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(APP, "key"), Ok(b"newer".to_vec()));
+}
+
+#[test]
+fn power_loss_during_write_keeps_previous_value() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "key", b"old"), ReturnCode::SUCCESS);
+    assert_eq!(kv.start_set(APP, "key", b"new"), ReturnCode::SUCCESS);
+
+    // Half of the page made it to flash
+    let page = (0..PAGES)
+        .find(|page| {
+            kv.flash
+                .with_page(*page, |data| data.iter().all(|b| *b == 0xff))
+        })
+        .unwrap();
+    kv.flash.lose_power();
+    kv.flash.set_page(page, &[0x42; PAGE_SIZE / 2]);
+
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(APP, "key"), Ok(b"old".to_vec()));
+    kv.flash
+        .with_page(page, |data| assert!(data.iter().all(|b| *b == 0xff)));
+    assert_eq!(kv.set(APP, "key", b"new"), ReturnCode::SUCCESS);
+    assert_eq!(kv.get(APP, "key"), Ok(b"new".to_vec()));
+}
+
+#[test]
+fn power_loss_before_erasing_previous_copy() {
+    let kv = Harness::new();
+    assert_eq!(kv.set(APP, "a", b"1"), ReturnCode::SUCCESS);
+    assert_eq!(kv.start_set(APP, "b", b"2"), ReturnCode::SUCCESS);
+    // The write completes, but not the erase of the previous head page
+    kv.flash.complete(Error::CommandComplete);
+    kv.flash.lose_power();
+
+    let kv = Harness::mount(kv.flash);
+    assert_eq!(kv.get(APP, "a"), Ok(b"1".to_vec()));
+    assert_eq!(kv.get(APP, "b"), Ok(b"2".to_vec()));
+    assert_eq!(kv.store.used(APP), 2 * (8 + 1 + 1));
+    for i in 0..100 {
+        assert_eq!(kv.set(APP, "a", &[i; 50]), ReturnCode::SUCCESS);
+    }
+    assert_eq!(kv.get(APP, "b"), Ok(b"2".to_vec()));
+}
+
+#[test]
+fn unmounted_store_refuses_operations() {
+    let flash = leak(MockFlash::new());
+    let kv = Harness::start_mount(flash);
+    assert_eq!(kv.set(APP, "key", b"value"), ReturnCode::EBUSY);
+    flash.complete(Error::FlashError);
+    assert_eq!(kv.client.result.take(), Some(ReturnCode::FAIL));
+    assert!(!kv.store.is_mounted());
+    assert_eq!(kv.set(APP, "key", b"value"), ReturnCode::EOFF);
+}
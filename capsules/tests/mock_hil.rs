@@ -0,0 +1,666 @@
+//! Capsule state machines driven step by step through the mock HILs of
+//! `capsules::test::mock`.
+
+mod common;
+
+use capsules::firmware_update::{ConfirmTimer, ImageUpdate, UpdateClient};
+use capsules::framed_console::{self, FramedConsole};
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::Mac;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender, MuxIP6Sender};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::sixlowpan_compression::Context;
+use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
+use capsules::net::udp::udp::UDPHeader;
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use capsules::test::mock::alarm::{self, MockAlarm};
+use capsules::test::mock::flash::{self, MockFlash, MockPage, PAGE_SIZE};
+use capsules::test::mock::i2c::{self, MockI2CMaster};
+use capsules::test::mock::spi::{self, MockSpiMaster};
+use capsules::test::mock::uart::MockUart;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use common::{leak, leak_buf, NoCcm};
+use kernel::capabilities::{self, ProcessManagementCapability};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+    DynamicDeferredCallClientState,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+use kernel::hil::i2c::{I2CClient, I2CDevice as _, I2CMaster};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::radio;
+use kernel::hil::spi::{SpiMaster, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, Time};
+use kernel::hil::uart::{Transmit, TransmitClient};
+use kernel::{Kernel, ReturnCode};
+use std::cell::{Cell, RefCell};
+
+/// Names of the clients that got a callback, in order.
+type Log = RefCell<Vec<&'static str>>;
+
+struct Recorder {
+    name: &'static str,
+    log: &'static Log,
+    buffer: RefCell<Option<&'static mut [u8]>>,
+    len: Cell<usize>,
+    error: Cell<Option<hil::i2c::Error>>,
+    rcode: Cell<ReturnCode>,
+}
+
+impl Recorder {
+    fn new(name: &'static str, log: &'static Log) -> &'static Recorder {
+        leak(Recorder {
+            name: name,
+            log: log,
+            buffer: RefCell::new(None),
+            len: Cell::new(0),
+            error: Cell::new(None),
+            rcode: Cell::new(ReturnCode::SUCCESS),
+        })
+    }
+
+    fn take_buffer(&self) -> &'static mut [u8] {
+        self.buffer.borrow_mut().take().expect("no callback")
+    }
+}
+
+impl AlarmClient for Recorder {
+    fn fired(&self) {
+        self.log.borrow_mut().push(self.name);
+    }
+}
+
+impl I2CClient for Recorder {
+    fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
+        self.log.borrow_mut().push(self.name);
+        self.error.set(Some(error));
+        *self.buffer.borrow_mut() = Some(buffer);
+    }
+}
+
+impl TransmitClient for Recorder {
+    fn transmitted_buffer(&self, tx_buffer: &'static mut [u8], tx_len: usize, rcode: ReturnCode) {
+        self.log.borrow_mut().push(self.name);
+        self.len.set(tx_len);
+        self.rcode.set(rcode);
+        *self.buffer.borrow_mut() = Some(tx_buffer);
+    }
+}
+
+impl SpiMasterClient for Recorder {
+    fn read_write_done(
+        &self,
+        _write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) {
+        self.log.borrow_mut().push(self.name);
+        self.len.set(len);
+        *self.buffer.borrow_mut() = read_buffer;
+    }
+}
+
+impl NonvolatileStorageClient<'static> for Recorder {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.log.borrow_mut().push("read");
+        self.len.set(length);
+        *self.buffer.borrow_mut() = Some(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.log.borrow_mut().push("write");
+        self.len.set(length);
+        *self.buffer.borrow_mut() = Some(buffer);
+    }
+}
+
+/// MAC layer that keeps the frames it is asked to transmit.
+struct FrameSink {
+    frames: Cell<usize>,
+}
+
+impl Mac for FrameSink {
+    fn initialize(&self, _mac_buf: &'static mut [u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn set_config_client(&self, _client: &'static dyn radio::ConfigClient) {}
+
+    fn set_transmit_client(&self, _client: &'static dyn radio::TxClient) {}
+
+    fn set_receive_client(&self, _client: &'static dyn radio::RxClient) {}
+
+    fn set_receive_buffer(&self, _buffer: &'static mut [u8]) {}
+
+    fn get_address(&self) -> u16 {
+        1
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        [0; 8]
+    }
+
+    fn get_pan(&self) -> u16 {
+        0xabcd
+    }
+
+    fn set_address(&self, _addr: u16) {}
+
+    fn set_address_long(&self, _addr: [u8; 8]) {}
+
+    fn set_pan(&self, _id: u16) {}
+
+    fn config_commit(&self) {}
+
+    fn is_on(&self) -> bool {
+        true
+    }
+
+    fn transmit(
+        &self,
+        _full_mac_frame: &'static mut [u8],
+        _frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.frames.set(self.frames.get() + 1);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+/// Kernel update that counts confirmations, and is busy while `busy` is set.
+struct ConfirmCounter {
+    confirms: Cell<usize>,
+    busy: Cell<bool>,
+}
+
+impl ImageUpdate<'static> for ConfirmCounter {
+    fn set_client(&self, _client: &'static dyn UpdateClient) {}
+
+    fn begin(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn write(&self, _data: &[u8]) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn finish(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn abort(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn confirm(&self) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.confirms.set(self.confirms.get() + 1);
+        ReturnCode::SUCCESS
+    }
+}
+
+struct ProcessMgmtCap;
+unsafe impl ProcessManagementCapability for ProcessMgmtCap {}
+
+/// A framed console without processes, whose kernel output goes to `client`.
+fn framed_console(
+    uart: &'static MockUart<'static>,
+    client: &'static Recorder,
+) -> &'static FramedConsole<'static, ProcessMgmtCap> {
+    let kernel = leak(Kernel::new(leak([])));
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let console = leak(FramedConsole::new(
+        uart,
+        leak_buf(32),
+        leak_buf(1),
+        kernel.create_grant(&grant_cap),
+        kernel,
+        ProcessMgmtCap,
+    ));
+    uart.set_transmit_client(console);
+    console.set_transmit_client(client);
+    console
+}
+
+fn new_log() -> &'static Log {
+    leak(RefCell::new(Vec::new()))
+}
+
+#[test]
+fn virtual_alarms_fire_in_order() {
+    let log = new_log();
+    let hw = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(hw));
+    hw.set_client(mux);
+    let late = leak(VirtualMuxAlarm::new(mux));
+    let early = leak(VirtualMuxAlarm::new(mux));
+    late.set_client(Recorder::new("late", log));
+    early.set_client(Recorder::new("early", log));
+
+    hw.set_now(1000);
+    late.set_alarm(1500);
+    early.set_alarm(1200);
+    assert_eq!(hw.get_alarm(), 1200);
+
+    hw.advance(199);
+    assert!(log.borrow().is_empty());
+    hw.advance(1);
+    assert_eq!(*log.borrow(), ["early"]);
+    hw.advance(300);
+    assert_eq!(*log.borrow(), ["early", "late"]);
+    assert!(!early.is_enabled() && !late.is_enabled());
+}
+
+#[test]
+fn virtual_alarm_disable_keeps_others() {
+    let log = new_log();
+    let hw = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(hw));
+    hw.set_client(mux);
+    let a = leak(VirtualMuxAlarm::new(mux));
+    let b = leak(VirtualMuxAlarm::new(mux));
+    a.set_client(Recorder::new("a", log));
+    b.set_client(Recorder::new("b", log));
+
+    a.set_alarm(100);
+    b.set_alarm(200);
+    a.disable();
+    hw.advance(1000);
+    assert_eq!(*log.borrow(), ["b"]);
+    assert!(hw.calls.contains(alarm::Call::SetAlarm(100)));
+}
+
+#[test]
+fn virtual_alarm_in_the_past_fires_at_once() {
+    let log = new_log();
+    let hw = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(hw));
+    hw.set_client(mux);
+    let a = leak(VirtualMuxAlarm::new(mux));
+    a.set_client(Recorder::new("a", log));
+
+    // A deadline the counter has passed since the client read it is expired,
+    // not a full counter period away.
+    hw.set_now(1000);
+    let now = a.now();
+    hw.advance(20);
+    a.set_alarm(now + 10);
+    hw.advance(10);
+    assert_eq!(*log.borrow(), ["a"]);
+}
+
+#[test]
+fn virtual_alarm_waits_up_to_a_period() {
+    let log = new_log();
+    let hw = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(hw));
+    hw.set_client(mux);
+    let a = leak(VirtualMuxAlarm::new(mux));
+    a.set_client(Recorder::new("a", log));
+
+    // More than half a period after the client read the counter is still in
+    // the future.
+    hw.advance(0x1000);
+    let now = a.now();
+    a.set_alarm(now.wrapping_add(0xc000_0000));
+    hw.advance(0xbfff_ffff);
+    assert!(log.borrow().is_empty());
+    hw.advance(1);
+    assert_eq!(*log.borrow(), ["a"]);
+
+    // A repeating alarm counts from its last expiration.
+    a.set_alarm(a.get_alarm().wrapping_add(0xf000_0000));
+    hw.advance(0xefff_ffff);
+    assert_eq!(*log.borrow(), ["a"]);
+    hw.advance(1);
+    assert_eq!(*log.borrow(), ["a", "a"]);
+}
+
+#[test]
+fn virtual_alarm_ahead_of_now_waits() {
+    let log = new_log();
+    let hw = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(hw));
+    hw.set_client(mux);
+    let a = leak(VirtualMuxAlarm::new(mux));
+    a.set_client(Recorder::new("a", log));
+
+    // Less than half a period ahead, an alarm that wraps the counter is
+    // still in the future.
+    hw.advance(0xf000_0000);
+    a.set_alarm(0xf000_0000u32.wrapping_add(0x7000_0000));
+    hw.advance(0x6fff_ffff);
+    assert!(log.borrow().is_empty());
+    hw.advance(1);
+    assert_eq!(*log.borrow(), ["a"]);
+}
+
+#[test]
+fn firmware_is_confirmed_after_the_uptime() {
+    let hw = leak(MockAlarm::new());
+    let update = leak(ConfirmCounter {
+        confirms: Cell::new(0),
+        busy: Cell::new(true),
+    });
+    let timer = leak(ConfirmTimer::new(update, hw));
+    hw.set_client(timer);
+
+    // One second of the 32 kHz mock alarm
+    timer.start(1000);
+    hw.advance(32767);
+    assert_eq!(update.confirms.get(), 0);
+
+    // A busy update is confirmed once it is done
+    hw.advance(1);
+    assert_eq!(update.confirms.get(), 0);
+    update.busy.set(false);
+    hw.advance(32768);
+    assert_eq!(update.confirms.get(), 1);
+    assert!(!hw.is_enabled());
+}
+
+#[test]
+fn ip6_send_starts_when_the_counter_ticks_while_arming() {
+    let hw = leak(MockAlarm::new());
+    let mac = leak(FrameSink {
+        frames: Cell::new(0),
+    });
+    let framer = leak(Framer::new(&*mac, leak(NoCcm)));
+    let mux = leak(MuxIP6Sender::new(
+        &*hw,
+        leak_buf(radio::MAX_BUF_SIZE),
+        &*framer,
+    ));
+    hw.set_client(mux);
+
+    let sixlowpan_alarm = leak(MockAlarm::new());
+    let sixlowpan = leak(Sixlowpan::new(
+        Context {
+            prefix: [0; 16],
+            prefix_len: 0,
+            id: 0,
+            compress: false,
+        },
+        &*sixlowpan_alarm,
+    ));
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let ip_vis = leak(IpVisibilityCapability::new(&create_cap));
+    let net_cap = leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ));
+    let packet = leak(IP6Packet::new(IPPayload::new(
+        TransportHeader::UDP(UDPHeader::new()),
+        leak_buf(200),
+    )));
+    let sender = leak(IP6SendStruct::new(
+        packet,
+        TxState::new(&*sixlowpan as &dyn SixlowpanState),
+        &*mux,
+        MacAddress::Short(2),
+        MacAddress::Short(1),
+        ip_vis,
+    ));
+    mux.add_sender(sender);
+
+    // The counter moves on between reading it and arming the alarm, so an
+    // alarm armed for the value read would only fire after a full period.
+    hw.set_now(1000);
+    hw.set_tics_per_read(1);
+    let mut payload = LeasableBuffer::new(leak_buf(8));
+    payload.slice(..8);
+    let dst = IPAddr::generate_from_mac(MacAddress::Short(2));
+    let mut udp_header = UDPHeader::new();
+    udp_header.set_len((payload.len() + udp_header.get_hdr_size()) as u16);
+    let result = sender.send_to(dst, TransportHeader::UDP(udp_header), &payload, net_cap);
+    assert_eq!(result, ReturnCode::SUCCESS);
+    assert_eq!(mac.frames.get(), 0);
+    hw.advance(10);
+    assert_eq!(mac.frames.get(), 1);
+}
+
+#[test]
+fn framed_console_returns_refused_kernel_output() {
+    let log = new_log();
+    let uart = leak(MockUart::new());
+    let client = Recorder::new("kernel", log);
+    let console = framed_console(uart, client);
+
+    uart.fail_next(ReturnCode::EOFF);
+    let (rval, buffer) = console.transmit_buffer(leak_buf(8), 8);
+    assert_eq!(rval, ReturnCode::EOFF);
+    assert!(buffer.is_some());
+
+    // The console is not left waiting for the refused output
+    let (rval, buffer) = console.transmit_buffer(leak_buf(8), 8);
+    assert_eq!(rval, ReturnCode::SUCCESS);
+    assert!(buffer.is_none());
+    uart.complete_transmit(ReturnCode::SUCCESS);
+    assert_eq!(*log.borrow(), ["kernel"]);
+    assert_eq!(
+        (client.len.get(), client.rcode.get()),
+        (8, ReturnCode::SUCCESS)
+    );
+}
+
+#[test]
+fn framed_console_reports_failed_kernel_frames() {
+    let log = new_log();
+    let uart = leak(MockUart::new());
+    let client = Recorder::new("kernel", log);
+    let console = framed_console(uart, client);
+    let payload_per_frame = 32 - framed_console::FRAME_OVERHEAD;
+
+    // The UART refuses the second of three frames
+    console.transmit_buffer(leak_buf(60), 60);
+    uart.fail_next(ReturnCode::EOFF);
+    uart.complete_transmit(ReturnCode::SUCCESS);
+    assert_eq!(*log.borrow(), ["kernel"]);
+    assert_eq!(
+        (client.len.get(), client.rcode.get()),
+        (payload_per_frame, ReturnCode::EOFF)
+    );
+    assert!(!uart.is_transmitting());
+
+    // The UART fails the first frame
+    console.transmit_buffer(client.take_buffer(), 60);
+    uart.complete_transmit(ReturnCode::FAIL);
+    assert_eq!(*log.borrow(), ["kernel", "kernel"]);
+    assert_eq!(
+        (client.len.get(), client.rcode.get()),
+        (0, ReturnCode::FAIL)
+    );
+    assert!(!uart.is_transmitting());
+}
+
+#[test]
+fn uart_mux_recovers_from_refused_transmissions() {
+    let log = new_log();
+    let uart = leak(MockUart::new());
+    let ddc = leak(DynamicDeferredCall::new(leak([
+        DynamicDeferredCallClientState::default(),
+    ])));
+    let mux = leak(MuxUart::new(uart, leak_buf(1), 115200, ddc));
+    let handle = ddc.register(mux).unwrap();
+    mux.initialize_callback_handle(handle);
+    uart.set_transmit_client(mux);
+    let device = leak(UartDevice::new(mux, false));
+    device.setup();
+    let client = Recorder::new("console", log);
+    device.set_transmit_client(client);
+
+    // The UART refuses the first transmission, as a closed USB port does
+    uart.fail_next(ReturnCode::EOFF);
+    assert_eq!(
+        device.transmit_buffer(leak_buf(8), 8).0,
+        ReturnCode::SUCCESS
+    );
+    mux.call(handle);
+    assert_eq!(*log.borrow(), ["console"]);
+    assert_eq!(
+        (client.len.get(), client.rcode.get()),
+        (0, ReturnCode::EOFF)
+    );
+
+    // The mux is not left waiting for a callback of the refused one
+    let (rval, _) = device.transmit_buffer(client.take_buffer(), 8);
+    assert_eq!(rval, ReturnCode::SUCCESS);
+    mux.call(handle);
+    assert!(uart.is_transmitting());
+    uart.complete_transmit(ReturnCode::SUCCESS);
+    assert_eq!(*log.borrow(), ["console", "console"]);
+    assert_eq!(client.rcode.get(), ReturnCode::SUCCESS);
+}
+
+#[test]
+fn i2c_mux_serializes_devices() {
+    let log = new_log();
+    let hw = leak(MockI2CMaster::new());
+    let mux = leak(MuxI2C::new(hw));
+    hw.set_master_client(mux);
+    let sensor = leak(I2CDevice::new(mux, 0x40));
+    let eeprom = leak(I2CDevice::new(mux, 0x50));
+    let sensor_client = Recorder::new("sensor", log);
+    let eeprom_client = Recorder::new("eeprom", log);
+    sensor.set_client(sensor_client);
+    eeprom.set_client(eeprom_client);
+
+    sensor.enable();
+    eeprom.enable();
+    assert_eq!(hw.calls.next(), Some(i2c::Call::Enable));
+    assert!(hw.calls.is_empty());
+
+    let command = leak_buf(4);
+    command[0] = 0xe3;
+    sensor.write_read(command, 1, 2);
+    eeprom.write(leak_buf(4), 3);
+    assert_eq!(
+        hw.calls.next(),
+        Some(i2c::Call::WriteRead {
+            addr: 0x40,
+            write_len: 1,
+            read_len: 2
+        })
+    );
+    assert_eq!(hw.with_written(|data| data.to_vec()), Some(vec![0xe3]));
+    // The eeprom waits for the bus
+    assert!(hw.calls.is_empty());
+
+    hw.complete(&[0x12, 0x34], hil::i2c::Error::CommandComplete);
+    assert_eq!(&sensor_client.take_buffer()[..2], &[0x12, 0x34]);
+    assert_eq!(
+        hw.calls.next(),
+        Some(i2c::Call::Write { addr: 0x50, len: 3 })
+    );
+
+    hw.complete(&[], hil::i2c::Error::AddressNak);
+    assert_eq!(eeprom_client.error.get(), Some(hil::i2c::Error::AddressNak));
+    assert_eq!(*log.borrow(), ["sensor", "eeprom"]);
+    assert!(!hw.is_busy());
+}
+
+#[test]
+fn spi_mux_selects_chip_per_device() {
+    let log = new_log();
+    let hw = leak(MockSpiMaster::new());
+    let mux = leak(MuxSpiMaster::new(hw));
+    hw.set_client(mux);
+    let radio = leak(VirtualSpiMasterDevice::new(mux, 1));
+    let flash = leak(VirtualSpiMasterDevice::new(mux, 2));
+    let radio_client = Recorder::new("radio", log);
+    radio.set_client(radio_client);
+    flash.set_client(Recorder::new("flash", log));
+
+    radio.configure(
+        hil::spi::ClockPolarity::IdleHigh,
+        hil::spi::ClockPhase::SampleTrailing,
+        4_000_000,
+    );
+    assert_eq!(hw.calls.next(), Some(spi::Call::ChipSelect(1)));
+    assert_eq!(
+        hw.calls.next(),
+        Some(spi::Call::SetClock(hil::spi::ClockPolarity::IdleHigh))
+    );
+    hw.calls.clear();
+
+    radio.read_write_bytes(leak_buf(8), Some(leak_buf(8)), 3);
+    flash.read_write_bytes(leak_buf(8), None, 5);
+    assert_eq!(hw.chip_select(), Some(1));
+    assert!(hw.is_busy());
+
+    hw.complete(&[7, 8, 9]);
+    assert_eq!(radio_client.len.get(), 3);
+    assert_eq!(&radio_client.take_buffer()[..3], &[7, 8, 9]);
+    assert_eq!(hw.chip_select(), Some(2));
+    assert_eq!(
+        hw.calls.last(),
+        Some(spi::Call::ReadWriteBytes {
+            len: 5,
+            read: false
+        })
+    );
+
+    hw.complete(&[]);
+    assert_eq!(*log.borrow(), ["radio", "flash"]);
+}
+
+#[test]
+fn nonvolatile_unaligned_write_reads_modifies_and_writes() {
+    let log = new_log();
+    let hw = leak(MockFlash::new());
+    let storage = leak(NonvolatileToPages::new(hw, leak(MockPage::default())));
+    hw.set_client(storage);
+    let client = Recorder::new("storage", log);
+    storage.set_client(client);
+
+    hw.set_page(1, &[0; PAGE_SIZE]);
+    // Spans the end of page 0 and the start of page 1
+    let data = leak_buf(8);
+    data.copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(storage.write(data, PAGE_SIZE - 4, 8), ReturnCode::SUCCESS);
+    assert_eq!(hw.pending(), Some(flash::Call::Read(0)));
+    hw.complete(hil::flash::Error::CommandComplete);
+    assert_eq!(hw.pending(), Some(flash::Call::Write(0)));
+    assert_eq!(storage.write(leak_buf(1), 0, 1), ReturnCode::EBUSY);
+    assert_eq!(hw.complete_all(), 3);
+
+    assert_eq!(*log.borrow(), ["write"]);
+    assert_eq!(client.len.get(), 8);
+    hw.with_page(0, |page| {
+        assert_eq!(&page[PAGE_SIZE - 5..], &[0xff, 1, 2, 3, 4]);
+    });
+    // Page 1 was all zeroes, and NOR flash cannot set bits back
+    hw.with_page(1, |page| assert_eq!(&page[..5], &[0; 5]));
+
+    let buffer = client.take_buffer();
+    assert_eq!(storage.read(buffer, PAGE_SIZE - 2, 4), ReturnCode::SUCCESS);
+    hw.complete_all();
+    assert_eq!(&client.take_buffer()[..4], &[3, 4, 0, 0]);
+}
+
+#[test]
+fn nonvolatile_returns_synchronous_flash_errors() {
+    let hw = leak(MockFlash::new());
+    let storage = leak(NonvolatileToPages::new(hw, leak(MockPage::default())));
+    hw.set_client(storage);
+    storage.set_client(Recorder::new("storage", new_log()));
+
+    hw.fail_next(ReturnCode::FAIL);
+    assert_eq!(storage.read(leak_buf(4), 0, 4), ReturnCode::FAIL);
+    assert_eq!(hw.calls.next(), Some(flash::Call::Read(0)));
+    assert_eq!(hw.pending(), None);
+}
@@ -0,0 +1,263 @@
+//! The allocation table of the nonvolatile storage driver, over storage held
+//! in memory.
+
+mod common;
+
+use capsules::nonvolatile_storage_driver::{RegionTable, TableDone};
+use common::{leak, leak_buf};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ReturnCode;
+use std::cell::{Cell, RefCell};
+
+const START: usize = 64;
+const MAGIC: u32 = 0x4e56_5354;
+const APP: u32 = 0x1234_5678;
+const OTHER_APP: u32 = 0x0bad_cafe;
+const THIRD_APP: u32 = 0x0000_0003;
+
+/// Storage that overwrites bytes, as the page writes of the flash drivers
+/// do, and completes operations when the test says so.
+struct MemoryStorage {
+    data: RefCell<Vec<u8>>,
+    // Whether the pending operation is a write, its address and length
+    pending: Cell<Option<(bool, usize, usize)>>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient<'static>>,
+}
+
+impl MemoryStorage {
+    fn new(length: usize) -> &'static MemoryStorage {
+        leak(MemoryStorage {
+            data: RefCell::new(vec![0xff; START + length]),
+            pending: Cell::new(None),
+            buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        })
+    }
+
+    fn start(&self, write: bool, buffer: &'static mut [u8], address: usize, length: usize) {
+        assert!(self.pending.get().is_none());
+        assert!(length <= buffer.len());
+        assert!(address + length <= self.data.borrow().len());
+        self.buffer.replace(buffer);
+        self.pending.set(Some((write, address, length)));
+    }
+
+    /// Completes operations until the client stops starting new ones.
+    fn complete_all(&self) {
+        while let Some((write, address, length)) = self.pending.take() {
+            let buffer = self.buffer.take().unwrap();
+            let mut data = self.data.borrow_mut();
+            if write {
+                data[address..address + length].copy_from_slice(&buffer[..length]);
+            } else {
+                buffer[..length].copy_from_slice(&data[address..address + length]);
+            }
+            drop(data);
+            self.client.map(move |client| {
+                if write {
+                    client.write_done(buffer, length)
+                } else {
+                    client.read_done(buffer, length)
+                }
+            });
+        }
+    }
+
+    fn bytes(&self, address: usize, length: usize) -> Vec<u8> {
+        self.data.borrow()[START + address..START + address + length].to_vec()
+    }
+
+    fn set_bytes(&self, address: usize, bytes: &[u8]) {
+        self.data.borrow_mut()[START + address..START + address + bytes.len()]
+            .copy_from_slice(bytes);
+    }
+}
+
+impl NonvolatileStorage<'static> for MemoryStorage {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient<'static>) {
+        self.client.set(client);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+        self.start(false, buffer, address, length);
+        ReturnCode::SUCCESS
+    }
+
+    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+        self.start(true, buffer, address, length);
+        ReturnCode::SUCCESS
+    }
+}
+
+/// Passes the completions of the storage to a table, as the driver does.
+struct Table {
+    storage: &'static MemoryStorage,
+    table: RegionTable<'static>,
+    buffer: RefCell<Option<&'static mut [u8]>>,
+    result: Cell<Option<Result<usize, ReturnCode>>>,
+}
+
+impl Table {
+    fn new(storage: &'static MemoryStorage, length: usize, app_quota: usize) -> &'static Table {
+        let table = leak(Table {
+            storage: storage,
+            table: RegionTable::new(storage, START, length, app_quota),
+            buffer: RefCell::new(Some(leak_buf(512))),
+            result: Cell::new(None),
+        });
+        storage.set_client(table);
+        table
+    }
+
+    fn step_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.table.step_done(buffer, length) {
+            TableDone::Continue => {}
+            TableDone::Done(buffer, result) => {
+                *self.buffer.borrow_mut() = Some(buffer);
+                self.result.set(Some(result));
+            }
+            TableDone::Failed(result) => self.result.set(Some(Err(result))),
+        }
+    }
+
+    fn find(&self, id: u32) -> Result<usize, ReturnCode> {
+        let buffer = self.buffer.borrow_mut().take().unwrap();
+        assert_eq!(self.table.find(id, buffer), ReturnCode::SUCCESS);
+        self.storage.complete_all();
+        self.result.take().unwrap()
+    }
+
+    fn release(&self, id: u32) -> Result<usize, ReturnCode> {
+        let buffer = self.buffer.borrow_mut().take().unwrap();
+        assert_eq!(self.table.release(id, buffer), ReturnCode::SUCCESS);
+        self.storage.complete_all();
+        self.result.take().unwrap()
+    }
+
+    /// Offset of a region from the start of the table.
+    fn region(&self, region: usize) -> usize {
+        self.table.region_address(region) - START
+    }
+}
+
+impl NonvolatileStorageClient<'static> for Table {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.step_done(buffer, length);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.step_done(buffer, length);
+    }
+}
+
+fn entry(id: u32) -> Vec<u8> {
+    let mut entry = id.to_le_bytes().to_vec();
+    entry.extend_from_slice(&(!id).to_le_bytes());
+    entry
+}
+
+/// Length of a table with `regions` regions of `app_quota` bytes.
+fn length(regions: usize, app_quota: usize) -> usize {
+    8 + regions * (8 + app_quota)
+}
+
+#[test]
+fn erased_storage_is_formatted() {
+    let storage = MemoryStorage::new(length(4, 32));
+    let table = Table::new(storage, length(4, 32), 32);
+    assert_eq!(table.table.regions(), 4);
+
+    assert_eq!(table.find(APP), Ok(0));
+    let mut header = MAGIC.to_le_bytes().to_vec();
+    header.extend_from_slice(&32u32.to_le_bytes());
+    assert_eq!(storage.bytes(0, 8), header);
+    assert_eq!(storage.bytes(8, 8), entry(APP));
+    assert_eq!(storage.bytes(16, 24), vec![0; 24]);
+    assert_eq!(storage.bytes(table.region(0), 32), vec![0; 32]);
+
+    // Looking up again finds the same region, and writes nothing.
+    storage.set_bytes(table.region(0), &[1, 2, 3]);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(storage.bytes(table.region(0), 3), [1, 2, 3]);
+}
+
+#[test]
+fn quota_mismatch_reformats() {
+    let storage = MemoryStorage::new(length(4, 64));
+    let table = Table::new(storage, length(4, 64), 32);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+    storage.set_bytes(table.region(0), &[0x5a; 32]);
+
+    let table = Table::new(storage, length(4, 64), 64);
+    assert_eq!(table.find(OTHER_APP), Ok(0));
+    assert_eq!(storage.bytes(4, 4), 64u32.to_le_bytes());
+    assert_eq!(storage.bytes(8, 8), entry(OTHER_APP));
+    assert_eq!(storage.bytes(16, 24), vec![0; 24]);
+    assert_eq!(storage.bytes(table.region(0), 64), vec![0; 64]);
+}
+
+#[test]
+fn returning_app_gets_its_region() {
+    let storage = MemoryStorage::new(length(4, 32));
+    let table = Table::new(storage, length(4, 32), 32);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+    storage.set_bytes(table.region(1), &[7; 32]);
+
+    // As after a reboot
+    let table = Table::new(storage, length(4, 32), 32);
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+    assert_eq!(storage.bytes(table.region(1), 32), vec![7; 32]);
+    assert_eq!(table.find(APP), Ok(0));
+}
+
+#[test]
+fn full_table_returns_enomem() {
+    let storage = MemoryStorage::new(length(2, 32));
+    let table = Table::new(storage, length(2, 32), 32);
+    assert_eq!(table.table.regions(), 2);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+    assert_eq!(table.find(THIRD_APP), Err(ReturnCode::ENOMEM));
+    assert_eq!(
+        storage.bytes(8, 16),
+        [entry(APP), entry(OTHER_APP)].concat()
+    );
+}
+
+#[test]
+fn table_larger_than_buffer() {
+    // 808 bytes of table, read in chunks of the 512 byte buffer
+    let storage = MemoryStorage::new(length(100, 16));
+    let table = Table::new(storage, length(100, 16), 16);
+    assert_eq!(table.table.regions(), 100);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(storage.bytes(8 + 99 * 8, 8), vec![0; 8]);
+
+    // An entry in the second chunk
+    storage.set_bytes(8 + 90 * 8, &entry(OTHER_APP));
+    assert_eq!(table.find(OTHER_APP), Ok(90));
+    assert_eq!(table.find(THIRD_APP), Ok(1));
+    assert_eq!(storage.bytes(8 + 90 * 8, 8), entry(OTHER_APP));
+
+    // The last region ends the userspace region.
+    assert_eq!(table.region(99) + 16, length(100, 16));
+}
+
+#[test]
+fn released_region_is_cleared_and_reused() {
+    let storage = MemoryStorage::new(length(4, 32));
+    let table = Table::new(storage, length(4, 32), 32);
+    assert_eq!(table.find(APP), Ok(0));
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+    storage.set_bytes(table.region(0), &[0x5a; 32]);
+
+    assert_eq!(table.release(APP), Ok(0));
+    assert_eq!(table.release(APP), Err(ReturnCode::EALREADY));
+    assert_eq!(table.find(THIRD_APP), Ok(0));
+    assert_eq!(storage.bytes(table.region(0), 32), vec![0; 32]);
+    assert_eq!(table.find(OTHER_APP), Ok(1));
+}
@@ -0,0 +1,150 @@
+//! Multi-node tests of the 6LoWPAN stack over a simulated radio medium.
+
+mod common;
+
+use capsules::net::ipv6::ipv6_send::{TxQueue, TxQueueStats};
+use common::Network;
+use kernel::ReturnCode;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+#[test]
+fn fragmented_datagram_is_delivered() {
+    let net = Network::new();
+    let a = net.add_node(0x0001);
+    let b = net.add_node(0x0002);
+    net.run_for(10);
+
+    let data = payload(500);
+    assert_eq!(a.send(b, &data), ReturnCode::SUCCESS);
+    net.run_for(2000);
+
+    assert_eq!(a.sent(), vec![ReturnCode::SUCCESS]);
+    let received = b.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].src, a.ip);
+    assert_eq!(received[0].payload, data);
+
+    let stats = net.medium.stats();
+    assert!(stats.transmissions > 1);
+    assert_eq!(stats.transmissions, stats.deliveries);
+    assert_eq!(stats.losses, 0);
+}
+
+#[test]
+fn transmit_queue_counts_datagrams() {
+    let net = Network::new();
+    let a = net.add_node(0x0001);
+    let b = net.add_node(0x0002);
+    net.run_for(10);
+
+    assert_eq!(a.send(b, &payload(500)), ReturnCode::SUCCESS);
+    let stats = a.ip_send_mux.stats();
+    assert_eq!(stats.queued, 1);
+    assert_eq!(stats.max_queued, 1);
+
+    // The sender holds one datagram at a time.
+    assert_eq!(a.send(b, &payload(20)), ReturnCode::EBUSY);
+    assert_eq!(a.ip_send_mux.stats().dropped, 1);
+    net.run_for(2000);
+
+    let stats = a.ip_send_mux.stats();
+    assert_eq!(
+        stats,
+        TxQueueStats {
+            queued: 0,
+            max_queued: 1,
+            sent: 1,
+            dropped: 1,
+            fragments: stats.fragments,
+        }
+    );
+    assert!(stats.fragments > 1);
+    assert_eq!(b.ip_send_mux.stats(), TxQueueStats::default());
+}
+
+#[test]
+fn lost_frames_are_retransmitted() {
+    let net = Network::new();
+    let a = net.add_node(0x0001);
+    let b = net.add_node(0x0002);
+    net.medium.seed(2);
+    net.medium.set_loss(a.radio.node(), b.radio.node(), 500);
+    net.run_for(10);
+
+    let data = payload(500);
+    assert_eq!(a.send(b, &data), ReturnCode::SUCCESS);
+    net.run_for(2000);
+
+    let received = b.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].payload, data);
+
+    let stats = net.medium.stats();
+    assert!(stats.losses > 0);
+    assert_eq!(stats.transmissions, stats.deliveries + stats.losses);
+}
+
+#[test]
+fn frames_only_reach_connected_nodes() {
+    let net = Network::new();
+    let a = net.add_node(0x0001);
+    let b = net.add_node(0x0002);
+    let c = net.add_node(0x0003);
+    // a - b - c
+    net.medium
+        .set_connected(a.radio.node(), c.radio.node(), false);
+    net.run_for(10);
+
+    let data = payload(40);
+    assert_eq!(a.send(c, &data), ReturnCode::SUCCESS);
+    net.run_for(1000);
+    assert!(c.received().is_empty());
+    assert!(b.received().is_empty());
+
+    assert_eq!(a.send(b, &data), ReturnCode::SUCCESS);
+    assert_eq!(c.send(b, &data), ReturnCode::SUCCESS);
+    net.run_for(1000);
+    let received = b.received();
+    assert_eq!(received.len(), 2);
+    assert!(received.iter().any(|datagram| datagram.src == a.ip));
+    assert!(received.iter().any(|datagram| datagram.src == c.ip));
+    assert!(a.received().is_empty());
+    assert!(c.received().is_empty());
+}
+
+#[test]
+fn latency_delays_delivery() {
+    let net = Network::new();
+    let a = net.add_node(0x0001);
+    let b = net.add_node(0x0002);
+    net.medium.set_latency(50_000);
+    net.run_for(10);
+
+    assert_eq!(a.send(b, &payload(20)), ReturnCode::SUCCESS);
+    net.run_for(40);
+    assert!(b.received().is_empty());
+    net.run_for(20);
+    assert_eq!(b.received().len(), 1);
+}
+
+#[test]
+fn xmac_nodes_exchange_datagrams() {
+    let net = Network::new();
+    let a = net.add_xmac_node(0x0001);
+    let b = net.add_xmac_node(0x0002);
+    net.run_for(10);
+
+    let data = payload(60);
+    assert_eq!(a.send(b, &data), ReturnCode::SUCCESS);
+    net.run_for(3000);
+    assert_eq!(a.sent(), vec![ReturnCode::SUCCESS]);
+    assert_eq!(b.received().len(), 1);
+    assert_eq!(b.received()[0].payload, data);
+
+    assert_eq!(b.send(a, &data), ReturnCode::SUCCESS);
+    net.run_for(3000);
+    assert_eq!(a.received().len(), 1);
+}
@@ -1,6 +1,7 @@
 use crate::gpio;
 use crate::i2c;
 use crate::peripheral_interrupts::NvicIrq;
+use crate::rfc;
 use crate::rtc;
 use crate::uart;
 use core::fmt::Write;
@@ -53,6 +54,7 @@ impl kernel::Chip for Cc26X2 {
                     NvicIrq::AonRtc => rtc::RTC.handle_interrupt(),
                     NvicIrq::Uart0 => uart::UART0.handle_interrupt(),
                     NvicIrq::I2c0 => i2c::I2C0.handle_interrupt(),
+                    NvicIrq::RfCorePe1 | NvicIrq::RfCorePe2 => rfc::RFC.handle_interrupt(),
                     // We need to ignore JTAG events since some debuggers emit these
                     NvicIrq::AonProg => (),
                     _ => panic!("Unhandled interrupt {:?}", irq),
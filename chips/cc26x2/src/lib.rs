@@ -16,6 +16,7 @@ pub mod memory_map;
 pub mod peripheral_interrupts;
 pub mod prcm;
 pub mod pwm;
+pub mod rfc;
 pub mod rom;
 pub mod rtc;
 pub mod trng;
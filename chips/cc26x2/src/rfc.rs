@@ -0,0 +1,905 @@
+//! RF core (RFC) driver, cc26x2 family
+//!
+//! The RF core runs radio operations on its own processor, the command and
+//! packet engine (CPE). This driver hands it chains of radio operation
+//! commands kept in RAM through the doorbell registers: a setup command for
+//! the PHY, `CMD_FS` to tune the frequency synthesizer, and a receive command
+//! that runs until a packet is sent or the configuration changes.
+//!
+//! The O-QPSK PHY uses the IEEE 802.15.4 commands of the CPE. The FSK and
+//! GFSK PHYs use its proprietary mode, with the 802.15.4g PHR or a 1-byte
+//! length field written and parsed by the CPE. Sub-GHz frequencies are only
+//! available on chips with a sub-GHz front end, such as the cc1352 and
+//! cc1312.
+//!
+//! The RF core is clocked from the HF crystal oscillator, which the board
+//! must select before starting the radio. Register overrides, front end
+//! configuration and TX power settings depend on the chip and the board, are
+//! generated with SmartRF Studio, and are given to the driver as
+//! `RfSettings`.
+//!
+//! The driver implements `hil::radio::MultiPhyRadio`. It does not filter,
+//! acknowledge or retransmit packets, and sends them without clear channel
+//! assessment.
+
+use crate::prcm;
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil::radio::{self, Modulation, PacketFormat, PhyConfig};
+use kernel::ReturnCode;
+
+#[repr(C)]
+struct RfcDbellRegisters {
+    cmdr: ReadWrite<u32>,
+    cmdsta: ReadOnly<u32>,
+    _rfhwifg: ReadWrite<u32>,
+    _rfhwien: ReadWrite<u32>,
+    // Flags are cleared by writing 0
+    rfcpeifg: ReadWrite<u32, CpeInterrupt::Register>,
+    rfcpeien: ReadWrite<u32, CpeInterrupt::Register>,
+    rfcpeisl: ReadWrite<u32, CpeInterrupt::Register>,
+    rfackifg: ReadWrite<u32, Ack::Register>,
+    _sysgpoctl: ReadWrite<u32>,
+}
+
+#[repr(C)]
+struct RfcPwrRegisters {
+    pwmclken: ReadWrite<u32, ModuleClocks::Register>,
+}
+
+register_bitfields![
+    u32,
+    CpeInterrupt [
+        INTERNAL_ERROR OFFSET(31) NUMBITS(1) [],
+        SYNTH_NO_LOCK OFFSET(28) NUMBITS(1) [],
+        RX_ENTRY_DONE OFFSET(23) NUMBITS(1) [],
+        TX_DONE OFFSET(4) NUMBITS(1) [],
+        LAST_FG_COMMAND_DONE OFFSET(3) NUMBITS(1) [],
+        FG_COMMAND_DONE OFFSET(2) NUMBITS(1) [],
+        LAST_COMMAND_DONE OFFSET(1) NUMBITS(1) [],
+        COMMAND_DONE OFFSET(0) NUMBITS(1) []
+    ],
+    Ack [
+        ACKFLAG OFFSET(0) NUMBITS(1) []
+    ],
+    ModuleClocks [
+        RFCTRC OFFSET(10) NUMBITS(1) [],
+        FSCA OFFSET(9) NUMBITS(1) [],
+        PHA OFFSET(8) NUMBITS(1) [],
+        RAT OFFSET(7) NUMBITS(1) [],
+        RFERAM OFFSET(6) NUMBITS(1) [],
+        RFE OFFSET(5) NUMBITS(1) [],
+        MDMRAM OFFSET(4) NUMBITS(1) [],
+        MDM OFFSET(3) NUMBITS(1) [],
+        CPERAM OFFSET(2) NUMBITS(1) [],
+        CPE OFFSET(1) NUMBITS(1) [],
+        RFC OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+const RFC_DBELL_BASE: StaticRef<RfcDbellRegisters> =
+    unsafe { StaticRef::new(0x4004_1000 as *const RfcDbellRegisters) };
+
+const RFC_PWR_BASE: StaticRef<RfcPwrRegisters> =
+    unsafe { StaticRef::new(0x4004_0000 as *const RfcPwrRegisters) };
+
+// Direct commands
+const CMD_ABORT: u16 = 0x0401;
+const CMD_START_RAT: u16 = 0x0405;
+const CMD_PING: u16 = 0x0406;
+
+// Radio operation commands
+const CMD_RADIO_SETUP: u16 = 0x0802;
+const CMD_FS: u16 = 0x0803;
+const CMD_IEEE_RX: u16 = 0x2801;
+const CMD_IEEE_TX: u16 = 0x2C01;
+const CMD_PROP_TX_ADV: u16 = 0x3803;
+const CMD_PROP_RX_ADV: u16 = 0x3804;
+const CMD_PROP_RADIO_DIV_SETUP: u16 = 0x3807;
+
+// Result of a command in CMDSTA
+const CMDSTA_DONE: u32 = 0x01;
+
+// Radio operation status: bit 10 is set once the command has finished
+// successfully, bit 11 once it has failed. The upper bits identify the
+// command set.
+const STATUS_DONE: u16 = 0x0400;
+const STATUS_ERROR: u16 = 0x0800;
+
+const TRIG_NOW: u8 = 0;
+const TRIG_NEVER: u8 = 1;
+const COND_ALWAYS: u8 = 0;
+const COND_NEVER: u8 = 1;
+const COND_STOP_ON_FALSE: u8 = 2;
+
+// Radio operation common header
+const OFF_COMMAND_NO: usize = 0;
+const OFF_STATUS: usize = 2;
+const OFF_NEXT_OP: usize = 4;
+const OFF_START_TRIGGER: usize = 12;
+const OFF_CONDITION: usize = 13;
+
+// Data entry status
+const DATA_ENTRY_PENDING: u8 = 0;
+const DATA_ENTRY_FINISHED: u8 = 3;
+
+// Sync words of the proprietary formats: the 802.15.4g SFD of uncoded
+// packets, preceded by a preamble byte, and a 32-bit word for raw packets
+const SYNC_WORD_IEEE802154G: u32 = 0x0055_904E;
+const SYNC_WORD_RAW: u32 = 0x930B_51DE;
+
+// 802.15.4g PHR flag for a 2-byte FCS
+const PHR_FCS_2_BYTES: u8 = 0x10;
+
+// Commands are at most 60 bytes long, and must be 4-byte aligned
+#[repr(C, align(4))]
+struct Command([u8; 64]);
+
+static mut SETUP_CMD: Command = Command([0; 64]);
+static mut FS_CMD: Command = Command([0; 64]);
+static mut RX_CMD: Command = Command([0; 64]);
+static mut TX_CMD: Command = Command([0; 64]);
+
+// Entry of the receive queue. It points to itself, so the queue is circular
+// and the CPE never runs out of entries while a received packet is copied
+// out.
+#[repr(C, align(4))]
+struct DataEntry {
+    next: u32,
+    status: u8,
+    config: u8,
+    length: u16,
+    // 1-byte length field and the packet
+    data: [u8; 1 + radio::MAX_MTU],
+}
+
+#[repr(C, align(4))]
+struct DataQueue {
+    current: u32,
+    last: u32,
+}
+
+static mut RX_ENTRY: DataEntry = DataEntry {
+    next: 0,
+    status: 0,
+    config: 0,
+    length: 0,
+    data: [0; 1 + radio::MAX_MTU],
+};
+static mut RX_QUEUE: DataQueue = DataQueue {
+    current: 0,
+    last: 0,
+};
+
+// Output statistics of the receive commands, which the CPE requires
+static mut RX_OUTPUT: Command = Command([0; 64]);
+
+fn put_u8(cmd: &mut Command, off: usize, val: u8) {
+    cmd.0[off] = val;
+}
+
+fn put_u16(cmd: &mut Command, off: usize, val: u16) {
+    cmd.0[off..off + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(cmd: &mut Command, off: usize, val: u32) {
+    cmd.0[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+// Clears `cmd` and writes its common header
+fn header(cmd: &mut Command, command_no: u16, next: Option<&Command>) {
+    cmd.0 = [0; 64];
+    put_u16(cmd, OFF_COMMAND_NO, command_no);
+    put_u8(cmd, OFF_START_TRIGGER, TRIG_NOW);
+    match next {
+        Some(next) => {
+            put_u32(cmd, OFF_NEXT_OP, address(next));
+            put_u8(cmd, OFF_CONDITION, COND_STOP_ON_FALSE);
+        }
+        None => put_u8(cmd, OFF_CONDITION, COND_NEVER),
+    }
+}
+
+fn address(cmd: &Command) -> u32 {
+    cmd.0.as_ptr() as u32
+}
+
+// The status of a command is updated by the CPE. It is read through a
+// pointer to the 4-byte aligned command, so the 16-bit read is aligned.
+fn status(cmd: &Command) -> u16 {
+    let halfwords = cmd as *const Command as *const u16;
+    unsafe { ptr::read_volatile(halfwords.add(OFF_STATUS / 2)) }
+}
+
+fn finished(status: u16) -> bool {
+    status & (STATUS_DONE | STATUS_ERROR) != 0
+}
+
+// Whether the command completed without error. Radio operations of the
+// IEEE and proprietary command sets report `STATUS_DONE` in the lower 12
+// bits.
+fn succeeded(status: u16) -> bool {
+    status & 0x0fff == STATUS_DONE
+}
+
+/// Settings of the RF core that depend on the chip and the board, as
+/// generated by SmartRF Studio.
+pub struct RfSettings {
+    /// `config` field of the setup commands, which selects the front end and
+    /// bias modes
+    pub frontend_config: u16,
+    /// Register overrides of the O-QPSK PHY, terminated by `0xffffffff`
+    pub ieee_overrides: &'static [u32],
+    /// Register overrides of the FSK and GFSK PHYs, terminated by
+    /// `0xffffffff`
+    pub prop_overrides: &'static [u32],
+    /// TX power settings of the O-QPSK PHY as (dBm, `txPower` value) pairs,
+    /// sorted by increasing power
+    pub ieee_tx_power: &'static [(i8, u16)],
+    /// TX power settings of the FSK and GFSK PHYs
+    pub prop_tx_power: &'static [(i8, u16)],
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RfcState {
+    Off,
+    /// Running the setup chain, which ends with the receive command
+    Setup,
+    /// Powered, but not receiving after a failed setup
+    Idle,
+    Rx,
+    Tx,
+}
+
+pub struct Rfc {
+    dbell: StaticRef<RfcDbellRegisters>,
+    pwr: StaticRef<RfcPwrRegisters>,
+    settings: OptionalCell<&'static RfSettings>,
+    state: Cell<RfcState>,
+    // Whether the setup chain was started by `start`, rather than by
+    // `config_commit`
+    starting: Cell<bool>,
+    config_pending: Cell<bool>,
+    phy: Cell<PhyConfig>,
+    frequency: Cell<u32>,
+    tx_power: Cell<i8>,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+}
+
+pub static mut RFC: Rfc = Rfc::new();
+
+impl Rfc {
+    const fn new() -> Rfc {
+        Rfc {
+            dbell: RFC_DBELL_BASE,
+            pwr: RFC_PWR_BASE,
+            settings: OptionalCell::empty(),
+            state: Cell::new(RfcState::Off),
+            starting: Cell::new(false),
+            config_pending: Cell::new(false),
+            phy: Cell::new(PhyConfig::IEEE802154_OQPSK),
+            frequency: Cell::new(2_405_000),
+            tx_power: Cell::new(0),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            tx_buf: TakeCell::empty(),
+            rx_buf: TakeCell::empty(),
+        }
+    }
+
+    /// Sets the chip- and board-specific settings. They must be set before
+    /// the radio is started.
+    pub fn set_settings(&self, settings: &'static RfSettings) {
+        self.settings.set(settings);
+    }
+
+    fn ieee_mode(&self) -> bool {
+        self.phy.get().modulation == Modulation::Oqpsk
+    }
+
+    fn tx_power_value(&self, settings: &RfSettings) -> Option<(i8, u16)> {
+        let table = if self.ieee_mode() {
+            settings.ieee_tx_power
+        } else {
+            settings.prop_tx_power
+        };
+        // The highest power not above the requested one, or the lowest one
+        table
+            .iter()
+            .rev()
+            .find(|&&(dbm, _)| dbm <= self.tx_power.get())
+            .or_else(|| table.first())
+            .cloned()
+    }
+
+    // Sends a direct command, and waits for the CPE to acknowledge it
+    fn direct_command(&self, command: u16) -> ReturnCode {
+        self.post(((command as u32) << 16) | 1)
+    }
+
+    fn post(&self, cmdr: u32) -> ReturnCode {
+        let regs = &*self.dbell;
+        compiler_fence(Ordering::SeqCst);
+        regs.rfackifg.set(0);
+        regs.cmdr.set(cmdr);
+        while !regs.rfackifg.is_set(Ack::ACKFLAG) {}
+        regs.rfackifg.set(0);
+        if regs.cmdsta.get() & 0xff == CMDSTA_DONE {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
+
+    fn post_command(&self, cmd: &Command) -> ReturnCode {
+        self.post(address(cmd))
+    }
+
+    // Aborts the running radio operations
+    fn abort(&self) {
+        let rx = unsafe { &RX_CMD };
+        if self.direct_command(CMD_ABORT) == ReturnCode::SUCCESS {
+            // Aborting takes a few microseconds
+            for _ in 0..10_000 {
+                if !running(status(rx)) {
+                    break;
+                }
+            }
+        }
+        let regs = &*self.dbell;
+        regs.rfcpeifg.set(0);
+    }
+
+    fn power_on(&self) -> ReturnCode {
+        prcm::Power::enable_domain(prcm::PowerDomain::RFC);
+        prcm::Clock::enable_rfc();
+
+        let pwr = &*self.pwr;
+        pwr.pwmclken.write(
+            ModuleClocks::RFC::SET
+                + ModuleClocks::CPE::SET
+                + ModuleClocks::CPERAM::SET
+                + ModuleClocks::MDM::SET
+                + ModuleClocks::MDMRAM::SET
+                + ModuleClocks::RFE::SET
+                + ModuleClocks::RFERAM::SET
+                + ModuleClocks::RAT::SET
+                + ModuleClocks::PHA::SET
+                + ModuleClocks::FSCA::SET,
+        );
+
+        let regs = &*self.dbell;
+        regs.rfcpeifg.set(0);
+        // All interrupts go to the CPE0 line
+        regs.rfcpeisl.set(0);
+
+        let result = self.direct_command(CMD_PING);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let result = self.direct_command(CMD_START_RAT);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+
+        regs.rfcpeien.write(
+            CpeInterrupt::COMMAND_DONE::SET
+                + CpeInterrupt::LAST_COMMAND_DONE::SET
+                + CpeInterrupt::FG_COMMAND_DONE::SET
+                + CpeInterrupt::TX_DONE::SET
+                + CpeInterrupt::RX_ENTRY_DONE::SET
+                + CpeInterrupt::SYNTH_NO_LOCK::SET
+                + CpeInterrupt::INTERNAL_ERROR::SET,
+        );
+        ReturnCode::SUCCESS
+    }
+
+    fn power_off(&self) {
+        let regs = &*self.dbell;
+        regs.rfcpeien.set(0);
+        regs.rfcpeifg.set(0);
+        let pwr = &*self.pwr;
+        pwr.pwmclken.set(0);
+        prcm::Clock::disable_rfc();
+        prcm::Power::disable_domain(prcm::PowerDomain::RFC);
+    }
+
+    // Fills the setup, frequency synthesizer and receive commands for the
+    // current configuration, and starts them.
+    fn run_setup(&self) -> ReturnCode {
+        let settings = match self.settings.map(|settings| *settings) {
+            Some(settings) => settings,
+            None => return ReturnCode::EOFF,
+        };
+        let (lo_divider, frequency) = match lo_divider(self.frequency.get()) {
+            Some(lo_divider) => (lo_divider, self.frequency.get()),
+            None => return ReturnCode::EINVAL,
+        };
+        if self.ieee_mode() != (lo_divider == 0) {
+            // O-QPSK is only available in the 2.4 GHz band
+            return ReturnCode::EINVAL;
+        }
+        let tx_power = match self.tx_power_value(settings) {
+            Some((_, value)) => value,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+
+        let (setup, fs, rx) = unsafe { (&mut SETUP_CMD, &mut FS_CMD, &mut RX_CMD) };
+        if self.ieee_mode() {
+            header(setup, CMD_RADIO_SETUP, Some(fs));
+            // IEEE 802.15.4 mode
+            put_u8(setup, 14, 0x01);
+            put_u8(setup, 15, 0);
+            put_u16(setup, 16, settings.frontend_config);
+            put_u16(setup, 18, tx_power);
+            put_u32(setup, 20, overrides(settings.ieee_overrides));
+        } else {
+            let phy = self.phy.get();
+            let (mod_type, deviation) = match phy.modulation {
+                Modulation::Fsk { deviation } => (0, deviation),
+                Modulation::Gfsk { deviation } => (1, deviation),
+                Modulation::Oqpsk => (0, 0),
+            };
+            header(setup, CMD_PROP_RADIO_DIV_SETUP, Some(fs));
+            // Deviation in steps of 250 Hz
+            put_u16(
+                setup,
+                14,
+                mod_type | (((deviation / 250) as u16 & 0x7ff) << 3),
+            );
+            // Symbol rate of (rateWord * 24 MHz) / (preScale * 2^20)
+            let rate_word = (phy.data_rate as u64 * 15 * (1 << 20) / 24_000_000) as u32;
+            put_u32(setup, 16, 15 | ((rate_word & 0x1f_ffff) << 8));
+            put_u8(setup, 20, rx_bandwidth(phy.data_rate, deviation));
+            let (preamble_bytes, sync_word_bits) = match phy.packet_format {
+                PacketFormat::Ieee802154g => (7, 24),
+                _ => (4, 32),
+            };
+            put_u8(setup, 21, preamble_bytes);
+            // Sync word and length sent most significant bit first
+            put_u16(setup, 22, sync_word_bits | (1 << 7));
+            put_u16(setup, 24, settings.frontend_config);
+            put_u16(setup, 26, tx_power);
+            put_u32(setup, 28, overrides(settings.prop_overrides));
+            put_u16(setup, 32, (frequency / 1000) as u16);
+            // Default intermediate frequency
+            put_u16(setup, 34, 0x8000);
+            put_u8(setup, 36, lo_divider);
+        }
+
+        header(fs, CMD_FS, Some(rx));
+        put_u16(fs, 14, (frequency / 1000) as u16);
+        put_u16(fs, 16, ((frequency % 1000) * 65536 / 1000) as u16);
+
+        self.prepare_rx();
+        self.state.set(RfcState::Setup);
+        let result = self.post_command(setup);
+        if result != ReturnCode::SUCCESS {
+            self.state.set(RfcState::Idle);
+        }
+        result
+    }
+
+    fn prepare_rx(&self) {
+        let (rx, output, entry, queue) =
+            unsafe { (&mut RX_CMD, &RX_OUTPUT, &mut RX_ENTRY, &mut RX_QUEUE) };
+        entry.next = entry as *const DataEntry as u32;
+        entry.status = DATA_ENTRY_PENDING;
+        // General entry with a 1-byte length field
+        entry.config = 1 << 2;
+        entry.length = entry.data.len() as u16;
+        queue.current = entry as *const DataEntry as u32;
+        queue.last = 0;
+        let queue = queue as *const DataQueue as u32;
+
+        if self.ieee_mode() {
+            header(rx, CMD_IEEE_RX, None);
+            // Channel 0: use the frequency of CMD_FS
+            put_u8(rx, 14, 0);
+            // Flush packets with CRC errors and ignored packets
+            put_u8(rx, 15, 0x03);
+            put_u32(rx, 16, queue);
+            put_u32(rx, 20, address(output));
+            // No frame filtering, no automatic ACKs, no CCA
+            put_u16(rx, 24, 0);
+            put_u8(rx, 26, 0);
+            put_u8(rx, 27, 0);
+            put_u8(rx, 28, -90i8 as u8);
+            put_u8(rx, 55, TRIG_NEVER);
+        } else {
+            let format = self.phy.get().packet_format;
+            header(rx, CMD_PROP_RX_ADV, None);
+            // Keep receiving after good and bad packets, with CRC
+            put_u8(rx, 14, (1 << 1) | (1 << 2) | (1 << 3));
+            // Flush ignored packets and packets with CRC errors
+            put_u8(rx, 15, 0x03);
+            put_u32(rx, 16, sync_word(format));
+            put_u16(rx, 24, radio::MAX_MTU as u16);
+            match format {
+                PacketFormat::Ieee802154g => {
+                    // 16-bit PHR whose 11 lowest bits are the length,
+                    // including the 2-byte FCS
+                    put_u16(rx, 26, 16 | (11 << 11));
+                    put_u8(rx, 30, -2i8 as u8);
+                }
+                _ => {
+                    put_u16(rx, 26, 8 | (8 << 11));
+                    put_u8(rx, 30, 0);
+                }
+            }
+            put_u8(rx, 31, TRIG_NEVER);
+            put_u32(rx, 40, queue);
+            put_u32(rx, 44, address(output));
+        }
+    }
+
+    fn setup_done(&self, result: ReturnCode) {
+        if result == ReturnCode::SUCCESS {
+            self.state.set(RfcState::Rx);
+        } else {
+            self.state.set(RfcState::Idle);
+        }
+        if self.starting.get() {
+            self.starting.set(false);
+            self.power_client.map(|client| client.changed(true));
+        } else {
+            self.config_client.map(|client| client.config_done(result));
+        }
+    }
+
+    fn tx_done(&self, result: ReturnCode) {
+        self.state.set(RfcState::Rx);
+        if self.config_pending.get() {
+            self.config_pending.set(false);
+            self.abort();
+            let result = self.run_setup();
+            if result != ReturnCode::SUCCESS {
+                self.config_client.map(|client| client.config_done(result));
+            }
+        }
+        self.tx_buf.take().map(|buf| {
+            self.tx_client
+                .map(move |client| client.send_done(buf, false, result));
+        });
+    }
+
+    fn rx_entry_done(&self) {
+        let entry = unsafe { &mut RX_ENTRY };
+        let entry_status = unsafe { ptr::read_volatile(&entry.status) };
+        if entry_status != DATA_ENTRY_FINISHED {
+            return;
+        }
+        let len = entry.data[0] as usize;
+        let received = self.rx_buf.take().and_then(|buf| {
+            if len > radio::MAX_MTU - radio::MFR_SIZE || radio::PSDU_OFFSET + len > buf.len() {
+                self.rx_buf.replace(buf);
+                return None;
+            }
+            buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len]
+                .copy_from_slice(&entry.data[1..1 + len]);
+            buf[1] = (len + radio::MFR_SIZE) as u8;
+            Some(buf)
+        });
+        unsafe { ptr::write_volatile(&mut entry.status, DATA_ENTRY_PENDING) };
+
+        // Packets with CRC errors are flushed by the CPE
+        received.map(|buf| {
+            self.rx_client
+                .map(move |client| client.receive(buf, len, true, ReturnCode::SUCCESS));
+        });
+    }
+
+    fn command_done(&self, flags: u32) {
+        let chain_ended = flags & CpeInterrupt::LAST_COMMAND_DONE::SET.value != 0;
+        let (fs, rx, tx) = unsafe { (&FS_CMD, &RX_CMD, &TX_CMD) };
+        match self.state.get() {
+            RfcState::Setup => {
+                if succeeded(status(fs)) {
+                    self.setup_done(ReturnCode::SUCCESS);
+                } else if chain_ended || finished(status(fs)) {
+                    self.setup_done(ReturnCode::FAIL);
+                }
+            }
+            RfcState::Tx => {
+                let tx_status = status(tx);
+                if finished(tx_status) {
+                    let result = if succeeded(tx_status) {
+                        ReturnCode::SUCCESS
+                    } else {
+                        ReturnCode::FAIL
+                    };
+                    self.tx_done(result);
+                }
+            }
+            RfcState::Rx => {
+                if chain_ended && finished(status(rx)) {
+                    // Reception stopped on an error, start over
+                    let result = self.run_setup();
+                    if result != ReturnCode::SUCCESS {
+                        self.state.set(RfcState::Idle);
+                    }
+                }
+            }
+            RfcState::Off | RfcState::Idle => {}
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.dbell;
+        let flags = regs.rfcpeifg.get() & regs.rfcpeien.get();
+        regs.rfcpeifg.set(!flags);
+
+        if flags & CpeInterrupt::RX_ENTRY_DONE::SET.value != 0 {
+            self.rx_entry_done();
+        }
+        let done = CpeInterrupt::COMMAND_DONE::SET
+            + CpeInterrupt::LAST_COMMAND_DONE::SET
+            + CpeInterrupt::FG_COMMAND_DONE::SET
+            + CpeInterrupt::TX_DONE::SET
+            + CpeInterrupt::SYNTH_NO_LOCK::SET
+            + CpeInterrupt::INTERNAL_ERROR::SET;
+        if flags & done.value != 0 {
+            self.command_done(flags);
+        }
+    }
+}
+
+// Whether a command is still pending or running
+fn running(status: u16) -> bool {
+    status == 0x0001 || status == 0x0002
+}
+
+fn overrides(list: &'static [u32]) -> u32 {
+    if list.is_empty() {
+        0
+    } else {
+        list.as_ptr() as u32
+    }
+}
+
+fn sync_word(format: PacketFormat) -> u32 {
+    match format {
+        PacketFormat::Ieee802154g => SYNC_WORD_IEEE802154G,
+        _ => SYNC_WORD_RAW,
+    }
+}
+
+// Divider of the synthesizer for `frequency` in kHz, 0 in the 2.4 GHz band,
+// or `None` if the RF core cannot tune to it
+fn lo_divider(frequency: u32) -> Option<u8> {
+    match frequency {
+        359_000..=527_000 => Some(10),
+        779_000..=1_054_000 => Some(5),
+        2_360_000..=2_500_000 => Some(0),
+        _ => None,
+    }
+}
+
+// Receiver bandwidth setting for the Carson bandwidth of the signal, as
+// chosen by SmartRF Studio
+fn rx_bandwidth(data_rate: u32, deviation: u32) -> u8 {
+    let bandwidth = data_rate + 2 * deviation;
+    if bandwidth <= 98_000 {
+        0x52
+    } else if bandwidth <= 311_000 {
+        0x59
+    } else {
+        0x64
+    }
+}
+
+impl radio::MultiPhyRadio for Rfc {}
+
+impl radio::MultiPhyConfig for Rfc {
+    fn start(&self) -> ReturnCode {
+        if self.state.get() != RfcState::Off {
+            return ReturnCode::EALREADY;
+        } else if self.settings.is_none() {
+            return ReturnCode::EOFF;
+        }
+        let result = self.power_on();
+        if result != ReturnCode::SUCCESS {
+            self.power_off();
+            return result;
+        }
+        self.starting.set(true);
+        let result = self.run_setup();
+        if result != ReturnCode::SUCCESS {
+            self.starting.set(false);
+            self.power_off();
+            self.state.set(RfcState::Off);
+        }
+        result
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if self.state.get() == RfcState::Off {
+            return ReturnCode::EALREADY;
+        }
+        self.abort();
+        self.power_off();
+        self.state.set(RfcState::Off);
+        self.config_pending.set(false);
+        self.tx_buf.take().map(|buf| {
+            self.tx_client
+                .map(move |client| client.send_done(buf, false, ReturnCode::ECANCEL));
+        });
+        self.power_client.map(|client| client.changed(false));
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.state.get() != RfcState::Off
+    }
+
+    fn busy(&self) -> bool {
+        match self.state.get() {
+            RfcState::Setup | RfcState::Tx => true,
+            _ => false,
+        }
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        match self.state.get() {
+            RfcState::Off => {
+                self.config_client
+                    .map(|client| client.config_done(ReturnCode::SUCCESS));
+            }
+            RfcState::Tx | RfcState::Setup => self.config_pending.set(true),
+            RfcState::Rx | RfcState::Idle => {
+                self.abort();
+                let result = self.run_setup();
+                if result != ReturnCode::SUCCESS {
+                    self.config_client.map(|client| client.config_done(result));
+                }
+            }
+        }
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn set_phy(&self, phy: PhyConfig) -> ReturnCode {
+        let supported = match (phy.modulation, phy.packet_format) {
+            (Modulation::Oqpsk, PacketFormat::Ieee802154) => phy.data_rate == 250_000,
+            (Modulation::Fsk { deviation }, PacketFormat::Ieee802154g)
+            | (Modulation::Fsk { deviation }, PacketFormat::Raw)
+            | (Modulation::Gfsk { deviation }, PacketFormat::Ieee802154g)
+            | (Modulation::Gfsk { deviation }, PacketFormat::Raw) => {
+                phy.data_rate > 0 && phy.data_rate <= 1_000_000 && deviation < 512_000
+            }
+            _ => false,
+        };
+        if supported {
+            self.phy.set(phy);
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::ENOSUPPORT
+        }
+    }
+
+    fn get_phy(&self) -> PhyConfig {
+        self.phy.get()
+    }
+
+    fn set_frequency(&self, frequency: u32) -> ReturnCode {
+        match lo_divider(frequency) {
+            Some(_) => {
+                self.frequency.set(frequency);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn get_frequency(&self) -> u32 {
+        self.frequency.get()
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.tx_power.set(power);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.settings
+            .and_then(|settings| self.tx_power_value(settings))
+            .map_or(self.tx_power.get(), |(dbm, _)| dbm)
+    }
+}
+
+impl radio::RadioData for Rfc {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient, buffer: &'static mut [u8]) {
+        self.rx_client.set(client);
+        self.rx_buf.replace(buffer);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.rx_buf.replace(buffer);
+    }
+
+    fn transmit(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        match self.state.get() {
+            RfcState::Off | RfcState::Idle => return (ReturnCode::EOFF, Some(buf)),
+            RfcState::Setup | RfcState::Tx => return (ReturnCode::EBUSY, Some(buf)),
+            RfcState::Rx => {}
+        }
+        if frame_len + radio::MFR_SIZE > radio::MAX_MTU
+            || radio::PSDU_OFFSET + frame_len > buf.len()
+        {
+            return (ReturnCode::ESIZE, Some(buf));
+        }
+
+        let psdu = unsafe { buf.as_ptr().add(radio::PSDU_OFFSET) } as u32;
+        let result = if self.ieee_mode() {
+            // Sent in the foreground while the receive command runs; the CPE
+            // adds the PHR and FCS
+            let tx = unsafe { &mut TX_CMD };
+            header(tx, CMD_IEEE_TX, None);
+            put_u8(tx, 15, frame_len as u8);
+            put_u32(tx, 16, psdu);
+            self.post_command(tx)
+        } else {
+            // The CPE adds the FCS, and sends the header written in front of
+            // the packet. Reception resumes after the transmission.
+            let format = self.phy.get().packet_format;
+            let header_len = match format {
+                PacketFormat::Ieee802154g => {
+                    let len = frame_len + radio::MFR_SIZE;
+                    buf[0] = PHR_FCS_2_BYTES | ((len >> 8) as u8 & 0x07);
+                    buf[1] = len as u8;
+                    2
+                }
+                _ => {
+                    buf[1] = frame_len as u8;
+                    1
+                }
+            };
+            self.abort();
+            self.prepare_rx();
+            let (tx, rx) = unsafe { (&mut TX_CMD, &RX_CMD) };
+            header(tx, CMD_PROP_TX_ADV, Some(rx));
+            put_u8(tx, OFF_CONDITION, COND_ALWAYS);
+            // With CRC
+            put_u8(tx, 14, 1 << 3);
+            put_u8(tx, 15, header_len as u8 * 8);
+            put_u16(tx, 16, (header_len + frame_len) as u16);
+            put_u8(tx, 19, TRIG_NOW);
+            put_u32(tx, 24, sync_word(format));
+            put_u32(tx, 28, psdu - header_len as u32);
+            self.post_command(tx)
+        };
+
+        if result == ReturnCode::SUCCESS {
+            self.state.set(RfcState::Tx);
+            self.tx_buf.replace(buf);
+            (ReturnCode::SUCCESS, None)
+        } else {
+            (result, Some(buf))
+        }
+    }
+}
@@ -1,9 +1,18 @@
 //! RTC driver
+//!
+//! Besides acting as the alarm, the RTC provides wall-clock time through
+//! `hil::date_time`. The seconds counter is never reset by the kernel, so the
+//! Unix time is kept as an offset from it. Channel 0 and channel 2 are
+//! otherwise unused, and because their compare registers are in the AON
+//! domain, they hold the offset and a marker that it is valid across system
+//! resets.
 
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
+use kernel::hil::date_time::{self, DateTimeValues};
 use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::ReturnCode;
 
 #[repr(C)]
 struct RtcRegisters {
@@ -19,9 +28,9 @@ struct RtcRegisters {
 
     _subsec_inc: ReadOnly<u32>,
     channel_ctl: ReadWrite<u32, ChannelControl::Register>,
-    _channel0_cmp: ReadOnly<u32>,
+    channel0_cmp: ReadWrite<u32>,
     channel1_cmp: ReadWrite<u32>,
-    _channel2_cmp: ReadOnly<u32>,
+    channel2_cmp: ReadWrite<u32>,
     _channel2_cmp_inc: ReadOnly<u32>,
     _channel1_capture: ReadOnly<u32>,
 
@@ -57,6 +66,9 @@ register_bitfields![
     ]
 ];
 
+/// Stored in the channel 2 compare register once the date and time are set.
+const DATE_TIME_MAGIC: u32 = 0xDA7E_71E5;
+
 const RTC_BASE: StaticRef<RtcRegisters> =
     unsafe { StaticRef::new(0x40092000 as *const RtcRegisters) };
 
@@ -198,3 +210,31 @@ impl Alarm<'a> for Rtc<'a> {
         self.is_running()
     }
 }
+
+impl date_time::DateTime for Rtc<'a> {
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode> {
+        let regs = &*self.registers;
+        if regs.channel2_cmp.get() != DATE_TIME_MAGIC {
+            return Err(ReturnCode::EOFF);
+        }
+        let offset = regs.channel0_cmp.get();
+        Ok(DateTimeValues::from_unix_time(
+            offset.wrapping_add(regs.sec.get()),
+        ))
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode {
+        let regs = &*self.registers;
+        let timestamp = match date_time.to_unix_time() {
+            Some(timestamp) => timestamp,
+            None if date_time.is_valid() => return ReturnCode::ESIZE,
+            None => return ReturnCode::EINVAL,
+        };
+
+        regs.channel0_cmp
+            .set(timestamp.wrapping_sub(regs.sec.get()));
+        regs.channel2_cmp.set(DATE_TIME_MAGIC);
+        regs.sync.get();
+        ReturnCode::SUCCESS
+    }
+}
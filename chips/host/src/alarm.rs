@@ -0,0 +1,96 @@
+//! Alarm driven by the monotonic clock of the host.
+//!
+//! The counter runs at 32 kHz from the moment the alarm is created and wraps
+//! like a 32-bit hardware counter does.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Frequency, Time};
+use std::time::{Duration, Instant};
+
+pub struct Alarm<'a> {
+    start: Instant,
+    alarm: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> Alarm<'a> {
+    pub fn new() -> Alarm<'a> {
+        Alarm {
+            start: Instant::now(),
+            alarm: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    // Tics until the alarm, or 0 if the counter has already passed it. An
+    // alarm half a counter period away, as the alarm mux sets to keep track
+    // of wraps, is in the future.
+    fn tics_to_alarm(&self) -> u32 {
+        let delta = self.alarm.get().wrapping_sub(self.now());
+        if delta > (1 << 31) {
+            0
+        } else {
+            delta
+        }
+    }
+
+    /// Whether the counter has reached the armed alarm.
+    pub fn is_pending(&self) -> bool {
+        self.armed.get() && self.tics_to_alarm() == 0
+    }
+
+    /// Time left until the alarm fires, if it is armed.
+    pub fn time_to_alarm(&self) -> Option<Duration> {
+        if self.armed.get() {
+            let nanos = self.tics_to_alarm() as u64 * 1_000_000_000
+                / <Self as Time>::Frequency::frequency() as u64;
+            Some(Duration::from_nanos(nanos))
+        } else {
+            None
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        self.armed.set(false);
+        self.client.map(|client| client.fired());
+    }
+}
+
+impl Time for Alarm<'_> {
+    type Frequency = time::Freq32KHz;
+
+    fn now(&self) -> u32 {
+        let nanos = self.start.elapsed().as_nanos();
+        (nanos * <Self as Time>::Frequency::frequency() as u128 / 1_000_000_000) as u32
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl<'a> time::Alarm<'a> for Alarm<'a> {
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(tics);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn disable(&self) {
+        self.armed.set(false);
+    }
+}
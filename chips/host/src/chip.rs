@@ -0,0 +1,140 @@
+//! Chip trait setup for running the kernel as a Linux process.
+
+use crate::alarm::Alarm;
+use crate::deferred_call_tasks::DeferredCallTask;
+use crate::flash::Flash;
+use crate::radio::Radio;
+use crate::syscall;
+use crate::uart::Uart;
+use core::cell::Cell;
+use core::fmt::Write;
+use kernel::common::cells::OptionalCell;
+use kernel::common::deferred_call;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time the chip sleeps before it polls the UART and the radio
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+pub struct Host {
+    userspace_kernel_boundary: syscall::SysCall,
+    started: Instant,
+    alarm: &'static Alarm<'static>,
+    uart: &'static Uart<'static>,
+    flash: OptionalCell<&'static Flash<'static>>,
+    radio: OptionalCell<&'static Radio>,
+    exit_at: Cell<Option<Instant>>,
+}
+
+impl Host {
+    pub fn new(alarm: &'static Alarm<'static>, uart: &'static Uart<'static>) -> Host {
+        Host {
+            userspace_kernel_boundary: syscall::SysCall::new(),
+            started: Instant::now(),
+            alarm: alarm,
+            uart: uart,
+            flash: OptionalCell::empty(),
+            radio: OptionalCell::empty(),
+            exit_at: Cell::new(None),
+        }
+    }
+
+    pub fn set_flash(&self, flash: &'static Flash<'static>) {
+        self.flash.set(flash);
+    }
+
+    pub fn set_radio(&self, radio: &'static Radio) {
+        self.radio.set(radio);
+    }
+
+    /// Makes the process exit successfully once `duration` has passed, so
+    /// that a test can run the kernel for a bounded time.
+    pub fn exit_after(&self, duration: Duration) {
+        self.exit_at.set(Some(Instant::now() + duration));
+    }
+
+    fn check_exit(&self) {
+        if let Some(exit_at) = self.exit_at.get() {
+            if Instant::now() >= exit_at {
+                process::exit(0);
+            }
+        }
+    }
+}
+
+impl kernel::Chip for Host {
+    type MPU = ();
+    type UserspaceKernelBoundary = syscall::SysCall;
+    type SysTick = ();
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn systick(&self) -> &Self::SysTick {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &Self::UserspaceKernelBoundary {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        loop {
+            if let Some(task) = deferred_call::DeferredCall::next_pending() {
+                match task {
+                    DeferredCallTask::Uart => self.uart.handle_deferred_call(),
+                    DeferredCallTask::Flash => {
+                        self.flash.map(|flash| flash.handle_deferred_call());
+                    }
+                    DeferredCallTask::Radio => {
+                        self.radio.map(|radio| radio.handle_deferred_call());
+                    }
+                }
+            } else if self.alarm.is_pending() {
+                self.alarm.handle_interrupt();
+            } else if self.uart.is_pending() {
+                self.uart.handle_interrupt();
+            } else if self.radio.map_or(false, |radio| radio.is_pending()) {
+                self.radio.map(|radio| radio.handle_interrupt());
+            } else {
+                break;
+            }
+        }
+        self.check_exit();
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        deferred_call::has_tasks()
+            || self.alarm.is_pending()
+            || self.uart.is_pending()
+            || self.radio.map_or(false, |radio| radio.is_pending())
+    }
+
+    fn sleep(&self) {
+        let timeout = self
+            .alarm
+            .time_to_alarm()
+            .map_or(POLL_INTERVAL, |left| core::cmp::min(left, POLL_INTERVAL));
+        thread::sleep(timeout);
+        self.check_exit();
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Nothing interrupts the kernel: events are only picked up when it
+        // services interrupts
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Host State |---\r\nUptime: {:?}\r\nAlarm armed: {}\r\n",
+            self.started.elapsed(),
+            self.alarm.time_to_alarm().is_some()
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+//! Flash backed by a file.
+//!
+//! Page `n` is stored at offset `n * PAGE_SIZE` of the file, which is
+//! created, or extended, with erased pages as needed. Like NOR flash, a
+//! write can only clear bits, so pages have to be erased before they are
+//! rewritten with arbitrary data. Operations are carried out on the file
+//! straight away and complete from a deferred call.
+
+use crate::deferred_call_tasks::DeferredCallTask;
+use core::cell::{Cell, RefCell};
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::DeferredCall;
+use kernel::hil;
+use kernel::ReturnCode;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
+    unsafe { DeferredCall::new(DeferredCallTask::Flash) };
+
+pub const PAGE_SIZE: usize = 512;
+
+const ERASED: u8 = 0xff;
+
+pub struct HostPage(pub [u8; PAGE_SIZE]);
+
+impl Default for HostPage {
+    fn default() -> Self {
+        Self { 0: [0; PAGE_SIZE] }
+    }
+}
+
+impl HostPage {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Index<usize> for HostPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for HostPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for HostPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct Flash<'a> {
+    file: RefCell<File>,
+    pages: usize,
+    client: OptionalCell<&'a dyn hil::flash::Client<Flash<'a>>>,
+    buffer: TakeCell<'static, HostPage>,
+    operation: Cell<Option<Operation>>,
+    error: Cell<hil::flash::Error>,
+}
+
+impl<'a> Flash<'a> {
+    /// Opens the flash image at `path`, with room for `pages` pages.
+    pub fn new(path: &Path, pages: usize) -> io::Result<Flash<'a>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let len = file.seek(SeekFrom::End(0))? as usize;
+        if len < pages * PAGE_SIZE {
+            file.write_all(&vec![ERASED; pages * PAGE_SIZE - len])?;
+        }
+        Ok(Flash {
+            file: RefCell::new(file),
+            pages: pages,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(None),
+            error: Cell::new(hil::flash::Error::CommandComplete),
+        })
+    }
+
+    /// Number of pages of the flash.
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn handle_deferred_call(&self) {
+        let error = self.error.get();
+        match self.operation.take() {
+            Some(Operation::Read) => {
+                self.buffer.take().map(|buf| {
+                    self.client
+                        .map(move |client| client.read_complete(buf, error));
+                });
+            }
+            Some(Operation::Write) => {
+                self.buffer.take().map(|buf| {
+                    self.client
+                        .map(move |client| client.write_complete(buf, error));
+                });
+            }
+            Some(Operation::Erase) => {
+                self.client.map(|client| client.erase_complete(error));
+            }
+            None => {}
+        }
+    }
+
+    fn read_file(&self, page_number: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((page_number * PAGE_SIZE) as u64))?;
+        file.read_exact(buf)
+    }
+
+    fn write_file(&self, page_number: usize, buf: &[u8]) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((page_number * PAGE_SIZE) as u64))?;
+        file.write_all(buf)?;
+        file.flush()
+    }
+
+    // Checks that the flash is idle and the page exists
+    fn start(&self, page_number: usize, operation: Operation) -> ReturnCode {
+        if self.operation.get().is_some() {
+            ReturnCode::EBUSY
+        } else if page_number >= self.pages {
+            ReturnCode::EINVAL
+        } else {
+            self.operation.set(Some(operation));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn complete(&self, result: io::Result<()>) {
+        self.error.set(match result {
+            Ok(()) => hil::flash::Error::CommandComplete,
+            Err(_) => hil::flash::Error::FlashError,
+        });
+        DEFERRED_CALL.set();
+    }
+}
+
+impl<'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C> for Flash<'a> {
+    fn set_client(&'a self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl hil::flash::Flash for Flash<'_> {
+    type Page = HostPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        let rc = self.start(page_number, Operation::Read);
+        if rc != ReturnCode::SUCCESS {
+            return Err((rc, buf));
+        }
+        self.complete(self.read_file(page_number, &mut buf.0));
+        self.buffer.replace(buf);
+        Ok(())
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        let rc = self.start(page_number, Operation::Write);
+        if rc != ReturnCode::SUCCESS {
+            return Err((rc, buf));
+        }
+        let mut page = HostPage::default();
+        let result = self.read_file(page_number, &mut page.0).and_then(|()| {
+            // Programming clears bits but never sets them
+            for i in 0..page.len() {
+                page[i] &= buf[i];
+            }
+            self.write_file(page_number, &page.0)
+        });
+        self.complete(result);
+        self.buffer.replace(buf);
+        Ok(())
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        let rc = self.start(page_number, Operation::Erase);
+        if rc == ReturnCode::SUCCESS {
+            self.complete(self.write_file(page_number, &[ERASED; PAGE_SIZE]));
+        }
+        rc
+    }
+}
@@ -0,0 +1,17 @@
+//! Peripheral implementations for running Tock as a Linux process.
+//!
+//! Unlike the other chips, this crate uses the standard library: the
+//! "peripherals" are the monotonic clock, stdio or a terminal, a file and
+//! UNIX sockets of the host. Events that would be interrupts on hardware are
+//! polled for by the chip whenever the kernel checks for pending interrupts.
+
+#![crate_name = "host_chip"]
+#![crate_type = "rlib"]
+
+pub mod alarm;
+pub mod chip;
+mod deferred_call_tasks;
+pub mod flash;
+pub mod radio;
+pub mod syscall;
+pub mod uart;
@@ -0,0 +1,303 @@
+//! IEEE 802.15.4 radio carrying frames over UNIX datagram sockets.
+//!
+//! Every radio binds a socket `<name>.sock` in a directory shared by the
+//! nodes of a network, which plays the part of the air: a transmitted frame
+//! is sent to every other socket in the directory, and received by the
+//! radios that are on and listening on the same channel. Each datagram holds
+//! the channel followed by the frame, without its FCS.
+//!
+//! Frames are never acknowledged, so transmissions that request an
+//! acknowledgement complete with `acked` false.
+
+use crate::deferred_call_tasks::DeferredCallTask;
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::DeferredCall;
+use kernel::hil::radio;
+use kernel::ReturnCode;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
+    unsafe { DeferredCall::new(DeferredCallTask::Radio) };
+
+const SOCKET_EXTENSION: &str = "sock";
+
+pub struct Radio {
+    socket: UnixDatagram,
+    dir: PathBuf,
+    path: PathBuf,
+    on: Cell<bool>,
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+    // A received datagram waiting for the receive buffer
+    rx_frame: RefCell<[u8; 1 + radio::MAX_FRAME_SIZE]>,
+    rx_len: Cell<Option<usize>>,
+    // Callbacks to issue from the deferred call
+    power_pending: Cell<bool>,
+    config_pending: Cell<bool>,
+    tx_pending: Cell<bool>,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+}
+
+impl Radio {
+    /// Attaches a radio called `name` to the network in `dir`.
+    pub fn new(dir: &Path, name: &str) -> io::Result<Radio> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(name).with_extension(SOCKET_EXTENSION);
+        // A socket left behind by an earlier run of the same node
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Radio {
+            socket: socket,
+            dir: dir.to_path_buf(),
+            path: path,
+            on: Cell::new(false),
+            addr: Cell::new(0),
+            addr_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            channel: Cell::new(26),
+            tx_power: Cell::new(0),
+            tx_buf: TakeCell::empty(),
+            rx_buf: TakeCell::empty(),
+            rx_frame: RefCell::new([0; 1 + radio::MAX_FRAME_SIZE]),
+            rx_len: Cell::new(None),
+            power_pending: Cell::new(false),
+            config_pending: Cell::new(false),
+            tx_pending: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+        })
+    }
+
+    /// Whether a received frame is waiting to be handed to the client.
+    pub fn is_pending(&self) -> bool {
+        if self.rx_len.get().is_none() {
+            let mut rx_frame = self.rx_frame.borrow_mut();
+            // Frames that arrive while the radio is off or listening on
+            // another channel are lost
+            while let Ok(len) = self.socket.recv(&mut rx_frame[..]) {
+                if self.on.get() && len > 1 && rx_frame[0] == self.channel.get() {
+                    self.rx_len.set(Some(len - 1));
+                    break;
+                }
+            }
+        }
+        self.rx_len.get().is_some() && self.rx_buf.is_some()
+    }
+
+    pub fn handle_interrupt(&self) {
+        let len = match self.rx_len.take() {
+            Some(len) => len,
+            None => return,
+        };
+        match self.rx_buf.take() {
+            Some(buf) if buf.len() >= radio::PSDU_OFFSET + len => {
+                let rx_frame = self.rx_frame.borrow();
+                buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len]
+                    .copy_from_slice(&rx_frame[1..1 + len]);
+                buf[1] = (len + radio::MFR_SIZE) as u8;
+                drop(rx_frame);
+                self.rx_client.map(move |client| {
+                    client.receive(buf, len, true, ReturnCode::SUCCESS);
+                });
+            }
+            buf => {
+                buf.map(|buf| self.rx_buf.replace(buf));
+            }
+        }
+    }
+
+    pub fn handle_deferred_call(&self) {
+        if self.power_pending.take() {
+            let on = self.on.get();
+            self.power_client.map(|client| client.changed(on));
+        }
+        if self.config_pending.take() {
+            self.config_client
+                .map(|client| client.config_done(ReturnCode::SUCCESS));
+        }
+        if self.tx_pending.take() {
+            self.tx_buf.take().map(|buf| {
+                self.tx_client.map(move |client| {
+                    client.send_done(buf, false, ReturnCode::SUCCESS);
+                });
+            });
+        }
+    }
+
+    // Sends the frame to every other radio of the network
+    fn broadcast(&self, frame: &[u8]) -> io::Result<()> {
+        let mut datagram = [0; 1 + radio::MAX_FRAME_SIZE];
+        datagram[0] = self.channel.get();
+        datagram[1..1 + frame.len()].copy_from_slice(frame);
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path != self.path
+                && path
+                    .extension()
+                    .map_or(false, |ext| ext == SOCKET_EXTENSION)
+            {
+                // Nodes that are gone leave their socket behind
+                let _ = self.socket.send_to(&datagram[..1 + frame.len()], &path);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl radio::Radio for Radio {}
+
+impl radio::RadioConfig for Radio {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.stop()
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.on.set(true);
+        self.power_pending.set(true);
+        DEFERRED_CALL.set();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.on.set(false);
+        self.power_pending.set(true);
+        DEFERRED_CALL.set();
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_buf.is_some()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.config_pending.set(true);
+        DEFERRED_CALL.set();
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.tx_power.set(power);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan >= 11 && chan <= 26 {
+            self.channel.set(chan);
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+}
+
+impl radio::RadioData for Radio {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'static dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.rx_client.set(client);
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.on.get() {
+            return (ReturnCode::EOFF, Some(spi_buf));
+        } else if self.tx_buf.is_some() {
+            return (ReturnCode::EBUSY, Some(spi_buf));
+        } else if frame_len + radio::MFR_SIZE > radio::MAX_FRAME_SIZE
+            || radio::PSDU_OFFSET + frame_len > spi_buf.len()
+        {
+            return (ReturnCode::ESIZE, Some(spi_buf));
+        }
+        if self
+            .broadcast(&spi_buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len])
+            .is_err()
+        {
+            return (ReturnCode::FAIL, Some(spi_buf));
+        }
+        self.tx_buf.replace(spi_buf);
+        self.tx_pending.set(true);
+        DEFERRED_CALL.set();
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -0,0 +1,68 @@
+//! Kernel-userland boundary of the host chip.
+//!
+//! Processes are compiled for the instruction set of a microcontroller, and
+//! the host cannot run them, so the host kernel runs without processes:
+//! `initialize_process` fails, and processes never get to run.
+
+use core::fmt::Write;
+use kernel::procs;
+use kernel::syscall::{self, ContextSwitchReason};
+
+#[derive(Default)]
+pub struct HostStoredState {}
+
+pub struct SysCall();
+
+impl SysCall {
+    pub const fn new() -> SysCall {
+        SysCall()
+    }
+}
+
+impl syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    unsafe fn initialize_process(
+        &self,
+        _stack_pointer: *const usize,
+        _stack_size: usize,
+        _state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        Err(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &mut Self::StoredState,
+        _return_value: isize,
+    ) {
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        _state: &mut Self::StoredState,
+        _callback: procs::FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        Err(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        _state: &mut Self::StoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        (stack_pointer as *mut usize, ContextSwitchReason::Fault)
+    }
+
+    unsafe fn print_context(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!("\r\n(no process context on the host)\r\n"));
+    }
+}
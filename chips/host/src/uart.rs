@@ -0,0 +1,231 @@
+//! UART backed by the standard input and output of the process, or by a
+//! terminal device such as the slave side of a pty.
+//!
+//! Transmissions are written out immediately and complete from a deferred
+//! call. Received bytes are read by a background thread, and handed to the
+//! client when the kernel services interrupts.
+
+use crate::deferred_call_tasks::DeferredCallTask;
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::DeferredCall;
+use kernel::hil::uart;
+use kernel::ReturnCode;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
+    unsafe { DeferredCall::new(DeferredCallTask::Uart) };
+
+pub struct Uart<'a> {
+    output: RefCell<Box<dyn Write>>,
+    input: Receiver<u8>,
+    rx_bytes: RefCell<VecDeque<u8>>,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    rx_aborted: Cell<bool>,
+}
+
+// Forwards everything read from `input` to the returned channel
+fn spawn_reader<R: Read + Send + 'static>(mut input: R) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 64];
+        loop {
+            match input.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    for &byte in &buf[..n] {
+                        if sender.send(byte).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    receiver
+}
+
+impl<'a> Uart<'a> {
+    fn with_io(output: Box<dyn Write>, input: Receiver<u8>) -> Uart<'a> {
+        Uart {
+            output: RefCell::new(output),
+            input: input,
+            rx_bytes: RefCell::new(VecDeque::new()),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_aborted: Cell::new(false),
+        }
+    }
+
+    /// A UART on the standard input and output of the process.
+    pub fn new_stdio() -> Uart<'a> {
+        Uart::with_io(Box::new(io::stdout()), spawn_reader(io::stdin()))
+    }
+
+    /// A UART on the terminal device, or other file, at `path`.
+    pub fn new(path: &Path) -> io::Result<Uart<'a>> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let input = spawn_reader(file.try_clone()?);
+        Ok(Uart::with_io(Box::new(file), input))
+    }
+
+    /// Whether received bytes are waiting for a pending receive.
+    pub fn is_pending(&self) -> bool {
+        if self.rx_buffer.is_none() {
+            return false;
+        }
+        let mut rx_bytes = self.rx_bytes.borrow_mut();
+        rx_bytes.extend(self.input.try_iter());
+        !rx_bytes.is_empty()
+    }
+
+    pub fn handle_interrupt(&self) {
+        let done = self.rx_buffer.map_or(false, |buf| {
+            let mut rx_bytes = self.rx_bytes.borrow_mut();
+            let mut index = self.rx_index.get();
+            while index < self.rx_len.get() {
+                match rx_bytes.pop_front() {
+                    Some(byte) => {
+                        buf[index] = byte;
+                        index += 1;
+                    }
+                    None => break,
+                }
+            }
+            self.rx_index.set(index);
+            index == self.rx_len.get()
+        });
+        if done {
+            self.receive_done(ReturnCode::SUCCESS, uart::Error::None);
+        }
+    }
+
+    pub fn handle_deferred_call(&self) {
+        if let Some(buf) = self.tx_buffer.take() {
+            self.tx_client.map(move |client| {
+                client.transmitted_buffer(buf, self.tx_len.get(), ReturnCode::SUCCESS);
+            });
+        }
+        if self.rx_aborted.get() {
+            self.rx_aborted.set(false);
+            self.receive_done(ReturnCode::ECANCEL, uart::Error::Aborted);
+        }
+    }
+
+    fn receive_done(&self, rval: ReturnCode, error: uart::Error) {
+        if let Some(buf) = self.rx_buffer.take() {
+            self.rx_client.map(move |client| {
+                client.received_buffer(buf, self.rx_index.get(), rval, error);
+            });
+        }
+    }
+}
+
+impl<'a> uart::Configure for Uart<'a> {
+    fn configure(&self, params: uart::Parameters) -> ReturnCode {
+        if params.baud_rate == 0 {
+            ReturnCode::EINVAL
+        } else {
+            // Bytes are passed through whatever the line settings
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        } else if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        let mut output = self.output.borrow_mut();
+        if output
+            .write_all(&tx_buffer[..tx_len])
+            .and_then(|()| output.flush())
+            .is_err()
+        {
+            return (ReturnCode::FAIL, Some(tx_buffer));
+        }
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        DEFERRED_CALL.set();
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        // Transmissions complete as soon as they start
+        if self.tx_buffer.is_some() {
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        } else if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.rx_buffer.is_some() {
+            self.rx_aborted.set(true);
+            DEFERRED_CALL.set();
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::UartData<'a> for Uart<'a> {}
+impl<'a> uart::Uart<'a> for Uart<'a> {}
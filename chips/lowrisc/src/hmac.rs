@@ -266,3 +266,15 @@ impl hil::digest::HMACSha256 for Hmac<'a> {
         Ok(())
     }
 }
+
+impl hil::digest::Sha256 for Hmac<'a> {
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        let regs = self.registers;
+
+        // Plain SHA256, the key registers are ignored
+        regs.cfg
+            .write(CFG::ENDIAN_SWAP::SET + CFG::SHA_EN::SET + CFG::DIGEST_SWAP::SET);
+
+        Ok(())
+    }
+}
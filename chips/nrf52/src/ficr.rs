@@ -304,6 +304,12 @@ impl Ficr {
         }
     }
 
+    /// The 64 bit unique device identifier
+    pub fn id(&self) -> u64 {
+        let regs = &*self.registers;
+        (regs.deviceid1.get() as u64) << 32 | regs.deviceid0.get() as u64
+    }
+
     fn part(&self) -> Part {
         let regs = &*self.registers;
         match regs.info_part.get() {
@@ -1,12 +1,15 @@
 //! Implementation of I2C for nRF52 using EasyDMA.
 //!
-//! This module supports nRF52's two I2C master (`TWIM`) peripherals,
-//! but not I2C slave (`TWIS`).
+//! This module supports nRF52's two I2C master (`TWIM`) peripherals. The I2C
+//! slave (`TWIS`) peripherals are in the `twis` module, and `TWIMasterSlave`
+//! combines the two for drivers that need both.
 //!
 //! - Author: Jay Kickliter
 //! - Author: Andrew Thompson
 //! - Date: Nov 4, 2017
 
+use crate::twis::TWIS;
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
 use kernel::common::cells::VolatileCell;
@@ -208,6 +211,114 @@ pub static mut TWIM0: TWIM = TWIM::new(INSTANCES[0]);
 /// I2C master instace 1.
 pub static mut TWIM1: TWIM = TWIM::new(INSTANCES[1]);
 
+/// An I2C peripheral that can act as both master and slave.
+///
+/// `TWIM` and `TWIS` of the same instance share their registers, so only one
+/// of them can be enabled at a time. Master operations enable the `TWIM`,
+/// and `listen` re-enables the `TWIS` once the master transfer is done.
+///
+/// The `TWIMasterSlave` must be set as the client of its `TWIM`.
+pub struct TWIMasterSlave {
+    master: &'static TWIM,
+    slave: &'static TWIS,
+    master_client: OptionalCell<&'static dyn hil::i2c::I2CHwMasterClient>,
+    listening: Cell<bool>,
+}
+
+impl TWIMasterSlave {
+    pub fn new(master: &'static TWIM, slave: &'static TWIS) -> TWIMasterSlave {
+        TWIMasterSlave {
+            master: master,
+            slave: slave,
+            master_client: OptionalCell::empty(),
+            listening: Cell::new(false),
+        }
+    }
+
+    fn start_master(&self) {
+        hil::i2c::I2CSlave::disable(self.slave);
+        self.master.enable();
+    }
+}
+
+impl hil::i2c::I2CHwMasterClient for TWIMasterSlave {
+    fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
+        self.master.disable();
+        if self.listening.get() {
+            hil::i2c::I2CSlave::enable(self.slave);
+            hil::i2c::I2CSlave::listen(self.slave);
+        }
+        self.master_client
+            .map(move |client| client.command_complete(buffer, error));
+    }
+}
+
+impl hil::i2c::I2CMaster for TWIMasterSlave {
+    fn set_master_client(&self, client: &'static dyn hil::i2c::I2CHwMasterClient) {
+        self.master_client.set(client);
+    }
+
+    fn enable(&self) {}
+
+    fn disable(&self) {
+        self.master.disable();
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        self.start_master();
+        hil::i2c::I2CMaster::write_read(self.master, addr, data, write_len, read_len);
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        self.start_master();
+        hil::i2c::I2CMaster::write(self.master, addr, data, len);
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.start_master();
+        hil::i2c::I2CMaster::read(self.master, addr, buffer, len);
+    }
+}
+
+impl hil::i2c::I2CSlave for TWIMasterSlave {
+    fn set_slave_client(&self, client: &'static dyn hil::i2c::I2CHwSlaveClient) {
+        hil::i2c::I2CSlave::set_slave_client(self.slave, client);
+    }
+
+    fn enable(&self) {
+        if !self.master.is_enabled() {
+            hil::i2c::I2CSlave::enable(self.slave);
+        }
+    }
+
+    fn disable(&self) {
+        self.listening.set(false);
+        hil::i2c::I2CSlave::disable(self.slave);
+    }
+
+    fn set_address(&self, addr: u8) {
+        hil::i2c::I2CSlave::set_address(self.slave, addr);
+    }
+
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        hil::i2c::I2CSlave::write_receive(self.slave, data, max_len);
+    }
+
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        hil::i2c::I2CSlave::read_send(self.slave, data, max_len);
+    }
+
+    fn listen(&self) {
+        self.listening.set(true);
+        if !self.master.is_enabled() {
+            hil::i2c::I2CSlave::enable(self.slave);
+            hil::i2c::I2CSlave::listen(self.slave);
+        }
+    }
+}
+
+impl hil::i2c::I2CMasterSlave for TWIMasterSlave {}
+
 // The SPI0_TWI0 and SPI1_TWI1 interrupts are dispatched to the
 // correct handler by the service_pending_interrupts() routine in
 // chip.rs based on which peripheral is enabled.
@@ -0,0 +1,570 @@
+//! Inter-IC Sound (I2S) interface for nRF52.
+//!
+//! The I2S peripheral is used as bus master and streams 16-bit samples to or
+//! from an external codec using EasyDMA. Every 32-bit word in memory holds two
+//! samples, so buffers must be 4-byte aligned and contain an even number of
+//! samples. The data pointers are double-buffered in hardware: the
+//! `RXPTRUPD`/`TXPTRUPD` events signal that the peripheral has latched the
+//! pointer for the next buffer and a new one can be written.
+//!
+//! This driver streams in one direction at a time; simultaneous recording and
+//! playback is not supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf52::i2s::I2S.set_pins(
+//!     Some(nrf52::pinmux::Pinmux::new(I2S_MCK as u32)),
+//!     nrf52::pinmux::Pinmux::new(I2S_SCK as u32),
+//!     nrf52::pinmux::Pinmux::new(I2S_LRCK as u32),
+//!     Some(nrf52::pinmux::Pinmux::new(I2S_SDIN as u32)),
+//!     Some(nrf52::pinmux::Pinmux::new(I2S_SDOUT as u32)),
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::audio;
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const I2S_BASE: StaticRef<I2sRegisters> =
+    unsafe { StaticRef::new(0x40025000 as *const I2sRegisters) };
+
+/// Maximum number of 32-bit words in a single DMA transfer.
+const MAX_WORDS: usize = 0x3FFF;
+
+/// Frequency of the clock the master clock is divided from.
+const SOURCE_CLOCK: u32 = 32000000;
+
+/// Supported master clock dividers as (register value, divider).
+const MCK_DIVIDERS: [(u32, u32); 18] = [
+    (0x80000000, 2),
+    (0x50000000, 3),
+    (0x40000000, 4),
+    (0x30000000, 5),
+    (0x28000000, 6),
+    (0x20000000, 8),
+    (0x18000000, 10),
+    (0x16000000, 11),
+    (0x11000000, 15),
+    (0x10000000, 16),
+    (0x0C000000, 21),
+    (0x0B000000, 23),
+    (0x08800000, 30),
+    (0x08400000, 31),
+    (0x08000000, 32),
+    (0x06000000, 42),
+    (0x04100000, 63),
+    (0x020C0000, 125),
+];
+
+/// Supported MCK / LRCK ratios, indexed by their register value. A 16-bit
+/// stereo frame needs at least 32 bit clocks, which all of these provide.
+const RATIOS: [u32; 9] = [32, 48, 64, 96, 128, 192, 256, 384, 512];
+
+#[repr(C)]
+struct I2sRegisters {
+    /// Starts continuous I2S transfer
+    tasks_start: WriteOnly<u32, TASK::Register>,
+    /// Stops I2S transfer
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 252],
+    /// The RXD.PTR register has been copied to internal double-buffers
+    events_rxptrupd: ReadWrite<u32, EVENT::Register>,
+    /// I2S transfer stopped
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 8],
+    /// The TXD.PTR register has been copied to internal double-buffers
+    events_txptrupd: ReadWrite<u32, EVENT::Register>,
+    _reserved2: [u8; 488],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTE::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE::Register>,
+    _reserved3: [u8; 500],
+    /// Enable I2S module
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// I2S mode
+    config_mode: ReadWrite<u32, CONFIG_MODE::Register>,
+    /// Reception (RX) enable
+    config_rxen: ReadWrite<u32, ENABLE::Register>,
+    /// Transmission (TX) enable
+    config_txen: ReadWrite<u32, ENABLE::Register>,
+    /// Master clock generator enable
+    config_mcken: ReadWrite<u32, ENABLE::Register>,
+    /// Master clock generator frequency
+    config_mckfreq: ReadWrite<u32>,
+    /// MCK / LRCK ratio
+    config_ratio: ReadWrite<u32, CONFIG_RATIO::Register>,
+    /// Sample width
+    config_swidth: ReadWrite<u32, CONFIG_SWIDTH::Register>,
+    /// Alignment of sample within a frame
+    config_align: ReadWrite<u32, CONFIG_ALIGN::Register>,
+    /// Frame format
+    config_format: ReadWrite<u32, CONFIG_FORMAT::Register>,
+    /// Enable channels
+    config_channels: ReadWrite<u32, CONFIG_CHANNELS::Register>,
+    _reserved4: [u8; 12],
+    /// Receive buffer RAM start address
+    rxd_ptr: VolatileCell<*const i16>,
+    _reserved5: [u8; 4],
+    /// Transmit buffer RAM start address
+    txd_ptr: VolatileCell<*const i16>,
+    _reserved6: [u8; 12],
+    /// Size of RXD and TXD buffers in 32-bit words
+    rxtxd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    _reserved7: [u8; 12],
+    /// Pin select for MCK signal
+    psel_mck: VolatileCell<u32>,
+    /// Pin select for SCK signal
+    psel_sck: VolatileCell<Pinmux>,
+    /// Pin select for LRCK signal
+    psel_lrck: VolatileCell<Pinmux>,
+    /// Pin select for SDIN signal
+    psel_sdin: VolatileCell<u32>,
+    /// Pin select for SDOUT signal
+    psel_sdout: VolatileCell<u32>,
+}
+
+register_bitfields![u32,
+    TASK [
+        TASK 0
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    INTE [
+        RXPTRUPD 1,
+        STOPPED 2,
+        TXPTRUPD 5
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    CONFIG_MODE [
+        MODE OFFSET(0) NUMBITS(1) [
+            Master = 0,
+            Slave = 1
+        ]
+    ],
+    CONFIG_RATIO [
+        RATIO OFFSET(0) NUMBITS(4) []
+    ],
+    CONFIG_SWIDTH [
+        SWIDTH OFFSET(0) NUMBITS(2) [
+            Bit8 = 0,
+            Bit16 = 1,
+            Bit24 = 2
+        ]
+    ],
+    CONFIG_ALIGN [
+        ALIGN OFFSET(0) NUMBITS(1) [
+            Left = 0,
+            Right = 1
+        ]
+    ],
+    CONFIG_FORMAT [
+        FORMAT OFFSET(0) NUMBITS(1) [
+            I2S = 0,
+            Aligned = 1
+        ]
+    ],
+    CONFIG_CHANNELS [
+        CHANNELS OFFSET(0) NUMBITS(2) [
+            Stereo = 0,
+            Left = 1,
+            Right = 2
+        ]
+    ],
+    MAXCNT [
+        MAXCNT OFFSET(0) NUMBITS(14)
+    ]
+];
+
+/// Value written to a PSEL register to leave the signal disconnected.
+const PSEL_DISCONNECTED: u32 = 1 << 31;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Idle,
+    Receive,
+    Transmit,
+}
+
+pub static mut I2S: I2s<'static> = I2s::new();
+
+pub struct I2s<'a> {
+    registers: StaticRef<I2sRegisters>,
+    input_client: OptionalCell<&'a dyn audio::InputClient>,
+    output_client: OptionalCell<&'a dyn audio::OutputClient>,
+    mode: Cell<Mode>,
+    mck_divider: Cell<usize>,
+    ratio: Cell<usize>,
+    params: Cell<audio::Parameters>,
+    // The buffer the peripheral is currently streaming.
+    current: TakeCell<'static, [i16]>,
+    // The buffer the peripheral switches to once `current` is done.
+    next: TakeCell<'static, [i16]>,
+    // Whether the pointer update for the first buffer is still outstanding.
+    first_update: Cell<bool>,
+}
+
+impl<'a> I2s<'a> {
+    const fn new() -> I2s<'a> {
+        I2s {
+            registers: I2S_BASE,
+            input_client: OptionalCell::empty(),
+            output_client: OptionalCell::empty(),
+            mode: Cell::new(Mode::Idle),
+            mck_divider: Cell::new(13),
+            ratio: Cell::new(2),
+            params: Cell::new(audio::Parameters {
+                sample_rate: 16000,
+                channels: audio::Channels::Stereo,
+                format: audio::Format::I2s,
+            }),
+            current: TakeCell::empty(),
+            next: TakeCell::empty(),
+            first_update: Cell::new(false),
+        }
+    }
+
+    /// Configure which pins the I2S signals are connected to. The master
+    /// clock and either data line may be left unconnected.
+    pub fn set_pins(
+        &self,
+        mck: Option<Pinmux>,
+        sck: Pinmux,
+        lrck: Pinmux,
+        sdin: Option<Pinmux>,
+        sdout: Option<Pinmux>,
+    ) {
+        let regs = &*self.registers;
+        regs.psel_mck
+            .set(mck.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+        regs.psel_sck.set(sck);
+        regs.psel_lrck.set(lrck);
+        regs.psel_sdin
+            .set(sdin.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+        regs.psel_sdout
+            .set(sdout.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+    }
+
+    fn valid_buffer(buffer: &[i16]) -> bool {
+        buffer.len() > 0
+            && buffer.len() % 2 == 0
+            && buffer.len() / 2 <= MAX_WORDS
+            && buffer.as_ptr() as usize % 4 == 0
+    }
+
+    fn program_buffer(&self, buffer: &[i16]) {
+        let regs = &*self.registers;
+        match self.mode.get() {
+            Mode::Receive => regs.rxd_ptr.set(buffer.as_ptr()),
+            Mode::Transmit => regs.txd_ptr.set(buffer.as_ptr()),
+            Mode::Idle => {}
+        }
+        regs.rxtxd_maxcnt
+            .write(MAXCNT::MAXCNT.val((buffer.len() / 2) as u32));
+    }
+
+    fn start(
+        &self,
+        mode: Mode,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        let regs = &*self.registers;
+        if self.mode.get() != Mode::Idle || regs.enable.is_set(ENABLE::ENABLE) {
+            return (ReturnCode::EBUSY, Some(buffer1), Some(buffer2));
+        }
+        if !Self::valid_buffer(buffer1) || !Self::valid_buffer(buffer2) {
+            return (ReturnCode::ESIZE, Some(buffer1), Some(buffer2));
+        }
+
+        let params = self.params.get();
+        regs.config_mode.write(CONFIG_MODE::MODE::Master);
+        regs.config_mcken.write(ENABLE::ENABLE::SET);
+        regs.config_mckfreq
+            .set(MCK_DIVIDERS[self.mck_divider.get()].0);
+        regs.config_ratio
+            .write(CONFIG_RATIO::RATIO.val(self.ratio.get() as u32));
+        regs.config_swidth.write(CONFIG_SWIDTH::SWIDTH::Bit16);
+        match params.format {
+            audio::Format::I2s => {
+                regs.config_format.write(CONFIG_FORMAT::FORMAT::I2S);
+                regs.config_align.write(CONFIG_ALIGN::ALIGN::Left);
+            }
+            audio::Format::LeftJustified => {
+                regs.config_format.write(CONFIG_FORMAT::FORMAT::Aligned);
+                regs.config_align.write(CONFIG_ALIGN::ALIGN::Left);
+            }
+            audio::Format::RightJustified => {
+                regs.config_format.write(CONFIG_FORMAT::FORMAT::Aligned);
+                regs.config_align.write(CONFIG_ALIGN::ALIGN::Right);
+            }
+        }
+        match params.channels {
+            audio::Channels::Mono => regs.config_channels.write(CONFIG_CHANNELS::CHANNELS::Left),
+            audio::Channels::Stereo => regs
+                .config_channels
+                .write(CONFIG_CHANNELS::CHANNELS::Stereo),
+        }
+
+        self.mode.set(mode);
+        match mode {
+            Mode::Receive => {
+                regs.config_rxen.write(ENABLE::ENABLE::SET);
+                regs.config_txen.write(ENABLE::ENABLE::CLEAR);
+            }
+            _ => {
+                regs.config_rxen.write(ENABLE::ENABLE::CLEAR);
+                regs.config_txen.write(ENABLE::ENABLE::SET);
+            }
+        }
+
+        self.program_buffer(buffer1);
+        self.current.replace(buffer1);
+        self.next.replace(buffer2);
+        self.first_update.set(true);
+
+        regs.events_rxptrupd.write(EVENT::EVENT::CLEAR);
+        regs.events_txptrupd.write(EVENT::EVENT::CLEAR);
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.intenset
+            .write(INTE::RXPTRUPD::SET + INTE::TXPTRUPD::SET + INTE::STOPPED::SET);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+
+        (ReturnCode::SUCCESS, None, None)
+    }
+
+    fn provide(
+        &self,
+        mode: Mode,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        if self.mode.get() != mode {
+            return (ReturnCode::EOFF, Some(buffer));
+        }
+        if !Self::valid_buffer(buffer) {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        if self.next.is_some() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        self.next.replace(buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn stop(&self, mode: Mode) -> ReturnCode {
+        if self.mode.get() != mode {
+            return ReturnCode::EALREADY;
+        }
+        self.mode.set(Mode::Idle);
+        self.registers.tasks_stop.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn retrieve(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        if self.mode.get() != Mode::Idle || self.registers.enable.is_set(ENABLE::ENABLE) {
+            return (ReturnCode::EBUSY, None, None);
+        }
+        (ReturnCode::SUCCESS, self.current.take(), self.next.take())
+    }
+
+    // Called when the peripheral has latched the pointer of the next buffer.
+    fn pointer_updated(&self) {
+        if self.first_update.get() {
+            // The first buffer has just started; nothing is complete yet.
+            self.first_update.set(false);
+        } else if let Some(done) = self.current.take() {
+            self.next.take().map(|next| self.current.replace(next));
+            match self.mode.get() {
+                Mode::Receive => {
+                    let length = done.len();
+                    self.input_client
+                        .map(move |client| client.samples_received(done, length));
+                }
+                Mode::Transmit => {
+                    self.output_client
+                        .map(move |client| client.samples_transmitted(done));
+                }
+                Mode::Idle => {
+                    self.current.replace(done);
+                }
+            }
+        }
+
+        if self.mode.get() == Mode::Idle {
+            return;
+        }
+        if self.next.is_some() {
+            self.next.map(|next| self.program_buffer(next));
+        } else {
+            // Nothing to switch to after the current buffer. Stop rather
+            // than reuse a buffer the client owns.
+            self.mode.set(Mode::Idle);
+            self.registers.tasks_stop.write(TASK::TASK::SET);
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_rxptrupd.is_set(EVENT::EVENT) {
+            regs.events_rxptrupd.write(EVENT::EVENT::CLEAR);
+            if self.mode.get() == Mode::Receive {
+                self.pointer_updated();
+            }
+        }
+
+        if regs.events_txptrupd.is_set(EVENT::EVENT) {
+            regs.events_txptrupd.write(EVENT::EVENT::CLEAR);
+            if self.mode.get() == Mode::Transmit {
+                self.pointer_updated();
+            }
+        }
+
+        if regs.events_stopped.is_set(EVENT::EVENT) {
+            regs.events_stopped.write(EVENT::EVENT::CLEAR);
+            regs.intenclr
+                .write(INTE::RXPTRUPD::SET + INTE::TXPTRUPD::SET + INTE::STOPPED::SET);
+            regs.enable.write(ENABLE::ENABLE::CLEAR);
+        }
+    }
+}
+
+impl<'a> audio::Configure for I2s<'a> {
+    fn configure(&self, params: audio::Parameters) -> ReturnCode {
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if params.sample_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        // Search all divider and ratio combinations for the closest rate.
+        let mut best = (0, 0, core::u32::MAX);
+        for (d, (_, divider)) in MCK_DIVIDERS.iter().enumerate() {
+            for (r, ratio) in RATIOS.iter().enumerate() {
+                let rate = SOURCE_CLOCK / (divider * ratio);
+                let error = if rate > params.sample_rate {
+                    rate - params.sample_rate
+                } else {
+                    params.sample_rate - rate
+                };
+                if error < best.2 {
+                    best = (d, r, error);
+                }
+            }
+        }
+        // Reject requests more than 2% away from anything we can produce.
+        if best.2 * 50 > params.sample_rate {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        self.mck_divider.set(best.0);
+        self.ratio.set(best.1);
+        self.params.set(params);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_sample_rate(&self) -> u32 {
+        SOURCE_CLOCK / (MCK_DIVIDERS[self.mck_divider.get()].1 * RATIOS[self.ratio.get()])
+    }
+}
+
+impl<'a> audio::AudioInput<'a> for I2s<'a> {
+    fn set_client(&self, client: &'a dyn audio::InputClient) {
+        self.input_client.set(client);
+    }
+
+    fn start_receive(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.start(Mode::Receive, buffer1, buffer2)
+    }
+
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        self.provide(Mode::Receive, buffer)
+    }
+
+    fn stop_receive(&self) -> ReturnCode {
+        self.stop(Mode::Receive)
+    }
+
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.retrieve()
+    }
+}
+
+impl<'a> audio::AudioOutput<'a> for I2s<'a> {
+    fn set_client(&self, client: &'a dyn audio::OutputClient) {
+        self.output_client.set(client);
+    }
+
+    fn start_transmit(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.start(Mode::Transmit, buffer1, buffer2)
+    }
+
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        self.provide(Mode::Transmit, buffer)
+    }
+
+    fn stop_transmit(&self) -> ReturnCode {
+        self.stop(Mode::Transmit)
+    }
+
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.retrieve()
+    }
+}
@@ -1,33 +1,45 @@
 //! IEEE 802.15.4 radio driver for nRF52
+//!
+//! Frames are sent with unslotted CSMA-CA (IEEE 802.15.4-2011 Section
+//! 5.1.1.4): the radio waits a random number of backoff periods, assesses
+//! the channel, and transmits if it is clear, using the CCA shortcuts of the
+//! radio. Frames that request an acknowledgement are retransmitted up to
+//! `IEEE802154_MAX_FRAME_RETRIES` times until an acknowledgement with their
+//! sequence number arrives.
+//!
+//! Received frames that request an acknowledgement and are addressed to this
+//! node are acknowledged by the radio itself. Once the destination address
+//! of a frame is received, the driver points the radio at an acknowledgement
+//! frame and arms the END -> DISABLE -> TXEN shortcuts, which send it
+//! `IEEE802154_ACK_TURNAROUND` microseconds after the frame ends. The
+//! acknowledgement is cancelled if the frame fails its CRC.
+//!
+//! Backoffs and acknowledgement timeouts are timed with TIMER0, which the
+//! board has to connect to the radio with `TIMER0.set_client(&RADIO)`.
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::radio::{self, PowerClient};
-use kernel::hil::time::Alarm;
+use kernel::hil::time::{self, Alarm, Time};
 use kernel::ReturnCode;
 
-use crate::ppi;
 use nrf5x;
 use nrf5x::constants::TxPower;
 
-//extern crate::net;
-//use capsules;
-//use capsules::net::ieee802154::{
-//    FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
-//};
-//use capsules::net::ieee802154::Header;
-
 const RADIO_BASE: StaticRef<RadioRegisters> =
     unsafe { StaticRef::new(0x40001000 as *const RadioRegisters) };
 
 pub const IEEE802154_PAYLOAD_LENGTH: usize = 255;
 pub const IEEE802154_BACKOFF_PERIOD: usize = 320; //microseconds = 20 symbols
-pub const IEEE802154_ACK_TIME: usize = 512; //microseconds = 32 symbols
+pub const IEEE802154_ACK_TIME: usize = 864; //microseconds = 54 symbols
+pub const IEEE802154_ACK_TURNAROUND: usize = 192; //microseconds = 12 symbols
 pub const IEEE802154_MAX_POLLING_ATTEMPTS: u8 = 4;
+pub const IEEE802154_MAX_FRAME_RETRIES: u8 = 3;
 pub const IEEE802154_MIN_BE: u8 = 3;
 pub const IEEE802154_MAX_BE: u8 = 5;
 pub const RAM_S0_BYTES: usize = 1;
@@ -35,6 +47,29 @@ pub const RAM_LEN_BITS: usize = 8;
 pub const RAM_S1_BITS: usize = 0;
 pub const PREBUF_LEN_BYTES: usize = 2;
 
+// Frame control field, IEEE 802.15.4-2011 Section 5.2.1.1
+const FRAME_TYPE_MASK: u8 = 0x07;
+const FRAME_TYPE_DATA: u8 = 0x01;
+const FRAME_TYPE_ACK: u8 = 0x02;
+const FRAME_TYPE_COMMAND: u8 = 0x03;
+const FRAME_ACK_REQUEST: u8 = 0x20;
+const FRAME_VERSION_2015: u8 = 0x20;
+const ADDR_MODE_SHORT: u8 = 0x02;
+const ADDR_MODE_LONG: u8 = 0x03;
+
+// Frame control and sequence number, after which the destination
+// addressing mode of a received frame is known
+const HEADER_START_LEN: usize = 3;
+
+// Length of an acknowledgement frame, including the FCS
+const ACK_FRAME_LEN: usize = 5;
+const ACK_SEQ_OFFSET: usize = radio::PSDU_OFFSET + 2;
+
+// Acknowledgement sent for received frames. It is only written to while the
+// radio is not transmitting it.
+static mut ACK_BUF: [u8; radio::PSDU_OFFSET + ACK_FRAME_LEN] =
+    [0, ACK_FRAME_LEN as u8, FRAME_TYPE_ACK, 0, 0, 0, 0];
+
 // IEEEStd 802.15.4-2011 Section 8.1.2.2
 // Frequency is 2405 + 5 * (k - 11) MHz, where k = 11, 12, ... , 26.
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -352,7 +387,17 @@ register_bitfields! [u32,
         /// Shortcut between ADDRESS event and BCSTART task
         ADDRESS_BCSTART OFFSET(6) NUMBITS(1),
         /// Shortcut between DISABLED event and RSSISTOP task
-        DISABLED_RSSISTOP OFFSET(8) NUMBITS(1)
+        DISABLED_RSSISTOP OFFSET(8) NUMBITS(1),
+        /// Shortcut between RXREADY event and CCASTART task
+        RXREADY_CCASTART OFFSET(11) NUMBITS(1),
+        /// Shortcut between CCAIDLE event and TXEN task
+        CCAIDLE_TXEN OFFSET(12) NUMBITS(1),
+        /// Shortcut between CCABUSY event and DISABLE task
+        CCABUSY_DISABLE OFFSET(13) NUMBITS(1),
+        /// Shortcut between FRAMESTART event and BCSTART task
+        FRAMESTART_BCSTART OFFSET(14) NUMBITS(1),
+        /// Shortcut between TXREADY event and START task
+        TXREADY_START OFFSET(18) NUMBITS(1)
     ],
     /// Interrupt register
     Interrupt [
@@ -650,6 +695,18 @@ register_bitfields! [u32,
     ]
 ];
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RadioState {
+    /// Receiving frames, possibly waiting for a backoff to end
+    Rx,
+    /// Sending the acknowledgement of a received frame
+    TxAck,
+    /// Assessing the channel, then sending `tx_buf` if it is clear
+    Tx,
+    /// Receiving frames while waiting for the acknowledgement of `tx_buf`
+    RxAck,
+}
+
 pub struct Radio {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
@@ -665,6 +722,16 @@ pub struct Radio {
     random_nonce: Cell<u32>,
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
+    state: Cell<RadioState>,
+    // Set once the header of a frame has been received
+    receiving: Cell<bool>,
+    // Set while the radio is armed to acknowledge the frame being received
+    ack_pending: Cell<bool>,
+    // Set when a backoff ended while the radio was busy
+    cca_due: Cell<bool>,
+    // Set when a configuration could not be applied while the radio was busy
+    config_pending: Cell<bool>,
+    tx_retries: Cell<u8>,
 }
 
 pub static mut RADIO: Radio = Radio::new();
@@ -686,6 +753,12 @@ impl Radio {
             random_nonce: Cell::new(0xDEADBEEF),
             channel: Cell::new(RadioChannel::DataChannel11),
             transmitting: Cell::new(false),
+            state: Cell::new(RadioState::Rx),
+            receiving: Cell::new(false),
+            ack_pending: Cell::new(false),
+            cca_due: Cell::new(false),
+            config_pending: Cell::new(false),
+            tx_retries: Cell::new(0),
         }
     }
 
@@ -695,28 +768,309 @@ impl Radio {
             .matches_all(Mode::MODE::IEEE802154_250KBIT)
     }
 
+    // Starts receiving frames into `rx_buf`. The radio has to be disabled.
     fn rx(&self) {
         let regs = &*self.registers;
-        regs.event_ready.write(Event::READY::CLEAR);
+        self.state.set(RadioState::Rx);
+        self.receiving.set(false);
+        self.ack_pending.set(false);
+
+        regs.event_end.write(Event::READY::CLEAR);
+        regs.event_bcmatch.write(Event::READY::CLEAR);
+        regs.shorts.write(
+            Shortcut::READY_START::SET
+                + Shortcut::END_DISABLE::SET
+                + Shortcut::FRAMESTART_BCSTART::SET,
+        );
+        regs.bcc.set((HEADER_START_LEN * 8) as u32);
 
-        if self.transmitting.get() {
-            let tbuf = self
-                .tx_buf
-                .take()
-                .expect("Radio TX Buffer produced an invalid result when setting the DMA pointer.");
+        let rbuf = self
+            .rx_buf
+            .take()
+            .expect("Radio RX Buffer produced an invalid result when setting the DMA pointer.");
+        self.rx_buf.replace(self.set_dma_ptr(rbuf));
+
+        regs.task_rxen.write(Task::ENABLE::SET);
+
+        self.enable_interrupts();
+    }
+
+    // Resets the radio and sends `tx_buf` if the channel is clear. The radio
+    // ends up disabled either way, and `event_ccabusy` tells which happened.
+    fn start_cca(&self) {
+        let regs = &*self.registers;
+        self.radio_off();
+        self.radio_configure();
+        self.config_pending.set(false);
+        self.cca_due.set(false);
+        self.state.set(RadioState::Tx);
+
+        regs.shorts.write(
+            Shortcut::RXREADY_CCASTART::SET
+                + Shortcut::CCAIDLE_TXEN::SET
+                + Shortcut::CCABUSY_DISABLE::SET
+                + Shortcut::TXREADY_START::SET
+                + Shortcut::END_DISABLE::SET,
+        );
+
+        let tbuf = self
+            .tx_buf
+            .take()
+            .expect("Radio TX Buffer produced an invalid result when setting the DMA pointer.");
+        self.tx_buf.replace(self.set_dma_ptr(tbuf));
+
+        regs.task_rxen.write(Task::ENABLE::SET);
+
+        self.enable_interrupts();
+    }
+
+    // Continues after the radio was disabled: with a transmission whose
+    // backoff ended in the meantime, or by receiving again.
+    fn next(&self) {
+        if self.cca_due.replace(false) {
+            self.start_cca();
+        } else if self.config_pending.replace(false) {
+            self.radio_off();
+            self.radio_initialize(self.channel.get());
+        } else {
+            self.rx();
+        }
+    }
+
+    // Starts the CSMA-CA algorithm for `tx_buf`
+    fn start_csma(&self) {
+        self.cca_count.set(0);
+        self.cca_be.set(IEEE802154_MIN_BE);
+        self.backoff();
+    }
+
+    // Waits a random number of backoff periods before assessing the channel
+    fn backoff(&self) {
+        let backoff_periods = self.random_nonce() & ((1 << self.cca_be.get()) - 1);
+        if backoff_periods == 0 {
+            self.backoff_done();
+        } else {
+            self.set_timer(backoff_periods * (IEEE802154_BACKOFF_PERIOD as u32));
+        }
+    }
 
-            self.tx_buf.replace(self.set_dma_ptr(tbuf));
+    fn backoff_done(&self) {
+        if self.state.get() == RadioState::Rx && !self.receiving.get() {
+            self.start_cca();
         } else {
+            // Assess the channel once the frame being received, or its
+            // acknowledgement, is done
+            self.cca_due.set(true);
+        }
+    }
+
+    // TIMER0 runs at 1 MHz, so the delay is in microseconds
+    fn set_timer(&self, us: u32) {
+        unsafe {
+            let timer = &nrf5x::timer::TIMER0;
+            timer.set_alarm(timer.now().wrapping_add(us));
+        }
+    }
+
+    fn cancel_timer(&self) {
+        unsafe {
+            nrf5x::timer::TIMER0.disable();
+        }
+    }
+
+    fn tx_done(&self, acked: bool, result: ReturnCode) {
+        self.transmitting.set(false);
+        self.tx_client.map(|client| {
+            let tbuf = self.tx_buf.take().expect(
+                "TX Buffer produced error when sending it back to the requestor after transmission.",
+            );
+            client.send_done(tbuf, acked, result)
+        });
+    }
+
+    fn deliver(&self, crc_valid: bool) {
+        let result = if crc_valid {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.rx_client.map(|client| {
             let rbuf = self
                 .rx_buf
                 .take()
-                .expect("Radio RX Buffer produced an invalid result when setting the DMA pointer.");
-            self.rx_buf.replace(self.set_dma_ptr(rbuf));
+                .expect("RX Buffer produced error when sending received packet to requestor");
+
+            // The length field counts the PSDU, including the FCS
+            let frame_len = (rbuf[RAM_S0_BYTES] as usize).saturating_sub(radio::MFR_SIZE);
+
+            client.receive(rbuf, frame_len, crc_valid, result)
+        });
+    }
+
+    // Returns the length of the header up to the destination address if the
+    // frame requests an acknowledgement, or `None` if it does not.
+    fn ack_header_len(psdu: &[u8]) -> Option<usize> {
+        let frame_type = psdu[0] & FRAME_TYPE_MASK;
+        if (frame_type != FRAME_TYPE_DATA && frame_type != FRAME_TYPE_COMMAND)
+            || psdu[0] & FRAME_ACK_REQUEST == 0
+            || psdu[1] & 0x30 >= FRAME_VERSION_2015
+        {
+            return None;
+        }
+        // Sequence number and destination PAN ID precede the address
+        match (psdu[1] >> 2) & 0x03 {
+            ADDR_MODE_SHORT => Some(HEADER_START_LEN + 2 + 2),
+            ADDR_MODE_LONG => Some(HEADER_START_LEN + 2 + 8),
+            _ => None,
         }
+    }
 
-        regs.task_rxen.write(Task::ENABLE::SET);
+    fn addressed_to_us(&self, psdu: &[u8], header_len: usize) -> bool {
+        let pan = u16::from_le_bytes([psdu[3], psdu[4]]);
+        if pan != self.pan.get() && pan != 0xffff {
+            return false;
+        }
+        let addr = &psdu[5..header_len];
+        if addr.len() == 2 {
+            u16::from_le_bytes([addr[0], addr[1]]) == self.addr.get()
+        } else {
+            // Long addresses are sent least significant byte first
+            addr.iter().eq(self.addr_long.get().iter().rev())
+        }
+    }
 
-        self.enable_interrupts();
+    // Called when the bit counter matches during reception: first after the
+    // frame control field and sequence number, and then after the
+    // destination address if the frame requests an acknowledgement.
+    fn header_received(&self) {
+        let regs = &*self.registers;
+        self.receiving.set(true);
+        if self.state.get() != RadioState::Rx {
+            return;
+        }
+
+        let received = regs.bcc.get() as usize / 8;
+        self.rx_buf.map(|rbuf| {
+            let psdu = &rbuf[radio::PSDU_OFFSET..];
+            match Self::ack_header_len(psdu) {
+                Some(header_len) if header_len > received => {
+                    regs.bcc.set((header_len * 8) as u32);
+                }
+                Some(header_len) if self.addressed_to_us(psdu, header_len) => {
+                    // The radio reads the packet pointer when it starts
+                    // transmitting, so it can be changed during reception
+                    unsafe {
+                        ACK_BUF[ACK_SEQ_OFFSET] = psdu[2];
+                        regs.packetptr.set(ACK_BUF.as_ptr() as u32);
+                    }
+                    regs.tifs.set(IEEE802154_ACK_TURNAROUND as u32);
+                    regs.shorts.write(
+                        Shortcut::READY_START::SET
+                            + Shortcut::END_DISABLE::SET
+                            + Shortcut::DISABLED_TXEN::SET,
+                    );
+                    self.ack_pending.set(true);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    // The radio was disabled while receiving
+    fn rx_disabled(&self) {
+        let regs = &*self.registers;
+        let received = regs.event_end.is_set(Event::READY);
+        regs.event_end.write(Event::READY::CLEAR);
+        let crc_valid = regs.crcstatus.is_set(Event::READY);
+
+        let mut cancelled = false;
+        if self.ack_pending.replace(false) {
+            // The DISABLED_TXEN shortcut has already started the radio up
+            // for the acknowledgement, which is sent after the turnaround
+            // time unless it is cancelled here.
+            regs.shorts
+                .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+            if received && crc_valid {
+                self.state.set(RadioState::TxAck);
+            } else {
+                // Reception restarts at the next DISABLED event
+                regs.task_disable.write(Task::ENABLE::SET);
+                cancelled = true;
+            }
+        }
+
+        if received {
+            self.deliver(crc_valid);
+        }
+        if self.state.get() == RadioState::Rx && !cancelled {
+            self.next();
+        }
+    }
+
+    // The radio was disabled after the channel assessment or transmission
+    fn tx_disabled(&self) {
+        let regs = &*self.registers;
+        if regs.event_ccabusy.is_set(Event::READY) {
+            regs.event_ccabusy.write(Event::READY::CLEAR);
+            self.rx();
+            //need to back off for a period of time outlined
+            //in the IEEE 802.15.4 standard (see Figure 69 in
+            //section 7.5.1.4 The CSMA-CA algorithm of the
+            //standard).
+            if self.cca_count.get() < IEEE802154_MAX_POLLING_ATTEMPTS {
+                self.cca_count.set(self.cca_count.get() + 1);
+                self.cca_be
+                    .set(cmp::min(self.cca_be.get() + 1, IEEE802154_MAX_BE));
+                self.backoff();
+            } else {
+                self.tx_done(false, ReturnCode::FAIL);
+            }
+            return;
+        }
+
+        regs.event_end.write(Event::READY::CLEAR);
+        // Version 2015 frames are acknowledged with Enhanced ACKs, which
+        // are left to the MAC layer
+        let ack_requested = self.tx_buf.map_or(false, |tbuf| {
+            tbuf[radio::PSDU_OFFSET] & FRAME_ACK_REQUEST != 0
+                && tbuf[radio::PSDU_OFFSET + 1] & 0x30 < FRAME_VERSION_2015
+        });
+        self.rx();
+        if ack_requested {
+            self.state.set(RadioState::RxAck);
+            self.set_timer(IEEE802154_ACK_TIME as u32);
+        } else {
+            self.tx_done(false, ReturnCode::SUCCESS);
+        }
+    }
+
+    // The radio was disabled while waiting for an acknowledgement
+    fn rx_ack_disabled(&self) {
+        let regs = &*self.registers;
+        let received = regs.event_end.is_set(Event::READY);
+        regs.event_end.write(Event::READY::CLEAR);
+        let crc_valid = regs.crcstatus.is_set(Event::READY);
+
+        let seq = self.tx_buf.map_or(0, |tbuf| tbuf[ACK_SEQ_OFFSET]);
+        let acked = received
+            && crc_valid
+            && self.rx_buf.map_or(false, |rbuf| {
+                rbuf[RAM_S0_BYTES] as usize == ACK_FRAME_LEN
+                    && rbuf[radio::PSDU_OFFSET] & FRAME_TYPE_MASK == FRAME_TYPE_ACK
+                    && rbuf[ACK_SEQ_OFFSET] == seq
+            });
+
+        if acked {
+            self.cancel_timer();
+            self.next();
+            self.tx_done(true, ReturnCode::SUCCESS);
+        } else {
+            if received {
+                self.deliver(crc_valid);
+            }
+            self.rx();
+            self.state.set(RadioState::RxAck);
+        }
     }
 
     fn set_rx_address(&self) {
@@ -752,132 +1106,35 @@ impl Radio {
         buffer
     }
 
-    // TODO: Theres an additional step for 802154 rx/tx handling
     #[inline(never)]
     pub fn handle_interrupt(&self) {
         let regs = &*self.registers;
         self.disable_all_interrupts();
 
-        if regs.event_ready.is_set(Event::READY) {
-            regs.event_ready.write(Event::READY::CLEAR);
-            regs.event_end.write(Event::READY::CLEAR);
-            if self.transmitting.get() && regs.state.get() == nrf5x::constants::RADIO_STATE_RXIDLE {
-                if self.cca_count.get() > 0 {
-                    unsafe {
-                        ppi::PPI.disable(ppi::Channel::CH21::SET);
-                    }
-                }
-                regs.task_ccastart.write(Task::ENABLE::SET);
-            } else {
-                regs.task_start.write(Task::ENABLE::SET);
-            }
+        if regs.event_bcmatch.is_set(Event::READY) {
+            regs.event_bcmatch.write(Event::READY::CLEAR);
+            self.header_received();
         }
 
-        if regs.event_framestart.is_set(Event::READY) {
-            regs.event_framestart.write(Event::READY::CLEAR);
-        }
-
-        //   IF we receive the go ahead (channel is clear)
-        // THEN start the transmit part of the radio
-        if regs.event_ccaidle.is_set(Event::READY) {
-            regs.event_ccaidle.write(Event::READY::CLEAR);
-            regs.task_txen.write(Task::ENABLE::SET)
-        }
-
-        if regs.event_ccabusy.is_set(Event::READY) {
-            regs.event_ccabusy.write(Event::READY::CLEAR);
-            //need to back off for a period of time outlined
-            //in the IEEE 802.15.4 standard (see Figure 69 in
-            //section 7.5.1.4 The CSMA-CA algorithm of the
-            //standard).
-            if self.cca_count.get() < IEEE802154_MAX_POLLING_ATTEMPTS {
-                self.cca_count.set(self.cca_count.get() + 1);
-                self.cca_be.set(self.cca_be.get() + 1);
-                let backoff_periods = self.random_nonce() & ((1 << self.cca_be.get()) - 1);
-                unsafe {
-                    ppi::PPI.enable(ppi::Channel::CH21::SET);
-                    nrf5x::timer::TIMER0
-                        .set_alarm(backoff_periods * (IEEE802154_BACKOFF_PERIOD as u32));
-                }
-            } else {
-                self.transmitting.set(false);
-                //if we are transmitting, the CRCstatus check is always going to be an error
-                let result = ReturnCode::EBUSY;
-                //TODO: Acked is flagged as false until I get around to fixing it.
-                self.tx_client
-                    .map(|client| {
-                        let tbuf = self.tx_buf.take().expect("TX Buffer produced error when sending it back to the requestor after the channel was busy.");
-                        client.send_done(tbuf, false, result)
-                    });
+        // Every reception, channel assessment and transmission ends with
+        // the radio disabled by a shortcut
+        if regs.event_disabled.is_set(Event::READY) {
+            regs.event_disabled.write(Event::READY::CLEAR);
+            match self.state.get() {
+                RadioState::Rx => self.rx_disabled(),
+                RadioState::TxAck => self.next(),
+                RadioState::Tx => self.tx_disabled(),
+                RadioState::RxAck => self.rx_ack_disabled(),
             }
-
-            regs.event_ready.write(Event::READY::CLEAR);
-            regs.task_disable.write(Task::ENABLE::SET);
-            self.enable_interrupts();
         }
 
-        // tx or rx finished!
-        if regs.event_end.is_set(Event::READY) {
-            regs.event_end.write(Event::READY::CLEAR);
-
-            let result = if regs.crcstatus.is_set(Event::READY) {
-                ReturnCode::SUCCESS
-            } else {
-                ReturnCode::FAIL
-            };
-
-            match regs.state.get() {
-                nrf5x::constants::RADIO_STATE_TXRU
-                | nrf5x::constants::RADIO_STATE_TXIDLE
-                | nrf5x::constants::RADIO_STATE_TXDISABLE
-                | nrf5x::constants::RADIO_STATE_TX => {
-                    self.transmitting.set(false);
-                    //if we are transmitting, the CRCstatus check is always going to be an error
-                    let result = ReturnCode::SUCCESS;
-                    //TODO: Acked is flagged as false until I get around to fixing it.
-                    self.tx_client
-                        .map(|client|{
-                        let tbuf = self.tx_buf.take().expect("TX Buffer produced error when sending it back to the requestor after successful transmission.");
-
-                         client.send_done(tbuf, false, result)
-                    });
-                }
-                nrf5x::constants::RADIO_STATE_RXRU
-                | nrf5x::constants::RADIO_STATE_RXIDLE
-                | nrf5x::constants::RADIO_STATE_RXDISABLE
-                | nrf5x::constants::RADIO_STATE_RX => {
-                    self.rx_client.map(|client| {
-                        let rbuf = self.rx_buf.take().expect(
-                            "RX Buffer produced error when sending received packet to requestor",
-                        );
-
-                        let frame_len = rbuf[1] as usize - radio::MFR_SIZE;
-                        // Length is: S0 (1 Byte) + Length (1 Byte) + S1 (0 Bytes) + Payload
-                        // And because the length field is directly read from the packet
-                        // We need to add 2 to length to get the total length
-
-                        client.receive(rbuf, frame_len, regs.crcstatus.get() == 1, result)
-                    });
-                }
-                // Radio state - Disabled
-                _ => (),
-            }
-            self.radio_off();
-            self.radio_initialize(self.channel.get());
-            self.rx();
-        }
         self.enable_interrupts();
     }
 
     pub fn enable_interrupts(&self) {
         let regs = &*self.registers;
-        regs.intenset.write(
-            Interrupt::READY::SET
-                + Interrupt::CCAIDLE::SET
-                + Interrupt::CCABUSY::SET
-                + Interrupt::END::SET
-                + Interrupt::FRAMESTART::SET,
-        );
+        regs.intenset
+            .write(Interrupt::DISABLED::SET + Interrupt::BCMATCH::SET);
     }
 
     pub fn enable_interrupt(&self, intr: u32) {
@@ -897,6 +1154,11 @@ impl Radio {
     }
 
     fn radio_initialize(&self, _channel: RadioChannel) {
+        self.radio_configure();
+        self.rx();
+    }
+
+    fn radio_configure(&self) {
         self.radio_on();
 
         self.ieee802154_set_channel_rate();
@@ -914,8 +1176,6 @@ impl Radio {
 
         self.set_tx_address();
         self.set_rx_address();
-
-        self.rx();
     }
 
     // IEEE802.15.4 SPECIFICATION Section 6.20.12.5 of the NRF52840 Datasheet
@@ -1053,8 +1313,12 @@ impl kernel::hil::radio::RadioConfig for Radio {
     /// PAN ID, TX power, and channel to the specified values, issues
     /// a callback to the config client when done.
     fn config_commit(&self) {
-        self.radio_off();
-        self.radio_initialize(self.channel.get());
+        if self.state.get() == RadioState::Rx && !self.transmitting.get() {
+            self.radio_off();
+            self.radio_initialize(self.channel.get());
+        } else {
+            self.config_pending.set(true);
+        }
     }
 
     fn set_config_client(&self, _client: &'static dyn radio::ConfigClient) {}
@@ -1090,6 +1354,9 @@ impl kernel::hil::radio::RadioConfig for Radio {
 
     fn set_address(&self, addr: u16) {
         self.addr.set(addr);
+        // Nodes should not all draw the same backoffs
+        self.random_nonce
+            .set(self.random_nonce.get() ^ ((addr as u32) << 8));
     }
 
     fn set_address_long(&self, addr: [u8; 8]) {
@@ -1143,6 +1410,8 @@ impl kernel::hil::radio::RadioData for Radio {
         buf: &'static mut [u8],
         frame_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        // The length field counts the FCS, which the radio appends
+        let frame_len = frame_len + radio::MFR_SIZE;
         if self.tx_buf.is_some() || self.transmitting.get() {
             return (ReturnCode::EBUSY, Some(buf));
         } else if radio::PSDU_OFFSET + frame_len >= buf.len() {
@@ -1155,14 +1424,28 @@ impl kernel::hil::radio::RadioData for Radio {
         self.tx_buf.replace(buf);
 
         self.transmitting.set(true);
+        self.tx_retries.set(0);
+        self.start_csma();
 
-        self.cca_count.set(0);
-        self.cca_be.set(IEEE802154_MIN_BE);
-
-        self.radio_off();
-        self.radio_initialize(self.channel.get());
-
-        //self.enable_interrupts();
         (ReturnCode::SUCCESS, None)
     }
 }
+
+impl time::AlarmClient for Radio {
+    fn fired(&self) {
+        match self.state.get() {
+            RadioState::RxAck => {
+                // No acknowledgement arrived in time
+                self.state.set(RadioState::Rx);
+                if self.tx_retries.get() < IEEE802154_MAX_FRAME_RETRIES {
+                    self.tx_retries.set(self.tx_retries.get() + 1);
+                    self.start_csma();
+                } else {
+                    self.tx_done(false, ReturnCode::SUCCESS);
+                }
+            }
+            RadioState::Rx | RadioState::TxAck if self.transmitting.get() => self.backoff_done(),
+            _ => {}
+        }
+    }
+}
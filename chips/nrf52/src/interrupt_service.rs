@@ -2,9 +2,13 @@ use crate::acomp;
 use crate::adc;
 use crate::ble_radio;
 use crate::i2c;
+use crate::i2s;
 use crate::ieee802154_radio;
+use crate::pdm;
 use crate::power;
 use crate::spi;
+use crate::spis;
+use crate::twis;
 use crate::uart;
 use kernel::debug;
 use nrf5x::peripheral_interrupts;
@@ -94,35 +98,43 @@ impl InterruptService for Nrf52InterruptService {
             peripheral_interrupts::TIMER2 => nrf5x::timer::TIMER2.handle_interrupt(),
             peripheral_interrupts::UART0 => uart::UARTE0.handle_interrupt(),
             peripheral_interrupts::SPI0_TWI0 => {
-                // SPI0 and TWI0 share interrupts.
+                // SPIM0, SPIS0, TWIM0 and TWIS0 share registers and
+                // interrupts, and at most one of them can be enabled.
                 // Dispatch the correct handler.
-                match (spi::SPIM0.is_enabled(), i2c::TWIM0.is_enabled()) {
-                    (false, false) => (),
-                    (true, false) => spi::SPIM0.handle_interrupt(),
-                    (false, true) => i2c::TWIM0.handle_interrupt(),
-                    (true, true) => debug_assert!(
-                        false,
-                        "SPIM0 and TWIM0 cannot be \
-                         enabled at the same time."
-                    ),
+                if spi::SPIM0.is_enabled() {
+                    spi::SPIM0.handle_interrupt();
+                } else if spis::SPIS0.is_enabled() {
+                    spis::SPIS0.handle_interrupt();
+                } else if i2c::TWIM0.is_enabled() {
+                    i2c::TWIM0.handle_interrupt();
+                } else if twis::TWIS0.is_enabled() {
+                    twis::TWIS0.handle_interrupt();
                 }
             }
             peripheral_interrupts::SPI1_TWI1 => {
-                // SPI1 and TWI1 share interrupts.
+                // SPIM1, SPIS1, TWIM1 and TWIS1 share registers and
+                // interrupts, and at most one of them can be enabled.
                 // Dispatch the correct handler.
-                match (spi::SPIM1.is_enabled(), i2c::TWIM1.is_enabled()) {
-                    (false, false) => (),
-                    (true, false) => spi::SPIM1.handle_interrupt(),
-                    (false, true) => i2c::TWIM1.handle_interrupt(),
-                    (true, true) => debug_assert!(
-                        false,
-                        "SPIM1 and TWIM1 cannot be \
-                         enabled at the same time."
-                    ),
+                if spi::SPIM1.is_enabled() {
+                    spi::SPIM1.handle_interrupt();
+                } else if spis::SPIS1.is_enabled() {
+                    spis::SPIS1.handle_interrupt();
+                } else if i2c::TWIM1.is_enabled() {
+                    i2c::TWIM1.handle_interrupt();
+                } else if twis::TWIS1.is_enabled() {
+                    twis::TWIS1.handle_interrupt();
+                }
+            }
+            peripheral_interrupts::SPIM2_SPIS2_SPI2 => {
+                if spis::SPIS2.is_enabled() {
+                    spis::SPIS2.handle_interrupt();
+                } else {
+                    spi::SPIM2.handle_interrupt();
                 }
             }
-            peripheral_interrupts::SPIM2_SPIS2_SPI2 => spi::SPIM2.handle_interrupt(),
             peripheral_interrupts::ADC => adc::ADC.handle_interrupt(),
+            peripheral_interrupts::PDM => pdm::PDM.handle_interrupt(),
+            peripheral_interrupts::I2S => i2s::I2S.handle_interrupt(),
             _ => return false,
         }
         true
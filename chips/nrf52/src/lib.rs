@@ -12,13 +12,18 @@ pub mod crt1;
 mod deferred_call_tasks;
 pub mod ficr;
 pub mod i2c;
+pub mod i2s;
 pub mod ieee802154_radio;
 pub mod interrupt_service;
 pub mod nvmc;
+pub mod pdm;
 pub mod power;
 pub mod ppi;
 pub mod pwm;
+pub mod qspi;
 pub mod spi;
+pub mod spis;
+pub mod twis;
 pub mod uart;
 pub mod uicr;
 pub mod usbd;
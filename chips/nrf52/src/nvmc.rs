@@ -313,11 +313,9 @@ impl Nvmc {
         Ok(())
     }
 
-    fn write_page(
-        &self,
-        page_number: usize,
-        data: &'static mut NrfPage,
-    ) -> Result<(), (ReturnCode, &'static mut NrfPage)> {
+    /// Erase and program a page without issuing a callback. This is for code
+    /// that runs outside of the kernel, like a boot stage.
+    pub fn write_page_blocking(&self, page_number: usize, data: &NrfPage) {
         let regs = &*self.registers;
 
         // Need to erase the page first.
@@ -340,6 +338,14 @@ impl Nvmc {
         // Make sure that the NVMC is done. The CPU should be blocked while the
         // write is happening, but it doesn't hurt to check too.
         while !regs.ready.is_set(Ready::READY) {}
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        data: &'static mut NrfPage,
+    ) -> Result<(), (ReturnCode, &'static mut NrfPage)> {
+        self.write_page_blocking(page_number, data);
 
         // Save the buffer so we can return it with the callback.
         self.buffer.replace(data);
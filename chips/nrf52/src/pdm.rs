@@ -0,0 +1,363 @@
+//! Pulse density modulation (PDM) microphone interface for nRF52.
+//!
+//! The PDM peripheral samples one or two digital microphones, decimates the
+//! bitstream to 16-bit PCM and writes the samples to RAM with EasyDMA. The
+//! sample pointer is double-buffered in hardware: once the `STARTED` event
+//! fires, the pointer for the following buffer can be written and the
+//! peripheral switches to it as soon as the current buffer is full (`END`).
+//!
+//! The decimation ratio is fixed at 64, so the available sample rates are the
+//! supported PDM clock frequencies divided by 64 (roughly 15.6 kHz to
+//! 20.8 kHz).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf52::pdm::PDM.set_pins(
+//!     nrf52::pinmux::Pinmux::new(PDM_CLK as u32),
+//!     nrf52::pinmux::Pinmux::new(PDM_DIN as u32),
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::audio;
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const PDM_BASE: StaticRef<PdmRegisters> =
+    unsafe { StaticRef::new(0x4001D000 as *const PdmRegisters) };
+
+/// Maximum number of 16-bit samples in a single DMA transfer.
+const MAX_SAMPLES: usize = 0x7FFF;
+
+/// PCM samples produced per PDM clock cycle.
+const DECIMATION_RATIO: u32 = 64;
+
+/// Supported PDM clock frequencies as (register value, frequency in Hz).
+const PDM_CLOCKS: [(u32, u32); 6] = [
+    (0x08000000, 1000000),
+    (0x08400000, 1032000),
+    (0x08800000, 1067000),
+    (0x09800000, 1231000),
+    (0x0A000000, 1280000),
+    (0x0A800000, 1333000),
+];
+
+#[repr(C)]
+struct PdmRegisters {
+    /// Starts continuous PDM transfer
+    tasks_start: WriteOnly<u32, TASK::Register>,
+    /// Stops PDM transfer
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 248],
+    /// PDM transfer has started
+    events_started: ReadWrite<u32, EVENT::Register>,
+    /// PDM transfer has finished
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    /// The PDM has written the last sample specified by SAMPLE.MAXCNT
+    events_end: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 500],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTE::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE::Register>,
+    _reserved2: [u8; 500],
+    /// PDM module enable register
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// PDM clock generator control
+    pdmclkctrl: ReadWrite<u32>,
+    /// Defines the routing of the connected PDM microphones' signals
+    mode: ReadWrite<u32, MODE::Register>,
+    _reserved3: [u8; 12],
+    /// Left output gain adjustment
+    gainl: ReadWrite<u32, GAIN::Register>,
+    /// Right output gain adjustment
+    gainr: ReadWrite<u32, GAIN::Register>,
+    _reserved4: [u8; 32],
+    /// Pin number configuration for PDM CLK signal
+    psel_clk: VolatileCell<Pinmux>,
+    /// Pin number configuration for PDM DIN signal
+    psel_din: VolatileCell<Pinmux>,
+    _reserved5: [u8; 24],
+    /// RAM address pointer to write samples to with EasyDMA
+    sample_ptr: VolatileCell<*const i16>,
+    /// Number of samples to allocate memory for in EasyDMA mode
+    sample_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+}
+
+register_bitfields![u32,
+    TASK [
+        TASK 0
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    INTE [
+        STARTED 0,
+        STOPPED 1,
+        END 2
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    MODE [
+        /// Mono or stereo operation
+        OPERATION OFFSET(0) NUMBITS(1) [
+            Stereo = 0,
+            Mono = 1
+        ],
+        /// Defines on which PDM_CLK edge the left (or mono) channel is sampled
+        EDGE OFFSET(1) NUMBITS(1) [
+            LeftFalling = 0,
+            LeftRising = 1
+        ]
+    ],
+    GAIN [
+        /// Gain in 0.5 dB steps, from 0x00 (-20 dB) to 0x50 (+20 dB)
+        GAIN OFFSET(0) NUMBITS(7) [
+            MinGain = 0x00,
+            DefaultGain = 0x28,
+            MaxGain = 0x50
+        ]
+    ],
+    MAXCNT [
+        BUFFSIZE OFFSET(0) NUMBITS(15)
+    ]
+];
+
+pub static mut PDM: Pdm<'static> = Pdm::new();
+
+pub struct Pdm<'a> {
+    registers: StaticRef<PdmRegisters>,
+    client: OptionalCell<&'a dyn audio::InputClient>,
+    active: Cell<bool>,
+    clock: Cell<usize>,
+    channels: Cell<audio::Channels>,
+    // The buffer the peripheral is currently filling.
+    current: TakeCell<'static, [i16]>,
+    // The buffer the peripheral switches to once `current` is full.
+    next: TakeCell<'static, [i16]>,
+    // Whether `next` has been written to SAMPLE.PTR yet.
+    next_programmed: Cell<bool>,
+}
+
+impl<'a> Pdm<'a> {
+    const fn new() -> Pdm<'a> {
+        Pdm {
+            registers: PDM_BASE,
+            client: OptionalCell::empty(),
+            active: Cell::new(false),
+            clock: Cell::new(1),
+            channels: Cell::new(audio::Channels::Mono),
+            current: TakeCell::empty(),
+            next: TakeCell::empty(),
+            next_programmed: Cell::new(false),
+        }
+    }
+
+    /// Configure which pins the microphone clock and data are connected to.
+    pub fn set_pins(&self, clk: Pinmux, din: Pinmux) {
+        self.registers.psel_clk.set(clk);
+        self.registers.psel_din.set(din);
+    }
+
+    /// Set the gain applied to both channels, in 0.5 dB steps from -20 dB
+    /// (`0x00`) to +20 dB (`0x50`). The default is 0 dB (`0x28`).
+    pub fn set_gain(&self, gain: u8) -> ReturnCode {
+        if gain as u32 > GAIN::GAIN::MaxGain.value {
+            return ReturnCode::EINVAL;
+        }
+        self.registers.gainl.write(GAIN::GAIN.val(gain as u32));
+        self.registers.gainr.write(GAIN::GAIN.val(gain as u32));
+        ReturnCode::SUCCESS
+    }
+
+    fn program_buffer(&self, buffer: &[i16]) {
+        let regs = &*self.registers;
+        regs.sample_ptr.set(buffer.as_ptr());
+        regs.sample_maxcnt
+            .write(MAXCNT::BUFFSIZE.val(buffer.len() as u32));
+    }
+
+    fn valid_buffer(buffer: &[i16]) -> bool {
+        buffer.len() > 0 && buffer.len() <= MAX_SAMPLES
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        // `END` for one buffer and `STARTED` for the following one are
+        // generated together. Handle `END` first so the client gets a chance
+        // to provide a new buffer before we need to program it.
+        if regs.events_end.is_set(EVENT::EVENT) {
+            regs.events_end.write(EVENT::EVENT::CLEAR);
+            if let Some(done) = self.current.take() {
+                if self.next_programmed.get() {
+                    self.next.take().map(|next| self.current.replace(next));
+                    self.next_programmed.set(false);
+                }
+                let length = done.len();
+                self.client
+                    .map(move |client| client.samples_received(done, length));
+            }
+        }
+
+        if regs.events_started.is_set(EVENT::EVENT) {
+            regs.events_started.write(EVENT::EVENT::CLEAR);
+            if self.active.get() {
+                if self.next.is_some() {
+                    self.next.map(|next| self.program_buffer(next));
+                    self.next_programmed.set(true);
+                } else {
+                    // Nowhere to put the samples after the current buffer.
+                    // Stop rather than overwrite a buffer the client owns.
+                    self.active.set(false);
+                    regs.tasks_stop.write(TASK::TASK::SET);
+                }
+            }
+        }
+
+        if regs.events_stopped.is_set(EVENT::EVENT) {
+            regs.events_stopped.write(EVENT::EVENT::CLEAR);
+            regs.intenclr
+                .write(INTE::STARTED::SET + INTE::STOPPED::SET + INTE::END::SET);
+            regs.enable.write(ENABLE::ENABLE::CLEAR);
+            self.next_programmed.set(false);
+        }
+    }
+}
+
+impl<'a> audio::Configure for Pdm<'a> {
+    fn configure(&self, params: audio::Parameters) -> ReturnCode {
+        if self.active.get() {
+            return ReturnCode::EBUSY;
+        }
+        if params.sample_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        // Pick the PDM clock whose decimated rate is closest to the request.
+        let (index, error) = PDM_CLOCKS
+            .iter()
+            .map(|(_, freq)| freq / DECIMATION_RATIO)
+            .map(|rate| {
+                if rate > params.sample_rate {
+                    rate - params.sample_rate
+                } else {
+                    params.sample_rate - rate
+                }
+            })
+            .enumerate()
+            .min_by_key(|(_, error)| *error)
+            .unwrap_or((1, 0));
+        // Reject requests more than 5% away from anything we can produce.
+        if error * 20 > params.sample_rate {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        self.clock.set(index);
+        self.channels.set(params.channels);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_sample_rate(&self) -> u32 {
+        PDM_CLOCKS[self.clock.get()].1 / DECIMATION_RATIO
+    }
+}
+
+impl<'a> audio::AudioInput<'a> for Pdm<'a> {
+    fn set_client(&self, client: &'a dyn audio::InputClient) {
+        self.client.set(client);
+    }
+
+    fn start_receive(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        if self.active.get() || self.registers.enable.is_set(ENABLE::ENABLE) {
+            return (ReturnCode::EBUSY, Some(buffer1), Some(buffer2));
+        }
+        if !Self::valid_buffer(buffer1) || !Self::valid_buffer(buffer2) {
+            return (ReturnCode::ESIZE, Some(buffer1), Some(buffer2));
+        }
+
+        let regs = &*self.registers;
+        regs.pdmclkctrl.set(PDM_CLOCKS[self.clock.get()].0);
+        match self.channels.get() {
+            audio::Channels::Mono => {
+                regs.mode
+                    .write(MODE::OPERATION::Mono + MODE::EDGE::LeftFalling);
+            }
+            audio::Channels::Stereo => {
+                regs.mode
+                    .write(MODE::OPERATION::Stereo + MODE::EDGE::LeftFalling);
+            }
+        }
+
+        self.program_buffer(buffer1);
+        self.current.replace(buffer1);
+        self.next.replace(buffer2);
+        self.next_programmed.set(false);
+        self.active.set(true);
+
+        regs.events_started.write(EVENT::EVENT::CLEAR);
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.events_end.write(EVENT::EVENT::CLEAR);
+        regs.intenset
+            .write(INTE::STARTED::SET + INTE::STOPPED::SET + INTE::END::SET);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+
+        (ReturnCode::SUCCESS, None, None)
+    }
+
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        if !self.active.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        }
+        if !Self::valid_buffer(buffer) {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        if self.next.is_some() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        self.next.replace(buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn stop_receive(&self) -> ReturnCode {
+        if !self.active.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.active.set(false);
+        self.registers.tasks_stop.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        if self.active.get() || self.registers.enable.is_set(ENABLE::ENABLE) {
+            return (ReturnCode::EBUSY, None, None);
+        }
+        (ReturnCode::SUCCESS, self.current.take(), self.next.take())
+    }
+}
@@ -0,0 +1,446 @@
+//! Quad SPI (QSPI) external flash interface for nRF52840.
+//!
+//! The QSPI peripheral drives a serial NOR flash over up to four data lines.
+//! Reads, writes and erases are started with tasks and transfer data with
+//! EasyDMA; the peripheral sends write enable instructions, splits writes into
+//! page programs and polls the flash status register by itself, and signals
+//! the end of every operation with the `READY` event. Other flash instructions
+//! are sent as custom instructions of up to 8 data bytes.
+//!
+//! While the peripheral is idle, the flash can also be read through the
+//! execute in place (XIP) window at 0x12000000.
+//!
+//! EasyDMA requires word aligned buffers and flash addresses, and transfer
+//! lengths that are a multiple of four bytes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf52::qspi::QSPI.initialize(
+//!     nrf52::pinmux::Pinmux::new(QSPI_SCK as u32),
+//!     nrf52::pinmux::Pinmux::new(QSPI_CSN as u32),
+//!     [
+//!         nrf52::pinmux::Pinmux::new(QSPI_IO0 as u32),
+//!         nrf52::pinmux::Pinmux::new(QSPI_IO1 as u32),
+//!         nrf52::pinmux::Pinmux::new(QSPI_IO2 as u32),
+//!         nrf52::pinmux::Pinmux::new(QSPI_IO3 as u32),
+//!     ],
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::qspi;
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const QSPI_BASE: StaticRef<QspiRegisters> =
+    unsafe { StaticRef::new(0x40029000 as *const QspiRegisters) };
+
+/// Start of the XIP window.
+const XIP_BASE: usize = 0x12000000;
+
+/// Size of the XIP window, which is also the largest supported flash.
+const XIP_SIZE: usize = 0x08000000;
+
+/// Largest number of bytes in a single EasyDMA transfer.
+const MAX_TRANSFER: usize = 0x3FFFC;
+
+#[repr(C)]
+struct QspiRegisters {
+    /// Activate QSPI interface
+    tasks_activate: WriteOnly<u32, TASK::Register>,
+    /// Start transfer from external flash memory to internal RAM
+    tasks_readstart: WriteOnly<u32, TASK::Register>,
+    /// Start transfer from internal RAM to external flash memory
+    tasks_writestart: WriteOnly<u32, TASK::Register>,
+    /// Start external flash memory erase operation
+    tasks_erasestart: WriteOnly<u32, TASK::Register>,
+    /// Deactivate QSPI interface
+    tasks_deactivate: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 236],
+    /// QSPI peripheral is ready
+    events_ready: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 508],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTE::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE::Register>,
+    _reserved2: [u8; 500],
+    /// Enable QSPI peripheral and acquire the pins selected in PSELn registers
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// Flash memory source address
+    read_src: ReadWrite<u32>,
+    /// RAM destination address
+    read_dst: VolatileCell<*const u8>,
+    /// Read transfer length
+    read_cnt: ReadWrite<u32, CNT::Register>,
+    /// Flash destination address
+    write_dst: ReadWrite<u32>,
+    /// RAM source address
+    write_src: VolatileCell<*const u8>,
+    /// Write transfer length
+    write_cnt: ReadWrite<u32, CNT::Register>,
+    /// Start address of flash block to be erased
+    erase_ptr: ReadWrite<u32>,
+    /// Size of block to be erased
+    erase_len: ReadWrite<u32, ERASE_LEN::Register>,
+    /// Pin select for serial clock SCK
+    psel_sck: VolatileCell<Pinmux>,
+    /// Pin select for chip select signal CSN
+    psel_csn: VolatileCell<Pinmux>,
+    _reserved3: [u8; 4],
+    /// Pin select for serial data IO0 to IO3
+    psel_io: [VolatileCell<Pinmux>; 4],
+    /// Address offset into the external memory for XIP
+    xipoffset: ReadWrite<u32>,
+    /// Interface configuration
+    ifconfig0: ReadWrite<u32, IFCONFIG0::Register>,
+    _reserved4: [u8; 184],
+    /// Interface configuration
+    ifconfig1: ReadWrite<u32, IFCONFIG1::Register>,
+    /// Status register
+    status: ReadOnly<u32, STATUS::Register>,
+    _reserved5: [u8; 12],
+    /// Set the duration required to enter/exit deep power-down mode
+    dpmdur: ReadWrite<u32>,
+    _reserved6: [u8; 12],
+    /// Extended address configuration
+    addrconf: ReadWrite<u32>,
+    _reserved7: [u8; 12],
+    /// Custom instruction configuration register
+    cinstrconf: ReadWrite<u32, CINSTRCONF::Register>,
+    /// Custom instruction data register 0 and 1
+    cinstrdat: [ReadWrite<u32>; 2],
+}
+
+register_bitfields![u32,
+    TASK [
+        TASK 0
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    INTE [
+        READY 0
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    CNT [
+        CNT OFFSET(0) NUMBITS(18)
+    ],
+    ERASE_LEN [
+        LEN OFFSET(0) NUMBITS(2) [
+            Erase4KB = 0,
+            Erase64KB = 1,
+            All = 2
+        ]
+    ],
+    IFCONFIG0 [
+        /// Opcode used for read operations
+        READOC OFFSET(0) NUMBITS(3) [
+            FastRead = 0,
+            Read2O = 1,
+            Read2IO = 2,
+            Read4O = 3,
+            Read4IO = 4
+        ],
+        /// Opcode used for write operations
+        WRITEOC OFFSET(3) NUMBITS(3) [
+            PP = 0,
+            PP2O = 1,
+            PP4O = 2,
+            PP4IO = 3
+        ],
+        /// Addressing mode
+        ADDRMODE OFFSET(6) NUMBITS(1) [
+            Bit24 = 0,
+            Bit32 = 1
+        ],
+        /// Enable deep power-down mode feature
+        DPMENABLE OFFSET(7) NUMBITS(1) [],
+        /// Page size for commands PP, PP2O, PP4O and PP4IO
+        PPSIZE OFFSET(12) NUMBITS(1) [
+            Bytes256 = 0,
+            Bytes512 = 1
+        ]
+    ],
+    IFCONFIG1 [
+        /// Minimum time CSN must stay high, in 62.5 ns units
+        SCKDELAY OFFSET(0) NUMBITS(8) [],
+        /// Enter deep power-down mode
+        DPMEN OFFSET(24) NUMBITS(1) [],
+        /// Select SPI mode
+        SPIMODE OFFSET(25) NUMBITS(1) [
+            Mode0 = 0,
+            Mode3 = 1
+        ],
+        /// SCK frequency is 32 MHz / (SCKFREQ + 1)
+        SCKFREQ OFFSET(28) NUMBITS(4) []
+    ],
+    STATUS [
+        /// Deep power-down mode status
+        DPM OFFSET(2) NUMBITS(1) [],
+        /// Ready status
+        READY OFFSET(3) NUMBITS(1) [],
+        /// Value of the status register of the external flash
+        SREG OFFSET(24) NUMBITS(8) []
+    ],
+    CINSTRCONF [
+        /// Opcode of custom instruction
+        OPCODE OFFSET(0) NUMBITS(8) [],
+        /// Length of custom instruction in number of bytes, including the
+        /// opcode
+        LENGTH OFFSET(8) NUMBITS(4) [],
+        /// Level of the IO2 line during the instruction
+        LIO2 OFFSET(12) NUMBITS(1) [],
+        /// Level of the IO3 line during the instruction
+        LIO3 OFFSET(13) NUMBITS(1) [],
+        /// Wait for the write in progress bit to clear after the instruction
+        WIPWAIT OFFSET(14) NUMBITS(1) [],
+        /// Send write enable before the instruction
+        WREN OFFSET(15) NUMBITS(1) []
+    ]
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Writing,
+    Erasing,
+    Command,
+    Xip,
+}
+
+pub struct Qspi<'a> {
+    registers: StaticRef<QspiRegisters>,
+    client: OptionalCell<&'a dyn qspi::QspiClient>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    command_len: Cell<usize>,
+}
+
+pub static mut QSPI: Qspi<'static> = Qspi::new();
+
+impl<'a> Qspi<'a> {
+    const fn new() -> Qspi<'a> {
+        Qspi {
+            registers: QSPI_BASE,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            command_len: Cell::new(0),
+        }
+    }
+
+    /// Configure the pins and enable the interface for quad I/O at 16 MHz.
+    ///
+    /// The flash must also have quad mode enabled before operations that use
+    /// four data lines; this is flash specific and left to the user.
+    pub fn initialize(&self, sck: Pinmux, csn: Pinmux, io: [Pinmux; 4]) {
+        let regs = &*self.registers;
+        regs.psel_sck.set(sck);
+        regs.psel_csn.set(csn);
+        for (psel, pin) in regs.psel_io.iter().zip(io.iter()) {
+            psel.set(*pin);
+        }
+
+        regs.ifconfig0.write(
+            IFCONFIG0::READOC::Read4IO
+                + IFCONFIG0::WRITEOC::PP4IO
+                + IFCONFIG0::ADDRMODE::Bit24
+                + IFCONFIG0::PPSIZE::Bytes256,
+        );
+        regs.ifconfig1.write(
+            IFCONFIG1::SCKDELAY.val(1) + IFCONFIG1::SPIMODE::Mode0 + IFCONFIG1::SCKFREQ.val(1),
+        );
+        regs.xipoffset.set(0);
+
+        regs.enable.write(ENABLE::ENABLE::SET);
+
+        // Operations can only start once the interface has become active,
+        // which takes a few microseconds.
+        regs.events_ready.write(EVENT::EVENT::CLEAR);
+        regs.tasks_activate.write(TASK::TASK::SET);
+        while !regs.events_ready.is_set(EVENT::EVENT) {}
+        regs.events_ready.write(EVENT::EVENT::CLEAR);
+        regs.intenset.write(INTE::READY::SET);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if !regs.events_ready.is_set(EVENT::EVENT) {
+            return;
+        }
+        regs.events_ready.write(EVENT::EVENT::CLEAR);
+
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        match state {
+            State::Reading => {
+                self.buffer.take().map(|buffer| {
+                    self.client.map(move |client| {
+                        client.read_done(buffer, self.len.get(), ReturnCode::SUCCESS)
+                    });
+                });
+            }
+            State::Writing => {
+                self.buffer.take().map(|buffer| {
+                    self.client.map(move |client| {
+                        client.write_done(buffer, self.len.get(), ReturnCode::SUCCESS)
+                    });
+                });
+            }
+            State::Erasing => {
+                self.client
+                    .map(|client| client.erase_done(ReturnCode::SUCCESS));
+            }
+            State::Command => {
+                let mut response = [0; 8];
+                for (i, byte) in response.iter_mut().enumerate() {
+                    *byte = (regs.cinstrdat[i / 4].get() >> (8 * (i % 4))) as u8;
+                }
+                self.client.map(move |client| {
+                    client.command_done(&response[..self.command_len.get()], ReturnCode::SUCCESS)
+                });
+            }
+            State::Xip => self.state.set(State::Xip),
+            State::Idle => {}
+        }
+    }
+
+    fn check_transfer(&self, address: u32, buffer: &[u8], len: usize) -> Result<(), ReturnCode> {
+        if self.state.get() != State::Idle {
+            Err(ReturnCode::EBUSY)
+        } else if len > buffer.len() || len > MAX_TRANSFER {
+            Err(ReturnCode::ESIZE)
+        } else if address % 4 != 0 || buffer.as_ptr() as usize % 4 != 0 || len % 4 != 0 {
+            Err(ReturnCode::EINVAL)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> qspi::QspiMaster<'a> for Qspi<'a> {
+    fn set_client(&self, client: &'a dyn qspi::QspiClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        address: u32,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if let Err(rval) = self.check_transfer(address, buffer, len) {
+            return Err((rval, buffer));
+        }
+        let regs = &*self.registers;
+        regs.read_src.set(address);
+        regs.read_dst.set(buffer.as_ptr());
+        regs.read_cnt.write(CNT::CNT.val(len as u32));
+        self.buffer.replace(buffer);
+        self.len.set(len);
+        self.state.set(State::Reading);
+        regs.tasks_readstart.write(TASK::TASK::SET);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        address: u32,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if let Err(rval) = self.check_transfer(address, buffer, len) {
+            return Err((rval, buffer));
+        }
+        let regs = &*self.registers;
+        regs.write_dst.set(address);
+        regs.write_src.set(buffer.as_ptr());
+        regs.write_cnt.write(CNT::CNT.val(len as u32));
+        self.buffer.replace(buffer);
+        self.len.set(len);
+        self.state.set(State::Writing);
+        regs.tasks_writestart.write(TASK::TASK::SET);
+        Ok(())
+    }
+
+    fn erase(&self, address: u32, size: qspi::EraseSize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let regs = &*self.registers;
+        regs.erase_ptr.set(address);
+        regs.erase_len.write(match size {
+            qspi::EraseSize::Sector4K => ERASE_LEN::LEN::Erase4KB,
+            qspi::EraseSize::Block64K => ERASE_LEN::LEN::Erase64KB,
+            qspi::EraseSize::Chip => ERASE_LEN::LEN::All,
+        });
+        self.state.set(State::Erasing);
+        regs.tasks_erasestart.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn command(&self, opcode: u8, data: &[u8], write: bool) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if data.len() > 8 {
+            return ReturnCode::ESIZE;
+        }
+        let regs = &*self.registers;
+        let mut words = [0u32; 2];
+        for (i, byte) in data.iter().enumerate() {
+            words[i / 4] |= (*byte as u32) << (8 * (i % 4));
+        }
+        regs.cinstrdat[0].set(words[0]);
+        regs.cinstrdat[1].set(words[1]);
+        self.command_len.set(data.len());
+        self.state.set(State::Command);
+
+        // Keep IO2 (write protect) and IO3 (hold) high so the flash accepts
+        // the instruction. Writing the register starts the instruction.
+        let wait = if write { 1 } else { 0 };
+        regs.cinstrconf.write(
+            CINSTRCONF::OPCODE.val(opcode as u32)
+                + CINSTRCONF::LENGTH.val(data.len() as u32 + 1)
+                + CINSTRCONF::LIO2::SET
+                + CINSTRCONF::LIO3::SET
+                + CINSTRCONF::WIPWAIT.val(wait)
+                + CINSTRCONF::WREN.val(wait),
+        );
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> qspi::QspiXip for Qspi<'a> {
+    fn enable_xip(&self, offset: u32) -> Result<&'static [u8], ReturnCode> {
+        if self.state.get() != State::Idle {
+            return Err(ReturnCode::EBUSY);
+        }
+        let offset = offset as usize;
+        if offset >= XIP_SIZE {
+            return Err(ReturnCode::EINVAL);
+        }
+        self.registers.xipoffset.set(offset as u32);
+        self.state.set(State::Xip);
+        Ok(unsafe { core::slice::from_raw_parts(XIP_BASE as *const u8, XIP_SIZE - offset) })
+    }
+
+    fn disable_xip(&self) {
+        if self.state.get() == State::Xip {
+            self.state.set(State::Idle);
+        }
+    }
+}
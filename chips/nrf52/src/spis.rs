@@ -0,0 +1,383 @@
+//! Implementation of SPI slave (`SPIS`) for nRF52 using EasyDMA.
+//!
+//! The SPIS shares its buffers with the CPU through a hardware semaphore. The
+//! CPU acquires the semaphore to update the buffer pointers, and releases it to
+//! let the SPIS use them for the next transaction started by the master. A
+//! shortcut makes the SPIS hand the semaphore back to the CPU at the end of
+//! every transaction, so the buffers of one transaction are never reused for
+//! the next. While the CPU holds the semaphore, the SPIS answers the master
+//! with the default character.
+//!
+//! The SPIS has no event for the chip select line going low, so
+//! `SpiSlaveClient::chip_selected` is never called.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf52::spis::SPIS2.configure(
+//!     nrf52::pinmux::Pinmux::new(SPIS_SCK as u32),
+//!     nrf52::pinmux::Pinmux::new(SPIS_MISO as u32),
+//!     nrf52::pinmux::Pinmux::new(SPIS_MOSI as u32),
+//!     nrf52::pinmux::Pinmux::new(SPIS_CSN as u32),
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiSlaveClient};
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const INSTANCES: [StaticRef<SpisRegisters>; 3] = unsafe {
+    [
+        StaticRef::new(0x40003000 as *const SpisRegisters),
+        StaticRef::new(0x40004000 as *const SpisRegisters),
+        StaticRef::new(0x40023000 as *const SpisRegisters),
+    ]
+};
+
+#[repr(C)]
+struct SpisRegisters {
+    _reserved0: [u8; 36],
+    /// Acquire SPI semaphore
+    tasks_acquire: WriteOnly<u32, TASK::Register>,
+    /// Release SPI semaphore, enabling the SPI slave to acquire it
+    tasks_release: WriteOnly<u32, TASK::Register>,
+    _reserved1: [u8; 216],
+    /// Granted transaction completed
+    events_end: ReadWrite<u32, EVENT::Register>,
+    _reserved2: [u8; 8],
+    /// End of RXD buffer reached
+    events_endrx: ReadWrite<u32, EVENT::Register>,
+    _reserved3: [u8; 20],
+    /// Semaphore acquired
+    events_acquired: ReadWrite<u32, EVENT::Register>,
+    _reserved4: [u8; 212],
+    /// Shortcut register
+    shorts: ReadWrite<u32, SHORTS::Register>,
+    _reserved5: [u8; 256],
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE::Register>,
+    _reserved6: [u8; 244],
+    /// Semaphore status register
+    semstat: ReadWrite<u32, SEMSTAT::Register>,
+    _reserved7: [u8; 60],
+    /// Status from last transaction
+    status: ReadWrite<u32, STATUS::Register>,
+    _reserved8: [u8; 188],
+    /// Enable SPI slave
+    enable: ReadWrite<u32, ENABLE::Register>,
+    _reserved9: [u8; 4],
+    /// Pin select for SCK
+    psel_sck: VolatileCell<Pinmux>,
+    /// Pin select for MISO signal
+    psel_miso: VolatileCell<Pinmux>,
+    /// Pin select for MOSI signal
+    psel_mosi: VolatileCell<Pinmux>,
+    /// Pin select for CSN signal
+    psel_csn: VolatileCell<Pinmux>,
+    _reserved10: [u8; 28],
+    /// RXD data pointer
+    rxd_ptr: VolatileCell<*mut u8>,
+    /// Maximum number of bytes in receive buffer
+    rxd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes received in last granted transaction
+    rxd_amount: ReadWrite<u32>,
+    _reserved11: [u8; 4],
+    /// TXD data pointer
+    txd_ptr: VolatileCell<*const u8>,
+    /// Maximum number of bytes in transmit buffer
+    txd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes transmitted in last granted transaction
+    txd_amount: ReadWrite<u32>,
+    _reserved12: [u8; 4],
+    /// Configuration register
+    config: ReadWrite<u32, CONFIG::Register>,
+    _reserved13: [u8; 4],
+    /// Default character, clocked out while the CPU holds the semaphore
+    def: ReadWrite<u32, CHAR::Register>,
+    _reserved14: [u8; 96],
+    /// Over-read character, clocked out once the TXD buffer is exhausted
+    orc: ReadWrite<u32, CHAR::Register>,
+}
+
+register_bitfields![u32,
+    TASK [
+        TASK 0
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    SHORTS [
+        /// Shortcut between EVENTS_END event and TASKS_ACQUIRE task
+        END_ACQUIRE 2
+    ],
+    INTE [
+        /// Interrupt on EVENTS_END event
+        END 1,
+        /// Interrupt on EVENTS_ENDRX event
+        ENDRX 4,
+        /// Interrupt on EVENTS_ACQUIRED event
+        ACQUIRED 10
+    ],
+    SEMSTAT [
+        SEMSTAT OFFSET(0) NUMBITS(2) [
+            Free = 0,
+            CPU = 1,
+            SPIS = 2,
+            CPUPending = 3
+        ]
+    ],
+    STATUS [
+        /// TX buffer over-read detected
+        OVERREAD 0,
+        /// RX buffer overflow detected
+        OVERFLOW 1
+    ],
+    ENABLE [
+        ENABLE OFFSET(0) NUMBITS(4) [
+            Disable = 0,
+            Enable = 2
+        ]
+    ],
+    MAXCNT [
+        /// Maximum number of bytes in buffer
+        MAXCNT OFFSET(0) NUMBITS(16)
+    ],
+    CONFIG [
+        /// Bit order
+        ORDER OFFSET(0) NUMBITS(1) [
+            MostSignificantBitShiftedOutFirst = 0,
+            LeastSignificantBitShiftedOutFirst = 1
+        ],
+        /// Serial clock (SCK) phase
+        CPHA OFFSET(1) NUMBITS(1) [
+            SampleOnLeadingEdge = 0,
+            SampleOnTrailingEdge = 1
+        ],
+        /// Serial clock (SCK) polarity
+        CPOL OFFSET(2) NUMBITS(1) [
+            ActiveHigh = 0,
+            ActiveLow = 1
+        ]
+    ],
+    CHAR [
+        CHAR OFFSET(0) NUMBITS(8)
+    ]
+];
+
+/// An SPI slave device.
+pub struct SPIS {
+    registers: StaticRef<SpisRegisters>,
+    client: OptionalCell<&'static dyn SpiSlaveClient>,
+    /// Whether the CPU holds the semaphore.
+    acquired: Cell<bool>,
+    /// Whether buffers are waiting for the CPU to acquire the semaphore.
+    pending: Cell<bool>,
+    /// Whether the SPIS owns the buffers for the next transaction.
+    busy: Cell<bool>,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+    transfer_len: Cell<usize>,
+}
+
+/// SPI slave instance 0.
+pub static mut SPIS0: SPIS = SPIS::new(0);
+/// SPI slave instance 1.
+pub static mut SPIS1: SPIS = SPIS::new(1);
+/// SPI slave instance 2.
+pub static mut SPIS2: SPIS = SPIS::new(2);
+
+impl SPIS {
+    const fn new(instance: usize) -> SPIS {
+        SPIS {
+            registers: INSTANCES[instance],
+            client: OptionalCell::empty(),
+            acquired: Cell::new(false),
+            pending: Cell::new(false),
+            busy: Cell::new(false),
+            tx_buf: TakeCell::empty(),
+            rx_buf: TakeCell::empty(),
+            transfer_len: Cell::new(0),
+        }
+    }
+
+    /// Configures the pins of an already constructed `SPIS`.
+    pub fn configure(&self, sck: Pinmux, miso: Pinmux, mosi: Pinmux, csn: Pinmux) {
+        self.registers.psel_sck.set(sck);
+        self.registers.psel_miso.set(miso);
+        self.registers.psel_mosi.set(mosi);
+        self.registers.psel_csn.set(csn);
+    }
+
+    /// Enables `SPIS` peripheral.
+    pub fn enable(&self) {
+        self.registers.enable.write(ENABLE::ENABLE::Enable);
+    }
+
+    /// Disables `SPIS` peripheral.
+    pub fn disable(&self) {
+        self.registers.enable.write(ENABLE::ENABLE::Disable);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.registers.enable.matches_all(ENABLE::ENABLE::Enable)
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.registers.events_end.is_set(EVENT::EVENT) {
+            self.registers.events_end.write(EVENT::EVENT::CLEAR);
+            self.registers.events_endrx.write(EVENT::EVENT::CLEAR);
+            if self.busy.get() {
+                self.busy.set(false);
+                let len = self.transfer_len.get();
+                let tx_buf = self.tx_buf.take();
+                let rx_buf = self.rx_buf.take();
+                self.client
+                    .map(move |client| client.read_write_done(tx_buf, rx_buf, len));
+            }
+        }
+
+        if self.registers.events_acquired.is_set(EVENT::EVENT) {
+            self.registers.events_acquired.write(EVENT::EVENT::CLEAR);
+            self.acquired.set(true);
+            if self.pending.get() {
+                self.start_transfer();
+            }
+        }
+    }
+
+    /// Hand the pending buffers to the SPIS for the next transaction. The CPU
+    /// must hold the semaphore.
+    fn start_transfer(&self) {
+        let len = self.transfer_len.get();
+        self.tx_buf.map(|buf| {
+            self.registers.txd_ptr.set(buf.as_ptr());
+            self.registers
+                .txd_maxcnt
+                .write(MAXCNT::MAXCNT.val(cmp::min(len, buf.len()) as u32));
+        });
+        if self.tx_buf.is_none() {
+            self.registers.txd_maxcnt.write(MAXCNT::MAXCNT.val(0));
+        }
+        self.rx_buf.map(|buf| {
+            self.registers.rxd_ptr.set(buf.as_mut_ptr());
+            self.registers
+                .rxd_maxcnt
+                .write(MAXCNT::MAXCNT.val(cmp::min(len, buf.len()) as u32));
+        });
+        if self.rx_buf.is_none() {
+            self.registers.rxd_maxcnt.write(MAXCNT::MAXCNT.val(0));
+        }
+
+        self.pending.set(false);
+        self.busy.set(true);
+        self.acquired.set(false);
+        self.registers.tasks_release.write(TASK::TASK::SET);
+    }
+}
+
+impl spi::SpiSlave for SPIS {
+    fn init(&self) {
+        self.registers
+            .config
+            .modify(CONFIG::ORDER::MostSignificantBitShiftedOutFirst);
+        self.registers.shorts.write(SHORTS::END_ACQUIRE::SET);
+        self.registers
+            .intenset
+            .write(INTE::END::SET + INTE::ACQUIRED::SET);
+        self.enable();
+        // Take the semaphore so the buffers can be set up.
+        self.registers.tasks_acquire.write(TASK::TASK::SET);
+    }
+
+    fn has_client(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn set_client(&self, client: Option<&'static dyn SpiSlaveClient>) {
+        self.client.insert(client);
+    }
+
+    /// Sets the byte sent to the master while no transmit buffer is set up.
+    fn set_write_byte(&self, write_byte: u8) {
+        self.registers.def.write(CHAR::CHAR.val(write_byte as u32));
+        self.registers.orc.write(CHAR::CHAR.val(write_byte as u32));
+    }
+
+    /// Setup buffers for the next SPI transaction initiated by the master.
+    ///
+    /// Returns:
+    /// - `SUCCESS` if the buffers were accepted. A callback will be generated
+    ///   once the master ends the transaction.
+    /// - `EINVAL` if neither the read or write buffer is provided.
+    /// - `EBUSY` if buffers are already set up.
+    fn read_write_bytes(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if write_buffer.is_none() && read_buffer.is_none() {
+            return ReturnCode::EINVAL;
+        }
+        if self.busy.get() || self.pending.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        let mut count = len;
+        write_buffer
+            .as_ref()
+            .map(|buf| count = cmp::min(count, buf.len()));
+        read_buffer
+            .as_ref()
+            .map(|buf| count = cmp::min(count, buf.len()));
+        self.transfer_len.set(count);
+        self.tx_buf.put(write_buffer);
+        self.rx_buf.put(read_buffer);
+        self.pending.set(true);
+
+        if self.acquired.get() {
+            self.start_transfer();
+        } else {
+            self.registers.tasks_acquire.write(TASK::TASK::SET);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        let cpol = match polarity {
+            ClockPolarity::IdleLow => CONFIG::CPOL::ActiveHigh,
+            ClockPolarity::IdleHigh => CONFIG::CPOL::ActiveLow,
+        };
+        self.registers.config.modify(cpol);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        match self.registers.config.read(CONFIG::CPOL) {
+            0 => ClockPolarity::IdleLow,
+            _ => ClockPolarity::IdleHigh,
+        }
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        let cpha = match phase {
+            ClockPhase::SampleLeading => CONFIG::CPHA::SampleOnLeadingEdge,
+            ClockPhase::SampleTrailing => CONFIG::CPHA::SampleOnTrailingEdge,
+        };
+        self.registers.config.modify(cpha);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        match self.registers.config.read(CONFIG::CPHA) {
+            0 => ClockPhase::SampleLeading,
+            _ => ClockPhase::SampleTrailing,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Implementation of I2C slave (`TWIS`) for nRF52 using EasyDMA.
+//!
+//! When a master addresses the `TWIS`, it stretches the clock until a buffer
+//! for the transfer has been prepared. Buffers given to `write_receive` and
+//! `read_send` ahead of time are prepared right away, so the master is only
+//! held up if the client has not provided one yet.
+//!
+//! The `TWIS` shares its registers with `TWIM`, `SPIM` and `SPIS` of the same
+//! instance, so only one of them can be enabled at a time. See
+//! `i2c::TWIMasterSlave` for switching between master and slave mode.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil;
+use nrf5x::pinmux::Pinmux;
+
+const INSTANCES: [StaticRef<TwisRegisters>; 2] = unsafe {
+    [
+        StaticRef::new(0x40003000 as *const TwisRegisters),
+        StaticRef::new(0x40004000 as *const TwisRegisters),
+    ]
+};
+
+#[repr(C)]
+struct TwisRegisters {
+    _reserved0: [u8; 20],
+    /// Stop TWI transaction
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved1: [u8; 24],
+    /// Prepare the TWI slave to respond to a write command
+    tasks_preparerx: WriteOnly<u32, TASK::Register>,
+    /// Prepare the TWI slave to respond to a read command
+    tasks_preparetx: WriteOnly<u32, TASK::Register>,
+    _reserved2: [u8; 204],
+    /// TWI stopped
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    _reserved3: [u8; 28],
+    /// TWI error
+    events_error: ReadWrite<u32, EVENT::Register>,
+    _reserved4: [u8; 36],
+    /// Receive sequence started
+    events_rxstarted: ReadWrite<u32, EVENT::Register>,
+    /// Transmit sequence started
+    events_txstarted: ReadWrite<u32, EVENT::Register>,
+    _reserved5: [u8; 16],
+    /// Write command received
+    events_write: ReadWrite<u32, EVENT::Register>,
+    /// Read command received
+    events_read: ReadWrite<u32, EVENT::Register>,
+    _reserved6: [u8; 404],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTE::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE::Register>,
+    _reserved7: [u8; 452],
+    /// Error source
+    errorsrc: ReadWrite<u32, ERRORSRC::Register>,
+    /// Status register indicating which address had a match
+    match_: ReadWrite<u32>,
+    _reserved8: [u8; 40],
+    /// Enable TWIS
+    enable: ReadWrite<u32, ENABLE::Register>,
+    _reserved9: [u8; 4],
+    /// Pin select for SCL signal
+    psel_scl: VolatileCell<Pinmux>,
+    /// Pin select for SDA signal
+    psel_sda: VolatileCell<Pinmux>,
+    _reserved10: [u8; 36],
+    /// RXD data pointer
+    rxd_ptr: VolatileCell<*mut u8>,
+    /// Maximum number of bytes in RXD buffer
+    rxd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes transferred in the last RXD transaction
+    rxd_amount: ReadWrite<u32>,
+    _reserved11: [u8; 4],
+    /// TXD data pointer
+    txd_ptr: VolatileCell<*mut u8>,
+    /// Maximum number of bytes in TXD buffer
+    txd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes transferred in the last TXD transaction
+    txd_amount: ReadWrite<u32>,
+    _reserved12: [u8; 56],
+    /// TWI slave address 0 and 1
+    address: [ReadWrite<u32, ADDRESS::Register>; 2],
+    _reserved13: [u8; 4],
+    /// Configuration register for the address match mechanism
+    config: ReadWrite<u32, CONFIG::Register>,
+    _reserved14: [u8; 40],
+    /// Over-read character, sent when the TXD buffer is exhausted
+    orc: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    INTE [
+        /// Interrupt on EVENTS_STOPPED event
+        STOPPED 1,
+        /// Interrupt on EVENTS_ERROR event
+        ERROR 9,
+        /// Interrupt on EVENTS_WRITE event
+        WRITE 25,
+        /// Interrupt on EVENTS_READ event
+        READ 26
+    ],
+    ERRORSRC [
+        /// RX buffer overflow detected, and prevented (write '1' to clear)
+        OVERFLOW 0,
+        /// NACK sent after receiving a data byte (write '1' to clear)
+        DNACK 2,
+        /// TX buffer over-read detected, and prevented (write '1' to clear)
+        OVERREAD 3
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ],
+    ENABLE [
+        /// Enable or disable TWIS
+        ENABLE OFFSET(0) NUMBITS(4) [
+            Disable = 0,
+            Enable = 9
+        ]
+    ],
+    MAXCNT [
+        /// Maximum number of bytes in buffer
+        MAXCNT OFFSET(0) NUMBITS(16)
+    ],
+    ADDRESS [
+        /// TWI slave address
+        ADDRESS OFFSET(0) NUMBITS(7)
+    ],
+    CONFIG [
+        /// Enable or disable address matching on ADDRESS[0]
+        ADDRESS0 0,
+        /// Enable or disable address matching on ADDRESS[1]
+        ADDRESS1 1
+    ]
+];
+
+/// The transfer the master started most recently.
+#[derive(Copy, Clone, PartialEq)]
+enum Transfer {
+    None,
+    Write,
+    Read,
+}
+
+/// An I2C slave device.
+pub struct TWIS {
+    registers: StaticRef<TwisRegisters>,
+    client: OptionalCell<&'static dyn hil::i2c::I2CHwSlaveClient>,
+    transfer: Cell<Transfer>,
+    rx_buf: TakeCell<'static, [u8]>,
+    tx_buf: TakeCell<'static, [u8]>,
+}
+
+/// I2C slave instance 0.
+pub static mut TWIS0: TWIS = TWIS::new(INSTANCES[0]);
+/// I2C slave instance 1.
+pub static mut TWIS1: TWIS = TWIS::new(INSTANCES[1]);
+
+impl TWIS {
+    const fn new(registers: StaticRef<TwisRegisters>) -> TWIS {
+        TWIS {
+            registers: registers,
+            client: OptionalCell::empty(),
+            transfer: Cell::new(Transfer::None),
+            rx_buf: TakeCell::empty(),
+            tx_buf: TakeCell::empty(),
+        }
+    }
+
+    /// Configures an already constructed `TWIS`.
+    pub fn configure(&self, scl: Pinmux, sda: Pinmux) {
+        self.registers.psel_scl.set(scl);
+        self.registers.psel_sda.set(sda);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.registers.enable.matches_all(ENABLE::ENABLE::Enable)
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.registers.events_write.is_set(EVENT::EVENT) {
+            self.registers.events_write.write(EVENT::EVENT::CLEAR);
+            self.transfer.set(Transfer::Write);
+            if self.rx_buf.is_none() {
+                self.client.map(|client| client.write_expected());
+            }
+        }
+
+        if self.registers.events_read.is_set(EVENT::EVENT) {
+            self.registers.events_read.write(EVENT::EVENT::CLEAR);
+            self.transfer.set(Transfer::Read);
+            if self.tx_buf.is_none() {
+                self.client.map(|client| client.read_expected());
+            }
+        }
+
+        if self.registers.events_error.is_set(EVENT::EVENT) {
+            // Overflows and over-reads are truncated by the hardware, so the
+            // transfer still completes normally when the master stops.
+            self.registers.events_error.write(EVENT::EVENT::CLEAR);
+            self.registers
+                .errorsrc
+                .write(ERRORSRC::OVERFLOW::SET + ERRORSRC::DNACK::SET + ERRORSRC::OVERREAD::SET);
+        }
+
+        if self.registers.events_stopped.is_set(EVENT::EVENT) {
+            self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
+            match self.transfer.get() {
+                Transfer::Write => {
+                    let amount = self.registers.rxd_amount.get() as u8;
+                    self.rx_buf.take().map(|buf| {
+                        self.client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                amount,
+                                hil::i2c::SlaveTransmissionType::Write,
+                            )
+                        });
+                    });
+                }
+                Transfer::Read => {
+                    let amount = self.registers.txd_amount.get() as u8;
+                    self.tx_buf.take().map(|buf| {
+                        self.client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                amount,
+                                hil::i2c::SlaveTransmissionType::Read,
+                            )
+                        });
+                    });
+                }
+                Transfer::None => {}
+            }
+            self.transfer.set(Transfer::None);
+        }
+    }
+}
+
+impl hil::i2c::I2CSlave for TWIS {
+    fn set_slave_client(&self, client: &'static dyn hil::i2c::I2CHwSlaveClient) {
+        self.client.set(client);
+    }
+
+    fn enable(&self) {
+        self.registers.enable.write(ENABLE::ENABLE::Enable);
+    }
+
+    fn disable(&self) {
+        self.registers
+            .intenclr
+            .write(INTE::STOPPED::SET + INTE::ERROR::SET + INTE::WRITE::SET + INTE::READ::SET);
+        self.registers.enable.write(ENABLE::ENABLE::Disable);
+    }
+
+    fn set_address(&self, addr: u8) {
+        self.registers.address[0].write(ADDRESS::ADDRESS.val(addr as u32));
+        self.registers.config.write(CONFIG::ADDRESS0::SET);
+    }
+
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        let len = cmp::min(max_len as usize, data.len());
+        self.registers.rxd_ptr.set(data.as_mut_ptr());
+        self.registers
+            .rxd_maxcnt
+            .write(MAXCNT::MAXCNT.val(len as u32));
+        self.rx_buf.replace(data);
+        self.registers.tasks_preparerx.write(TASK::TASK::SET);
+    }
+
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        let len = cmp::min(max_len as usize, data.len());
+        self.registers.txd_ptr.set(data.as_mut_ptr());
+        self.registers
+            .txd_maxcnt
+            .write(MAXCNT::MAXCNT.val(len as u32));
+        self.tx_buf.replace(data);
+        self.registers.tasks_preparetx.write(TASK::TASK::SET);
+    }
+
+    fn listen(&self) {
+        self.registers
+            .intenset
+            .write(INTE::STOPPED::SET + INTE::ERROR::SET + INTE::WRITE::SET + INTE::READ::SET);
+    }
+}
@@ -995,11 +995,11 @@ impl<'a> Usbd<'a> {
             0 => EndpointEnable::EP0::Enable,
             1 => EndpointEnable::EP1::Enable,
             2 => EndpointEnable::EP2::Enable,
-            3 => EndpointEnable::EP2::Enable,
-            4 => EndpointEnable::EP2::Enable,
-            5 => EndpointEnable::EP2::Enable,
-            6 => EndpointEnable::EP2::Enable,
-            7 => EndpointEnable::EP2::Enable,
+            3 => EndpointEnable::EP3::Enable,
+            4 => EndpointEnable::EP4::Enable,
+            5 => EndpointEnable::EP5::Enable,
+            6 => EndpointEnable::EP6::Enable,
+            7 => EndpointEnable::EP7::Enable,
             8 => EndpointEnable::ISO::Enable,
             _ => unreachable!("unexisting endpoint"),
         });
@@ -1033,11 +1033,11 @@ impl<'a> Usbd<'a> {
             0 => EndpointEnable::EP0::Enable,
             1 => EndpointEnable::EP1::Enable,
             2 => EndpointEnable::EP2::Enable,
-            3 => EndpointEnable::EP2::Enable,
-            4 => EndpointEnable::EP2::Enable,
-            5 => EndpointEnable::EP2::Enable,
-            6 => EndpointEnable::EP2::Enable,
-            7 => EndpointEnable::EP2::Enable,
+            3 => EndpointEnable::EP3::Enable,
+            4 => EndpointEnable::EP4::Enable,
+            5 => EndpointEnable::EP5::Enable,
+            6 => EndpointEnable::EP6::Enable,
+            7 => EndpointEnable::EP7::Enable,
             8 => EndpointEnable::ISO::Enable,
             _ => unreachable!("unexisting endpoint"),
         });
@@ -1071,11 +1071,11 @@ impl<'a> Usbd<'a> {
             0 => EndpointEnable::EP0::Enable,
             1 => EndpointEnable::EP1::Enable,
             2 => EndpointEnable::EP2::Enable,
-            3 => EndpointEnable::EP2::Enable,
-            4 => EndpointEnable::EP2::Enable,
-            5 => EndpointEnable::EP2::Enable,
-            6 => EndpointEnable::EP2::Enable,
-            7 => EndpointEnable::EP2::Enable,
+            3 => EndpointEnable::EP3::Enable,
+            4 => EndpointEnable::EP4::Enable,
+            5 => EndpointEnable::EP5::Enable,
+            6 => EndpointEnable::EP6::Enable,
+            7 => EndpointEnable::EP7::Enable,
             8 => EndpointEnable::ISO::Enable,
             _ => unreachable!("unexisting endpoint"),
         });
@@ -1083,11 +1083,11 @@ impl<'a> Usbd<'a> {
             0 => EndpointEnable::EP0::Enable,
             1 => EndpointEnable::EP1::Enable,
             2 => EndpointEnable::EP2::Enable,
-            3 => EndpointEnable::EP2::Enable,
-            4 => EndpointEnable::EP2::Enable,
-            5 => EndpointEnable::EP2::Enable,
-            6 => EndpointEnable::EP2::Enable,
-            7 => EndpointEnable::EP2::Enable,
+            3 => EndpointEnable::EP3::Enable,
+            4 => EndpointEnable::EP4::Enable,
+            5 => EndpointEnable::EP5::Enable,
+            6 => EndpointEnable::EP6::Enable,
+            7 => EndpointEnable::EP7::Enable,
             8 => EndpointEnable::ISO::Enable,
             _ => unreachable!("unexisting endpoint"),
         });
@@ -1386,50 +1386,57 @@ impl<'a> Usbd<'a> {
                 regs.task_ep0rcvout.write(Task::ENABLE::SET);
             }
             1..=7 => {
-                // Notify the client about the new packet.
-                let packet_bytes = regs.size_epout[endpoint].get();
-                let (transfer_type, in_state, out_state) =
-                    self.descriptors[endpoint].state.get().bulk_state();
+                let (_, _, out_state) = self.descriptors[endpoint].state.get().bulk_state();
                 assert_eq!(out_state, Some(BulkOutState::OutDma));
 
-                self.debug_out_packet(packet_bytes as usize, endpoint);
-
-                self.client.map(|client| {
-                    let result = client.packet_out(transfer_type, endpoint, packet_bytes);
-                    debug_packets!("packet_out => {:?}", result);
-                    let new_out_state = match result {
-                        hil::usb::OutResult::Ok => {
-                            // Indicate that the endpoint is ready to receive data again.
-                            regs.size_epout[endpoint].set(0);
-                            BulkOutState::Init
-                        }
-
-                        hil::usb::OutResult::Delay => {
-                            // We can't send the packet now. Wait for a resume_out call from the client.
-                            BulkOutState::OutDelay
-                        }
-
-                        hil::usb::OutResult::Error => {
-                            regs.epstall.write(
-                                EndpointStall::EP.val(endpoint as u32)
-                                    + EndpointStall::IO::Out
-                                    + EndpointStall::STALL::Stall,
-                            );
-                            BulkOutState::Init
-                        }
-                    };
-                    self.descriptors[endpoint].state.set(EndpointState::Bulk(
-                        transfer_type,
-                        in_state,
-                        Some(new_out_state),
-                    ));
-                });
+                // Notify the client about the new packet.
+                self.deliver_out_packet(endpoint);
             }
             8 => unimplemented!("isochronous endpoint"),
             _ => unreachable!("unexisting endpoint"),
         }
     }
 
+    /// Hands the packet received on `endpoint` to the client.
+    fn deliver_out_packet(&self, endpoint: usize) {
+        let regs = &*self.registers;
+        let packet_bytes = regs.size_epout[endpoint].get();
+        let (transfer_type, in_state, _) = self.descriptors[endpoint].state.get().bulk_state();
+
+        self.debug_out_packet(packet_bytes as usize, endpoint);
+
+        self.client.map(|client| {
+            let result = client.packet_out(transfer_type, endpoint, packet_bytes);
+            debug_packets!("packet_out => {:?}", result);
+            let new_out_state = match result {
+                hil::usb::OutResult::Ok => {
+                    // Indicate that the endpoint is ready to receive data again.
+                    regs.size_epout[endpoint].set(0);
+                    BulkOutState::Init
+                }
+
+                hil::usb::OutResult::Delay => {
+                    // We can't send the packet now. Wait for a resume_out call from the client.
+                    BulkOutState::OutDelay
+                }
+
+                hil::usb::OutResult::Error => {
+                    regs.epstall.write(
+                        EndpointStall::EP.val(endpoint as u32)
+                            + EndpointStall::IO::Out
+                            + EndpointStall::STALL::Stall,
+                    );
+                    BulkOutState::Init
+                }
+            };
+            self.descriptors[endpoint].state.set(EndpointState::Bulk(
+                transfer_type,
+                in_state,
+                Some(new_out_state),
+            ));
+        });
+    }
+
     fn handle_endisoout(&self) {
         unimplemented!("handle_endisoout");
     }
@@ -1936,19 +1943,14 @@ impl<'a> hil::usb::UsbController<'a> for Usbd<'a> {
     fn endpoint_resume_out(&self, endpoint: usize) {
         debug_events!("endpoint_resume_out({})", endpoint);
 
-        let (transfer_type, in_state, out_state) =
-            self.descriptors[endpoint].state.get().bulk_state();
+        let (_, _, out_state) = self.descriptors[endpoint].state.get().bulk_state();
         assert!(out_state.is_some());
 
         match out_state.unwrap() {
             BulkOutState::OutDelay => {
-                // The endpoint has now finished processing the last ENDEPOUT. No EPDATA event
-                // happened in the meantime, so the state is now back to Init.
-                self.descriptors[endpoint].state.set(EndpointState::Bulk(
-                    transfer_type,
-                    in_state,
-                    Some(BulkOutState::Init),
-                ));
+                // The delayed packet is still in the endpoint buffer, and the endpoint does not
+                // accept new data until it is consumed. Offer it to the client again.
+                self.deliver_out_packet(endpoint);
             }
             BulkOutState::OutData => {
                 // Although the client reported a delay before, an EPDATA event has
@@ -18,6 +18,7 @@ impl InterruptService for Nrf52840InterruptService {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         match interrupt {
             peripheral_interrupts::USBD => nrf52::usbd::USBD.handle_interrupt(),
+            peripheral_interrupts::QSPI => nrf52::qspi::QSPI.handle_interrupt(),
             _ => return self.nrf52.service_interrupt(interrupt),
         }
         true
@@ -1,8 +1,9 @@
 #![no_std]
 
 pub use nrf52::{
-    acomp, adc, aes, ble_radio, clock, constants, crt1, ficr, i2c, ieee802154_radio, init, nvmc,
-    pinmux, ppi, pwm, rtc, spi, temperature, timer, trng, uart, uicr, usbd,
+    acomp, adc, aes, ble_radio, clock, constants, crt1, ficr, i2c, i2s, ieee802154_radio, init,
+    nvmc, pdm, pinmux, ppi, pwm, qspi, rtc, spi, spis, temperature, timer, trng, twis, uart, uicr,
+    usbd,
 };
 pub mod chip;
 pub mod gpio;
@@ -340,6 +340,8 @@ impl hil::time::Alarm<'a> for TimerAlarm<'a> {
 
     fn set_alarm(&self, tics: u32) {
         self.disable_interrupts();
+        // A compare event of a disabled alarm must not fire this one
+        self.registers.events_compare[ALARM_COMPARE].write(Event::READY::CLEAR);
         self.registers.bitmode.write(Bitmode::BITMODE::Bit32);
         self.registers.cc[ALARM_COMPARE].write(CC::CC.val(tics));
         self.registers.tasks_start.write(Task::ENABLE::SET);
@@ -1,15 +1,26 @@
 //! Implementation of a single hardware timer.
 //!
+//! The AST also provides wall-clock time through `hil::date_time`. Because the
+//! counter is needed for alarms, the AST always runs in counter mode rather
+//! than in its calendar mode. Instead, the Unix time at which the counter
+//! last started from zero is kept in a BSCIF backup register and advanced on
+//! every counter overflow. Both the counter and the backup registers live in
+//! the backup power domain, so the time survives resets of the rest of the
+//! chip.
+//!
 //! - Author: Amit Levy <levya@cs.stanford.edu>
 //! - Author: Philip Levis <pal@cs.stanford.edu>
 //! - Date: July 16, 2015
 
+use crate::bscif;
 use crate::pm::{self, PBDClock};
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil::date_time::{self, DateTimeValues};
 use kernel::hil::time::{self, Alarm, Freq16KHz, Time};
 use kernel::hil::Controller;
+use kernel::ReturnCode;
 
 /// Minimum number of clock tics to make sure ALARM0 register is synchronized
 ///
@@ -27,6 +38,18 @@ use kernel::hil::Controller;
 /// tics. Seems safe enough and in practice has seemed to work.
 const ALARM0_SYNC_TICS: u32 = 8;
 
+/// Backup register holding the Unix time at which the counter was last zero.
+const BACKUP_BASE_TIME: usize = 0;
+/// Backup register holding `BACKUP_MAGIC` once the base time has been set.
+const BACKUP_VALID: usize = 1;
+const BACKUP_MAGIC: u32 = 0xDA7E_71E5;
+
+/// The counter runs at 32768 Hz / 2. `Freq16KHz` rounds this down, which is
+/// close enough for alarms but would make the wall clock drift.
+const COUNTER_HZ: u32 = 16384;
+/// Seconds per counter overflow: 2^32 tics / 2^14 Hz.
+const OVERFLOW_SECONDS: u32 = 1 << 18;
+
 #[repr(C)]
 struct AstRegisters {
     cr: ReadWrite<u32, Control::Register>,
@@ -187,6 +210,12 @@ impl Controller for Ast<'a> {
         self.set_prescalar(0); // 32KHz / (2^(0 + 1)) = 16KHz
         self.enable_alarm_wake();
         self.clear_alarm();
+
+        // Keep the wall clock running if it was set before the last reset.
+        if self.date_time_is_set() {
+            self.enable_overflow_irq();
+            self.enable();
+        }
     }
 }
 
@@ -272,6 +301,11 @@ impl Ast<'a> {
         regs.ier.write(Interrupt::ALARM0::SET);
     }
 
+    fn alarm_irq_enabled(&self) -> bool {
+        let regs: &AstRegisters = &*self.registers;
+        regs.imr.is_set(Interrupt::ALARM0)
+    }
+
     fn disable_alarm_irq(&self) {
         let regs: &AstRegisters = &*self.registers;
         regs.idr.write(Interrupt::ALARM0::SET);
@@ -290,11 +324,41 @@ impl Ast<'a> {
         regs.cv.read(Value::VALUE)
     }
 
+    fn overflow_pending(&self) -> bool {
+        let regs: &AstRegisters = &*self.registers;
+        while self.busy() {}
+        regs.sr.is_set(Status::OVF)
+    }
+
+    fn clear_overflow(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        while self.busy() {}
+        regs.scr.write(Interrupt::OVF::SET);
+        while self.busy() {}
+    }
+
+    fn enable_overflow_irq(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        regs.ier.write(Interrupt::OVF::SET);
+    }
+
+    fn date_time_is_set(&self) -> bool {
+        bscif::read_backup_register(BACKUP_VALID) == BACKUP_MAGIC
+    }
+
     pub fn handle_interrupt(&mut self) {
-        self.clear_alarm();
-        self.callback.map(|cb| {
-            cb.fired();
-        });
+        if self.overflow_pending() {
+            self.clear_overflow();
+            let base = bscif::read_backup_register(BACKUP_BASE_TIME);
+            bscif::write_backup_register(BACKUP_BASE_TIME, base.wrapping_add(OVERFLOW_SECONDS));
+        }
+
+        if self.is_alarm_enabled() && self.alarm_irq_enabled() {
+            self.clear_alarm();
+            self.callback.map(|cb| {
+                cb.fired();
+            });
+        }
     }
 }
 
@@ -349,3 +413,41 @@ impl Alarm<'a> for Ast<'a> {
         self.is_alarm_enabled()
     }
 }
+
+impl date_time::DateTime for Ast<'a> {
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode> {
+        if !self.date_time_is_set() {
+            return Err(ReturnCode::EOFF);
+        }
+        let mut counter = self.get_counter();
+        let mut base = bscif::read_backup_register(BACKUP_BASE_TIME);
+        if self.overflow_pending() {
+            // The counter wrapped but the interrupt has not been handled yet,
+            // so the base time is one overflow behind. The counter may have
+            // been read just before it wrapped, so read it again.
+            counter = self.get_counter();
+            base = base.wrapping_add(OVERFLOW_SECONDS);
+        }
+        Ok(DateTimeValues::from_unix_time(
+            base.wrapping_add(counter / COUNTER_HZ),
+        ))
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode {
+        let timestamp = match date_time.to_unix_time() {
+            Some(timestamp) => timestamp,
+            None if date_time.is_valid() => return ReturnCode::ESIZE,
+            None => return ReturnCode::EINVAL,
+        };
+
+        // Any pending overflow is accounted for by the new base time.
+        self.clear_overflow();
+        let base = timestamp.wrapping_sub(self.get_counter() / COUNTER_HZ);
+        bscif::write_backup_register(BACKUP_BASE_TIME, base);
+        bscif::write_backup_register(BACKUP_VALID, BACKUP_MAGIC);
+
+        self.enable_overflow_irq();
+        self.enable();
+        ReturnCode::SUCCESS
+    }
+}
@@ -29,10 +29,10 @@ struct BscifRegisters {
     bgctrl: ReadWrite<u32, BandgapControl::Register>,
     bgsr: ReadOnly<u32, BandgapStatus::Register>,
     _reserved3: [u32; 4],
-    br0: ReadOnly<u32, Backup::Register>,
-    br1: ReadOnly<u32, Backup::Register>,
-    br2: ReadOnly<u32, Backup::Register>,
-    br3: ReadOnly<u32, Backup::Register>,
+    br0: ReadWrite<u32, Backup::Register>,
+    br1: ReadWrite<u32, Backup::Register>,
+    br2: ReadWrite<u32, Backup::Register>,
+    br3: ReadWrite<u32, Backup::Register>,
 }
 
 register_bitfields![u32,
@@ -382,3 +382,29 @@ pub unsafe fn disable_rc_1mhz() {
     // Wait for the RC1M to be disabled
     while BSCIF.rc1mcr.is_set(RC1MClockConfig::CLKOEN) {}
 }
+
+/// Read one of the four backup registers. Backup registers keep their value
+/// across resets as long as the backup power domain stays powered.
+pub fn read_backup_register(index: usize) -> u32 {
+    match index {
+        0 => BSCIF.br0.get(),
+        1 => BSCIF.br1.get(),
+        2 => BSCIF.br2.get(),
+        _ => BSCIF.br3.get(),
+    }
+}
+
+/// Write one of the four backup registers.
+pub fn write_backup_register(index: usize, value: u32) {
+    let index = core::cmp::min(index, 3);
+    // Unlock the BSCIF::BRn register
+    BSCIF
+        .unlock
+        .write(Unlock::KEY.val(0xAA) + Unlock::ADDR.val(0x78 + 4 * index as u32));
+    match index {
+        0 => BSCIF.br0.set(value),
+        1 => BSCIF.br1.set(value),
+        2 => BSCIF.br2.set(value),
+        _ => BSCIF.br3.set(value),
+    }
+}
@@ -86,7 +86,7 @@ impl Chip for Sam4l {
                     }
                 } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
                     match interrupt {
-                        nvic::ASTALARM => ast::AST.handle_interrupt(),
+                        nvic::ASTALARM | nvic::ASTOVF => ast::AST.handle_interrupt(),
 
                         nvic::USART0 => usart::USART0.handle_interrupt(),
                         nvic::USART1 => usart::USART1.handle_interrupt(),
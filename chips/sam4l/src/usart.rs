@@ -929,6 +929,7 @@ impl uart::Transmit<'a> for USART<'a> {
     }
 }
 impl uart::UartAdvanced<'a> for USART<'a> {}
+impl uart::UartData<'a> for USART<'a> {}
 impl uart::Uart<'a> for USART<'a> {}
 
 impl uart::Configure for USART<'a> {
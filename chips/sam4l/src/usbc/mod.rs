@@ -686,7 +686,11 @@ impl Usbc<'a> {
         if config.matches_all(EndpointConfig::EPTYPE::Control) {
             endpoint_enable_interrupts(endpoint, EndpointControl::RXSTPE::SET);
             state.endpoint_states[endpoint] = EndpointState::Ctrl(CtrlState::Init);
-        } else if config.matches_all(EndpointConfig::EPTYPE::Bulk + EndpointConfig::EPDIR::In) {
+        } else if config.matches_all(EndpointConfig::EPTYPE::Bulk + EndpointConfig::EPDIR::In)
+            || config.matches_all(EndpointConfig::EPTYPE::Interrupt + EndpointConfig::EPDIR::In)
+        {
+            // Interrupt IN endpoints are serviced like bulk ones, only the
+            // host polls them differently
             endpoint_enable_interrupts(endpoint, EndpointControl::TXINE::SET);
             state.endpoint_states[endpoint] = EndpointState::BulkIn(BulkInState::Init);
         } else if config.matches_all(EndpointConfig::EPTYPE::Bulk + EndpointConfig::EPDIR::Out) {
@@ -1523,7 +1527,13 @@ impl hil::usb::UsbController<'a> for Usbc<'a> {
                     + EndpointConfig::EPSIZE::Bytes8
                     + EndpointConfig::EPBK::Single,
             )),
-            TransferType::Interrupt | TransferType::Isochronous => unimplemented!(),
+            TransferType::Interrupt => LocalRegisterCopy::new(From::from(
+                EndpointConfig::EPTYPE::Interrupt
+                    + EndpointConfig::EPDIR::In
+                    + EndpointConfig::EPSIZE::Bytes8
+                    + EndpointConfig::EPBK::Single,
+            )),
+            TransferType::Isochronous => unimplemented!(),
         };
 
         self._endpoint_enable(endpoint, endpoint_cfg)
@@ -67,6 +67,10 @@ pub struct Uart<'a> {
     buffer: TakeCell<'static, [u8]>,
     len: Cell<usize>,
     index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    rx_aborting: Cell<bool>,
 }
 
 #[derive(Copy, Clone)]
@@ -85,6 +89,10 @@ impl Uart<'a> {
             buffer: TakeCell::empty(),
             len: Cell::new(0),
             index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_aborting: Cell::new(false),
         }
     }
 
@@ -115,14 +123,79 @@ impl Uart<'a> {
         regs.ie.modify(interrupt::txwm::CLEAR);
     }
 
+    fn enable_rx_interrupt(&self) {
+        let regs = self.registers;
+        regs.ie.modify(interrupt::rxwm::SET);
+    }
+
+    fn disable_rx_interrupt(&self) {
+        let regs = self.registers;
+        regs.ie.modify(interrupt::rxwm::CLEAR);
+    }
+
+    // Moves received bytes from the FIFO to the receive buffer, and tells
+    // the client once the buffer holds all the bytes it asked for.
+    fn receive_bytes(&self) {
+        let regs = self.registers;
+        let done = self.rx_buffer.map_or(false, |buffer| {
+            while self.rx_index.get() < self.rx_len.get() {
+                // Reading the register pops the byte from the FIFO, so the
+                // empty flag has to come from the same read.
+                let rxdata = regs.rxdata.extract();
+                if rxdata.is_set(rxdata::empty) {
+                    break;
+                }
+                buffer[self.rx_index.get()] = rxdata.read(rxdata::data) as u8;
+                self.rx_index.set(self.rx_index.get() + 1);
+            }
+            self.rx_index.get() == self.rx_len.get()
+        });
+
+        if done {
+            self.disable_rx_interrupt();
+            self.rx_client.map(|client| {
+                self.rx_buffer.take().map(|buffer| {
+                    client.received_buffer(
+                        buffer,
+                        self.rx_len.get(),
+                        ReturnCode::SUCCESS,
+                        hil::uart::Error::None,
+                    );
+                });
+            });
+        }
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
 
         // Get a copy so we can check each interrupt flag in the register.
         let pending_interrupts = regs.ip.extract();
+        let enabled_interrupts = regs.ie.extract();
+
+        if pending_interrupts.is_set(interrupt::rxwm) && enabled_interrupts.is_set(interrupt::rxwm)
+        {
+            self.receive_bytes();
+        }
+
+        if self.rx_aborting.get() {
+            self.rx_aborting.set(false);
+            self.rx_client.map(|client| {
+                self.rx_buffer.take().map(|buffer| {
+                    client.received_buffer(
+                        buffer,
+                        self.rx_index.get(),
+                        ReturnCode::ECANCEL,
+                        hil::uart::Error::Aborted,
+                    );
+                });
+            });
+        }
 
-        // Determine why an interrupt occurred.
-        if pending_interrupts.is_set(interrupt::txwm) {
+        // The TX watermark stays pending while the FIFO is below it, so it
+        // only means a transmission made progress if its interrupt is enabled.
+        if pending_interrupts.is_set(interrupt::txwm) && enabled_interrupts.is_set(interrupt::txwm)
+        {
             // Got a TX interrupt which means the number of bytes in the FIFO
             // has fallen to zero. If there is more to send do that, otherwise
             // send a callback to the client.
@@ -250,14 +323,44 @@ impl hil::uart::Receive<'a> for Uart<'a> {
 
     fn receive_buffer(
         &self,
-        _rx_buffer: &'static mut [u8],
-        _rx_len: usize,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>) {
-        (ReturnCode::FAIL, None)
+        let regs = self.registers;
+
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        } else if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+
+        // Interrupt as soon as the FIFO holds a byte.
+        regs.rxctrl
+            .write(rxctrl::enable::SET + rxctrl::counter.val(0));
+        self.enable_rx_interrupt();
+
+        (ReturnCode::SUCCESS, None)
     }
 
     fn receive_abort(&self) -> ReturnCode {
-        ReturnCode::FAIL
+        let regs = self.registers;
+
+        if self.rx_buffer.is_none() {
+            return ReturnCode::SUCCESS;
+        }
+
+        // The callback comes from the next interrupt. Raising the TX
+        // watermark above the (empty or draining) FIFO makes sure there is
+        // one soon even if nothing is being received.
+        self.disable_rx_interrupt();
+        self.rx_aborting.set(true);
+        regs.txctrl.modify(txctrl::txcnt.val(1));
+        self.enable_tx_interrupt();
+        ReturnCode::EBUSY
     }
 
     fn receive_word(&self) -> ReturnCode {
@@ -43,9 +43,12 @@ impl Chip for Stm32f3xx {
                         nvic::TIM2 => tim2::TIM2.handle_interrupt(),
 
                         nvic::SPI1 => spi::SPI1.handle_interrupt(),
+                        nvic::SPI2 => spi::SPI2.handle_interrupt(),
 
                         nvic::I2C1_EV => i2c::I2C1.handle_event(),
                         nvic::I2C1_ER => i2c::I2C1.handle_error(),
+                        nvic::I2C2_EV => i2c::I2C2.handle_event(),
+                        nvic::I2C2_ER => i2c::I2C2.handle_error(),
 
                         nvic::EXTI0 => exti::EXTI.handle_interrupt(),
                         nvic::EXTI1 => exti::EXTI.handle_interrupt(),
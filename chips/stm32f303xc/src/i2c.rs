@@ -1,10 +1,11 @@
 use core::cell::Cell;
+use core::cmp;
 
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::registers::{register_bitfields, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::hil;
-use kernel::hil::i2c::{self, Error, I2CHwMasterClient, I2CMaster};
+use kernel::hil::i2c::{self, Error, I2CHwMasterClient, I2CHwSlaveClient, I2CMaster};
 use kernel::ClockInterface;
 
 use crate::rcc;
@@ -77,7 +78,7 @@ register_bitfields![u32,
         /// Not acknowledge received Interrupt enable
         NACKIE OFFSET(4) NUMBITS(1) [],
         /// Address match Interrupt enable (slave only)
-        ADDRIE OFFSET(3) NUMBITS(3) [],
+        ADDRIE OFFSET(3) NUMBITS(1) [],
         /// RX Interrupt enable
         RXIE OFFSET(2) NUMBITS(1) [],
         /// TX Interrupt enable
@@ -226,15 +227,15 @@ register_bitfields![u32,
 const I2C1_BASE: StaticRef<I2CRegisters> =
     unsafe { StaticRef::new(0x4000_5400 as *const I2CRegisters) };
 
-// const I2C2_BASE: StaticRef<I2CRegisters> =
-// 	unsafe { StaticRef::new(0x4000_5800 as *const I2CRegisters) };
+const I2C2_BASE: StaticRef<I2CRegisters> =
+    unsafe { StaticRef::new(0x4000_5800 as *const I2CRegisters) };
 
 pub struct I2C<'a> {
     registers: StaticRef<I2CRegisters>,
     clock: I2CClock,
 
-    // I2C slave support not yet implemented
     master_client: OptionalCell<&'a dyn hil::i2c::I2CHwMasterClient>,
+    slave_client: OptionalCell<&'a dyn hil::i2c::I2CHwSlaveClient>,
 
     buffer: TakeCell<'static, [u8]>,
     tx_position: Cell<u8>,
@@ -246,6 +247,14 @@ pub struct I2C<'a> {
 
     status: Cell<I2CStatus>,
     // transfers: Cell<u8>
+    listening: Cell<bool>,
+    slave_status: Cell<I2CSlaveStatus>,
+    slave_rx_buffer: TakeCell<'static, [u8]>,
+    slave_rx_len: Cell<usize>,
+    slave_rx_position: Cell<usize>,
+    slave_tx_buffer: TakeCell<'static, [u8]>,
+    slave_tx_len: Cell<usize>,
+    slave_tx_position: Cell<usize>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -256,11 +265,25 @@ enum I2CStatus {
     Reading,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+enum I2CSlaveStatus {
+    Idle,
+    /// The master is writing to us
+    Receiving,
+    /// The master is reading from us
+    Transmitting,
+}
+
 pub static mut I2C1: I2C = I2C::new(
     I2C1_BASE,
     I2CClock(rcc::PeripheralClock::APB1(rcc::PCLK1::I2C1)),
 );
 
+pub static mut I2C2: I2C = I2C::new(
+    I2C2_BASE,
+    I2CClock(rcc::PeripheralClock::APB1(rcc::PCLK1::I2C2)),
+);
+
 impl I2C<'a> {
     const fn new(base_addr: StaticRef<I2CRegisters>, clock: I2CClock) -> I2C<'a> {
         I2C {
@@ -268,6 +291,7 @@ impl I2C<'a> {
             clock,
 
             master_client: OptionalCell::empty(),
+            slave_client: OptionalCell::empty(),
 
             slave_address: Cell::new(0),
 
@@ -279,6 +303,15 @@ impl I2C<'a> {
             rx_len: Cell::new(0),
 
             status: Cell::new(I2CStatus::Idle),
+
+            listening: Cell::new(false),
+            slave_status: Cell::new(I2CSlaveStatus::Idle),
+            slave_rx_buffer: TakeCell::empty(),
+            slave_rx_len: Cell::new(0),
+            slave_rx_position: Cell::new(0),
+            slave_tx_buffer: TakeCell::empty(),
+            slave_tx_len: Cell::new(0),
+            slave_tx_position: Cell::new(0),
         }
     }
 
@@ -325,6 +358,11 @@ impl I2C<'a> {
     }
 
     pub fn handle_event(&self) {
+        if self.status.get() == I2CStatus::Idle && self.listening.get() {
+            self.handle_slave_event();
+            return;
+        }
+
         if self.registers.isr.is_set(ISR::TXIS) {
             // send the next byte
             if self.buffer.is_some() && self.tx_position.get() < self.tx_len.get() {
@@ -407,6 +445,15 @@ impl I2C<'a> {
     }
 
     pub fn handle_error(&self) {
+        if self.status.get() == I2CStatus::Idle && self.listening.get() {
+            // The master retries or gives up; the transfer still ends with
+            // a stop condition that completes it.
+            self.registers
+                .icr
+                .write(ICR::BERRCF::SET + ICR::ARLOCF::SET + ICR::OVRCF::SET);
+            return;
+        }
+
         // not sure that this is the best error to send
         self.master_client.map(|client| {
             self.buffer
@@ -416,6 +463,97 @@ impl I2C<'a> {
         self.stop();
     }
 
+    fn handle_slave_event(&self) {
+        if self.registers.isr.is_set(ISR::ADDR) {
+            if self.registers.isr.is_set(ISR::DIR) {
+                self.slave_status.set(I2CSlaveStatus::Transmitting);
+                self.slave_tx_position.set(0);
+                // flush any byte left over from the previous transfer
+                self.registers.isr.modify(ISR::TXE::SET);
+                if self.slave_tx_buffer.is_none() {
+                    self.slave_client.map(|client| client.read_expected());
+                }
+            } else {
+                self.slave_status.set(I2CSlaveStatus::Receiving);
+                self.slave_rx_position.set(0);
+                if self.slave_rx_buffer.is_none() {
+                    self.slave_client.map(|client| client.write_expected());
+                }
+                if self.slave_rx_buffer.is_none() {
+                    // nowhere to put the data
+                    self.registers.cr2.modify(CR2::NACK::SET);
+                }
+            }
+            // releases the clock, which is stretched until now
+            self.registers.icr.write(ICR::ADDRCF::SET);
+        }
+
+        while self.registers.isr.is_set(ISR::RXNE) {
+            let byte = self.registers.rxdr.read(RXDR::RXDATA) as u8;
+            let position = self.slave_rx_position.get();
+            if position < self.slave_rx_len.get() {
+                self.slave_rx_buffer.map(|buf| {
+                    buf[position] = byte;
+                });
+                self.slave_rx_position.set(position + 1);
+            }
+        }
+
+        if self.registers.isr.is_set(ISR::TXIS) {
+            // send 0xff once the buffer runs out
+            let position = self.slave_tx_position.get();
+            let byte = if position < self.slave_tx_len.get() {
+                self.slave_tx_buffer.map_or(0xff, |buf| buf[position])
+            } else {
+                0xff
+            };
+            self.registers.txdr.write(TXDR::TXDATA.val(byte as u32));
+            self.slave_tx_position.set(position + 1);
+        }
+
+        if self.registers.isr.is_set(ISR::NACKF) {
+            // the master does not want any more data, so the byte already
+            // loaded into the transmit data register is never sent
+            self.registers.icr.write(ICR::NACKCF::SET);
+            self.slave_tx_position
+                .set(self.slave_tx_position.get().saturating_sub(1));
+        }
+
+        if self.registers.isr.is_set(ISR::STOPF) {
+            self.registers.icr.write(ICR::STOPCF::SET);
+            self.registers.cr2.modify(CR2::NACK::CLEAR);
+            self.registers.isr.modify(ISR::TXE::SET);
+            match self.slave_status.get() {
+                I2CSlaveStatus::Receiving => {
+                    let len = cmp::min(self.slave_rx_position.get(), self.slave_rx_len.get());
+                    self.slave_rx_buffer.take().map(|buf| {
+                        self.slave_client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                len as u8,
+                                i2c::SlaveTransmissionType::Write,
+                            )
+                        });
+                    });
+                }
+                I2CSlaveStatus::Transmitting => {
+                    let len = cmp::min(self.slave_tx_position.get(), self.slave_tx_len.get());
+                    self.slave_tx_buffer.take().map(|buf| {
+                        self.slave_client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                len as u8,
+                                i2c::SlaveTransmissionType::Read,
+                            )
+                        });
+                    });
+                }
+                I2CSlaveStatus::Idle => {}
+            }
+            self.slave_status.set(I2CSlaveStatus::Idle);
+        }
+    }
+
     fn reset(&self) {
         self.disable();
         self.enable();
@@ -511,6 +649,60 @@ impl i2c::I2CMaster for I2C<'a> {
     }
 }
 
+/// The slave shares the peripheral with the master, so it only responds
+/// while no master transfer is in progress. Clients have to call `listen`
+/// again after using the master.
+impl i2c::I2CSlave for I2C<'a> {
+    fn set_slave_client(&self, slave_client: &'static dyn I2CHwSlaveClient) {
+        self.slave_client.replace(slave_client);
+    }
+    fn enable(&self) {
+        self.registers.cr1.modify(CR1::PE::SET);
+    }
+    fn disable(&self) {
+        self.listening.set(false);
+        self.registers.cr1.modify(
+            CR1::ADDRIE::CLEAR
+                + CR1::RXIE::CLEAR
+                + CR1::TXIE::CLEAR
+                + CR1::STOPIE::CLEAR
+                + CR1::NACKIE::CLEAR
+                + CR1::ERRIE::CLEAR,
+        );
+        self.registers.oar1.modify(OAR1::OA1EN::CLEAR);
+    }
+    fn set_address(&self, addr: u8) {
+        // OA1 can only be changed while the address is disabled
+        self.registers.oar1.modify(OAR1::OA1EN::CLEAR);
+        self.registers
+            .oar1
+            .write(OAR1::OA1.val((addr as u32) << 1) + OAR1::OA1EN::SET);
+    }
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        self.slave_rx_len
+            .set(cmp::min(max_len as usize, data.len()));
+        self.slave_rx_buffer.replace(data);
+    }
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        self.slave_tx_len
+            .set(cmp::min(max_len as usize, data.len()));
+        self.slave_tx_buffer.replace(data);
+    }
+    fn listen(&self) {
+        self.listening.set(true);
+        self.registers.cr1.modify(
+            CR1::ADDRIE::SET
+                + CR1::RXIE::SET
+                + CR1::TXIE::SET
+                + CR1::STOPIE::SET
+                + CR1::NACKIE::SET
+                + CR1::ERRIE::SET,
+        );
+    }
+}
+
+impl i2c::I2CMasterSlave for I2C<'a> {}
+
 struct I2CClock(rcc::PeripheralClock);
 
 impl ClockInterface for I2CClock {
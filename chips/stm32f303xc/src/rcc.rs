@@ -594,6 +594,39 @@ impl Rcc {
         self.registers.apb1rstr.modify(APB1RSTR::I2C1RST::SET);
         self.registers.apb1rstr.modify(APB1RSTR::I2C1RST::CLEAR);
     }
+
+    // I2C2 clock
+
+    fn is_enabled_i2c2_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::I2C2EN)
+    }
+
+    fn enable_i2c2_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::I2C2EN::SET)
+    }
+
+    fn disable_i2c2_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::I2C2EN::CLEAR)
+    }
+
+    fn reset_i2c2(&self) {
+        self.registers.apb1rstr.modify(APB1RSTR::I2C2RST::SET);
+        self.registers.apb1rstr.modify(APB1RSTR::I2C2RST::CLEAR);
+    }
+
+    // SPI2 clock
+
+    fn is_enabled_spi2_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::SPI2EN)
+    }
+
+    fn enable_spi2_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::SPI2EN::SET)
+    }
+
+    fn disable_spi2_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::SPI2EN::CLEAR)
+    }
 }
 
 /// Clock sources for CPU
@@ -634,7 +667,8 @@ pub enum PCLK1 {
     USART2,
     USART3,
     I2C1,
-    // I2C2,
+    I2C2,
+    SPI2,
     // SPI3,
 }
 
@@ -662,6 +696,8 @@ impl ClockInterface for PeripheralClock {
                 PCLK1::USART2 => unsafe { RCC.is_enabled_usart2_clock() },
                 PCLK1::USART3 => unsafe { RCC.is_enabled_usart3_clock() },
                 PCLK1::I2C1 => unsafe { RCC.is_enabled_i2c1_clock() },
+                PCLK1::I2C2 => unsafe { RCC.is_enabled_i2c2_clock() },
+                PCLK1::SPI2 => unsafe { RCC.is_enabled_spi2_clock() },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SPI1 => unsafe { RCC.is_enabled_spi1_clock() },
@@ -710,6 +746,13 @@ impl ClockInterface for PeripheralClock {
                     RCC.enable_i2c1_clock();
                     RCC.reset_i2c1();
                 },
+                PCLK1::I2C2 => unsafe {
+                    RCC.enable_i2c2_clock();
+                    RCC.reset_i2c2();
+                },
+                PCLK1::SPI2 => unsafe {
+                    RCC.enable_spi2_clock();
+                },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SYSCFG => unsafe {
@@ -763,6 +806,12 @@ impl ClockInterface for PeripheralClock {
                 PCLK1::I2C1 => unsafe {
                     RCC.disable_i2c1_clock();
                 },
+                PCLK1::I2C2 => unsafe {
+                    RCC.disable_i2c2_clock();
+                },
+                PCLK1::SPI2 => unsafe {
+                    RCC.disable_spi2_clock();
+                },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SYSCFG => unsafe {
@@ -6,7 +6,7 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::hil::gpio::Output;
-use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient, SpiSlaveClient};
 use kernel::{ClockInterface, ReturnCode};
 
 use crate::gpio::PinId;
@@ -174,8 +174,8 @@ register_bitfields![u32,
 const SPI1_BASE: StaticRef<SpiRegisters> =
     unsafe { StaticRef::new(0x4001_3000 as *const SpiRegisters) };
 
-// const SPI2_BASE: StaticRef<SpiRegisters> =
-//     unsafe { StaticRef::new(0x4000_3800 as *const SpiRegisters) };
+const SPI2_BASE: StaticRef<SpiRegisters> =
+    unsafe { StaticRef::new(0x4000_3800 as *const SpiRegisters) };
 
 // const SPI3_BASE: StaticRef<SpiRegisters> =
 //     unsafe { StaticRef::new(0x4000_3C00 as *const SpiRegisters) };
@@ -184,8 +184,12 @@ pub struct Spi<'a> {
     registers: StaticRef<SpiRegisters>,
     clock: SpiClock,
 
-    // SPI slave support not yet implemented
     master_client: OptionalCell<&'a dyn hil::spi::SpiMasterClient>,
+    slave_client: OptionalCell<&'a dyn hil::spi::SpiSlaveClient>,
+    /// Whether the SPI was initialized as a slave
+    slave: Cell<bool>,
+    /// Byte sent in slave mode when there is no write buffer
+    write_byte: Cell<u8>,
 
     active_slave: OptionalCell<PinId>,
 
@@ -206,6 +210,11 @@ pub static mut SPI1: Spi = Spi::new(
     SpiClock(rcc::PeripheralClock::APB2(rcc::PCLK2::SPI1)),
 );
 
+pub static mut SPI2: Spi = Spi::new(
+    SPI2_BASE,
+    SpiClock(rcc::PeripheralClock::APB1(rcc::PCLK1::SPI2)),
+);
+
 impl Spi<'a> {
     const fn new(base_addr: StaticRef<SpiRegisters>, clock: SpiClock) -> Spi<'a> {
         Spi {
@@ -213,6 +222,9 @@ impl Spi<'a> {
             clock,
 
             master_client: OptionalCell::empty(),
+            slave_client: OptionalCell::empty(),
+            slave: Cell::new(false),
+            write_byte: Cell::new(0),
             active_slave: OptionalCell::empty(),
 
             tx_buffer: TakeCell::empty(),
@@ -243,13 +255,16 @@ impl Spi<'a> {
 
     pub fn handle_interrupt(&self) {
         if self.registers.sr.is_set(SR::TXE) {
-            if self.tx_buffer.is_some() && self.tx_position.get() < self.len.get() {
-                self.tx_buffer.map(|buf| {
-                    self.registers
-                        .dr
-                        .write(DR::DR.val(buf[self.tx_position.get()]));
-                    self.tx_position.set(self.tx_position.get() + 1);
-                });
+            if (self.tx_buffer.is_some() || self.slave.get())
+                && self.tx_position.get() < self.len.get()
+            {
+                // In slave mode, a read-only transfer still has to give the
+                // master something to clock in.
+                let byte = self
+                    .tx_buffer
+                    .map_or(self.write_byte.get(), |buf| buf[self.tx_position.get()]);
+                self.registers.dr.write(DR::DR.val(byte));
+                self.tx_position.set(self.tx_position.get() + 1);
             } else {
                 self.registers.cr2.modify(CR2::TXEIE::CLEAR);
                 self.transfers
@@ -274,7 +289,14 @@ impl Spi<'a> {
             }
         }
 
-        if self.transfers.get() == SPI_IN_PROGRESS {
+        if self.transfers.get() == SPI_IN_PROGRESS && self.slave.get() {
+            self.transfers.set(SPI_IDLE);
+            let len = self.len.get();
+            let tx_buffer = self.tx_buffer.take();
+            let rx_buffer = self.rx_buffer.take();
+            self.slave_client
+                .map(move |client| client.read_write_done(tx_buffer, rx_buffer, len));
+        } else if self.transfers.get() == SPI_IN_PROGRESS {
             // we release the line and put the SPI in IDLE as the client might
             // initiate another SPI transfer right away
             if !self.active_after.get() {
@@ -369,7 +391,7 @@ impl Spi<'a> {
                 .as_ref()
                 .map(|buf| count = cmp::min(count, buf.len()));
 
-            if write_buffer.is_some() {
+            if write_buffer.is_some() || self.slave.get() {
                 self.transfers
                     .set(self.transfers.get() | SPI_WRITE_IN_PROGRESS);
             }
@@ -383,17 +405,18 @@ impl Spi<'a> {
 
             read_buffer.map(|buf| {
                 self.rx_buffer.replace(buf);
-                self.len.set(count);
             });
 
             self.registers.cr2.modify(CR2::RXNEIE::SET);
 
+            self.len.set(count);
+            self.tx_position.set(0);
             write_buffer.map(|buf| {
                 self.tx_buffer.replace(buf);
-                self.len.set(count);
-                self.tx_position.set(0);
-                self.registers.cr2.modify(CR2::TXEIE::SET);
             });
+            if self.tx_buffer.is_some() || self.slave.get() {
+                self.registers.cr2.modify(CR2::TXEIE::SET);
+            }
 
             ReturnCode::SUCCESS
         } else {
@@ -410,6 +433,8 @@ impl spi::SpiMaster for Spi<'a> {
     }
 
     fn init(&self) {
+        self.slave.set(false);
+
         // enable error interrupt (used only for debugging)
         // self.registers.cr2.modify(CR2::ERRIE::SET);
 
@@ -527,6 +552,71 @@ impl spi::SpiMaster for Spi<'a> {
     }
 }
 
+/// In slave mode, the SPI uses its hardware NSS pin as chip select, which
+/// the board must configure as an alternate function. `chip_selected` is
+/// never called; transfers are set up in advance and complete once the
+/// master has clocked `len` bytes.
+impl spi::SpiSlave for Spi<'a> {
+    fn init(&self) {
+        self.slave.set(true);
+
+        self.registers.cr2.modify(
+            // Set 8 bit mode
+            CR2::DS.val (0b0111)+
+            // Set FIFO level at 1/4
+            CR2::FRXTH::SET,
+        );
+
+        self.registers.cr1.modify(
+            // 2 line unidirectional mode
+            CR1::BIDIMODE::CLEAR +
+            // Select as slave
+            CR1::MSTR::CLEAR +
+            // Hardware slave management
+            CR1::SSM::CLEAR +
+            // Enable
+            CR1::SPE::SET,
+        );
+    }
+
+    fn has_client(&self) -> bool {
+        self.slave_client.is_some()
+    }
+
+    fn set_client(&self, client: Option<&'static dyn SpiSlaveClient>) {
+        self.slave_client.insert(client);
+    }
+
+    fn set_write_byte(&self, write_byte: u8) {
+        self.write_byte.set(write_byte);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        self.read_write_bytes(write_buffer, read_buffer, len)
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.set_polarity(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.get_polarity()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.set_phase(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.get_phase()
+    }
+}
+
 struct SpiClock(rcc::PeripheralClock);
 
 impl ClockInterface for SpiClock {
@@ -6,6 +6,7 @@ use kernel::Chip;
 
 use crate::dma1;
 use crate::exti;
+use crate::i2c;
 use crate::nvic;
 use crate::spi;
 use crate::tim2;
@@ -61,6 +62,9 @@ impl Chip for Stm32f4xx {
 
                         nvic::SPI3 => spi::SPI3.handle_interrupt(),
 
+                        nvic::I2C1_EV => i2c::I2C1.handle_event(),
+                        nvic::I2C1_ER => i2c::I2C1.handle_error(),
+
                         nvic::EXTI0 => exti::EXTI.handle_interrupt(),
                         nvic::EXTI1 => exti::EXTI.handle_interrupt(),
                         nvic::EXTI2 => exti::EXTI.handle_interrupt(),
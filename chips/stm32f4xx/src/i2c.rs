@@ -0,0 +1,650 @@
+//! Inter-integrated circuit (I2C) master and slave.
+//!
+//! Transfers are interrupt driven, one byte at a time. The peripheral acts
+//! as a slave on its own address whenever it is not running a master
+//! transfer, and switches to master mode by itself when a transfer starts.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::registers::{register_bitfields, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::hil::i2c::{self, Error, I2CHwMasterClient, I2CHwSlaveClient};
+use kernel::ClockInterface;
+
+use crate::rcc;
+
+pub enum I2CSpeed {
+    Speed100k,
+    Speed400k,
+}
+
+/// Inter-integrated circuit
+#[repr(C)]
+struct I2CRegisters {
+    /// control register 1
+    cr1: ReadWrite<u32, CR1::Register>,
+    /// control register 2
+    cr2: ReadWrite<u32, CR2::Register>,
+    /// own address register 1
+    oar1: ReadWrite<u32, OAR1::Register>,
+    /// own address register 2
+    oar2: ReadWrite<u32, OAR2::Register>,
+    /// data register
+    dr: ReadWrite<u32, DR::Register>,
+    /// status register 1
+    sr1: ReadWrite<u32, SR1::Register>,
+    /// status register 2
+    sr2: ReadWrite<u32, SR2::Register>,
+    /// clock control register
+    ccr: ReadWrite<u32, CCR::Register>,
+    /// TRISE register
+    trise: ReadWrite<u32, TRISE::Register>,
+    /// FLTR register
+    fltr: ReadWrite<u32, FLTR::Register>,
+}
+
+register_bitfields![u32,
+    CR1 [
+        /// Software reset
+        SWRST OFFSET(15) NUMBITS(1) [],
+        /// SMBus alert
+        ALERT OFFSET(13) NUMBITS(1) [],
+        /// Packet error checking
+        PEC OFFSET(12) NUMBITS(1) [],
+        /// Acknowledge/PEC Position (for data reception)
+        POS OFFSET(11) NUMBITS(1) [],
+        /// Acknowledge enable
+        ACK OFFSET(10) NUMBITS(1) [],
+        /// Stop generation
+        STOP OFFSET(9) NUMBITS(1) [],
+        /// Start generation
+        START OFFSET(8) NUMBITS(1) [],
+        /// Clock stretching disable (Slave mode)
+        NOSTRETCH OFFSET(7) NUMBITS(1) [],
+        /// General call enable
+        ENGC OFFSET(6) NUMBITS(1) [],
+        /// PEC enable
+        ENPEC OFFSET(5) NUMBITS(1) [],
+        /// ARP enable
+        ENARP OFFSET(4) NUMBITS(1) [],
+        /// SMBus type
+        SMBTYPE OFFSET(3) NUMBITS(1) [],
+        /// SMBus mode
+        SMBUS OFFSET(1) NUMBITS(1) [],
+        /// Peripheral enable
+        PE OFFSET(0) NUMBITS(1) []
+    ],
+    CR2 [
+        /// DMA last transfer
+        LAST OFFSET(12) NUMBITS(1) [],
+        /// DMA requests enable
+        DMAEN OFFSET(11) NUMBITS(1) [],
+        /// Buffer interrupt enable
+        ITBUFEN OFFSET(10) NUMBITS(1) [],
+        /// Event interrupt enable
+        ITEVTEN OFFSET(9) NUMBITS(1) [],
+        /// Error interrupt enable
+        ITERREN OFFSET(8) NUMBITS(1) [],
+        /// Peripheral clock frequency
+        FREQ OFFSET(0) NUMBITS(6) []
+    ],
+    OAR1 [
+        /// Addressing mode (slave mode)
+        ADDMODE OFFSET(15) NUMBITS(1) [],
+        /// Should always be kept at 1 by software
+        ONE OFFSET(14) NUMBITS(1) [],
+        /// Interface address
+        ADD OFFSET(0) NUMBITS(10) []
+    ],
+    OAR2 [
+        /// Interface address
+        ADD2 OFFSET(1) NUMBITS(7) [],
+        /// Dual addressing mode enable
+        ENDUAL OFFSET(0) NUMBITS(1) []
+    ],
+    DR [
+        /// 8-bit data register
+        DR OFFSET(0) NUMBITS(8) []
+    ],
+    SR1 [
+        /// SMBus alert
+        SMBALERT OFFSET(15) NUMBITS(1) [],
+        /// Timeout or Tlow error
+        TIMEOUT OFFSET(14) NUMBITS(1) [],
+        /// PEC Error in reception
+        PECERR OFFSET(12) NUMBITS(1) [],
+        /// Overrun/Underrun
+        OVR OFFSET(11) NUMBITS(1) [],
+        /// Acknowledge failure
+        AF OFFSET(10) NUMBITS(1) [],
+        /// Arbitration lost (master mode)
+        ARLO OFFSET(9) NUMBITS(1) [],
+        /// Bus error
+        BERR OFFSET(8) NUMBITS(1) [],
+        /// Data register empty (transmitters)
+        TXE OFFSET(7) NUMBITS(1) [],
+        /// Data register not empty (receivers)
+        RXNE OFFSET(6) NUMBITS(1) [],
+        /// Stop detection (slave mode)
+        STOPF OFFSET(4) NUMBITS(1) [],
+        /// 10-bit header sent (Master mode)
+        ADD10 OFFSET(3) NUMBITS(1) [],
+        /// Byte transfer finished
+        BTF OFFSET(2) NUMBITS(1) [],
+        /// Address sent (master mode)/matched (slave mode)
+        ADDR OFFSET(1) NUMBITS(1) [],
+        /// Start bit (Master mode)
+        SB OFFSET(0) NUMBITS(1) []
+    ],
+    SR2 [
+        /// Packet error checking register
+        PEC OFFSET(8) NUMBITS(8) [],
+        /// Dual flag (Slave mode)
+        DUALF OFFSET(7) NUMBITS(1) [],
+        /// SMBus host header (Slave mode)
+        SMBHOST OFFSET(6) NUMBITS(1) [],
+        /// SMBus device default address (Slave mode)
+        SMBDEFAULT OFFSET(5) NUMBITS(1) [],
+        /// General call address (Slave mode)
+        GENCALL OFFSET(4) NUMBITS(1) [],
+        /// Transmitter/receiver
+        TRA OFFSET(2) NUMBITS(1) [],
+        /// Bus busy
+        BUSY OFFSET(1) NUMBITS(1) [],
+        /// Master/slave
+        MSL OFFSET(0) NUMBITS(1) []
+    ],
+    CCR [
+        /// I2C master mode selection
+        F_S OFFSET(15) NUMBITS(1) [],
+        /// Fast mode duty cycle
+        DUTY OFFSET(14) NUMBITS(1) [],
+        /// Clock control register in Fast/Standard mode (Master mode)
+        CCR OFFSET(0) NUMBITS(12) []
+    ],
+    TRISE [
+        /// Maximum rise time in Fast/Standard mode (Master mode)
+        TRISE OFFSET(0) NUMBITS(6) []
+    ],
+    FLTR [
+        /// Analog noise filter OFF
+        ANOFF OFFSET(4) NUMBITS(1) [],
+        /// Digital noise filter
+        DNF OFFSET(0) NUMBITS(4) []
+    ]
+];
+
+const I2C1_BASE: StaticRef<I2CRegisters> =
+    unsafe { StaticRef::new(0x4000_5400 as *const I2CRegisters) };
+
+pub struct I2C<'a> {
+    registers: StaticRef<I2CRegisters>,
+    clock: I2CClock,
+
+    master_client: OptionalCell<&'a dyn hil::i2c::I2CHwMasterClient>,
+    slave_client: OptionalCell<&'a dyn hil::i2c::I2CHwSlaveClient>,
+
+    buffer: TakeCell<'static, [u8]>,
+    tx_position: Cell<u8>,
+    rx_position: Cell<u8>,
+    tx_len: Cell<u8>,
+    rx_len: Cell<u8>,
+
+    slave_address: Cell<u8>,
+    /// Whether the slave acknowledged its address in the current transfer
+    address_acked: Cell<bool>,
+
+    status: Cell<I2CStatus>,
+
+    listening: Cell<bool>,
+    slave_status: Cell<I2CSlaveStatus>,
+    slave_rx_buffer: TakeCell<'static, [u8]>,
+    slave_rx_len: Cell<usize>,
+    slave_rx_position: Cell<usize>,
+    slave_tx_buffer: TakeCell<'static, [u8]>,
+    slave_tx_len: Cell<usize>,
+    slave_tx_position: Cell<usize>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum I2CStatus {
+    Idle,
+    Writing,
+    WritingReading,
+    Reading,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum I2CSlaveStatus {
+    Idle,
+    /// The master is writing to us
+    Receiving,
+    /// The master is reading from us
+    Transmitting,
+}
+
+pub static mut I2C1: I2C = I2C::new(
+    I2C1_BASE,
+    I2CClock(rcc::PeripheralClock::APB1(rcc::PCLK1::I2C1)),
+);
+
+impl I2C<'a> {
+    const fn new(base_addr: StaticRef<I2CRegisters>, clock: I2CClock) -> I2C<'a> {
+        I2C {
+            registers: base_addr,
+            clock,
+
+            master_client: OptionalCell::empty(),
+            slave_client: OptionalCell::empty(),
+
+            buffer: TakeCell::empty(),
+            tx_position: Cell::new(0),
+            rx_position: Cell::new(0),
+            tx_len: Cell::new(0),
+            rx_len: Cell::new(0),
+
+            slave_address: Cell::new(0),
+            address_acked: Cell::new(false),
+
+            status: Cell::new(I2CStatus::Idle),
+
+            listening: Cell::new(false),
+            slave_status: Cell::new(I2CSlaveStatus::Idle),
+            slave_rx_buffer: TakeCell::empty(),
+            slave_rx_len: Cell::new(0),
+            slave_rx_position: Cell::new(0),
+            slave_tx_buffer: TakeCell::empty(),
+            slave_tx_len: Cell::new(0),
+            slave_tx_position: Cell::new(0),
+        }
+    }
+
+    /// Configures the bus speed. `pclk1_in_mhz` is the frequency of the APB1
+    /// clock, which must be at least 2 MHz, or 4 MHz for 400 kHz.
+    pub fn set_speed(&self, speed: I2CSpeed, pclk1_in_mhz: usize) {
+        let enabled = self.registers.cr1.is_set(CR1::PE);
+        self.registers.cr1.modify(CR1::PE::CLEAR);
+        self.registers
+            .cr2
+            .modify(CR2::FREQ.val(pclk1_in_mhz as u32));
+        match speed {
+            I2CSpeed::Speed100k => {
+                // SCL high and low time are both CCR * Tpclk1, and the
+                // maximum rise time is 1000 ns
+                self.registers
+                    .ccr
+                    .write(CCR::CCR.val((pclk1_in_mhz * 5) as u32));
+                self.registers
+                    .trise
+                    .write(TRISE::TRISE.val((pclk1_in_mhz + 1) as u32));
+            }
+            I2CSpeed::Speed400k => {
+                // SCL low time is 2 * CCR * Tpclk1 and high time CCR *
+                // Tpclk1, and the maximum rise time is 300 ns
+                self.registers
+                    .ccr
+                    .write(CCR::F_S::SET + CCR::CCR.val(cmp::max(pclk1_in_mhz * 5 / 6, 1) as u32));
+                self.registers
+                    .trise
+                    .write(TRISE::TRISE.val((pclk1_in_mhz * 3 / 10 + 1) as u32));
+            }
+        }
+        if enabled {
+            self.registers.cr1.modify(CR1::PE::SET);
+        }
+    }
+
+    pub fn is_enabled_clock(&self) -> bool {
+        self.clock.is_enabled()
+    }
+
+    pub fn enable_clock(&self) {
+        self.clock.enable();
+    }
+
+    pub fn disable_clock(&self) {
+        self.clock.disable();
+    }
+
+    pub fn handle_event(&self) {
+        if self.status.get() == I2CStatus::Idle {
+            if self.listening.get() {
+                self.handle_slave_event();
+            }
+            return;
+        }
+
+        if self.registers.sr1.is_set(SR1::SB) {
+            // reading SR1 followed by writing DR clears SB
+            let read = if self.status.get() == I2CStatus::Reading {
+                1
+            } else {
+                0
+            };
+            self.address_acked.set(false);
+            self.registers
+                .dr
+                .write(DR::DR.val(((self.slave_address.get() as u32) << 1) | read));
+            return;
+        }
+
+        if self.registers.sr1.is_set(SR1::ADDR) {
+            self.address_acked.set(true);
+            if self.status.get() == I2CStatus::Reading {
+                if self.rx_len.get() <= 1 {
+                    // NACK the only byte and stop right after it
+                    self.registers.cr1.modify(CR1::ACK::CLEAR);
+                    self.registers.sr2.get();
+                    self.registers.cr1.modify(CR1::STOP::SET);
+                } else {
+                    self.registers.cr1.modify(CR1::ACK::SET);
+                    self.registers.sr2.get();
+                }
+            } else {
+                self.registers.sr2.get();
+            }
+            return;
+        }
+
+        match self.status.get() {
+            I2CStatus::Writing | I2CStatus::WritingReading => {
+                if self.tx_position.get() < self.tx_len.get() {
+                    if self.registers.sr1.is_set(SR1::TXE) {
+                        self.buffer.map(|buf| {
+                            let byte = buf[self.tx_position.get() as usize];
+                            self.registers.dr.write(DR::DR.val(byte as u32));
+                        });
+                        self.tx_position.set(self.tx_position.get() + 1);
+                    }
+                } else if self.registers.sr1.is_set(SR1::BTF) {
+                    // the last byte has been acknowledged
+                    if self.status.get() == I2CStatus::Writing {
+                        self.registers.cr1.modify(CR1::STOP::SET);
+                        self.complete(Error::CommandComplete);
+                    } else {
+                        self.status.set(I2CStatus::Reading);
+                        self.start_read();
+                    }
+                } else {
+                    // wait for BTF without being interrupted for TXE
+                    self.registers.cr2.modify(CR2::ITBUFEN::CLEAR);
+                }
+            }
+            I2CStatus::Reading => {
+                if self.registers.sr1.is_set(SR1::RXNE) {
+                    let position = self.rx_position.get();
+                    if position + 2 == self.rx_len.get() {
+                        // the byte being received now is the last one
+                        self.registers.cr1.modify(CR1::ACK::CLEAR);
+                        self.registers.cr1.modify(CR1::STOP::SET);
+                    }
+                    let byte = self.registers.dr.read(DR::DR) as u8;
+                    if position < self.rx_len.get() {
+                        self.buffer.map(|buf| {
+                            buf[position as usize] = byte;
+                        });
+                        self.rx_position.set(position + 1);
+                    }
+                    if self.rx_position.get() >= self.rx_len.get() {
+                        self.complete(Error::CommandComplete);
+                    }
+                }
+            }
+            I2CStatus::Idle => {}
+        }
+    }
+
+    pub fn handle_error(&self) {
+        let error = if self.registers.sr1.is_set(SR1::AF) {
+            self.registers.sr1.modify(SR1::AF::CLEAR);
+            if self.status.get() == I2CStatus::Idle {
+                // in slave mode, the master ends a read by not acknowledging
+                // the last byte
+                self.slave_read_done();
+                return;
+            }
+            if self.address_acked.get() {
+                Error::DataNak
+            } else {
+                Error::AddressNak
+            }
+        } else if self.registers.sr1.is_set(SR1::ARLO) {
+            self.registers.sr1.modify(SR1::ARLO::CLEAR);
+            Error::ArbitrationLost
+        } else if self.registers.sr1.is_set(SR1::OVR) {
+            self.registers.sr1.modify(SR1::OVR::CLEAR);
+            Error::Overrun
+        } else {
+            // not sure that this is the best error to send
+            self.registers
+                .sr1
+                .modify(SR1::BERR::CLEAR + SR1::TIMEOUT::CLEAR + SR1::PECERR::CLEAR);
+            Error::DataNak
+        };
+
+        if self.status.get() != I2CStatus::Idle {
+            // arbitration loss already released the bus
+            if error != Error::ArbitrationLost {
+                self.registers.cr1.modify(CR1::STOP::SET);
+            }
+            self.complete(error);
+        }
+    }
+
+    fn complete(&self, error: Error) {
+        self.stop();
+        self.master_client.map(|client| {
+            self.buffer
+                .take()
+                .map(|buf| client.command_complete(buf, error))
+        });
+    }
+
+    fn handle_slave_event(&self) {
+        if self.registers.sr1.is_set(SR1::ADDR) {
+            // reading SR2 after SR1 clears ADDR
+            if self.registers.sr2.is_set(SR2::TRA) {
+                self.slave_status.set(I2CSlaveStatus::Transmitting);
+                self.slave_tx_position.set(0);
+                if self.slave_tx_buffer.is_none() {
+                    self.slave_client.map(|client| client.read_expected());
+                }
+            } else {
+                self.slave_status.set(I2CSlaveStatus::Receiving);
+                self.slave_rx_position.set(0);
+                if self.slave_rx_buffer.is_none() {
+                    self.slave_client.map(|client| client.write_expected());
+                }
+                if self.slave_rx_buffer.is_none() {
+                    // nowhere to put the data
+                    self.registers.cr1.modify(CR1::ACK::CLEAR);
+                }
+            }
+        }
+
+        if self.registers.sr1.is_set(SR1::RXNE) {
+            let byte = self.registers.dr.read(DR::DR) as u8;
+            let position = self.slave_rx_position.get();
+            if position < self.slave_rx_len.get() {
+                self.slave_rx_buffer.map(|buf| {
+                    buf[position] = byte;
+                });
+                self.slave_rx_position.set(position + 1);
+            }
+        }
+
+        if self.slave_status.get() == I2CSlaveStatus::Transmitting
+            && self.registers.sr1.is_set(SR1::TXE)
+        {
+            // send 0xff once the buffer runs out
+            let position = self.slave_tx_position.get();
+            let byte = if position < self.slave_tx_len.get() {
+                self.slave_tx_buffer.map_or(0xff, |buf| buf[position])
+            } else {
+                0xff
+            };
+            self.registers.dr.write(DR::DR.val(byte as u32));
+            self.slave_tx_position.set(position + 1);
+        }
+
+        if self.registers.sr1.is_set(SR1::STOPF) {
+            // reading SR1 followed by writing CR1 clears STOPF
+            self.registers.cr1.modify(CR1::ACK::SET);
+            if self.slave_status.get() == I2CSlaveStatus::Receiving {
+                let len = cmp::min(self.slave_rx_position.get(), self.slave_rx_len.get());
+                self.slave_rx_buffer.take().map(|buf| {
+                    self.slave_client.map(move |client| {
+                        client.command_complete(buf, len as u8, i2c::SlaveTransmissionType::Write)
+                    });
+                });
+            }
+            self.slave_status.set(I2CSlaveStatus::Idle);
+        }
+    }
+
+    fn slave_read_done(&self) {
+        if self.slave_status.get() != I2CSlaveStatus::Transmitting {
+            return;
+        }
+        self.slave_status.set(I2CSlaveStatus::Idle);
+        // the byte already loaded into the data register is never sent
+        let len = cmp::min(
+            self.slave_tx_position.get().saturating_sub(1),
+            self.slave_tx_len.get(),
+        );
+        self.slave_tx_buffer.take().map(|buf| {
+            self.slave_client.map(move |client| {
+                client.command_complete(buf, len as u8, i2c::SlaveTransmissionType::Read)
+            });
+        });
+    }
+
+    fn start_write(&self) {
+        self.tx_position.set(0);
+        self.registers
+            .cr2
+            .modify(CR2::ITEVTEN::SET + CR2::ITBUFEN::SET + CR2::ITERREN::SET);
+        self.registers.cr1.modify(CR1::START::SET);
+    }
+
+    fn start_read(&self) {
+        self.rx_position.set(0);
+        self.registers
+            .cr2
+            .modify(CR2::ITEVTEN::SET + CR2::ITBUFEN::SET + CR2::ITERREN::SET);
+        self.registers.cr1.modify(CR1::START::SET);
+    }
+
+    fn stop(&self) {
+        self.status.set(I2CStatus::Idle);
+        if self.listening.get() {
+            // the slave answers its own address again with ACK
+            self.registers.cr1.modify(CR1::ACK::SET);
+        } else {
+            self.registers
+                .cr2
+                .modify(CR2::ITEVTEN::CLEAR + CR2::ITBUFEN::CLEAR + CR2::ITERREN::CLEAR);
+        }
+    }
+}
+
+impl i2c::I2CMaster for I2C<'a> {
+    fn set_master_client(&self, master_client: &'static dyn I2CHwMasterClient) {
+        self.master_client.replace(master_client);
+    }
+    fn enable(&self) {
+        self.registers.cr1.modify(CR1::PE::SET);
+    }
+    fn disable(&self) {
+        self.registers.cr1.modify(CR1::PE::CLEAR);
+    }
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        if self.status.get() == I2CStatus::Idle {
+            self.status.set(I2CStatus::WritingReading);
+            self.slave_address.set(addr);
+            self.buffer.replace(data);
+            self.tx_len.set(write_len);
+            self.rx_len.set(read_len);
+            self.start_write();
+        }
+    }
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        if self.status.get() == I2CStatus::Idle {
+            self.status.set(I2CStatus::Writing);
+            self.slave_address.set(addr);
+            self.buffer.replace(data);
+            self.tx_len.set(len);
+            self.start_write();
+        }
+    }
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        if self.status.get() == I2CStatus::Idle {
+            self.status.set(I2CStatus::Reading);
+            self.slave_address.set(addr);
+            self.buffer.replace(buffer);
+            self.rx_len.set(len);
+            self.start_read();
+        }
+    }
+}
+
+impl i2c::I2CSlave for I2C<'a> {
+    fn set_slave_client(&self, slave_client: &'static dyn I2CHwSlaveClient) {
+        self.slave_client.replace(slave_client);
+    }
+    fn enable(&self) {
+        self.registers.cr1.modify(CR1::PE::SET);
+    }
+    fn disable(&self) {
+        self.listening.set(false);
+        self.registers.cr1.modify(CR1::ACK::CLEAR);
+        if self.status.get() == I2CStatus::Idle {
+            self.registers
+                .cr2
+                .modify(CR2::ITEVTEN::CLEAR + CR2::ITBUFEN::CLEAR + CR2::ITERREN::CLEAR);
+        }
+    }
+    fn set_address(&self, addr: u8) {
+        self.registers
+            .oar1
+            .write(OAR1::ONE::SET + OAR1::ADD.val((addr as u32) << 1));
+    }
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        self.slave_rx_len
+            .set(cmp::min(max_len as usize, data.len()));
+        self.slave_rx_buffer.replace(data);
+    }
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        self.slave_tx_len
+            .set(cmp::min(max_len as usize, data.len()));
+        self.slave_tx_buffer.replace(data);
+    }
+    fn listen(&self) {
+        self.listening.set(true);
+        self.registers.cr1.modify(CR1::ACK::SET);
+        self.registers
+            .cr2
+            .modify(CR2::ITEVTEN::SET + CR2::ITBUFEN::SET + CR2::ITERREN::SET);
+    }
+}
+
+impl i2c::I2CMasterSlave for I2C<'a> {}
+
+struct I2CClock(rcc::PeripheralClock);
+
+impl ClockInterface for I2CClock {
+    fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    fn enable(&self) {
+        self.0.enable();
+    }
+
+    fn disable(&self) {
+        self.0.disable();
+    }
+}
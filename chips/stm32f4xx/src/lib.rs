@@ -16,7 +16,9 @@ pub mod dbg;
 pub mod dma1;
 pub mod exti;
 pub mod gpio;
+pub mod i2c;
 pub mod rcc;
+pub mod rtc;
 pub mod spi;
 pub mod syscfg;
 pub mod tim2;
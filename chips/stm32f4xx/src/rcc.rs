@@ -749,6 +749,20 @@ impl Rcc {
         self.registers.apb1enr.modify(APB1ENR::SPI3EN::CLEAR)
     }
 
+    // I2C1 clock
+
+    fn is_enabled_i2c1_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::I2C1EN)
+    }
+
+    fn enable_i2c1_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::I2C1EN::SET)
+    }
+
+    fn disable_i2c1_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::I2C1EN::CLEAR)
+    }
+
     // TIM2 clock
 
     fn is_enabled_tim2_clock(&self) -> bool {
@@ -930,6 +944,71 @@ impl Rcc {
     fn disable_usart3_clock(&self) {
         self.registers.apb1enr.modify(APB1ENR::USART3EN::CLEAR)
     }
+
+    // PWR clock
+
+    fn is_enabled_pwr_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::PWREN)
+    }
+
+    fn enable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::SET)
+    }
+
+    fn disable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::CLEAR)
+    }
+
+    // RTC clock
+    //
+    // These registers are in the backup domain, so writes only take effect
+    // once backup domain write protection has been disabled in PWR_CR.
+
+    pub fn is_enabled_rtc_clock(&self) -> bool {
+        self.registers.bdcr.is_set(BDCR::RTCEN)
+    }
+
+    pub fn get_rtc_clock_source(&self) -> Option<RtcClockSource> {
+        match self.registers.bdcr.read(BDCR::RTCSEL) {
+            0b01 => Some(RtcClockSource::LSE),
+            0b10 => Some(RtcClockSource::LSI),
+            _ => None,
+        }
+    }
+
+    pub fn enable_rtc_clock(&self, source: RtcClockSource) {
+        self.registers
+            .bdcr
+            .modify(BDCR::RTCSEL.val(source as u32) + BDCR::RTCEN::SET);
+    }
+
+    /// Start the external 32.768 kHz oscillator. Returns false if it does not
+    /// become ready within `timeout` polls, e.g. because no crystal is
+    /// fitted.
+    pub fn enable_lse(&self, timeout: usize) -> bool {
+        self.registers.bdcr.modify(BDCR::LSEON::SET);
+        for _ in 0..timeout {
+            if self.registers.bdcr.is_set(BDCR::LSERDY) {
+                return true;
+            }
+        }
+        self.registers.bdcr.modify(BDCR::LSEON::CLEAR);
+        false
+    }
+
+    /// Start the internal 32 kHz RC oscillator. Unlike the LSE, the LSI is
+    /// not in the backup domain and must be started again after every reset.
+    pub fn enable_lsi(&self) {
+        self.registers.csr.modify(CSR::LSION::SET);
+        while !self.registers.csr.is_set(CSR::LSIRDY) {}
+    }
+}
+
+/// Clock sources for the RTC
+#[derive(Copy, Clone, PartialEq)]
+pub enum RtcClockSource {
+    LSE = 0b01,
+    LSI = 0b10,
 }
 
 /// Clock sources for CPU
@@ -971,6 +1050,8 @@ pub enum PCLK1 {
     USART2,
     USART3,
     SPI3,
+    I2C1,
+    PWR,
 }
 
 /// Peripherals clocked by PCLK2
@@ -997,6 +1078,8 @@ impl ClockInterface for PeripheralClock {
                 PCLK1::USART2 => unsafe { RCC.is_enabled_usart2_clock() },
                 PCLK1::USART3 => unsafe { RCC.is_enabled_usart3_clock() },
                 PCLK1::SPI3 => unsafe { RCC.is_enabled_spi3_clock() },
+                PCLK1::I2C1 => unsafe { RCC.is_enabled_i2c1_clock() },
+                PCLK1::PWR => unsafe { RCC.is_enabled_pwr_clock() },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SYSCFG => unsafe { RCC.is_enabled_syscfg_clock() },
@@ -1048,6 +1131,12 @@ impl ClockInterface for PeripheralClock {
                 PCLK1::SPI3 => unsafe {
                     RCC.enable_spi3_clock();
                 },
+                PCLK1::I2C1 => unsafe {
+                    RCC.enable_i2c1_clock();
+                },
+                PCLK1::PWR => unsafe {
+                    RCC.enable_pwr_clock();
+                },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SYSCFG => unsafe {
@@ -1101,6 +1190,12 @@ impl ClockInterface for PeripheralClock {
                 PCLK1::SPI3 => unsafe {
                     RCC.disable_spi3_clock();
                 },
+                PCLK1::I2C1 => unsafe {
+                    RCC.disable_i2c1_clock();
+                },
+                PCLK1::PWR => unsafe {
+                    RCC.disable_pwr_clock();
+                },
             },
             &PeripheralClock::APB2(ref v) => match v {
                 PCLK2::SYSCFG => unsafe {
@@ -0,0 +1,314 @@
+//! Real-time clock (RTC) driver.
+//!
+//! The RTC keeps calendar time in BCD registers in the backup domain, so the
+//! time survives resets for as long as VBAT or VDD stays powered. It is
+//! clocked from the external 32.768 kHz crystal (LSE) if one is fitted, and
+//! from the internal 32 kHz RC oscillator (LSI) otherwise. The LSI is far less
+//! accurate and is not kept running across resets.
+//!
+//! The hardware calendar only stores a two-digit year, which this driver maps
+//! to 2000 through 2099.
+
+use crate::rcc;
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::date_time::{self, DateTimeValues};
+use kernel::ClockInterface;
+use kernel::ReturnCode;
+
+/// Real-time clock
+#[repr(C)]
+struct RtcRegisters {
+    /// time register
+    tr: ReadWrite<u32, TR::Register>,
+    /// date register
+    dr: ReadWrite<u32, DR::Register>,
+    /// control register
+    cr: ReadWrite<u32, CR::Register>,
+    /// initialization and status register
+    isr: ReadWrite<u32, ISR::Register>,
+    /// prescaler register
+    prer: ReadWrite<u32, PRER::Register>,
+    _reserved0: [u32; 4],
+    /// write protection register
+    wpr: WriteOnly<u32, WPR::Register>,
+    _reserved1: [u32; 10],
+    /// backup register 0
+    bkp0r: ReadWrite<u32>,
+}
+
+/// Power control
+#[repr(C)]
+struct PwrRegisters {
+    /// power control register
+    cr: ReadWrite<u32, PWR_CR::Register>,
+}
+
+register_bitfields![u32,
+    TR [
+        /// AM/PM notation
+        PM OFFSET(22) NUMBITS(1) [],
+        /// Hour tens in BCD format
+        HT OFFSET(20) NUMBITS(2) [],
+        /// Hour units in BCD format
+        HU OFFSET(16) NUMBITS(4) [],
+        /// Minute tens in BCD format
+        MNT OFFSET(12) NUMBITS(3) [],
+        /// Minute units in BCD format
+        MNU OFFSET(8) NUMBITS(4) [],
+        /// Second tens in BCD format
+        ST OFFSET(4) NUMBITS(3) [],
+        /// Second units in BCD format
+        SU OFFSET(0) NUMBITS(4) []
+    ],
+    DR [
+        /// Year tens in BCD format
+        YT OFFSET(20) NUMBITS(4) [],
+        /// Year units in BCD format
+        YU OFFSET(16) NUMBITS(4) [],
+        /// Week day units (1 is Monday, 7 is Sunday)
+        WDU OFFSET(13) NUMBITS(3) [],
+        /// Month tens in BCD format
+        MT OFFSET(12) NUMBITS(1) [],
+        /// Month units in BCD format
+        MU OFFSET(8) NUMBITS(4) [],
+        /// Date tens in BCD format
+        DT OFFSET(4) NUMBITS(2) [],
+        /// Date units in BCD format
+        DU OFFSET(0) NUMBITS(4) []
+    ],
+    CR [
+        /// Hour format (0 is 24 hour format)
+        FMT OFFSET(6) NUMBITS(1) [],
+        /// Bypass the shadow registers
+        BYPSHAD OFFSET(5) NUMBITS(1) []
+    ],
+    ISR [
+        /// Initialization mode
+        INIT OFFSET(7) NUMBITS(1) [],
+        /// Initialization flag
+        INITF OFFSET(6) NUMBITS(1) [],
+        /// Registers synchronization flag
+        RSF OFFSET(5) NUMBITS(1) [],
+        /// Initialization status flag
+        INITS OFFSET(4) NUMBITS(1) []
+    ],
+    PRER [
+        /// Asynchronous prescaler factor
+        PREDIV_A OFFSET(16) NUMBITS(7) [],
+        /// Synchronous prescaler factor
+        PREDIV_S OFFSET(0) NUMBITS(15) []
+    ],
+    WPR [
+        /// Write protection key
+        KEY OFFSET(0) NUMBITS(8) []
+    ],
+    PWR_CR [
+        /// Disable backup domain write protection
+        DBP OFFSET(8) NUMBITS(1) []
+    ]
+];
+
+const RTC_BASE: StaticRef<RtcRegisters> =
+    unsafe { StaticRef::new(0x40002800 as *const RtcRegisters) };
+
+const PWR_BASE: StaticRef<PwrRegisters> =
+    unsafe { StaticRef::new(0x40007000 as *const PwrRegisters) };
+
+/// Written to backup register 0 once the calendar has been set, so that a
+/// calendar left over from before a backup domain reset is not trusted.
+const BACKUP_MAGIC: u32 = 0xDA7E_71E5;
+
+/// Number of polls to wait for the LSE to start. The LSE can take up to two
+/// seconds to stabilize.
+const LSE_STARTUP_TIMEOUT: usize = 4_000_000;
+
+pub struct Rtc {
+    registers: StaticRef<RtcRegisters>,
+    pwr: StaticRef<PwrRegisters>,
+    clock: RtcClock,
+}
+
+pub static mut RTC: Rtc = Rtc::new();
+
+impl Rtc {
+    const fn new() -> Rtc {
+        Rtc {
+            registers: RTC_BASE,
+            pwr: PWR_BASE,
+            clock: RtcClock(rcc::PeripheralClock::APB1(rcc::PCLK1::PWR)),
+        }
+    }
+
+    pub fn is_enabled_clock(&self) -> bool {
+        self.clock.is_enabled()
+    }
+
+    pub fn enable_clock(&self) {
+        self.clock.enable();
+    }
+
+    pub fn disable_clock(&self) {
+        self.clock.disable();
+    }
+
+    /// Start the RTC if it is not already running from before the last reset.
+    ///
+    /// Before calling this, the PWR clock must have been enabled with
+    /// `enable_clock`.
+    pub fn enable(&self) {
+        // Allow writes to the backup domain
+        self.pwr.cr.modify(PWR_CR::DBP::SET);
+
+        unsafe {
+            match rcc::RCC.get_rtc_clock_source() {
+                Some(rcc::RtcClockSource::LSI) => rcc::RCC.enable_lsi(),
+                Some(rcc::RtcClockSource::LSE) => {}
+                None => {
+                    let source = if rcc::RCC.enable_lse(LSE_STARTUP_TIMEOUT) {
+                        rcc::RtcClockSource::LSE
+                    } else {
+                        rcc::RCC.enable_lsi();
+                        rcc::RtcClockSource::LSI
+                    };
+                    rcc::RCC.enable_rtc_clock(source);
+                    self.init_prescaler(source);
+                }
+            }
+        }
+
+        // After a reset, the shadow registers must be resynchronized before
+        // the calendar can be read.
+        self.unlock();
+        self.registers.isr.modify(ISR::RSF::CLEAR);
+        self.lock();
+        self.wait_synchronized();
+    }
+
+    fn unlock(&self) {
+        self.registers.wpr.write(WPR::KEY.val(0xCA));
+        self.registers.wpr.write(WPR::KEY.val(0x53));
+    }
+
+    fn lock(&self) {
+        self.registers.wpr.write(WPR::KEY.val(0xFF));
+    }
+
+    fn wait_synchronized(&self) {
+        while !self.registers.isr.is_set(ISR::RSF) {}
+    }
+
+    /// Run `f` with the calendar stopped in initialization mode.
+    fn initialize<F: FnOnce()>(&self, f: F) {
+        self.unlock();
+        self.registers.isr.modify(ISR::INIT::SET);
+        while !self.registers.isr.is_set(ISR::INITF) {}
+
+        f();
+
+        self.registers
+            .isr
+            .modify(ISR::INIT::CLEAR + ISR::RSF::CLEAR);
+        self.lock();
+        self.wait_synchronized();
+    }
+
+    fn init_prescaler(&self, source: rcc::RtcClockSource) {
+        // Divide the oscillator down to the 1 Hz calendar clock.
+        let prediv_s = match source {
+            rcc::RtcClockSource::LSE => 255, // 32768 Hz / 128 / 256
+            rcc::RtcClockSource::LSI => 249, // 32000 Hz / 128 / 250
+        };
+        self.initialize(|| {
+            // The two prescalers must be written in separate accesses.
+            self.registers.prer.write(PRER::PREDIV_S.val(prediv_s));
+            self.registers.prer.modify(PRER::PREDIV_A.val(127));
+            self.registers
+                .cr
+                .modify(CR::FMT::CLEAR + CR::BYPSHAD::CLEAR);
+        });
+    }
+}
+
+fn from_bcd(tens: u32, units: u32) -> u8 {
+    (tens * 10 + units) as u8
+}
+
+impl date_time::DateTime for Rtc {
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode> {
+        if self.registers.bkp0r.get() != BACKUP_MAGIC {
+            return Err(ReturnCode::EOFF);
+        }
+
+        // Reading TR locks the shadow DR until DR is read, so the two are
+        // always consistent.
+        let tr = self.registers.tr.extract();
+        let dr = self.registers.dr.extract();
+        Ok(DateTimeValues {
+            year: 2000 + from_bcd(dr.read(DR::YT), dr.read(DR::YU)) as u16,
+            month: from_bcd(dr.read(DR::MT), dr.read(DR::MU)),
+            day: from_bcd(dr.read(DR::DT), dr.read(DR::DU)),
+            hour: from_bcd(tr.read(TR::HT), tr.read(TR::HU)),
+            minute: from_bcd(tr.read(TR::MNT), tr.read(TR::MNU)),
+            second: from_bcd(tr.read(TR::ST), tr.read(TR::SU)),
+        })
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode {
+        if !date_time.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        if date_time.year < 2000 || date_time.year > 2099 {
+            return ReturnCode::ESIZE;
+        }
+
+        let year = (date_time.year - 2000) as u32;
+        let month = date_time.month as u32;
+        let day = date_time.day as u32;
+        let hour = date_time.hour as u32;
+        let minute = date_time.minute as u32;
+        let second = date_time.second as u32;
+        let week_day = match date_time.day_of_week() {
+            date_time::DayOfWeek::Sunday => 7,
+            day_of_week => day_of_week as u32,
+        };
+
+        self.initialize(|| {
+            self.registers.tr.write(
+                TR::HT.val(hour / 10)
+                    + TR::HU.val(hour % 10)
+                    + TR::MNT.val(minute / 10)
+                    + TR::MNU.val(minute % 10)
+                    + TR::ST.val(second / 10)
+                    + TR::SU.val(second % 10),
+            );
+            self.registers.dr.write(
+                DR::YT.val(year / 10)
+                    + DR::YU.val(year % 10)
+                    + DR::WDU.val(week_day)
+                    + DR::MT.val(month / 10)
+                    + DR::MU.val(month % 10)
+                    + DR::DT.val(day / 10)
+                    + DR::DU.val(day % 10),
+            );
+        });
+        self.registers.bkp0r.set(BACKUP_MAGIC);
+        ReturnCode::SUCCESS
+    }
+}
+
+struct RtcClock(rcc::PeripheralClock);
+
+impl ClockInterface for RtcClock {
+    fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    fn enable(&self) {
+        self.0.enable();
+    }
+
+    fn disable(&self) {
+        self.0.disable();
+    }
+}
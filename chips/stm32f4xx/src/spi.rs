@@ -6,7 +6,7 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::hil::gpio::Output;
-use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient, SpiSlaveClient};
 use kernel::{ClockInterface, ReturnCode};
 
 use crate::dma1;
@@ -143,8 +143,12 @@ pub struct Spi<'a> {
     registers: StaticRef<SpiRegisters>,
     clock: SpiClock,
 
-    // SPI slave support not yet implemented
     master_client: OptionalCell<&'a dyn hil::spi::SpiMasterClient>,
+    slave_client: OptionalCell<&'a dyn hil::spi::SpiSlaveClient>,
+    /// Whether the SPI was initialized as a slave
+    slave: Cell<bool>,
+    /// Byte sent in slave mode when there is no write buffer
+    write_byte: Cell<u8>,
 
     tx_dma: OptionalCell<&'a dma1::Stream<'a>>,
     tx_dma_pid: Dma1Peripheral,
@@ -182,6 +186,9 @@ impl Spi<'a> {
             clock,
 
             master_client: OptionalCell::empty(),
+            slave_client: OptionalCell::empty(),
+            slave: Cell::new(false),
+            write_byte: Cell::new(0),
 
             tx_dma: OptionalCell::empty(),
             tx_dma_pid: tx_dma_pid,
@@ -315,6 +322,13 @@ impl Spi<'a> {
 
         self.transfers_in_progress.set(0);
 
+        if write_buffer.is_none() && self.slave.get() {
+            // The master clocks out whatever is in the data register
+            self.registers
+                .dr
+                .write(DR::DR.val(self.write_byte.get() as u32));
+        }
+
         read_buffer.map(|rx_buffer| {
             self.transfers_in_progress
                 .set(self.transfers_in_progress.get() + 1);
@@ -345,6 +359,8 @@ impl spi::SpiMaster for Spi<'a> {
     }
 
     fn init(&self) {
+        self.slave.set(false);
+
         // enable error interrupt (used only for debugging)
         // self.registers.cr2.modify(CR2::ERRIE::SET);
 
@@ -455,6 +471,70 @@ impl spi::SpiMaster for Spi<'a> {
     }
 }
 
+/// In slave mode, the SPI uses its hardware NSS pin as chip select, which
+/// the board must configure as an alternate function. `chip_selected` is
+/// never called; transfers are set up in advance and complete once the
+/// master has clocked `len` bytes.
+impl spi::SpiSlave for Spi<'a> {
+    fn init(&self) {
+        self.slave.set(true);
+
+        self.registers.cr1.modify(
+            // 2 line unidirectional mode
+            CR1::BIDIMODE::CLEAR +
+            // Select as slave
+            CR1::MSTR::CLEAR +
+            // Hardware slave management
+            CR1::SSM::CLEAR +
+            // 8 bit data frame format
+            CR1::DFF::CLEAR +
+            // Enable
+            CR1::SPE::SET,
+        );
+    }
+
+    fn has_client(&self) -> bool {
+        self.slave_client.is_some()
+    }
+
+    fn set_client(&self, client: Option<&'static dyn SpiSlaveClient>) {
+        self.slave_client.insert(client);
+    }
+
+    fn set_write_byte(&self, write_byte: u8) {
+        self.write_byte.set(write_byte);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.transfers_in_progress.get() != 0 {
+            return ReturnCode::EBUSY;
+        }
+
+        self.read_write_bytes(write_buffer, read_buffer, len)
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.set_polarity(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.get_polarity()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.set_phase(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.get_phase()
+    }
+}
+
 impl dma1::StreamClient for Spi<'a> {
     fn transfer_done(&self, pid: dma1::Dma1Peripheral) {
         if pid == self.tx_dma_pid {
@@ -483,6 +563,12 @@ impl dma1::StreamClient for Spi<'a> {
             let length = self.dma_len.get();
             self.dma_len.set(0);
 
+            if self.slave.get() {
+                self.slave_client
+                    .map(move |client| client.read_write_done(tx_buffer, rx_buffer, length));
+                return;
+            }
+
             self.master_client.map(|client| {
                 tx_buffer.map(|t| {
                     client.read_write_done(t, rx_buffer, length);
@@ -130,6 +130,39 @@ impl AppId {
             (start, end)
         })
     }
+
+    /// Returns an identifier for the app that persists across reboots,
+    /// restarts and reinstalls, unlike `id()`. It is a 32-bit FNV-1a hash of
+    /// the package name in the app's TBF header, so capsules can use it to
+    /// key data they store on behalf of the app.
+    ///
+    /// Apps without a package name have no persistent identity, and `None` is
+    /// returned for them, as well as if the app no longer exists.
+    ///
+    /// The identity is only as trustworthy as the apps the board loads. The
+    /// package name is not authenticated, so an app that declares the name
+    /// of another app gets the same identity, and with it access to the data
+    /// of that app. FNV-1a is not a cryptographic hash either, and a name
+    /// that collides with another one is easy to construct on purpose, while
+    /// accidental collisions between honest apps are unlikely. Capsules that
+    /// key storage on this identity therefore isolate apps from each other's
+    /// mistakes, not from each other.
+    pub fn persistent_id(&self) -> Option<u32> {
+        self.kernel.process_map_or(None, *self, |process| {
+            persistent_id_of(process.get_process_name())
+        })
+    }
+}
+
+/// Computes the persistent identity of an app with the package name `name`.
+fn persistent_id_of(name: &str) -> Option<u32> {
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        }))
+    }
 }
 
 /// Type to uniquely identify a callback subscription across all drivers.
@@ -206,3 +239,35 @@ impl Callback {
         res
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::persistent_id_of;
+
+    #[test]
+    fn persistent_id_is_fnv1a_of_the_name() {
+        // Stored data is keyed on these values, so they must not change
+        assert_eq!(persistent_id_of(""), None);
+        assert_eq!(persistent_id_of("a"), Some(0xe40c_292c));
+        assert_eq!(persistent_id_of("foobar"), Some(0xbf9c_f968));
+    }
+
+    #[test]
+    fn different_names_do_not_share_an_identity() {
+        let names = [
+            "blink", "Blink", "blink2", "blin", "kv_app", "kv_apq", "fat_app", "afp_tat",
+            "sensors", "sensor", "c_hello", "hello",
+        ];
+        for (i, first) in names.iter().enumerate() {
+            for second in &names[i + 1..] {
+                assert_ne!(
+                    persistent_id_of(first),
+                    persistent_id_of(second),
+                    "{} and {}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
+}
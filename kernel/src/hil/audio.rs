@@ -0,0 +1,163 @@
+//! Interfaces for streaming digital audio peripherals.
+//!
+//! These traits cover peripherals that move a continuous stream of PCM
+//! samples between memory and an external audio device, such as PDM
+//! microphones or I2S codecs. Samples are always signed 16-bit PCM. For stereo
+//! streams, samples are interleaved left then right.
+//!
+//! Streams are double-buffered: the client hands the driver two buffers when
+//! starting, and the driver returns each buffer through a callback once it has
+//! been filled (input) or played out (output). The client is expected to hand
+//! a buffer back with `provide_buffer` from within that callback so the
+//! peripheral always has a buffer queued; if it does not, the driver will stop
+//! the stream rather than overwrite a buffer the client still owns.
+
+use crate::returncode::ReturnCode;
+
+/// Number of interleaved channels in a stream.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channels {
+    Mono = 1,
+    Stereo = 2,
+}
+
+/// Framing of samples on the serial audio bus.
+///
+/// Peripherals without a frame format, such as PDM microphones, ignore this
+/// setting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// Standard (Philips) I2S, with data delayed one clock after the word
+    /// select edge.
+    I2s,
+    /// Data aligned to the word select edge.
+    LeftJustified,
+    /// Data aligned to the end of the word select period.
+    RightJustified,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Parameters {
+    pub sample_rate: u32, // sample rate in Hz, per channel
+    pub channels: Channels,
+    pub format: Format,
+}
+
+/// Trait for configuring a digital audio peripheral.
+pub trait Configure {
+    /// Set the stream parameters. Must be called while the peripheral is not
+    /// streaming.
+    ///
+    /// Returns SUCCESS, or
+    /// - EBUSY: A stream is currently active.
+    /// - EINVAL: Impossible parameters (e.g. a `sample_rate` of 0).
+    /// - ENOSUPPORT: The peripheral cannot satisfy this configuration.
+    fn configure(&self, params: Parameters) -> ReturnCode;
+
+    /// Returns the sample rate the peripheral actually achieves, in Hz.
+    ///
+    /// Audio peripherals usually derive their clocks by dividing a fixed
+    /// source, so the achieved rate can differ slightly from the one that was
+    /// requested.
+    fn get_sample_rate(&self) -> u32;
+}
+
+/// Interface for receiving a continuous stream of samples.
+pub trait AudioInput<'a>: Configure {
+    /// Set the client that receives filled buffers.
+    fn set_client(&self, client: &'a dyn InputClient);
+
+    /// Start recording into `buffer1` and then `buffer2`.
+    ///
+    /// The full length of each buffer is used. If an error occurs, the
+    /// buffers are returned.
+    fn start_receive(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+
+    /// Provide the next buffer to record into.
+    ///
+    /// Expected to be called in a `samples_received` callback. If an error
+    /// occurs, the buffer is returned.
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>);
+
+    /// Stop recording. No further `samples_received` callbacks will occur.
+    fn stop_receive(&self) -> ReturnCode;
+
+    /// Reclaim ownership of the buffers the driver still holds.
+    ///
+    /// Can only be called when the peripheral is not recording, otherwise
+    /// EBUSY is returned.
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+}
+
+/// Trait for handling callbacks from an `AudioInput`.
+pub trait InputClient {
+    /// Called when a buffer has been filled with `length` samples.
+    fn samples_received(&self, buffer: &'static mut [i16], length: usize);
+}
+
+/// Interface for playing a continuous stream of samples.
+pub trait AudioOutput<'a>: Configure {
+    /// Set the client that receives played-out buffers.
+    fn set_client(&self, client: &'a dyn OutputClient);
+
+    /// Start playing `buffer1` and then `buffer2`.
+    ///
+    /// The full length of each buffer is used. If an error occurs, the
+    /// buffers are returned.
+    fn start_transmit(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+
+    /// Provide the next buffer to play.
+    ///
+    /// Expected to be called in a `samples_transmitted` callback. If an error
+    /// occurs, the buffer is returned.
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>);
+
+    /// Stop playing. No further `samples_transmitted` callbacks will occur.
+    fn stop_transmit(&self) -> ReturnCode;
+
+    /// Reclaim ownership of the buffers the driver still holds.
+    ///
+    /// Can only be called when the peripheral is not playing, otherwise
+    /// EBUSY is returned.
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+}
+
+/// Trait for handling callbacks from an `AudioOutput`.
+pub trait OutputClient {
+    /// Called when all samples in `buffer` have been played out.
+    fn samples_transmitted(&self, buffer: &'static mut [i16]);
+}
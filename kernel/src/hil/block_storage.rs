@@ -0,0 +1,45 @@
+//! Interface for storage devices addressed in fixed-size blocks.
+//!
+//! SD cards and disks are accessed a block at a time. Other storage, such as
+//! a region of a nonvolatile storage device, can be exported as blocks too,
+//! so that users such as USB mass storage or a filesystem work on any of
+//! them.
+
+use crate::returncode::ReturnCode;
+
+/// Size of the blocks of a `BlockStorage`.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Block-addressed storage.
+pub trait BlockStorage<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// The number of blocks of `BLOCK_SIZE` bytes, or `None` while no medium
+    /// is available.
+    fn block_count(&self) -> Option<u32>;
+
+    /// Read block `block` into the first `BLOCK_SIZE` bytes of `buffer`. The
+    /// buffer is returned if the read could not be started.
+    fn read_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Write the first `BLOCK_SIZE` bytes of `buffer` to block `block`. The
+    /// buffer is returned if the write could not be started.
+    fn write_block(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+/// Callbacks of a `BlockStorage`.
+pub trait BlockStorageClient {
+    fn read_done(&self, buffer: &'static mut [u8], rcode: ReturnCode);
+    fn write_done(&self, buffer: &'static mut [u8], rcode: ReturnCode);
+
+    /// A medium was inserted or removed.
+    fn medium_changed(&self);
+}
@@ -0,0 +1,204 @@
+//! Interface for real-time clocks that keep calendar (wall-clock) time.
+//!
+//! Unlike `hil::time`, which only exposes free-running tick counters, a
+//! `DateTime` implementation tracks the current date and time of day. On most
+//! chips this time lives in a backup or always-on power domain, so it survives
+//! a reset of the rest of the chip.
+//!
+//! All times are in UTC. Dates are in the proleptic Gregorian calendar and are
+//! limited to the range representable as an unsigned 32-bit Unix timestamp,
+//! i.e. 1970-01-01 00:00:00 to 2106-02-07 06:28:15.
+
+use crate::returncode::ReturnCode;
+
+const SECONDS_PER_DAY: u32 = 86400;
+const DAYS_BEFORE_MONTH: [u16; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DayOfWeek {
+    Sunday = 0,
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+}
+
+/// A calendar date and time of day.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DateTimeValues {
+    pub year: u16,  // e.g. 2020
+    pub month: u8,  // 1 to 12
+    pub day: u8,    // 1 to 31
+    pub hour: u8,   // 0 to 23
+    pub minute: u8, // 0 to 59
+    pub second: u8, // 0 to 59
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTimeValues {
+    /// Converts a Unix timestamp (seconds since 1970-01-01 00:00:00 UTC).
+    pub fn from_unix_time(timestamp: u32) -> DateTimeValues {
+        let mut days = timestamp / SECONDS_PER_DAY;
+        let secs = timestamp % SECONDS_PER_DAY;
+
+        let mut year = 1970;
+        loop {
+            let days_in_year = if is_leap_year(year) { 366 } else { 365 };
+            if days < days_in_year {
+                break;
+            }
+            days -= days_in_year;
+            year += 1;
+        }
+
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u32 {
+            days -= days_in_month(year, month) as u32;
+            month += 1;
+        }
+
+        DateTimeValues {
+            year: year as u16,
+            month: month,
+            day: days as u8 + 1,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Converts to a Unix timestamp.
+    ///
+    /// Returns `None` if the fields do not describe a valid date and time, or
+    /// if it lies outside the range of an unsigned 32-bit timestamp.
+    pub fn to_unix_time(&self) -> Option<u32> {
+        if !self.is_valid() {
+            return None;
+        }
+        let year = self.year as u32;
+
+        // Days from 1970-01-01 to the start of `year`, counting leap days.
+        let leap_days = |y: u32| y / 4 - y / 100 + y / 400;
+        let mut days = (year - 1970) * 365 + leap_days(year - 1) - leap_days(1969);
+        days += DAYS_BEFORE_MONTH[self.month as usize - 1] as u32;
+        if self.month > 2 && is_leap_year(year) {
+            days += 1;
+        }
+        days += self.day as u32 - 1;
+
+        let secs = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        let timestamp = days as u64 * SECONDS_PER_DAY as u64 + secs;
+        if timestamp > core::u32::MAX as u64 {
+            None
+        } else {
+            Some(timestamp as u32)
+        }
+    }
+
+    /// Returns whether every field is within its range.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && self.year <= 2106
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year as u32, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    pub fn day_of_week(&self) -> DayOfWeek {
+        // 1970-01-01 was a Thursday.
+        let days = self.to_unix_time().unwrap_or(0) / SECONDS_PER_DAY;
+        match (days + 4) % 7 {
+            0 => DayOfWeek::Sunday,
+            1 => DayOfWeek::Monday,
+            2 => DayOfWeek::Tuesday,
+            3 => DayOfWeek::Wednesday,
+            4 => DayOfWeek::Thursday,
+            5 => DayOfWeek::Friday,
+            _ => DayOfWeek::Saturday,
+        }
+    }
+}
+
+/// Interface for reading and setting the calendar time of a real-time clock.
+///
+/// Both operations are synchronous, as on-chip RTCs expose the current time
+/// directly in registers.
+pub trait DateTime {
+    /// Returns the current date and time.
+    ///
+    /// Returns an error if the clock has never been set since it last lost
+    /// power:
+    /// - EOFF: The clock is not running or has not been set.
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode>;
+
+    /// Sets the current date and time.
+    ///
+    /// Returns SUCCESS, or
+    /// - EINVAL: `date_time` is not a valid date and time.
+    /// - ESIZE: `date_time` lies outside the range the clock can represent.
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dt(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTimeValues {
+        DateTimeValues {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn unix_epoch() {
+        assert_eq!(DateTimeValues::from_unix_time(0), dt(1970, 1, 1, 0, 0, 0));
+        assert_eq!(dt(1970, 1, 1, 0, 0, 0).to_unix_time(), Some(0));
+        assert_eq!(dt(1970, 1, 1, 0, 0, 0).day_of_week(), DayOfWeek::Thursday);
+    }
+
+    #[test]
+    fn leap_days() {
+        let feb29 = dt(2000, 2, 29, 12, 30, 45);
+        assert_eq!(feb29.to_unix_time(), Some(951827445));
+        assert_eq!(DateTimeValues::from_unix_time(951827445), feb29);
+        assert_eq!(feb29.day_of_week(), DayOfWeek::Tuesday);
+        assert!(!dt(2100, 2, 29, 0, 0, 0).is_valid());
+        assert_eq!(
+            DateTimeValues::from_unix_time(1583020800),
+            dt(2020, 3, 1, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn range_limits() {
+        let last = dt(2106, 2, 7, 6, 28, 15);
+        assert_eq!(last.to_unix_time(), Some(core::u32::MAX));
+        assert_eq!(DateTimeValues::from_unix_time(core::u32::MAX), last);
+        assert_eq!(dt(2106, 2, 7, 6, 28, 16).to_unix_time(), None);
+        assert_eq!(dt(1969, 12, 31, 23, 59, 59).to_unix_time(), None);
+        assert_eq!(dt(2020, 13, 1, 0, 0, 0).to_unix_time(), None);
+    }
+}
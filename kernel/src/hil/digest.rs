@@ -70,3 +70,8 @@ pub trait HMACSha256 {
     /// The key used for the HMAC is passed to this function.
     fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode>;
 }
+
+pub trait Sha256 {
+    /// Call before `Digest::run()` to perform Sha256
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode>;
+}
@@ -2,9 +2,12 @@
 
 pub mod adc;
 pub mod analog_comparator;
+pub mod audio;
 pub mod ble_advertising;
+pub mod block_storage;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
@@ -16,6 +19,7 @@ pub mod led;
 pub mod log;
 pub mod nonvolatile_storage;
 pub mod pwm;
+pub mod qspi;
 pub mod radio;
 pub mod rng;
 pub mod sensors;
@@ -0,0 +1,103 @@
+//! Interface for quad-SPI (QSPI) controllers for external serial flash.
+//!
+//! A QSPI controller talks to a serial NOR flash over up to four data lines
+//! and knows the flash command set, so reads, writes and erases are single
+//! operations rather than sequences of SPI transfers. The controller issues
+//! any write enable commands, splits writes at flash page boundaries and
+//! waits for the flash to finish programming or erasing before it reports
+//! completion.
+//!
+//! Many controllers can also map the flash into the address space, so that it
+//! can be read, or code can be executed from it, like internal memory
+//! (execute in place, XIP). This is exposed by the separate `QspiXip` trait.
+
+use crate::returncode::ReturnCode;
+
+/// Size of the region cleared by an erase.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EraseSize {
+    /// A 4 KB sector.
+    Sector4K,
+    /// A 64 KB block.
+    Block64K,
+    /// The entire flash.
+    Chip,
+}
+
+pub trait QspiClient {
+    /// Called when a read finishes. `buffer` holds `len` bytes read from the
+    /// flash.
+    fn read_done(&self, buffer: &'static mut [u8], len: usize, rval: ReturnCode);
+
+    /// Called when a write, including programming the flash, finishes.
+    fn write_done(&self, buffer: &'static mut [u8], len: usize, rval: ReturnCode);
+
+    /// Called when an erase finishes.
+    fn erase_done(&self, rval: ReturnCode);
+
+    /// Called when a custom command finishes. `response` holds the bytes
+    /// clocked in from the flash while the command data was sent.
+    fn command_done(&self, response: &[u8], rval: ReturnCode);
+}
+
+/// Command-based access to an external flash through a QSPI controller.
+///
+/// Only one operation can be outstanding at a time; all of them return EBUSY
+/// otherwise. Controllers may require buffers to be word aligned and lengths
+/// to be a multiple of four bytes, and return EINVAL for other buffers.
+pub trait QspiMaster<'a> {
+    fn set_client(&self, client: &'a dyn QspiClient);
+
+    /// Read `len` bytes starting at flash address `address` into `buffer`.
+    ///
+    /// Returns SUCCESS, or
+    /// - EBUSY: An operation is in progress or XIP is enabled.
+    /// - EINVAL: `address`, `buffer` or `len` is not suitably aligned.
+    /// - ESIZE: `len` is longer than `buffer` or than the controller can
+    ///   transfer at once.
+    fn read(
+        &self,
+        address: u32,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Program `len` bytes from `buffer` to flash starting at `address`. The
+    /// region must have been erased first.
+    ///
+    /// Returns the same errors as `read`.
+    fn write(
+        &self,
+        address: u32,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Erase the sector or block containing flash address `address`, or the
+    /// whole flash.
+    fn erase(&self, address: u32, size: EraseSize) -> ReturnCode;
+
+    /// Send a flash instruction not covered by the other operations, such as
+    /// reading or writing a status register, using a single data line.
+    ///
+    /// `opcode` is followed by the bytes in `data`, at most 8. The response
+    /// has the same length as `data`, so pad `data` to read registers. If
+    /// `write` is set, a write enable instruction is sent first and the
+    /// controller waits until the flash is no longer busy before completing.
+    fn command(&self, opcode: u8, data: &[u8], write: bool) -> ReturnCode;
+}
+
+/// Memory-mapped (execute in place) access to external flash.
+pub trait QspiXip {
+    /// Map the flash into memory, starting at flash address `offset`, and
+    /// return the mapped window.
+    ///
+    /// The window can only be accessed until `disable_xip` is called. While
+    /// it is enabled, the `QspiMaster` operations return EBUSY.
+    ///
+    /// Returns EBUSY if an operation is in progress.
+    fn enable_xip(&self, offset: u32) -> Result<&'static [u8], ReturnCode>;
+
+    /// Stop mapping the flash into memory.
+    fn disable_xip(&self);
+}
@@ -6,6 +6,11 @@
 //! address of packets but does not change the address stored in hardware used
 //! for address recognition. This must be committed to hardware with a call to
 //! config_commit. Please see the relevant TRD for more details.
+//!
+//! Radios that are not limited to the 2.4 GHz 802.15.4 PHY, such as sub-GHz
+//! transceivers, implement `MultiPhyRadio` instead, which configures the
+//! modulation, data rate, frequency and packet format. Capsules adapt such
+//! radios to the 802.15.4 `Radio` interface.
 
 use crate::returncode::ReturnCode;
 pub trait TxClient {
@@ -111,3 +116,155 @@ pub trait RadioData {
         frame_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>);
 }
+
+/// Modulation of a multi-PHY radio.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Modulation {
+    /// Offset QPSK with DSSS, the 2.4 GHz 802.15.4 PHY
+    Oqpsk,
+    /// Binary frequency-shift keying, with the frequency deviation in Hz
+    Fsk { deviation: u32 },
+    /// Gaussian-filtered binary frequency-shift keying, with the frequency
+    /// deviation in Hz
+    Gfsk { deviation: u32 },
+}
+
+/// Framing of the packets sent over the air. In all formats, the packet is
+/// passed in buffers laid out as for 802.15.4 frames, at `PSDU_OFFSET`, and
+/// the radio adds and checks a 2-byte CRC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// 802.15.4 PPDU with a 1-byte PHR
+    Ieee802154,
+    /// 802.15.4g SUN PPDU with a 2-byte PHR
+    Ieee802154g,
+    /// A 1-byte length field followed by the packet
+    Raw,
+}
+
+/// Physical layer settings of a multi-PHY radio.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PhyConfig {
+    pub modulation: Modulation,
+    /// Data rate, in bits per second
+    pub data_rate: u32,
+    pub packet_format: PacketFormat,
+}
+
+impl PhyConfig {
+    /// The 2.4 GHz O-QPSK PHY of 802.15.4
+    pub const IEEE802154_OQPSK: PhyConfig = PhyConfig {
+        modulation: Modulation::Oqpsk,
+        data_rate: 250_000,
+        packet_format: PacketFormat::Ieee802154,
+    };
+
+    /// The 50 kb/s 2-FSK SUN PHY of 802.15.4g (operating mode #1)
+    pub const IEEE802154G_FSK_50K: PhyConfig = PhyConfig {
+        modulation: Modulation::Fsk { deviation: 25_000 },
+        data_rate: 50_000,
+        packet_format: PacketFormat::Ieee802154g,
+    };
+}
+
+/// Channel numbering of a band: channel `first_channel + n` is centered on
+/// `channel0 + n * spacing` kHz, for `n` below `channels`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelPlan {
+    pub first_channel: u8,
+    pub channels: u8,
+    /// Center frequency of the first channel, in kHz
+    pub channel0: u32,
+    /// Channel spacing, in kHz
+    pub spacing: u32,
+}
+
+impl ChannelPlan {
+    /// 802.15.4 channels 11 to 26 in the 2.4 GHz band
+    pub const IEEE802154_2450: ChannelPlan = ChannelPlan {
+        first_channel: 11,
+        channels: 16,
+        channel0: 2_405_000,
+        spacing: 5_000,
+    };
+
+    /// 802.15.4g channels of the 863-870 MHz band for operating mode #1
+    pub const IEEE802154G_863: ChannelPlan = ChannelPlan {
+        first_channel: 0,
+        channels: 34,
+        channel0: 863_125,
+        spacing: 200,
+    };
+
+    /// 802.15.4g channels of the 902-928 MHz band for operating mode #1
+    pub const IEEE802154G_902: ChannelPlan = ChannelPlan {
+        first_channel: 0,
+        channels: 129,
+        channel0: 902_200,
+        spacing: 200,
+    };
+
+    /// Returns the center frequency of `channel` in kHz, or `None` if the
+    /// band has no such channel.
+    pub fn frequency(&self, channel: u8) -> Option<u32> {
+        let n = channel.checked_sub(self.first_channel)?;
+        if n < self.channels {
+            Some(self.channel0 + n as u32 * self.spacing)
+        } else {
+            None
+        }
+    }
+}
+
+pub trait MultiPhyRadio: MultiPhyConfig + RadioData {}
+
+/// Configure a radio that supports several modulations, data rates,
+/// frequencies and packet formats, such as sub-GHz transceivers. As with
+/// `RadioConfig`, settings take effect on the next call to `config_commit`.
+/// Packets are sent and received through `RadioData`, with `frame_len`
+/// excluding the CRC. Radios implementing this interface do not filter or
+/// acknowledge packets.
+pub trait MultiPhyConfig {
+    fn start(&self) -> ReturnCode;
+    fn stop(&self) -> ReturnCode;
+    fn is_on(&self) -> bool;
+    fn busy(&self) -> bool;
+
+    fn set_power_client(&self, client: &'static dyn PowerClient);
+
+    /// Commit the config calls to hardware, issues a callback to the config
+    /// client when done.
+    fn config_commit(&self);
+    fn set_config_client(&self, client: &'static dyn ConfigClient);
+
+    /// Returns ENOSUPPORT if the radio cannot use `phy`.
+    fn set_phy(&self, phy: PhyConfig) -> ReturnCode;
+    fn get_phy(&self) -> PhyConfig;
+
+    /// Returns EINVAL if `frequency`, in kHz, is outside of the bands the
+    /// radio supports.
+    fn set_frequency(&self, frequency: u32) -> ReturnCode;
+    fn get_frequency(&self) -> u32;
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode;
+    fn get_tx_power(&self) -> i8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_frequencies() {
+        let plan = ChannelPlan::IEEE802154_2450;
+        assert_eq!(plan.frequency(10), None);
+        assert_eq!(plan.frequency(11), Some(2_405_000));
+        assert_eq!(plan.frequency(26), Some(2_480_000));
+        assert_eq!(plan.frequency(27), None);
+
+        let plan = ChannelPlan::IEEE802154G_863;
+        assert_eq!(plan.frequency(0), Some(863_125));
+        assert_eq!(plan.frequency(33), Some(869_725));
+        assert_eq!(plan.frequency(34), None);
+    }
+}
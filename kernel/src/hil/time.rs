@@ -121,6 +121,39 @@ pub trait AlarmClient {
     fn fired(&self);
 }
 
+/// The `Ticks` trait models a clock extended in software to 64 bits.
+///
+/// Hardware counters are typically 24 or 32 bits wide, so at 32 kHz they wrap
+/// every few minutes to every day and a half. A 64-bit tick count does not
+/// wrap for millions of years at any supported frequency, so `Ticks` values
+/// can be compared and subtracted directly, without reasoning about
+/// wraparound.
+///
+/// The extended count runs at the same frequency as the underlying clock, and
+/// its lowest bits match the value returned by [`Time#now`](trait.Time.html#tymethod.now).
+pub trait Ticks<W = u32>: Time<W> {
+    /// Returns the current time in clock tics, extended to 64 bits.
+    fn now_ticks(&self) -> u64;
+}
+
+/// The `TicksAlarm` trait models an alarm on a [`Ticks`](trait.Ticks.html)
+/// clock.
+///
+/// Setting the client, enabling and disabling the alarm are inherited from
+/// [`Alarm`](trait.Alarm.html); `AlarmClient#fired` is signaled when the
+/// extended clock reaches the value set in
+/// [`set_alarm_ticks`](#tymethod.set_alarm_ticks).
+pub trait TicksAlarm<'a, W = u32>: Alarm<'a, W> + Ticks<W> {
+    /// Sets a one-shot alarm to fire when the extended clock reaches `ticks`.
+    ///
+    /// If `ticks` is not after the current time, the alarm fires as soon as
+    /// possible.
+    fn set_alarm_ticks(&self, ticks: u64);
+
+    /// Returns the value set in [`set_alarm_ticks`](#tymethod.set_alarm_ticks).
+    fn get_alarm_ticks(&self) -> u64;
+}
+
 /// The `Timer` trait models a timer that can notify when a particular interval
 /// has elapsed.
 pub trait Timer<'a, W = u32>: Time<W> {
@@ -0,0 +1,150 @@
+//! Frame format of `capsules::framed_console`.
+//!
+//! ```text
+//! +------+------+----------+-------------+------+---------+------------+
+//! | 0xF5 | 0x7C | name len | payload len | name | payload | Fletcher16 |
+//! +------+------+----------+-------------+------+---------+------------+
+//! ```
+
+/// Bytes that start every frame.
+pub const START: [u8; 2] = [0xF5, 0x7C];
+
+/// Longest name the kernel accepts in a frame.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Longest payload carried in a frame.
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let mut sum1: u16 = 0;
+    let mut sum2: u16 = 0;
+    for byte in data {
+        sum1 = (sum1 + *byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    (sum2 << 8) | sum1
+}
+
+/// A frame sent by the kernel or to a process.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    /// Process name, empty for the kernel.
+    pub name: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+/// Encodes `payload` into frames addressed to `name`.
+pub fn encode(name: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for chunk in payload.chunks(MAX_PAYLOAD_LEN) {
+        let mut frame = vec![name.len() as u8, chunk.len() as u8];
+        frame.extend_from_slice(name);
+        frame.extend_from_slice(chunk);
+        let checksum = fletcher16(&frame);
+        out.extend_from_slice(&START);
+        out.extend_from_slice(&frame);
+        out.extend_from_slice(&checksum.to_le_bytes());
+    }
+    out
+}
+
+/// Reassembles frames from a stream of bytes.
+#[derive(Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Adds received bytes and returns all frames they completed. Bytes that
+    /// do not belong to a valid frame are skipped.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Frame> {
+        self.buffer.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+        loop {
+            match self.buffer.windows(2).position(|w| w == START) {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    // Keep a trailing first start byte, the second may follow.
+                    let keep = self.buffer.last() == Some(&START[0]);
+                    self.buffer.clear();
+                    if keep {
+                        self.buffer.push(START[0]);
+                    }
+                    return frames;
+                }
+            }
+            if self.buffer.len() < 4 {
+                return frames;
+            }
+            let name_len = self.buffer[2] as usize;
+            let payload_len = self.buffer[3] as usize;
+            if name_len > MAX_NAME_LEN {
+                self.buffer.drain(..2);
+                continue;
+            }
+            let end = 4 + name_len + payload_len + 2;
+            if self.buffer.len() < end {
+                return frames;
+            }
+            let checksum = u16::from_le_bytes([self.buffer[end - 2], self.buffer[end - 1]]);
+            if checksum != fletcher16(&self.buffer[2..end - 2]) {
+                // A false start, look for the next one.
+                self.buffer.drain(..2);
+                continue;
+            }
+            frames.push(Frame {
+                name: self.buffer[4..4 + name_len].to_vec(),
+                payload: self.buffer[4 + name_len..end - 2].to_vec(),
+            });
+            self.buffer.drain(..end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut decoder = Decoder::new();
+        let mut bytes = vec![0x00, START[0]];
+        bytes.extend(encode(b"blink", b"hello\n"));
+        bytes.extend(encode(b"", b"kernel"));
+        let frames = decoder.push(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].name, b"blink");
+        assert_eq!(frames[0].payload, b"hello\n");
+        assert_eq!(frames[1].name, b"");
+        assert_eq!(frames[1].payload, b"kernel");
+    }
+
+    #[test]
+    fn split_input() {
+        let mut decoder = Decoder::new();
+        let bytes = encode(b"app", &[0xAA; 300]);
+        let (first, second) = bytes.split_at(100);
+        assert!(decoder.push(first).is_empty());
+        let frames = decoder.push(second);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload.len(), 255);
+        assert_eq!(frames[1].payload.len(), 45);
+    }
+
+    #[test]
+    fn skips_corrupted_frame() {
+        let mut decoder = Decoder::new();
+        let mut bytes = encode(b"app", b"bad");
+        bytes[5] ^= 0xFF;
+        bytes.extend(encode(b"app", b"good"));
+        let frames = decoder.push(&bytes);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload, b"good");
+    }
+}
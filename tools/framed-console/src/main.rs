@@ -0,0 +1,112 @@
+//! Host side of `capsules::framed_console`.
+//!
+//! Reads frames from a serial device and prints the output of the kernel and
+//! of each process on separate lines, prefixed with the name of its source.
+//! With `--to`, lines typed on stdin are sent to the named process.
+//!
+//! The serial device has to be configured beforehand, for example with
+//! `stty -F /dev/ttyACM0 115200 raw -echo`.
+
+mod frame;
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+use std::thread;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: framed-console <device> [--to <process>]
+
+Prints the framed console output of a Tock board, one line per source.
+
+Options:
+  --to <process>  Send lines read from stdin to <process>"
+    );
+    process::exit(1);
+}
+
+/// Prints complete lines of each source, keeping partial lines until the rest
+/// arrives so that sources do not interleave within a line.
+fn print_frame(lines: &mut HashMap<Vec<u8>, Vec<u8>>, frame: frame::Frame) {
+    let pending = lines.entry(frame.name.clone()).or_insert_with(Vec::new);
+    pending.extend_from_slice(&frame.payload);
+    let source = if frame.name.is_empty() {
+        "kernel".to_string()
+    } else {
+        String::from_utf8_lossy(&frame.name).into_owned()
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    while let Some(newline) = pending.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = pending.drain(..=newline).collect();
+        let text = String::from_utf8_lossy(&line);
+        let _ = writeln!(
+            out,
+            "[{}] {}",
+            source,
+            text.trim_end_matches(&['\r', '\n'][..])
+        );
+    }
+    let _ = out.flush();
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (device, target) = match args.as_slice() {
+        [device] => (device.clone(), None),
+        [device, option, process] if option == "--to" => (device.clone(), Some(process.clone())),
+        _ => usage(),
+    };
+
+    let mut port = OpenOptions::new()
+        .read(true)
+        .write(target.is_some())
+        .open(&device)
+        .unwrap_or_else(|err| {
+            eprintln!("Cannot open {}: {}", device, err);
+            process::exit(1);
+        });
+
+    if let Some(target) = target {
+        if target.len() > frame::MAX_NAME_LEN {
+            eprintln!(
+                "Process names are truncated to {} bytes",
+                frame::MAX_NAME_LEN
+            );
+        }
+        let name = target.as_bytes()[..target.len().min(frame::MAX_NAME_LEN)].to_vec();
+        let mut writer = port.try_clone().expect("Cannot clone device handle");
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let mut line = line.expect("Cannot read stdin");
+                line.push('\n');
+                if let Err(err) = writer.write_all(&frame::encode(&name, line.as_bytes())) {
+                    eprintln!("Cannot write to device: {}", err);
+                    process::exit(1);
+                }
+            }
+        });
+    }
+
+    let mut decoder = frame::Decoder::new();
+    let mut lines = HashMap::new();
+    let mut buffer = [0; 512];
+    loop {
+        match port.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => {
+                for frame in decoder.push(&buffer[..len]) {
+                    print_frame(&mut lines, frame);
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                eprintln!("Cannot read from device: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+#!/usr/bin/env python3
+"""Install a TBF application on a node over UDP.
+
+Sends the image to the `capsules::net::app_update` capsule one request at a
+time and retransmits a request when no response arrives. The node answers
+every request with the offset it expects next, so an interrupted transfer
+resumes where it stopped when the script is run again.
+
+The node only installs images authenticated with an HMAC-SHA256 under the
+key set in its board file. The key file holds the same key as 64 hex digits.
+
+    ./ota_app_update.py --key-file update.key fe80::1%lowpan0 app.tbf
+"""
+
+import argparse
+import hashlib
+import hmac
+import socket
+import struct
+import sys
+
+START = 1
+DATA = 2
+FINISH = 3
+ABORT = 4
+RESPONSE = 0x80
+
+STATUS = ["ok", "invalid request", "no space", "authentication failed", "failed"]
+
+# Size of the chunk buffer of the imix component
+CHUNK_LENGTH = 128
+
+
+class UpdateError(Exception):
+    pass
+
+
+def request(sock, address, message, retries, timeout):
+    sock.settimeout(timeout)
+    for _ in range(retries):
+        sock.sendto(message, address)
+        try:
+            while True:
+                response, _ = sock.recvfrom(64)
+                if len(response) == 6 and response[0] == RESPONSE | message[0]:
+                    status, offset = struct.unpack("<BI", response[1:])
+                    if status != 0:
+                        reason = STATUS[status] if status < len(STATUS) else status
+                        raise UpdateError("node replied: {}".format(reason))
+                    return offset
+        except socket.timeout:
+            pass
+    raise UpdateError("no response after {} attempts".format(retries))
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("address", help="IPv6 address of the node")
+    parser.add_argument("image", help="TBF application image")
+    parser.add_argument(
+        "--key-file", required=True, help="file with the update key in hex"
+    )
+    parser.add_argument("--port", type=int, default=5000, help="update port")
+    parser.add_argument("--chunk", type=int, default=CHUNK_LENGTH)
+    parser.add_argument("--retries", type=int, default=10)
+    parser.add_argument("--timeout", type=float, default=1.0, help="seconds")
+    parser.add_argument(
+        "--abort", action="store_true", help="abort the transfer in progress"
+    )
+    args = parser.parse_args()
+
+    with open(args.image, "rb") as f:
+        image = f.read()
+    with open(args.key_file) as f:
+        key = bytes.fromhex(f.read().strip())
+    if len(key) != 32:
+        parser.error("the key must be 32 bytes")
+
+    info = socket.getaddrinfo(args.address, args.port, socket.AF_INET6, socket.SOCK_DGRAM)
+    address = info[0][4]
+    sock = socket.socket(socket.AF_INET6, socket.SOCK_DGRAM)
+
+    try:
+        if args.abort:
+            request(sock, address, bytes([ABORT]), args.retries, args.timeout)
+            return
+
+        start = struct.pack("<BI", START, len(image)) + hmac.new(key, image, hashlib.sha256).digest()
+        offset = request(sock, address, start, args.retries, args.timeout)
+        while offset < len(image):
+            chunk = image[offset : offset + args.chunk]
+            message = struct.pack("<BI", DATA, offset) + chunk
+            offset = request(sock, address, message, args.retries, args.timeout)
+            print("\r{}/{} bytes".format(offset, len(image)), end="", flush=True)
+        print()
+        request(sock, address, bytes([FINISH]), args.retries, args.timeout)
+        print("Installed, the application starts after the next reset.")
+    except UpdateError as e:
+        print("\nerror: {}".format(e), file=sys.stderr)
+        sys.exit(1)
+
+
+if __name__ == "__main__":
+    main()
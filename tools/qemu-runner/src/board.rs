@@ -0,0 +1,154 @@
+//! Boards that scenarios can run on, and how to start and control them.
+
+use rexpect::errors::Error;
+use rexpect::session::{spawn_command, PtySession};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+enum Emulator {
+    /// QEMU, started with the `qemu` and `qemu-app` targets of the board
+    /// Makefile. Its monitor is multiplexed on the console.
+    Qemu,
+    /// The host board, which runs the kernel as a Linux process. It cannot
+    /// load apps.
+    Host,
+}
+
+pub struct Board {
+    pub name: &'static str,
+    /// Directory of the board, relative to `boards/`
+    dir: &'static str,
+    /// Variables for the board Makefile, with paths relative to the root of
+    /// the repository
+    make_vars: &'static [(&'static str, &'static str)],
+    emulator: Emulator,
+}
+
+/// Every board that can be emulated.
+pub const BOARDS: &[Board] = &[
+    Board {
+        name: "hifive1",
+        dir: "hifive1",
+        make_vars: &[],
+        emulator: Emulator::Qemu,
+    },
+    Board {
+        name: "opentitan",
+        dir: "opentitan",
+        make_vars: &[("OPENTITAN_BOOT_ROM", "opentitan-boot-rom.elf")],
+        emulator: Emulator::Qemu,
+    },
+    Board {
+        name: "host",
+        dir: "host",
+        make_vars: &[],
+        emulator: Emulator::Host,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Board> {
+    BOARDS.iter().find(|board| board.name == name)
+}
+
+/// Root of the Tock repository.
+pub fn tock_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+impl Board {
+    fn make(&self) -> Command {
+        let root = tock_root();
+        let mut make = Command::new("make");
+        make.arg("-C").arg(root.join("boards").join(self.dir));
+        for (var, path) in self.make_vars {
+            make.arg(format!("{}={}", var, root.join(path).display()));
+        }
+        make
+    }
+
+    pub fn can_load_apps(&self) -> bool {
+        match self.emulator {
+            Emulator::Qemu => true,
+            Emulator::Host => false,
+        }
+    }
+
+    /// Builds the kernel, so that compiling does not count towards the
+    /// timeouts of the scenario.
+    pub fn build(&self) -> Result<(), String> {
+        let output = self
+            .make()
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("cannot run make: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "building {} failed:\n{}",
+                self.name,
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    }
+
+    /// Starts the board, loading the TBF image `apps` if given.
+    pub fn start(&self, apps: Option<&Path>, timeout_ms: u64) -> Result<PtySession, Error> {
+        let mut make = self.make();
+        match (&self.emulator, apps) {
+            (Emulator::Qemu, Some(apps)) => {
+                make.arg("qemu-app").arg(format!("APP={}", apps.display()));
+            }
+            (Emulator::Qemu, None) => {
+                make.arg("qemu");
+            }
+            (Emulator::Host, _) => {
+                make.arg("run");
+            }
+        }
+        spawn_command(make, Some(timeout_ms))
+    }
+
+    /// Stops the board and waits for it to exit.
+    pub fn stop(&self, session: &mut PtySession) -> Result<(), Error> {
+        match self.emulator {
+            Emulator::Qemu => {
+                session.send_control('a')?;
+                session.send("x")?;
+                session.flush()?;
+            }
+            Emulator::Host => session.send_control('c')?,
+        }
+        session.exp_eof()?;
+        Ok(())
+    }
+
+    /// Resets the board. QEMU resets the machine from its monitor, and the
+    /// host board is restarted, which keeps the contents of its flash file.
+    pub fn reset(
+        &self,
+        session: &mut PtySession,
+        apps: Option<&Path>,
+        timeout_ms: u64,
+    ) -> Result<(), Error> {
+        match self.emulator {
+            Emulator::Qemu => {
+                // Ctrl-A c switches between the console and the monitor
+                session.send_control('a')?;
+                session.send("c")?;
+                session.flush()?;
+                session.exp_string("(qemu)")?;
+                session.send_line("system_reset")?;
+                session.exp_string("(qemu)")?;
+                session.send_control('a')?;
+                session.send("c")?;
+                session.flush()?;
+            }
+            Emulator::Host => {
+                self.stop(session)?;
+                *session = self.start(apps, timeout_ms)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Test results in the JUnit XML format understood by CI services.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+pub struct TestCase {
+    pub name: String,
+    pub time: Duration,
+    pub outcome: Outcome,
+    /// Console output of the board
+    pub output: String,
+}
+
+pub struct TestSuite {
+    pub name: String,
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    pub fn failures(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Skipped(_)))
+    }
+
+    fn count<F: Fn(&Outcome) -> bool>(&self, f: F) -> usize {
+        self.cases.iter().filter(|case| f(&case.outcome)).count()
+    }
+
+    fn time(&self) -> Duration {
+        self.cases.iter().map(|case| case.time).sum()
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Characters that XML 1.0 does not allow at all
+            c if c < ' ' && c != '\t' && c != '\n' && c != '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the suites as a JUnit XML document.
+pub fn render(suites: &[TestSuite]) -> String {
+    let mut xml = String::new();
+    let tests: usize = suites.iter().map(|suite| suite.cases.len()).sum();
+    let failures: usize = suites.iter().map(TestSuite::failures).sum();
+    let skipped: usize = suites.iter().map(TestSuite::skipped).sum();
+    // Writing to a String cannot fail
+    let _ = writeln!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\">",
+        tests, failures, skipped
+    );
+    for suite in suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape(&suite.name),
+            suite.cases.len(),
+            suite.failures(),
+            suite.skipped(),
+            suite.time().as_secs_f64()
+        );
+        for case in &suite.cases {
+            let _ = writeln!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">",
+                escape(&suite.name),
+                escape(&case.name),
+                case.time.as_secs_f64()
+            );
+            match &case.outcome {
+                Outcome::Passed => {}
+                Outcome::Failed(message) => {
+                    let _ = writeln!(xml, "      <failure message=\"{}\"/>", escape(message));
+                }
+                Outcome::Skipped(message) => {
+                    let _ = writeln!(xml, "      <skipped message=\"{}\"/>", escape(message));
+                }
+            }
+            if !case.output.is_empty() {
+                let _ = writeln!(
+                    xml,
+                    "      <system-out>{}</system-out>",
+                    escape(&case.output)
+                );
+            }
+            let _ = writeln!(xml, "    </testcase>");
+        }
+        let _ = writeln!(xml, "  </testsuite>");
+    }
+    let _ = writeln!(xml, "</testsuites>");
+    xml
+}
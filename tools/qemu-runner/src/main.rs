@@ -1,45 +1,130 @@
-use rexpect::errors::Error;
-use rexpect::session::PtySession;
-use rexpect::spawn;
+//! Runs integration test scenarios on emulated boards.
+//!
+//! Each scenario in `scenarios/` boots a board, optionally with apps, and
+//! drives its console: it waits for output, types commands to the process
+//! console and injects faults. See `scenario.rs` for the format. Results are
+//! printed, and written in the JUnit XML format with `--junit`.
+//!
+//! Boards are built before their scenarios run. The QEMU boards need QEMU
+//! from `make emulation-setup`, and the host board runs as a Linux process.
+//! Apps are TBF files, laid out one after the other after the kernel, so
+//! each one must already be padded to the alignment the board requires.
+//! Scenarios that only need an app that prints and can be faulted use the
+//! test app of `tbf.rs`, which needs no userspace toolchain.
+//!
+//! The console does not say which app printed what, so the text of an
+//! `expect-app` step should only be printed by that app. Each app checked by
+//! a scenario gets its own test case in the results.
 
-fn kill_qemu(p: &mut PtySession) -> Result<(), Error> {
-    p.send_control('a')?;
-    p.send("x")?;
-    p.flush()?;
+mod board;
+mod junit;
+mod runner;
+mod scenario;
+mod tbf;
 
-    Ok(())
-}
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: qemu-runner [--board <name>] [--junit <file>] [<scenario>...]
 
-fn hifive1() -> Result<(), Error> {
-    let mut p = spawn("make qemu -C ../../boards/hifive1", Some(3_000))?;
+Runs the given scenarios, or every scenario in scenarios/.
 
-    p.exp_string("HiFive1 initialization complete.")?;
-    p.exp_string("Entering main loop.")?;
+Options:
+  --board <name>  Only run the scenarios for this board
+  --junit <file>  Write the results to <file> in the JUnit XML format
 
-    // Test completed, kill QEMU
-    kill_qemu(&mut p)?;
+Boards: {}",
+        board::BOARDS
+            .iter()
+            .map(|board| board.name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    process::exit(2);
+}
 
-    p.exp_eof()?;
-    Ok(())
+fn all_scenarios() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot list {}: {}", dir.display(), e))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "scenario"))
+        .collect();
+    paths.sort();
+    paths
 }
 
-fn opentitan() -> Result<(), Error> {
-    let mut p = spawn(
-        "make OPENTITAN_BOOT_ROM=../../opentitan-boot-rom.elf qemu -C ../../boards/opentitan",
-        Some(10_000),
-    )?;
+fn main() {
+    let mut only_board = None;
+    let mut junit_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--board" => only_board = Some(args.next().unwrap_or_else(|| usage())),
+            "--junit" => junit_path = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') => usage(),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths = all_scenarios();
+    }
 
-    p.exp_string("Boot ROM initialisation has completed, jump into flash!")?;
-    p.exp_string("Entering main loop")?;
+    let mut scenarios = Vec::new();
+    for path in &paths {
+        match scenario::Scenario::load(path) {
+            Ok(scenario) => scenarios.push(scenario),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    if let Some(ref name) = only_board {
+        if board::find(name).is_none() {
+            usage();
+        }
+        scenarios.retain(|scenario| &scenario.board == name);
+    }
 
-    // Test completed, kill QEMU
-    kill_qemu(&mut p)?;
+    // One suite per board, in the order of the scenarios
+    let mut suites: Vec<junit::TestSuite> = Vec::new();
+    for scenario in &scenarios {
+        println!("Running {} on {}", scenario.name, scenario.board);
+        let cases = runner::run(scenario);
+        for case in &cases {
+            match case.outcome {
+                junit::Outcome::Passed => println!("  PASS {}", case.name),
+                junit::Outcome::Failed(ref message) => {
+                    println!("  FAIL {}: {}", case.name, message)
+                }
+                junit::Outcome::Skipped(ref message) => {
+                    println!("  SKIP {}: {}", case.name, message)
+                }
+            }
+        }
+        match suites.iter_mut().find(|suite| suite.name == scenario.board) {
+            Some(suite) => suite.cases.extend(cases),
+            None => suites.push(junit::TestSuite {
+                name: scenario.board.clone(),
+                cases: cases,
+            }),
+        }
+    }
 
-    p.exp_eof()?;
-    Ok(())
-}
+    if let Some(path) = junit_path {
+        fs::write(&path, junit::render(&suites))
+            .unwrap_or_else(|e| panic!("cannot write {}: {}", path, e));
+    }
 
-fn main() {
-    hifive1().unwrap_or_else(|e| panic!("hifive1 job failed with {}", e));
-    opentitan().unwrap_or_else(|e| panic!("opentitan job failed with {}", e));
+    let failures: usize = suites.iter().map(junit::TestSuite::failures).sum();
+    let tests: usize = suites.iter().map(|suite| suite.cases.len()).sum();
+    println!("{} tests, {} failed", tests, failures);
+    if failures > 0 {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,194 @@
+//! Runs a scenario on its board and turns what happened into test cases.
+
+use crate::board::{self, Board};
+use crate::junit::{Outcome, TestCase};
+use crate::scenario::{App, Fault, Scenario, Step};
+use crate::tbf;
+use rexpect::errors::Error;
+use rexpect::session::PtySession;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+/// Why a scenario failed.
+enum Failure {
+    /// The board could not be built or started
+    NotStarted(String),
+    /// The step with this index failed
+    Step(usize, String),
+    /// All the steps passed but the board did not stop
+    Stop(String),
+}
+
+impl Failure {
+    fn message(&self) -> &str {
+        match self {
+            Failure::NotStarted(message) | Failure::Step(_, message) | Failure::Stop(message) => {
+                message
+            }
+        }
+    }
+}
+
+/// How far a scenario got.
+struct Run {
+    failure: Option<Failure>,
+    output: String,
+}
+
+impl Run {
+    fn not_started(message: String) -> Run {
+        Run {
+            failure: Some(Failure::NotStarted(message)),
+            output: String::new(),
+        }
+    }
+}
+
+// Lays the apps out one after the other, as tockloader does, in a single
+// image to load after the kernel
+fn write_apps(scenario: &Scenario) -> Result<Option<PathBuf>, String> {
+    if scenario.apps.is_empty() {
+        return Ok(None);
+    }
+    let mut image = Vec::new();
+    for app in &scenario.apps {
+        let tbf = match app {
+            App::Tbf(path) => fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            App::Test { name, text } => tbf::test_app(name, text)
+                .ok_or_else(|| format!("cannot build the test app {}", name))?,
+        };
+        image.extend_from_slice(&tbf);
+    }
+    let path = env::temp_dir().join(format!(
+        "qemu-runner-{}-{}.tbf",
+        scenario.name,
+        process::id()
+    ));
+    fs::write(&path, image).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Some(path))
+}
+
+fn run_step(
+    board: &Board,
+    session: &mut PtySession,
+    step: &Step,
+    apps: Option<&Path>,
+    timeout_ms: u64,
+    output: &mut String,
+) -> Result<(), Error> {
+    match step {
+        Step::Expect(text) | Step::ExpectApp { text, .. } => {
+            output.push_str(&session.exp_string(text)?);
+            output.push_str(text);
+        }
+        Step::ExpectRegex(regex) => {
+            let (before, matched) = session.exp_regex(regex)?;
+            output.push_str(&before);
+            output.push_str(&matched);
+        }
+        Step::Send(line) => {
+            session.send_line(line)?;
+        }
+        Step::Inject(Fault::App(app)) => {
+            session.send_line(&format!("fault {}", app))?;
+        }
+        Step::Inject(Fault::Reset) => board.reset(session, apps, timeout_ms)?,
+    }
+    Ok(())
+}
+
+fn run_steps(board: &Board, scenario: &Scenario, apps: Option<&Path>) -> Run {
+    let mut output = String::new();
+    let mut session = match board.start(apps, scenario.timeout_ms) {
+        Ok(session) => session,
+        Err(e) => return Run::not_started(format!("cannot start {}: {}", board.name, e)),
+    };
+
+    for (index, step) in scenario.steps.iter().enumerate() {
+        if let Err(e) = run_step(
+            board,
+            &mut session,
+            step,
+            apps,
+            scenario.timeout_ms,
+            &mut output,
+        ) {
+            // Dropping the session kills the board if it did not stop
+            let _ = board.stop(&mut session);
+            return Run {
+                failure: Some(Failure::Step(index, format!("`{}` failed: {}", step, e))),
+                output: output,
+            };
+        }
+    }
+
+    let failure = board
+        .stop(&mut session)
+        .err()
+        .map(|e| Failure::Stop(format!("{} did not stop: {}", board.name, e)));
+    Run {
+        failure: failure,
+        output: output,
+    }
+}
+
+/// Runs the scenario. The first test case is the scenario as a whole, and
+/// it is followed by a test case for each app the scenario checks the
+/// output of.
+pub fn run(scenario: &Scenario) -> Vec<TestCase> {
+    let started = Instant::now();
+    let run = match board::find(&scenario.board) {
+        None => Run::not_started(format!("unknown board `{}`", scenario.board)),
+        Some(board) if !scenario.apps.is_empty() && !board.can_load_apps() => {
+            Run::not_started(format!("{} cannot load apps", board.name))
+        }
+        Some(board) => match board.build().and_then(|()| write_apps(scenario)) {
+            Err(e) => Run::not_started(e),
+            Ok(apps) => {
+                let run = run_steps(board, scenario, apps.as_deref());
+                if let Some(apps) = apps {
+                    let _ = fs::remove_file(apps);
+                }
+                run
+            }
+        },
+    };
+
+    let mut cases = vec![TestCase {
+        name: scenario.name.clone(),
+        time: started.elapsed(),
+        outcome: match run.failure {
+            Some(ref failure) => Outcome::Failed(failure.message().to_string()),
+            None => Outcome::Passed,
+        },
+        output: run.output,
+    }];
+
+    for app in scenario.checked_apps() {
+        let steps: Vec<usize> = (0..scenario.steps.len())
+            .filter(|&index| scenario.steps[index].app() == Some(app))
+            .collect();
+        let outcome = match run.failure {
+            Some(Failure::NotStarted(_)) => {
+                Outcome::Skipped("the scenario did not run".to_string())
+            }
+            Some(Failure::Step(failed, ref message)) if steps.contains(&failed) => {
+                Outcome::Failed(message.clone())
+            }
+            Some(Failure::Step(failed, _)) if steps.iter().any(|&index| index > failed) => {
+                Outcome::Skipped("an earlier step of the scenario failed".to_string())
+            }
+            _ => Outcome::Passed,
+        };
+        cases.push(TestCase {
+            name: format!("{}/{}", scenario.name, app),
+            time: Duration::default(),
+            outcome: outcome,
+            output: String::new(),
+        });
+    }
+    cases
+}
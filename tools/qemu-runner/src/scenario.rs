@@ -0,0 +1,270 @@
+//! Scenario files.
+//!
+//! A scenario is a text file with one step per line, made of a keyword and
+//! its argument. Blank lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # Faults an app and checks that the kernel notices
+//! board hifive1
+//! timeout 3000
+//! app apps/blink.tbf
+//! expect Entering main loop.
+//! expect-app blink Blink started
+//! inject fault blink
+//! expect Process blink had a fault
+//! ```
+//!
+//! The first lines say where the scenario runs:
+//!
+//! - `board <name>`: board to run on, required.
+//! - `timeout <ms>`: how long to wait for each expectation, 3 seconds by
+//!   default.
+//! - `app <path>`: TBF to load with the kernel. May be repeated.
+//! - `test-app <name> <text>`: loads the test app of `tbf.rs`, with the
+//!   package name `<name>`, which prints `<text>` and then waits until it is
+//!   faulted. May be repeated.
+//!
+//! and the other lines are carried out in order:
+//!
+//! - `expect <text>`: waits for the text on the console.
+//! - `expect-regex <regex>`: waits for text matching the regular expression.
+//! - `expect-app <app> <text>`: waits for the text, which the app `<app>`
+//!   prints.
+//! - `send <line>`: types the line on the console, for the process console.
+//! - `inject fault <app>`: faults the app with the `fault` command of the
+//!   process console.
+//! - `inject reset`: resets the board.
+//!
+//! App paths are relative to the scenario file.
+
+use crate::tbf;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Expectations time out after this many milliseconds unless the scenario
+/// sets another timeout.
+const DEFAULT_TIMEOUT_MS: u64 = 3_000;
+
+/// An app to load with the kernel.
+#[derive(Debug, PartialEq)]
+pub enum App {
+    /// A TBF file
+    Tbf(PathBuf),
+    /// The test app built by the runner
+    Test { name: String, text: String },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    /// Faults the process with this name through the process console.
+    App(String),
+    /// Resets the board, as if its reset line was pulled.
+    Reset,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Expect(String),
+    ExpectRegex(String),
+    ExpectApp { app: String, text: String },
+    Send(String),
+    Inject(Fault),
+}
+
+impl Step {
+    /// The app an expectation is about, if any.
+    pub fn app(&self) -> Option<&str> {
+        match self {
+            Step::ExpectApp { app, .. } => Some(app),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Expect(text) => write!(f, "expect {:?}", text),
+            Step::ExpectRegex(regex) => write!(f, "expect-regex {:?}", regex),
+            Step::ExpectApp { app, text } => write!(f, "expect-app {} {:?}", app, text),
+            Step::Send(line) => write!(f, "send {:?}", line),
+            Step::Inject(Fault::App(app)) => write!(f, "inject fault {}", app),
+            Step::Inject(Fault::Reset) => write!(f, "inject reset"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Scenario {
+    /// File name without the extension.
+    pub name: String,
+    pub board: String,
+    pub timeout_ms: u64,
+    pub apps: Vec<App>,
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Scenario, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        Scenario::parse(&name, dir, &text).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Parses the scenario `text`, with app paths relative to `dir`. Errors
+    /// start with the line number.
+    pub fn parse(name: &str, dir: &Path, text: &str) -> Result<Scenario, String> {
+        let mut board = None;
+        let mut timeout_ms = DEFAULT_TIMEOUT_MS;
+        let mut apps = Vec::new();
+        let mut steps = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, argument) = match line.find(char::is_whitespace) {
+                Some(end) => (&line[..end], line[end..].trim()),
+                None => (line, ""),
+            };
+            let error = |message: &str| format!("{}: {}", index + 1, message);
+            if argument.is_empty() {
+                return Err(error(&format!("`{}` needs an argument", keyword)));
+            }
+            match keyword {
+                "board" => board = Some(argument.to_string()),
+                "timeout" => {
+                    timeout_ms = argument
+                        .parse()
+                        .map_err(|_| error("the timeout is not a number of milliseconds"))?
+                }
+                "app" => apps.push(App::Tbf(dir.join(argument))),
+                "test-app" => {
+                    let mut words = argument.splitn(2, char::is_whitespace);
+                    let name = words.next().unwrap_or_default();
+                    let text = words.next().map(str::trim).unwrap_or_default();
+                    if text.is_empty() || text.len() > tbf::MAX_TEXT_LEN {
+                        return Err(error(&format!(
+                            "`test-app` needs a name and a text of at most {} bytes",
+                            tbf::MAX_TEXT_LEN
+                        )));
+                    }
+                    apps.push(App::Test {
+                        name: name.to_string(),
+                        text: text.to_string(),
+                    });
+                }
+                "expect" => steps.push(Step::Expect(argument.to_string())),
+                "expect-regex" => steps.push(Step::ExpectRegex(argument.to_string())),
+                "expect-app" => {
+                    let mut words = argument.splitn(2, char::is_whitespace);
+                    let app = words.next().unwrap_or_default();
+                    let text = words.next().map(str::trim).unwrap_or_default();
+                    if text.is_empty() {
+                        return Err(error("`expect-app` needs an app and a text"));
+                    }
+                    steps.push(Step::ExpectApp {
+                        app: app.to_string(),
+                        text: text.to_string(),
+                    });
+                }
+                "send" => steps.push(Step::Send(argument.to_string())),
+                "inject" => {
+                    let mut words = argument.split_whitespace();
+                    let fault = match (words.next(), words.next(), words.next()) {
+                        (Some("reset"), None, _) => Fault::Reset,
+                        (Some("fault"), Some(app), None) => Fault::App(app.to_string()),
+                        _ => return Err(error("expected `inject reset` or `inject fault <app>`")),
+                    };
+                    steps.push(Step::Inject(fault));
+                }
+                _ => return Err(error(&format!("unknown step `{}`", keyword))),
+            }
+        }
+
+        Ok(Scenario {
+            name: name.to_string(),
+            board: board.ok_or_else(|| "the scenario does not name a board".to_string())?,
+            timeout_ms: timeout_ms,
+            apps: apps,
+            steps: steps,
+        })
+    }
+
+    /// Names of the apps that the scenario has expectations about, in the
+    /// order they first appear.
+    pub fn checked_apps(&self) -> Vec<&str> {
+        let mut apps: Vec<&str> = Vec::new();
+        for app in self.steps.iter().filter_map(Step::app) {
+            if !apps.contains(&app) {
+                apps.push(app);
+            }
+        }
+        apps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let text = "
+            # Comment
+            board hifive1
+            timeout 500
+            app apps/blink.tbf
+            test-app hello Hello from hello
+            expect Entering main loop.
+            expect-app blink  Blink started
+            send list
+            inject fault blink
+            inject reset
+        ";
+        let scenario = Scenario::parse("blink", Path::new("scenarios"), text).unwrap();
+        assert_eq!(scenario.board, "hifive1");
+        assert_eq!(scenario.timeout_ms, 500);
+        assert_eq!(
+            scenario.apps,
+            vec![
+                App::Tbf(PathBuf::from("scenarios/apps/blink.tbf")),
+                App::Test {
+                    name: "hello".to_string(),
+                    text: "Hello from hello".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            scenario.steps,
+            vec![
+                Step::Expect("Entering main loop.".to_string()),
+                Step::ExpectApp {
+                    app: "blink".to_string(),
+                    text: "Blink started".to_string()
+                },
+                Step::Send("list".to_string()),
+                Step::Inject(Fault::App("blink".to_string())),
+                Step::Inject(Fault::Reset),
+            ]
+        );
+        assert_eq!(scenario.checked_apps(), vec!["blink"]);
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        let text = "board hifive1\n\nexpect\n";
+        let error = Scenario::parse("bad", Path::new("."), text).unwrap_err();
+        assert!(error.starts_with("3: "));
+    }
+
+    #[test]
+    fn requires_board() {
+        assert!(Scenario::parse("bad", Path::new("."), "expect boot").is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Test apps built by the runner.
+//!
+//! Scenarios can load apps without a userspace toolchain: `test-app <name>
+//! <text>` loads an app called `<name>` that prints `<text>` once on the
+//! console and then yields forever, until the scenario faults it. The app is
+//! a handful of position independent RV32I instructions, so it runs on every
+//! RISC-V board wherever it is loaded.
+
+/// The app is padded to this size, a power of two as the MPUs require.
+const APP_SIZE: usize = 512;
+
+/// RAM the app asks for. It copies the text to the start of its RAM.
+const MINIMUM_RAM_SIZE: u32 = 1024;
+
+/// Longest text the app can print, so that its length fits the immediate of
+/// an `addi` and the app fits in `APP_SIZE`.
+pub const MAX_TEXT_LEN: usize = 256;
+
+const CONSOLE_DRIVER: i32 = 1;
+
+// Registers
+const ZERO: u32 = 0;
+const T0: u32 = 5;
+const T1: u32 = 6;
+const T2: u32 = 7;
+const S0: u32 = 8;
+const A0: u32 = 10;
+const A1: u32 = 11;
+const A2: u32 = 12;
+const A3: u32 = 13;
+const A4: u32 = 14;
+const T3: u32 = 28;
+
+fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0x13, 0, rd, rs1, imm)
+}
+
+fn lbu(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0x03, 4, rd, rs1, imm)
+}
+
+fn sb(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5 & 0x7f) << 25) | (rs2 << 20) | (rs1 << 15) | ((imm & 0x1f) << 7) | 0x23
+}
+
+fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 12 & 1) << 31)
+        | ((imm >> 5 & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (1 << 12)
+        | ((imm >> 1 & 0xf) << 8)
+        | ((imm >> 11 & 1) << 7)
+        | 0x63
+}
+
+fn jal(rd: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 20 & 1) << 31)
+        | ((imm >> 1 & 0x3ff) << 21)
+        | ((imm >> 11 & 1) << 20)
+        | ((imm >> 12 & 0xff) << 12)
+        | (rd << 7)
+        | 0x6f
+}
+
+fn auipc(rd: u32, imm: u32) -> u32 {
+    (imm << 12) | (rd << 7) | 0x17
+}
+
+const ECALL: u32 = 0x0000_0073;
+
+/// Code of the app, followed by the text. The kernel starts it with the
+/// start of its RAM in `a1`.
+fn code(text_len: usize) -> Vec<u32> {
+    let len = text_len as i32;
+    let mut code = vec![
+        addi(S0, A1, 0), // s0 = RAM
+        auipc(T0, 0),    // t0 = text, placed after the code
+        0,
+        addi(T1, ZERO, len),
+        addi(T2, S0, 0),
+        // Copy the text to RAM, which is the only memory it can allow.
+        lbu(T3, T0, 0),
+        sb(T3, T2, 0),
+        addi(T0, T0, 1),
+        addi(T2, T2, 1),
+        addi(T1, T1, -1),
+        bne(T1, ZERO, -20),
+        // allow(console, 1, s0, len)
+        addi(A0, ZERO, 3),
+        addi(A1, ZERO, CONSOLE_DRIVER),
+        addi(A2, ZERO, 1),
+        addi(A3, S0, 0),
+        addi(A4, ZERO, len),
+        ECALL,
+        // command(console, 1, len, 0), which prints the text
+        addi(A0, ZERO, 2),
+        addi(A1, ZERO, CONSOLE_DRIVER),
+        addi(A2, ZERO, 1),
+        addi(A3, ZERO, len),
+        addi(A4, ZERO, 0),
+        ECALL,
+        // yield, forever
+        addi(A0, ZERO, 0),
+        ECALL,
+        jal(ZERO, -8),
+    ];
+    // The text follows the code, counted from the `auipc`.
+    code[2] = addi(T0, T0, (code.len() as i32 - 1) * 4);
+    code
+}
+
+/// TBF of the test app `name` printing `text`, or `None` if the text is too
+/// long.
+pub fn test_app(name: &str, text: &str) -> Option<Vec<u8>> {
+    if text.is_empty() || text.len() > MAX_TEXT_LEN {
+        return None;
+    }
+
+    // Base header, then the main and package name TLVs
+    let name_len = name.len();
+    let header_size = 16 + 4 + 12 + 4 + (name_len + 3) / 4 * 4;
+    let mut tbf = Vec::new();
+    tbf.extend_from_slice(&2u16.to_le_bytes());
+    tbf.extend_from_slice(&(header_size as u16).to_le_bytes());
+    tbf.extend_from_slice(&(APP_SIZE as u32).to_le_bytes());
+    // Enabled
+    tbf.extend_from_slice(&1u32.to_le_bytes());
+    // Checksum, filled in below
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+
+    tbf.extend_from_slice(&1u16.to_le_bytes());
+    tbf.extend_from_slice(&12u16.to_le_bytes());
+    // The code starts right after the header.
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&MINIMUM_RAM_SIZE.to_le_bytes());
+
+    tbf.extend_from_slice(&3u16.to_le_bytes());
+    tbf.extend_from_slice(&(name_len as u16).to_le_bytes());
+    tbf.extend_from_slice(name.as_bytes());
+    tbf.resize(header_size, 0);
+
+    let checksum = tbf
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .fold(0, |checksum, word| checksum ^ word);
+    tbf[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    for instruction in code(text.len()) {
+        tbf.extend_from_slice(&instruction.to_le_bytes());
+    }
+    tbf.extend_from_slice(text.as_bytes());
+    if tbf.len() > APP_SIZE {
+        return None;
+    }
+    tbf.resize(APP_SIZE, 0);
+    Some(tbf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_instructions() {
+        // As assembled by `llvm-mc -triple=riscv32`
+        assert_eq!(addi(S0, A1, 0), 0x0005_8413);
+        assert_eq!(auipc(T0, 0), 0x0000_0297);
+        assert_eq!(addi(T1, ZERO, -1), 0xfff0_0313);
+        assert_eq!(lbu(T3, T0, 0), 0x0002_ce03);
+        assert_eq!(sb(T3, T2, 0), 0x01c3_8023);
+        assert_eq!(bne(T1, ZERO, -20), 0xfe03_16e3);
+        assert_eq!(jal(ZERO, -8), 0xff9f_f06f);
+    }
+
+    #[test]
+    fn builds_tbf() {
+        let tbf = test_app("hello", "Hello from hello\n").unwrap();
+        assert_eq!(tbf.len(), APP_SIZE);
+        let header_size = u16::from_le_bytes([tbf[2], tbf[3]]) as usize;
+        assert_eq!(header_size, 44);
+        assert_eq!(&tbf[36..41], b"hello");
+
+        // The checksum makes the XOR of all header words, but the checksum,
+        // match it.
+        let words: Vec<u32> = tbf[..header_size]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let xor = words
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 3)
+            .fold(0, |checksum, (_, word)| checksum ^ word);
+        assert_eq!(xor, words[3]);
+
+        // The text follows the code, where the `auipc` plus its offset point.
+        let code_len = code(17).len() * 4;
+        assert_eq!(
+            &tbf[header_size + code_len..header_size + code_len + 17],
+            b"Hello from hello\n"
+        );
+        let offset = u32::from_le_bytes([
+            tbf[header_size + 8],
+            tbf[header_size + 9],
+            tbf[header_size + 10],
+            tbf[header_size + 11],
+        ]) >> 20;
+        assert_eq!(offset as usize, code_len - 4);
+    }
+
+    #[test]
+    fn rejects_long_text() {
+        assert!(test_app("hello", &"x".repeat(MAX_TEXT_LEN + 1)).is_none());
+        assert!(test_app("hello", "").is_none());
+    }
+}
@@ -0,0 +1,148 @@
+#!/usr/bin/env python3
+"""Connect a 6LoWPAN border router on a serial port to a tun interface.
+
+Packets received from the node as SLIP frames are written to the tun
+interface, and packets the host routes to the interface are sent to the node.
+Creating the interface needs CAP_NET_ADMIN. Once it is up, route the mesh
+prefix over it:
+
+    sudo ./tunslip6.py /dev/ttyUSB0 --address fd00::ff/64
+    sudo ip -6 route add fd00:0:0:1::/64 dev tun0
+"""
+
+import argparse
+import fcntl
+import os
+import select
+import struct
+import subprocess
+import sys
+import termios
+
+END = 0xC0
+ESC = 0xDB
+ESC_END = 0xDC
+ESC_ESC = 0xDD
+
+TUNSETIFF = 0x400454CA
+IFF_TUN = 0x0001
+IFF_NO_PI = 0x1000
+
+BAUD_RATES = {
+    9600: termios.B9600,
+    19200: termios.B19200,
+    38400: termios.B38400,
+    57600: termios.B57600,
+    115200: termios.B115200,
+    230400: termios.B230400,
+}
+
+
+def slip_encode(packet):
+    frame = bytearray([END])
+    for byte in packet:
+        if byte == END:
+            frame += bytes([ESC, ESC_END])
+        elif byte == ESC:
+            frame += bytes([ESC, ESC_ESC])
+        else:
+            frame.append(byte)
+    frame.append(END)
+    return bytes(frame)
+
+
+class SlipDecoder:
+    def __init__(self):
+        self.packet = bytearray()
+        self.escaped = False
+
+    def receive(self, data):
+        """Returns the packets completed by `data`."""
+        packets = []
+        for byte in data:
+            if byte == END:
+                if self.packet:
+                    packets.append(bytes(self.packet))
+                self.packet = bytearray()
+                self.escaped = False
+            elif byte == ESC:
+                self.escaped = True
+            else:
+                if self.escaped:
+                    byte = {ESC_END: END, ESC_ESC: ESC}.get(byte, byte)
+                    self.escaped = False
+                self.packet.append(byte)
+        return packets
+
+
+def open_serial(path, baud_rate):
+    fd = os.open(path, os.O_RDWR | os.O_NOCTTY)
+    attrs = termios.tcgetattr(fd)
+    # Raw 8N1 without flow control
+    attrs[0] = 0
+    attrs[1] = 0
+    attrs[2] = termios.CS8 | termios.CREAD | termios.CLOCAL
+    attrs[3] = 0
+    attrs[4] = attrs[5] = BAUD_RATES[baud_rate]
+    attrs[6][termios.VMIN] = 1
+    attrs[6][termios.VTIME] = 0
+    termios.tcsetattr(fd, termios.TCSANOW, attrs)
+    termios.tcflush(fd, termios.TCIOFLUSH)
+    return fd
+
+
+def open_tun(name):
+    fd = os.open("/dev/net/tun", os.O_RDWR)
+    ifr = struct.pack("16sH", name.encode(), IFF_TUN | IFF_NO_PI)
+    ifr = fcntl.ioctl(fd, TUNSETIFF, ifr)
+    return fd, ifr[:16].rstrip(b"\0").decode()
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("serial", help="serial port of the border router")
+    parser.add_argument(
+        "--baud", type=int, default=115200, choices=sorted(BAUD_RATES)
+    )
+    parser.add_argument("--interface", default="tun0", help="tun interface name")
+    parser.add_argument(
+        "--address", help="IPv6 address/prefix length to assign to the interface"
+    )
+    parser.add_argument("--mtu", type=int, default=1280)
+    parser.add_argument(
+        "-v", "--verbose", action="store_true", help="print every packet"
+    )
+    args = parser.parse_args()
+
+    serial = open_serial(args.serial, args.baud)
+    tun, name = open_tun(args.interface)
+    subprocess.run(
+        ["ip", "link", "set", name, "up", "mtu", str(args.mtu)], check=True
+    )
+    if args.address:
+        subprocess.run(["ip", "-6", "addr", "add", args.address, "dev", name], check=True)
+    print("Forwarding between {} and {}".format(args.serial, name))
+
+    decoder = SlipDecoder()
+    while True:
+        readable, _, _ = select.select([serial, tun], [], [])
+        if serial in readable:
+            for packet in decoder.receive(os.read(serial, 2048)):
+                # Only forward IPv6 packets, the rest is debug output or noise
+                if packet[0] >> 4 != 6:
+                    continue
+                if args.verbose:
+                    print("node -> host: {} bytes".format(len(packet)))
+                os.write(tun, packet)
+        if tun in readable:
+            packet = os.read(tun, args.mtu)
+            if args.verbose:
+                print("host -> node: {} bytes".format(len(packet)))
+            os.write(serial, slip_encode(packet))
+
+
+if __name__ == "__main__":
+    try:
+        main()
+    except KeyboardInterrupt:
+        sys.exit(0)